@@ -0,0 +1,65 @@
+;; SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+;;
+;; Copyleft (c) 2024 James Wong. This file is part of James Wong.
+;; is free software: you can redistribute it and/or modify it under
+;; the terms of the GNU General Public License as published by the
+;; Free Software Foundation, either version 3 of the License, or
+;; (at your option) any later version.
+;;
+;; James Wong is distributed in the hope that it will be useful,
+;; but WITHOUT ANY WARRANTY; without even the implied warranty of
+;; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+;; GNU General Public License for more details.
+;;
+;; You should have received a copy of the GNU General Public License
+;; along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+;;
+;; IMPORTANT: Any software that fully or partially contains or uses materials
+;; covered by this license must also be released under the GNU GPL license.
+;; This includes modifications and derived works.
+
+;; The example wasm plugin of the header based allowlist, which allows the requests with the trusted client
+;; header (skips the ModSecurity) and adds the marking header to the upstream request, otherwise continue.
+;;
+;; The host ABI is imported from module "botwaf":
+;;   get_method(buf_ptr, buf_cap) -> len
+;;   get_path(buf_ptr, buf_cap) -> len
+;;   get_query(buf_ptr, buf_cap) -> len
+;;   get_body(buf_ptr, buf_cap) -> len                          (the body sample)
+;;   get_header(name_ptr, name_len, buf_ptr, buf_cap) -> len    (-1 if missing)
+;;   add_header(name_ptr, name_len, value_ptr, value_len)       (to the upstream request)
+;;   add_score(delta)
+;; The plugin exports the "memory" and "botwaf_on_request() -> verdict", verdict: 0=CONTINUE, 1=ALLOW, 2=BLOCK
+;;
+;; It can be built into binary by: wat2wasm header_allowlist.wat, or loaded the text format directly.
+
+(module
+  (import "botwaf" "get_header" (func $get_header (param i32 i32 i32 i32) (result i32)))
+  (import "botwaf" "add_header" (func $add_header (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  ;; The allowlist header name and the trusted value.
+  (data (i32.const 0) "x-client-id")
+  (data (i32.const 32) "trusted-client")
+  ;; The marking header added to the upstream request.
+  (data (i32.const 64) "x-botwaf-allowlisted")
+  (data (i32.const 96) "true")
+
+  (func (export "botwaf_on_request") (result i32)
+    (local $len i32)
+    (local $i i32)
+    ;; Read the header value into the buffer at 128.
+    (local.set $len (call $get_header (i32.const 0) (i32.const 11) (i32.const 128) (i32.const 64)))
+    (if (i32.ne (local.get $len) (i32.const 14))
+      (then (return (i32.const 0))))
+    (block $mismatch
+      (loop $compare
+        (br_if $mismatch
+          (i32.ne
+            (i32.load8_u (i32.add (i32.const 128) (local.get $i)))
+            (i32.load8_u (i32.add (i32.const 32) (local.get $i)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $compare (i32.lt_u (local.get $i) (i32.const 14))))
+      (call $add_header (i32.const 64) (i32.const 20) (i32.const 96) (i32.const 4))
+      (return (i32.const 1)))
+    (i32.const 0)))
@@ -22,25 +22,23 @@ pub mod dyn_log;
 pub mod metric_jemalloc;
 pub mod profiling;
 
-use axum::{routing, Router};
+use axum::routing::{self, MethodRouter};
 use profiling::{mem_prof_router, pprof_router};
 use prometheus::{Encoder, TextEncoder};
 
-/// Export to debugging operations router.
-pub fn debug_router() -> axum::Router {
+pub const DEBUG_LOG_LEVEL_URI: &str = "/debug/log_level";
+pub const DEBUG_PROF_CPU_URI: &str = "/debug/prof/cpu";
+pub const DEBUG_PROF_MEM_URI: &str = "/debug/prof/mem";
+
+/// Export to debugging operations routes, which are mounted on the management server only.
+pub fn debug_routes() -> Vec<(&'static str, MethodRouter)> {
     // Handlers for debug, we don't expect a timeout.
-    Router::new().nest(
-        "/debug",
-        Router::new()
-            // handler for changing log level dynamically.
-            .route("/log_level", routing::post(dyn_log::dyn_log_handler))
-            .nest(
-                "/prof",
-                Router::new()
-                    .route("/cpu", routing::post(pprof_router::pprof_handler))
-                    .route("/mem", routing::post(mem_prof_router::mem_prof_handler)),
-            ),
-    )
+    vec![
+        // handler for changing log level dynamically.
+        (DEBUG_LOG_LEVEL_URI, routing::post(dyn_log::dyn_log_handler)),
+        (DEBUG_PROF_CPU_URI, routing::post(pprof_router::pprof_handler)),
+        (DEBUG_PROF_MEM_URI, routing::post(mem_prof_router::mem_prof_handler)),
+    ]
 }
 
 /// Handler to export metrics.
@@ -18,19 +18,29 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use super::listener::WebListener;
 use crate::cmd::management::ManagementServer;
+use crate::cmd::output::{self, CommandResult, CommandStatus};
+use anyhow::Context;
+use axum::http::StatusCode;
 use axum::Router;
+use botwaf_forwarder::access_writer::AccessEventWriter;
 use botwaf_forwarder::forwarder_base::BotwafForwarderManager;
 use botwaf_server::config::config::AppConfig;
 use botwaf_server::config::config::{self, GIT_BUILD_DATE, GIT_COMMIT_HASH, GIT_VERSION};
 use botwaf_server::context::state::BotwafState;
-use botwaf_server::mgmt::{apm, health::init as health_router};
+use botwaf_server::mgmt::apm::{self, shutdown_summary::ShutdownSummary};
+use botwaf_server::mgmt::health::init as health_router;
+use botwaf_server::modules::llm::handler::llm_base::LLMManager;
+use botwaf_server::modules::modsec::data_file::DataFileManager;
+use botwaf_server::modules::modsec::rule_version::RuleVersionManager;
+use botwaf_server::sys::dead_letter::DeadLetterManager;
+use botwaf_server::sys::signing_key::SigningKeyManager;
 use botwaf_utils::panics::PanicHelper;
 use botwaf_utils::tokio_signal::tokio_graceful_shutdown_signal;
 use clap::Command;
 use std::env;
 use std::sync::Arc;
-use tokio::net::TcpListener;
 use tokio::sync::oneshot;
 
 pub struct BotwafForwarderServer {}
@@ -44,7 +54,7 @@ impl BotwafForwarderServer {
 
     #[allow(unused)]
     #[tokio::main]
-    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> () {
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
         PanicHelper::set_hook_default();
 
         let config = config::get_config();
@@ -57,26 +67,37 @@ impl BotwafForwarderServer {
         let (signal_s, signal_r) = oneshot::channel();
         let signal_handle = ManagementServer::start(&config, true, signal_s).await;
 
-        signal_r.await.expect("Failed to start Management server.");
+        if signal_r.await.is_err() {
+            return CommandResult::failure(CommandStatus::FAILURE, "Failed to start Management server.");
+        }
         tracing::info!("Management server is ready on {}", config.mgmt.get_bind_addr());
 
-        Self::start(&config, true).await;
+        if let Err(e) = Self::start(&config, true).await {
+            return CommandResult::failure(output::classify_error(&e), e.to_string());
+        }
 
         signal_handle.await.unwrap();
+        CommandResult::success(serde_json::Value::Null)
     }
 
     #[allow(unused)]
-    async fn start(config: &Arc<AppConfig>, verbose: bool) {
-        BotwafForwarderManager::init();
+    async fn start(config: &Arc<AppConfig>, verbose: bool) -> Result<(), anyhow::Error> {
+        LLMManager::init(Self::COMMAND_NAME).await;
 
-        let app_state = BotwafState::new(&config).await;
+        let app_router = Self::build_router(config).await?;
 
         let bind_addr = config.server.get_bind_addr();
         tracing::info!("Starting Botwaf Forwarder server on {}", bind_addr);
-        let listener = match TcpListener::bind(&bind_addr).await {
-            Ok(l) => {
-                tracing::info!("Botwaf Forwarder server is ready on {}", bind_addr);
-                l
+        let listeners = match config
+            .server
+            .get_bind_addrs()
+            .and_then(|addrs| WebListener::bind(&addrs))
+        {
+            Ok(listeners) => {
+                for listener in &listeners {
+                    tracing::info!("Botwaf Forwarder server is ready on {}", listener.local_addr()?);
+                }
+                listeners
             }
             Err(e) => {
                 tracing::error!("Failed to bind to {}: {}", bind_addr, e);
@@ -84,20 +105,67 @@ impl BotwafForwarderServer {
             }
         };
 
-        let app_router = Router::new().merge(health_router()).with_state(app_state);
-        match axum::serve(listener, app_router.into_make_service())
-            .with_graceful_shutdown(tokio_graceful_shutdown_signal())
-            // .tcp_nodelay(true)
-            .await
-        {
+        let result =
+            WebListener::serve_all(listeners, app_router, &config.server, tokio_graceful_shutdown_signal()).await;
+        // Flush the pending access events (and the failed into dead letters) before exit.
+        AccessEventWriter::get().flush().await;
+        if let Some(dead_letters) = DeadLetterManager::get() {
+            dead_letters.flush().await;
+        }
+        match result {
             Ok(_) => {
                 tracing::info!("Botwaf Forwarder server shut down gracefully");
+                ShutdownSummary::collect("forwarder").log();
             }
             Err(e) => {
                 tracing::error!("Error running web server: {}", e);
                 panic!("Error start Botwaf Forwarder server: {}", e);
             }
         }
+        Ok(())
+    }
+
+    /// Build the proxy data-plane router, which applies the IP filter + ModSec (and the LLM classification)
+    /// to all the requests, and then forwards to the upstreams, but without the auth/admin APIs.
+    pub async fn build_router(config: &Arc<AppConfig>) -> Result<Router, anyhow::Error> {
+        BotwafForwarderManager::init().await;
+
+        let app_state = BotwafForwarderManager::wire(BotwafState::builder().with_config(config))
+            .context("Failed to wire the Botwaf forwarder components")?
+            .build()
+            .await?;
+        if let Err(e) = DataFileManager::start_scheduler(app_state.clone()).await {
+            tracing::error!("Failed to start the data files retention scheduler. cause: {}", e);
+        }
+        if let Err(e) = DeadLetterManager::init(config).await {
+            tracing::error!("Failed to init the dead letters. cause: {}", e);
+        }
+        match RuleVersionManager::init(config).await {
+            // Notice: The rules were compiled on the state built, so recompile with the managed rules.
+            Ok(_) => {
+                if RuleVersionManager::get().is_some_and(|m| !m.heads().is_empty()) {
+                    app_state.reload_modsec_rules();
+                }
+            }
+            Err(e) => tracing::error!("Failed to init the rule versions. cause: {}", e),
+        }
+        // Notice: The signed routes are rejected with 'key-unavailable' if the signing keys failed to init.
+        if !config.services.request_signing.routes.is_empty() {
+            if let Err(e) = SigningKeyManager::init(config).await {
+                tracing::error!("Failed to init the request signing keys. cause: {}", e);
+            }
+        }
+
+        // Notice: The middleware only wraps the matched routes, so that the fallback is required to
+        // intercept all the requests, actually it's never reached as the middleware forwarded itself.
+        let layer =
+            axum::middleware::from_fn_with_state(app_state.to_owned(), BotwafForwarderManager::botwaf_middleware);
+        let proxy_router = Router::new().fallback(|| async { StatusCode::NOT_FOUND }).layer(layer);
+
+        Ok(Router::new()
+            .merge(health_router())
+            .with_state(app_state)
+            .merge(proxy_router))
     }
 
     fn print_banner(config: Arc<AppConfig>, verbose: bool) {
@@ -149,9 +217,105 @@ impl BotwafForwarderServer {
             }
         }
         if verbose {
-            let config_json = serde_json::to_string(&config.inner).unwrap_or_default();
+            let config_json = config.inner.to_masked_json();
             eprintln!("Configuration loaded: {}", config_json);
         }
         eprintln!("");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use axum::{body::Body, routing::get};
+    use botwaf_server::config::config::{AppConfigProperties, AppDBType, LlmClassificationMode, UpstreamProperties};
+    use botwaf_server::modules::llm::handler::{
+        llm_base::{ILLMHandler, LlmGeneration},
+        llm_langchain::LangchainLLMHandler,
+    };
+    use botwaf_types::modules::llm::knowledge::KnowledgeUploadInfo;
+    use http_body_util::BodyExt;
+    use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+    use std::{fs::File, net::SocketAddr};
+    use tokio::net::TcpListener;
+
+    struct MockLLMHandler {}
+
+    #[async_trait::async_trait]
+    impl ILLMHandler for MockLLMHandler {
+        async fn init(&self) {}
+
+        async fn embedding(&self, _info: KnowledgeUploadInfo, _file: File) -> Result<KnowledgeUploadInfo, Error> {
+            Err(Error::msg("Unsupported"))
+        }
+
+        async fn generate(&self, _prompt: String) -> Result<LlmGeneration, Error> {
+            Err(Error::msg("Unsupported"))
+        }
+    }
+
+    async fn start_mock_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route("/hello", get(|| async { "Hello from upstream" }));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        addr
+    }
+
+    fn setup_config(upstream_addr: SocketAddr) {
+        let dir = env::temp_dir().join(format!("botwaf-forwarder-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut properties = AppConfigProperties::default();
+        properties.appdb.db_type = AppDBType::SQLITE;
+        properties.appdb.sqlite.dir = Some(dir.to_string_lossy().to_string());
+        properties.services.llm_classification.mode = LlmClassificationMode::OFF;
+        properties.services.forward.upstreams = vec![UpstreamProperties {
+            url_prefix: format!("http://{}", upstream_addr),
+            default: true,
+            ..Default::default()
+        }];
+
+        let path = dir.join("botwaf.yaml");
+        std::fs::write(&path, serde_yaml::to_string(&properties).unwrap()).unwrap();
+        env::set_var("BOTWAF_CFG_PATH", path.to_string_lossy().to_string());
+        config::refresh_config().unwrap();
+
+        LLMManager::get()
+            .write()
+            .unwrap()
+            .implementations
+            .insert(LangchainLLMHandler::NAME.to_owned(), Arc::new(MockLLMHandler {}));
+    }
+
+    #[tokio::test]
+    async fn test_forwarder_proxy_to_upstream() {
+        let upstream_addr = start_mock_upstream().await;
+        setup_config(upstream_addr);
+
+        let config = config::get_config();
+        let router = BotwafForwarderServer::build_router(&config).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_s, shutdown_r) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            WebListener::serve(listener, router, &config.server, async move {
+                let _ = shutdown_r.await;
+            })
+            .await
+            .unwrap();
+        });
+
+        let client = Client::builder(TokioExecutor::new()).build_http::<Body>();
+        let req = hyper::Request::get(format!("http://{}/hello", addr))
+            .body(Body::empty())
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(String::from_utf8_lossy(&body), "Hello from upstream");
+
+        let _ = shutdown_s.send(());
+    }
+}
@@ -0,0 +1,571 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use anyhow::{Error, Result};
+use axum::{body::Body, extract::Request, Router};
+use botwaf_server::{
+    config::config::{ServerProperties, TlsProperties},
+    util::auths::ClientCertIdentity,
+};
+use common_telemetry::{debug, info, warn};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{fs::File, future::Future, io::BufReader, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_rustls::{
+    rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig, ServerConnection},
+    TlsAcceptor,
+};
+use tower::ServiceExt;
+
+/// The client-facing web listener, which serves HTTP/1.1 and HTTP/2 (ALPN-negotiated h2 with TLS, or
+/// cleartext h2c prior knowledge if allowed), and the experimental HTTP/3 with cargo feature 'http3'.
+pub struct WebListener {}
+
+impl WebListener {
+    pub const ALPN_H2: &'static [u8] = b"h2";
+    pub const ALPN_HTTP11: &'static [u8] = b"http/1.1";
+    #[cfg(feature = "http3")]
+    pub const ALPN_H3: &'static [u8] = b"h3";
+    // The transport accepts the multiple of the header limits, so that the exceeded requests are rejected with
+    // the explanatory 431 by the header limits middleware, rather than the bare 431 of hyper.
+    const TRANSPORT_HEADER_LIMITS_FACTOR: usize = 4;
+    // The min buffer size of the HTTP/1 connection allowed by hyper.
+    const MIN_HTTP1_BUF_SIZE: usize = 8192;
+    const LISTEN_BACKLOG: i32 = 1024;
+
+    /// Bind the listeners of all the addresses, e.g: the both of 0.0.0.0 and :: for the dual-stack.
+    pub fn bind(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>, Error> {
+        addrs
+            .iter()
+            .map(|addr| {
+                // The IPv6 wildcard also accepts the IPv4-mapped by default on most OS, which conflicts with the
+                // IPv4 listener of the same port, so it's restricted to the IPv6 only if both are listed.
+                let only_v6 = addr.is_ipv6() && addrs.iter().any(|a| a.is_ipv4() && a.port() == addr.port());
+                Self::bind_addr(addr, only_v6)
+                    .map_err(|e| Error::msg(format!("Failed to bind to {}. cause: {}", addr, e)))
+            })
+            .collect()
+    }
+
+    fn bind_addr(addr: &SocketAddr, only_v6: bool) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+        if only_v6 {
+            socket.set_only_v6(true)?;
+        }
+        // Notice: Same as the std, the reuse address is not set on windows which allows the port hijacking.
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&(*addr).into())?;
+        socket.listen(Self::LISTEN_BACKLOG)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    /// Serve all the listeners which are feeding the same router, and stop accepting on all once shutdown.
+    pub async fn serve_all(
+        listeners: Vec<TcpListener>,
+        router: Router,
+        config: &ServerProperties,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), Error> {
+        let shutdown_r = Self::broadcast_shutdown(shutdown);
+        let mut serves = JoinSet::new();
+        for listener in listeners {
+            let (router, config, mut shutdown_r) = (router.to_owned(), config.to_owned(), shutdown_r.to_owned());
+            serves.spawn(async move {
+                Self::serve(listener, router, &config, async move {
+                    let _ = shutdown_r.changed().await;
+                })
+                .await
+            });
+        }
+        while let Some(result) = serves.join_next().await {
+            result??;
+        }
+        Ok(())
+    }
+
+    /// Serve all the listeners with the plain axum server, i.e: without the TLS and the HTTP/2 settings of the
+    /// web listener, e.g: the management and the health servers.
+    pub async fn serve_plain_all(
+        listeners: Vec<TcpListener>,
+        router: Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), Error> {
+        let shutdown_r = Self::broadcast_shutdown(shutdown);
+        let mut serves = JoinSet::new();
+        for listener in listeners {
+            let (router, mut shutdown_r) = (router.to_owned(), shutdown_r.to_owned());
+            serves.spawn(async move {
+                axum::serve(listener, router.into_make_service())
+                    .with_graceful_shutdown(async move {
+                        let _ = shutdown_r.changed().await;
+                    })
+                    .await
+            });
+        }
+        while let Some(result) = serves.join_next().await {
+            result??;
+        }
+        Ok(())
+    }
+
+    fn broadcast_shutdown(shutdown: impl Future<Output = ()> + Send + 'static) -> watch::Receiver<()> {
+        let (shutdown_s, shutdown_r) = watch::channel(());
+        tokio::spawn(async move {
+            shutdown.await;
+            let _ = shutdown_s.send(());
+        });
+        shutdown_r
+    }
+
+    pub async fn serve(
+        listener: TcpListener,
+        router: Router,
+        config: &ServerProperties,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), Error> {
+        let tls_acceptor = if config.tls.enabled {
+            let mut tls_config = Self::build_tls_config(&config.tls)?;
+            tls_config.alpn_protocols = if config.http2.enabled {
+                vec![Self::ALPN_H2.to_vec(), Self::ALPN_HTTP11.to_vec()]
+            } else {
+                vec![Self::ALPN_HTTP11.to_vec()]
+            };
+            Some(TlsAcceptor::from(Arc::new(tls_config)))
+        } else {
+            None
+        };
+
+        tokio::pin!(shutdown);
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept connection. cause: {}", e);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => {
+                    info!("Web listener is stop accepting connections.");
+                    return Ok(());
+                }
+            };
+            let _ = stream.set_nodelay(true);
+
+            let router = router.to_owned();
+            let config = config.to_owned();
+            let tls_acceptor = tls_acceptor.to_owned();
+            tokio::spawn(async move {
+                match tls_acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(tls_stream) => {
+                            // Notice: Only the ALPN-negotiated h2 is served as HTTP/2 on the TLS connections.
+                            let h2 = tls_stream.get_ref().1.alpn_protocol() == Some(Self::ALPN_H2);
+                            let identity = Self::get_client_cert_identity(tls_stream.get_ref().1);
+                            Self::serve_connection(tls_stream, remote_addr, identity, router, &config, h2).await
+                        }
+                        Err(e) => debug!("Failed to TLS handshake with {}. cause: {}", remote_addr, e),
+                    },
+                    None => {
+                        let h2c = config.http2.enabled && config.http2.h2c;
+                        Self::serve_connection(stream, remote_addr, None, router, &config, h2c).await
+                    }
+                }
+            });
+        }
+    }
+
+    async fn serve_connection<IO>(
+        io: IO,
+        remote_addr: SocketAddr,
+        identity: Option<ClientCertIdentity>,
+        router: Router,
+        config: &ServerProperties,
+        h2: bool,
+    ) where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // The IPv4-mapped peer of the dual-stack listener (e.g: ::ffff:10.0.0.1) is canonicalized to the IPv4, so
+        // that the client IP is uniform for the rate limiting and the CIDR matching.
+        let remote_addr = SocketAddr::new(remote_addr.ip().to_canonical(), remote_addr.port());
+        let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+            // Notice: Compatible with 'HttpIncomingRequest' to extract the client remote address.
+            req.extensions_mut().insert(remote_addr);
+            // The verified client certificate identity for the authentication, see: auths::authenticate_client_cert
+            if let Some(identity) = &identity {
+                req.extensions_mut().insert(identity.to_owned());
+            }
+            router.to_owned().oneshot(req.map(Body::new))
+        });
+
+        let mut builder = Builder::new(TokioExecutor::new());
+        // The per-stream flow control window is bounded by the max concurrent streams, and the request
+        // body limit is accounted per stream, see: HttpIncomingRequest::new()
+        builder
+            .http2()
+            .max_concurrent_streams(config.http2.max_concurrent_streams);
+        // The exact header limits are checked by the middleware, see: header_limits::header_limits_middleware
+        if config.limits.max_header_bytes > 0 {
+            let max_bytes = config
+                .limits
+                .max_header_bytes
+                .saturating_mul(Self::TRANSPORT_HEADER_LIMITS_FACTOR);
+            builder.http1().max_buf_size(max_bytes.max(Self::MIN_HTTP1_BUF_SIZE));
+            builder
+                .http2()
+                .max_header_list_size(max_bytes.min(u32::MAX as usize) as u32);
+        }
+        if config.limits.max_header_count > 0 {
+            builder.http1().max_headers(
+                config
+                    .limits
+                    .max_header_count
+                    .saturating_mul(Self::TRANSPORT_HEADER_LIMITS_FACTOR),
+            );
+        }
+        let builder = if h2 { builder } else { builder.http1_only() };
+
+        if let Err(e) = builder.serve_connection_with_upgrades(TokioIo::new(io), service).await {
+            debug!("Failed to serve connection with {}. cause: {}", remote_addr, e);
+        }
+    }
+
+    fn build_tls_config(config: &TlsProperties) -> Result<ServerConfig, Error> {
+        let cert_path = config
+            .cert_path
+            .as_ref()
+            .ok_or_else(|| Error::msg("Missing config 'server.tls.cert-path'"))?;
+        let key_path = config
+            .key_path
+            .as_ref()
+            .ok_or_else(|| Error::msg("Missing config 'server.tls.key-path'"))?;
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+            .ok_or_else(|| Error::msg(format!("No found private key in '{}'", key_path)))?;
+
+        let builder = match &config.client_ca_path {
+            Some(client_ca_path) => {
+                // The client certificates are optional, the unauthenticated clients fallback to the other principals.
+                let mut roots = RootCertStore::empty();
+                for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(client_ca_path)?)) {
+                    roots.add(cert?)?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        Ok(builder.with_single_cert(certs, key)?)
+    }
+
+    fn get_client_cert_identity(conn: &ServerConnection) -> Option<ClientCertIdentity> {
+        let der = conn.peer_certificates()?.first()?;
+        match ClientCertIdentity::from_der(der) {
+            Ok(identity) => Some(identity),
+            Err(e) => {
+                warn!("Failed to parse the client certificate. cause: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Serve the experimental HTTP/3 (QUIC) listener on the UDP port 'server.http3.port' of each bind host.
+    #[cfg(feature = "http3")]
+    pub async fn serve_h3(router: Router, config: &ServerProperties, max_body_bytes: usize) -> Result<(), Error> {
+        let mut tls_config = Self::build_tls_config(&config.tls)?;
+        tls_config.alpn_protocols = vec![Self::ALPN_H3.to_vec()];
+        let quic_config = quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)?;
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(quic_config));
+
+        let mut endpoints = JoinSet::new();
+        for bind_addr in config.get_http3_bind_addrs()? {
+            let endpoint = quinn::Endpoint::server(server_config.to_owned(), bind_addr)?;
+            info!("Web HTTP/3 listener is ready on udp://{}", bind_addr);
+            endpoints.spawn(Self::serve_h3_endpoint(endpoint, router.to_owned(), max_body_bytes));
+        }
+        while let Some(result) = endpoints.join_next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "http3")]
+    async fn serve_h3_endpoint(endpoint: quinn::Endpoint, router: Router, max_body_bytes: usize) {
+        while let Some(connecting) = endpoint.accept().await {
+            let router = router.to_owned();
+            tokio::spawn(async move {
+                let conn = match connecting.await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        debug!("Failed to QUIC handshake. cause: {}", e);
+                        return;
+                    }
+                };
+                let remote_addr = conn.remote_address();
+                let remote_addr = SocketAddr::new(remote_addr.ip().to_canonical(), remote_addr.port());
+                let mut h3_conn = match h3::server::Connection::new(h3_quinn::Connection::new(conn)).await {
+                    Ok(h3_conn) => h3_conn,
+                    Err(e) => {
+                        debug!("Failed to establish h3 with {}. cause: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                loop {
+                    match h3_conn.accept().await {
+                        Ok(Some((req, stream))) => {
+                            let router = router.to_owned();
+                            tokio::spawn(async move {
+                                let result =
+                                    Self::serve_h3_request(router, req, stream, remote_addr, max_body_bytes).await;
+                                if let Err(e) = result {
+                                    debug!("Failed to serve h3 request with {}. cause: {}", remote_addr, e);
+                                }
+                            });
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            debug!("The h3 connection with {} closed. cause: {}", remote_addr, e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    #[cfg(feature = "http3")]
+    async fn serve_h3_request<S>(
+        router: Router,
+        req: hyper::Request<()>,
+        mut stream: h3::server::RequestStream<S, bytes::Bytes>,
+        remote_addr: SocketAddr,
+        max_body_bytes: usize,
+    ) -> Result<(), Error>
+    where
+        S: h3::quic::BidiStream<bytes::Bytes>,
+    {
+        use bytes::{Buf, BytesMut};
+
+        // Collect the request body with per-stream accounting of body limit.
+        let mut body = BytesMut::new();
+        while let Some(mut chunk) = stream.recv_data().await? {
+            if body.len() + chunk.remaining() > max_body_bytes {
+                let resp = hyper::Response::builder()
+                    .status(hyper::StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(())?;
+                stream.send_response(resp).await?;
+                return Ok(stream.finish().await?);
+            }
+            body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+        }
+
+        let (mut parts, _) = req.into_parts();
+        parts.version = hyper::Version::HTTP_3;
+        parts.extensions.insert(remote_addr);
+        let req = Request::from_parts(parts, Body::from(body.freeze()));
+
+        let resp = router.oneshot(req).await?;
+        let (parts, body) = resp.into_parts();
+        stream.send_response(hyper::Response::from_parts(parts, ())).await?;
+        let bytes = axum::body::to_bytes(body, usize::MAX).await?;
+        if !bytes.is_empty() {
+            stream.send_data(bytes).await?;
+        }
+        Ok(stream.finish().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::post,
+    };
+    use botwaf_server::util::header_limits::{header_limits_middleware, LIMIT_MAX_HEADER_BYTES};
+    use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+    use http_body_util::{BodyExt, Full};
+    use hyper_util::client::legacy::Client;
+    use tokio::sync::oneshot;
+
+    const MAX_BODY_BYTES: usize = 64 * 1024;
+
+    async fn handle_echo(req: Request<Body>) -> Response {
+        match HttpIncomingRequest::new(req, MAX_BODY_BYTES).await {
+            Ok(incoming) => {
+                let len = incoming.body.as_ref().map(|b| b.len()).unwrap_or_default();
+                (StatusCode::OK, format!("{}:{}", incoming.protocol(), len)).into_response()
+            }
+            Err(_) => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+        }
+    }
+
+    async fn start_listener(h2c: bool) -> (SocketAddr, oneshot::Sender<()>) {
+        let mut config = ServerProperties::default();
+        config.http2.h2c = h2c;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/echo", post(handle_echo))
+            .layer(axum::middleware::from_fn(header_limits_middleware));
+        let (shutdown_s, shutdown_r) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            WebListener::serve(listener, router, &config, async move {
+                let _ = shutdown_r.await;
+            })
+            .await
+            .unwrap();
+        });
+        (addr, shutdown_s)
+    }
+
+    async fn post_echo(
+        client: &Client<hyper_util::client::legacy::connect::HttpConnector, Full<Bytes>>,
+        addr: SocketAddr,
+        size: usize,
+    ) -> Result<(StatusCode, String), Error> {
+        let req = hyper::Request::post(format!("http://{}/echo", addr))
+            .body(Full::new(Bytes::from(vec![b'a'; size])))?;
+        let resp = client.request(req).await?;
+        let status = resp.status();
+        let body = resp.into_body().collect().await?.to_bytes();
+        Ok((status, String::from_utf8_lossy(&body).to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_h2c_concurrent_streams_body_limit() {
+        let (addr, _shutdown) = start_listener(true).await;
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http::<Full<Bytes>>();
+
+        // Large concurrent streams on the same h2 connection, each body limit is accounted per stream.
+        let tasks = (0..64)
+            .map(|i| {
+                let client = client.to_owned();
+                let size = if i % 2 == 0 { MAX_BODY_BYTES } else { MAX_BODY_BYTES + 1 };
+                tokio::spawn(async move { (size, post_echo(&client, addr, size).await) })
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            let (size, result) = task.await.unwrap();
+            let (status, body) = result.unwrap();
+            if size <= MAX_BODY_BYTES {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body, format!("h2:{}", size));
+            } else {
+                assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_h2c_disabled_fallback_http1() {
+        let (addr, _shutdown) = start_listener(false).await;
+
+        let h2_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http::<Full<Bytes>>();
+        assert!(post_echo(&h2_client, addr, 16).await.is_err());
+
+        let http1_client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+        let (status, body) = post_echo(&http1_client, addr, 16).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "http1.1:16");
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_listeners_accept_both() {
+        let mut addrs = vec!["127.0.0.1:0".parse::<SocketAddr>().unwrap()];
+        // Notice: The IPv6 loopback may be unavailable in some CI containers.
+        if std::net::TcpListener::bind("[::1]:0").is_ok() {
+            addrs.push("[::1]:0".parse().unwrap());
+        }
+        let listeners = WebListener::bind(&addrs).unwrap();
+        let bound = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bound.len(), addrs.len());
+
+        let config = ServerProperties::default();
+        let router = Router::new().route("/echo", post(handle_echo));
+        let (shutdown_s, shutdown_r) = oneshot::channel::<()>();
+        let serving = tokio::spawn(async move {
+            WebListener::serve_all(listeners, router, &config, async move {
+                let _ = shutdown_r.await;
+            })
+            .await
+        });
+
+        // The same router is fed by all the listeners.
+        let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+        for addr in bound {
+            let (status, body) = post_echo(&client, addr, 16).await.unwrap();
+            assert_eq!(status, StatusCode::OK, "{}", addr);
+            assert_eq!(body, "http1.1:16");
+        }
+
+        // All the listeners stop accepting once shutdown.
+        shutdown_s.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_large_cookie_rejected_with_json_431() {
+        let (addr, _shutdown) = start_listener(false).await;
+        let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+
+        // The 64 KB cookie exceeds the limit but not the transport, which is rejected with the explanatory 431.
+        let req = hyper::Request::post(format!("http://{}/echo", addr))
+            .header("cookie", format!("sso_session={}", "x".repeat(64 * 1024)))
+            .body(Full::new(Bytes::from_static(b"a")))
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["errmsg"].as_str().unwrap().contains(LIMIT_MAX_HEADER_BYTES), "{}", body);
+
+        // The connection is still usable for the subsequent requests.
+        let (status, body) = post_echo(&client, addr, 16).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "http1.1:16");
+    }
+}
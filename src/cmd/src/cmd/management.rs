@@ -18,11 +18,25 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use super::listener::WebListener;
 use crate::apm;
-use axum::{routing::get, Router};
+use axum::{
+    routing::{get, MethodRouter},
+    Router,
+};
 use axum_prometheus::PrometheusMetricLayer;
-use botwaf_server::{config::config::AppConfig, mgmt};
-use common_telemetry::info;
+use botwaf_server::{
+    config::config::{AppConfig, MgmtAuthMode},
+    mgmt::{
+        self,
+        auth::mgmt_auth_middleware,
+        routes::{
+            self, MGMT_APM_METRICS_URI, MGMT_COMPONENTS_URI, MGMT_CONFIG_URI, MGMT_ENGINE_CAPABILITIES_URI,
+            MGMT_EVENTS_SCHEMA_URI, MGMT_FAIL_OPEN_BUDGET_URI, MGMT_METRICS_URI,
+        },
+    },
+};
+use common_telemetry::{info, warn};
 use std::sync::Arc;
 use tokio::{sync::oneshot, task::JoinHandle};
 
@@ -31,27 +45,128 @@ pub struct ManagementServer {}
 impl ManagementServer {
     #[allow(unused)]
     pub async fn start(config: &Arc<AppConfig>, verbose: bool, signal_s: oneshot::Sender<()>) -> JoinHandle<()> {
+        // e.g: The sidecar deployments without the management server.
+        if !config.mgmt.enabled {
+            info!("The Management server is disabled.");
+            let _ = signal_s.send(());
+            return tokio::spawn(async {});
+        }
         let (prometheus_layer, _) = PrometheusMetricLayer::pair();
-
-        let app: Router = Router::new()
-            // TODO: There are merge??
-            .route("/metrics1", get(mgmt::apm::metrics::handle_metrics))
-            .route("/metrics2", get(apm::handle_metrics))
-            .layer(prometheus_layer)
-            .merge(apm::debug_router());
+        let app = Self::router(config).layer(prometheus_layer);
 
         let bind_addr = config.mgmt.get_bind_addr();
+        if !config.mgmt.host.is_loopback() && config.mgmt.auth.mode == MgmtAuthMode::NONE {
+            warn!(
+                "The Management server is bound on the non-loopback {} without auth, please configure the 'mgmt.auth'.",
+                bind_addr
+            );
+        }
         info!("Starting Management server on {}", bind_addr);
+        let listeners = config
+            .mgmt
+            .get_bind_addrs()
+            .and_then(|addrs| WebListener::bind(&addrs))
+            .unwrap_or_else(|e| panic!("Error starting Management server: {}", e));
+        for listener in &listeners {
+            if let Ok(addr) = listener.local_addr() {
+                info!("Management server is bound on {}", addr);
+            }
+        }
 
         tokio::spawn(async move {
             // When started call to signal sender.
             let _ = signal_s.send(());
-            axum::serve(
-                tokio::net::TcpListener::bind(&bind_addr).await.unwrap(),
-                app.into_make_service(),
-            )
-            .await
-            .unwrap_or_else(|e| panic!("Error starting Management server: {}", e));
+            WebListener::serve_plain_all(listeners, app, std::future::pending())
+                .await
+                .unwrap_or_else(|e| panic!("Error starting Management server: {}", e));
         })
     }
+
+    /// The management router with its own auth, which is independent of the public JWT auth.
+    pub fn router(config: &AppConfig) -> Router {
+        let auth = Arc::new(config.mgmt.auth.to_owned());
+        Self::routes()
+            .into_iter()
+            .fold(Router::new(), |router, (path, method_router)| {
+                router.route(path, method_router)
+            })
+            .layer(axum::middleware::from_fn_with_state(auth, mgmt_auth_middleware))
+    }
+
+    /// The route paths of the management server, which must be disjoint with the public server, see:
+    /// routes::check_routes_disjoint
+    pub fn paths() -> Vec<&'static str> {
+        Self::routes().into_iter().map(|(path, _)| path).collect()
+    }
+
+    fn routes() -> Vec<(&'static str, MethodRouter)> {
+        let mut routes = vec![
+            // TODO: There are merge??
+            (MGMT_METRICS_URI, get(mgmt::apm::metrics::handle_metrics)),
+            (MGMT_APM_METRICS_URI, get(apm::handle_metrics)),
+            (MGMT_FAIL_OPEN_BUDGET_URI, get(mgmt::fail_open::handle_fail_open_budget)),
+            (MGMT_COMPONENTS_URI, get(mgmt::supervisor::handle_components)),
+            (MGMT_CONFIG_URI, get(routes::handle_config)),
+            (MGMT_EVENTS_SCHEMA_URI, get(routes::handle_events_schema)),
+            (MGMT_ENGINE_CAPABILITIES_URI, get(routes::handle_engine_capabilities)),
+        ];
+        routes.extend(apm::debug_routes());
+        routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use botwaf_server::config::{
+        config::{AppConfigProperties, MgmtAuthProperties},
+        swagger,
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_public_and_mgmt_routes_disjoint() {
+        let config = AppConfig::new(&AppConfigProperties::default());
+        let mgmt_paths = ManagementServer::paths();
+        for path in ["/metrics1", "/config", "/events/schema", "/debug/prof/cpu"] {
+            assert!(mgmt_paths.contains(&path), "missing mgmt path: {}", path);
+        }
+        assert!(
+            routes::check_routes_disjoint(&swagger::init(&config), &mgmt_paths, None)
+                .await
+                .is_ok()
+        );
+        // The management router itself mounted on the public server.
+        let err = routes::check_routes_disjoint(&ManagementServer::router(&config), &mgmt_paths, None)
+            .await
+            .unwrap_err();
+        assert!(mgmt_paths.contains(&err.path.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_mgmt_router_enforces_api_key() {
+        let mut props = AppConfigProperties::default();
+        props.mgmt.auth = MgmtAuthProperties {
+            mode: MgmtAuthMode::API_KEY,
+            api_key: Some(String::from("s3cr3t")),
+            ..MgmtAuthProperties::default()
+        };
+        let config = AppConfig::new(&props);
+        let request = |api_key: Option<&str>| {
+            let mut req = Request::builder().uri(MGMT_EVENTS_SCHEMA_URI);
+            if let Some(api_key) = api_key {
+                req = req.header(mgmt::auth::MGMT_API_KEY_HEADER, api_key);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let resp = ManagementServer::router(&config).oneshot(request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = ManagementServer::router(&config)
+            .oneshot(request(Some("s3cr3t")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
 }
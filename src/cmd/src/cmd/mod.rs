@@ -19,23 +19,32 @@
 // This includes modifications and derived works.
 
 pub mod forwarder;
+pub mod listener;
 pub mod management;
+pub mod output;
+pub mod reindex_vectors;
+pub mod replay_events;
 pub mod server;
 pub mod standalone;
 pub mod updater;
 pub mod verifier;
+pub mod verify_decisions;
 
 use botwaf_server::config::config;
 use clap::{Arg, ArgMatches, Command};
 use forwarder::BotwafForwarderServer;
+use output::{CommandOutput, CommandResult, CommandStatus, OutputFormat};
+use reindex_vectors::ReindexVectorsCommand;
+use replay_events::ReplayEventsCommand;
 use server::WebServer;
 use standalone::StandaloneServer;
-use std::{collections::BTreeMap, sync::OnceLock};
+use std::{collections::BTreeMap, panic::AssertUnwindSafe, sync::OnceLock, time::Instant};
 use updater::BotwafUpdaterServer;
 use verifier::BotwafVerifierServer;
+use verify_decisions::VerifyDecisionsCommand;
 
 type SubcommandBuildFn = fn() -> Command;
-type SubcommandHandleFn = fn(&ArgMatches, bool) -> ();
+type SubcommandHandleFn = fn(&ArgMatches, bool) -> CommandResult;
 
 static SUBCOMMAND_MAP: OnceLock<BTreeMap<&'static str, (SubcommandBuildFn, SubcommandHandleFn)>> = OnceLock::new();
 
@@ -82,6 +91,30 @@ pub fn register_subcommand_handles() -> &'static BTreeMap<&'static str, (Subcomm
                 BotwafForwarderServer::run as SubcommandHandleFn,
             ),
         );
+        map.insert(
+            ReindexVectorsCommand::COMMAND_NAME,
+            (
+                // Type inference error, forced conversion need.
+                ReindexVectorsCommand::build as SubcommandBuildFn,
+                ReindexVectorsCommand::run as SubcommandHandleFn,
+            ),
+        );
+        map.insert(
+            ReplayEventsCommand::COMMAND_NAME,
+            (
+                // Type inference error, forced conversion need.
+                ReplayEventsCommand::build as SubcommandBuildFn,
+                ReplayEventsCommand::run as SubcommandHandleFn,
+            ),
+        );
+        map.insert(
+            VerifyDecisionsCommand::COMMAND_NAME,
+            (
+                // Type inference error, forced conversion need.
+                VerifyDecisionsCommand::build as SubcommandBuildFn,
+                VerifyDecisionsCommand::run as SubcommandHandleFn,
+            ),
+        );
         map
     })
 }
@@ -106,7 +139,8 @@ pub fn execute_commands_app() -> () {
                 .value_name("PRINT") // Tips for the user.
                 .help("Set up global details print flag")
                 .global(true), // Global args are available to all subcommands.
-        );
+        )
+        .arg(OutputFormat::arg());
 
     let subcommand_map = register_subcommand_handles();
     // Add to all subcommands.
@@ -116,18 +150,22 @@ pub fn execute_commands_app() -> () {
 
     let matches = app.get_matches();
     let verbose = matches.contains_id("verbose");
+    let format = OutputFormat::from_matches(&matches);
 
     // Handling to actual subcommand.
     match matches.subcommand() {
         Some((name, sub_matches)) => {
-            if let Some(&(_, handler)) = subcommand_map.get(name) {
+            let start_time = Instant::now();
+            let result = if let Some(&(_, handler)) = subcommand_map.get(name) {
                 tracing::info!("Executing subcommand: {}", name);
-                handler(sub_matches, verbose);
+                run_subcommand(handler, sub_matches, verbose)
             } else {
-                // panic!("Unknown subcommand: {}. Use --help for a list of available commands.", name);
-                eprintln!("Invalid commands and Use <command> --help for more information about a specific command.");
-                std::process::exit(1);
-            }
+                CommandResult::failure(
+                    CommandStatus::VALIDATION_FAILURE,
+                    "Invalid commands and Use <command> --help for more information about a specific command.",
+                )
+            };
+            std::process::exit(CommandOutput::new(name, start_time, result).emit(format));
         }
         None => {
             tracing::info!("No subcommand was used. Available commands are:");
@@ -138,3 +176,58 @@ pub fn execute_commands_app() -> () {
         }
     }
 }
+
+/// Run the subcommand handler with the configuration checked, and the panic (e.g: the startup failure
+/// of the servers) is reported as the result instead of aborting the process without an output.
+pub fn run_subcommand(handler: SubcommandHandleFn, matches: &ArgMatches, verbose: bool) -> CommandResult {
+    if let Err(e) = config::check_config() {
+        return CommandResult::failure(CommandStatus::VALIDATION_FAILURE, e.to_string());
+    }
+    match std::panic::catch_unwind(AssertUnwindSafe(|| handler(matches, verbose))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown panic".to_owned());
+            CommandResult::failure(CommandStatus::FAILURE, message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panicked_handler(_: &ArgMatches, _: bool) -> CommandResult {
+        panic!("Failed to bind to 0.0.0.0:9999: Address already in use")
+    }
+
+    fn validation_handler(_: &ArgMatches, _: bool) -> CommandResult {
+        CommandResult::failure(
+            CommandStatus::VALIDATION_FAILURE,
+            "The --samples must be greater than 0",
+        )
+    }
+
+    #[test]
+    fn test_run_subcommand_panicked_as_failure() {
+        let matches = Command::new("server").get_matches_from(vec!["server"]);
+        let result = run_subcommand(panicked_handler, &matches, false);
+        assert_eq!(result.status, CommandStatus::FAILURE);
+        assert!(result.message.unwrap().contains("Address already in use"));
+    }
+
+    #[test]
+    fn test_run_subcommand_json_output() {
+        let matches = Command::new("reindex-vectors").get_matches_from(vec!["reindex-vectors"]);
+        let result = run_subcommand(validation_handler, &matches, false);
+        let output = CommandOutput::new("reindex-vectors", Instant::now(), result);
+        assert_eq!(output.exit_code, 2);
+
+        let value: serde_json::Value = serde_json::from_str(&output.render(OutputFormat::JSON)).unwrap();
+        assert_eq!(value["status"], "VALIDATION_FAILURE");
+        assert_eq!(value["exitCode"], 2);
+    }
+}
@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use clap::{Arg, ArgMatches};
+use serde::Serialize;
+use std::time::Instant;
+
+/// The global output format of the subcommands results, the results are always written to stdout, and
+/// the logs are written to stderr, so the stdout keeps parseable for the scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    TEXT,
+    JSON,
+}
+
+impl OutputFormat {
+    pub const ARG_NAME: &'static str = "output";
+
+    pub fn arg() -> Arg {
+        Arg::new(Self::ARG_NAME)
+            .short('o')
+            .long(Self::ARG_NAME)
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .help("Set up global output format of the subcommand results, the logs are always written to stderr")
+            .global(true) // Global args are available to all subcommands.
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.get_one::<String>(Self::ARG_NAME).map(|s| s.as_str()) {
+            Some("json") => OutputFormat::JSON,
+            _ => OutputFormat::TEXT,
+        }
+    }
+}
+
+/// The stable exit status of the subcommands, which the scripts could rely on.
+///
+/// | Exit code | Status                 | Description                                                  |
+/// |-----------|------------------------|--------------------------------------------------------------|
+/// | 0         | SUCCESS                | The subcommand succeeded.                                    |
+/// | 1         | FAILURE                | The unexpected failure, e.g: the subcommand panicked.        |
+/// | 2         | VALIDATION_FAILURE     | The invalid arguments or configuration.                      |
+/// | 3         | DEPENDENCY_UNREACHABLE | The dependency (e.g: database, vector DB) is unreachable.    |
+/// | 4         | PARTIAL_FAILURE        | Some of the items failed, see the details of the result.     |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum CommandStatus {
+    SUCCESS,
+    FAILURE,
+    VALIDATION_FAILURE,
+    DEPENDENCY_UNREACHABLE,
+    PARTIAL_FAILURE,
+}
+
+impl CommandStatus {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CommandStatus::SUCCESS => 0,
+            CommandStatus::FAILURE => 1,
+            CommandStatus::VALIDATION_FAILURE => 2,
+            CommandStatus::DEPENDENCY_UNREACHABLE => 3,
+            CommandStatus::PARTIAL_FAILURE => 4,
+        }
+    }
+}
+
+/// Classify the failure of the subcommand, e.g: the unreachable database is a dependency failure.
+pub fn classify_error(e: &anyhow::Error) -> CommandStatus {
+    for cause in e.chain() {
+        if let Some(err) = cause.downcast_ref::<sqlx::Error>() {
+            return match err {
+                sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                    CommandStatus::DEPENDENCY_UNREACHABLE
+                }
+                _ => CommandStatus::FAILURE,
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            return match err.kind() {
+                std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::TimedOut => CommandStatus::DEPENDENCY_UNREACHABLE,
+                _ => CommandStatus::FAILURE,
+            };
+        }
+    }
+    CommandStatus::FAILURE
+}
+
+/// The result returned by the subcommand handler instead of panicking or exiting the process.
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub status: CommandStatus,
+    pub message: Option<String>,
+    // The machine-readable details, e.g: the per-item results.
+    pub details: serde_json::Value,
+}
+
+impl CommandResult {
+    pub fn success(details: serde_json::Value) -> Self {
+        Self {
+            status: CommandStatus::SUCCESS,
+            message: None,
+            details,
+        }
+    }
+
+    pub fn failure(status: CommandStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: Some(message.into()),
+            details: serde_json::Value::Null,
+        }
+    }
+}
+
+/// The rendered output of the subcommand result.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutput {
+    pub command: String,
+    pub status: CommandStatus,
+    #[serde(rename = "exitCode")]
+    pub exit_code: i32,
+    #[serde(rename = "durationMillis")]
+    pub duration_millis: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    pub details: serde_json::Value,
+}
+
+impl CommandOutput {
+    pub fn new(command: &str, start_time: Instant, result: CommandResult) -> Self {
+        Self {
+            command: command.to_owned(),
+            status: result.status,
+            exit_code: result.status.exit_code(),
+            duration_millis: start_time.elapsed().as_millis(),
+            message: result.message,
+            details: result.details,
+        }
+    }
+
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::JSON => serde_json::to_string(self).unwrap_or_default(),
+            OutputFormat::TEXT => {
+                let mut lines = vec![format!(
+                    "{} {:?} (exit code: {}, duration: {}ms)",
+                    self.command, self.status, self.exit_code, self.duration_millis
+                )];
+                if let Some(message) = &self.message {
+                    lines.push(format!("  {}", message));
+                }
+                if let serde_json::Value::Object(details) = &self.details {
+                    for (key, value) in details {
+                        match value {
+                            serde_json::Value::String(s) => lines.push(format!("  {}: {}", key, s)),
+                            _ => lines.push(format!("  {}: {}", key, value)),
+                        }
+                    }
+                }
+                lines.join("\n")
+            }
+        }
+    }
+
+    /// Write the rendered result to stdout and returns the exit code.
+    pub fn emit(&self, format: OutputFormat) -> i32 {
+        println!("{}", self.render(format));
+        self.exit_code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_stable() {
+        assert_eq!(CommandStatus::SUCCESS.exit_code(), 0);
+        assert_eq!(CommandStatus::FAILURE.exit_code(), 1);
+        assert_eq!(CommandStatus::VALIDATION_FAILURE.exit_code(), 2);
+        assert_eq!(CommandStatus::DEPENDENCY_UNREACHABLE.exit_code(), 3);
+        assert_eq!(CommandStatus::PARTIAL_FAILURE.exit_code(), 4);
+    }
+
+    #[test]
+    fn test_classify_error() {
+        let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused");
+        let e = anyhow::Error::new(sqlx::Error::Io(refused)).context("Failed to sample latency");
+        assert_eq!(classify_error(&e), CommandStatus::DEPENDENCY_UNREACHABLE);
+        assert_eq!(
+            classify_error(&anyhow::Error::new(sqlx::Error::PoolTimedOut)),
+            CommandStatus::DEPENDENCY_UNREACHABLE
+        );
+        assert_eq!(
+            classify_error(&anyhow::Error::new(sqlx::Error::RowNotFound)),
+            CommandStatus::FAILURE
+        );
+        assert_eq!(classify_error(&anyhow::anyhow!("unexpected")), CommandStatus::FAILURE);
+    }
+
+    #[test]
+    fn test_render_json_parseable() {
+        let result = CommandResult::failure(CommandStatus::VALIDATION_FAILURE, "Error parsing config: \"bad\"\nline");
+        let output = CommandOutput::new("server", Instant::now(), result);
+        let rendered = output.render(OutputFormat::JSON);
+        assert!(!rendered.contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(value["command"], "server");
+        assert_eq!(value["status"], "VALIDATION_FAILURE");
+        assert_eq!(value["exitCode"], 2);
+        assert!(value["durationMillis"].is_u64());
+        assert!(value.get("details").is_none());
+    }
+
+    #[test]
+    fn test_render_text() {
+        let result = CommandResult::success(serde_json::json!({ "indexName": "idx_embeddings", "dryRun": true }));
+        let output = CommandOutput::new("reindex-vectors", Instant::now(), result);
+        let rendered = output.render(OutputFormat::TEXT);
+        assert!(rendered.starts_with("reindex-vectors SUCCESS (exit code: 0"));
+        assert!(rendered.contains("  indexName: idx_embeddings"));
+        assert!(rendered.contains("  dryRun: true"));
+    }
+
+    #[test]
+    fn test_output_format_arg() {
+        let command = clap::Command::new("botwaf")
+            .arg(OutputFormat::arg())
+            .subcommand(clap::Command::new("server"));
+        let matches = command
+            .clone()
+            .try_get_matches_from(vec!["botwaf", "server", "--output", "json"])
+            .unwrap();
+        assert_eq!(OutputFormat::from_matches(&matches), OutputFormat::JSON);
+
+        let matches = command.clone().try_get_matches_from(vec!["botwaf", "server"]).unwrap();
+        assert_eq!(OutputFormat::from_matches(&matches), OutputFormat::TEXT);
+
+        let err = command
+            .try_get_matches_from(vec!["botwaf", "--output", "yaml", "server"])
+            .unwrap_err();
+        assert_eq!(err.exit_code(), CommandStatus::VALIDATION_FAILURE.exit_code());
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::cmd::output::{self, CommandResult, CommandStatus};
+use anyhow::{anyhow, Error};
+use botwaf_server::config::config;
+use botwaf_server::modules::llm::handler::vector_maintenance::{IVectorMaintenanceHandler, PgVectorMaintenanceHandler};
+use botwaf_types::modules::llm::knowledge::{VectorIndexType, VectorReindexRequest};
+use botwaf_utils::panics::PanicHelper;
+use clap::{value_parser, Arg, ArgAction, Command};
+
+pub struct ReindexVectorsCommand {}
+
+impl ReindexVectorsCommand {
+    pub const COMMAND_NAME: &'static str = "reindex-vectors";
+
+    pub fn build() -> Command {
+        Command::new(Self::COMMAND_NAME)
+            .about("Rebuild the pgvector embeddings index and report the query latency before and after.")
+            .arg(
+                Arg::new("index-type")
+                    .long("index-type")
+                    .value_parser(["hnsw", "ivfflat"])
+                    .default_value("hnsw")
+                    .help("The vector index type."),
+            )
+            .arg(
+                Arg::new("ops")
+                    .long("ops")
+                    .default_value("vector_cosine_ops")
+                    .help("The vector index operator class, e.g: vector_cosine_ops, vector_l2_ops, vector_ip_ops"),
+            )
+            .arg(
+                Arg::new("m")
+                    .long("m")
+                    .value_parser(value_parser!(u32))
+                    .default_value("16")
+                    .help("The HNSW max number of connections per layer."),
+            )
+            .arg(
+                Arg::new("ef-construction")
+                    .long("ef-construction")
+                    .value_parser(value_parser!(u32))
+                    .default_value("64")
+                    .help("The HNSW size of the dynamic candidate list for constructing the graph."),
+            )
+            .arg(
+                Arg::new("lists")
+                    .long("lists")
+                    .value_parser(value_parser!(u32))
+                    .default_value("100")
+                    .help("The IVFFlat number of inverted lists."),
+            )
+            .arg(
+                Arg::new("samples")
+                    .long("samples")
+                    .value_parser(value_parser!(u32))
+                    .default_value("20")
+                    .help("The number of sample queries for the latency report."),
+            )
+            .arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("Only print the index statement and current latency, do not rebuild."),
+            )
+    }
+
+    #[allow(unused)]
+    #[tokio::main]
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
+        PanicHelper::set_hook_default();
+
+        let param = match Self::parse_request(matches) {
+            Ok(param) => param,
+            Err(e) => return CommandResult::failure(CommandStatus::VALIDATION_FAILURE, e.to_string()),
+        };
+        let maintenance = PgVectorMaintenanceHandler::new(&config::get_config().vecdb.pg_vector);
+        match maintenance.reindex(param).await {
+            Ok(report) => CommandResult::success(serde_json::to_value(report).unwrap_or_default()),
+            Err(e) => CommandResult::failure(
+                output::classify_error(&e),
+                format!("Failed to reindex vectors. cause: {}", e),
+            ),
+        }
+    }
+
+    fn parse_request(matches: &clap::ArgMatches) -> Result<VectorReindexRequest, Error> {
+        let param = VectorReindexRequest {
+            index_type: match matches.get_one::<String>("index-type").map(|s| s.as_str()) {
+                Some("ivfflat") => VectorIndexType::IVFFLAT,
+                _ => VectorIndexType::HNSW,
+            },
+            ops: matches.get_one::<String>("ops").cloned().unwrap_or_default(),
+            m: *matches.get_one::<u32>("m").unwrap(),
+            ef_construction: *matches.get_one::<u32>("ef-construction").unwrap(),
+            lists: *matches.get_one::<u32>("lists").unwrap(),
+            samples: *matches.get_one::<u32>("samples").unwrap(),
+            dry_run: matches.get_flag("dry-run"),
+        };
+        // The operator class is a part of the index statement, so only the identifier is allowed.
+        if param.ops.is_empty() || !param.ops.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!(
+                "Invalid --ops '{}', which should be an operator class name",
+                param.ops
+            ));
+        }
+        if param.samples == 0 {
+            return Err(anyhow!("Invalid --samples, which should be greater than 0"));
+        }
+        Ok(param)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::output::{CommandOutput, OutputFormat};
+    use std::time::Instant;
+
+    #[test]
+    fn test_cli_reindex_defaults() {
+        let matches = ReindexVectorsCommand::build().try_get_matches_from(vec![""]).unwrap();
+        let param = ReindexVectorsCommand::parse_request(&matches).unwrap();
+        assert_eq!(param.index_type, VectorIndexType::HNSW);
+        assert_eq!(param.ops, "vector_cosine_ops");
+        assert_eq!(param.m, 16);
+        assert!(!param.dry_run);
+    }
+
+    #[test]
+    fn test_cli_reindex_ivfflat_dry_run() {
+        let matches = ReindexVectorsCommand::build()
+            .try_get_matches_from(vec!["", "--index-type", "ivfflat", "--lists", "200", "--dry-run"])
+            .unwrap();
+        let param = ReindexVectorsCommand::parse_request(&matches).unwrap();
+        assert_eq!(param.index_type, VectorIndexType::IVFFLAT);
+        assert_eq!(param.lists, 200);
+        assert!(param.dry_run);
+    }
+
+    #[test]
+    fn test_cli_reindex_invalid_args() {
+        let matches = ReindexVectorsCommand::build()
+            .try_get_matches_from(vec!["", "--ops", "vector_cosine_ops; DROP TABLE"])
+            .unwrap();
+        assert!(ReindexVectorsCommand::parse_request(&matches).is_err());
+
+        let matches = ReindexVectorsCommand::build()
+            .try_get_matches_from(vec!["", "--samples", "0"])
+            .unwrap();
+        assert!(ReindexVectorsCommand::parse_request(&matches).is_err());
+    }
+
+    #[test]
+    fn test_cli_reindex_validation_failure_output() {
+        let matches = ReindexVectorsCommand::build()
+            .try_get_matches_from(vec!["", "--samples", "0"])
+            .unwrap();
+        let result = ReindexVectorsCommand::run(&matches, false);
+        assert_eq!(result.status, CommandStatus::VALIDATION_FAILURE);
+
+        let output = CommandOutput::new(ReindexVectorsCommand::COMMAND_NAME, Instant::now(), result);
+        let value: serde_json::Value = serde_json::from_str(&output.render(OutputFormat::JSON)).unwrap();
+        assert_eq!(value["command"], "reindex-vectors");
+        assert_eq!(value["exitCode"], 2);
+    }
+}
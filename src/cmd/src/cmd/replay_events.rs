@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::cmd::output::{self, CommandResult, CommandStatus};
+use anyhow::{anyhow, Error};
+use botwaf_forwarder::stats::replay::{AccessEventsFileSource, AccessEventsReplayJob};
+use botwaf_server::config::config::{self, ReplayProperties};
+use botwaf_server::context::state::BotwafState;
+use botwaf_server::modules::modsec::replay_result::ReplayResultManager;
+use botwaf_types::modules::modsec::replay::ReplayEventsRequest;
+use botwaf_utils::panics::PanicHelper;
+use clap::{value_parser, Arg, Command};
+use hyper::StatusCode;
+use std::sync::Arc;
+
+pub struct ReplayEventsCommand {}
+
+impl ReplayEventsCommand {
+    pub const COMMAND_NAME: &'static str = "replay-events";
+
+    pub fn build() -> Command {
+        Command::new(Self::COMMAND_NAME)
+            .about("Replay the historical access events against the current rules, and record the old/new decisions.")
+            .arg(
+                Arg::new("job-id")
+                    .long("job-id")
+                    .help("The job to be resumed from the checkpoint, generated if not specified."),
+            )
+            .arg(
+                Arg::new("limit")
+                    .long("limit")
+                    .value_parser(value_parser!(u64))
+                    .help("The max events to be replayed of this run, unlimited if not specified."),
+            )
+            .arg(
+                Arg::new("events-file").long("events-file").help(
+                    "The JSON lines of the access events audit trail, defaults to 'services.replay.events-file'.",
+                ),
+            )
+            .arg(
+                Arg::new("rate-per-sec")
+                    .long("rate-per-sec")
+                    .value_parser(value_parser!(u32))
+                    .help("The max replayed events per second, defaults to 'services.replay.rate-per-sec'."),
+            )
+            .arg(
+                Arg::new("concurrency")
+                    .long("concurrency")
+                    .value_parser(value_parser!(usize))
+                    .help("The max simultaneous evaluations, defaults to 'services.replay.concurrency'."),
+            )
+    }
+
+    #[allow(unused)]
+    #[tokio::main]
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
+        PanicHelper::set_hook_default();
+
+        let config = config::get_config();
+        let (param, replay) = match Self::parse_request(matches, &config.services.replay) {
+            Ok(parsed) => parsed,
+            Err(e) => return CommandResult::failure(CommandStatus::VALIDATION_FAILURE, e.to_string()),
+        };
+        if let Err(e) = ReplayResultManager::init(&config).await {
+            return CommandResult::failure(
+                output::classify_error(&e),
+                format!("Failed to init the replay results. cause: {}", e),
+            );
+        }
+        let results = ReplayResultManager::get().expect("The replay results should be initialized");
+        let app_state = match BotwafState::new(&config).await {
+            Ok(app_state) => app_state,
+            Err(e) => return CommandResult::failure(output::classify_error(&e), e.to_string()),
+        };
+        let job = AccessEventsReplayJob::new(
+            &replay,
+            Arc::new(AccessEventsFileSource::new(&replay.events_file)),
+            results,
+            app_state.modsec_engine.to_owned(),
+            app_state.modsec_rules.load_full(),
+            config.services.blocked_status_or(StatusCode::FORBIDDEN),
+        );
+
+        let job_id = param.job_id.unwrap_or_else(AccessEventsReplayJob::new_job_id);
+        match job.run(&job_id, param.limit).await {
+            Ok(progress) => CommandResult::success(serde_json::to_value(progress).unwrap_or_default()),
+            Err(e) => CommandResult::failure(
+                output::classify_error(&e),
+                format!(
+                    "Failed to replay the access events of the job '{}'. cause: {}",
+                    job_id, e
+                ),
+            ),
+        }
+    }
+
+    fn parse_request(
+        matches: &clap::ArgMatches,
+        defaults: &ReplayProperties,
+    ) -> Result<(ReplayEventsRequest, ReplayProperties), Error> {
+        let param = ReplayEventsRequest {
+            job_id: matches.get_one::<String>("job-id").cloned(),
+            limit: matches.get_one::<u64>("limit").copied(),
+        };
+        let replay = ReplayProperties {
+            events_file: matches
+                .get_one::<String>("events-file")
+                .cloned()
+                .unwrap_or_else(|| defaults.events_file.to_owned()),
+            rate_per_sec: matches
+                .get_one::<u32>("rate-per-sec")
+                .copied()
+                .unwrap_or(defaults.rate_per_sec),
+            concurrency: matches
+                .get_one::<usize>("concurrency")
+                .copied()
+                .unwrap_or(defaults.concurrency),
+            batch_size: defaults.batch_size,
+        };
+        if let Some(job_id) = &param.job_id {
+            if job_id.is_empty() || job_id.len() > 64 {
+                return Err(anyhow!("Invalid --job-id '{}', which length should be 1-64", job_id));
+            }
+        }
+        if param.limit == Some(0) {
+            return Err(anyhow!("Invalid --limit, which should be greater than 0"));
+        }
+        if replay.rate_per_sec == 0 || replay.concurrency == 0 {
+            return Err(anyhow!(
+                "Invalid --rate-per-sec or --concurrency, which should be greater than 0"
+            ));
+        }
+        Ok((param, replay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_replay_defaults() {
+        let matches = ReplayEventsCommand::build().try_get_matches_from(vec![""]).unwrap();
+        let defaults = ReplayProperties::default();
+        let (param, replay) = ReplayEventsCommand::parse_request(&matches, &defaults).unwrap();
+        assert_eq!(param, ReplayEventsRequest::default());
+        assert_eq!(replay, defaults);
+    }
+
+    #[test]
+    fn test_cli_replay_overrides() {
+        let matches = ReplayEventsCommand::build()
+            .try_get_matches_from(vec![
+                "",
+                "--job-id",
+                "after-942-rules",
+                "--limit",
+                "1000",
+                "--events-file",
+                "/tmp/access.log",
+                "--rate-per-sec",
+                "50",
+                "--concurrency",
+                "1",
+            ])
+            .unwrap();
+        let (param, replay) = ReplayEventsCommand::parse_request(&matches, &ReplayProperties::default()).unwrap();
+        assert_eq!(param.job_id.as_deref(), Some("after-942-rules"));
+        assert_eq!(param.limit, Some(1000));
+        assert_eq!(replay.events_file, "/tmp/access.log");
+        assert_eq!(replay.rate_per_sec, 50);
+        assert_eq!(replay.concurrency, 1);
+    }
+
+    #[test]
+    fn test_cli_replay_invalid_args() {
+        for args in [
+            vec!["", "--limit", "0"],
+            vec!["", "--rate-per-sec", "0"],
+            vec!["", "--concurrency", "0"],
+        ] {
+            let matches = ReplayEventsCommand::build().try_get_matches_from(args).unwrap();
+            assert!(ReplayEventsCommand::parse_request(&matches, &ReplayProperties::default()).is_err());
+        }
+    }
+}
@@ -18,7 +18,11 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::cmd::management::ManagementServer;
+use crate::cmd::{
+    listener::WebListener,
+    management::ManagementServer,
+    output::{self, CommandResult, CommandStatus},
+};
 use axum::{
     body::Body,
     extract::{Request, State},
@@ -32,18 +36,39 @@ use botwaf_server::{
         swagger,
     },
     context::state::BotwafState,
-    mgmt::{apm, health::init as health_router},
-    modules::llm::handler::llm_base::LLMManager,
-    sys::route::{
-        auth_router::{auth_middleware, init as auth_router},
-        user_router::init as user_router,
+    mgmt::{
+        apm::{self, shutdown_summary::ShutdownSummary},
+        health::init as health_router,
+        routes,
+    },
+    modules::{
+        llm::{handler::llm_base::LLMManager, route::knowledge_router::init as knowledge_router},
+        modsec::{
+            data_file::DataFileManager,
+            route::{data_file_router::init as data_file_router, rule_router::init as rule_router},
+            rule_changeset::RuleChangesetManager,
+            rule_version::RuleVersionManager,
+        },
+    },
+    sys::{
+        dead_letter::DeadLetterManager,
+        route::{
+            auth_router::{auth_middleware, init as auth_router},
+            bootstrap_router::init as bootstrap_router,
+            dead_letter_router::init as dead_letter_router,
+            signing_key_router::init as signing_key_router,
+            stats_router::init as stats_router,
+            user_router::init as user_router,
+        },
+        signing_key::SigningKeyManager,
     },
+    util::{header_limits::header_limits_middleware, login_keypairs::LoginKeyPairPool},
 };
 use botwaf_utils::{panics::PanicHelper, tokio_signal::tokio_graceful_shutdown_signal};
 use clap::Command;
-use common_telemetry::{debug, error, info};
+use common_telemetry::{debug, error, info, warn};
 use std::{env, future::Future, pin::Pin, sync::Arc};
-use tokio::{net::TcpListener, sync::oneshot};
+use tokio::sync::oneshot;
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
@@ -61,7 +86,7 @@ impl WebServer {
 
     #[allow(unused)]
     #[tokio::main]
-    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> () {
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
         PanicHelper::set_hook_default();
 
         let config = config::get_config();
@@ -74,31 +99,73 @@ impl WebServer {
         let (signal_s, signal_r) = oneshot::channel();
         let signal_handle = ManagementServer::start(&config, true, signal_s).await;
 
-        signal_r.await.expect("Failed to start Management server.");
+        if signal_r.await.is_err() {
+            return CommandResult::failure(CommandStatus::FAILURE, "Failed to start Management server.");
+        }
         info!("Management server is started");
 
         // let dummy_addition_middleware = None::<
         //     fn(State<BotwafState>, Request<Body>, Next) -> Pin<Box<dyn Future<Output = IntoResponse> + Send + 'static>>,
         // >;
-        Self::start(&config, true, None, None).await;
+        if let Err(e) = Self::start(&config, true, None, None, None).await {
+            return CommandResult::failure(output::classify_error(&e), e.to_string());
+        }
 
         signal_handle.await.unwrap();
+        CommandResult::success(serde_json::Value::Null)
     }
 
     #[allow(unused)]
     pub async fn start(
         config: &Arc<AppConfig>,
         verbose: bool,
+        app_state: Option<BotwafState>,
         addition_router: Option<Router<BotwafState>>,
         addition_middleware: Option<MiddlewareFunction>,
-    ) {
-        LLMManager::init().await;
+    ) -> Result<(), anyhow::Error> {
+        LLMManager::init(Self::COMMAND_NAME).await;
 
-        let app_state = BotwafState::new(&config).await;
+        // Fallback to the state with the default components, e.g: without the data plane.
+        let app_state = match app_state {
+            Some(app_state) => app_state,
+            None => BotwafState::new(&config).await?,
+        };
+        if let Err(e) = DataFileManager::start_scheduler(app_state.clone()).await {
+            tracing::error!("Failed to start the data files retention scheduler. cause: {}", e);
+        }
+        if let Err(e) = DeadLetterManager::init(&config).await {
+            tracing::error!("Failed to init the dead letters. cause: {}", e);
+        }
+        if let Err(e) = SigningKeyManager::init(&config).await {
+            tracing::error!("Failed to init the request signing keys. cause: {}", e);
+        }
+        // Pre-generate the login RSA keypairs in the background, so that the first logins are served fast.
+        LoginKeyPairPool::get().prefill();
+        match RuleVersionManager::init(&config).await {
+            // Notice: The rules were compiled on the state built, so recompile with the managed rules.
+            Ok(_) => {
+                if RuleVersionManager::get().is_some_and(|m| !m.heads().is_empty()) {
+                    app_state.reload_modsec_rules();
+                }
+            }
+            Err(e) => tracing::error!("Failed to init the rule versions. cause: {}", e),
+        }
+        if let Err(e) = RuleChangesetManager::init(&config).await {
+            tracing::error!("Failed to init the rule changesets. cause: {}", e);
+        }
 
         // 1. Merge the biz modules routes.
         debug!("Register Web server app routers ...");
-        let mut register_router = Router::new().merge(auth_router()).merge(user_router());
+        let mut register_router = Router::new()
+            .merge(auth_router())
+            .merge(bootstrap_router())
+            .merge(user_router())
+            .merge(dead_letter_router())
+            .merge(signing_key_router())
+            .merge(stats_router())
+            .merge(knowledge_router())
+            .merge(rule_router())
+            .merge(data_file_router());
 
         // 1.1 Merge the addition router.
         register_router = if let Some(addition_router) = addition_router {
@@ -130,11 +197,17 @@ impl WebServer {
             }
         };
 
-        // 3. Merge the swagger router.
-        if config.swagger.enabled {
-            debug!("Register Web server swagger middlewares ...");
-            app_router = app_router.merge(swagger::init(&config));
-        }
+        // 3. Merge the swagger router, the openapi.json is always served even if the swagger UI is disabled.
+        debug!("Register Web server swagger middlewares ...");
+        app_router = app_router.merge(swagger::init(&config));
+
+        // 3.1 Assert the management only routes (e.g: metrics, debug, config, schema) are never mounted.
+        routes::check_routes_disjoint(
+            &app_router,
+            &ManagementServer::paths(),
+            config.server.context_path.as_deref(),
+        )
+        .await?;
 
         // 4. Finally add the (auth) middlewares.
         // Notice: The settings of middlewares are in order, which will affect the priority of route matching.
@@ -163,14 +236,39 @@ impl WebServer {
             let layer = axum::middleware::from_fn_with_state(app_state.to_owned(), addition_middleware.unwrap());
             app_router = app_router.layer(layer);
         }
+        // The outermost, so that the requests exceeded the header limits are rejected before any evaluation.
+        app_router = app_router.layer(axum::middleware::from_fn(header_limits_middleware));
         //.route_layer(axum::Extension(app_state));
 
+        // 5. Start the experimental HTTP/3 listener.
+        if config.server.http3.enabled {
+            #[cfg(feature = "http3")]
+            {
+                let router = app_router.to_owned();
+                let server_config = config.server.to_owned();
+                let max_body_bytes = config.services.forward.max_body_bytes;
+                tokio::spawn(async move {
+                    if let Err(e) = WebListener::serve_h3(router, &server_config, max_body_bytes).await {
+                        error!("Error running web HTTP/3 listener: {}", e);
+                    }
+                });
+            }
+            #[cfg(not(feature = "http3"))]
+            warn!("The HTTP/3 listener is enabled but not supported, please rebuild with cargo feature 'http3'.");
+        }
+
         let bind_addr = config.server.get_bind_addr();
         info!("Starting web server on {}", bind_addr);
-        let listener = match TcpListener::bind(&bind_addr).await {
-            Ok(l) => {
-                info!("Web server is ready on {}", bind_addr);
-                l
+        let listeners = match config
+            .server
+            .get_bind_addrs()
+            .and_then(|addrs| WebListener::bind(&addrs))
+        {
+            Ok(listeners) => {
+                for listener in &listeners {
+                    info!("Web server is ready on {}", listener.local_addr()?);
+                }
+                listeners
             }
             Err(e) => {
                 error!("Failed to bind to {}: {}", bind_addr, e);
@@ -178,19 +276,17 @@ impl WebServer {
             }
         };
 
-        match axum::serve(listener, app_router.into_make_service())
-            .with_graceful_shutdown(tokio_graceful_shutdown_signal())
-            // .tcp_nodelay(true)
-            .await
-        {
+        match WebListener::serve_all(listeners, app_router, &config.server, tokio_graceful_shutdown_signal()).await {
             Ok(_) => {
                 info!("Web server shut down gracefully");
+                ShutdownSummary::collect("server").log();
             }
             Err(e) => {
                 error!("Error running web server: {}", e);
                 panic!("Error starting API server: {}", e);
             }
         }
+        Ok(())
     }
 
     fn print_banner(config: Arc<AppConfig>, verbose: bool) {
@@ -217,7 +313,9 @@ impl WebServer {
         eprintln!("        Configuration file path: {:?}", path);
         eprintln!(
             "            Web Serve listen on: \"{}://{}:{}\"",
-            "http", &config.server.host, config.server.port
+            if config.server.tls.enabled { "https" } else { "http" },
+            &config.server.host,
+            config.server.port
         );
         if config.mgmt.enabled {
             eprintln!(
@@ -242,7 +340,7 @@ impl WebServer {
             }
         }
         if verbose {
-            let config_json = serde_json::to_string(&config.inner).unwrap_or_default();
+            let config_json = config.inner.to_masked_json();
             eprintln!("Configuration loaded: {}", config_json);
         }
         eprintln!("");
@@ -20,30 +20,55 @@
 
 use super::server::WebServer;
 use crate::cmd::management::ManagementServer;
+use crate::cmd::output::{self, CommandResult, CommandStatus};
+use anyhow::Context;
 use axum::body::Body;
 use axum::extract::{Request, State};
 use axum::http::Response;
 use axum::middleware::Next;
+use botwaf_forwarder::access_writer::AccessEventWriter;
 use botwaf_forwarder::forwarder_base::BotwafForwarderManager;
+use botwaf_forwarder::headers::header_filter_router::{self, HeaderFilterApiDoc};
+use botwaf_forwarder::ipfilter::ipfilter_router::{self, IPFilterApiDoc};
+use botwaf_forwarder::probe_synthetic::SyntheticProber;
+use botwaf_forwarder::stats::event_export_router::{self, EventExportApiDoc};
+use botwaf_forwarder::stats::event_stream_router::{self, EventStreamApiDoc};
+use botwaf_forwarder::stats::replay_router::{self, ReplayApiDoc};
+use botwaf_forwarder::stats::topk_router::{self, TopKApiDoc};
 use botwaf_server::config::config::AppConfig;
+use botwaf_server::config::swagger;
 use botwaf_server::context::state::BotwafState;
+use botwaf_server::mgmt::supervisor::{ComponentSupervisor, RestartPolicy};
 use botwaf_server::modules::llm::handler::llm_base::LLMManager;
+use botwaf_server::modules::modsec::replay_result::ReplayResultManager;
+use botwaf_server::sys::dead_letter::DeadLetterManager;
 use botwaf_server::{
     config::config::{self, GIT_BUILD_DATE, GIT_COMMIT_HASH, GIT_VERSION},
     mgmt::apm,
 };
-use botwaf_updater::updater_base::BotwafUpdaterManager;
+use botwaf_updater::{
+    updater_base::BotwafUpdaterManager,
+    updater_router::{self, UpdaterApiDoc},
+};
 use botwaf_utils::panics::PanicHelper;
-use botwaf_verifier::verifier_base::BotwafVerifierManager;
+use botwaf_verifier::{
+    verifier_base::BotwafVerifierManager,
+    verifier_router::{self, VerifierApiDoc},
+};
 use clap::Command;
 use std::env;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::oneshot;
+use utoipa::OpenApi;
 
 pub struct StandaloneServer {}
 
+const COMPONENT_WEB_SERVER: &str = "web-server";
+const COMPONENT_UPDATER: &str = "updater";
+const COMPONENT_VERIFIER: &str = "verifier";
+
 impl StandaloneServer {
     pub const COMMAND_NAME: &'static str = "standalone";
 
@@ -53,7 +78,7 @@ impl StandaloneServer {
 
     #[allow(unused)]
     #[tokio::main]
-    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> () {
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
         PanicHelper::set_hook_default();
 
         let config = config::get_config();
@@ -66,21 +91,99 @@ impl StandaloneServer {
         let (signal_s, signal_r) = oneshot::channel();
         let signal_handle = ManagementServer::start(&config, true, signal_s).await;
 
-        signal_r.await.expect("Failed to start Management server.");
+        if signal_r.await.is_err() {
+            return CommandResult::failure(CommandStatus::FAILURE, "Failed to start Management server.");
+        }
         tracing::info!("Management server is ready on {}", config.mgmt.get_bind_addr());
 
-        Self::start(&config, true).await;
+        if let Err(e) = Self::start(&config, true).await {
+            return CommandResult::failure(output::classify_error(&e), e.to_string());
+        }
 
         signal_handle.await.unwrap();
+        CommandResult::success(serde_json::Value::Null)
     }
 
     #[allow(unused)]
-    async fn start(config: &Arc<AppConfig>, verbose: bool) {
-        LLMManager::init().await;
-        BotwafUpdaterManager::init().await;
-        BotwafVerifierManager::init().await;
+    async fn start(config: &Arc<AppConfig>, verbose: bool) -> Result<(), anyhow::Error> {
+        LLMManager::init(Self::COMMAND_NAME).await;
+        // The background components are restarted on failure, e.g: the transient vector DB outage.
+        Self::spawn_supervised(COMPONENT_UPDATER, || async {
+            BotwafUpdaterManager::init().await;
+            Ok::<(), anyhow::Error>(())
+        });
+        Self::spawn_supervised(COMPONENT_VERIFIER, || async {
+            BotwafVerifierManager::init().await;
+            Ok::<(), anyhow::Error>(())
+        });
         BotwafForwarderManager::init().await;
-        WebServer::start(config, verbose, None, Some(Self::wrapped_botwaf_middleware)).await;
+        if let Err(e) = ReplayResultManager::init(config).await {
+            tracing::error!("Failed to init the replay results. cause: {}", e);
+        }
+        // Wire the data plane components into the state for the botwaf middleware.
+        let app_state = BotwafForwarderManager::wire(BotwafState::builder().with_config(config))
+            .context("Failed to wire the Botwaf forwarder components")?
+            .build()
+            .await?;
+        Self::start_probes(config, &app_state).await;
+        // Register the API docs of the addition routers into the aggregated OpenAPI spec.
+        swagger::register(IPFilterApiDoc::openapi());
+        swagger::register(TopKApiDoc::openapi());
+        swagger::register(EventStreamApiDoc::openapi());
+        swagger::register(EventExportApiDoc::openapi());
+        swagger::register(ReplayApiDoc::openapi());
+        swagger::register(HeaderFilterApiDoc::openapi());
+        swagger::register(UpdaterApiDoc::openapi());
+        swagger::register(VerifierApiDoc::openapi());
+        // The web server is never restarted, its failure takes the process down.
+        ComponentSupervisor::get().mark_running(COMPONENT_WEB_SERVER, RestartPolicy::NEVER);
+        WebServer::start(
+            config,
+            verbose,
+            Some(app_state),
+            Some(
+                ipfilter_router::init()
+                    .merge(topk_router::init())
+                    .merge(event_stream_router::init())
+                    .merge(event_export_router::init())
+                    .merge(replay_router::init())
+                    .merge(header_filter_router::init())
+                    .merge(updater_router::init())
+                    .merge(verifier_router::init()),
+            ),
+            Some(Self::wrapped_botwaf_middleware),
+        )
+        .await?;
+        // Flush the pending access events (and the failed into dead letters) before exit.
+        AccessEventWriter::get().flush().await;
+        if let Some(dead_letters) = DeadLetterManager::get() {
+            dead_letters.flush().await;
+        }
+        Ok(())
+    }
+
+    fn spawn_supervised<F, Fut>(name: &'static str, start: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        tokio::spawn(async move {
+            // The failed permanently is reported by the readiness and the notification.
+            let _ = ComponentSupervisor::get()
+                .supervise(name, RestartPolicy::ON_FAILURE, start)
+                .await;
+        });
+    }
+
+    // Probe the live rules of the forwarder state, so that the reloaded rules are also probed.
+    async fn start_probes(config: &Arc<AppConfig>, app_state: &BotwafState) {
+        if !config.services.probe.enabled {
+            return;
+        }
+        let prober = SyntheticProber::new(&config.services.probe, app_state);
+        if let Err(e) = prober.start().await {
+            tracing::error!("Failed to start the synthetic probes. cause: {}", e);
+        }
     }
 
     fn wrapped_botwaf_middleware(
@@ -140,7 +243,7 @@ impl StandaloneServer {
             }
         }
         if verbose {
-            let config_json = serde_json::to_string(&config.inner).unwrap_or_default();
+            let config_json = config.inner.to_masked_json();
             eprintln!("Configuration loaded: {}", config_json);
         }
         eprintln!("");
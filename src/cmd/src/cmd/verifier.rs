@@ -18,7 +18,9 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use super::listener::WebListener;
 use crate::cmd::management::ManagementServer;
+use crate::cmd::output::{self, CommandResult, CommandStatus};
 use axum::Router;
 use botwaf_server::config::config::AppConfig;
 use botwaf_server::context::state::BotwafState;
@@ -26,7 +28,7 @@ use botwaf_server::mgmt::health::init as health_router;
 use botwaf_server::modules::llm::handler::llm_base::LLMManager;
 use botwaf_server::{
     config::config::{self, GIT_BUILD_DATE, GIT_COMMIT_HASH, GIT_VERSION},
-    mgmt::apm,
+    mgmt::apm::{self, shutdown_summary::ShutdownSummary},
 };
 use botwaf_utils::panics::PanicHelper;
 use botwaf_utils::tokio_signal::tokio_graceful_shutdown_signal;
@@ -34,7 +36,6 @@ use botwaf_verifier::verifier_base::BotwafVerifierManager;
 use clap::Command;
 use std::env;
 use std::sync::Arc;
-use tokio::net::TcpListener;
 use tokio::sync::oneshot;
 
 pub struct BotwafVerifierServer {}
@@ -48,7 +49,7 @@ impl BotwafVerifierServer {
 
     #[allow(unused)]
     #[tokio::main]
-    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> () {
+    pub async fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
         PanicHelper::set_hook_default();
 
         let config = config::get_config();
@@ -61,27 +62,38 @@ impl BotwafVerifierServer {
         let (signal_s, signal_r) = oneshot::channel();
         let signal_handle = ManagementServer::start(&config, true, signal_s).await;
 
-        signal_r.await.expect("Failed to start Management server.");
+        if signal_r.await.is_err() {
+            return CommandResult::failure(CommandStatus::FAILURE, "Failed to start Management server.");
+        }
         tracing::info!("Management server is ready on {}", config.mgmt.get_bind_addr());
 
-        Self::start(&config, true).await;
+        if let Err(e) = Self::start(&config, true).await {
+            return CommandResult::failure(output::classify_error(&e), e.to_string());
+        }
 
         signal_handle.await.unwrap();
+        CommandResult::success(serde_json::Value::Null)
     }
 
     #[allow(unused)]
-    async fn start(config: &Arc<AppConfig>, verbose: bool) {
-        LLMManager::init().await;
+    async fn start(config: &Arc<AppConfig>, verbose: bool) -> Result<(), anyhow::Error> {
+        LLMManager::init(Self::COMMAND_NAME).await;
         BotwafVerifierManager::init().await;
 
-        let app_state = BotwafState::new(&config).await;
+        let app_state = BotwafState::new(&config).await?;
 
         let bind_addr = config.server.get_bind_addr();
         tracing::info!("Starting Botwaf Verifier server on {}", bind_addr);
-        let listener = match TcpListener::bind(&bind_addr).await {
-            Ok(l) => {
-                tracing::info!("Botwaf Verifier server is ready on {}", bind_addr);
-                l
+        let listeners = match config
+            .server
+            .get_bind_addrs()
+            .and_then(|addrs| WebListener::bind(&addrs))
+        {
+            Ok(listeners) => {
+                for listener in &listeners {
+                    tracing::info!("Botwaf Verifier server is ready on {}", listener.local_addr()?);
+                }
+                listeners
             }
             Err(e) => {
                 tracing::error!("Failed to bind to {}: {}", bind_addr, e);
@@ -90,19 +102,17 @@ impl BotwafVerifierServer {
         };
 
         let app_router = Router::new().merge(health_router()).with_state(app_state);
-        match axum::serve(listener, app_router.into_make_service())
-            .with_graceful_shutdown(tokio_graceful_shutdown_signal())
-            // .tcp_nodelay(true)
-            .await
-        {
+        match WebListener::serve_plain_all(listeners, app_router, tokio_graceful_shutdown_signal()).await {
             Ok(_) => {
                 tracing::info!("Botwaf Verifier server shut down gracefully");
+                ShutdownSummary::collect("verifier").log();
             }
             Err(e) => {
                 tracing::error!("Error running web server: {}", e);
                 panic!("Error start Botwaf Verifier server: {}", e);
             }
         }
+        Ok(())
     }
 
     fn print_banner(config: Arc<AppConfig>, verbose: bool) {
@@ -154,7 +164,7 @@ impl BotwafVerifierServer {
             }
         }
         if verbose {
-            let config_json = serde_json::to_string(&config.inner).unwrap_or_default();
+            let config_json = config.inner.to_masked_json();
             eprintln!("Configuration loaded: {}", config_json);
         }
         eprintln!("");
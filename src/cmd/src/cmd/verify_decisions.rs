@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::cmd::output::{CommandResult, CommandStatus};
+use anyhow::{Context, Error};
+use botwaf_forwarder::decision_harness::DecisionHarness;
+use botwaf_server::config::config;
+use botwaf_server::modules::modsec::{body_processor::BODY_PROCESSOR_RULES, rule_loader};
+use botwaf_utils::panics::PanicHelper;
+use clap::{Arg, ArgAction, Command};
+use modsecurity::{ModSecurity, Rules};
+use std::path::{Path, PathBuf};
+
+pub struct VerifyDecisionsCommand {}
+
+#[derive(Debug, Clone, PartialEq)]
+struct VerifyDecisionsRequest {
+    corpus: PathBuf,
+    rules: Option<PathBuf>,
+    update_golden: bool,
+}
+
+impl VerifyDecisionsCommand {
+    pub const COMMAND_NAME: &'static str = "verify-decisions";
+
+    pub fn build() -> Command {
+        Command::new(Self::COMMAND_NAME)
+            .about("Verify the decisions of the request fixtures corpus against the golden (expected) decisions.")
+            .arg(
+                Arg::new("corpus")
+                    .long("corpus")
+                    .required(true)
+                    .help("The YAML (or JSON) corpus of the request fixtures with the expected decisions."),
+            )
+            .arg(Arg::new("rules").long("rules").help(
+                "The ModSecurity rules file to be evaluated, defaults to the 'services.static-rules' of the config.",
+            ))
+            .arg(
+                Arg::new("update-golden")
+                    .long("update-golden")
+                    .action(ArgAction::SetTrue)
+                    .help("Regenerate the expected decisions of the corpus with the actual decisions."),
+            )
+    }
+
+    #[allow(unused)]
+    pub fn run(matches: &clap::ArgMatches, verbose: bool) -> CommandResult {
+        PanicHelper::set_hook_default();
+
+        let param = Self::parse_request(matches);
+        let rules = match &param.rules {
+            Some(path) => match Self::load_rules_file(path) {
+                Ok(rules) => rules,
+                Err(e) => return CommandResult::failure(CommandStatus::VALIDATION_FAILURE, e.to_string()),
+            },
+            None => rule_loader::load_rules(&config::get_config()).0,
+        };
+        let engine = ModSecurity::default();
+        match DecisionHarness::new(&engine, &rules).verify_file(&param.corpus, param.update_golden) {
+            Ok(report) if !param.update_golden && report.is_drifted() => {
+                CommandResult::failure(CommandStatus::FAILURE, report.to_string())
+            }
+            Ok(report) => CommandResult::success(serde_json::to_value(report).unwrap_or_default()),
+            Err(e) => CommandResult::failure(
+                CommandStatus::VALIDATION_FAILURE,
+                format!("Failed to verify the decisions corpus. cause: {:#}", e),
+            ),
+        }
+    }
+
+    fn parse_request(matches: &clap::ArgMatches) -> VerifyDecisionsRequest {
+        VerifyDecisionsRequest {
+            corpus: PathBuf::from(matches.get_one::<String>("corpus").expect("The corpus is required")),
+            rules: matches.get_one::<String>("rules").map(PathBuf::from),
+            update_golden: matches.get_flag("update-golden"),
+        }
+    }
+
+    fn load_rules_file(path: &Path) -> Result<Rules, Error> {
+        let plain =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read the rules {}", path.display()))?;
+        let mut rules = Rules::new();
+        rules
+            .add_plain(BODY_PROCESSOR_RULES)
+            .map_err(|e| anyhow::anyhow!("Failed to add body processor rules. cause: {}", e))?;
+        rules
+            .add_plain(&plain)
+            .map_err(|e| anyhow::anyhow!("Invalid the rules {}. cause: {}", path.display(), e))?;
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_verify_decisions_args() {
+        let matches = VerifyDecisionsCommand::build()
+            .try_get_matches_from(vec!["", "--corpus", "corpus.yaml"])
+            .unwrap();
+        assert_eq!(
+            VerifyDecisionsCommand::parse_request(&matches),
+            VerifyDecisionsRequest {
+                corpus: PathBuf::from("corpus.yaml"),
+                rules: None,
+                update_golden: false,
+            }
+        );
+
+        let matches = VerifyDecisionsCommand::build()
+            .try_get_matches_from(vec![
+                "",
+                "--corpus",
+                "corpus.json",
+                "--rules",
+                "crs.conf",
+                "--update-golden",
+            ])
+            .unwrap();
+        let param = VerifyDecisionsCommand::parse_request(&matches);
+        assert_eq!(param.rules, Some(PathBuf::from("crs.conf")));
+        assert!(param.update_golden);
+
+        assert!(VerifyDecisionsCommand::build().try_get_matches_from(vec![""]).is_err());
+    }
+}
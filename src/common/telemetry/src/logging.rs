@@ -33,9 +33,10 @@ use tracing_appender::non_blocking::WorkerGuard;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_log::LogTracer;
 use tracing_subscriber::filter::{FilterFn, Targets};
-use tracing_subscriber::fmt::Layer;
+use tracing_subscriber::fmt::{Layer, MakeWriter};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{filter, EnvFilter, Registry};
 
 pub const DEFAULT_LOG_TARGETS: &str = "info";
@@ -55,8 +56,15 @@ pub struct LoggingOptions {
     pub level: Option<String>,
 
     /// The log format that can be one of "json" or "text". Default is "text".
+    /// It's the format of the stdout appender if not a TTY and the `stdout_format` is not set.
     pub log_format: LogFormat,
 
+    /// The log format of the stdout appender. Default is "text" if the stdout is a TTY, otherwise the `log_format`.
+    pub stdout_format: Option<LogFormat>,
+
+    /// The log format of the file appenders. Default is "json" for shipping.
+    pub file_format: Option<LogFormat>,
+
     /// The maximum number of log files set by default.
     pub max_log_files: usize,
 
@@ -117,6 +125,8 @@ impl Default for LoggingOptions {
             dir: "./botwaf/logs".to_string(),
             level: None,
             log_format: LogFormat::Text,
+            stdout_format: None,
+            file_format: None,
             enable_otlp_tracing: false,
             otlp_endpoint: None,
             tracing_sample_ratio: None,
@@ -128,6 +138,24 @@ impl Default for LoggingOptions {
     }
 }
 
+impl LoggingOptions {
+    /// The resolved log format of the stdout appender, i.e: human-readable in the terminal by default.
+    pub fn get_stdout_format(&self) -> LogFormat {
+        self.stdout_format.unwrap_or_else(|| {
+            if atty::is(atty::Stream::Stdout) {
+                LogFormat::Text
+            } else {
+                self.log_format
+            }
+        })
+    }
+
+    /// The resolved log format of the file appenders.
+    pub fn get_file_format(&self) -> LogFormat {
+        self.file_format.unwrap_or(LogFormat::Json)
+    }
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TracingOptions {
     #[cfg(feature = "profiling-tokio-console")]
@@ -153,22 +181,11 @@ pub fn init_global_logging(
             let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
             guards.push(guard);
 
-            if opts.log_format == LogFormat::Json {
-                Some(
-                    Layer::new()
-                        .json()
-                        .with_writer(writer)
-                        .with_ansi(atty::is(atty::Stream::Stdout))
-                        .boxed(),
-                )
-            } else {
-                Some(
-                    Layer::new()
-                        .with_writer(writer)
-                        .with_ansi(atty::is(atty::Stream::Stdout))
-                        .boxed(),
-                )
-            }
+            Some(build_format_layer(
+                opts.get_stdout_format(),
+                writer,
+                atty::is(atty::Stream::Stdout),
+            ))
         } else {
             None
         };
@@ -184,11 +201,7 @@ pub fn init_global_logging(
             let (writer, guard) = tracing_appender::non_blocking(rolling_appender);
             guards.push(guard);
 
-            if opts.log_format == LogFormat::Json {
-                Some(Layer::new().json().with_writer(writer).with_ansi(false).boxed())
-            } else {
-                Some(Layer::new().with_writer(writer).with_ansi(false).boxed())
-            }
+            Some(build_format_layer(opts.get_file_format(), writer, false))
         } else {
             None
         };
@@ -204,24 +217,11 @@ pub fn init_global_logging(
             let (writer, guard) = tracing_appender::non_blocking(rolling_appender);
             guards.push(guard);
 
-            if opts.log_format == LogFormat::Json {
-                Some(
-                    Layer::new()
-                        .json()
-                        .with_writer(writer)
-                        .with_ansi(false)
-                        .with_filter(filter::LevelFilter::ERROR)
-                        .boxed(),
-                )
-            } else {
-                Some(
-                    Layer::new()
-                        .with_writer(writer)
-                        .with_ansi(false)
-                        .with_filter(filter::LevelFilter::ERROR)
-                        .boxed(),
-                )
-            }
+            Some(
+                build_format_layer(opts.get_file_format(), writer, false)
+                    .with_filter(filter::LevelFilter::ERROR)
+                    .boxed(),
+            )
         } else {
             None
         };
@@ -240,24 +240,11 @@ pub fn init_global_logging(
             let slow_query_filter =
                 FilterFn::new(|metadata| metadata.fields().iter().any(|field| field.name().contains("slow")));
 
-            if opts.log_format == LogFormat::Json {
-                Some(
-                    Layer::new()
-                        .json()
-                        .with_writer(writer)
-                        .with_ansi(false)
-                        .with_filter(slow_query_filter)
-                        .boxed(),
-                )
-            } else {
-                Some(
-                    Layer::new()
-                        .with_writer(writer)
-                        .with_ansi(false)
-                        .with_filter(slow_query_filter)
-                        .boxed(),
-                )
-            }
+            Some(
+                build_format_layer(opts.get_file_format(), writer, false)
+                    .with_filter(slow_query_filter)
+                    .boxed(),
+            )
         } else {
             None
         };
@@ -364,3 +351,96 @@ pub fn init_global_logging(
 
     guards
 }
+
+/// Build the formatting layer of the appender with the log format.
+fn build_format_layer<S, W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => Layer::new().json().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Text => Layer::new().with_writer(writer).with_ansi(ansi).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TestWriter {
+        fn output(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).trim().to_string()
+        }
+    }
+
+    #[test]
+    fn test_resolve_format_per_appender() {
+        let opts = LoggingOptions::default();
+        assert_eq!(opts.get_file_format(), LogFormat::Json);
+
+        let opts = LoggingOptions {
+            log_format: LogFormat::Json,
+            stdout_format: Some(LogFormat::Text),
+            file_format: Some(LogFormat::Text),
+            ..Default::default()
+        };
+        assert_eq!(opts.get_stdout_format(), LogFormat::Text);
+        assert_eq!(opts.get_file_format(), LogFormat::Text);
+    }
+
+    #[test]
+    fn test_format_layers_differ_per_appender() {
+        let opts = LoggingOptions {
+            stdout_format: Some(LogFormat::Text),
+            ..Default::default()
+        };
+        let (stdout, file) = (TestWriter::default(), TestWriter::default());
+        let (stdout_writer, file_writer) = (stdout.clone(), file.clone());
+        let subscriber = Registry::default()
+            .with(build_format_layer(
+                opts.get_stdout_format(),
+                move || stdout_writer.clone(),
+                false,
+            ))
+            .with(build_format_layer(
+                opts.get_file_format(),
+                move || file_writer.clone(),
+                false,
+            ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(appender = "test", "hello appenders");
+        });
+
+        let stdout = stdout.output();
+        assert!(stdout.contains("hello appenders"), "{}", stdout);
+        assert!(
+            serde_json::from_str::<serde_json::Value>(&stdout).is_err(),
+            "{}",
+            stdout
+        );
+        let file: serde_json::Value = serde_json::from_str(&file.output()).unwrap();
+        assert_eq!(file["fields"]["message"], "hello appenders");
+        assert_eq!(file["fields"]["appender"], "test");
+    }
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::access_writer::AccessEventWriter;
+use botwaf_server::{
+    config::config::{self, DataProtectionProperties},
+    modules::privacy::data_protector::DataProtector,
+};
+use botwaf_types::modules::forward::{
+    access_event::{BotwafAccessEvent, ResponseRewriteSummary},
+    forwarder::HttpIncomingRequest,
+};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+lazy_static! {
+    // The in-process access events bus, the subscribers (e.g: persistence, webhooks) only
+    // ever receive the protected events.
+    static ref ACCESS_EVENT_BUS: broadcast::Sender<Arc<BotwafAccessEvent>> = broadcast::channel(1024).0;
+    static ref SINGLE_INSTANCE: AccessEventRecorder = AccessEventRecorder::new(
+        &config::get_config().services.data_protection,
+        config::get_config().server.limits.max_event_header_bytes
+    );
+}
+
+/// The access events sink, which scrubs the PII before the events leave the request path.
+pub struct AccessEventRecorder {
+    protector: DataProtector,
+    // The max bytes of each recorded header value, see: ServerLimitsProperties::max_event_header_bytes
+    max_header_bytes: usize,
+}
+
+impl AccessEventRecorder {
+    pub fn new(config: &DataProtectionProperties, max_header_bytes: usize) -> Self {
+        AccessEventRecorder {
+            protector: DataProtector::new(config),
+            max_header_bytes,
+        }
+    }
+
+    pub fn get() -> &'static AccessEventRecorder {
+        &SINGLE_INSTANCE
+    }
+
+    pub fn subscribe() -> broadcast::Receiver<Arc<BotwafAccessEvent>> {
+        ACCESS_EVENT_BUS.subscribe()
+    }
+
+    /// Record the access event after the decision was made with the raw incoming request, the event is
+    /// inserted asynchronously by the writer, see: access_writer::AccessEventWriter
+    pub async fn record(
+        &self,
+        incoming: &HttpIncomingRequest,
+        start_time: u64,
+        status: StatusCode,
+    ) -> Arc<BotwafAccessEvent> {
+        self.record_with_rule(incoming, start_time, status, None).await
+    }
+
+    /// Record the access event of the blocked request with the matched rule id.
+    pub async fn record_with_rule(
+        &self,
+        incoming: &HttpIncomingRequest,
+        start_time: u64,
+        status: StatusCode,
+        rule_id: Option<String>,
+    ) -> Arc<BotwafAccessEvent> {
+        let mut event = BotwafAccessEvent::from_incoming(incoming, start_time);
+        event.rule_id = rule_id;
+        self.publish(event, status).await
+    }
+
+    /// Record the access event of the rewritten response once the body is streamed, with the fired patterns,
+    /// see: response_rewrite::ResponseRewriteOutcome
+    pub async fn record_with_rewrite(
+        &self,
+        incoming: &HttpIncomingRequest,
+        start_time: u64,
+        status: StatusCode,
+        rewrite: ResponseRewriteSummary,
+    ) -> Arc<BotwafAccessEvent> {
+        let mut event = BotwafAccessEvent::from_incoming(incoming, start_time);
+        event.resp_rewrite = Some(rewrite).filter(|r| !r.is_empty());
+        self.publish(event, status).await
+    }
+
+    async fn publish(&self, mut event: BotwafAccessEvent, status: StatusCode) -> Arc<BotwafAccessEvent> {
+        event.resp_status_code = Some(status.as_u16() as i32);
+        event.duration = Some((chrono::Utc::now().timestamp_millis() as u64).saturating_sub(event.start_time));
+        event.truncate_headers(self.max_header_bytes);
+
+        // Must be protected before the audit trail and publishing.
+        let event = Arc::new(self.protector.protect(&event));
+        AccessEventWriter::get().write(event.to_owned()).await;
+
+        // Ignore the error that there is no any subscribers.
+        let _ = ACCESS_EVENT_BUS.send(event.to_owned());
+        event
+    }
+
+    /// The scrubbed query string for logging, e.g: user=jack&password=******
+    pub fn scrub_query(&self, query: &Option<String>) -> String {
+        query
+            .as_ref()
+            .and_then(|q| self.protector.scrub_query(q))
+            .unwrap_or_default()
+    }
+
+    pub fn to_audit_line(event: &BotwafAccessEvent) -> String {
+        serde_json::to_string(event).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+
+    #[tokio::test]
+    async fn test_record_password_masked_everywhere() {
+        let recorder = AccessEventRecorder::new(&DataProtectionProperties::default(), 0);
+        let mut subscriber = AccessEventRecorder::subscribe();
+
+        let incoming = TestIncomingBuilder::new("POST", "/login")
+            .header("referer", "http://example.com/login?password=hunter2")
+            .query("user=jack&password=hunter2")
+            .body("user=jack&password=hunter2")
+            .client_ip("198.51.100.23")
+            .build();
+        let recorded = recorder
+            .record(&incoming, chrono::Utc::now().timestamp_millis() as u64, StatusCode::OK)
+            .await;
+
+        // The audit trail.
+        let audit_line = AccessEventRecorder::to_audit_line(&recorded);
+        assert!(!audit_line.contains("hunter2"));
+        assert!(!audit_line.contains("198.51.100.23"));
+        assert_eq!(recorded.query, Some(String::from("user=jack&password=******")));
+        assert_eq!(recorded.body, Some(String::from("user=jack&password=******")));
+        assert_eq!(recorder.scrub_query(&incoming.query), "user=jack&password=******");
+
+        // The events bus.
+        let published = subscriber.recv().await.unwrap();
+        assert!(!AccessEventRecorder::to_audit_line(&published).contains("hunter2"));
+
+        // The raw values are still available in-memory for the blocking decision.
+        assert_eq!(incoming.query, Some(String::from("user=jack&password=hunter2")));
+    }
+
+    #[tokio::test]
+    async fn test_record_large_cookie_truncated() {
+        let recorder = AccessEventRecorder::new(&DataProtectionProperties::default(), 4096);
+
+        // The 64 KB cookie and the claims of the SSO deployments.
+        let cookie = format!("sso_session={}", "x".repeat(64 * 1024));
+        let claims = "c".repeat(64 * 1024);
+        let incoming = TestIncomingBuilder::new("GET", "/sso/callback")
+            .header("cookie", &cookie)
+            .header("x-sso-claims", &claims)
+            .header("user-agent", "curl/8.0")
+            .client_ip("198.51.100.23")
+            .build();
+        let recorded = recorder
+            .record(&incoming, chrono::Utc::now().timestamp_millis() as u64, StatusCode::OK)
+            .await;
+
+        assert!(recorded.headers_truncated);
+        let headers = recorded.headers.as_ref().unwrap();
+        // The sensitive cookie is still masked.
+        assert_eq!(headers.get("cookie").unwrap().as_deref(), Some("******"));
+        let recorded_claims = headers.get("x-sso-claims").unwrap().as_ref().unwrap();
+        assert_eq!(recorded_claims.len(), 4096);
+        assert!(claims.starts_with(recorded_claims.as_str()));
+        assert_eq!(headers.get("user-agent").unwrap().as_deref(), Some("curl/8.0"));
+        // The raw header is still available in-memory for the blocking decision.
+        assert_eq!(
+            incoming.headers.get("cookie").unwrap().as_ref().unwrap().len(),
+            cookie.len()
+        );
+
+        // The small headers are never flagged.
+        let mut incoming = incoming;
+        incoming.headers.remove("cookie");
+        incoming.headers.remove("x-sso-claims");
+        let recorded = recorder.record(&incoming, 0, StatusCode::OK).await;
+        assert!(!recorded.headers_truncated);
+    }
+}
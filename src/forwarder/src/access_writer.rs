@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_server::{
+    config::config::{self, EventOverflowPolicy, EventWriterProperties},
+    mgmt::apm::metrics::{BOTWAF_EVENT_WRITER_DROPPED_TOTAL, BOTWAF_EVENT_WRITER_QUEUE_DEPTH},
+    sys::dead_letter::{DeadLetterManager, IDeadLetterReplayer},
+};
+use botwaf_types::modules::forward::access_event::BotwafAccessEvent;
+use lazy_static::lazy_static;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+pub const DEAD_LETTER_KIND_ACCESS_EVENTS: &str = "ACCESS_EVENTS";
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: AccessEventWriter = {
+        let config = config::get_config();
+        AccessEventWriter::new(
+            &config.services.event_writer,
+            config.services.event_writer.get_channel_size(&config.services.updaters),
+            Arc::new(AuditLogAccessEventSink {}),
+        )
+    };
+}
+
+/// The destination of the access events batch inserts.
+#[async_trait]
+pub trait IAccessEventSink: Send + Sync {
+    async fn insert_batch(&self, events: &[Arc<BotwafAccessEvent>]) -> Result<(), Error>;
+}
+
+/// The default sink which writes the protected events into the audit trail.
+pub struct AuditLogAccessEventSink {}
+
+#[async_trait]
+impl IAccessEventSink for AuditLogAccessEventSink {
+    async fn insert_batch(&self, events: &[Arc<BotwafAccessEvent>]) -> Result<(), Error> {
+        for event in events {
+            let line = serde_json::to_string(event.as_ref()).unwrap_or_default();
+            tracing::info!(target: "botwaf::access", "[Botwaf] [AccessEvent] - {}", line);
+        }
+        Ok(())
+    }
+}
+
+/// Replay the dead letters of the failed access events batch inserts through the writer sink.
+pub struct AccessEventsReplayer {}
+
+#[async_trait]
+impl IDeadLetterReplayer for AccessEventsReplayer {
+    async fn replay(&self, payload: &str) -> Result<(), Error> {
+        let events: Vec<Arc<BotwafAccessEvent>> = serde_json::from_str(payload)?;
+        AccessEventWriter::get().sink.insert_batch(&events).await
+    }
+}
+
+enum WriterMessage {
+    Event(Arc<BotwafAccessEvent>),
+    // Insert the pending events, and then acknowledge.
+    Flush(oneshot::Sender<()>),
+}
+
+/// The asynchronous access events writer, which decouples the inserts from the request path with the
+/// bounded channel, the overflowed events are dropped or waited according to the overflow policy.
+pub struct AccessEventWriter {
+    policy: EventOverflowPolicy,
+    sender: mpsc::Sender<WriterMessage>,
+    sink: Arc<dyn IAccessEventSink>,
+}
+
+impl AccessEventWriter {
+    pub fn new(config: &EventWriterProperties, channel_size: usize, sink: Arc<dyn IAccessEventSink>) -> Self {
+        let (sender, receiver) = mpsc::channel(channel_size.max(1));
+        tokio::spawn(Self::run(
+            receiver,
+            sink.clone(),
+            config.batch_size.max(1),
+            (*config.flush_interval_ms).max(Duration::from_millis(1)),
+        ));
+        AccessEventWriter {
+            policy: config.overflow_policy,
+            sender,
+            sink,
+        }
+    }
+
+    pub fn get() -> &'static AccessEventWriter {
+        &SINGLE_INSTANCE
+    }
+
+    /// Submit the event to be inserted asynchronously, returns false if the event was dropped.
+    pub async fn write(&self, event: Arc<BotwafAccessEvent>) -> bool {
+        let result = match self.policy {
+            EventOverflowPolicy::DROP => match self.sender.try_send(WriterMessage::Event(event)) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    BOTWAF_EVENT_WRITER_DROPPED_TOTAL.inc();
+                    return false;
+                }
+                Err(TrySendError::Closed(_)) => Err(()),
+            },
+            EventOverflowPolicy::BLOCK => self.sender.send(WriterMessage::Event(event)).await.map_err(|_| ()),
+        };
+        match result {
+            Ok(()) => {
+                BOTWAF_EVENT_WRITER_QUEUE_DEPTH.inc();
+                true
+            }
+            Err(()) => {
+                tracing::warn!("The access events writer was closed, the event is dropped.");
+                false
+            }
+        }
+    }
+
+    /// Wait for the all submitted events to be inserted, e.g: on shutdown.
+    pub async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(WriterMessage::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    async fn run(
+        mut receiver: mpsc::Receiver<WriterMessage>,
+        sink: Arc<dyn IAccessEventSink>,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        // Notice: The first tick is delayed, otherwise it completes immediately.
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + flush_interval, flush_interval);
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(WriterMessage::Event(event)) => {
+                        BOTWAF_EVENT_WRITER_QUEUE_DEPTH.dec();
+                        batch.push(event);
+                        if batch.len() >= batch_size {
+                            Self::insert(&sink, &mut batch).await;
+                        }
+                    }
+                    Some(WriterMessage::Flush(ack)) => {
+                        Self::insert(&sink, &mut batch).await;
+                        let _ = ack.send(());
+                    }
+                    None => {
+                        Self::insert(&sink, &mut batch).await;
+                        break;
+                    }
+                },
+                _ = ticker.tick() => Self::insert(&sink, &mut batch).await,
+            }
+        }
+    }
+
+    async fn insert(sink: &Arc<dyn IAccessEventSink>, batch: &mut Vec<Arc<BotwafAccessEvent>>) {
+        if batch.is_empty() {
+            return;
+        }
+        if let Err(e) = sink.insert_batch(batch).await {
+            tracing::error!("Failed to insert the {} access events. cause: {}", batch.len(), e);
+            DeadLetterManager::push(DEAD_LETTER_KIND_ACCESS_EVENTS, &batch, &e.to_string(), 1);
+        }
+        batch.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationMillis;
+    use std::sync::Mutex;
+    use tokio::sync::Semaphore;
+
+    #[derive(Default)]
+    struct MockAccessEventSink {
+        inserted: Mutex<Vec<usize>>,
+        // The inserts are blocked until the permits are added.
+        gate: Option<Semaphore>,
+    }
+
+    impl MockAccessEventSink {
+        fn inserted_count(&self) -> usize {
+            self.inserted.lock().unwrap().iter().sum()
+        }
+    }
+
+    #[async_trait]
+    impl IAccessEventSink for MockAccessEventSink {
+        async fn insert_batch(&self, events: &[Arc<BotwafAccessEvent>]) -> Result<(), Error> {
+            if let Some(gate) = &self.gate {
+                gate.acquire().await?.forget();
+            }
+            self.inserted.lock().unwrap().push(events.len());
+            Ok(())
+        }
+    }
+
+    fn create_test_event() -> Arc<BotwafAccessEvent> {
+        Arc::new(BotwafAccessEvent {
+            method: String::from("GET"),
+            scheme: None,
+            host: None,
+            port: None,
+            headers: None,
+            path: String::from("/"),
+            query: None,
+            body: None,
+            req_id: None,
+            client_ip: None,
+            start_time: 0,
+            resp_status_code: Some(200),
+            resp_headers: None,
+            resp_body: None,
+            duration: None,
+            synthetic: false,
+            rule_id: None,
+            headers_truncated: false,
+            resp_rewrite: None,
+        })
+    }
+
+    fn create_test_config(policy: EventOverflowPolicy, batch_size: usize) -> EventWriterProperties {
+        EventWriterProperties {
+            channel_size: None,
+            batch_size,
+            flush_interval_ms: DurationMillis::from_millis(60_000),
+            overflow_policy: policy,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overflow_dropped_under_drop_policy() {
+        let sink = Arc::new(MockAccessEventSink {
+            gate: Some(Semaphore::new(0)),
+            ..Default::default()
+        });
+        let writer = AccessEventWriter::new(&create_test_config(EventOverflowPolicy::DROP, 1), 1, sink.clone());
+
+        let dropped_before = BOTWAF_EVENT_WRITER_DROPPED_TOTAL.get();
+        let mut accepted = 0;
+        for _ in 0..10 {
+            if writer.write(create_test_event()).await {
+                accepted += 1;
+            }
+        }
+        // At most one event is inserting (blocked) and one is pending in the channel.
+        assert!(accepted <= 2);
+        assert!(BOTWAF_EVENT_WRITER_DROPPED_TOTAL.get() - dropped_before >= 10 - accepted);
+
+        // The accepted events are still inserted after the sink recovered.
+        sink.gate.as_ref().unwrap().add_permits(10);
+        writer.flush().await;
+        assert_eq!(sink.inserted_count(), accepted as usize);
+    }
+
+    #[tokio::test]
+    async fn test_block_policy_never_drops() {
+        let sink = Arc::new(MockAccessEventSink::default());
+        let writer = AccessEventWriter::new(&create_test_config(EventOverflowPolicy::BLOCK, 4), 1, sink.clone());
+
+        for _ in 0..10 {
+            assert!(writer.write(create_test_event()).await);
+        }
+        writer.flush().await;
+        assert_eq!(sink.inserted_count(), 10);
+        // The full batches and the flushed partial batch.
+        assert_eq!(*sink.inserted.lock().unwrap(), vec![4, 4, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_pending_on_shutdown() {
+        let sink = Arc::new(MockAccessEventSink::default());
+        let writer = AccessEventWriter::new(&create_test_config(EventOverflowPolicy::DROP, 100), 100, sink.clone());
+
+        for _ in 0..5 {
+            assert!(writer.write(create_test_event()).await);
+        }
+        // The partial batch is waiting for the flush interval.
+        assert_eq!(sink.inserted_count(), 0);
+        writer.flush().await;
+        assert_eq!(sink.inserted_count(), 5);
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+//! The deterministic decision harness, which runs a corpus of the request fixtures through the shared
+//! evaluation function (see: `BotwafForwarderManager::evaluate`) with the given rule set, and compares the
+//! decisions with the expected (golden) decisions, so that any rule or normalization change which silently
+//! alters the decisions is caught with a per-fixture diff.
+//!
+//! # Corpus format
+//!
+//! The corpus is a YAML (or JSON with the `.json` extension) file of the request fixtures with the expected
+//! decisions, e.g:
+//!
+//! ```yaml
+//! name: "my-regressions"
+//! fixtures:
+//!   - name: "sqli-in-query"
+//!     desc: "The classic tautology SQL injection."
+//!     request:
+//!       method: "GET"               # Optional, defaults to GET.
+//!       path: "/products"
+//!       query: "id=1%27%20OR%20%271%27%3D%271" # Optional, the raw (URL encoded) query string.
+//!       headers:                    # Optional, the header names are lower-cased as the real requests.
+//!         Host: "shop.example.com"
+//!       body: null                  # Optional, the raw request body.
+//!     expect:
+//!       decision: "BLOCK"           # Options: PASS|BLOCK
+//!       status: 403                 # Only for BLOCK.
+//!       rule-id: "190001"           # Only for BLOCK, the rule id of the intervention.
+//! ```
+//!
+//! The fixture without `expect` is always reported as drifted, so the new fixtures are recorded by the
+//! update mode, i.e: `botwaf verify-decisions --corpus <file> --rules <file> --update-golden`, or by
+//! `BOTWAF_UPDATE_GOLDEN=1 cargo test -p botwaf-forwarder decision_harness` of the built-in golden files.
+
+use crate::forwarder_base::{BotwafDecision, BotwafForwarderManager};
+use anyhow::{Context, Error};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use hyper::Version;
+use modsecurity::{ModSecurity, Rules};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecisionCorpus {
+    pub name: String,
+    pub fixtures: Vec<DecisionFixture>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecisionFixture {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    pub request: FixtureRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<FixtureDecision>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    #[serde(default = "FixtureRequest::default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FixtureDecisionType {
+    PASS,
+    BLOCK,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureDecision {
+    pub decision: FixtureDecisionType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(rename = "rule-id", default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+}
+
+/// The decision of a fixture, the drifted if the actual decision differs from the expected.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FixtureResult {
+    pub name: String,
+    pub expected: Option<FixtureDecision>,
+    pub actual: FixtureDecision,
+}
+
+/// The diffable report of the corpus run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecisionReport {
+    pub corpus: String,
+    pub results: Vec<FixtureResult>,
+}
+
+/// The runner of the corpus with the given engine and rule set.
+pub struct DecisionHarness<'a> {
+    engine: &'a ModSecurity,
+    rules: &'a Rules,
+}
+
+impl DecisionCorpus {
+    /// Load the corpus from the YAML, or the JSON if the file extension is `.json`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read the corpus {}", path.display()))?;
+        if Self::is_json(path) {
+            serde_json::from_str(&content).with_context(|| format!("Invalid the JSON corpus {}", path.display()))
+        } else {
+            serde_yaml::from_str(&content).with_context(|| format!("Invalid the YAML corpus {}", path.display()))
+        }
+    }
+
+    /// Save the corpus with the same format of the file extension, e.g: the regenerated golden decisions.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = if Self::is_json(path) {
+            serde_json::to_string_pretty(self)? + "\n"
+        } else {
+            serde_yaml::to_string(self)?
+        };
+        std::fs::write(path, content).with_context(|| format!("Failed to write the corpus {}", path.display()))
+    }
+
+    fn is_json(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+    }
+}
+
+impl FixtureRequest {
+    fn default_method() -> String {
+        String::from("GET")
+    }
+
+    fn to_incoming(&self) -> HttpIncomingRequest {
+        HttpIncomingRequest {
+            method: self.method.to_uppercase(),
+            scheme: None,
+            host: self.headers.get("host").or(self.headers.get("Host")).cloned(),
+            port: None,
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_lowercase(), Some(value.to_owned())))
+                .collect(),
+            path: self.path.to_owned(),
+            query: self.query.to_owned(),
+            body: self.body.as_ref().map(|b| b.to_owned().into()),
+            client_ip: None,
+            peer_ip: None,
+            synthetic: false,
+            version: Version::HTTP_11,
+        }
+    }
+}
+
+impl From<BotwafDecision> for FixtureDecision {
+    fn from(decision: BotwafDecision) -> Self {
+        match decision {
+            BotwafDecision::PASS => FixtureDecision {
+                decision: FixtureDecisionType::PASS,
+                status: None,
+                rule_id: None,
+            },
+            BotwafDecision::BLOCK { status, rule_id, .. } => FixtureDecision {
+                decision: FixtureDecisionType::BLOCK,
+                status: Some(status.as_u16()),
+                rule_id: Some(rule_id),
+            },
+        }
+    }
+}
+
+impl Display for FixtureDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decision: {:?}", self.decision)?;
+        if let Some(status) = self.status {
+            write!(f, ", status: {}", status)?;
+        }
+        if let Some(rule_id) = &self.rule_id {
+            write!(f, ", rule-id: {}", rule_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl FixtureResult {
+    pub fn is_drifted(&self) -> bool {
+        self.expected.as_ref() != Some(&self.actual)
+    }
+}
+
+impl DecisionReport {
+    pub fn drifted(&self) -> Vec<&FixtureResult> {
+        self.results.iter().filter(|r| r.is_drifted()).collect()
+    }
+
+    pub fn is_drifted(&self) -> bool {
+        self.results.iter().any(|r| r.is_drifted())
+    }
+
+    /// The corpus with the expectations of the actual decisions, i.e: the regenerated golden file.
+    pub fn to_golden(&self, corpus: &DecisionCorpus) -> DecisionCorpus {
+        let mut golden = corpus.to_owned();
+        for (fixture, result) in golden.fixtures.iter_mut().zip(self.results.iter()) {
+            fixture.expect = Some(result.actual.to_owned());
+        }
+        golden
+    }
+}
+
+/// The unified-diff like report of the drifted fixtures, e.g:
+///
+/// ```text
+/// corpus 'emergency-rules': 1 of 6 fixtures drifted
+/// --- fixture 'sqli-in-query'
+/// -   decision: BLOCK, status: 403, rule-id: 190001
+/// +   decision: PASS
+/// ```
+impl Display for DecisionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let drifted = self.drifted();
+        write!(
+            f,
+            "corpus '{}': {} of {} fixtures drifted",
+            self.corpus,
+            drifted.len(),
+            self.results.len()
+        )?;
+        for result in drifted {
+            write!(f, "\n--- fixture '{}'", result.name)?;
+            match &result.expected {
+                Some(expected) => write!(f, "\n-   {}", expected)?,
+                None => write!(f, "\n-   (no expectation)")?,
+            }
+            write!(f, "\n+   {}", result.actual)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> DecisionHarness<'a> {
+    pub fn new(engine: &'a ModSecurity, rules: &'a Rules) -> Self {
+        Self { engine, rules }
+    }
+
+    /// Run all the fixtures of the corpus in order, which is deterministic for the same rule set.
+    pub fn run(&self, corpus: &DecisionCorpus) -> DecisionReport {
+        let results = corpus
+            .fixtures
+            .iter()
+            .map(|fixture| {
+                let decision =
+                    BotwafForwarderManager::evaluate(self.engine, self.rules, &fixture.request.to_incoming());
+                FixtureResult {
+                    name: fixture.name.to_owned(),
+                    expected: fixture.expect.to_owned(),
+                    actual: decision.into(),
+                }
+            })
+            .collect();
+        DecisionReport {
+            corpus: corpus.name.to_owned(),
+            results,
+        }
+    }
+
+    /// Run the corpus file and compare with the golden decisions, or regenerate the golden decisions of the
+    /// file if updating, returns the report of the run.
+    pub fn verify_file(&self, path: &Path, update_golden: bool) -> Result<DecisionReport, Error> {
+        let corpus = DecisionCorpus::load(path)?;
+        let report = self.run(&corpus);
+        if update_golden && report.is_drifted() {
+            report.to_golden(&corpus).save(path)?;
+            tracing::info!("Updated the golden decisions of {}\n{}", path.display(), report);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::modules::modsec::{body_processor::BODY_PROCESSOR_RULES, rule_loader::EMERGENCY_RULES};
+    use std::path::PathBuf;
+
+    const UPDATE_GOLDEN_ENV: &str = "BOTWAF_UPDATE_GOLDEN";
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("golden").join(name)
+    }
+
+    fn create_rules(plains: &[&str]) -> Rules {
+        let mut rules = Rules::new();
+        rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        for plain in plains {
+            rules.add_plain(plain).unwrap();
+        }
+        rules
+    }
+
+    // Regenerate the golden decisions by: BOTWAF_UPDATE_GOLDEN=1 cargo test -p botwaf-forwarder decision_harness
+    fn assert_golden_decisions(corpus: &str, rules: &Rules) {
+        let engine = ModSecurity::default();
+        let update_golden = std::env::var(UPDATE_GOLDEN_ENV).is_ok();
+        let report = DecisionHarness::new(&engine, rules)
+            .verify_file(&golden_path(corpus), update_golden)
+            .unwrap();
+        assert!(
+            update_golden || !report.is_drifted(),
+            "The decisions are drifted from the golden file '{}', please check the diff below, and regenerate \
+             it by '{}=1' if intended.\n{}",
+            corpus,
+            UPDATE_GOLDEN_ENV,
+            report
+        );
+    }
+
+    #[test]
+    fn test_golden_decisions_of_emergency_rules() {
+        assert_golden_decisions("emergency_rules.yaml", &create_rules(&[EMERGENCY_RULES]));
+    }
+
+    #[test]
+    fn test_golden_decisions_of_crs_subset() {
+        let crs_subset = std::fs::read_to_string(golden_path("crs_subset.conf")).unwrap();
+        assert_golden_decisions("crs_subset.yaml", &create_rules(&[&crs_subset]));
+    }
+
+    #[test]
+    fn test_report_diff_of_drifted_fixtures() {
+        let corpus: DecisionCorpus = serde_yaml::from_str(
+            r#"
+name: "drifts"
+fixtures:
+  - name: "benign"
+    request:
+      path: "/products"
+      query: "id=42"
+    expect:
+      decision: "PASS"
+  - name: "sqli-expected-but-passed"
+    request:
+      path: "/products"
+      query: "id=42"
+    expect:
+      decision: "BLOCK"
+      status: 403
+      rule-id: "190001"
+  - name: "new-fixture"
+    request:
+      method: "post"
+      path: "/products"
+"#,
+        )
+        .unwrap();
+        let engine = ModSecurity::default();
+        let rules = create_rules(&[EMERGENCY_RULES]);
+        let report = DecisionHarness::new(&engine, &rules).run(&corpus);
+
+        assert!(report.is_drifted());
+        assert_eq!(
+            report.drifted().iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["sqli-expected-but-passed", "new-fixture"]
+        );
+        assert_eq!(
+            report.to_string(),
+            "corpus 'drifts': 2 of 3 fixtures drifted\n\
+             --- fixture 'sqli-expected-but-passed'\n\
+             -   decision: BLOCK, status: 403, rule-id: 190001\n\
+             +   decision: PASS\n\
+             --- fixture 'new-fixture'\n\
+             -   (no expectation)\n\
+             +   decision: PASS"
+        );
+
+        // The regenerated golden decisions are no longer drifted.
+        let golden = report.to_golden(&corpus);
+        assert!(!DecisionHarness::new(&engine, &rules).run(&golden).is_drifted());
+        assert_eq!(golden.fixtures[0], corpus.fixtures[0]);
+    }
+}
@@ -19,33 +19,59 @@
 // This includes modifications and derived works.
 
 use crate::{
+    access_recorder::AccessEventRecorder,
+    access_writer::{AccessEventsReplayer, DEAD_LETTER_KIND_ACCESS_EVENTS},
     forwarder_http::HttpForwardHandler,
+    forwarder_tls::ForwardError,
+    headers::{
+        proxy_headers::ProxyHeaders,
+        upstream_signals::{BotwafSignals, UpstreamSignaler, SIGNAL_DECISION_ALLOW},
+    },
     ipfilter::{ipfilter::IPFilterManager, ipfilter_redis::RedisIPFilter},
+    llm_classifier::LlmClassifier,
+    modsec_limiter::ModSecLimiter,
+    plugin_wasm::{PluginAction, WasmPluginHost},
+    probe_synthetic::SyntheticProber,
+    request_signing::RequestSignatureVerifier,
+    response_rewrite::ResponseRewriteOutcome,
+    stats::topk::AccessTopKTracker,
+    uri_limit::UriLengthLimiter,
 };
 use anyhow::{Error, Result};
-use async_trait::async_trait;
 use axum::{
     body::Body,
     extract::{Request, State},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use botwaf_server::{config::config, context::state::BotwafState, util::auths};
+pub use botwaf_server::modules::forward::forwarder::IForwarder;
+use botwaf_server::{
+    config::config,
+    context::state::{BotwafState, BotwafStateBuilder},
+    mgmt::{
+        apm::{
+            body_size::ByteCountingBody,
+            metrics::{
+                BOTWAF_BLOCKED_REQUESTS_TOTAL, BOTWAF_HTTP_REQUEST_BODY_BYTES, BOTWAF_HTTP_RESPONSE_BODY_BYTES,
+                BOTWAF_MODSEC_SKIPPED_TOTAL, MY_HTTP_REQUESTS_TOTAL,
+            },
+        },
+        fail_open::{FailOpenBudget, FAIL_OPEN_IPFILTER},
+    },
+    modules::modsec::{body_processor::RequestBodyProcessor, rule_promotion::RulePromotionManager},
+    sys::dead_letter::DeadLetterManager,
+    util::{auths, header_limits::HeaderLimitExceeded},
+};
 use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
 use hyper::StatusCode;
 use lazy_static::lazy_static;
+use modsecurity::{ModSecurity, Rules};
 use regex::Regex;
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
 };
 
-#[async_trait]
-pub trait IForwarder {
-    async fn init(&self) -> Result<()>;
-    async fn http_forward(&self, incoming: Arc<HttpIncomingRequest>) -> Result<Response<Body>>;
-}
-
 lazy_static! {
     /// RwLock Notices:
     /// Read lock (shared lock):
@@ -58,6 +84,24 @@ lazy_static! {
     /// When a thread holds a write lock, other threads cannot obtain read or write locks. ---Therefore, the phantom read problem of the database is avoided
     /// When one or more threads hold a read lock, other threads cannot obtain a write lock. ---Therefore, RwLock is only suitable for scenarios with more reads and less writes, such as cache systems, configuration file reading, etc.
     static ref SINGLE_INSTANCE: RwLock<BotwafForwarderManager> = RwLock::new(BotwafForwarderManager::new());
+    static ref RULE_ID_REGEX: Regex = Regex::new(r#"\[id "\s*(\d+)\s*"\]"#).unwrap();
+}
+
+/// The decision of ModSecurity engine evaluated for an incoming request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BotwafDecision {
+    PASS,
+    BLOCK {
+        status: StatusCode,
+        rule_id: String,
+        log: String,
+    },
+}
+
+impl BotwafDecision {
+    pub fn is_blocked(&self) -> bool {
+        matches!(self, BotwafDecision::BLOCK { .. })
+    }
 }
 
 pub struct BotwafForwarderManager {
@@ -77,6 +121,10 @@ impl BotwafForwarderManager {
 
     pub async fn init() {
         IPFilterManager::init().await;
+        if config::get_config().services.top_k.enabled {
+            AccessTopKTracker::start();
+        }
+        DeadLetterManager::register_replayer(DEAD_LETTER_KIND_ACCESS_EVENTS, Arc::new(AccessEventsReplayer {}));
 
         tracing::info!("Register Botwaf Http IForwarder ...");
         match Self::get()
@@ -86,7 +134,9 @@ impl BotwafForwarderManager {
         {
             Ok(registered) => {
                 tracing::info!("Initializing Botwaf Http IForwarder ...");
-                let _ = registered.init().await;
+                if let Err(e) = registered.init().await {
+                    panic!("Failed to initialize Http IForwarder: {}", e);
+                }
             }
             Err(e) => panic!("Failed to register Http IForwarder: {}", e),
         }
@@ -105,6 +155,13 @@ impl BotwafForwarderManager {
         Ok(handler)
     }
 
+    /// Wire the registered IP filter and forwarder into the state, requires initialized, see: init()
+    pub fn wire(builder: BotwafStateBuilder) -> Result<BotwafStateBuilder, Error> {
+        let ipfilter = IPFilterManager::get_implementation(RedisIPFilter::NAME.to_owned())?;
+        let forwarder = Self::get_implementation(HttpForwardHandler::NAME.to_owned())?;
+        Ok(builder.with_ipfilter(ipfilter).with_forwarder(forwarder))
+    }
+
     pub fn get_implementation(name: String) -> Result<Arc<dyn IForwarder + Send + Sync>, Error> {
         // If the read lock is poisoned, the program will panic.
         let this = BotwafForwarderManager::get().read().unwrap();
@@ -116,43 +173,23 @@ impl BotwafForwarderManager {
         }
     }
 
-    pub async fn botwaf_middleware(State(state): State<BotwafState>, req: Request<Body>, next: Next) -> Response {
-        let uri = req.uri();
-
-        // 1. Exclude if there is any path excluded.
-        if auths::is_anonymous_request(&state.config, uri) {
-            return next.run(req).await;
-        }
-
-        // Wrap to unified incoming request.
-        let incoming = HttpIncomingRequest::new(req, config::get_config().services.forward.max_body_bytes).await;
-
-        // Obtain the available IP filter instance.
-        let ipfilter = IPFilterManager::get_implementation(RedisIPFilter::NAME.to_owned()).expect(&format!(
-            "Failed to get IP filter implementation with {}.",
-            RedisIPFilter::NAME.to_owned()
-        ));
-
-        // Check if the request client IP address is blocked.
-        if ipfilter.is_blocked(incoming.to_owned()).await.unwrap_or(false) {
-            let code = StatusCode::from_u16(config::get_config().services.blocked_status_code.unwrap()).unwrap();
-            return Response::builder()
-                .status(code)
-                .body("Access denied by Botwaf IP Filter".into())
-                .unwrap();
-        }
-
+    /// Evaluate the incoming request with the ModSecurity engine and rules, which is the in-process
+    /// decision function shared by the middleware and the synthetic probes.
+    pub fn evaluate(engine: &ModSecurity, rules: &Rules, incoming: &HttpIncomingRequest) -> BotwafDecision {
         // Create a ModSecurity engine transaction with rules.
-        let mut transaction = state
-            .modsec_engine
+        let mut transaction = engine
             .transaction_builder()
-            .with_rules(&state.modsec_rules)
+            .with_rules(rules)
             .build()
             .expect("Error building transaction");
 
-        // Process the request URI with ModSecurity engine.
+        // Process the request URI (with query args) with ModSecurity engine.
+        let uri = match &incoming.query {
+            Some(query) => format!("{}?{}", incoming.path, query),
+            None => incoming.path.to_owned(),
+        };
         transaction
-            .process_uri(&incoming.path, &incoming.method, "1.1")
+            .process_uri(&uri, &incoming.method, "1.1")
             .expect("Error processing URI");
         // Process the request headers with ModSecurity engine.
         for (key, value) in incoming.headers.iter() {
@@ -163,65 +200,689 @@ impl BotwafForwarderManager {
         transaction
             .process_request_headers()
             .expect("Error processing request headers");
-        // Process the request body with ModSecurity engine.
+        // Process the request body with ModSecurity engine, the structured (form/json/multipart) bodies
+        // are parsed into ARGS by the body processor selected with the Content-Type, see: BODY_PROCESSOR_RULES
+        let content_type = incoming
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("content-type"))
+            .and_then(|(_, value)| value.as_deref());
+        let processor = RequestBodyProcessor::detect(content_type);
+        tracing::debug!("[Botwaf] [BodyProcessor] - {} - {}", incoming.path, processor.name());
         let req_body = incoming.body.to_owned().unwrap_or_default().to_vec();
         transaction
             .append_request_body(&req_body)
+            .expect("Error appending request body");
+        transaction
+            .process_request_body()
             .expect("Error processing request body");
 
         // Check if the request is blocked by ModSecurity engine.
         if let Some(intervention) = transaction.intervention() {
             if intervention.status() == 401 || intervention.status() == 403 {
-                let status_code =
+                let status =
                     StatusCode::from_u16(intervention.status() as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-                let logmsg = intervention
+                let log = intervention
                     .log()
                     .map(|msg| msg.to_string())
                     .unwrap_or_else(|| "Access denied by Botwaf".to_string());
-                tracing::info!("[Botwaf] [AccessDeined] - {}, reason: {}", incoming.path, logmsg);
-
-                // Getting forbidded by modsec rule id.
-                let mut rule_id = String::from("Masked");
-                if config::get_config().services.allow_addition_modsec_info {
-                    let re = Regex::new(r#"\[id "\s*(\d+)\s*"\]"#).unwrap();
-                    rule_id = intervention
-                        .log()
-                        .and_then(|log| re.captures(log))
-                        .and_then(|caps| caps.get(1))
-                        .map(|m| m.as_str())
-                        .unwrap_or("Unknown")
-                        .to_owned();
+                let rule_id = RULE_ID_REGEX
+                    .captures(&log)
+                    .and_then(|caps| caps.get(1))
+                    .map(|m| m.as_str())
+                    .unwrap_or("Unknown")
+                    .to_owned();
+                return BotwafDecision::BLOCK { status, rule_id, log };
+            }
+        }
+        BotwafDecision::PASS
+    }
+
+    /// Evaluate the incoming request with the SHADOW rules, which only logs the would-block requests
+    /// and never blocks, the rule is promoted to ACTIVE once met the threshold, returns the would-block rules.
+    fn evaluate_shadow(state: &BotwafState, incoming: &HttpIncomingRequest) -> Vec<String> {
+        let mut promoted = false;
+        let mut would_block = Vec::new();
+        for shadow in state.modsec_shadow_rules.load().iter() {
+            if let BotwafDecision::BLOCK { log, .. } = Self::evaluate(&state.modsec_engine, &shadow.rules, incoming) {
+                tracing::info!(
+                    "[Botwaf] [ShadowWouldBlock] - {}, rule: {}, reason: {}",
+                    incoming.path,
+                    shadow.name,
+                    log
+                );
+                promoted |= RulePromotionManager::get().record_hit(&shadow.name);
+                would_block.push(shadow.name.to_owned());
+            }
+        }
+        if promoted {
+            state.schedule_reload_modsec_rules();
+        }
+        would_block
+    }
+
+    // Verify the signature of the signed routes, returns the rejected response if failed.
+    async fn verify_signature(incoming: &HttpIncomingRequest, start_time: u64) -> Option<Response> {
+        let failure = RequestSignatureVerifier::get().verify(incoming).await.err()?;
+        tracing::warn!("[Botwaf] [SignatureInvalid] - {}, reason: {}", incoming.path, failure);
+        AccessEventRecorder::get()
+            .record_with_rule(
+                incoming,
+                start_time,
+                StatusCode::UNAUTHORIZED,
+                Some(format!("signature:{}", failure.reason())),
+            )
+            .await;
+        Some(
+            (
+                StatusCode::UNAUTHORIZED,
+                axum::Json(serde_json::json!({ "reason": failure.reason() })),
+            )
+                .into_response(),
+        )
+    }
+
+    // Check if the request client IP address is blocked, the errors (e.g: redis is down) are fail-open
+    // unless the fail-open budget is exceeded, returns whether blocked and whether failed open.
+    async fn check_ipfilter(state: &BotwafState, incoming: &HttpIncomingRequest) -> (bool, bool) {
+        // Obtain the IP filter instance wired into the state.
+        let ipfilter = state
+            .ipfilter
+            .to_owned()
+            .expect("The IP filter is not wired into the Botwaf state.");
+        match ipfilter.is_blocked(incoming.to_owned()).await {
+            Ok(blocked) => (blocked, false),
+            Err(e) => {
+                tracing::warn!("[Botwaf] [IPFilterErr] - {} - {}", incoming.path, e);
+                (FailOpenBudget::get().report(FAIL_OPEN_IPFILTER), true)
+            }
+        }
+    }
+
+    async fn reject_ipfilter_blocked(incoming: &HttpIncomingRequest, start_time: u64) -> Response {
+        let code = config::get_config().services.blocked_status_or(StatusCode::FORBIDDEN);
+        Self::count_blocked(incoming, "ipfilter");
+        AccessEventRecorder::get().record(incoming, start_time, code).await;
+        Response::builder()
+            .status(code)
+            .body("Access denied by Botwaf IP Filter".into())
+            .unwrap()
+    }
+
+    // The blocked header value of the rule id or the plugin name, which is masked unless the addition info allowed.
+    fn blocked_info(allow_addition_info: bool, info: String) -> String {
+        if allow_addition_info {
+            info
+        } else {
+            String::from("Masked")
+        }
+    }
+
+    // The synthetic probe requests are excluded from the blocked statistics.
+    fn count_blocked(incoming: &HttpIncomingRequest, source: &str) {
+        if !incoming.synthetic {
+            BOTWAF_BLOCKED_REQUESTS_TOTAL.with_label_values(&[source]).inc();
+        }
+    }
+
+    pub async fn botwaf_middleware(State(state): State<BotwafState>, mut req: Request<Body>, next: Next) -> Response {
+        // Strip the probe header from all the requests, which is honored only if signed by our prober.
+        let synthetic = SyntheticProber::take_probe_header(&mut req);
+
+        // Reject the too long raw URI before any evaluation, which may be the DoS or rule evasion.
+        if UriLengthLimiter::get().is_exceeded(req.uri()) {
+            tracing::warn!("[Botwaf] [UriTooLong] - {} bytes", req.uri().to_string().len());
+            return (StatusCode::URI_TOO_LONG, "URI Too Long").into_response();
+        }
+
+        // Reject the looped request which has passed through this instance already, e.g: the misconfigured
+        // upstream pointing back to the botwaf.
+        if ProxyHeaders::get().is_looped(req.headers()) {
+            tracing::warn!("[Botwaf] [LoopDetected] - {}", req.uri().path());
+            return (StatusCode::LOOP_DETECTED, "Loop Detected").into_response();
+        }
+
+        // The orchestrator probes (e.g: health) bypass the evaluation and the access recording, but not the
+        // signature verification and the IP filter.
+        if state.config.is_bypass_path(req.uri().path()) {
+            return Self::forward_bypassed(state, req).await;
+        }
+        // The synthetic probe requests are excluded from the body size statistics.
+        if synthetic {
+            return Self::do_botwaf_middleware(state, req, next).await;
+        }
+        // Count the body bytes as they flow, so the streamed bodies are never fully buffered for counting.
+        let req = req.map(|body| ByteCountingBody::wrap(body, BOTWAF_HTTP_REQUEST_BODY_BYTES.clone()));
+        Self::do_botwaf_middleware(state, req, next)
+            .await
+            .map(|body| ByteCountingBody::wrap(body, BOTWAF_HTTP_RESPONSE_BODY_BYTES.clone()))
+    }
+
+    // Forward the bypassed request to the upstream as-is, i.e: no any evaluation, statistics or access events,
+    // but the unsigned requests of the signed routes and the blocked client IPs are still rejected, and the spoofed
+    // upstream signal headers are still stripped.
+    async fn forward_bypassed(state: BotwafState, req: Request<Body>) -> Response {
+        let start_time = chrono::Utc::now().timestamp_millis() as u64;
+        let max_body_bytes = config::get_config().services.forward.max_body_bytes;
+        let incoming = match HttpIncomingRequest::new(req, max_body_bytes).await {
+            std::result::Result::Ok(incoming) => incoming,
+            Err(e) => {
+                tracing::warn!("[Botwaf] [PayloadTooLarge] - {}", e);
+                return (StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large").into_response();
+            }
+        };
+        let incoming = UpstreamSignaler::get().strip(ProxyHeaders::get().resolve(incoming));
+        if let Some(resp) = Self::verify_signature(&incoming, start_time).await {
+            return resp;
+        }
+        if Self::check_ipfilter(&state, &incoming).await.0 {
+            return Self::reject_ipfilter_blocked(&incoming, start_time).await;
+        }
+        let forwarder = state
+            .forwarder
+            .to_owned()
+            .expect("The forwarder is not wired into the Botwaf state.");
+        match forwarder.http_forward(incoming.to_owned()).await {
+            std::result::Result::Ok(response) => response,
+            Err(err) => {
+                tracing::warn!("[Botwaf] [ForwardErr] - {} - {}", &incoming.path, err);
+                match err.downcast_ref::<ForwardError>() {
+                    Some(e) => {
+                        (e.kind.status(), format!("Gateway Forwarded Error: {}", e.kind.label())).into_response()
+                    }
+                    None => (StatusCode::INTERNAL_SERVER_ERROR, "Gateway Forwarded Error").into_response(),
                 }
+            }
+        }
+    }
 
-                // Determining ModSec rejected response status code.
-                let code = match config::get_config().services.blocked_status_code {
-                    Some(code) => StatusCode::from_u16(code).unwrap(),
-                    None => status_code,
-                };
+    async fn do_botwaf_middleware(state: BotwafState, req: Request<Body>, next: Next) -> Response {
+        let uri = req.uri();
+        let start_time = chrono::Utc::now().timestamp_millis() as u64;
 
-                return Response::builder()
-                    .status(code)
-                    .header(config::get_config().services.blocked_header_name.to_owned(), rule_id)
-                    .body("Access denied by Botwaf Threaten".into())
-                    .unwrap();
+        // 1. Exclude if there is any path excluded.
+        if auths::is_anonymous_request(&state.config, uri) {
+            return next.run(req).await;
+        }
+
+        // Wrap to unified incoming request.
+        let max_body_bytes = config::get_config().services.forward.max_body_bytes;
+        let incoming = match HttpIncomingRequest::new(req, max_body_bytes).await {
+            std::result::Result::Ok(incoming) => incoming,
+            Err(e) => {
+                tracing::warn!("[Botwaf] [PayloadTooLarge] - {}", e);
+                return (StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large").into_response();
             }
+        };
+        // Resolve the reported client IP through the trusted proxies of the X-Forwarded-For chain.
+        let incoming = ProxyHeaders::get().resolve(incoming);
+        // Strip the spoofed upstream signal headers before any evaluation.
+        let incoming = UpstreamSignaler::get().strip(incoming);
+        let mut signals = BotwafSignals::default();
+
+        // The synthetic probe requests are excluded from the access statistics.
+        if !incoming.synthetic {
+            MY_HTTP_REQUESTS_TOTAL.with_label_values(&[incoming.protocol()]).inc();
+        }
+
+        // Verify the signature of the internal service-to-service routes, which is never forwarded if failed.
+        if let Some(resp) = Self::verify_signature(&incoming, start_time).await {
+            return resp;
+        }
+
+        // Check if the request client IP address is blocked.
+        let (blocked, failed_open) = Self::check_ipfilter(&state, &incoming).await;
+        if failed_open {
+            signals.flag(format!("fail-open:{}", FAIL_OPEN_IPFILTER));
+        }
+        if blocked {
+            return Self::reject_ipfilter_blocked(&incoming, start_time).await;
+        }
+
+        // Execute the custom wasm plugins between the normalization and ModSecurity.
+        let verdict = WasmPluginHost::get()
+            .run(&config::get_config().services.wasm_plugins, incoming.to_owned())
+            .await;
+        let incoming = verdict.apply_headers(incoming);
+        signals.score = verdict.score;
+        if verdict.action == PluginAction::BLOCK {
+            let plugin = verdict.plugin.unwrap_or_default();
+            tracing::info!("[Botwaf] [AccessDeined] - {}, reason: plugin {}", incoming.path, plugin);
+
+            let code = config::get_config().services.blocked_status_or(StatusCode::FORBIDDEN);
+            Self::count_blocked(&incoming, "plugin");
+            AccessEventRecorder::get().record(&incoming, start_time, code).await;
+
+            let blocked_info = Self::blocked_info(
+                config::get_config().services.allow_addition_modsec_info,
+                format!("plugin:{}", plugin),
+            );
+            return Response::builder()
+                .status(code)
+                .header(
+                    config::get_config().services.blocked_header_name.to_owned(),
+                    blocked_info,
+                )
+                .body("Access denied by Botwaf Plugin".into())
+                .unwrap();
         }
 
+        // Evaluate the request with ModSecurity engine, and then the LLM classification if not blocked.
+        // Notice: The requests allowed by the plugins skip the ModSecurity and LLM classification.
+        // Notice: The methods not to be inspected skip the ModSecurity, but were filtered by the IP filter already.
+        let mut decision = if verdict.action == PluginAction::ALLOW {
+            signals.decision = SIGNAL_DECISION_ALLOW;
+            signals.flag(format!("plugin-allow:{}", verdict.plugin.to_owned().unwrap_or_default()));
+            BotwafDecision::PASS
+        } else if !state.config.services.is_inspected_method(&incoming.method) {
+            if !incoming.synthetic {
+                BOTWAF_MODSEC_SKIPPED_TOTAL.with_label_values(&[&incoming.method]).inc();
+            }
+            signals.flag("uninspected-method");
+            BotwafDecision::PASS
+        } else {
+            // Bounds the simultaneous ModSecurity transactions.
+            let _permit = match ModSecLimiter::get().acquire().await {
+                std::result::Result::Ok(permit) => permit,
+                Err(e) => {
+                    tracing::warn!("[Botwaf] [Saturated] - {}, {}", incoming.path, e);
+                    AccessEventRecorder::get()
+                        .record(&incoming, start_time, StatusCode::SERVICE_UNAVAILABLE)
+                        .await;
+                    return (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable").into_response();
+                }
+            };
+            // Apply the rule exclusions of the matched route.
+            let rules = state
+                .modsec_rule_exclusions
+                .load()
+                .find(&incoming.path)
+                .unwrap_or_else(|| state.modsec_rules.load_full());
+            let decision = Self::evaluate(&state.modsec_engine, &rules, &incoming);
+            // The synthetic probe requests are excluded from the shadow rules statistics.
+            if !incoming.synthetic {
+                for name in Self::evaluate_shadow(&state, &incoming) {
+                    signals.flag(format!("shadow:{}", name));
+                }
+            }
+            decision
+        };
+        if !decision.is_blocked() && !incoming.synthetic && verdict.action != PluginAction::ALLOW {
+            let classifier = LlmClassifier::new(
+                &config::get_config().services.llm_classification,
+                state.llm_handler.to_owned(),
+            );
+            decision = classifier.decide(incoming.to_owned()).await;
+        }
+        if let BotwafDecision::BLOCK { status, rule_id, log } = decision {
+            tracing::info!("[Botwaf] [AccessDeined] - {}, reason: {}", incoming.path, log);
+
+            // Getting forbidded by modsec rule id.
+            let matched_rule_id = rule_id.to_owned();
+            let rule_id = Self::blocked_info(config::get_config().services.allow_addition_modsec_info, rule_id);
+
+            // Determining ModSec rejected response status code.
+            let code = config::get_config().services.blocked_status_or(status);
+            Self::count_blocked(&incoming, "rule");
+            AccessEventRecorder::get()
+                .record_with_rule(&incoming, start_time, code, Some(matched_rule_id))
+                .await;
+
+            return Response::builder()
+                .status(code)
+                .header(config::get_config().services.blocked_header_name.to_owned(), rule_id)
+                .body("Access denied by Botwaf Threaten".into())
+                .unwrap();
+        }
+
+        // Inject the signals of the evaluation into the forwarded request of the enabled routes.
+        let incoming = UpstreamSignaler::get().apply(incoming, &signals);
+
         // Forwarding request to the upstream servers.
-        let forwarder =
-            BotwafForwarderManager::get_implementation(HttpForwardHandler::NAME.to_owned()).expect(&format!(
-                "Failed to get forwarder implementation with {}.",
-                HttpForwardHandler::NAME.to_owned()
-            ));
+        let forwarder = state
+            .forwarder
+            .to_owned()
+            .expect("The forwarder is not wired into the Botwaf state.");
         match forwarder.http_forward(incoming.to_owned()).await {
-            std::result::Result::Ok(response) => {
+            std::result::Result::Ok(mut response) => {
                 tracing::info!("[Botwaf] [Forwarded] - {}", &incoming.path);
+                let status = response.status();
+                match ResponseRewriteOutcome::take(&mut response) {
+                    // The rewritten response is recorded with the fired patterns once the body is streamed.
+                    Some(outcome) => {
+                        let incoming = incoming.to_owned();
+                        tokio::spawn(async move {
+                            let rewrite = outcome.summary().await;
+                            AccessEventRecorder::get()
+                                .record_with_rewrite(&incoming, start_time, status, rewrite)
+                                .await;
+                        });
+                    }
+                    None => {
+                        AccessEventRecorder::get().record(&incoming, start_time, status).await;
+                    }
+                }
                 response
             }
             Err(err) => {
                 tracing::warn!("[Botwaf] [ForwardErr] - {} - {}", &incoming.path, err);
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("Gateway Forwarded Error")).into_response()
+                // The headers exceeded the limit of the upstream are rejected with the explanatory 431.
+                if let Some(e) = err.downcast_ref::<HeaderLimitExceeded>() {
+                    let code = StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+                    AccessEventRecorder::get().record(&incoming, start_time, code).await;
+                    return e.to_owned().into_response();
+                }
+                // The handshake failures are distinguishable from the connect timeouts.
+                let (code, message) = match err.downcast_ref::<ForwardError>() {
+                    Some(e) => (e.kind.status(), format!("Gateway Forwarded Error: {}", e.kind.label())),
+                    None => (StatusCode::INTERNAL_SERVER_ERROR, format!("Gateway Forwarded Error")),
+                };
+                AccessEventRecorder::get().record(&incoming, start_time, code).await;
+                (code, message).into_response()
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use botwaf_server::{
+        config::config::AppConfig,
+        context::test_support::{
+            create_in_memory_cache, create_test_config, InMemoryIPFilter, StaticForwarder, StaticLLMHandler,
+        },
+        modules::{forward::ipfilter::IPFilter, modsec::body_processor::BODY_PROCESSOR_RULES},
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    // The full proxy path with the in-memory fakes, which requires no external services.
+    async fn create_test_router(ipfilter: Arc<InMemoryIPFilter>, forwarder: Arc<StaticForwarder>) -> Router {
+        create_test_router_with_config(create_test_config("proxy-path"), ipfilter, forwarder).await
+    }
+
+    async fn create_test_router_with_config(
+        config: Arc<AppConfig>,
+        ipfilter: Arc<InMemoryIPFilter>,
+        forwarder: Arc<StaticForwarder>,
+    ) -> Router {
+        create_test_router_with_rules(
+            config,
+            r#"
+SecRuleEngine On
+SecRule ARGS "@detectSQLi" "id:3201,phase:2,deny,status:403,msg:'SQLi'"
+"#,
+            ipfilter,
+            forwarder,
+        )
+        .await
+    }
+
+    async fn create_test_router_with_rules(
+        config: Arc<AppConfig>,
+        plain_rules: &str,
+        ipfilter: Arc<InMemoryIPFilter>,
+        forwarder: Arc<StaticForwarder>,
+    ) -> Router {
+        let mut rules = Rules::new();
+        rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        rules.add_plain(plain_rules).unwrap();
+        let state = BotwafState::builder()
+            .with_config(&config)
+            .with_cache(create_in_memory_cache())
+            .with_ipfilter(ipfilter)
+            .with_forwarder(forwarder)
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(rules, Vec::new())
+            .build()
+            .await
+            .unwrap();
+
+        let layer = axum::middleware::from_fn_with_state(state.to_owned(), BotwafForwarderManager::botwaf_middleware);
+        Router::new()
+            .fallback(|| async { StatusCode::NOT_FOUND })
+            .layer(layer)
+            .with_state(state)
+    }
+
+    fn create_test_request(uri: &str) -> Request<Body> {
+        hyper::Request::builder()
+            .uri(uri)
+            .extension(SocketAddr::from(([203, 0, 113, 9], 41234)))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_blocked_info_masked_unless_allowed() {
+        assert_eq!(
+            BotwafForwarderManager::blocked_info(true, String::from("plugin:header-allowlist")),
+            "plugin:header-allowlist"
+        );
+        assert_eq!(
+            BotwafForwarderManager::blocked_info(false, String::from("plugin:header-allowlist")),
+            "Masked"
+        );
+        assert_eq!(
+            BotwafForwarderManager::blocked_info(false, String::from("3201")),
+            "Masked"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_path_without_external_services() {
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router = create_test_router(ipfilter.to_owned(), forwarder.to_owned()).await;
+
+        let resp = router
+            .to_owned()
+            .oneshot(create_test_request("/orders?id=1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(forwarder.forwarded().len(), 1);
+        assert_eq!(forwarder.forwarded()[0].client_ip.as_deref(), Some("203.0.113.9"));
+
+        // Blocked by the ModSecurity rules, and never forwarded.
+        let resp = router
+            .to_owned()
+            .oneshot(create_test_request("/orders?id=1%27%20OR%20%271%27%3D%271"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        // Blocked by the IP filter, and never forwarded.
+        ipfilter.block("203.0.113.9", None, None).await.unwrap();
+        let resp = router
+            .to_owned()
+            .oneshot(create_test_request("/orders?id=1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_uri_too_long_rejected_before_evaluation() {
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router = create_test_router(ipfilter, forwarder.to_owned()).await;
+
+        // The boundary length of the raw URI including the query string is passed.
+        let max_length = config::get_config().services.max_uri_length;
+        let boundary = format!("/orders?q={}", "x".repeat(max_length - "/orders?q=".len()));
+        let resp = router.to_owned().oneshot(create_test_request(&boundary)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        let resp = router
+            .to_owned()
+            .oneshot(create_test_request(&format!("{}x", boundary)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+        assert_eq!(forwarder.forwarded().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_looped_request_rejected_before_forwarding() {
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router = create_test_router(ipfilter, forwarder.to_owned()).await;
+
+        // The multi-hop chain passed through the other proxies is forwarded, and the peer is the client since
+        // none is trusted by default, i.e: the X-Forwarded-For could be spoofed.
+        let mut req = create_test_request("/orders?id=1");
+        req.headers_mut()
+            .insert("X-Forwarded-For", "198.51.100.7, 203.0.113.9".parse().unwrap());
+        req.headers_mut().insert(
+            "Via",
+            "1.1 abc.cloudfront.net (CloudFront), 1.1 botwaf-other".parse().unwrap(),
+        );
+        let resp = router.to_owned().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(forwarder.forwarded().len(), 1);
+        assert_eq!(forwarder.forwarded()[0].client_ip.as_deref(), Some("203.0.113.9"));
+
+        // Our own Via element is already present.
+        let mut req = create_test_request("/orders?id=1");
+        let via = format!(
+            "1.1 abc.cloudfront.net (CloudFront), {}",
+            ProxyHeaders::get().via_token()
+        );
+        req.headers_mut().insert("Via", via.parse().unwrap());
+        let resp = router.to_owned().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::LOOP_DETECTED);
+        assert_eq!(forwarder.forwarded().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_upstream_signals_stripped_before_forwarding() {
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router = create_test_router(ipfilter, forwarder.to_owned()).await;
+
+        let mut req = create_test_request("/orders?id=1");
+        req.headers_mut().insert("X-Botwaf-Decision", "ALLOW".parse().unwrap());
+        req.headers_mut().insert("X-Botwaf-Score", "0".parse().unwrap());
+        req.headers_mut().insert("X-Botwaf-Signature", "deadbeef".parse().unwrap());
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let forwarded = forwarder.forwarded();
+        assert_eq!(forwarded.len(), 1);
+        assert!(
+            !forwarded[0].headers.keys().any(|name| name.starts_with("x-botwaf-")),
+            "{:?}",
+            forwarded[0].headers
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inspect_methods_skips_get() {
+        let mut properties = create_test_config("inspect-methods").inner.to_owned();
+        properties.services.inspect_methods = vec!["POST".to_owned(), "PUT".to_owned(), "DELETE".to_owned()];
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router =
+            create_test_router_with_config(AppConfig::new(&properties), ipfilter.to_owned(), forwarder.to_owned())
+                .await;
+        let sqli = "/orders?id=1%27%20OR%20%271%27%3D%271";
+        let skipped = BOTWAF_MODSEC_SKIPPED_TOTAL.with_label_values(&["GET"]).get();
+
+        // The GET is not inspected, so that the SQLi is forwarded.
+        let resp = router.to_owned().oneshot(create_test_request(sqli)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(forwarder.forwarded().len(), 1);
+        assert_eq!(
+            BOTWAF_MODSEC_SKIPPED_TOTAL.with_label_values(&["GET"]).get(),
+            skipped + 1
+        );
+
+        // The POST is inspected and blocked.
+        let mut req = create_test_request(sqli);
+        *req.method_mut() = hyper::Method::POST;
+        let resp = router.to_owned().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+        assert_eq!(BOTWAF_MODSEC_SKIPPED_TOTAL.with_label_values(&["POST"]).get(), 0);
+
+        // The skipped GET is still filtered by the IP filter.
+        ipfilter.block("203.0.113.9", None, None).await.unwrap();
+        let resp = router
+            .to_owned()
+            .oneshot(create_test_request("/orders?id=1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bypass_paths_skip_evaluation_and_recording() {
+        let mut properties = create_test_config("bypass-paths").inner.to_owned();
+        properties.services.bypass_paths = vec![String::from("/healthz")];
+        let ipfilter = Arc::new(InMemoryIPFilter::default());
+        let forwarder = StaticForwarder::new(StatusCode::OK, "upstream");
+        let router = create_test_router_with_rules(
+            AppConfig::new(&properties),
+            r#"
+SecRuleEngine On
+SecRule REQUEST_URI "@rx ." "id:3202,phase:1,deny,status:403,msg:'Catch-all'"
+"#,
+            ipfilter.to_owned(),
+            forwarder.to_owned(),
+        )
+        .await;
+        let mut subscriber = AccessEventRecorder::subscribe();
+
+        // The health probe is never blocked by the catch-all rule, and produces no access event.
+        let resp = router.to_owned().oneshot(create_test_request("/healthz")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        // The others are still evaluated and recorded.
+        let resp = router.to_owned().oneshot(create_test_request("/orders")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        // The events bus is shared by the concurrent tests, so that only the paths of this test are checked.
+        let mut recorded = Vec::new();
+        while let Ok(event) = subscriber.try_recv() {
+            recorded.push(event.path.to_owned());
+        }
+        assert!(!recorded.iter().any(|path| path == "/healthz"), "{:?}", recorded);
+        assert!(recorded.iter().any(|path| path == "/orders"), "{:?}", recorded);
+
+        // The traversal out of the bypass path (plain or encoded) is still evaluated.
+        for uri in ["/healthz/../orders", "/healthz/..%2forders", "/healthz/%2e%2e/orders"] {
+            let resp = router.to_owned().oneshot(create_test_request(uri)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        // The too long and the looped requests of the bypass path are still rejected.
+        let max_length = config::get_config().services.max_uri_length;
+        let too_long = format!("/healthz?q={}", "x".repeat(max_length));
+        let resp = router.to_owned().oneshot(create_test_request(&too_long)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+        let mut req = create_test_request("/healthz");
+        req.headers_mut()
+            .insert("Via", ProxyHeaders::get().via_token().parse().unwrap());
+        let resp = router.to_owned().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::LOOP_DETECTED);
+        assert_eq!(forwarder.forwarded().len(), 1);
+
+        // The blocked client IP is rejected by the IP filter even on the bypass path.
+        ipfilter.block("203.0.113.9", None, None).await.unwrap();
+        let resp = router.to_owned().oneshot(create_test_request("/healthz")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(forwarder.forwarded().len(), 1);
+    }
+}
@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::config::config::{AddressFamilyPreference, HappyEyeballsProperties};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{collections::VecDeque, error::Error as StdError, net::SocketAddr, time::Duration};
+
+/// The resolver of the upstream hosts for the dual-stack backends, which sorts the addresses with the preferred
+/// family first and then interleaved (see: RFC 8305 section 4). Notice: The connector of the client attempts the
+/// first family and starts the other family after a short delay (i.e: 300ms of hyper-util) rather than waiting
+/// the whole connect timeout, so that a broken IPv6 (or IPv4) path doesn't add seconds of the latency.
+pub struct HappyEyeballsResolver {
+    prefer: AddressFamilyPreference,
+    resolve_timeout: Duration,
+}
+
+impl HappyEyeballsResolver {
+    pub fn new(config: &HappyEyeballsProperties) -> Self {
+        HappyEyeballsResolver {
+            prefer: config.prefer,
+            resolve_timeout: *config.resolve_timeout,
+        }
+    }
+
+    /// Sort the addresses with the preferred family first and then interleaved, the AUTO prefers the family of
+    /// the first address, i.e: the order of the system resolver (see: RFC 6724).
+    pub fn sort_addrs(addrs: Vec<SocketAddr>, prefer: AddressFamilyPreference) -> Vec<SocketAddr> {
+        let prefer_v6 = match prefer {
+            AddressFamilyPreference::AUTO => addrs.first().is_none_or(|addr| addr.is_ipv6()),
+            AddressFamilyPreference::IPV6 => true,
+            AddressFamilyPreference::IPV4 => false,
+        };
+        let (mut preferred, mut fallback): (VecDeque<_>, VecDeque<_>) =
+            addrs.into_iter().partition(|addr| addr.is_ipv6() == prefer_v6);
+        let mut sorted = Vec::with_capacity(preferred.len() + fallback.len());
+        while !preferred.is_empty() || !fallback.is_empty() {
+            sorted.extend(preferred.pop_front());
+            sorted.extend(fallback.pop_front());
+        }
+        sorted
+    }
+}
+
+impl Resolve for HappyEyeballsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (host, prefer, resolve_timeout) = (name.as_str().to_owned(), self.prefer, self.resolve_timeout);
+        Box::pin(async move {
+            // The port is set by the connector, see: hyper_util::client::legacy::connect::HttpConnector
+            let addrs = match tokio::time::timeout(resolve_timeout, tokio::net::lookup_host((host.as_str(), 0))).await {
+                Ok(addrs) => addrs?,
+                Err(_) => {
+                    let err: Box<dyn StdError + Send + Sync> =
+                        format!("Timed out to resolve '{}' after {:?}", host, resolve_timeout).into();
+                    return Err(err);
+                }
+            };
+            let addrs: Addrs = Box::new(Self::sort_addrs(addrs.collect(), prefer).into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sort_addrs_interleaved() {
+        let resolved = addrs(&[
+            "[2001:db8::1]:0",
+            "[2001:db8::2]:0",
+            "[2001:db8::3]:0",
+            "10.0.0.1:0",
+            "10.0.0.2:0",
+        ]);
+        assert_eq!(
+            HappyEyeballsResolver::sort_addrs(resolved.to_owned(), AddressFamilyPreference::AUTO),
+            addrs(&[
+                "[2001:db8::1]:0",
+                "10.0.0.1:0",
+                "[2001:db8::2]:0",
+                "10.0.0.2:0",
+                "[2001:db8::3]:0"
+            ])
+        );
+        assert_eq!(
+            HappyEyeballsResolver::sort_addrs(resolved.to_owned(), AddressFamilyPreference::IPV4),
+            addrs(&[
+                "10.0.0.1:0",
+                "[2001:db8::1]:0",
+                "10.0.0.2:0",
+                "[2001:db8::2]:0",
+                "[2001:db8::3]:0"
+            ])
+        );
+
+        // The single family is kept as is.
+        let resolved = addrs(&["10.0.0.1:0", "10.0.0.2:0"]);
+        assert_eq!(
+            HappyEyeballsResolver::sort_addrs(resolved.to_owned(), AddressFamilyPreference::IPV6),
+            resolved
+        );
+        assert!(HappyEyeballsResolver::sort_addrs(Vec::new(), AddressFamilyPreference::AUTO).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_localhost() {
+        let resolver = HappyEyeballsResolver::new(&HappyEyeballsProperties::default());
+        let resolved = resolver.resolve(Name::from_str("localhost").unwrap()).await.unwrap();
+        let resolved = resolved.collect::<Vec<_>>();
+        assert!(!resolved.is_empty());
+        assert!(resolved.iter().all(|addr| addr.ip().is_loopback()));
+    }
+}
@@ -18,40 +18,128 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::forwarder_base::IForwarder;
+use crate::{
+    access_recorder::AccessEventRecorder,
+    forwarder_base::IForwarder,
+    forwarder_mirror::RequestMirrors,
+    forwarder_tls::{ForwardError, UpstreamClients},
+    headers::{header_filter::ResponseHeaderFilters, proxy_headers::ProxyHeaders},
+    response_rewrite::ResponseRewriters,
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use axum::{body::Body, response::Response};
-use botwaf_server::config::config;
+use botwaf_server::config::config::{self, ForwardProperties};
+use botwaf_server::mgmt::apm::dependencies::{instrument_dep, DEP_UPSTREAM};
+use botwaf_server::util::header_limits::{HeaderLimitExceeded, LIMIT_UPSTREAM_MAX_HEADER_BYTES};
 use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
-use common_telemetry::{debug, info};
-use hyper::{header, Method};
-use reqwest::Proxy;
-use std::{str::FromStr, sync::Arc, time::Duration};
+use common_telemetry::{debug, info, warn};
+use hyper::{
+    header::{self, HeaderName, HeaderValue},
+    Method, Uri,
+};
+use std::{str::FromStr, sync::Arc};
 
 pub struct HttpForwardHandler {
-    pub(super) client: reqwest::Client,
+    pub(super) clients: UpstreamClients,
+    pub(super) mirrors: RequestMirrors,
+    pub(super) header_filters: ResponseHeaderFilters,
+    pub(super) proxy_headers: ProxyHeaders,
+    pub(super) response_rewriters: ResponseRewriters,
+    // The url prefix and the Host header override of the upstreams.
+    host_headers: Vec<(String, Option<String>)>,
+    // The url prefix and the max request header bytes of the upstreams.
+    max_header_bytes: Vec<(String, usize)>,
+    // The debug response header of the selected upstream host, only if allowed.
+    expose_upstream_header: Option<HeaderName>,
 }
 
 impl HttpForwardHandler {
     pub const NAME: &'static str = "http_forward";
 
     pub fn new() -> Arc<Self> {
-        let mut builder = reqwest::ClientBuilder::new()
-            .connect_timeout(Duration::from_secs(
-                config::get_config().services.forward.connect_timeout,
-            ))
-            .read_timeout(Duration::from_secs(config::get_config().services.forward.read_timeout))
-            .timeout(Duration::from_secs(config::get_config().services.forward.total_timeout))
-            .connection_verbose(config::get_config().services.forward.verbose);
-        if let Some(proxy) = &config::get_config().services.forward.http_proxy {
-            builder = builder.proxy(Proxy::http(proxy).expect("parse http proxy addr error"));
-        }
+        Self::new_with(&config::get_config().services.forward)
+    }
+
+    pub fn new_with(config: &ForwardProperties) -> Arc<Self> {
+        let expose_upstream_header = if config.allow_expose_upstream {
+            match HeaderName::from_str(&config.expose_upstream_header) {
+                Ok(name) => Some(name),
+                Err(e) => {
+                    warn!(
+                        "Ignored the invalid expose upstream header '{}'. cause: {}",
+                        config.expose_upstream_header, e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
         Arc::new(Self {
-            client: builder.build().expect("build http client error"),
+            clients: UpstreamClients::new(config),
+            mirrors: RequestMirrors::new(&config.upstreams),
+            header_filters: ResponseHeaderFilters::new(&config.upstreams),
+            proxy_headers: ProxyHeaders::new(&config.proxy_headers),
+            response_rewriters: ResponseRewriters::new(&config.upstreams),
+            host_headers: config
+                .upstreams
+                .iter()
+                .map(|u| (u.url_prefix.to_owned(), u.host_header.to_owned()))
+                .collect(),
+            max_header_bytes: config
+                .upstreams
+                .iter()
+                .filter_map(|u| u.max_request_header_bytes.map(|max| (u.url_prefix.to_owned(), max)))
+                .collect(),
+            expose_upstream_header,
+        })
+    }
+
+    // The host (and port) of the upstream URL, e.g: 10.0.0.11:8080
+    fn get_upstream_host(url: &str) -> Option<String> {
+        let uri = url.parse::<Uri>().ok()?;
+        let host = uri.host()?;
+        Some(match uri.port_u16() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
         })
     }
 
+    // The outgoing Host of the upstream (the longest url prefix matched), defaults to the upstream authority.
+    fn get_host_header(&self, url: &str) -> Option<String> {
+        self.host_headers
+            .iter()
+            .filter(|(url_prefix, _)| url.starts_with(url_prefix.as_str()))
+            .max_by_key(|(url_prefix, _)| url_prefix.len())
+            .and_then(|(_, host_header)| host_header.to_owned())
+            .or_else(|| Self::get_upstream_host(url))
+    }
+
+    // The max request header bytes of the upstream (the longest url prefix matched), 0 is unlimited.
+    fn get_max_header_bytes(&self, url: &str) -> Option<usize> {
+        self.max_header_bytes
+            .iter()
+            .filter(|(url_prefix, _)| url.starts_with(url_prefix.as_str()))
+            .max_by_key(|(url_prefix, _)| url_prefix.len())
+            .map(|(_, max)| *max)
+            .filter(|max| *max > 0)
+    }
+
+    // The hop-by-hop (connection-specific) headers, which must not be forwarded, see: RFC 9110 section 7.6.1
+    fn is_hop_by_hop_header(name: &str) -> bool {
+        [
+            header::CONNECTION.as_str(),
+            header::TRANSFER_ENCODING.as_str(),
+            header::UPGRADE.as_str(),
+            header::TE.as_str(),
+            "keep-alive",
+            "proxy-connection",
+        ]
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+    }
+
     // Extract the upstream URL from the request headers.
     fn get_upstream_url(&self, incoming: Arc<HttpIncomingRequest>) -> Result<String> {
         let upstream_header_name = config::get_config()
@@ -60,10 +148,20 @@ impl HttpForwardHandler {
             .upstream_destination_header_name
             .to_owned();
 
+        // Fallback to the default upstream if missing the upstream destination header.
         let upstream_base_uri = incoming
             .headers
             .get(&upstream_header_name)
             .map(|h| h.to_owned().unwrap_or_default())
+            .or_else(|| {
+                config::get_config()
+                    .services
+                    .forward
+                    .upstreams
+                    .iter()
+                    .find(|u| u.default)
+                    .map(|u| u.url_prefix.to_owned())
+            })
             .ok_or_else(||
                 // Only record warning logs instead of error stack
                 anyhow::anyhow!(
@@ -100,23 +198,58 @@ impl HttpForwardHandler {
             "Forwarding request to upstream with host: {} path: {}, query: {}",
             incoming.host.to_owned().unwrap_or_default(),
             incoming.path,
-            incoming.query.to_owned().unwrap_or_default(),
+            AccessEventRecorder::get().scrub_query(&incoming.query),
         );
 
-        let mut req_builder = self
-            .client
-            .request(Method::from_str(incoming.method.as_str())?, forward_url);
+        let mirror = self.mirrors.find(&forward_url).cloned();
+        let header_filter = self.header_filters.find(&forward_url).cloned();
+        let rewriter = self.response_rewriters.find(&forward_url).cloned();
+        let upstream_host = self
+            .expose_upstream_header
+            .as_ref()
+            .and_then(|_| Self::get_upstream_host(&forward_url));
+        // Notice: Resolved before the url is rewritten by the SNI override.
+        let host_header = self.get_host_header(&forward_url);
+        let max_header_bytes = self.get_max_header_bytes(&forward_url);
+        // Obtain the client by the upstream TLS settings, e.g: custom CA, mTLS, SNI override.
+        let (client, forward_url) = self.clients.get(forward_url).await?;
+        let mut req_builder = client.request(Method::from_str(incoming.method.as_str())?, forward_url);
 
         // Copy original request headers, but exclude certain headers
+        let mut header_bytes = 0;
         for (name, value) in incoming.headers.iter() {
             // Skip certain headers, such as custom upstream destination header and connection related headers.
             let name = name.to_uppercase();
-            if name != upstream_header && name != "POST" && name != "CONNECTION" {
+            if name != upstream_header
+                && name != "POST"
+                && name != "HOST"
+                && !Self::is_hop_by_hop_header(&name)
+                && !ProxyHeaders::is_proxy_header(&name)
+            {
                 for v in value.iter() {
+                    header_bytes += name.len() + v.len();
                     req_builder = req_builder.header(name.to_owned(), v);
                 }
             }
         }
+        if let Some(host) = host_header {
+            header_bytes += header::HOST.as_str().len() + host.len();
+            req_builder = req_builder.header(header::HOST, host);
+        }
+        // Append this hop to the Via and X-Forwarded-* (or Forwarded) chains.
+        for (name, value) in self.proxy_headers.request_headers(&incoming) {
+            header_bytes += name.len() + value.len();
+            req_builder = req_builder.header(name, value);
+        }
+        // Never re-send the headers larger than the upstream accepts, e.g: it's less permissive than the listener.
+        if let Some(max) = max_header_bytes.filter(|max| header_bytes > *max) {
+            return Err(HeaderLimitExceeded {
+                limit: LIMIT_UPSTREAM_MAX_HEADER_BYTES,
+                max,
+                actual: header_bytes,
+            }
+            .into());
+        }
 
         // Addidtional set the request body if provided.
         // The body is type of axum::Bytes is cheaply cloneable and thereby shareable unlimited amount.
@@ -124,40 +257,83 @@ impl HttpForwardHandler {
             req_builder = req_builder.body(body);
         }
 
+        // Fire the mirrored request asynchronously, which never delays the primary request.
+        let mirror_handle = mirror.and_then(|m| m.dispatch(incoming.to_owned()));
+
         // Execute the request.
-        let resp = req_builder.send().await?;
+        let start = std::time::Instant::now();
+        let resp = instrument_dep(DEP_UPSTREAM, "forward", async {
+            req_builder.send().await.map_err(ForwardError::from)
+        })
+        .await?;
 
         let status = resp.status();
+        if let Some(handle) = mirror_handle {
+            handle.complete(status.as_u16(), start.elapsed().as_millis() as u64);
+        }
         let headers = resp.headers().clone();
-        let bytes = resp
-            .bytes()
-            .await
-            .context("Failed to read response body from upstream")?;
+        // The body of the configured content types is rewritten streaming, the others are read as is.
+        let (body, rewrite_outcome) = match rewriter.filter(|r| r.is_applicable(&headers)) {
+            Some(rewriter) => {
+                let (stream, outcome) = rewriter.rewrite(resp.bytes_stream());
+                (Body::from_stream(stream), Some(outcome))
+            }
+            None => {
+                let bytes = resp
+                    .bytes()
+                    .await
+                    .context("Failed to read response body from upstream")?;
+                (Body::from(bytes), None)
+            }
+        };
 
         info!(
             "Forwarded response from upstream status: {}, host: {} path: {}, query: {}, headers: {:?}",
             status,
             incoming.host.to_owned().unwrap_or_default(),
             incoming.path,
-            incoming.query.to_owned().unwrap_or_default(),
+            AccessEventRecorder::get().scrub_query(&incoming.query),
             headers
         );
 
         // Build the response.
         let mut response = Response::builder()
             .status(status.as_u16())
-            .body(Body::from(bytes))
+            .body(body)
             .context("Failed to build response")?;
 
         // Copy the headers from the upstream response.
         let resp_headers = response.headers_mut();
+        let mut current = None;
         for (name, value) in headers {
-            if let Some(name) = name {
-                if name != header::CONNECTION {
-                    resp_headers.insert(name, value);
+            // The subsequent values of the same header (e.g: multiple 'Set-Cookie') are yielded without name.
+            if name.is_some() {
+                current = name;
+            }
+            if let Some(name) = current.as_ref() {
+                // The connection-specific headers are forbidden in h2/h3 responses, and the body is
+                // re-framed by the client-facing connection (e.g: no chunked encoding on h2).
+                if !Self::is_hop_by_hop_header(name.as_str()) {
+                    resp_headers.append(name, value);
                 }
             }
         }
+        // Strip the response headers not in the allowlist of the upstream (ALLOWLIST mode only).
+        if let Some(filter) = header_filter {
+            filter.apply(resp_headers);
+        }
+        self.proxy_headers.append_response_via(resp_headers);
+        // Expose the selected upstream host for debugging, which is disabled by default.
+        if let (Some(name), Some(host)) = (self.expose_upstream_header.as_ref(), upstream_host) {
+            if let Ok(value) = HeaderValue::from_str(&host) {
+                resp_headers.insert(name, value);
+            }
+        }
+        // The length of the rewritten body is unknown, which is re-framed as chunked.
+        if let Some(outcome) = rewrite_outcome {
+            response.headers_mut().remove(header::CONTENT_LENGTH);
+            response.extensions_mut().insert(outcome);
+        }
 
         Ok(response)
     }
@@ -166,6 +342,7 @@ impl HttpForwardHandler {
 #[async_trait]
 impl IForwarder for HttpForwardHandler {
     async fn init(&self) -> Result<()> {
+        self.clients.validate()?;
         Ok(())
     }
 
@@ -177,3 +354,299 @@ impl IForwarder for HttpForwardHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response_rewrite::ResponseRewriteOutcome;
+    use axum::{routing::get, Router};
+    use botwaf_server::config::config::{
+        ProxyHeadersProperties, ResponseRewriteAction, ResponseRewritePatternKind, ResponseRewritePatternProperties,
+        ResponseRewriteProperties, UpstreamProperties,
+    };
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use hyper::HeaderMap;
+    use tokio::net::TcpListener;
+
+    async fn spawn_ok_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/orders", get(|| async { "ok" }));
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    fn create_test_incoming() -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("GET", "/orders").build_arc()
+    }
+
+    // The upstream echoes the received X-Forwarded-For and Via, and responds with its own Via.
+    async fn spawn_echo_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/orders",
+            get(|headers: HeaderMap| async move {
+                let get = |name: &str| {
+                    headers
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .to_owned()
+                };
+                (
+                    [(header::VIA, "1.1 gateway")],
+                    format!("{}|{}", get("x-forwarded-for"), get("via")),
+                )
+            }),
+        );
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    // The upstream echoes the received Host.
+    async fn spawn_host_echo_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/orders",
+            get(|headers: HeaderMap| async move {
+                headers
+                    .get(header::HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned()
+            }),
+        );
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    fn create_test_incoming_with_host(host: &str) -> Arc<HttpIncomingRequest> {
+        let mut incoming = (*create_test_incoming()).clone();
+        incoming.host = Some(host.to_owned());
+        incoming.headers.insert(String::from("host"), Some(host.to_owned()));
+        Arc::new(incoming)
+    }
+
+    async fn forward_and_read_body(
+        handler: &HttpForwardHandler,
+        incoming: Arc<HttpIncomingRequest>,
+        url: String,
+    ) -> String {
+        let resp = handler.do_forward_request(incoming, url).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8_lossy(&body).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_forward_overrides_host_header_per_upstream() {
+        let upstream = spawn_host_echo_upstream().await;
+        let mut config = ForwardProperties::default();
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: upstream.to_owned(),
+            host_header: Some(String::from("orders.internal.example.com")),
+            ..Default::default()
+        });
+        let handler = HttpForwardHandler::new_with(&config);
+
+        let incoming = create_test_incoming_with_host("shop.example.com");
+        let host = forward_and_read_body(&handler, incoming, format!("{}/orders", upstream)).await;
+        assert_eq!(host, "orders.internal.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_forward_host_header_defaults_to_upstream_authority() {
+        let upstream = spawn_host_echo_upstream().await;
+        let mut config = ForwardProperties::default();
+        // The longest matched upstream without the override takes precedence over the shorter one.
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: String::from("http://127.0.0.1"),
+            host_header: Some(String::from("other.internal.example.com")),
+            ..Default::default()
+        });
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: upstream.to_owned(),
+            ..Default::default()
+        });
+        let handler = HttpForwardHandler::new_with(&config);
+
+        let incoming = create_test_incoming_with_host("shop.example.com");
+        let host = forward_and_read_body(&handler, incoming, format!("{}/orders", upstream)).await;
+        assert_eq!(host, upstream.trim_start_matches("http://"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_oversized_headers_rejected_per_upstream() {
+        let upstream = spawn_ok_upstream().await;
+        let mut config = ForwardProperties::default();
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: upstream.to_owned(),
+            max_request_header_bytes: Some(8192),
+            ..Default::default()
+        });
+        let handler = HttpForwardHandler::new_with(&config);
+
+        // The 64 KB cookie passed by the more permissive listener is never re-sent to the upstream.
+        let mut incoming = (*create_test_incoming()).clone();
+        incoming.headers.insert(
+            String::from("cookie"),
+            Some(format!("sso_session={}", "x".repeat(64 * 1024))),
+        );
+        let err = handler
+            .do_forward_request(Arc::new(incoming), format!("{}/orders", upstream))
+            .await
+            .unwrap_err();
+        let exceeded = err.downcast_ref::<HeaderLimitExceeded>().unwrap();
+        assert_eq!(exceeded.limit, LIMIT_UPSTREAM_MAX_HEADER_BYTES);
+        assert_eq!(exceeded.max, 8192);
+        assert!(exceeded.actual > 64 * 1024);
+
+        // The small headers are forwarded.
+        let body = forward_and_read_body(&handler, create_test_incoming(), format!("{}/orders", upstream)).await;
+        assert_eq!(body, "ok");
+    }
+
+    #[test]
+    fn test_get_upstream_host() {
+        assert_eq!(
+            HttpForwardHandler::get_upstream_host("http://10.0.0.11:8080/orders"),
+            Some(String::from("10.0.0.11:8080"))
+        );
+        assert_eq!(
+            HttpForwardHandler::get_upstream_host("https://internal.example.com/orders?id=1"),
+            Some(String::from("internal.example.com"))
+        );
+        assert_eq!(HttpForwardHandler::get_upstream_host("/orders"), None);
+    }
+
+    #[tokio::test]
+    async fn test_expose_upstream_header_reflects_selected_upstream() {
+        let upstream = spawn_ok_upstream().await;
+        let config = ForwardProperties {
+            allow_expose_upstream: true,
+            ..Default::default()
+        };
+        let handler = HttpForwardHandler::new_with(&config);
+
+        let resp = handler
+            .do_forward_request(create_test_incoming(), format!("{}/orders", upstream))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get("X-Botwaf-Upstream").unwrap().to_str().unwrap(),
+            upstream.trim_start_matches("http://")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_appends_proxy_headers_chains() {
+        let upstream = spawn_echo_upstream().await;
+        let config = ForwardProperties {
+            proxy_headers: ProxyHeadersProperties {
+                instance_id: Some(String::from("01")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let handler = HttpForwardHandler::new_with(&config);
+        let mut incoming = (*create_test_incoming()).clone();
+        incoming.peer_ip = Some(String::from("130.176.1.10"));
+        incoming.headers.insert(
+            String::from("x-forwarded-for"),
+            Some(String::from("198.51.100.7, 203.0.113.9")),
+        );
+        incoming
+            .headers
+            .insert(String::from("via"), Some(String::from("1.1 cdn")));
+
+        let resp = handler
+            .do_forward_request(Arc::new(incoming), format!("{}/orders", upstream))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let via: Vec<&str> = resp
+            .headers()
+            .get_all(header::VIA)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect();
+        assert_eq!(via, vec!["1.1 gateway", "1.1 botwaf-01"]);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "198.51.100.7, 203.0.113.9, 130.176.1.10|1.1 cdn, 1.1 botwaf-01"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expose_upstream_header_disabled_by_default() {
+        let upstream = spawn_ok_upstream().await;
+        let handler = HttpForwardHandler::new_with(&ForwardProperties::default());
+
+        let resp = handler
+            .do_forward_request(create_test_incoming(), format!("{}/orders", upstream))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("X-Botwaf-Upstream").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forward_rewritten_response_chunked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/orders",
+            get(|| async {
+                (
+                    [(header::CONTENT_TYPE, "application/json")],
+                    r#"{"card":"4111 1111 1111 1111","order":"4111 1111 1111 1112"}"#,
+                )
+            }),
+        );
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        let upstream = format!("http://{}", addr);
+        let mut config = ForwardProperties::default();
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: upstream.to_owned(),
+            response_rewrite: Some(ResponseRewriteProperties {
+                patterns: vec![ResponseRewritePatternProperties {
+                    name: String::from("pan"),
+                    kind: ResponseRewritePatternKind::PAN,
+                    regex: None,
+                    action: ResponseRewriteAction::MASK,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let handler = HttpForwardHandler::new_with(&config);
+
+        let mut resp = handler
+            .do_forward_request(create_test_incoming(), format!("{}/orders", upstream))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(header::CONTENT_LENGTH).is_none());
+        let outcome = ResponseRewriteOutcome::take(&mut resp).expect("Should be rewritten");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            r#"{"card":"**** **** **** ****","order":"4111 1111 1111 1112"}"#
+        );
+        let summary = outcome.summary().await;
+        assert_eq!(summary.hits.len(), 1);
+        assert_eq!(summary.hits[0].replacements, 1);
+    }
+}
@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::{
+    config::config::{MirrorProperties, UpstreamProperties},
+    mgmt::apm::metrics::{BOTWAF_MIRROR_REQUESTS_TOTAL, BOTWAF_MIRROR_REQUEST_DURATION},
+};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use common_telemetry::debug;
+use hyper::{header, Method};
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+use tokio::sync::{oneshot, Semaphore};
+
+lazy_static! {
+    // The recent pairs of primary and mirror status codes for diffing.
+    static ref MIRROR_COMPARISONS: RwLock<VecDeque<MirrorComparison>> = RwLock::new(VecDeque::new());
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorComparison {
+    pub upstream: String,
+    pub method: String,
+    pub path: String,
+    pub primary_status: Option<u16>,
+    pub mirror_status: Option<u16>,
+    pub primary_latency_ms: Option<u64>,
+    pub mirror_latency_ms: u64,
+    pub mirror_error: Option<String>,
+}
+
+/// The primary side of the mirrored request, which reports the primary outcome for the comparison.
+pub struct MirrorHandle {
+    primary_tx: oneshot::Sender<(u16, u64)>,
+}
+
+impl MirrorHandle {
+    /// Never blocks, and it's ignored if the mirror task is already completed.
+    pub fn complete(self, primary_status: u16, primary_latency_ms: u64) {
+        let _ = self.primary_tx.send((primary_status, primary_latency_ms));
+    }
+}
+
+/// The traffic shadowing of an upstream, the mirrored requests are fired asynchronously and
+/// the responses are always discarded, so that never affect the client response.
+pub struct RequestMirror {
+    url_prefix: String,
+    config: MirrorProperties,
+    client: Client,
+    permits: Arc<Semaphore>,
+    sequence: AtomicU64,
+}
+
+impl RequestMirror {
+    pub const MAX_COMPARISONS: usize = 1000;
+    // The golden ratio conjugate, which makes the sampled sequence evenly distributed.
+    const GOLDEN_RATIO: f64 = 0.618_033_988_749_894_9;
+
+    pub fn new(url_prefix: &str, config: &MirrorProperties) -> Arc<Self> {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(*config.timeout_ms)
+            .build()
+            .expect("build mirror http client error");
+        Arc::new(RequestMirror {
+            url_prefix: url_prefix.to_owned(),
+            config: config.to_owned(),
+            client,
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether the n-th request is sampled by the percentage (0-100).
+    pub fn is_sampled(n: u64, percent: f64) -> bool {
+        if percent <= 0.0 {
+            return false;
+        }
+        if percent >= 100.0 {
+            return true;
+        }
+        ((n as f64) * Self::GOLDEN_RATIO).fract() < percent / 100.0
+    }
+
+    fn record(&self, outcome: &str) {
+        BOTWAF_MIRROR_REQUESTS_TOTAL
+            .with_label_values(&[self.url_prefix.as_str(), outcome])
+            .inc();
+    }
+
+    /// Fire the mirrored request asynchronously if sampled, returns immediately.
+    pub fn dispatch(self: &Arc<Self>, incoming: Arc<HttpIncomingRequest>) -> Option<MirrorHandle> {
+        if !Self::is_sampled(
+            self.sequence.fetch_add(1, Ordering::Relaxed),
+            self.config.sample_percent,
+        ) {
+            return None;
+        }
+        // Only the buffered body is mirrored, never re-read the request stream.
+        let body_size = incoming.body.as_ref().map(|b| b.len()).unwrap_or(0);
+        if self.config.include_body && body_size > self.config.max_body_bytes {
+            self.record("skipped_body_size");
+            return None;
+        }
+        let permit = match self.permits.to_owned().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.record("skipped_concurrency");
+                return None;
+            }
+        };
+
+        let (primary_tx, primary_rx) = oneshot::channel();
+        let this = self.to_owned();
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let result = this.send(&incoming).await;
+            let mirror_latency = start.elapsed();
+            drop(permit);
+
+            BOTWAF_MIRROR_REQUEST_DURATION
+                .with_label_values(&[this.url_prefix.as_str()])
+                .observe(mirror_latency.as_secs_f64());
+            let (mirror_status, mirror_error) = match result {
+                Ok(status) => {
+                    this.record("success");
+                    (Some(status), None)
+                }
+                Err(e) => {
+                    debug!("Failed to mirror request to {}. cause: {}", this.config.url, e);
+                    this.record(if e.is_timeout() { "timeout" } else { "error" });
+                    (None, Some(e.to_string()))
+                }
+            };
+
+            if this.config.record_comparison {
+                // The primary is dropped if it's failed.
+                let primary = primary_rx.await.ok();
+                Self::add_comparison(MirrorComparison {
+                    upstream: this.url_prefix.to_owned(),
+                    method: incoming.method.to_owned(),
+                    path: incoming.path.to_owned(),
+                    primary_status: primary.map(|p| p.0),
+                    mirror_status,
+                    primary_latency_ms: primary.map(|p| p.1),
+                    mirror_latency_ms: mirror_latency.as_millis() as u64,
+                    mirror_error,
+                });
+            }
+        });
+        Some(MirrorHandle { primary_tx })
+    }
+
+    async fn send(&self, incoming: &HttpIncomingRequest) -> Result<u16, reqwest::Error> {
+        let mut url = format!("{}{}", self.config.url.trim_end_matches('/'), incoming.path);
+        if let Some(query) = &incoming.query {
+            url = format!("{}?{}", url, query);
+        }
+        let method = Method::from_str(incoming.method.as_str()).unwrap_or(Method::GET);
+        let mut req_builder = self.client.request(method, url);
+        if self.config.include_headers {
+            for (name, value) in incoming.headers.iter() {
+                // The host and connection related headers are determined by the mirror connection.
+                if [
+                    header::HOST.as_str(),
+                    header::CONNECTION.as_str(),
+                    header::CONTENT_LENGTH.as_str(),
+                ]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name))
+                {
+                    continue;
+                }
+                if let Some(v) = value {
+                    req_builder = req_builder.header(name.as_str(), v.as_str());
+                }
+            }
+        }
+        if self.config.include_body {
+            if let Some(body) = incoming.body.to_owned() {
+                req_builder = req_builder.body(body);
+            }
+        }
+        // The response body is discarded.
+        let resp = req_builder.send().await?;
+        Ok(resp.status().as_u16())
+    }
+
+    fn add_comparison(comparison: MirrorComparison) {
+        let mut comparisons = MIRROR_COMPARISONS.write().unwrap();
+        if comparisons.len() >= Self::MAX_COMPARISONS {
+            comparisons.pop_front();
+        }
+        comparisons.push_back(comparison);
+    }
+
+    /// The recent pairs of primary and mirror status codes.
+    pub fn get_comparisons() -> Vec<MirrorComparison> {
+        MIRROR_COMPARISONS.read().unwrap().iter().cloned().collect()
+    }
+}
+
+/// The mirrors of all upstreams, matched by the longest url prefix of the upstream destination.
+pub struct RequestMirrors {
+    mirrors: Vec<Arc<RequestMirror>>,
+}
+
+impl RequestMirrors {
+    pub fn new(upstreams: &Vec<UpstreamProperties>) -> Self {
+        let mirrors = upstreams
+            .iter()
+            .filter_map(|u| u.mirror.as_ref().map(|m| RequestMirror::new(&u.url_prefix, m)))
+            .collect();
+        RequestMirrors { mirrors }
+    }
+
+    pub fn find(&self, url: &str) -> Option<&Arc<RequestMirror>> {
+        self.mirrors
+            .iter()
+            .filter(|m| url.starts_with(m.url_prefix.as_str()))
+            .max_by_key(|m| m.url_prefix.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationMillis;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn create_test_incoming(body: &str) -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("POST", "/orders")
+            .query("id=1")
+            .body(body)
+            .build_arc()
+    }
+
+    // The mirror upstream which accepts the connections but never responds.
+    async fn spawn_hanging_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut conns = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                conns.push(stream);
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn spawn_ok_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    let resp = "HTTP/1.1 201 Created\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                    let _ = stream.write_all(resp.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn create_test_config(url: String) -> MirrorProperties {
+        MirrorProperties {
+            url,
+            sample_percent: 100.0,
+            timeout_ms: DurationMillis::from_millis(10_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sampling_math() {
+        for percent in [1.0, 10.0, 25.0, 50.0, 99.0] {
+            let sampled = (0..10_000).filter(|n| RequestMirror::is_sampled(*n, percent)).count();
+            let expected = (percent * 100.0) as usize;
+            assert!(
+                sampled.abs_diff(expected) <= 2,
+                "percent: {}, sampled: {}",
+                percent,
+                sampled
+            );
+        }
+        assert_eq!((0..1000).filter(|n| RequestMirror::is_sampled(*n, 0.0)).count(), 0);
+        assert_eq!((0..1000).filter(|n| RequestMirror::is_sampled(*n, 100.0)).count(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_not_delayed_by_hanging_mirror() {
+        let mirror = RequestMirror::new("http://primary", &create_test_config(spawn_hanging_upstream().await));
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            let handle = mirror.dispatch(create_test_incoming("{}")).expect("should be sampled");
+            handle.complete(200, 1);
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_concurrency_cap_and_body_size() {
+        let mut config = create_test_config(spawn_hanging_upstream().await);
+        config.max_concurrent = 1;
+        config.max_body_bytes = 4;
+        let mirror = RequestMirror::new("http://primary", &config);
+
+        assert!(mirror.dispatch(create_test_incoming("{}")).is_some());
+        // The first mirrored request is hanging and occupied the permit.
+        assert!(mirror.dispatch(create_test_incoming("{}")).is_none());
+        // The larger body than buffered size is skipped.
+        assert!(mirror.dispatch(create_test_incoming("{\"id\":1}")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_record_comparison() {
+        let mut config = create_test_config(spawn_ok_upstream().await);
+        config.record_comparison = true;
+        let mirror = RequestMirror::new("http://comparison", &config);
+
+        mirror.dispatch(create_test_incoming("{}")).unwrap().complete(200, 5);
+        for _ in 0..50 {
+            let found = RequestMirror::get_comparisons()
+                .into_iter()
+                .find(|c| c.upstream == "http://comparison");
+            if let Some(c) = found {
+                assert_eq!(c.primary_status, Some(200));
+                assert_eq!(c.mirror_status, Some(201));
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("The mirror comparison is not recorded");
+    }
+}
@@ -0,0 +1,585 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::forwarder_dns::HappyEyeballsResolver;
+use botwaf_server::{
+    config::config::{ForwardProperties, UpstreamProperties, UpstreamTlsProperties},
+    mgmt::apm::metrics::BOTWAF_FORWARD_ERRORS_TOTAL,
+};
+use common_telemetry::{error, info};
+use hyper::StatusCode;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Proxy};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fs,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+/// The kind of the upstream forwarding errors, which is distinguishable in the metrics and error bodies.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForwardErrorKind {
+    CONNECT_TIMEOUT,
+    TLS_HANDSHAKE,
+    TLS_CONFIG,
+    CONNECT,
+    TIMEOUT,
+    OTHER,
+}
+
+impl ForwardErrorKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ForwardErrorKind::CONNECT_TIMEOUT => "connect_timeout",
+            ForwardErrorKind::TLS_HANDSHAKE => "tls_handshake",
+            ForwardErrorKind::TLS_CONFIG => "tls_config",
+            ForwardErrorKind::CONNECT => "connect",
+            ForwardErrorKind::TIMEOUT => "timeout",
+            ForwardErrorKind::OTHER => "other",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ForwardErrorKind::CONNECT_TIMEOUT | ForwardErrorKind::TIMEOUT => StatusCode::GATEWAY_TIMEOUT,
+            ForwardErrorKind::OTHER => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub fn classify(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return if err.is_connect() {
+                ForwardErrorKind::CONNECT_TIMEOUT
+            } else {
+                ForwardErrorKind::TIMEOUT
+            };
+        }
+        if err.is_connect() {
+            // The underlying TLS errors are wrapped by the connector, e.g: certificate verify failed.
+            let mut source: Option<&(dyn StdError + 'static)> = err.source();
+            while let Some(e) = source {
+                let msg = e.to_string().to_lowercase();
+                if ["certificate", "handshake", "tls", "ssl"]
+                    .iter()
+                    .any(|k| msg.contains(k))
+                {
+                    return ForwardErrorKind::TLS_HANDSHAKE;
+                }
+                source = e.source();
+            }
+            return ForwardErrorKind::CONNECT;
+        }
+        ForwardErrorKind::OTHER
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Upstream forward error ({kind:?}): {message}")]
+pub struct ForwardError {
+    pub kind: ForwardErrorKind,
+    pub message: String,
+}
+
+impl ForwardError {
+    pub fn new(kind: ForwardErrorKind, message: String) -> Self {
+        BOTWAF_FORWARD_ERRORS_TOTAL.with_label_values(&[kind.label()]).inc();
+        ForwardError { kind, message }
+    }
+}
+
+impl From<reqwest::Error> for ForwardError {
+    fn from(err: reqwest::Error) -> Self {
+        ForwardError::new(ForwardErrorKind::classify(&err), err.to_string())
+    }
+}
+
+// The cached clients key of the TLS parameters set, and the upstream authority if the SNI is overridden.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TlsClientKey {
+    tls: UpstreamTlsProperties,
+    authority: Option<String>,
+}
+
+struct CachedClient {
+    // The modified time of the certificate files, which will be rebuilt if changed.
+    modified: Vec<Option<SystemTime>>,
+    client: Client,
+}
+
+/// The per upstream HTTP clients, which are lazily built and cached by the TLS parameters.
+pub struct UpstreamClients {
+    config: ForwardProperties,
+    default_client: Client,
+    cache: RwLock<HashMap<TlsClientKey, CachedClient>>,
+}
+
+impl UpstreamClients {
+    pub fn new(config: &ForwardProperties) -> Self {
+        UpstreamClients {
+            config: config.to_owned(),
+            default_client: Self::new_client_builder(config)
+                .build()
+                .expect("build http client error"),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn new_client_builder(config: &ForwardProperties) -> ClientBuilder {
+        let mut builder = ClientBuilder::new()
+            .connect_timeout(*config.connect_timeout)
+            .read_timeout(*config.read_timeout)
+            .timeout(*config.total_timeout)
+            .connection_verbose(config.verbose);
+        if config.happy_eyeballs.enabled {
+            builder = builder.dns_resolver(Arc::new(HappyEyeballsResolver::new(&config.happy_eyeballs)));
+        }
+        if let Some(proxy) = &config.http_proxy {
+            builder = builder.proxy(Proxy::http(proxy).expect("parse http proxy addr error"));
+        }
+        builder
+    }
+
+    /// Validate the TLS settings of all upstreams, e.g: the 'insecure-skip-verify' without acknowledgement.
+    pub fn validate(&self) -> Result<(), ForwardError> {
+        for upstream in &self.config.upstreams {
+            if let Some(tls) = &upstream.tls {
+                self.check_insecure(&upstream.url_prefix, tls)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_insecure(&self, url_prefix: &str, tls: &UpstreamTlsProperties) -> Result<(), ForwardError> {
+        if !tls.insecure_skip_verify {
+            return Ok(());
+        }
+        if !self.config.insecure_skip_verify_acknowledged {
+            error!(
+                "Rejected the 'insecure-skip-verify' of upstream '{}' without 'insecure-skip-verify-acknowledged'.",
+                url_prefix
+            );
+            return Err(ForwardError::new(
+                ForwardErrorKind::TLS_CONFIG,
+                format!("Rejected the insecure-skip-verify of upstream '{}'", url_prefix),
+            ));
+        }
+        error!(
+            "DANGEROUS!!! The certificate verification of upstream '{}' is disabled, it's vulnerable to MITM attacks.",
+            url_prefix
+        );
+        Ok(())
+    }
+
+    // Find the upstream of the longest matched url prefix.
+    fn find_upstream(&self, url: &str) -> Option<&UpstreamProperties> {
+        self.config
+            .upstreams
+            .iter()
+            .filter(|u| url.starts_with(u.url_prefix.as_str()))
+            .max_by_key(|u| u.url_prefix.len())
+    }
+
+    /// Obtain the client for the forward url, and the rewritten url if the SNI is overridden.
+    pub async fn get(&self, url: String) -> Result<(Client, String), ForwardError> {
+        let upstream = match self.find_upstream(&url) {
+            Some(upstream) => upstream,
+            None => return Ok((self.default_client.to_owned(), url)),
+        };
+        let tls = match &upstream.tls {
+            Some(tls) => tls,
+            None => return Ok((self.default_client.to_owned(), url)),
+        };
+
+        // The SNI override is implemented by connecting the sni name which is resolved to the original addresses.
+        let (authority, url) = match &tls.sni {
+            Some(sni) => {
+                let mut parsed = url::Url::parse(&url)
+                    .map_err(|e| ForwardError::new(ForwardErrorKind::OTHER, format!("Invalid url '{}'. {}", url, e)))?;
+                let authority = format!(
+                    "{}:{}",
+                    parsed.host_str().unwrap_or_default(),
+                    parsed.port_or_known_default().unwrap_or(443)
+                );
+                parsed
+                    .set_host(Some(sni))
+                    .map_err(|e| ForwardError::new(ForwardErrorKind::TLS_CONFIG, format!("Invalid sni. {}", e)))?;
+                (Some(authority), parsed.to_string())
+            }
+            None => (None, url),
+        };
+
+        let key = TlsClientKey {
+            tls: tls.to_owned(),
+            authority,
+        };
+        let modified = Self::get_modified_times(tls);
+        if let Some(cached) = self.cache.read().unwrap().get(&key) {
+            if cached.modified == modified {
+                return Ok((cached.client.to_owned(), url));
+            }
+            info!(
+                "Reloading the upstream client due to certificate files changed for {}",
+                upstream.url_prefix
+            );
+        }
+
+        let client = self.build_client(&upstream.url_prefix, &key).await?;
+        self.cache.write().unwrap().insert(
+            key,
+            CachedClient {
+                modified,
+                client: client.to_owned(),
+            },
+        );
+        Ok((client, url))
+    }
+
+    fn get_modified_times(tls: &UpstreamTlsProperties) -> Vec<Option<SystemTime>> {
+        [&tls.ca_path, &tls.client_cert_path, &tls.client_key_path]
+            .iter()
+            .map(|path| {
+                path.as_ref()
+                    .and_then(|p| fs::metadata(p).ok())
+                    .and_then(|m| m.modified().ok())
+            })
+            .collect()
+    }
+
+    async fn build_client(&self, url_prefix: &str, key: &TlsClientKey) -> Result<Client, ForwardError> {
+        let tls = &key.tls;
+        self.check_insecure(url_prefix, tls)?;
+
+        let tls_config_err = |e: String| ForwardError::new(ForwardErrorKind::TLS_CONFIG, e);
+        let read_file = |path: &String| fs::read(path).map_err(|e| tls_config_err(format!("Read '{}'. {}", path, e)));
+
+        let mut builder = Self::new_client_builder(&self.config);
+        if tls.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if let Some(ca_path) = &tls.ca_path {
+            let certs = Certificate::from_pem_bundle(&read_file(ca_path)?)
+                .map_err(|e| tls_config_err(format!("Invalid CA bundle '{}'. {}", ca_path, e)))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        match (&tls.client_cert_path, &tls.client_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let identity = Identity::from_pkcs8_pem(&read_file(cert_path)?, &read_file(key_path)?)
+                    .map_err(|e| tls_config_err(format!("Invalid client certificate '{}'. {}", cert_path, e)))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(tls_config_err(format!(
+                    "Both client-cert-path and client-key-path are required for upstream '{}'",
+                    url_prefix
+                )))
+            }
+        }
+        if let (Some(sni), Some(authority)) = (&tls.sni, &key.authority) {
+            let addrs = tokio::net::lookup_host(authority)
+                .await
+                .map_err(|e| ForwardError::new(ForwardErrorKind::CONNECT, format!("Resolve '{}'. {}", authority, e)))?
+                .collect::<Vec<SocketAddr>>();
+            let addrs = if self.config.happy_eyeballs.enabled {
+                HappyEyeballsResolver::sort_addrs(addrs, self.config.happy_eyeballs.prefer)
+            } else {
+                addrs
+            };
+            builder = builder.resolve_to_addrs(sni, &addrs);
+        }
+
+        builder
+            .build()
+            .map_err(|e| tls_config_err(format!("Build client for upstream '{}'. {}", url_prefix, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::{BigNum, MsbOption},
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        x509::{
+            extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
+            X509Builder, X509NameBuilder, X509,
+        },
+    };
+    use std::{io::BufReader, path::PathBuf, sync::Arc};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{
+        rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig},
+        TlsAcceptor,
+    };
+
+    const UPSTREAM_NAME: &str = "upstream.internal";
+
+    fn new_key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    // Issue the self-signed CA if no issuer, otherwise the server or client certificate.
+    fn new_cert(cn: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>, server: bool) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+        builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        match issuer {
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                builder
+                    .append_extension(KeyUsage::new().critical().key_cert_sign().crl_sign().build().unwrap())
+                    .unwrap();
+                builder.sign(key, MessageDigest::sha256()).unwrap();
+            }
+            Some((ca_cert, ca_key)) => {
+                builder.set_issuer_name(ca_cert.subject_name()).unwrap();
+                builder
+                    .append_extension(BasicConstraints::new().build().unwrap())
+                    .unwrap();
+                builder
+                    .append_extension(
+                        KeyUsage::new()
+                            .critical()
+                            .digital_signature()
+                            .key_encipherment()
+                            .build()
+                            .unwrap(),
+                    )
+                    .unwrap();
+                if server {
+                    builder
+                        .append_extension(ExtendedKeyUsage::new().server_auth().build().unwrap())
+                        .unwrap();
+                    let san = SubjectAlternativeName::new()
+                        .dns(cn)
+                        .dns("localhost")
+                        .build(&builder.x509v3_context(Some(ca_cert), None))
+                        .unwrap();
+                    builder.append_extension(san).unwrap();
+                } else {
+                    builder
+                        .append_extension(ExtendedKeyUsage::new().client_auth().build().unwrap())
+                        .unwrap();
+                }
+                builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        builder.build()
+    }
+
+    // Generate the PKI files of the CA, upstream server and client.
+    fn create_test_pki() -> PathBuf {
+        let mut suffix = BigNum::new().unwrap();
+        suffix.rand(32, MsbOption::MAYBE_ZERO, false).unwrap();
+        let dir = std::env::temp_dir().join(format!("botwaf-test-tls-{}", suffix.to_dec_str().unwrap()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = new_key();
+        let ca_cert = new_cert("Botwaf Test CA", &ca_key, None, false);
+        let server_key = new_key();
+        let server_cert = new_cert(UPSTREAM_NAME, &server_key, Some((&ca_cert, &ca_key)), true);
+        let client_key = new_key();
+        let client_cert = new_cert("botwaf-client", &client_key, Some((&ca_cert, &ca_key)), false);
+
+        fs::write(dir.join("ca.pem"), ca_cert.to_pem().unwrap()).unwrap();
+        fs::write(dir.join("server.pem"), server_cert.to_pem().unwrap()).unwrap();
+        fs::write(dir.join("server.key"), server_key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        fs::write(dir.join("client.pem"), client_cert.to_pem().unwrap()).unwrap();
+        fs::write(dir.join("client.key"), client_key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        dir
+    }
+
+    // Spawn the TLS upstream, which requires the client certificate issued by the CA if mTLS.
+    async fn spawn_tls_upstream(dir: &PathBuf, mtls: bool) -> u16 {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(fs::File::open(dir.join("server.pem")).unwrap()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = rustls_pemfile::private_key(&mut BufReader::new(fs::File::open(dir.join("server.key")).unwrap()))
+            .unwrap()
+            .unwrap();
+        let tls_config = if mtls {
+            let mut roots = RootCertStore::empty();
+            for ca in rustls_pemfile::certs(&mut BufReader::new(fs::File::open(dir.join("ca.pem")).unwrap())) {
+                roots.add(ca.unwrap()).unwrap();
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build().unwrap();
+            ServerConfig::builder()
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(certs, key)
+                .unwrap()
+        } else {
+            ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .unwrap()
+        };
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.to_owned();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let mut buf = vec![0u8; 4096];
+                        let _ = stream.read(&mut buf).await;
+                        let resp = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+                        let _ = stream.write_all(resp.as_bytes()).await;
+                        let _ = stream.shutdown().await;
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    fn create_test_config(url_prefix: &str, tls: UpstreamTlsProperties) -> ForwardProperties {
+        let mut config = ForwardProperties::default();
+        config.upstreams.push(UpstreamProperties {
+            url_prefix: url_prefix.to_owned(),
+            default: false,
+            host_header: None,
+            tls: Some(tls),
+            mirror: None,
+            response_headers: Default::default(),
+            max_request_header_bytes: None,
+        });
+        config
+    }
+
+    async fn do_request(clients: &UpstreamClients, url: String) -> Result<String, ForwardError> {
+        let (client, url) = clients.get(url).await?;
+        let resp = client.get(url).send().await?;
+        Ok(resp.text().await?)
+    }
+
+    #[tokio::test]
+    async fn test_custom_ca_upstream() {
+        let dir = create_test_pki();
+        let port = spawn_tls_upstream(&dir, false).await;
+        let url = format!("https://localhost:{}/hello", port);
+
+        // Untrusted by the default client, which is distinguishable as the handshake failure.
+        let clients = UpstreamClients::new(&ForwardProperties::default());
+        let err = do_request(&clients, url.to_owned()).await.unwrap_err();
+        assert_eq!(err.kind, ForwardErrorKind::TLS_HANDSHAKE);
+
+        let tls = UpstreamTlsProperties {
+            ca_path: Some(dir.join("ca.pem").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let clients = UpstreamClients::new(&create_test_config("https://localhost", tls));
+        assert_eq!(do_request(&clients, url).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_mtls_upstream() {
+        let dir = create_test_pki();
+        let port = spawn_tls_upstream(&dir, true).await;
+        let url = format!("https://localhost:{}/hello", port);
+        let ca_path = Some(dir.join("ca.pem").to_string_lossy().to_string());
+
+        // Rejected by the upstream without client certificate.
+        let tls = UpstreamTlsProperties {
+            ca_path: ca_path.to_owned(),
+            ..Default::default()
+        };
+        let clients = UpstreamClients::new(&create_test_config("https://localhost", tls));
+        assert!(do_request(&clients, url.to_owned()).await.is_err());
+
+        let tls = UpstreamTlsProperties {
+            ca_path,
+            client_cert_path: Some(dir.join("client.pem").to_string_lossy().to_string()),
+            client_key_path: Some(dir.join("client.key").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let clients = UpstreamClients::new(&create_test_config("https://localhost", tls));
+        assert_eq!(do_request(&clients, url).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_sni_override_upstream() {
+        let dir = create_test_pki();
+        let port = spawn_tls_upstream(&dir, false).await;
+
+        let tls = UpstreamTlsProperties {
+            ca_path: Some(dir.join("ca.pem").to_string_lossy().to_string()),
+            sni: Some(UPSTREAM_NAME.to_owned()),
+            ..Default::default()
+        };
+        let clients = UpstreamClients::new(&create_test_config("https://127.0.0.1", tls));
+        let url = format!("https://127.0.0.1:{}/hello", port);
+        assert_eq!(do_request(&clients, url).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused_not_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let clients = UpstreamClients::new(&ForwardProperties::default());
+        let err = do_request(&clients, format!("https://127.0.0.1:{}/", port))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ForwardErrorKind::CONNECT);
+    }
+
+    #[test]
+    fn test_insecure_skip_verify_requires_acknowledged() {
+        let tls = UpstreamTlsProperties {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        let mut config = create_test_config("https://localhost", tls);
+        let err = UpstreamClients::new(&config).validate().unwrap_err();
+        assert_eq!(err.kind, ForwardErrorKind::TLS_CONFIG);
+
+        config.insecure_skip_verify_acknowledged = true;
+        assert!(UpstreamClients::new(&config).validate().is_ok());
+    }
+}
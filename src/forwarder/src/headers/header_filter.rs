@@ -0,0 +1,315 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::{
+    config::config::{ResponseHeadersMode, ResponseHeadersProperties, UpstreamProperties},
+    mgmt::apm::metrics::BOTWAF_STRIPPED_RESPONSE_HEADERS_TOTAL,
+};
+use botwaf_types::modules::forward::header_filter::{StrippedHeaderEntry, StrippedHeadersReport};
+use hyper::{header, HeaderMap};
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<StrippedHeaderTracker> = Arc::new(StrippedHeaderTracker::new());
+}
+
+/// The recently stripped response headers per upstream, which is bounded by the top-N (space-saving) of
+/// each upstream, and the metric labels are bounded by the first admitted header names.
+pub struct StrippedHeaderTracker {
+    upstreams: Mutex<HashMap<String, HashMap<String, StrippedHeaderEntry>>>,
+    labels: Mutex<HashSet<String>>,
+}
+
+impl StrippedHeaderTracker {
+    pub const MAX_HEADERS_PER_UPSTREAM: usize = 64;
+    pub const MAX_METRIC_LABELS: usize = 32;
+    pub const OTHER_LABEL: &'static str = "other";
+
+    fn new() -> Self {
+        StrippedHeaderTracker {
+            upstreams: Mutex::new(HashMap::new()),
+            labels: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn get() -> Arc<StrippedHeaderTracker> {
+        SINGLE_INSTANCE.to_owned()
+    }
+
+    pub fn record(&self, upstream: &str, header: &str, now_millis: i64) {
+        let header = header.to_lowercase();
+        BOTWAF_STRIPPED_RESPONSE_HEADERS_TOTAL
+            .with_label_values(&[upstream, self.metric_label(&header).as_str()])
+            .inc();
+
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let headers = upstreams.entry(upstream.to_owned()).or_default();
+        if let Some(entry) = headers.get_mut(&header) {
+            entry.count += 1;
+            entry.last_stripped_at = now_millis;
+            return;
+        }
+        // Replace the least stripped header with the over-estimated count when full.
+        let mut count = 1;
+        if headers.len() >= Self::MAX_HEADERS_PER_UPSTREAM {
+            if let Some(min) = headers.values().min_by_key(|e| e.count).map(|e| e.header.to_owned()) {
+                count += headers.remove(&min).map(|e| e.count).unwrap_or(0);
+            }
+        }
+        headers.insert(
+            header.to_owned(),
+            StrippedHeaderEntry {
+                header,
+                count,
+                last_stripped_at: now_millis,
+            },
+        );
+    }
+
+    fn metric_label(&self, header: &str) -> String {
+        let mut labels = self.labels.lock().unwrap();
+        if labels.contains(header) {
+            return header.to_owned();
+        }
+        if labels.len() < Self::MAX_METRIC_LABELS {
+            labels.insert(header.to_owned());
+            return header.to_owned();
+        }
+        Self::OTHER_LABEL.to_owned()
+    }
+
+    pub fn report(&self, upstream: Option<&str>) -> Vec<StrippedHeadersReport> {
+        let upstreams = self.upstreams.lock().unwrap();
+        let mut reports = upstreams
+            .iter()
+            .filter(|(u, _)| upstream.is_none() || upstream == Some(u.as_str()))
+            .map(|(u, headers)| {
+                let mut headers = headers.values().cloned().collect::<Vec<_>>();
+                headers.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.header.cmp(&b.header)));
+                StrippedHeadersReport {
+                    upstream: u.to_owned(),
+                    headers,
+                }
+            })
+            .collect::<Vec<_>>();
+        reports.sort_by(|a, b| a.upstream.cmp(&b.upstream));
+        reports
+    }
+}
+
+pub struct ResponseHeaderFilter {
+    url_prefix: String,
+    allowlist: HashSet<String>,
+    allow_set_cookie: bool,
+}
+
+impl ResponseHeaderFilter {
+    // The essential headers which are always allowed, note that the body is forwarded as is (e.g: without
+    // decompression), so the 'Content-Encoding' is also required for the client to decode it.
+    const ESSENTIAL_HEADERS: [&'static str; 5] = [
+        "content-type",
+        "content-length",
+        "content-encoding",
+        "transfer-encoding",
+        "cache-control",
+    ];
+
+    pub fn new(url_prefix: &str, config: &ResponseHeadersProperties) -> Arc<Self> {
+        Arc::new(ResponseHeaderFilter {
+            url_prefix: url_prefix.to_owned(),
+            allowlist: config.allowlist.iter().map(|h| h.trim().to_lowercase()).collect(),
+            allow_set_cookie: config.allow_set_cookie,
+        })
+    }
+
+    pub fn is_allowed(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        if Self::ESSENTIAL_HEADERS.contains(&name.as_str()) {
+            return true;
+        }
+        // The 'Set-Cookie' is only determined by the switch, regardless of the allowlist.
+        if name == header::SET_COOKIE.as_str() {
+            return self.allow_set_cookie;
+        }
+        self.allowlist.contains(&name)
+    }
+
+    /// Strip the disallowed headers (including all the values) and record them.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let stripped = headers
+            .keys()
+            .filter(|name| !self.is_allowed(name.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        if stripped.is_empty() {
+            return;
+        }
+        let now_millis = chrono::Utc::now().timestamp_millis();
+        let tracker = StrippedHeaderTracker::get();
+        for name in stripped {
+            headers.remove(&name);
+            tracker.record(&self.url_prefix, name.as_str(), now_millis);
+        }
+    }
+}
+
+pub struct ResponseHeaderFilters {
+    filters: Vec<Arc<ResponseHeaderFilter>>,
+}
+
+impl ResponseHeaderFilters {
+    pub fn new(upstreams: &Vec<UpstreamProperties>) -> Self {
+        // The PASSTHROUGH upstreams have no filter at all.
+        let filters = upstreams
+            .iter()
+            .filter(|u| u.response_headers.mode == ResponseHeadersMode::ALLOWLIST)
+            .map(|u| ResponseHeaderFilter::new(&u.url_prefix, &u.response_headers))
+            .collect();
+        ResponseHeaderFilters { filters }
+    }
+
+    pub fn find(&self, url: &str) -> Option<&Arc<ResponseHeaderFilter>> {
+        self.filters
+            .iter()
+            .filter(|f| url.starts_with(f.url_prefix.as_str()))
+            .max_by_key(|f| f.url_prefix.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn create_test_upstream(url_prefix: &str, mode: ResponseHeadersMode, allow_set_cookie: bool) -> UpstreamProperties {
+        UpstreamProperties {
+            url_prefix: url_prefix.to_owned(),
+            response_headers: ResponseHeadersProperties {
+                mode,
+                allowlist: vec![String::from("ETag"), String::from(" x-request-id ")],
+                allow_set_cookie,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn create_test_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static("2"));
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(header::ETAG, HeaderValue::from_static("\"v1\""));
+        headers.insert("X-Request-Id", HeaderValue::from_static("abc"));
+        headers.insert(header::SERVER, HeaderValue::from_static("internal/1.2.3"));
+        headers.append("X-Internal-Trace", HeaderValue::from_static("node-1"));
+        headers.append("X-Internal-Trace", HeaderValue::from_static("node-2"));
+        headers.append(header::SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(header::SET_COOKIE, HeaderValue::from_static("b=2"));
+        headers
+    }
+
+    #[test]
+    fn test_passthrough_has_no_filter() {
+        let filters = ResponseHeaderFilters::new(&vec![create_test_upstream(
+            "http://passthrough.internal",
+            ResponseHeadersMode::PASSTHROUGH,
+            false,
+        )]);
+        assert!(filters.find("http://passthrough.internal/orders").is_none());
+    }
+
+    #[test]
+    fn test_allowlist_keeps_essential_and_listed_headers() {
+        let filters = ResponseHeaderFilters::new(&vec![create_test_upstream(
+            "http://allowlist.internal",
+            ResponseHeadersMode::ALLOWLIST,
+            true,
+        )]);
+        let filter = filters.find("http://allowlist.internal/orders").unwrap();
+
+        let mut headers = create_test_headers();
+        filter.apply(&mut headers);
+        for name in [
+            header::CONTENT_TYPE,
+            header::CONTENT_LENGTH,
+            header::CONTENT_ENCODING,
+            header::CACHE_CONTROL,
+            header::ETAG,
+        ] {
+            assert!(headers.contains_key(&name), "missing: {}", name);
+        }
+        assert_eq!(headers.get("x-request-id").unwrap(), "abc");
+        assert!(!headers.contains_key(header::SERVER));
+        assert!(!headers.contains_key("x-internal-trace"));
+        assert!(ResponseHeaderFilter::new("http://te", &Default::default()).is_allowed("Transfer-Encoding"));
+
+        let report = StrippedHeaderTracker::get().report(Some("http://allowlist.internal"));
+        assert_eq!(report.len(), 1);
+        let stripped = report[0].headers.iter().map(|h| h.header.as_str()).collect::<Vec<_>>();
+        assert_eq!(stripped, vec!["server", "x-internal-trace"]);
+    }
+
+    #[test]
+    fn test_allowlist_set_cookie_switch() {
+        let filters = ResponseHeaderFilters::new(&vec![
+            create_test_upstream("http://cookie.internal", ResponseHeadersMode::ALLOWLIST, true),
+            create_test_upstream("http://cookie.internal/admin", ResponseHeadersMode::ALLOWLIST, false),
+        ]);
+
+        // All the values of the multiple 'Set-Cookie' are kept.
+        let mut headers = create_test_headers();
+        let filter = filters.find("http://cookie.internal/orders").unwrap();
+        filter.apply(&mut headers);
+        assert_eq!(headers.get_all(header::SET_COOKIE).iter().count(), 2);
+
+        // The longest prefix is matched, which disallowed the 'Set-Cookie'.
+        let mut headers = create_test_headers();
+        let filter = filters.find("http://cookie.internal/admin/users").unwrap();
+        filter.apply(&mut headers);
+        assert!(!headers.contains_key(header::SET_COOKIE));
+        assert!(headers.contains_key(header::CONTENT_TYPE));
+
+        // The 'Set-Cookie' is stripped even if listed explicitly.
+        let mut config = create_test_upstream("http://cookie.internal", ResponseHeadersMode::ALLOWLIST, false);
+        config.response_headers.allowlist.push(String::from("Set-Cookie"));
+        let filter = ResponseHeaderFilter::new(&config.url_prefix, &config.response_headers);
+        assert!(!filter.is_allowed("set-cookie"));
+    }
+
+    #[test]
+    fn test_tracker_bounded() {
+        let tracker = StrippedHeaderTracker::new();
+        for i in 0..(StrippedHeaderTracker::MAX_HEADERS_PER_UPSTREAM + 10) {
+            tracker.record("http://bounded.internal", &format!("x-header-{}", i), 1);
+        }
+        tracker.record("http://bounded.internal", "X-Header-0", 2);
+
+        let report = tracker.report(None);
+        assert_eq!(report[0].headers.len(), StrippedHeaderTracker::MAX_HEADERS_PER_UPSTREAM);
+        assert!(tracker.labels.lock().unwrap().len() <= StrippedHeaderTracker::MAX_METRIC_LABELS);
+        assert_eq!(tracker.metric_label("x-header-999"), StrippedHeaderTracker::OTHER_LABEL);
+        assert_eq!(tracker.metric_label("x-header-0"), "x-header-0");
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::header_filter::StrippedHeaderTracker;
+use axum::{extract::Query, response::IntoResponse, routing::get, Json, Router};
+use botwaf_server::{context::state::BotwafState, util::web::RequireAdmin};
+use botwaf_types::modules::forward::header_filter::{
+    StrippedHeaderEntry, StrippedHeadersQueryRequest, StrippedHeadersReport,
+};
+use hyper::StatusCode;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(handle_stripped_headers),
+    components(schemas(StrippedHeaderEntry, StrippedHeadersReport))
+)]
+pub struct HeaderFilterApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/forward/stripped-headers", get(handle_stripped_headers))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/forward/stripped-headers",
+    params(StrippedHeadersQueryRequest),
+    responses((status = 200, description = "Getting the recently stripped response headers of the ALLOWLIST mode upstreams.", body = [StrippedHeadersReport])),
+    tag = "Forward"
+)]
+async fn handle_stripped_headers(
+    _: RequireAdmin,
+    Query(param): Query<StrippedHeadersQueryRequest>,
+) -> impl IntoResponse {
+    let reports = StrippedHeaderTracker::get().report(param.upstream.as_deref());
+    (StatusCode::OK, Json(reports)).into_response()
+}
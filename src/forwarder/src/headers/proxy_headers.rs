@@ -0,0 +1,444 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::config::config::{self, ProxyHeadersProperties};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use botwaf_utils::inets;
+use hyper::{
+    header::{self, HeaderValue},
+    HeaderMap, Version,
+};
+use lazy_static::lazy_static;
+use std::{net::IpAddr, sync::Arc};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ProxyHeaders = ProxyHeaders::new(&config::get_config().services.forward.proxy_headers);
+}
+
+pub const X_FORWARDED_FOR: &'static str = "x-forwarded-for";
+pub const X_FORWARDED_PROTO: &'static str = "x-forwarded-proto";
+pub const X_FORWARDED_HOST: &'static str = "x-forwarded-host";
+pub const X_FORWARDED_PORT: &'static str = "x-forwarded-port";
+pub const X_REAL_IP: &'static str = "x-real-ip";
+
+/// The received-protocol of the Via element, the requests are always forwarded to the upstream with HTTP/1.1.
+const VIA_PROTOCOL: &'static str = "1.1";
+
+/// The proxy headers of the forwarded requests in the multi-proxy topologies, i.e: the Via (RFC 9110 section
+/// 7.6.3), the X-Forwarded-* and the standardized Forwarded (RFC 7239), the existing chains of the previous
+/// proxies are always appended and never overwritten.
+pub struct ProxyHeaders {
+    // The Via received-by pseudonym of this instance, e.g: botwaf-01
+    pseudonym: String,
+    via: bool,
+    forwarded: bool,
+    trusted_proxies: Vec<String>,
+}
+
+impl ProxyHeaders {
+    pub fn new(config: &ProxyHeadersProperties) -> Self {
+        ProxyHeaders {
+            pseudonym: format!("botwaf-{}", config.get_instance_id()),
+            via: config.via,
+            forwarded: config.forwarded,
+            trusted_proxies: config.trusted_proxies.to_owned(),
+        }
+    }
+
+    pub fn get() -> &'static ProxyHeaders {
+        &SINGLE_INSTANCE
+    }
+
+    /// The Via element of this instance, e.g: 1.1 botwaf-01
+    pub fn via_token(&self) -> String {
+        format!("{} {}", VIA_PROTOCOL, self.pseudonym)
+    }
+
+    /// Whether the request has passed through this instance already, i.e: our own Via element is present.
+    pub fn is_looped(&self, headers: &HeaderMap) -> bool {
+        self.via
+            && headers
+                .get_all(header::VIA)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                // The Via element is: received-protocol received-by [ comment ]
+                .any(|element| element.split_whitespace().nth(1) == Some(self.pseudonym.as_str()))
+    }
+
+    /// Whether the header is regenerated by the forwarder, which must not be copied as-is.
+    pub fn is_proxy_header(name: &str) -> bool {
+        [
+            X_FORWARDED_FOR,
+            X_FORWARDED_PROTO,
+            X_FORWARDED_HOST,
+            X_FORWARDED_PORT,
+            header::VIA.as_str(),
+            header::FORWARDED.as_str(),
+        ]
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the connection peer is the trusted proxy, none is trusted if the trusted proxies are not configured.
+    pub fn is_trusted_peer(&self, peer_ip: Option<&str>) -> bool {
+        peer_ip
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .is_some_and(|ip| self.is_trusted_proxy(&ip))
+    }
+
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|p| inets::is_ip_in_cidr(ip, p))
+    }
+
+    /// Resolve the IP reported as the client, i.e: the right-most X-Forwarded-For entry which is not the trusted
+    /// proxy, or the peer itself if it's not the trusted proxy (the X-Forwarded-For could be set by anyone).
+    pub fn resolve_client_ip(&self, incoming: &HttpIncomingRequest) -> Option<String> {
+        let forwarded_for = Self::forwarded_for(incoming);
+        let client_ip = if self.is_trusted_peer(incoming.peer_ip.as_deref()) {
+            forwarded_for
+                .iter()
+                .rev()
+                .find(|entry| !entry.parse::<IpAddr>().is_ok_and(|ip| self.is_trusted_proxy(&ip)))
+                .or(forwarded_for.first())
+                .cloned()
+        } else {
+            return incoming.peer_ip.as_deref().map(inets::canonicalize_ip);
+        };
+        client_ip
+            .or_else(|| Self::header(incoming, X_REAL_IP))
+            .or_else(|| incoming.peer_ip.to_owned())
+            .map(|ip| inets::canonicalize_ip(&ip))
+    }
+
+    /// Wrap the incoming request with the resolved client IP.
+    pub fn resolve(&self, incoming: Arc<HttpIncomingRequest>) -> Arc<HttpIncomingRequest> {
+        let client_ip = self.resolve_client_ip(&incoming);
+        if client_ip == incoming.client_ip {
+            return incoming;
+        }
+        let mut incoming = (*incoming).clone();
+        incoming.client_ip = client_ip;
+        Arc::new(incoming)
+    }
+
+    /// The proxy headers of the request to be forwarded, the X-Forwarded-Proto/Host/Port are the original
+    /// client-facing values which are honored only from the trusted proxies.
+    pub fn request_headers(&self, incoming: &HttpIncomingRequest) -> Vec<(&'static str, String)> {
+        let trusted = self.is_trusted_peer(incoming.peer_ip.as_deref());
+        let honored = |name: &str| if trusted { Self::header(incoming, name) } else { None };
+        let proto = honored(X_FORWARDED_PROTO).unwrap_or_else(|| Self::client_facing_proto(incoming).to_owned());
+        let authority = Self::header(incoming, header::HOST.as_str());
+        let host = honored(X_FORWARDED_HOST)
+            .or_else(|| authority.to_owned())
+            .or(incoming.host.to_owned());
+        let port = honored(X_FORWARDED_PORT)
+            .unwrap_or_else(|| Self::client_facing_port(authority.as_deref(), incoming.port, &proto).to_string());
+        let forwarded_for = Self::forwarded_for(incoming);
+
+        let mut headers = Vec::new();
+        if self.forwarded {
+            let mut elements = match Self::header(incoming, header::FORWARDED.as_str()) {
+                Some(forwarded) => vec![forwarded],
+                // Converted from the X-Forwarded-For chain of the previous proxies.
+                None => forwarded_for
+                    .iter()
+                    .map(|entry| format!("for={}", Self::forwarded_node(entry)))
+                    .collect(),
+            };
+            let peer = incoming.peer_ip.as_deref().unwrap_or("unknown");
+            let mut element = format!("for={};proto={}", Self::forwarded_node(peer), proto);
+            if let Some(host) = host {
+                element.push_str(&format!(";host={}", Self::forwarded_value(&host)));
+            }
+            elements.push(element);
+            headers.push((header::FORWARDED.as_str(), elements.join(", ")));
+        } else {
+            let mut chain = forwarded_for;
+            chain.extend(incoming.peer_ip.to_owned());
+            if !chain.is_empty() {
+                headers.push((X_FORWARDED_FOR, chain.join(", ")));
+            }
+            headers.push((X_FORWARDED_PROTO, proto));
+            if let Some(host) = host {
+                headers.push((X_FORWARDED_HOST, host));
+            }
+            headers.push((X_FORWARDED_PORT, port));
+        }
+        if self.via {
+            let via = match Self::header(incoming, header::VIA.as_str()) {
+                Some(via) => format!("{}, {}", via, self.via_token()),
+                None => self.via_token(),
+            };
+            headers.push((header::VIA.as_str(), via));
+        }
+        headers
+    }
+
+    /// Append this instance to the Via of the upstream response.
+    pub fn append_response_via(&self, headers: &mut HeaderMap) {
+        if !self.via {
+            return;
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.via_token()) {
+            headers.append(header::VIA, value);
+        }
+    }
+
+    fn header(incoming: &HttpIncomingRequest, name: &str) -> Option<String> {
+        incoming
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn forwarded_for(incoming: &HttpIncomingRequest) -> Vec<String> {
+        Self::header(incoming, X_FORWARDED_FOR)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|entry| entry.trim().to_owned())
+                    .filter(|entry| !entry.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn client_facing_proto(incoming: &HttpIncomingRequest) -> &str {
+        match incoming.scheme.as_deref() {
+            Some(scheme) => scheme,
+            // The HTTP/3 is always over TLS (QUIC).
+            None if incoming.version == Version::HTTP_3 => "https",
+            None => "http",
+        }
+    }
+
+    // The port of the Host header (e.g: example.com:8443, [::1]:8443), fallback to the default port of the proto.
+    fn client_facing_port(authority: Option<&str>, port: Option<u16>, proto: &str) -> u16 {
+        authority
+            .and_then(|authority| authority.rsplit_once(':'))
+            .filter(|(_, port)| !port.contains(']'))
+            .and_then(|(_, port)| port.parse().ok())
+            .or(port)
+            .unwrap_or(if proto.eq_ignore_ascii_case("https") { 443 } else { 80 })
+    }
+
+    // The node of the Forwarded 'for' parameter, the IPv6 is bracketed and quoted, see: RFC 7239 section 6
+    fn forwarded_node(node: &str) -> String {
+        match node.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+            _ => Self::forwarded_value(node),
+        }
+    }
+
+    // The token or quoted-string value of the Forwarded parameter, see: RFC 7230 section 3.2.6
+    fn forwarded_value(value: &str) -> String {
+        let is_token = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+        if is_token {
+            value.to_owned()
+        } else {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+
+    fn create_proxy_headers(trusted_proxies: &[&str], forwarded: bool) -> ProxyHeaders {
+        ProxyHeaders::new(&ProxyHeadersProperties {
+            instance_id: Some(String::from("01")),
+            via: true,
+            trusted_proxies: trusted_proxies.iter().map(|p| p.to_string()).collect(),
+            forwarded,
+        })
+    }
+
+    fn create_test_incoming(peer_ip: &str, headers: &[(&str, &str)]) -> HttpIncomingRequest {
+        TestIncomingBuilder::new("GET", "/orders")
+            .host("shop.example.com")
+            .headers(headers)
+            .peer_ip(peer_ip)
+            .build()
+    }
+
+    fn find<'a>(headers: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(k, _)| *k == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_is_looped_by_own_via_token() {
+        let proxy_headers = create_proxy_headers(&[], false);
+        assert_eq!(proxy_headers.via_token(), "1.1 botwaf-01");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::VIA,
+            HeaderValue::from_static("1.1 abc.cloudfront.net (CloudFront)"),
+        );
+        assert!(!proxy_headers.is_looped(&headers));
+        // The other instance of botwaf is not the loop.
+        headers.append(header::VIA, HeaderValue::from_static("1.1 botwaf-02, 1.1 gateway"));
+        assert!(!proxy_headers.is_looped(&headers));
+        headers.append(
+            header::VIA,
+            HeaderValue::from_static("1.1 gateway, 1.1 botwaf-01 (Botwaf)"),
+        );
+        assert!(proxy_headers.is_looped(&headers));
+
+        let proxy_headers = ProxyHeaders::new(&ProxyHeadersProperties {
+            instance_id: Some(String::from("01")),
+            via: false,
+            ..Default::default()
+        });
+        assert!(!proxy_headers.is_looped(&headers));
+    }
+
+    #[test]
+    fn test_resolve_client_ip_by_trusted_proxies() {
+        let incoming = create_test_incoming(
+            "130.176.1.10",
+            &[("x-forwarded-for", "198.51.100.7, 203.0.113.9, 130.176.2.20")],
+        );
+        // None is trusted without the trusted proxies, so the X-Forwarded-For is ignored.
+        let proxy_headers = create_proxy_headers(&[], false);
+        assert!(!proxy_headers.is_trusted_peer(Some("130.176.1.10")));
+        assert_eq!(
+            proxy_headers.resolve_client_ip(&incoming).as_deref(),
+            Some("130.176.1.10")
+        );
+
+        // The right-most untrusted entry is the client, the left entries could be spoofed by it.
+        let proxy_headers = create_proxy_headers(&["130.176.0.0/16"], false);
+        assert_eq!(
+            proxy_headers.resolve_client_ip(&incoming).as_deref(),
+            Some("203.0.113.9")
+        );
+
+        // The X-Forwarded-For of the untrusted peer is ignored.
+        let incoming = create_test_incoming("192.0.2.1", &[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!(proxy_headers.resolve_client_ip(&incoming).as_deref(), Some("192.0.2.1"));
+
+        // The direct client without the X-Forwarded-For.
+        let incoming = create_test_incoming("130.176.1.10", &[]);
+        assert_eq!(
+            proxy_headers.resolve_client_ip(&incoming).as_deref(),
+            Some("130.176.1.10")
+        );
+    }
+
+    #[test]
+    fn test_request_headers_append_multi_hop_chains() {
+        let proxy_headers = create_proxy_headers(&["130.176.0.0/16"], false);
+        let incoming = create_test_incoming(
+            "130.176.1.10",
+            &[
+                ("host", "shop.example.com"),
+                ("x-forwarded-for", "198.51.100.7, 203.0.113.9"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-port", "443"),
+                ("via", "2.0 abc.cloudfront.net (CloudFront)"),
+            ],
+        );
+        let headers = proxy_headers.request_headers(&incoming);
+        assert_eq!(
+            find(&headers, X_FORWARDED_FOR),
+            Some("198.51.100.7, 203.0.113.9, 130.176.1.10")
+        );
+        assert_eq!(find(&headers, X_FORWARDED_PROTO), Some("https"));
+        assert_eq!(find(&headers, X_FORWARDED_HOST), Some("shop.example.com"));
+        assert_eq!(find(&headers, X_FORWARDED_PORT), Some("443"));
+        assert_eq!(
+            find(&headers, "via"),
+            Some("2.0 abc.cloudfront.net (CloudFront), 1.1 botwaf-01")
+        );
+        assert_eq!(find(&headers, "forwarded"), None);
+
+        // The client-facing values of the untrusted peer are not honored, but the chain is still appended.
+        let incoming = create_test_incoming(
+            "192.0.2.1",
+            &[
+                ("host", "shop.example.com:8080"),
+                ("x-forwarded-for", "198.51.100.7"),
+                ("x-forwarded-proto", "https"),
+            ],
+        );
+        let headers = proxy_headers.request_headers(&incoming);
+        assert_eq!(find(&headers, X_FORWARDED_FOR), Some("198.51.100.7, 192.0.2.1"));
+        assert_eq!(find(&headers, X_FORWARDED_PROTO), Some("http"));
+        assert_eq!(find(&headers, X_FORWARDED_HOST), Some("shop.example.com:8080"));
+        assert_eq!(find(&headers, X_FORWARDED_PORT), Some("8080"));
+        assert_eq!(find(&headers, "via"), Some("1.1 botwaf-01"));
+    }
+
+    #[test]
+    fn test_request_headers_with_forwarded() {
+        let proxy_headers = create_proxy_headers(&["130.176.0.0/16"], true);
+        // Converted from the X-Forwarded-For chain of the previous proxies.
+        let incoming = create_test_incoming(
+            "2001:db8::10",
+            &[("host", "shop.example.com:8443"), ("x-forwarded-for", "198.51.100.7")],
+        );
+        let headers = proxy_headers.request_headers(&incoming);
+        assert_eq!(
+            find(&headers, "forwarded"),
+            Some("for=198.51.100.7, for=\"[2001:db8::10]\";proto=http;host=\"shop.example.com:8443\"")
+        );
+        assert_eq!(find(&headers, X_FORWARDED_FOR), None);
+        assert_eq!(find(&headers, X_FORWARDED_PROTO), None);
+
+        // The existing Forwarded is appended, and the client-facing proto is honored from the trusted proxy.
+        let incoming = create_test_incoming(
+            "130.176.1.10",
+            &[
+                ("host", "shop.example.com"),
+                ("forwarded", "for=198.51.100.7;proto=https"),
+                ("x-forwarded-proto", "https"),
+            ],
+        );
+        let headers = proxy_headers.request_headers(&incoming);
+        assert_eq!(
+            find(&headers, "forwarded"),
+            Some("for=198.51.100.7;proto=https, for=130.176.1.10;proto=https;host=shop.example.com")
+        );
+    }
+
+    #[test]
+    fn test_append_response_via() {
+        let proxy_headers = create_proxy_headers(&[], false);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VIA, HeaderValue::from_static("1.1 gateway"));
+        proxy_headers.append_response_via(&mut headers);
+        let via: Vec<&str> = headers
+            .get_all(header::VIA)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect();
+        assert_eq!(via, vec!["1.1 gateway", "1.1 botwaf-01"]);
+    }
+}
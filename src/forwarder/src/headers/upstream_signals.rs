@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::config::config::{self, UpstreamSignalsProperties};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use botwaf_utils::upstream_signals::UpstreamSignals;
+use globset::{Glob, GlobMatcher};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: UpstreamSignaler =
+        UpstreamSignaler::new(&config::get_config().services.forward.upstream_signals);
+}
+
+/// The decision of the forwarded request, i.e: PASS, or ALLOW if allowed by the plugin.
+pub const SIGNAL_DECISION_PASS: &'static str = "PASS";
+pub const SIGNAL_DECISION_ALLOW: &'static str = "ALLOW";
+
+/// The signals collected along the evaluation of the (not blocked) request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotwafSignals {
+    pub decision: &'static str,
+    pub score: i64,
+    pub flags: Vec<String>,
+}
+
+impl Default for BotwafSignals {
+    fn default() -> Self {
+        BotwafSignals {
+            decision: SIGNAL_DECISION_PASS,
+            score: 0,
+            flags: Vec::new(),
+        }
+    }
+}
+
+impl BotwafSignals {
+    pub fn flag(&mut self, flag: impl Into<String>) {
+        self.flags.push(flag.into());
+    }
+}
+
+/// The upstream signaling, which injects the signals (i.e: the decision, score and flags) into the forwarded
+/// requests of the enabled routes, and strips the spoofed signal headers arrived from the clients.
+pub struct UpstreamSignaler {
+    routes: Vec<GlobMatcher>,
+    // The lowercase header names, i.e: decision, score, flags and signature.
+    decision_header: String,
+    score_header: String,
+    flags_header: String,
+    signature_header: String,
+    max_flags: usize,
+    max_bytes: usize,
+    signing_secret: Option<String>,
+}
+
+impl UpstreamSignaler {
+    pub fn new(config: &UpstreamSignalsProperties) -> Self {
+        let routes = config
+            .routes
+            .iter()
+            .filter_map(|route| match Glob::new(route) {
+                Ok(glob) => Some(glob.compile_matcher()),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to load the upstream signals route of {}, it will be ignored. cause: {}",
+                        route,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        UpstreamSignaler {
+            routes,
+            decision_header: config.decision_header.to_lowercase(),
+            score_header: config.score_header.to_lowercase(),
+            flags_header: config.flags_header.to_lowercase(),
+            signature_header: config.signature_header.to_lowercase(),
+            max_flags: config.max_flags,
+            max_bytes: config.max_bytes,
+            signing_secret: config.signing_secret.to_owned().filter(|s| !s.is_empty()),
+        }
+    }
+
+    pub fn get() -> &'static UpstreamSignaler {
+        &SINGLE_INSTANCE
+    }
+
+    pub fn is_enabled(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| route.is_match(path))
+    }
+
+    fn is_signal_header(&self, name: &str) -> bool {
+        [
+            &self.decision_header,
+            &self.score_header,
+            &self.flags_header,
+            &self.signature_header,
+        ]
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Strip the signal headers arrived from the client, which must be done before the evaluation so that
+    /// neither the evaluation nor the upstream ever sees the spoofed signals, regardless of the routes.
+    pub fn strip(&self, incoming: Arc<HttpIncomingRequest>) -> Arc<HttpIncomingRequest> {
+        if !incoming.headers.keys().any(|name| self.is_signal_header(name)) {
+            return incoming;
+        }
+        tracing::warn!("[Botwaf] [SignalsSpoofed] - {}", incoming.path);
+        let mut incoming = (*incoming).clone();
+        incoming.headers.retain(|name, _| !self.is_signal_header(name));
+        Arc::new(incoming)
+    }
+
+    /// The signal headers of the request, the flags are bounded by the max flags and the max total bytes of
+    /// the injected headers, and signed with the final values if the signing secret is configured.
+    pub fn signal_headers(&self, incoming: &HttpIncomingRequest, signals: &BotwafSignals) -> Vec<(String, String)> {
+        let decision = signals.decision.to_owned();
+        let score = signals.score.to_string();
+        let mut used = self.decision_header.len() + decision.len() + self.score_header.len() + score.len();
+        if self.signing_secret.is_some() {
+            // The hex encoded HMAC-SHA256.
+            used += self.signature_header.len() + 64;
+        }
+        let mut flags: Vec<String> = Vec::new();
+        let mut flags_bytes = self.flags_header.len();
+        for flag in signals.flags.iter().take(self.max_flags) {
+            // The flag must not break the comma-separated list, nor the header value.
+            let flag = flag
+                .chars()
+                .filter(|c| c.is_ascii_graphic() && *c != ',')
+                .collect::<String>();
+            let delta = flag.len() + if flags.is_empty() { 0 } else { 1 };
+            if flag.is_empty() || used + flags_bytes + delta > self.max_bytes {
+                continue;
+            }
+            flags_bytes += delta;
+            flags.push(flag);
+        }
+        let flags = flags.join(",");
+
+        let mut headers = vec![
+            (self.decision_header.to_owned(), decision),
+            (self.score_header.to_owned(), score),
+        ];
+        if let Some(secret) = &self.signing_secret {
+            let signature = UpstreamSignals {
+                method: &incoming.method,
+                path: &incoming.path,
+                decision: &headers[0].1,
+                score: &headers[1].1,
+                flags: &flags,
+            }
+            .sign(secret.as_bytes());
+            headers.push((self.signature_header.to_owned(), signature));
+        }
+        if !flags.is_empty() {
+            headers.push((self.flags_header.to_owned(), flags));
+        }
+        headers
+    }
+
+    /// Inject the signal headers into the incoming request which will be forwarded, if the route is enabled.
+    pub fn apply(&self, incoming: Arc<HttpIncomingRequest>, signals: &BotwafSignals) -> Arc<HttpIncomingRequest> {
+        if !self.is_enabled(&incoming.path) {
+            return incoming;
+        }
+        let headers = self.signal_headers(&incoming, signals);
+        let mut incoming = (*incoming).clone();
+        for (name, value) in headers {
+            incoming.headers.insert(name, Some(value));
+        }
+        Arc::new(incoming)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+
+    fn create_signaler(signing_secret: Option<&str>, max_bytes: usize) -> UpstreamSignaler {
+        UpstreamSignaler::new(&UpstreamSignalsProperties {
+            routes: vec![String::from("/api/login"), String::from("/api/payments/**")],
+            max_bytes,
+            signing_secret: signing_secret.map(|s| s.to_owned()),
+            ..UpstreamSignalsProperties::default()
+        })
+    }
+
+    fn create_test_incoming(path: &str, headers: &[(&str, &str)]) -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("POST", path)
+            .host("shop.example.com")
+            .headers(headers)
+            .build_arc()
+    }
+
+    fn header<'a>(incoming: &'a HttpIncomingRequest, name: &str) -> Option<&'a str> {
+        incoming.headers.get(name).and_then(|v| v.as_deref())
+    }
+
+    fn create_signals() -> BotwafSignals {
+        BotwafSignals {
+            decision: SIGNAL_DECISION_PASS,
+            score: 60,
+            flags: vec![
+                String::from("shadow:credential-stuffing"),
+                String::from("uninspected-method"),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_strip_spoofed_signal_headers() {
+        let signaler = create_signaler(None, 1024);
+        let incoming = create_test_incoming(
+            "/orders",
+            &[
+                ("x-botwaf-score", "0"),
+                ("x-botwaf-decision", "PASS"),
+                ("X-Botwaf-Flags", "trusted"),
+                ("x-botwaf-signature", "deadbeef"),
+                ("content-type", "application/json"),
+            ],
+        );
+        let stripped = signaler.strip(incoming);
+        assert_eq!(stripped.headers.len(), 1);
+        assert_eq!(header(&stripped, "content-type"), Some("application/json"));
+
+        // The spoofed values are replaced by the genuine signals of the enabled route.
+        let incoming = create_test_incoming("/api/login", &[("x-botwaf-score", "0")]);
+        let signaled = signaler.apply(signaler.strip(incoming), &create_signals());
+        assert_eq!(header(&signaled, "x-botwaf-score"), Some("60"));
+        assert_eq!(header(&signaled, "x-botwaf-decision"), Some("PASS"));
+        assert_eq!(
+            header(&signaled, "x-botwaf-flags"),
+            Some("shadow:credential-stuffing,uninspected-method")
+        );
+    }
+
+    #[test]
+    fn test_apply_only_enabled_routes() {
+        let signaler = create_signaler(None, 1024);
+        let incoming = create_test_incoming("/orders", &[]);
+        assert!(signaler.apply(incoming, &create_signals()).headers.is_empty());
+
+        let incoming = create_test_incoming("/api/payments/v1/charge", &[]);
+        let signaled = signaler.apply(incoming, &create_signals());
+        assert_eq!(header(&signaled, "x-botwaf-score"), Some("60"));
+        assert_eq!(header(&signaled, "x-botwaf-signature"), None);
+    }
+
+    #[test]
+    fn test_signal_headers_bounded() {
+        let incoming = create_test_incoming("/api/login", &[]);
+        let mut signals = create_signals();
+        signals.flags.push(String::from("plugin-allow:bad,name\r\n"));
+        signals.flags.extend((0..32).map(|i| format!("flag-{}", i)));
+
+        // The flags are sanitized and bounded by the max flags.
+        let headers = create_signaler(None, 1024).signal_headers(&incoming, &signals);
+        let flags = headers
+            .iter()
+            .find(|(k, _)| k == "x-botwaf-flags")
+            .unwrap()
+            .1
+            .to_owned();
+        assert_eq!(flags.split(',').count(), 16);
+        assert!(flags.contains("plugin-allow:badname,"));
+
+        // The excess flags beyond the max total bytes are dropped, but never the decision and score.
+        let headers = create_signaler(None, 80).signal_headers(&incoming, &signals);
+        let total: usize = headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        assert!(total <= 80, "{:?}", headers);
+        assert_eq!(headers[0], (String::from("x-botwaf-decision"), String::from("PASS")));
+        assert_eq!(headers[1], (String::from("x-botwaf-score"), String::from("60")));
+        assert_eq!(headers[2].1, "shadow:credential-stuffing");
+    }
+
+    #[test]
+    fn test_signature_verified_by_upstream() {
+        let signaler = create_signaler(Some("shared-secret"), 1024);
+        let signaled = signaler.apply(create_test_incoming("/api/login", &[]), &create_signals());
+
+        // The verification recipe of the upstream with the raw received headers.
+        let signature = header(&signaled, "x-botwaf-signature").unwrap();
+        let received = UpstreamSignals {
+            method: "POST",
+            path: "/api/login",
+            decision: header(&signaled, "x-botwaf-decision").unwrap_or_default(),
+            score: header(&signaled, "x-botwaf-score").unwrap_or_default(),
+            flags: header(&signaled, "x-botwaf-flags").unwrap_or_default(),
+        };
+        assert!(received.verify(b"shared-secret", signature));
+
+        // The tampered score (e.g: by the intermediary) is rejected.
+        let tampered = UpstreamSignals { score: "0", ..received };
+        assert!(!tampered.verify(b"shared-secret", signature));
+    }
+}
@@ -18,29 +18,77 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::ipfilter::ipfilter_redis::RedisIPFilter;
+use crate::ipfilter::{ipfilter_cached::CachedIPFilter, ipfilter_redis::RedisIPFilter};
 use anyhow::{Error, Result};
+pub use botwaf_server::modules::forward::ipfilter::IPFilter;
 use botwaf_server::{cache::redis::StringRedisCache, config::config};
-use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
 use lazy_static::lazy_static;
 use std::{
     collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
     sync::{Arc, RwLock},
 };
 
-#[async_trait::async_trait]
-pub trait IPFilter {
-    /// Initialization.
-    async fn init(&self) -> Result<(), Error>;
+/// The blocking target of IP address (as full prefix) or CIDR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IPBlockTarget {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
 
-    /// Checks if an IP address is in the blacklist
-    async fn is_blocked(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error>;
+impl IPBlockTarget {
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (IpAddr::from_str(addr)?, Some(prefix.parse::<u8>()?)),
+            None => (IpAddr::from_str(value.trim())?, None),
+        };
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(Error::msg(format!("Invalid CIDR prefix length: {}", value)));
+        }
+        Ok(Self {
+            addr: Self::network(addr, prefix),
+            prefix,
+        })
+    }
+
+    pub fn is_cidr(&self) -> bool {
+        self.prefix < if self.addr.is_ipv4() { 32 } else { 128 }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                Self::network(*ip, self.prefix) == self.addr
+            }
+            _ => false,
+        }
+    }
 
-    /// Adds an IP address to the blacklist
-    async fn block_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error>;
+    fn network(addr: IpAddr, prefix: u8) -> IpAddr {
+        match addr {
+            IpAddr::V4(v4) => {
+                let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) => {
+                let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix) };
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+        }
+    }
+}
 
-    /// Removes an IP address from the blacklist
-    async fn unblock_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error>;
+impl std::fmt::Display for IPBlockTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_cidr() {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        } else {
+            write!(f, "{}", self.addr)
+        }
+    }
 }
 
 lazy_static! {
@@ -75,10 +123,17 @@ impl IPFilterManager {
     pub async fn init() {
         tracing::info!("Register Botwaf Redis IPFilter ...");
         let redis_cache = Arc::new(StringRedisCache::new(&config::get_config().cache.redis));
-        let handler = RedisIPFilter::new(
+        let redis_handler = RedisIPFilter::new(
             redis_cache,
             config::get_config().services.blocked_header_name.to_owned(),
         );
+        // Caching the decisions in front of the Redis lookup, which is registered as the same name.
+        let cache_config = &config::get_config().services.ipfilter_cache;
+        let handler: Arc<dyn IPFilter + Send + Sync> = if cache_config.enabled {
+            CachedIPFilter::new(cache_config, redis_handler)
+        } else {
+            redis_handler
+        };
         match Self::get()
             .write() // If acquire fails, then it block until acquired.
             .unwrap() // If acquire fails, then it should panic.
@@ -92,11 +147,11 @@ impl IPFilterManager {
         }
     }
 
-    fn register<T: IPFilter + Send + Sync + 'static>(
+    fn register(
         &mut self,
         name: String,
-        handler: Arc<T>,
-    ) -> Result<Arc<T>, Error> {
+        handler: Arc<dyn IPFilter + Send + Sync>,
+    ) -> Result<Arc<dyn IPFilter + Send + Sync>, Error> {
         // Check if the name already exists
         if self.implementations.contains_key(&name) {
             let errmsg = format!("Updater handler Factory: Name '{}' already exists", name);
@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::ipfilter::{IPBlockTarget, IPFilter};
+use anyhow::{Error, Result};
+use botwaf_server::config::config::IPFilterCacheProperties;
+use botwaf_types::modules::forward::{forwarder::HttpIncomingRequest, ipfilter::IPFilterEntry};
+use moka::{future::Cache, policy::EvictionPolicy};
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+/// The IP filter with the in-process LRU cache of the decisions (both the blocked and the not blocked) in
+/// front of the delegate (e.g: Redis) lookup, the block/unblock through this instance invalidates the cached
+/// decisions immediately, and the others (i.e: through the other instances, or the expiry of the blocked
+/// entries) take effect after at most the ttl.
+pub struct CachedIPFilter {
+    delegate: Arc<dyn IPFilter + Send + Sync>,
+    cache: Cache<String, bool>,
+}
+
+impl CachedIPFilter {
+    pub fn new(config: &IPFilterCacheProperties, delegate: Arc<dyn IPFilter + Send + Sync>) -> Arc<CachedIPFilter> {
+        Arc::new(Self {
+            delegate,
+            cache: Cache::builder()
+                .max_capacity(config.max_capacity)
+                .time_to_live(*config.ttl_secs)
+                .eviction_policy(EvictionPolicy::lru())
+                .build(),
+        })
+    }
+
+    /// The normalized cache key of the client IP, e.g: the IPv6 '2001:DB8::0001' is '2001:db8::1'.
+    fn cache_key(ip: &str) -> String {
+        IpAddr::from_str(ip.trim())
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| ip.to_owned())
+    }
+
+    /// Invalidates the cached decision of the exact IP, or all if the CIDR, since which any cached IP may fall into.
+    async fn invalidate(&self, ip: &str) {
+        match IPBlockTarget::parse(ip) {
+            Ok(target) if !target.is_cidr() => self.cache.invalidate(&target.addr.to_string()).await,
+            _ => self.cache.invalidate_all(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IPFilter for CachedIPFilter {
+    async fn init(&self) -> Result<(), Error> {
+        self.delegate.init().await
+    }
+
+    async fn is_blocked(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        let key = match &incoming.client_ip {
+            Some(ip) => Self::cache_key(ip),
+            None => return self.delegate.is_blocked(incoming).await,
+        };
+        if let Some(blocked) = self.cache.get(&key).await {
+            return Ok(blocked);
+        }
+        // The failed lookup is not cached, so that it's retried on the next request.
+        let blocked = self.delegate.is_blocked(incoming).await?;
+        self.cache.insert(key, blocked).await;
+        Ok(blocked)
+    }
+
+    async fn block_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        let result = self.delegate.block_ip(incoming.to_owned()).await;
+        if let Some(ip) = &incoming.client_ip {
+            self.invalidate(ip).await;
+        }
+        result
+    }
+
+    async fn unblock_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        let result = self.delegate.unblock_ip(incoming.to_owned()).await;
+        if let Some(ip) = &incoming.client_ip {
+            self.invalidate(ip).await;
+        }
+        result
+    }
+
+    async fn block(&self, ip: &str, ttl: Option<u64>, operator: Option<String>) -> Result<bool, Error> {
+        let result = self.delegate.block(ip, ttl, operator).await;
+        self.invalidate(ip).await;
+        result
+    }
+
+    async fn unblock(&self, ip: &str) -> Result<bool, Error> {
+        let result = self.delegate.unblock(ip).await;
+        self.invalidate(ip).await;
+        result
+    }
+
+    async fn list(&self) -> Result<Vec<IPFilterEntry>, Error> {
+        self.delegate.list().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::{
+        config::duration::DurationSecs,
+        context::test_support::{InMemoryIPFilter, TestIncomingBuilder},
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The in-memory IP filter which counts the lookups, i.e: the cache misses.
+    #[derive(Default)]
+    struct CountingIPFilter {
+        delegate: InMemoryIPFilter,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl IPFilter for CountingIPFilter {
+        async fn init(&self) -> Result<(), Error> {
+            self.delegate.init().await
+        }
+
+        async fn is_blocked(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.delegate.is_blocked(incoming).await
+        }
+
+        async fn block_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+            self.delegate.block_ip(incoming).await
+        }
+
+        async fn unblock_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+            self.delegate.unblock_ip(incoming).await
+        }
+
+        async fn block(&self, ip: &str, ttl: Option<u64>, operator: Option<String>) -> Result<bool, Error> {
+            self.delegate.block(ip, ttl, operator).await
+        }
+
+        async fn unblock(&self, ip: &str) -> Result<bool, Error> {
+            self.delegate.unblock(ip).await
+        }
+
+        async fn list(&self) -> Result<Vec<IPFilterEntry>, Error> {
+            self.delegate.list().await
+        }
+    }
+
+    fn create_cached_ipfilter(ttl_secs: u64) -> (Arc<CountingIPFilter>, Arc<CachedIPFilter>) {
+        let delegate = Arc::new(CountingIPFilter::default());
+        let config = IPFilterCacheProperties {
+            ttl_secs: DurationSecs::from_secs(ttl_secs),
+            ..IPFilterCacheProperties::default()
+        };
+        (delegate.to_owned(), CachedIPFilter::new(&config, delegate))
+    }
+
+    fn mock_incoming(client_ip: &str) -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("GET", "/").client_ip(client_ip).build_arc()
+    }
+
+    #[tokio::test]
+    async fn test_repeated_lookups_hit_cache() {
+        let (delegate, ipfilter) = create_cached_ipfilter(60);
+        delegate.block("203.0.113.9", None, None).await.unwrap();
+
+        for _ in 0..3 {
+            assert!(ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+            assert!(!ipfilter.is_blocked(mock_incoming("198.51.100.7")).await.unwrap());
+        }
+        // Only the first lookups of the blocked and the not blocked IPs reached the delegate.
+        assert_eq!(delegate.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_block_and_unblock_invalidate_cache() {
+        let (delegate, ipfilter) = create_cached_ipfilter(60);
+        assert!(!ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+
+        ipfilter
+            .block("203.0.113.9", None, Some("admin".to_owned()))
+            .await
+            .unwrap();
+        assert!(ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+
+        ipfilter.unblock("203.0.113.9").await.unwrap();
+        assert!(!ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+        assert_eq!(delegate.lookups.load(Ordering::SeqCst), 3);
+
+        // The CIDR invalidates all the cached decisions.
+        assert!(!ipfilter.is_blocked(mock_incoming("198.51.100.7")).await.unwrap());
+        ipfilter.unblock("198.51.100.0/24").await.unwrap();
+        assert!(!ipfilter.is_blocked(mock_incoming("198.51.100.7")).await.unwrap());
+        assert_eq!(delegate.lookups.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_unblock_through_other_instance_bounded_by_ttl() {
+        let (delegate, ipfilter) = create_cached_ipfilter(1);
+        delegate.block("203.0.113.9", None, None).await.unwrap();
+        assert!(ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+
+        // e.g: unblocked through the other instance, which is stale until the ttl.
+        delegate.unblock("203.0.113.9").await.unwrap();
+        assert!(ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+
+        tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+        assert!(!ipfilter.is_blocked(mock_incoming("203.0.113.9")).await.unwrap());
+    }
+
+    #[test]
+    fn test_cache_key_normalized() {
+        assert_eq!(CachedIPFilter::cache_key("2001:DB8::0001"), "2001:db8::1");
+        assert_eq!(CachedIPFilter::cache_key(" 10.0.0.1 "), "10.0.0.1");
+        assert_eq!(CachedIPFilter::cache_key("unknown"), "unknown");
+    }
+}
@@ -18,10 +18,11 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use super::ipfilter::IPFilter;
+use super::ipfilter::{IPBlockTarget, IPFilter};
 use anyhow::{Error, Ok, Result};
 use botwaf_server::cache::{redis::StringRedisCache, ICache};
-use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use botwaf_types::modules::forward::{forwarder::HttpIncomingRequest, ipfilter::IPFilterEntry};
+use common_audit_log::audit_log;
 use std::{net::IpAddr, str::FromStr, sync::Arc};
 
 pub struct RedisIPFilter {
@@ -44,11 +45,20 @@ impl RedisIPFilter {
             .ok_or_else(|| anyhow::anyhow!("Client IP not found"))
     }
 
+    /// The hash key of the exact blocked IP entries.
+    fn ips_key(&self) -> String {
+        format!("{}:ips", self.redis_key)
+    }
+
+    /// The hash key of the blocked CIDR entries, which can't be stored in the bitmap.
+    fn cidrs_key(&self) -> String {
+        format!("{}:cidrs", self.redis_key)
+    }
+
     /// Converts an IP address to a bitmap offset.
-    fn get_ip_bitmap_offset(&self, incoming: Arc<HttpIncomingRequest>) -> Result<u64, Error> {
-        let ip = IpAddr::from_str(self.get_client_ip(incoming)?.as_ref())?;
+    fn get_ip_bitmap_offset(ip: &IpAddr) -> u64 {
         match ip {
-            IpAddr::V4(ipv4) => Ok(u32::from(ipv4) as u64),
+            IpAddr::V4(ipv4) => u32::from(*ipv4) as u64,
             IpAddr::V6(ipv6) => {
                 // For IPv6, we use a simplified mapping approach
                 let octets = ipv6.octets();
@@ -56,11 +66,18 @@ impl RedisIPFilter {
                 for i in 0..8 {
                     result = (result << 8) | ((((octets[i * 2] as u16) << 8) | (octets[i * 2 + 1] as u16)) as u64);
                 }
-                let offset = result % (u32::MAX as u64); // Limit to 32-bit range
-                Ok(offset)
+                result % (u32::MAX as u64) // Limit to 32-bit range
             }
         }
     }
+
+    async fn list_entries(&self, key: String) -> Result<Vec<IPFilterEntry>, Error> {
+        let entries = self.redis_cache.hget_all(key).await?.unwrap_or_default();
+        Ok(entries
+            .values()
+            .filter_map(|json| serde_json::from_str::<IPFilterEntry>(json).ok())
+            .collect())
+    }
 }
 
 #[async_trait::async_trait]
@@ -70,23 +87,157 @@ impl IPFilter for RedisIPFilter {
     }
 
     async fn is_blocked(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
-        let offset = self.get_ip_bitmap_offset(incoming)?;
-        self.redis_cache.get_bit(self.redis_key.clone(), offset).await
+        let ip = IpAddr::from_str(self.get_client_ip(incoming)?.as_ref())?;
+
+        // Fast path of exact IP bitmap.
+        let offset = Self::get_ip_bitmap_offset(&ip);
+        if self.redis_cache.get_bit(self.redis_key.clone(), offset).await? {
+            match self.redis_cache.hget(self.ips_key(), Some(ip.to_string())).await? {
+                Some(json) => match serde_json::from_str::<IPFilterEntry>(&json) {
+                    std::result::Result::Ok(entry) if entry.is_expired() => {
+                        // Lazy unblocking the expired entry.
+                        self.unblock(&entry.ip).await?;
+                    }
+                    _ => return Ok(true),
+                },
+                // The bit was set without entry, e.g: by block_ip()
+                None => return Ok(true),
+            }
+        }
+
+        // Slow path of CIDR entries.
+        for entry in self.list_entries(self.cidrs_key()).await? {
+            if let std::result::Result::Ok(target) = IPBlockTarget::parse(&entry.ip) {
+                if target.contains(&ip) {
+                    if entry.is_expired() {
+                        self.unblock(&entry.ip).await?;
+                    } else {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
     }
 
     async fn block_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
-        let offset = self.get_ip_bitmap_offset(incoming)?;
-        self.redis_cache.set_bit(self.redis_key.clone(), offset, true).await
+        let ip = self.get_client_ip(incoming)?;
+        self.block(&ip, None, None).await
     }
 
     async fn unblock_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
-        let offset = self.get_ip_bitmap_offset(incoming)?;
-        self.redis_cache.set_bit(self.redis_key.clone(), offset, false).await
+        let ip = self.get_client_ip(incoming)?;
+        self.unblock(&ip).await
+    }
+
+    #[audit_log("[IPFILTER][BLOCK] ip: {ip}, ttl: {ttl.unwrap_or_default()}")]
+    async fn block(&self, ip: &str, ttl: Option<u64>, operator: Option<String>) -> Result<bool, Error> {
+        let target = IPBlockTarget::parse(ip)?;
+        let entry = IPFilterEntry::new(target.to_string(), ttl, operator);
+        let json = serde_json::to_string(&entry)?;
+        if target.is_cidr() {
+            self.redis_cache
+                .hset(self.cidrs_key(), Some(vec![(entry.ip.to_owned(), json)]))
+                .await
+        } else {
+            self.redis_cache
+                .hset(self.ips_key(), Some(vec![(entry.ip.to_owned(), json)]))
+                .await?;
+            let offset = Self::get_ip_bitmap_offset(&target.addr);
+            self.redis_cache.set_bit(self.redis_key.clone(), offset, true).await?;
+            Ok(true)
+        }
+    }
+
+    #[audit_log("[IPFILTER][UNBLOCK] ip: {ip}")]
+    async fn unblock(&self, ip: &str) -> Result<bool, Error> {
+        let target = IPBlockTarget::parse(ip)?;
+        if target.is_cidr() {
+            self.redis_cache.hdel(self.cidrs_key(), target.to_string()).await
+        } else {
+            self.redis_cache.hdel(self.ips_key(), target.to_string()).await?;
+            let offset = Self::get_ip_bitmap_offset(&target.addr);
+            self.redis_cache.set_bit(self.redis_key.clone(), offset, false).await?;
+            Ok(true)
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<IPFilterEntry>, Error> {
+        let mut entries = self.list_entries(self.ips_key()).await?;
+        entries.extend(self.list_entries(self.cidrs_key()).await?);
+        entries.retain(|entry| !entry.is_expired());
+        entries.sort_by(|a, b| b.create_at.cmp(&a.create_at));
+        Ok(entries)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use botwaf_server::config::config::RedisProperties;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use std::env;
+
+    fn create_test_ipfilter() -> Option<Arc<RedisIPFilter>> {
+        let nodes = env::var("IT_REDIS_NODES")
+            .ok()?
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .collect();
+        let config = RedisProperties {
+            nodes,
+            username: None,
+            password: Some(env::var("IT_REDIS_PASSWORD").unwrap_or(String::from("bitnami"))),
+            connection_timeout: None,
+            response_timeout: None,
+            retries: None,
+            max_retry_wait: None,
+            min_retry_wait: None,
+            read_from_replicas: Some(true),
+        };
+        let key = format!("botwaf:test:ipfilter:{}", chrono::Utc::now().timestamp_millis());
+        Some(RedisIPFilter::new(Arc::new(StringRedisCache::new(&config)), key))
+    }
+
+    fn mock_incoming(client_ip: &str) -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("GET", "/").client_ip(client_ip).build_arc()
+    }
+
+    #[test]
+    fn test_parse_block_target() {
+        let target = IPBlockTarget::parse("10.0.1.7/24").unwrap();
+        assert_eq!(target.to_string(), "10.0.1.0/24");
+        assert!(target.contains(&IpAddr::from_str("10.0.1.200").unwrap()));
+        assert!(!target.contains(&IpAddr::from_str("10.0.2.1").unwrap()));
+        assert!(!IPBlockTarget::parse("1.1.1.1").unwrap().is_cidr());
+        assert!(IPBlockTarget::parse("2001:db8::/64").unwrap().is_cidr());
+        assert!(IPBlockTarget::parse("1.1.1.1/33").is_err());
+        assert!(IPBlockTarget::parse("not-an-ip").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_block_list_unblock_lifecycle() {
+        let Some(ipfilter) = create_test_ipfilter() else {
+            return;
+        };
+
+        // block -> listed -> is_blocked
+        assert!(ipfilter.block("10.9.8.7", None, Some("admin".to_owned())).await.unwrap());
+        assert!(ipfilter.block("172.16.0.0/16", Some(60), None).await.unwrap());
+        let listed: Vec<String> = ipfilter.list().await.unwrap().into_iter().map(|e| e.ip).collect();
+        assert!(listed.contains(&"10.9.8.7".to_owned()));
+        assert!(listed.contains(&"172.16.0.0/16".to_owned()));
+        assert!(ipfilter.is_blocked(mock_incoming("10.9.8.7")).await.unwrap());
+        assert!(ipfilter.is_blocked(mock_incoming("172.16.3.4")).await.unwrap());
+
+        // unblock -> not blocked
+        assert!(ipfilter.unblock("10.9.8.7").await.unwrap());
+        assert!(ipfilter.unblock("172.16.0.0/16").await.unwrap());
+        assert!(!ipfilter.is_blocked(mock_incoming("10.9.8.7")).await.unwrap());
+        assert!(!ipfilter.is_blocked(mock_incoming("172.16.3.4")).await.unwrap());
+        assert!(ipfilter.list().await.unwrap().is_empty());
+    }
+
     // use super::*;
     // use axum::{ body::Body, http::{ Request, StatusCode } };
     // use std::net::{ IpAddr, Ipv4Addr };
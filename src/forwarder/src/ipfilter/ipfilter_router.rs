@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::ipfilter::{IPBlockTarget, IPFilter};
+use axum::{
+    extract::State,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use botwaf_server::{
+    context::state::BotwafState,
+    util::{
+        auths::SecurityContext,
+        web::{RequireAdmin, ValidatedJson},
+    },
+};
+use botwaf_types::{
+    modules::forward::ipfilter::{IPFilterBlockRequest, IPFilterEntry, IPFilterUnblockRequest},
+    RespBase,
+};
+use hyper::StatusCode;
+use std::sync::Arc;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(handle_ipfilter_list, handle_ipfilter_block, handle_ipfilter_unblock),
+    components(schemas(IPFilterEntry, IPFilterBlockRequest, IPFilterUnblockRequest))
+)]
+pub struct IPFilterApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/ipfilter", get(handle_ipfilter_list))
+        .route("/api/v1/ipfilter/block", post(handle_ipfilter_block))
+        .route("/api/v1/ipfilter/unblock", post(handle_ipfilter_unblock))
+}
+
+fn get_ipfilter(state: &BotwafState) -> Result<Arc<dyn IPFilter + Send + Sync>, axum::response::Response> {
+    state.ipfilter.to_owned().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(RespBase::errmsg("The IP filter is not wired into the Botwaf state.")),
+        )
+            .into_response()
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/ipfilter",
+    responses((status = 200, description = "Getting the current blocked IPs and CIDRs.", body = [IPFilterEntry])),
+    tag = "IPFilter"
+)]
+async fn handle_ipfilter_list(State(state): State<BotwafState>, _: RequireAdmin) -> impl IntoResponse {
+    let ipfilter = match get_ipfilter(&state) {
+        Ok(ipfilter) => ipfilter,
+        Err(resp) => return resp,
+    };
+    match ipfilter.list().await {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/ipfilter/block",
+    request_body = IPFilterBlockRequest,
+    responses((status = 200, description = "Manually block the IP or CIDR.", body = RespBase)),
+    tag = "IPFilter"
+)]
+async fn handle_ipfilter_block(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    ValidatedJson(param): ValidatedJson<IPFilterBlockRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = IPBlockTarget::parse(&param.ip) {
+        return (StatusCode::BAD_REQUEST, Json(RespBase::error(e))).into_response();
+    }
+    let ipfilter = match get_ipfilter(&state) {
+        Ok(ipfilter) => ipfilter,
+        Err(resp) => return resp,
+    };
+    let operator = SecurityContext::get_instance().get_current_uname_for_store().await;
+    match ipfilter.block(&param.ip, param.ttl, operator).await {
+        Ok(_) => (StatusCode::OK, Json(RespBase::success())).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/ipfilter/unblock",
+    request_body = IPFilterUnblockRequest,
+    responses((status = 200, description = "Manually unblock the IP or CIDR.", body = RespBase)),
+    tag = "IPFilter"
+)]
+async fn handle_ipfilter_unblock(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    ValidatedJson(param): ValidatedJson<IPFilterUnblockRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = IPBlockTarget::parse(&param.ip) {
+        return (StatusCode::BAD_REQUEST, Json(RespBase::error(e))).into_response();
+    }
+    let ipfilter = match get_ipfilter(&state) {
+        Ok(ipfilter) => ipfilter,
+        Err(resp) => return resp,
+    };
+    match ipfilter.unblock(&param.ip).await {
+        Ok(_) => (StatusCode::OK, Json(RespBase::success())).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response(),
+    }
+}
@@ -19,4 +19,6 @@
 // This includes modifications and derived works.
 
 pub mod ipfilter;
+pub mod ipfilter_cached;
 pub mod ipfilter_redis;
+pub mod ipfilter_router;
@@ -18,6 +18,21 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod access_recorder;
+pub mod access_writer;
+pub mod decision_harness;
 pub mod forwarder_base;
+pub mod forwarder_dns;
 pub mod forwarder_http;
+pub mod forwarder_mirror;
+pub mod forwarder_tls;
+pub mod headers;
 pub mod ipfilter;
+pub mod llm_classifier;
+pub mod modsec_limiter;
+pub mod plugin_wasm;
+pub mod probe_synthetic;
+pub mod request_signing;
+pub mod response_rewrite;
+pub mod stats;
+pub mod uri_limit;
@@ -0,0 +1,386 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::forwarder_base::BotwafDecision;
+use anyhow::{Error, Result};
+use botwaf_server::{
+    config::config::{self, DataProtectionProperties, LlmClassificationMode, LlmClassificationProperties, ScrubMode},
+    mgmt::{
+        apm::metrics::BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL,
+        fail_open::{FailOpenBudget, FAIL_OPEN_LLM_CLASSIFIER},
+    },
+    modules::{llm::handler::llm_base::ILLMHandler, privacy::data_protector::DataProtector},
+};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+lazy_static! {
+    // The classifier is created per request, so that the protector and the permits are shared.
+    static ref PROMPT_PROTECTOR: DataProtector =
+        LlmClassifier::create_protector(&config::get_config().services.data_protection);
+    static ref ASYNC_PERMITS: Arc<Semaphore> = Arc::new(Semaphore::new(
+        config::get_config().services.llm_classification.max_async_concurrent
+    ));
+}
+
+/// The verdict of the LLM classified for an incoming request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmVerdict {
+    MALICIOUS,
+    BENIGN,
+}
+
+/// The LLM classifier in the WAF path, which must never hang the request path when the LLM is unavailable.
+pub struct LlmClassifier {
+    config: LlmClassificationProperties,
+    llm_handler: Arc<dyn ILLMHandler + Send + Sync>,
+    // The request sent to the third-party LLM provider is always masked, see: LlmClassifier::create_protector
+    protector: DataProtector,
+    async_permits: Arc<Semaphore>,
+}
+
+impl LlmClassifier {
+    pub const RULE_ID: &'static str = "LLM";
+    pub const RULE_ID_UNAVAILABLE: &'static str = "LLM-UNAVAILABLE";
+
+    pub fn new(config: &LlmClassificationProperties, llm_handler: Arc<dyn ILLMHandler + Send + Sync>) -> Arc<Self> {
+        Arc::new(Self {
+            config: config.to_owned(),
+            llm_handler,
+            protector: PROMPT_PROTECTOR.to_owned(),
+            async_permits: ASYNC_PERMITS.to_owned(),
+        })
+    }
+
+    // The protector of the prompt, i.e: the configured data protection, but the sensitive values (including the
+    // default patterns, e.g: Authorization, Cookie, API keys) are always masked even if the scrub mode is NONE.
+    fn create_protector(protection: &DataProtectionProperties) -> DataProtector {
+        let mut protection = protection.to_owned();
+        for mode in [&mut protection.query, &mut protection.headers, &mut protection.body] {
+            if *mode == ScrubMode::NONE {
+                *mode = ScrubMode::MASK;
+            }
+        }
+        for pattern in DataProtectionProperties::default().sensitive_patterns {
+            if !protection.sensitive_patterns.contains(&pattern) {
+                protection.sensitive_patterns.push(pattern);
+            }
+        }
+        DataProtector::new(&protection)
+    }
+
+    pub fn build_prompt(&self, incoming: &HttpIncomingRequest) -> String {
+        let headers = self
+            .protector
+            .scrub_headers(&incoming.headers)
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, v.as_deref().unwrap_or_default()))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let body = incoming
+            .body
+            .as_ref()
+            .map(|b| String::from_utf8_lossy(&b[..b.len().min(self.config.max_prompt_body_bytes)]).to_string())
+            .and_then(|b| self.protector.scrub_body(&b))
+            .unwrap_or_default();
+        let query = incoming.query.as_ref().and_then(|q| self.protector.scrub_query(q));
+        format!(
+            "You are a web application firewall. Classify the following HTTP request as MALICIOUS or BENIGN, \
+             answer with only one word.\n\n{} {}{}\n{}\n\n{}",
+            incoming.method,
+            incoming.path,
+            query.map(|q| format!("?{}", q)).unwrap_or_default(),
+            headers,
+            body
+        )
+    }
+
+    /// Parse the verdict of the exact single word answer, the others (e.g: 'NOT MALICIOUS') are unrecognized.
+    pub fn parse_verdict(result: &str) -> Result<LlmVerdict, Error> {
+        let word = result
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+            .to_uppercase();
+        let is_single_word = result.split_whitespace().count() == 1;
+        match word.as_str() {
+            "MALICIOUS" if is_single_word => Ok(LlmVerdict::MALICIOUS),
+            "BENIGN" if is_single_word => Ok(LlmVerdict::BENIGN),
+            _ => Err(Error::msg(format!("Unrecognized LLM classification result: {}", result.trim()))),
+        }
+    }
+
+    async fn classify(&self, incoming: &HttpIncomingRequest) -> Result<LlmVerdict, Error> {
+        let result = self.llm_handler.generate(self.build_prompt(incoming)).await?;
+        Self::parse_verdict(&result.content)
+    }
+
+    /// Decide the incoming request by the classification mode, the INLINE mode is bounded by the
+    /// strict timeout and falls back to the fail-open/fail-closed behavior on timeout/error.
+    pub async fn decide(self: &Arc<Self>, incoming: Arc<HttpIncomingRequest>) -> BotwafDecision {
+        match self.config.mode {
+            LlmClassificationMode::OFF => BotwafDecision::PASS,
+            LlmClassificationMode::ASYNC => {
+                // The excess requests are dropped to classify, so that the spike never creates unbounded tasks.
+                let permit = match self.async_permits.to_owned().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL.inc();
+                        tracing::debug!("[Botwaf] [LlmClassifyDropped] - {}", incoming.path);
+                        return BotwafDecision::PASS;
+                    }
+                };
+                let this = self.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    match this.classify(&incoming).await {
+                        Ok(verdict) => {
+                            tracing::info!("[Botwaf] [LlmClassified] - {} - {:?}", incoming.path, verdict)
+                        }
+                        Err(e) => tracing::warn!("[Botwaf] [LlmClassifyErr] - {} - {}", incoming.path, e),
+                    }
+                });
+                BotwafDecision::PASS
+            }
+            LlmClassificationMode::INLINE => {
+                let timeout = *self.config.timeout_ms;
+                let cause = match tokio::time::timeout(timeout, self.classify(&incoming)).await {
+                    Ok(Ok(LlmVerdict::BENIGN)) => return BotwafDecision::PASS,
+                    Ok(Ok(LlmVerdict::MALICIOUS)) => {
+                        return BotwafDecision::BLOCK {
+                            status: StatusCode::FORBIDDEN,
+                            rule_id: Self::RULE_ID.to_owned(),
+                            log: String::from("Classified as malicious by LLM"),
+                        }
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(_) => format!("Timeout after {}", self.config.timeout_ms),
+                };
+                // Fail-closed if the fail-open budget is exceeded, e.g: the LLM provider is down for a while.
+                let fail_open = self.config.fail_open && !FailOpenBudget::get().report(FAIL_OPEN_LLM_CLASSIFIER);
+                tracing::warn!(
+                    "[Botwaf] [LlmClassifyErr] - {} - {}, fail-open: {}",
+                    incoming.path,
+                    cause,
+                    fail_open
+                );
+                if fail_open {
+                    BotwafDecision::PASS
+                } else {
+                    BotwafDecision::BLOCK {
+                        status: StatusCode::FORBIDDEN,
+                        rule_id: Self::RULE_ID_UNAVAILABLE.to_owned(),
+                        log: format!("LLM classification unavailable: {}", cause),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationMillis;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use botwaf_server::modules::llm::handler::llm_base::LlmGeneration;
+    use botwaf_types::modules::llm::knowledge::KnowledgeUploadInfo;
+    use std::fs::File;
+    use std::time::Duration;
+
+    struct MockLLMHandler {
+        delay: Duration,
+        result: Option<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl ILLMHandler for MockLLMHandler {
+        async fn init(&self) {}
+
+        async fn embedding(&self, _info: KnowledgeUploadInfo, _file: File) -> Result<KnowledgeUploadInfo, Error> {
+            Err(Error::msg("Unsupported"))
+        }
+
+        async fn generate(&self, _prompt: String) -> Result<LlmGeneration, Error> {
+            tokio::time::sleep(self.delay).await;
+            let content = self.result.to_owned().ok_or_else(|| Error::msg("LLM is unavailable"))?;
+            Ok(LlmGeneration {
+                content,
+                provider: "mock".to_owned(),
+            })
+        }
+    }
+
+    fn create_classifier(fail_open: bool, delay_ms: u64, result: Option<&str>) -> Arc<LlmClassifier> {
+        let config = LlmClassificationProperties {
+            mode: LlmClassificationMode::INLINE,
+            timeout_ms: DurationMillis::from_millis(50),
+            fail_open,
+            ..LlmClassificationProperties::default()
+        };
+        let handler = Arc::new(MockLLMHandler {
+            delay: Duration::from_millis(delay_ms),
+            result: result.map(|r| r.to_owned()),
+        });
+        LlmClassifier::new(&config, handler)
+    }
+
+    fn mock_incoming() -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("GET", "/search").query("q=hello").build_arc()
+    }
+
+    #[test]
+    fn test_parse_verdict() {
+        assert_eq!(LlmClassifier::parse_verdict(" Malicious.").unwrap(), LlmVerdict::MALICIOUS);
+        assert_eq!(LlmClassifier::parse_verdict("BENIGN").unwrap(), LlmVerdict::BENIGN);
+        assert_eq!(LlmClassifier::parse_verdict("benign\n").unwrap(), LlmVerdict::BENIGN);
+        for result in [
+            "I don't know",
+            "NOT MALICIOUS",
+            "not malicious, BENIGN",
+            "MALICIOUS or BENIGN",
+            "MALICIOUS-ish",
+            "",
+        ] {
+            assert!(LlmClassifier::parse_verdict(result).is_err(), "{}", result);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inline_unrecognized_verdict_fail_open_and_closed() {
+        assert_eq!(
+            create_classifier(true, 0, Some("NOT MALICIOUS")).decide(mock_incoming()).await,
+            BotwafDecision::PASS
+        );
+        match create_classifier(false, 0, Some("NOT MALICIOUS")).decide(mock_incoming()).await {
+            BotwafDecision::BLOCK { rule_id, .. } => assert_eq!(rule_id, LlmClassifier::RULE_ID_UNAVAILABLE),
+            BotwafDecision::PASS => panic!("Expected blocked by fail-closed"),
+        }
+    }
+
+    #[test]
+    fn test_build_prompt_masks_credentials_and_truncates_body() {
+        // The scrub modes of NONE are still masked for the LLM provider.
+        let protection = DataProtectionProperties {
+            query: ScrubMode::NONE,
+            headers: ScrubMode::NONE,
+            body: ScrubMode::NONE,
+            sensitive_patterns: Vec::new(),
+            ..DataProtectionProperties::default()
+        };
+        let classifier = LlmClassifier {
+            config: LlmClassificationProperties {
+                max_prompt_body_bytes: 64,
+                ..LlmClassificationProperties::default()
+            },
+            llm_handler: Arc::new(MockLLMHandler {
+                delay: Duration::ZERO,
+                result: None,
+            }),
+            protector: LlmClassifier::create_protector(&protection),
+            async_permits: Arc::new(Semaphore::new(1)),
+        };
+        let incoming = TestIncomingBuilder::new("POST", "/login")
+            .header("Authorization", "Bearer eyJ-secret-jwt")
+            .header("Cookie", "session=secret-session")
+            .header("X-Api-Key", "secret-api-key")
+            .header("User-Agent", "curl/8.0")
+            .query("q=hello&access_token=secret-query-token")
+            .body(&format!("password=secret-password&note={}", "x".repeat(1024)))
+            .build();
+
+        let prompt = classifier.build_prompt(&incoming);
+        assert!(!prompt.contains("secret"), "{}", prompt);
+        assert!(prompt.contains("curl/8.0"));
+        assert!(prompt.contains("q=hello"));
+        assert!(!prompt.contains(&"x".repeat(64)));
+    }
+
+    #[tokio::test]
+    async fn test_async_dropped_when_saturated() {
+        let classifier = Arc::new(LlmClassifier {
+            config: LlmClassificationProperties {
+                mode: LlmClassificationMode::ASYNC,
+                ..LlmClassificationProperties::default()
+            },
+            llm_handler: Arc::new(MockLLMHandler {
+                delay: Duration::from_millis(500),
+                result: Some(String::from("BENIGN")),
+            }),
+            protector: LlmClassifier::create_protector(&DataProtectionProperties::default()),
+            async_permits: Arc::new(Semaphore::new(1)),
+        });
+        let dropped = BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL.get();
+
+        assert_eq!(classifier.decide(mock_incoming()).await, BotwafDecision::PASS);
+        assert_eq!(classifier.async_permits.available_permits(), 0);
+        // The excess request is never spawned to classify, but still passed.
+        assert_eq!(classifier.decide(mock_incoming()).await, BotwafDecision::PASS);
+        assert!(BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL.get() > dropped);
+    }
+
+    #[tokio::test]
+    async fn test_inline_timeout_fail_open() {
+        let classifier = create_classifier(true, 1000, Some("MALICIOUS"));
+        let started = std::time::Instant::now();
+        assert_eq!(classifier.decide(mock_incoming()).await, BotwafDecision::PASS);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_inline_timeout_fail_closed() {
+        let classifier = create_classifier(false, 1000, Some("BENIGN"));
+        let started = std::time::Instant::now();
+        let decision = classifier.decide(mock_incoming()).await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+        match decision {
+            BotwafDecision::BLOCK { status, rule_id, .. } => {
+                assert_eq!(status, StatusCode::FORBIDDEN);
+                assert_eq!(rule_id, LlmClassifier::RULE_ID_UNAVAILABLE);
+            }
+            BotwafDecision::PASS => panic!("Expected blocked by fail-closed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inline_error_fail_open_and_closed() {
+        assert_eq!(create_classifier(true, 0, None).decide(mock_incoming()).await, BotwafDecision::PASS);
+        assert!(create_classifier(false, 0, None)
+            .decide(mock_incoming())
+            .await
+            .is_blocked());
+    }
+
+    #[tokio::test]
+    async fn test_inline_classified() {
+        assert!(create_classifier(true, 0, Some("MALICIOUS"))
+            .decide(mock_incoming())
+            .await
+            .is_blocked());
+        assert_eq!(
+            create_classifier(false, 0, Some("BENIGN")).decide(mock_incoming()).await,
+            BotwafDecision::PASS
+        );
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::{
+    config::config::{self, ModSecProperties, ModSecSaturationPolicy},
+    mgmt::apm::metrics::{BOTWAF_MODSEC_CONCURRENT_TRANSACTIONS, BOTWAF_MODSEC_QUEUE_DEPTH},
+};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ModSecLimiter = ModSecLimiter::new(&config::get_config().services.modsec);
+}
+
+#[derive(Debug, Error)]
+#[error("The concurrent ModSecurity transactions are saturated with {0}")]
+pub struct ModSecSaturatedError(pub usize);
+
+/// The held ModSecurity transaction slot, which is released on drop.
+pub struct ModSecPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ModSecPermit {
+    fn drop(&mut self) {
+        BOTWAF_MODSEC_CONCURRENT_TRANSACTIONS.dec();
+    }
+}
+
+/// The limiter of the simultaneous ModSecurity transactions, which bounds the memory under load.
+pub struct ModSecLimiter {
+    config: ModSecProperties,
+    permits: Arc<Semaphore>,
+}
+
+impl ModSecLimiter {
+    pub fn new(config: &ModSecProperties) -> Self {
+        ModSecLimiter {
+            config: config.to_owned(),
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+        }
+    }
+
+    pub fn get() -> &'static ModSecLimiter {
+        &SINGLE_INSTANCE
+    }
+
+    /// Acquire a transaction slot, the saturated request is rejected immediately or queued until
+    /// the timeout according to the saturation policy.
+    pub async fn acquire(&self) -> Result<ModSecPermit, ModSecSaturatedError> {
+        if self.config.max_concurrent == 0 {
+            return Ok(Self::permit(None));
+        }
+        if let Ok(permit) = self.permits.to_owned().try_acquire_owned() {
+            return Ok(Self::permit(Some(permit)));
+        }
+        if self.config.saturation_policy == ModSecSaturationPolicy::REJECT {
+            return Err(ModSecSaturatedError(self.config.max_concurrent));
+        }
+
+        BOTWAF_MODSEC_QUEUE_DEPTH.inc();
+        let timeout = *self.config.queue_timeout_ms;
+        let acquired = tokio::time::timeout(timeout, self.permits.to_owned().acquire_owned()).await;
+        BOTWAF_MODSEC_QUEUE_DEPTH.dec();
+        match acquired {
+            Ok(Ok(permit)) => Ok(Self::permit(Some(permit))),
+            _ => Err(ModSecSaturatedError(self.config.max_concurrent)),
+        }
+    }
+
+    fn permit(permit: Option<OwnedSemaphorePermit>) -> ModSecPermit {
+        BOTWAF_MODSEC_CONCURRENT_TRANSACTIONS.inc();
+        ModSecPermit { _permit: permit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationMillis;
+    use std::time::Duration;
+
+    fn create_limiter(saturation_policy: ModSecSaturationPolicy) -> ModSecLimiter {
+        ModSecLimiter::new(&ModSecProperties {
+            max_concurrent: 2,
+            saturation_policy,
+            queue_timeout_ms: DurationMillis::from_millis(200),
+            ..ModSecProperties::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_saturated_rejected() {
+        let limiter = create_limiter(ModSecSaturationPolicy::REJECT);
+        let first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+
+        // The (N+1)th concurrent request is rejected immediately.
+        assert!(limiter.acquire().await.is_err());
+
+        // The released slot is available again.
+        drop(first);
+        assert!(limiter.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_saturated_queued() {
+        let limiter = Arc::new(create_limiter(ModSecSaturationPolicy::QUEUE));
+        let first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+
+        // The (N+1)th concurrent request is queued until a slot released.
+        let queued = {
+            let limiter = limiter.to_owned();
+            tokio::spawn(async move { limiter.acquire().await.is_ok() })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!queued.is_finished());
+        drop(first);
+        assert!(queued.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_saturated_queue_timeout() {
+        let limiter = create_limiter(ModSecSaturationPolicy::QUEUE);
+        let _first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+
+        let start = std::time::Instant::now();
+        assert!(limiter.acquire().await.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let limiter = ModSecLimiter::new(&ModSecProperties {
+            max_concurrent: 0,
+            saturation_policy: ModSecSaturationPolicy::REJECT,
+            queue_timeout_ms: DurationMillis::from_millis(0),
+            ..ModSecProperties::default()
+        });
+        let permits = (0..100).map(|_| limiter.acquire()).collect::<Vec<_>>();
+        for permit in permits {
+            assert!(permit.await.is_ok());
+        }
+    }
+}
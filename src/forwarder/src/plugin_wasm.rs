@@ -0,0 +1,693 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::{
+    config::config::{WasmPluginProperties, WasmPluginsProperties},
+    mgmt::apm::metrics::BOTWAF_PLUGIN_FAILURES_TOTAL,
+};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use common_telemetry::{info, warn};
+use hyper::header::{HeaderName, HeaderValue};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use wasmtime::{Caller, Config, Engine, Linker, Memory, Module, Store, Trap};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: WasmPluginHost = WasmPluginHost::new();
+}
+
+/// The verdict action of the plugin, which is returned by the plugin entrypoint with the code.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PluginAction {
+    // Continue to the next plugins and then the ModSecurity.
+    CONTINUE,
+    // Allow the request, which skips the remaining plugins and the ModSecurity.
+    ALLOW,
+    // Block the request immediately.
+    BLOCK,
+}
+
+impl PluginAction {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(PluginAction::CONTINUE),
+            1 => Some(PluginAction::ALLOW),
+            2 => Some(PluginAction::BLOCK),
+            _ => None,
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PluginFailureKind {
+    LOAD,
+    TRAP,
+    FUEL_EXHAUSTED,
+    TIMEOUT,
+    MALFORMED,
+}
+
+impl PluginFailureKind {
+    /// The metrics label of the failure kind.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PluginFailureKind::LOAD => "load",
+            PluginFailureKind::TRAP => "trap",
+            PluginFailureKind::FUEL_EXHAUSTED => "fuel_exhausted",
+            PluginFailureKind::TIMEOUT => "timeout",
+            PluginFailureKind::MALFORMED => "malformed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PluginFailure {
+    pub kind: PluginFailureKind,
+    pub message: String,
+}
+
+impl PluginFailure {
+    pub fn new(kind: PluginFailureKind, message: String) -> Self {
+        PluginFailure { kind, message }
+    }
+}
+
+/// The aggregated verdict of all the plugins for a request.
+#[derive(Debug, Clone)]
+pub struct PluginVerdict {
+    pub action: PluginAction,
+    // The plugin that made the final ALLOW or BLOCK action.
+    pub plugin: Option<String>,
+    // The accumulated score deltas of the executed plugins.
+    pub score: i64,
+    // The headers to be added to the upstream request.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Default for PluginVerdict {
+    fn default() -> Self {
+        PluginVerdict {
+            action: PluginAction::CONTINUE,
+            plugin: None,
+            score: 0,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl PluginVerdict {
+    /// Add the headers of the plugins to the incoming request, which will be forwarded to the upstream.
+    pub fn apply_headers(&self, incoming: Arc<HttpIncomingRequest>) -> Arc<HttpIncomingRequest> {
+        if self.headers.is_empty() {
+            return incoming;
+        }
+        let mut incoming = (*incoming).clone();
+        for (name, value) in &self.headers {
+            incoming.headers.insert(name.to_owned(), Some(value.to_owned()));
+        }
+        Arc::new(incoming)
+    }
+}
+
+/// The normalized request exposed to the plugin and the outputs collected by the host ABI.
+struct PluginContext {
+    method: String,
+    path: String,
+    query: String,
+    headers: HashMap<String, String>,
+    body_sample: Vec<u8>,
+    score: i64,
+    headers_added: Vec<(String, String)>,
+    malformed: Option<String>,
+}
+
+impl PluginContext {
+    fn new(incoming: &HttpIncomingRequest, max_body_sample_bytes: usize) -> Self {
+        PluginContext {
+            method: incoming.method.to_owned(),
+            path: incoming.path.to_owned(),
+            query: incoming.query.to_owned().unwrap_or_default(),
+            headers: incoming
+                .headers
+                .iter()
+                .map(|(k, v)| (k.to_lowercase(), v.to_owned().unwrap_or_default()))
+                .collect(),
+            body_sample: incoming
+                .body
+                .as_ref()
+                .map(|b| b[..b.len().min(max_body_sample_bytes)].to_vec())
+                .unwrap_or_default(),
+            score: 0,
+            headers_added: Vec::new(),
+            malformed: None,
+        }
+    }
+}
+
+struct WasmPlugin {
+    config: WasmPluginProperties,
+    // The module is none if failed to load, which is retried when the file modified.
+    module: Option<Module>,
+    modified: Option<SystemTime>,
+    disabled_until: Mutex<Option<Instant>>,
+}
+
+impl WasmPlugin {
+    fn is_disabled(&self) -> bool {
+        matches!(*self.disabled_until.lock().unwrap(), Some(until) if Instant::now() < until)
+    }
+
+    fn disable(&self, failure: &PluginFailure) {
+        warn!(
+            "Disabled the wasm plugin '{}' for {} due to {}. {}",
+            self.config.name,
+            self.config.cooldown_secs,
+            failure.kind.label(),
+            failure.message
+        );
+        BOTWAF_PLUGIN_FAILURES_TOTAL
+            .with_label_values(&[&self.config.name, failure.kind.label()])
+            .inc();
+        *self.disabled_until.lock().unwrap() = Some(Instant::now() + *self.config.cooldown_secs);
+    }
+}
+
+impl Default for WasmPluginHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The host of the WebAssembly plugins for the custom request logic, which are executed in order between the
+/// request normalization and ModSecurity. The plugins are sandboxed within the fuel and time limits, and any
+/// failures of plugin are contained, that is the plugin is disabled for the cooldown and never fail the request.
+///
+/// The host ABI (imported from module "botwaf"):
+/// - `get_method(buf_ptr, buf_cap) -> len`, `get_path`, `get_query` and `get_body` (the body sample), which copy
+///   the value into the buffer (truncated if exceeded the capacity), and returns the full length.
+/// - `get_header(name_ptr, name_len, buf_ptr, buf_cap) -> len`, which returns -1 if the header is missing.
+/// - `add_header(name_ptr, name_len, value_ptr, value_len)`, which adds the header to the upstream request.
+/// - `add_score(delta)`, which accumulates the score delta, and the request is blocked if reached the threshold.
+///
+/// The plugin must export the "memory" and the entrypoint `botwaf_on_request() -> verdict`, the verdict code is
+/// 0 (CONTINUE), 1 (ALLOW) or 2 (BLOCK). see the example: etc/plugins/header_allowlist.wat
+pub struct WasmPluginHost {
+    engine: Engine,
+    linker: Arc<Linker<PluginContext>>,
+    plugins: RwLock<Vec<Arc<WasmPlugin>>>,
+}
+
+impl WasmPluginHost {
+    pub const ABI_MODULE: &'static str = "botwaf";
+    pub const ENTRYPOINT: &'static str = "botwaf_on_request";
+    // The interval of the epoch ticks, which is the precision of the execution timeout.
+    const EPOCH_TICK: Duration = Duration::from_millis(2);
+    // The max bytes of the plugin memory to be read by the host, e.g: the header name.
+    const MAX_READ_BYTES: i32 = 64 * 1024;
+
+    pub fn new() -> Self {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("Failed to create the wasm plugin engine");
+        let linker = Self::build_linker(&engine).expect("Failed to build the wasm plugin host ABI");
+
+        // The epoch ticker of the execution timeout, which is stopped once the engine dropped.
+        let weak_engine = engine.weak();
+        thread::Builder::new()
+            .name(String::from("botwaf-wasm-epoch"))
+            .spawn(move || {
+                while let Some(engine) = weak_engine.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    thread::sleep(Self::EPOCH_TICK);
+                }
+            })
+            .expect("Failed to spawn the wasm plugin epoch ticker");
+
+        WasmPluginHost {
+            engine,
+            linker: Arc::new(linker),
+            plugins: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn get() -> &'static WasmPluginHost {
+        &SINGLE_INSTANCE
+    }
+
+    /// Execute the enabled plugins in order, and the plugins are (re)loaded if the configuration (e.g: the
+    /// config refreshed) or the plugin files modified.
+    pub async fn run(&self, config: &WasmPluginsProperties, incoming: Arc<HttpIncomingRequest>) -> PluginVerdict {
+        let mut verdict = PluginVerdict::default();
+        if config.items.is_empty() {
+            return verdict;
+        }
+
+        for plugin in self.refresh(config) {
+            let module = match &plugin.module {
+                Some(module) => module.to_owned(),
+                None => continue,
+            };
+            if plugin.is_disabled() {
+                continue;
+            }
+
+            let context = PluginContext::new(&incoming, plugin.config.max_body_sample_bytes);
+            let (engine, linker, plugin_config) =
+                (self.engine.to_owned(), self.linker.to_owned(), plugin.config.to_owned());
+            let result =
+                tokio::task::spawn_blocking(move || Self::execute(&engine, &linker, &plugin_config, &module, context))
+                    .await
+                    .unwrap_or_else(|e| Err(PluginFailure::new(PluginFailureKind::TRAP, e.to_string())));
+
+            match result {
+                Ok((action, context)) => {
+                    verdict.score += context.score;
+                    verdict.headers.extend(context.headers_added);
+                    if action != PluginAction::CONTINUE {
+                        verdict.action = action;
+                        verdict.plugin = Some(plugin.config.name.to_owned());
+                        return verdict;
+                    }
+                }
+                Err(failure) => plugin.disable(&failure),
+            }
+        }
+
+        if verdict.score >= config.score_threshold {
+            verdict.action = PluginAction::BLOCK;
+            verdict.plugin = Some(String::from("score-threshold"));
+        }
+        verdict
+    }
+
+    /// Whether the plugin is disabled in the cooldown.
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .any(|p| p.config.name == name && p.is_disabled())
+    }
+
+    fn refresh(&self, config: &WasmPluginsProperties) -> Vec<Arc<WasmPlugin>> {
+        let items = config
+            .items
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| (p, Self::get_modified_time(&p.path)))
+            .collect::<Vec<_>>();
+        let is_unchanged = |plugin: &WasmPlugin, item: &(&WasmPluginProperties, Option<SystemTime>)| {
+            &plugin.config == item.0 && plugin.modified == item.1
+        };
+
+        let plugins = self.plugins.read().unwrap();
+        if plugins.len() == items.len() && plugins.iter().zip(&items).all(|(p, item)| is_unchanged(p, item)) {
+            return plugins.to_owned();
+        }
+        drop(plugins);
+
+        // Reload the changed plugins, the unchanged plugins are retained with the cooldown state.
+        let mut plugins = self.plugins.write().unwrap();
+        let reloaded = items
+            .iter()
+            .map(|item| match plugins.iter().find(|p| is_unchanged(p, item)) {
+                Some(plugin) => plugin.to_owned(),
+                None => Arc::new(self.load(item.0, item.1)),
+            })
+            .collect::<Vec<_>>();
+        *plugins = reloaded.to_owned();
+        reloaded
+    }
+
+    fn load(&self, config: &WasmPluginProperties, modified: Option<SystemTime>) -> WasmPlugin {
+        info!("Loading the wasm plugin '{}' from {}", config.name, config.path);
+        let module = match Module::from_file(&self.engine, &config.path) {
+            Ok(module) => Some(module),
+            Err(e) => {
+                warn!("Failed to load the wasm plugin '{}'. cause: {:#}", config.name, e);
+                BOTWAF_PLUGIN_FAILURES_TOTAL
+                    .with_label_values(&[&config.name, PluginFailureKind::LOAD.label()])
+                    .inc();
+                None
+            }
+        };
+        WasmPlugin {
+            config: config.to_owned(),
+            module,
+            modified,
+            disabled_until: Mutex::new(None),
+        }
+    }
+
+    fn get_modified_time(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn execute(
+        engine: &Engine,
+        linker: &Linker<PluginContext>,
+        config: &WasmPluginProperties,
+        module: &Module,
+        context: PluginContext,
+    ) -> Result<(PluginAction, PluginContext), PluginFailure> {
+        let mut store = Store::new(engine, context);
+        store
+            .set_fuel(config.fuel)
+            .map_err(|e| PluginFailure::new(PluginFailureKind::TRAP, e.to_string()))?;
+        // The extra one tick is because of the current tick may be partially elapsed.
+        store.set_epoch_deadline(
+            (config.timeout_ms.as_millis() as u64).div_ceil(Self::EPOCH_TICK.as_millis() as u64) + 1,
+        );
+        store.epoch_deadline_trap();
+
+        match Self::call_entrypoint(&mut store, linker, module) {
+            Ok(code) => {
+                let context = store.into_data();
+                if let Some(message) = context.malformed.to_owned() {
+                    return Err(PluginFailure::new(PluginFailureKind::MALFORMED, message));
+                }
+                match PluginAction::from_code(code) {
+                    Some(action) => Ok((action, context)),
+                    None => Err(PluginFailure::new(
+                        PluginFailureKind::MALFORMED,
+                        format!("Invalid verdict code {}", code),
+                    )),
+                }
+            }
+            Err(e) => {
+                let kind = match e.downcast_ref::<Trap>() {
+                    Some(Trap::OutOfFuel) => PluginFailureKind::FUEL_EXHAUSTED,
+                    Some(Trap::Interrupt) => PluginFailureKind::TIMEOUT,
+                    _ => PluginFailureKind::TRAP,
+                };
+                Err(PluginFailure::new(kind, format!("{:#}", e)))
+            }
+        }
+    }
+
+    fn call_entrypoint(
+        store: &mut Store<PluginContext>,
+        linker: &Linker<PluginContext>,
+        module: &Module,
+    ) -> wasmtime::Result<i32> {
+        let instance = linker.instantiate(&mut *store, module)?;
+        let entrypoint = instance.get_typed_func::<(), i32>(&mut *store, Self::ENTRYPOINT)?;
+        entrypoint.call(&mut *store, ())
+    }
+
+    fn build_linker(engine: &Engine) -> wasmtime::Result<Linker<PluginContext>> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "get_method",
+            |mut caller: Caller<'_, PluginContext>, ptr: i32, cap: i32| {
+                let value = caller.data().method.as_bytes().to_vec();
+                Self::write_bytes(&mut caller, &value, ptr, cap)
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "get_path",
+            |mut caller: Caller<'_, PluginContext>, ptr: i32, cap: i32| {
+                let value = caller.data().path.as_bytes().to_vec();
+                Self::write_bytes(&mut caller, &value, ptr, cap)
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "get_query",
+            |mut caller: Caller<'_, PluginContext>, ptr: i32, cap: i32| {
+                let value = caller.data().query.as_bytes().to_vec();
+                Self::write_bytes(&mut caller, &value, ptr, cap)
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "get_body",
+            |mut caller: Caller<'_, PluginContext>, ptr: i32, cap: i32| {
+                let value = caller.data().body_sample.to_owned();
+                Self::write_bytes(&mut caller, &value, ptr, cap)
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "get_header",
+            |mut caller: Caller<'_, PluginContext>, name_ptr: i32, name_len: i32, ptr: i32, cap: i32| {
+                let name = String::from_utf8_lossy(&Self::read_bytes(&mut caller, name_ptr, name_len)?).to_lowercase();
+                match caller.data().headers.get(&name).map(|v| v.as_bytes().to_vec()) {
+                    Some(value) => Self::write_bytes(&mut caller, &value, ptr, cap),
+                    None => Ok(-1),
+                }
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "add_header",
+            |mut caller: Caller<'_, PluginContext>, name_ptr: i32, name_len: i32, value_ptr: i32, value_len: i32| {
+                let name = Self::read_bytes(&mut caller, name_ptr, name_len)?;
+                let value = Self::read_bytes(&mut caller, value_ptr, value_len)?;
+                match (HeaderName::from_bytes(&name), String::from_utf8(value)) {
+                    (Ok(name), Ok(value)) if HeaderValue::from_str(&value).is_ok() => {
+                        caller.data_mut().headers_added.push((name.as_str().to_owned(), value));
+                    }
+                    _ => caller.data_mut().malformed = Some(String::from("Invalid the added header name or value")),
+                }
+                wasmtime::Result::<()>::Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            Self::ABI_MODULE,
+            "add_score",
+            |mut caller: Caller<'_, PluginContext>, delta: i32| {
+                caller.data_mut().score += delta as i64;
+            },
+        )?;
+        Ok(linker)
+    }
+
+    fn get_memory(caller: &mut Caller<'_, PluginContext>) -> wasmtime::Result<Memory> {
+        caller
+            .get_export("memory")
+            .and_then(|e| e.into_memory())
+            .ok_or_else(|| wasmtime::Error::msg("Missing the exported memory"))
+    }
+
+    fn read_bytes(caller: &mut Caller<'_, PluginContext>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+        if len < 0 || len > Self::MAX_READ_BYTES {
+            return Err(wasmtime::Error::msg(format!("Invalid the read length {}", len)));
+        }
+        let memory = Self::get_memory(caller)?;
+        let mut buf = vec![0u8; len as usize];
+        memory.read(&*caller, ptr as u32 as usize, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_bytes(caller: &mut Caller<'_, PluginContext>, value: &[u8], ptr: i32, cap: i32) -> wasmtime::Result<i32> {
+        let memory = Self::get_memory(caller)?;
+        let len = value.len().min(cap.max(0) as usize);
+        memory.write(&mut *caller, ptr as u32 as usize, &value[..len])?;
+        Ok(value.len() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationMillis;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use std::env;
+
+    const EXAMPLE_HEADER_ALLOWLIST: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../../etc/plugins/header_allowlist.wat");
+
+    fn write_plugin(name: &str, wat: &str) -> String {
+        let path = env::temp_dir().join(format!("botwaf-plugin-{}-{}.wat", name, std::process::id()));
+        fs::write(&path, wat).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn create_config(plugins: Vec<(&str, String)>, fuel: u64, timeout_ms: u64) -> WasmPluginsProperties {
+        WasmPluginsProperties {
+            score_threshold: 100,
+            items: plugins
+                .into_iter()
+                .map(|(name, path)| WasmPluginProperties {
+                    name: name.to_owned(),
+                    path,
+                    fuel,
+                    timeout_ms: DurationMillis::from_millis(timeout_ms),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    fn mock_incoming(headers: Vec<(&str, &str)>) -> Arc<HttpIncomingRequest> {
+        TestIncomingBuilder::new("GET", "/search")
+            .query("q=hello")
+            .headers(&headers)
+            .build_arc()
+    }
+
+    fn failures(name: &str, kind: PluginFailureKind) -> u64 {
+        BOTWAF_PLUGIN_FAILURES_TOTAL
+            .with_label_values(&[name, kind.label()])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn test_example_header_allowlist() {
+        let host = WasmPluginHost::new();
+        let config = create_config(
+            vec![("test_allowlist", EXAMPLE_HEADER_ALLOWLIST.to_owned())],
+            1_000_000,
+            100,
+        );
+
+        let verdict = host
+            .run(&config, mock_incoming(vec![("X-Client-Id", "trusted-client")]))
+            .await;
+        assert_eq!(verdict.action, PluginAction::ALLOW);
+        assert_eq!(verdict.plugin.as_deref(), Some("test_allowlist"));
+        assert_eq!(
+            verdict.headers,
+            vec![(String::from("x-botwaf-allowlisted"), String::from("true"))]
+        );
+        let incoming = verdict.apply_headers(mock_incoming(vec![]));
+        assert_eq!(
+            incoming.headers.get("x-botwaf-allowlisted"),
+            Some(&Some(String::from("true")))
+        );
+
+        let verdict = host
+            .run(&config, mock_incoming(vec![("X-Client-Id", "unknown-client")]))
+            .await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+    }
+
+    #[tokio::test]
+    async fn test_trap_contained_and_cooldown() {
+        let path = write_plugin(
+            "trap",
+            r#"(module (memory (export "memory") 1) (func (export "botwaf_on_request") (result i32) unreachable))"#,
+        );
+        let host = WasmPluginHost::new();
+        let config = create_config(vec![("test_trap", path)], 1_000_000, 100);
+
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        assert!(host.is_disabled("test_trap"));
+        assert_eq!(failures("test_trap", PluginFailureKind::TRAP), 1);
+
+        // The disabled plugin should be skipped in the cooldown.
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        assert_eq!(failures("test_trap", PluginFailureKind::TRAP), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fuel_exhaustion_contained() {
+        let path = write_plugin(
+            "fuel",
+            r#"(module (memory (export "memory") 1) (func (export "botwaf_on_request") (result i32) (loop (br 0)) (i32.const 2)))"#,
+        );
+        let host = WasmPluginHost::new();
+        let config = create_config(vec![("test_fuel", path)], 10_000, 60_000);
+
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        assert!(host.is_disabled("test_fuel"));
+        assert_eq!(failures("test_fuel", PluginFailureKind::FUEL_EXHAUSTED), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_contained() {
+        let path = write_plugin(
+            "timeout",
+            r#"(module (memory (export "memory") 1) (func (export "botwaf_on_request") (result i32) (loop (br 0)) (i32.const 2)))"#,
+        );
+        let host = WasmPluginHost::new();
+        let config = create_config(vec![("test_timeout", path)], 1 << 40, 20);
+
+        let start = Instant::now();
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        assert!(host.is_disabled("test_timeout"));
+        assert_eq!(failures("test_timeout", PluginFailureKind::TIMEOUT), 1);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_verdict_contained() {
+        let path = write_plugin(
+            "malformed",
+            r#"(module (memory (export "memory") 1) (func (export "botwaf_on_request") (result i32) (i32.const 7)))"#,
+        );
+        let host = WasmPluginHost::new();
+        let config = create_config(vec![("test_malformed", path)], 1_000_000, 100);
+
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.action, PluginAction::CONTINUE);
+        assert!(host.is_disabled("test_malformed"));
+        assert_eq!(failures("test_malformed", PluginFailureKind::MALFORMED), 1);
+    }
+
+    #[tokio::test]
+    async fn test_score_threshold_and_failed_plugin_skipped() {
+        let trap = write_plugin(
+            "score_trap",
+            r#"(module (memory (export "memory") 1) (func (export "botwaf_on_request") (result i32) unreachable))"#,
+        );
+        let score = write_plugin(
+            "score",
+            r#"(module
+                (import "botwaf" "add_score" (func $add_score (param i32)))
+                (memory (export "memory") 1)
+                (func (export "botwaf_on_request") (result i32) (call $add_score (i32.const 60)) (i32.const 0)))"#,
+        );
+        let host = WasmPluginHost::new();
+        let config = create_config(
+            vec![
+                ("test_score_trap", trap),
+                ("test_score_1", score.to_owned()),
+                ("test_score_2", score),
+            ],
+            1_000_000,
+            100,
+        );
+
+        let verdict = host.run(&config, mock_incoming(vec![])).await;
+        assert_eq!(verdict.score, 120);
+        assert_eq!(verdict.action, PluginAction::BLOCK);
+        assert!(host.is_disabled("test_score_trap"));
+    }
+}
@@ -0,0 +1,436 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::forwarder_base::{BotwafDecision, BotwafForwarderManager};
+use anyhow::{Error, Result};
+use arc_swap::ArcSwap;
+use axum::body::Body;
+use botwaf_server::{
+    config::config::{self, ProbeDecision, ProbeItemProperties, ProbeProperties},
+    context::state::BotwafState,
+    mgmt::apm::metrics::BOTWAF_PROBE_SUCCESS,
+    modules::modsec::rule_exclusion::RuleExclusions,
+    sys::dead_letter::WebhookDelivery,
+};
+use botwaf_types::{
+    modules::forward::forwarder::{HttpIncomingRequest, SyntheticProbe},
+    sys::event::{BotwafEvent, ProbeFailedV1},
+};
+use botwaf_utils::{request_signing::RequestSigner, secrets::SecretHelper};
+use chrono::Utc;
+use common_telemetry::info;
+use hyper::Request;
+use lazy_static::lazy_static;
+use modsecurity::{ModSecurity, Rules};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+lazy_static! {
+    // The process-local signing key of the probe header, so that only the probes of this instance are honored.
+    static ref PROBE_SIGNING_KEY: String = SecretHelper::generate_secret_base64(32);
+}
+
+// The max skew (seconds) of the signed probe header, which bounds the replay of a leaked header value.
+const PROBE_SIGNATURE_MAX_SKEW_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub name: String,
+    pub expected: ProbeDecision,
+    pub actual: ProbeDecision,
+    pub success: bool,
+    // The end-to-end decision via the public listener, None if not enabled.
+    pub end_to_end: Option<ProbeDecision>,
+    pub consecutive_failures: u32,
+    pub time: i64,
+}
+
+/// The synthetic monitoring prober, which sends the known requests through the in-process
+/// evaluation function (and optionally the public listener) to verify that protection is working.
+pub struct SyntheticProber {
+    config: ProbeProperties,
+    modsec_engine: Arc<ModSecurity>,
+    // The live rules and exclusions of the forwarder state, which are loaded on each probe run, so that the
+    // reloaded (e.g: hot reloads, DB rule updates, shadow promotions) rules are probed.
+    modsec_rules: Arc<ArcSwap<Rules>>,
+    modsec_rule_exclusions: Arc<ArcSwap<RuleExclusions>>,
+    http_client: reqwest::Client,
+    results: RwLock<HashMap<String, ProbeResult>>,
+}
+
+impl SyntheticProber {
+    pub fn new(config: &ProbeProperties, state: &BotwafState) -> Arc<Self> {
+        Arc::new(Self {
+            config: config.to_owned(),
+            modsec_engine: state.modsec_engine.to_owned(),
+            modsec_rules: state.modsec_rules.to_owned(),
+            modsec_rule_exclusions: state.modsec_rule_exclusions.to_owned(),
+            http_client: reqwest::Client::new(),
+            results: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Schedule all the enabled probes with their cron expressions.
+    pub async fn start(self: &Arc<Self>) -> Result<(), Error> {
+        if !self.config.enabled {
+            info!("Skipping the synthetic probes.");
+            return Ok(());
+        }
+
+        let scheduler = JobScheduler::new_with_channel_size(self.config.channel_size).await?;
+        for item in self.config.items.iter().filter(|item| item.enabled) {
+            let this = self.clone();
+            let item = item.to_owned();
+            let cron = item.cron.to_owned();
+            let job = Job::new_async(cron.as_str(), move |_uuid, _lock| {
+                let that = this.clone();
+                let item = item.clone();
+                Box::pin(async move {
+                    that.run_probe(&item).await;
+                })
+            })?;
+            scheduler.add(job).await?;
+            info!("Scheduled the synthetic probe with cron '{}'", cron);
+        }
+        scheduler.start().await?;
+        Ok(())
+    }
+
+    pub async fn run_probe(&self, item: &ProbeItemProperties) -> ProbeResult {
+        let incoming = match Self::build_incoming(item).await {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                tracing::error!("Invalid synthetic probe '{}' request template. cause: {}", item.name, e);
+                return self.record(item, None, None);
+            }
+        };
+        // Apply the rule exclusions of the matched route the same as the forwarder.
+        let rules = self
+            .modsec_rule_exclusions
+            .load()
+            .find(&incoming.path)
+            .unwrap_or_else(|| self.modsec_rules.load_full());
+        let actual = Self::to_probe_decision(&BotwafForwarderManager::evaluate(
+            &self.modsec_engine,
+            &rules,
+            &incoming,
+        ));
+
+        let end_to_end = if item.end_to_end {
+            match self.probe_end_to_end(item).await {
+                Ok(decision) => Some(decision),
+                Err(e) => {
+                    tracing::warn!("Failed to end-to-end synthetic probe '{}'. cause: {}", item.name, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        self.record(item, Some(actual), end_to_end)
+    }
+
+    /// Sign the end-to-end probe header value, i.e: '<name>.<timestamp>.<hmac-sha256-hex>'
+    pub fn sign_probe_header(name: &str) -> String {
+        Self::sign_probe_header_at(name, Utc::now().timestamp())
+    }
+
+    fn sign_probe_header_at(name: &str, timestamp: i64) -> String {
+        let payload = format!("{}.{}", name, timestamp);
+        let signature = SecretHelper::hmac_sha256_hex(PROBE_SIGNING_KEY.as_bytes(), payload.as_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Whether the probe header value is signed by the prober of this instance and not expired.
+    pub fn verify_probe_header(value: &str) -> bool {
+        let Some((payload, signature)) = value.rsplit_once('.') else {
+            return false;
+        };
+        let fresh = payload
+            .rsplit_once('.')
+            .and_then(|(_, timestamp)| timestamp.parse::<i64>().ok())
+            .is_some_and(|timestamp| (Utc::now().timestamp() - timestamp).abs() <= PROBE_SIGNATURE_MAX_SKEW_SECS);
+        fresh && RequestSigner::verify(PROBE_SIGNING_KEY.as_bytes(), payload, signature)
+    }
+
+    /// Strip the probe header from the request before any evaluation, and mark it as the synthetic probe
+    /// only if the header is genuinely signed, i.e: the clients can never opt out of the statistics.
+    pub fn take_probe_header(req: &mut Request<Body>) -> bool {
+        let synthetic = req
+            .headers_mut()
+            .remove(HttpIncomingRequest::SYNTHETIC_PROBE_HEADER)
+            .is_some_and(|value| value.to_str().is_ok_and(Self::verify_probe_header));
+        if synthetic {
+            req.extensions_mut().insert(SyntheticProbe);
+        }
+        synthetic
+    }
+
+    pub fn get_results(&self) -> Vec<ProbeResult> {
+        self.results.read().unwrap().values().cloned().collect()
+    }
+
+    fn record(
+        &self,
+        item: &ProbeItemProperties,
+        actual: Option<ProbeDecision>,
+        end_to_end: Option<ProbeDecision>,
+    ) -> ProbeResult {
+        let success = actual == Some(item.expected) && (!item.end_to_end || end_to_end == Some(item.expected));
+        BOTWAF_PROBE_SUCCESS
+            .with_label_values(&[item.name.as_str()])
+            .set(if success { 1 } else { 0 });
+
+        let mut results = self.results.write().unwrap();
+        let consecutive_failures = match (success, results.get(&item.name)) {
+            (true, _) => 0,
+            (false, Some(last)) => last.consecutive_failures + 1,
+            (false, None) => 1,
+        };
+        let result = ProbeResult {
+            name: item.name.to_owned(),
+            expected: item.expected,
+            actual: actual.unwrap_or(ProbeDecision::PASS),
+            success,
+            end_to_end,
+            consecutive_failures,
+            time: chrono::Utc::now().timestamp_millis(),
+        };
+        results.insert(item.name.to_owned(), result.to_owned());
+        drop(results);
+
+        if success {
+            tracing::debug!("Synthetic probe '{}' succeed.", item.name);
+        } else {
+            tracing::warn!("Synthetic probe '{}' failed. {:?}", item.name, result);
+            // Notice: Only notify once when reached the threshold to avoid alert storms.
+            if consecutive_failures == self.config.failure_threshold {
+                self.notify(result.to_owned());
+            }
+        }
+        result
+    }
+
+    async fn build_incoming(item: &ProbeItemProperties) -> Result<Arc<HttpIncomingRequest>, Error> {
+        let mut builder = Request::builder()
+            .method(item.method.as_str())
+            .uri(item.uri.as_str())
+            .extension(SyntheticProbe);
+        for (key, value) in item.headers.iter() {
+            builder = builder.header(key, value);
+        }
+        let req = builder.body(Body::from(item.body.to_owned().unwrap_or_default()))?;
+        HttpIncomingRequest::new(req, config::get_config().services.forward.max_body_bytes).await
+    }
+
+    async fn probe_end_to_end(&self, item: &ProbeItemProperties) -> Result<ProbeDecision, Error> {
+        let endpoint = self
+            .config
+            .public_endpoint
+            .as_ref()
+            .ok_or_else(|| Error::msg("No configured the probe public-endpoint"))?;
+        let url = format!("{}{}", endpoint.trim_end_matches('/'), item.uri);
+        let mut builder = self
+            .http_client
+            .request(reqwest::Method::from_bytes(item.method.as_bytes())?, url)
+            .header(
+                HttpIncomingRequest::SYNTHETIC_PROBE_HEADER,
+                Self::sign_probe_header(&item.name),
+            );
+        for (key, value) in item.headers.iter() {
+            builder = builder.header(key, value);
+        }
+        if let Some(body) = &item.body {
+            builder = builder.body(body.to_owned());
+        }
+        let resp = builder.send().await?;
+
+        let services = &config::get_config().services;
+        let blocked = resp.headers().contains_key(services.blocked_header_name.as_str())
+            || services.blocked_status_code == Some(resp.status().as_u16());
+        Ok(if blocked { ProbeDecision::BLOCK } else { ProbeDecision::PASS })
+    }
+
+    fn notify(&self, result: ProbeResult) {
+        tracing::error!(
+            "Synthetic probe '{}' failed {} times consecutively, the protection may not be working!",
+            result.name,
+            result.consecutive_failures
+        );
+        // The failed notification is written into the dead letters for replay.
+        if let Some(webhook_url) = &self.config.notify_webhook_url {
+            let event = ProbeFailedV1 {
+                name: result.name,
+                expected: format!("{:?}", result.expected),
+                actual: format!("{:?}", result.actual),
+                end_to_end: result.end_to_end.map(|d| format!("{:?}", d)),
+                consecutive_failures: result.consecutive_failures,
+            };
+            WebhookDelivery::new(webhook_url, BotwafEvent::ProbeFailedV1(event))
+                .spawn(self.http_client.clone(), "synthetic probe failure");
+        }
+    }
+
+    fn to_probe_decision(decision: &BotwafDecision) -> ProbeDecision {
+        if decision.is_blocked() {
+            ProbeDecision::BLOCK
+        } else {
+            ProbeDecision::PASS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::{
+        context::test_support::{create_in_memory_cache, create_test_config, StaticLLMHandler},
+        modules::modsec::body_processor::BODY_PROCESSOR_RULES,
+    };
+
+    fn compile_rules(rules: &str) -> Rules {
+        let mut modsec_rules = Rules::new();
+        modsec_rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        modsec_rules.add_plain(rules).unwrap();
+        modsec_rules
+    }
+
+    async fn create_prober_with_state(rules: &str) -> (Arc<SyntheticProber>, BotwafState) {
+        let state = BotwafState::builder()
+            .with_config(&create_test_config("probe-synthetic"))
+            .with_cache(create_in_memory_cache())
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(compile_rules(rules), Vec::new())
+            .build()
+            .await
+            .unwrap();
+        let mut config = ProbeProperties::default();
+        config.enabled = true;
+        (SyntheticProber::new(&config, &state), state)
+    }
+
+    async fn create_prober(rules: &str) -> Arc<SyntheticProber> {
+        create_prober_with_state(rules).await.0
+    }
+
+    fn probe_item(name: &str) -> ProbeItemProperties {
+        ProbeProperties::default()
+            .items
+            .into_iter()
+            .find(|item| item.name == name)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_default_sqli_probe_blocked() {
+        let prober = create_prober(
+            r#"
+SecRuleEngine On
+SecRule ARGS "@detectSQLi" "id:3101,phase:2,deny,status:403,msg:'SQLi'"
+"#,
+        )
+        .await;
+        let result = prober.run_probe(&probe_item("basic_sqli")).await;
+        assert!(result.success);
+        assert_eq!(BOTWAF_PROBE_SUCCESS.with_label_values(&["basic_sqli"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_broken_rules_flips_probe_metric() {
+        // The broken rule set, which never matches the XSS payload.
+        let prober = create_prober(
+            r#"
+SecRuleEngine On
+SecRule ARGS "@streq never-matched" "id:3102,phase:2,deny,status:403,msg:'Broken'"
+"#,
+        )
+        .await;
+        let result = prober.run_probe(&probe_item("basic_xss")).await;
+        assert!(!result.success);
+        assert_eq!(result.actual, ProbeDecision::PASS);
+        assert_eq!(result.consecutive_failures, 1);
+        assert_eq!(BOTWAF_PROBE_SUCCESS.with_label_values(&["basic_xss"]).get(), 0);
+
+        let result = prober.run_probe(&probe_item("basic_xss")).await;
+        assert_eq!(result.consecutive_failures, 2);
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_rules_probed() {
+        let (prober, state) = create_prober_with_state(
+            r#"
+SecRuleEngine On
+SecRule ARGS "@detectSQLi" "id:3103,phase:2,deny,status:403,msg:'SQLi'"
+"#,
+        )
+        .await;
+        // The dedicated probe name, since the metrics are shared by the concurrent tests.
+        let mut item = probe_item("basic_sqli");
+        item.name = String::from("reloaded_sqli");
+        assert!(prober.run_probe(&item).await.success);
+
+        // The broken rule set reloaded into the live state flips the probe.
+        state.modsec_rules.store(Arc::new(compile_rules(
+            r#"
+SecRuleEngine On
+SecRule ARGS "@streq never-matched" "id:3104,phase:2,deny,status:403,msg:'Broken'"
+"#,
+        )));
+        let result = prober.run_probe(&item).await;
+        assert!(!result.success);
+        assert_eq!(result.actual, ProbeDecision::PASS);
+        assert_eq!(BOTWAF_PROBE_SUCCESS.with_label_values(&["reloaded_sqli"]).get(), 0);
+    }
+
+    #[test]
+    fn test_probe_header_only_honored_if_signed() {
+        let request = |value: &str| {
+            Request::builder()
+                .uri("/search")
+                .header(HttpIncomingRequest::SYNTHETIC_PROBE_HEADER, value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The spoofed probe header of the clients is stripped and never honored.
+        for value in ["1", "basic_sqli", "basic_sqli.1700000000.deadbeef"] {
+            let mut req = request(value);
+            assert!(!SyntheticProber::take_probe_header(&mut req));
+            assert!(!req.headers().contains_key(HttpIncomingRequest::SYNTHETIC_PROBE_HEADER));
+            assert!(req.extensions().get::<SyntheticProbe>().is_none());
+        }
+
+        // The expired signature is rejected, e.g: replayed from the leaked logs.
+        let expired = SyntheticProber::sign_probe_header_at("basic.sqli", Utc::now().timestamp() - 3600);
+        assert!(!SyntheticProber::take_probe_header(&mut request(&expired)));
+
+        let mut req = request(&SyntheticProber::sign_probe_header("basic.sqli"));
+        assert!(SyntheticProber::take_probe_header(&mut req));
+        assert!(!req.headers().contains_key(HttpIncomingRequest::SYNTHETIC_PROBE_HEADER));
+        assert!(req.extensions().get::<SyntheticProbe>().is_some());
+    }
+}
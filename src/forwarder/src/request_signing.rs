@@ -0,0 +1,353 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use botwaf_server::{
+    config::config::{self, RequestSigningRouteProperties, RequestSigningScheme},
+    mgmt::apm::metrics::BOTWAF_SIGNATURE_FAILURES_TOTAL,
+    sys::signing_key::SigningKeyManager,
+};
+use botwaf_types::modules::forward::forwarder::HttpIncomingRequest;
+use botwaf_utils::{
+    paths,
+    request_signing::{RequestSigner, SignatureAuthorization},
+};
+use chrono::{DateTime, Utc};
+use globset::{Glob, GlobMatcher};
+use lazy_static::lazy_static;
+use thiserror::Error;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: RequestSignatureVerifier =
+        RequestSignatureVerifier::new(&config::get_config().services.request_signing.routes);
+}
+
+/// The rejected reason of the signed request, which is returned to the caller as machine-readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[allow(non_camel_case_types)]
+pub enum SignatureFailure {
+    #[error("missing-signature")]
+    MISSING_SIGNATURE,
+    #[error("malformed")]
+    MALFORMED,
+    #[error("expired")]
+    EXPIRED,
+    #[error("unknown-key")]
+    UNKNOWN_KEY,
+    // The signing keys store is unavailable, e.g: the DB is down, the request is always rejected (fail-closed).
+    #[error("key-unavailable")]
+    KEY_UNAVAILABLE,
+    #[error("missing-signed-header")]
+    MISSING_SIGNED_HEADER,
+    #[error("digest-mismatch")]
+    DIGEST_MISMATCH,
+    #[error("signature-mismatch")]
+    SIGNATURE_MISMATCH,
+}
+
+impl SignatureFailure {
+    pub fn reason(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// The verifier of the HMAC signed requests of the configured routes, the first matched path-glob wins.
+/// Notice: The secrets of the keys are used as the HMAC keys as is (i.e: the generated base64 string).
+pub struct RequestSignatureVerifier {
+    routes: Vec<(GlobMatcher, RequestSigningRouteProperties)>,
+}
+
+impl RequestSignatureVerifier {
+    pub fn new(routes: &[RequestSigningRouteProperties]) -> Self {
+        let routes = routes
+            .iter()
+            .filter_map(|route| match Glob::new(&route.path_glob) {
+                Ok(glob) => Some((glob.compile_matcher(), route.to_owned())),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to load the request signing of {}, it will be ignored. cause: {}",
+                        route.path_glob,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        RequestSignatureVerifier { routes }
+    }
+
+    pub fn get() -> &'static RequestSignatureVerifier {
+        &SINGLE_INSTANCE
+    }
+
+    /// Find the route of the request path, which is matched by the normalized path rather than the raw path, so that
+    /// e.g: '//internal/x', '/public/../internal/x' or '/%69nternal/x' are still required to be signed.
+    pub fn find_route(&self, path: &str) -> Option<&RequestSigningRouteProperties> {
+        let normalized = paths::normalize_path(path);
+        self.routes
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(&normalized.path))
+            .map(|(_, route)| route)
+    }
+
+    /// Verify the signature of the request if the path is required to be signed, the failure is counted by reason.
+    pub async fn verify(&self, incoming: &HttpIncomingRequest) -> Result<(), SignatureFailure> {
+        let route = match self.find_route(&incoming.path) {
+            Some(route) => route,
+            None => return Ok(()),
+        };
+        let result = match Self::parse_authorization(incoming) {
+            Ok(auth) => match Self::find_secrets(&auth.key_id).await {
+                Ok(secrets) => Self::verify_with(route, incoming, &auth, &secrets, Utc::now()),
+                Err(failure) => Err(failure),
+            },
+            Err(failure) => Err(failure),
+        };
+        if let Err(failure) = &result {
+            BOTWAF_SIGNATURE_FAILURES_TOTAL
+                .with_label_values(&[&failure.reason()])
+                .inc();
+        }
+        result
+    }
+
+    pub fn parse_authorization(incoming: &HttpIncomingRequest) -> Result<SignatureAuthorization, SignatureFailure> {
+        let value =
+            Self::header(incoming, RequestSigner::AUTHORIZATION_HEADER).ok_or(SignatureFailure::MISSING_SIGNATURE)?;
+        RequestSigner::parse_authorization(value).ok_or(SignatureFailure::MALFORMED)
+    }
+
+    async fn find_secrets(key_id: &str) -> Result<Vec<String>, SignatureFailure> {
+        let manager = SigningKeyManager::get().ok_or(SignatureFailure::KEY_UNAVAILABLE)?;
+        match manager.find_active_secrets(key_id).await {
+            Ok(secrets) => Ok(secrets.to_vec()),
+            Err(e) => {
+                tracing::error!("Failed to find the signing keys of '{}'. cause: {}", key_id, e);
+                Err(SignatureFailure::KEY_UNAVAILABLE)
+            }
+        }
+    }
+
+    /// Verify the signed request with the active secrets of the key id, any of the secrets is matched for the
+    /// rotation, i.e: the clients roll over to the new key while the previous key is still active.
+    pub fn verify_with(
+        route: &RequestSigningRouteProperties,
+        incoming: &HttpIncomingRequest,
+        auth: &SignatureAuthorization,
+        secrets: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<(), SignatureFailure> {
+        match route.scheme {
+            RequestSigningScheme::HMAC_SHA256 => {}
+        }
+        if secrets.is_empty() {
+            return Err(SignatureFailure::UNKNOWN_KEY);
+        }
+
+        // The replay window of the signed date.
+        let date = Self::header(incoming, RequestSigner::DATE_HEADER).ok_or(SignatureFailure::MISSING_SIGNED_HEADER)?;
+        let signed_time = RequestSigner::parse_date(date).ok_or(SignatureFailure::MALFORMED)?;
+        if (now - signed_time).num_seconds().unsigned_abs() > route.max_skew_secs.as_secs() {
+            return Err(SignatureFailure::EXPIRED);
+        }
+
+        // The required headers must be signed, and the signed headers must be present.
+        let required = route.signed_headers.iter().map(|h| h.to_lowercase()).chain([
+            RequestSigner::DATE_HEADER.to_owned(),
+            RequestSigner::CONTENT_SHA256_HEADER.to_owned(),
+        ]);
+        for name in required {
+            if !auth.signed_headers.contains(&name) {
+                return Err(SignatureFailure::MISSING_SIGNED_HEADER);
+            }
+        }
+        let mut signed_headers = Vec::with_capacity(auth.signed_headers.len());
+        for name in auth.signed_headers.iter() {
+            let value = Self::header(incoming, name).ok_or(SignatureFailure::MISSING_SIGNED_HEADER)?;
+            signed_headers.push((name.as_str(), value));
+        }
+
+        // The body is bound to the signature by the content digest.
+        let content_sha256 = RequestSigner::content_sha256(incoming.body.as_deref().unwrap_or_default());
+        if Self::header(incoming, RequestSigner::CONTENT_SHA256_HEADER) != Some(content_sha256.as_str()) {
+            return Err(SignatureFailure::DIGEST_MISMATCH);
+        }
+
+        let canonical = RequestSigner::canonical_request(
+            &incoming.method,
+            &incoming.path,
+            incoming.query.as_deref(),
+            &signed_headers,
+            &content_sha256,
+        );
+        let string_to_sign = RequestSigner::string_to_sign(date, &canonical);
+        if secrets
+            .iter()
+            .any(|secret| RequestSigner::verify(secret.as_bytes(), &string_to_sign, &auth.signature))
+        {
+            Ok(())
+        } else {
+            Err(SignatureFailure::SIGNATURE_MISMATCH)
+        }
+    }
+
+    fn header<'a>(incoming: &'a HttpIncomingRequest, name: &str) -> Option<&'a str> {
+        incoming
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::duration::DurationSecs;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use botwaf_utils::request_signing::SigningRequest;
+
+    const SECRET: &str = "c2VjcmV0LW9mLW9yZGVycy1zZXJ2aWNlLWZvci10ZXN0aW5n";
+
+    fn create_route() -> RequestSigningRouteProperties {
+        RequestSigningRouteProperties {
+            path_glob: String::from("/internal/**"),
+            scheme: RequestSigningScheme::HMAC_SHA256,
+            signed_headers: vec![String::from("host")],
+            max_skew_secs: DurationSecs::from_secs(300),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        RequestSigner::parse_date("20261016T120000Z").unwrap()
+    }
+
+    // Sign by the client helper, and wrap to the incoming request as received by the forwarder.
+    fn signed_incoming(secret: &str, signed_at: DateTime<Utc>, body: &str) -> HttpIncomingRequest {
+        let request = SigningRequest {
+            method: "POST",
+            path: "/internal/orders",
+            query: Some("b=2&a=1"),
+            headers: &[("Host", "orders.internal")],
+            body: body.as_bytes(),
+        };
+        RequestSigner::sign("orders-service", secret.as_bytes(), &request, signed_at)
+            .into_iter()
+            .fold(
+                TestIncomingBuilder::new("POST", "/internal/orders"),
+                |builder, (name, value)| builder.header(&name, &value),
+            )
+            .header("host", "orders.internal")
+            .host("orders.internal")
+            .query("b=2&a=1")
+            .body(body)
+            .build()
+    }
+
+    fn verify(incoming: &HttpIncomingRequest, secrets: &[String]) -> Result<(), SignatureFailure> {
+        let auth = RequestSignatureVerifier::parse_authorization(incoming)?;
+        RequestSignatureVerifier::verify_with(&create_route(), incoming, &auth, secrets, now())
+    }
+
+    #[test]
+    fn test_verify_signed_by_client_helper() {
+        let incoming = signed_incoming(SECRET, now(), r#"{"id":1}"#);
+        assert_eq!(verify(&incoming, &[SECRET.to_owned()]), Ok(()));
+        // The previous key is still accepted during the rotation.
+        assert_eq!(verify(&incoming, &["n".repeat(44), SECRET.to_owned()]), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_failures_by_reason() {
+        let secrets = [SECRET.to_owned()];
+
+        let mut incoming = signed_incoming(SECRET, now(), "{}");
+        incoming.headers.remove("authorization");
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::MISSING_SIGNATURE));
+
+        let mut incoming = signed_incoming(SECRET, now(), "{}");
+        incoming
+            .headers
+            .insert(String::from("authorization"), Some(String::from("Bearer abc")));
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::MALFORMED));
+
+        let incoming = signed_incoming(SECRET, now() - chrono::Duration::seconds(301), "{}");
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::EXPIRED));
+
+        let incoming = signed_incoming(SECRET, now(), "{}");
+        assert_eq!(verify(&incoming, &[]), Err(SignatureFailure::UNKNOWN_KEY));
+
+        let mut incoming = signed_incoming(SECRET, now(), "{}");
+        incoming.headers.remove("host");
+        assert_eq!(
+            verify(&incoming, &secrets),
+            Err(SignatureFailure::MISSING_SIGNED_HEADER)
+        );
+
+        // The body is tampered.
+        let mut incoming = signed_incoming(SECRET, now(), "{}");
+        incoming.body = Some("{\"admin\":true}".into());
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::DIGEST_MISMATCH));
+
+        // The query is tampered.
+        let mut incoming = signed_incoming(SECRET, now(), "{}");
+        incoming.query = Some(String::from("a=1&b=3"));
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::SIGNATURE_MISMATCH));
+
+        let incoming = signed_incoming(&"x".repeat(44), now(), "{}");
+        assert_eq!(verify(&incoming, &secrets), Err(SignatureFailure::SIGNATURE_MISMATCH));
+    }
+
+    #[test]
+    fn test_verify_required_signed_headers() {
+        let route = RequestSigningRouteProperties {
+            signed_headers: vec![String::from("host"), String::from("x-tenant")],
+            ..create_route()
+        };
+        let incoming = signed_incoming(SECRET, now(), "{}");
+        let auth = RequestSignatureVerifier::parse_authorization(&incoming).unwrap();
+        assert_eq!(
+            RequestSignatureVerifier::verify_with(&route, &incoming, &auth, &[SECRET.to_owned()], now()),
+            Err(SignatureFailure::MISSING_SIGNED_HEADER)
+        );
+    }
+
+    #[test]
+    fn test_find_route_first_matched() {
+        let verifier = RequestSignatureVerifier::new(&[create_route()]);
+        assert!(verifier.find_route("/internal/orders").is_some());
+        assert!(verifier.find_route("/public/orders").is_none());
+    }
+
+    #[test]
+    fn test_find_route_by_normalized_path() {
+        let verifier = RequestSignatureVerifier::new(&[create_route()]);
+        for path in [
+            "//internal/orders",
+            "/internal/./orders",
+            "/public/../internal/orders",
+            "/public/..%2finternal/orders",
+            "/public/%2e%2e/internal/orders",
+            "/%69nternal/orders",
+            "/public\\..\\internal\\orders",
+        ] {
+            assert!(verifier.find_route(path).is_some(), "{}", path);
+        }
+        assert!(verifier.find_route("/internal/../public/orders").is_none());
+    }
+}
@@ -0,0 +1,581 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use anyhow::Error;
+use axum::{body::Bytes, response::Response};
+use botwaf_server::{
+    config::config::{
+        ResponseRewriteAction, ResponseRewritePatternKind, ResponseRewriteProperties, UpstreamProperties,
+    },
+    mgmt::apm::metrics::{BOTWAF_RESPONSE_REWRITES_TOTAL, BOTWAF_RESPONSE_REWRITE_ABANDONED_TOTAL},
+};
+use botwaf_types::modules::forward::access_event::ResponseRewriteSummary;
+use common_telemetry::warn;
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use hyper::{header, HeaderMap};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+lazy_static! {
+    // The 13-19 digits optionally separated by the space or dash, e.g: 4111 1111 1111 1111
+    static ref PAN_REGEX: Regex = Regex::new(r"(?-u)\b(?:\d[ -]?){12,18}\d\b").unwrap();
+    static ref EMAIL_REGEX: Regex =
+        Regex::new(r"(?-u)[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap();
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ResponseRewriteError {
+    #[error("The response is blocked by the rewrite pattern '{0}'")]
+    Blocked(String),
+}
+
+struct RewritePattern {
+    name: String,
+    kind: ResponseRewritePatternKind,
+    regex: Regex,
+    action: ResponseRewriteAction,
+}
+
+impl RewritePattern {
+    /// The replacement of the matched content, None if it's not the sensitive data (e.g: failed the Luhn check).
+    fn replace(&self, matched: &[u8]) -> Option<Vec<u8>> {
+        if self.kind == ResponseRewritePatternKind::PAN && !is_luhn_valid(matched) {
+            return None;
+        }
+        match self.action {
+            ResponseRewriteAction::REMOVE | ResponseRewriteAction::BLOCK => Some(Vec::new()),
+            ResponseRewriteAction::MASK => {
+                let keep_separators = self.kind == ResponseRewritePatternKind::PAN;
+                let masked = String::from_utf8_lossy(matched)
+                    .chars()
+                    .map(|c| {
+                        if keep_separators && (c == ' ' || c == '-') {
+                            c
+                        } else {
+                            '*'
+                        }
+                    })
+                    .collect::<String>();
+                Some(masked.into_bytes())
+            }
+        }
+    }
+}
+
+/// The Luhn (mod 10) checksum of the digits (the separators are ignored), see: ISO/IEC 7812-1
+fn is_luhn_valid(matched: &[u8]) -> bool {
+    let digits = matched
+        .iter()
+        .filter(|b| b.is_ascii_digit())
+        .map(|b| (b - b'0') as u32)
+        .collect::<Vec<_>>();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => *d,
+        })
+        .sum::<u32>();
+    sum % 10 == 0
+}
+
+/// The response body rewriter of the upstream, which masks (or removes) the sensitive data patterns streaming
+/// with a bounded overlap window, so that the patterns spanning the chunk boundaries are still caught without
+/// buffering the whole body.
+pub struct ResponseRewriter {
+    url_prefix: String,
+    content_types: Vec<String>,
+    patterns: Vec<RewritePattern>,
+    overlap_bytes: usize,
+    time_budget: Duration,
+}
+
+impl ResponseRewriter {
+    pub fn new(url_prefix: &str, config: &ResponseRewriteProperties) -> Arc<Self> {
+        let patterns = config
+            .patterns
+            .iter()
+            .filter_map(|p| {
+                let regex = match p.kind {
+                    ResponseRewritePatternKind::PAN => PAN_REGEX.clone(),
+                    ResponseRewritePatternKind::EMAIL => EMAIL_REGEX.clone(),
+                    // Notice: The regex was already validated on loading, see: ForwardProperties::validate_response_rewrites
+                    ResponseRewritePatternKind::REGEX => match Regex::new(p.regex.as_deref().unwrap_or_default()) {
+                        Ok(regex) => regex,
+                        Err(e) => {
+                            warn!(
+                                "Ignored the invalid response rewrite pattern '{}'. cause: {}",
+                                p.name, e
+                            );
+                            return None;
+                        }
+                    },
+                };
+                Some(RewritePattern {
+                    name: p.name.to_owned(),
+                    kind: p.kind,
+                    regex,
+                    action: p.action,
+                })
+            })
+            .collect();
+        Arc::new(ResponseRewriter {
+            url_prefix: url_prefix.to_owned(),
+            content_types: config.content_types.iter().map(|t| t.trim().to_lowercase()).collect(),
+            patterns,
+            overlap_bytes: config.overlap_bytes,
+            time_budget: *config.time_budget,
+        })
+    }
+
+    /// Whether the response is rewritten, i.e: the configured content types, and not encoded (e.g: gzip) which
+    /// is forwarded as is.
+    pub fn is_applicable(&self, headers: &HeaderMap) -> bool {
+        let encoded = headers
+            .get(header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| !v.trim().eq_ignore_ascii_case("identity"));
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_lowercase());
+        !self.patterns.is_empty() && !encoded && content_type.is_some_and(|t| self.content_types.contains(&t))
+    }
+
+    /// Rewrite the body chunks streaming, the outcome is summarized once the body is streamed (or dropped).
+    pub fn rewrite<S, E>(
+        self: &Arc<Self>,
+        body: S,
+    ) -> (
+        impl Stream<Item = Result<Bytes, Error>> + Send + 'static,
+        ResponseRewriteOutcome,
+    )
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let cursor = RewriteCursor {
+            rewriter: Arc::clone(self),
+            body: body.map_err(Error::new).boxed(),
+            carry: Vec::new(),
+            elapsed: Duration::ZERO,
+            abandoned: false,
+            finished: false,
+            summary: ResponseRewriteSummary::default(),
+            tx: Some(tx),
+        };
+        let stream = futures::stream::unfold(cursor, |mut cursor| async move {
+            if cursor.finished {
+                return None;
+            }
+            loop {
+                let (chunk, last) = match cursor.body.next().await {
+                    Some(Ok(chunk)) => (chunk, false),
+                    Some(Err(e)) => {
+                        cursor.finished = true;
+                        return Some((Err(e), cursor));
+                    }
+                    None => (Bytes::new(), true),
+                };
+                cursor.finished = last;
+                match cursor.feed(&chunk, last) {
+                    Ok(out) if out.is_empty() && !last => continue,
+                    Ok(out) if out.is_empty() => return None,
+                    Ok(out) => return Some((Ok(out), cursor)),
+                    Err(e) => {
+                        cursor.finished = true;
+                        return Some((Err(e.into()), cursor));
+                    }
+                }
+            }
+        });
+        (stream, ResponseRewriteOutcome(Arc::new(Mutex::new(Some(rx)))))
+    }
+
+    /// Rewrite the matches of the window, returns the output and the consumed bytes of the window, the tail of
+    /// the overlap bytes is held back to the next window unless the last.
+    fn rewrite_window(
+        &self,
+        buf: &[u8],
+        last: bool,
+        summary: &mut ResponseRewriteSummary,
+    ) -> Result<(Vec<u8>, usize), ResponseRewriteError> {
+        // The earliest (then the longest) match wins the overlapped matches of the patterns.
+        let mut matches = self
+            .patterns
+            .iter()
+            .flat_map(|p| p.regex.find_iter(buf).map(move |m| (m.start(), m.end(), p)))
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let safe = if last {
+            buf.len()
+        } else {
+            buf.len().saturating_sub(self.overlap_bytes)
+        };
+        let mut out = Vec::with_capacity(buf.len());
+        let mut cursor = 0;
+        for (start, end, pattern) in matches {
+            if start < cursor {
+                continue;
+            }
+            // The matches in the overlap may be continued in the next window.
+            if start >= safe {
+                break;
+            }
+            let Some(replacement) = pattern.replace(&buf[start..end]) else {
+                continue;
+            };
+            summary.record_hit(&pattern.name);
+            BOTWAF_RESPONSE_REWRITES_TOTAL
+                .with_label_values(&[self.url_prefix.as_str(), pattern.name.as_str()])
+                .inc();
+            if pattern.action == ResponseRewriteAction::BLOCK {
+                summary.blocked_by = Some(pattern.name.to_owned());
+                return Err(ResponseRewriteError::Blocked(pattern.name.to_owned()));
+            }
+            out.extend_from_slice(&buf[cursor..start]);
+            out.extend_from_slice(&replacement);
+            cursor = end;
+        }
+        let consumed = cursor.max(safe);
+        out.extend_from_slice(&buf[cursor..consumed]);
+        Ok((out, consumed))
+    }
+}
+
+struct RewriteCursor {
+    rewriter: Arc<ResponseRewriter>,
+    body: BoxStream<'static, Result<Bytes, Error>>,
+    // The held back tail of the previous chunks.
+    carry: Vec<u8>,
+    // The CPU time spent on rewriting.
+    elapsed: Duration,
+    abandoned: bool,
+    finished: bool,
+    summary: ResponseRewriteSummary,
+    tx: Option<oneshot::Sender<ResponseRewriteSummary>>,
+}
+
+impl RewriteCursor {
+    fn feed(&mut self, chunk: &[u8], last: bool) -> Result<Bytes, ResponseRewriteError> {
+        if !self.abandoned && self.elapsed >= self.rewriter.time_budget {
+            warn!(
+                "Abandoned the response rewriting of '{}' after {:?}, the rest is passed through.",
+                self.rewriter.url_prefix, self.elapsed
+            );
+            self.abandoned = true;
+            self.summary.abandoned = true;
+            BOTWAF_RESPONSE_REWRITE_ABANDONED_TOTAL
+                .with_label_values(&[self.rewriter.url_prefix.as_str()])
+                .inc();
+        }
+        let mut buf = std::mem::take(&mut self.carry);
+        buf.extend_from_slice(chunk);
+        if self.abandoned {
+            return Ok(Bytes::from(buf));
+        }
+
+        let started = Instant::now();
+        let result = self.rewriter.rewrite_window(&buf, last, &mut self.summary);
+        self.elapsed += started.elapsed();
+        let (out, consumed) = result.inspect_err(|e| warn!("{}, upstream: {}", e, self.rewriter.url_prefix))?;
+        self.carry = buf.split_off(consumed);
+        Ok(Bytes::from(out))
+    }
+}
+
+impl Drop for RewriteCursor {
+    // The outcome is also summarized if the client disconnected before the body streamed.
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(std::mem::take(&mut self.summary));
+        }
+    }
+}
+
+/// The pending outcome of the rewritten response, which is carried by the response extensions for recording
+/// the access event after the body is streamed.
+#[derive(Clone)]
+pub struct ResponseRewriteOutcome(Arc<Mutex<Option<oneshot::Receiver<ResponseRewriteSummary>>>>);
+
+impl ResponseRewriteOutcome {
+    pub fn take<B>(response: &mut Response<B>) -> Option<Self> {
+        response.extensions_mut().remove::<Self>()
+    }
+
+    pub async fn summary(self) -> ResponseRewriteSummary {
+        let rx = self.0.lock().unwrap().take();
+        match rx {
+            Some(rx) => rx.await.unwrap_or_default(),
+            None => ResponseRewriteSummary::default(),
+        }
+    }
+}
+
+pub struct ResponseRewriters {
+    rewriters: Vec<Arc<ResponseRewriter>>,
+}
+
+impl ResponseRewriters {
+    pub fn new(upstreams: &[UpstreamProperties]) -> Self {
+        let rewriters = upstreams
+            .iter()
+            .filter_map(|u| {
+                u.response_rewrite
+                    .as_ref()
+                    .map(|r| ResponseRewriter::new(&u.url_prefix, r))
+            })
+            .collect();
+        ResponseRewriters { rewriters }
+    }
+
+    pub fn find(&self, url: &str) -> Option<&Arc<ResponseRewriter>> {
+        self.rewriters
+            .iter()
+            .filter(|r| url.starts_with(r.url_prefix.as_str()))
+            .max_by_key(|r| r.url_prefix.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::config::{config::ResponseRewritePatternProperties, duration::DurationMillis};
+    use hyper::header::HeaderValue;
+
+    fn create_pattern(
+        name: &str,
+        kind: ResponseRewritePatternKind,
+        action: ResponseRewriteAction,
+    ) -> ResponseRewritePatternProperties {
+        ResponseRewritePatternProperties {
+            name: name.to_owned(),
+            kind,
+            regex: None,
+            action,
+        }
+    }
+
+    fn create_rewriter(patterns: Vec<ResponseRewritePatternProperties>, overlap_bytes: usize) -> Arc<ResponseRewriter> {
+        ResponseRewriter::new(
+            "http://orders.internal",
+            &ResponseRewriteProperties {
+                patterns,
+                overlap_bytes,
+                ..Default::default()
+            },
+        )
+    }
+
+    async fn rewrite_chunks(
+        rewriter: &Arc<ResponseRewriter>,
+        chunks: Vec<String>,
+    ) -> (String, Option<Error>, ResponseRewriteSummary) {
+        let body = futures::stream::iter(chunks.into_iter().map(|c| Ok::<_, std::io::Error>(Bytes::from(c))));
+        let (stream, outcome) = rewriter.rewrite(body);
+        let mut stream = Box::pin(stream);
+        let mut out = Vec::new();
+        let mut err = None;
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => out.extend_from_slice(&chunk),
+                Err(e) => err = Some(e),
+            }
+        }
+        drop(stream);
+        (String::from_utf8(out).unwrap(), err, outcome.summary().await)
+    }
+
+    #[tokio::test]
+    async fn test_pattern_straddling_chunk_boundary() {
+        let rewriter = create_rewriter(
+            vec![
+                create_pattern("pan", ResponseRewritePatternKind::PAN, ResponseRewriteAction::MASK),
+                create_pattern("email", ResponseRewritePatternKind::EMAIL, ResponseRewriteAction::MASK),
+            ],
+            32,
+        );
+        let padding = "x".repeat(100);
+        let chunks = vec![
+            format!("{} card: 4111 1111 ", padding),
+            format!("1111 1111, mail: jack@exam"),
+            format!("ple.com {}", padding),
+        ];
+
+        let (body, err, summary) = rewrite_chunks(&rewriter, chunks.to_owned()).await;
+        assert!(err.is_none());
+        assert_eq!(
+            body,
+            format!(
+                "{} card: **** **** **** ****, mail: **************** {}",
+                padding, padding
+            )
+        );
+        assert_eq!(body.len(), chunks.concat().len());
+        let hits = summary
+            .hits
+            .iter()
+            .map(|h| (h.pattern.as_str(), h.replacements))
+            .collect::<Vec<_>>();
+        assert_eq!(hits, vec![("pan", 1), ("email", 1)]);
+        assert!(!summary.abandoned);
+    }
+
+    #[tokio::test]
+    async fn test_luhn_false_positive_passed_through() {
+        let rewriter = create_rewriter(
+            vec![create_pattern(
+                "pan",
+                ResponseRewritePatternKind::PAN,
+                ResponseRewriteAction::MASK,
+            )],
+            32,
+        );
+        // The order id and the tracking number look like the PAN, but fail the Luhn check.
+        let text =
+            String::from(r#"{"order":"4111 1111 1111 1112","tracking":"1234567890123","card":"4242424242424242"}"#);
+        let (body, err, summary) = rewrite_chunks(&rewriter, vec![text]).await;
+        assert!(err.is_none());
+        assert_eq!(
+            body,
+            r#"{"order":"4111 1111 1111 1112","tracking":"1234567890123","card":"****************"}"#
+        );
+        assert_eq!(summary.hits.len(), 1);
+        assert_eq!(summary.hits[0].replacements, 1);
+
+        assert!(is_luhn_valid(b"4111-1111-1111-1111"));
+        assert!(!is_luhn_valid(b"4111-1111-1111-1112"));
+        // The valid checksum but too short.
+        assert!(!is_luhn_valid(b"18"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_regex_removed() {
+        let mut pattern = create_pattern(
+            "hostname",
+            ResponseRewritePatternKind::REGEX,
+            ResponseRewriteAction::REMOVE,
+        );
+        pattern.regex = Some(String::from(r"[a-z0-9-]+\.corp\.internal"));
+        let rewriter = create_rewriter(vec![pattern], 32);
+
+        let chunks = vec![
+            String::from("<a href=\"http://db-01.corp.int"),
+            String::from("ernal/\">db</a>"),
+        ];
+        let (body, _, summary) = rewrite_chunks(&rewriter, chunks).await;
+        assert_eq!(body, "<a href=\"http:///\">db</a>");
+        assert_eq!(summary.hits[0].pattern, "hostname");
+    }
+
+    #[tokio::test]
+    async fn test_block_aborts_response() {
+        let rewriter = create_rewriter(
+            vec![create_pattern(
+                "pan",
+                ResponseRewritePatternKind::PAN,
+                ResponseRewriteAction::BLOCK,
+            )],
+            8,
+        );
+        let chunks = vec![
+            format!("{} ", "x".repeat(64)),
+            String::from("card: 4111111111111111 end"),
+        ];
+        let (body, err, summary) = rewrite_chunks(&rewriter, chunks).await;
+        assert!(!body.contains("4111"));
+        let err = err.expect("Should be aborted");
+        assert_eq!(
+            err.downcast_ref::<ResponseRewriteError>(),
+            Some(&ResponseRewriteError::Blocked(String::from("pan")))
+        );
+        assert_eq!(summary.blocked_by, Some(String::from("pan")));
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_exceeded_passed_through() {
+        let rewriter = ResponseRewriter::new(
+            "http://orders.internal",
+            &ResponseRewriteProperties {
+                patterns: vec![create_pattern(
+                    "pan",
+                    ResponseRewritePatternKind::PAN,
+                    ResponseRewriteAction::MASK,
+                )],
+                time_budget: DurationMillis::from_millis(0),
+                ..Default::default()
+            },
+        );
+        let chunks = vec![String::from("card: 4111 1111 1111 1111")];
+        let (body, err, summary) = rewrite_chunks(&rewriter, chunks).await;
+        assert!(err.is_none());
+        assert_eq!(body, "card: 4111 1111 1111 1111");
+        assert!(summary.abandoned);
+        assert!(summary.hits.is_empty());
+    }
+
+    #[test]
+    fn test_is_applicable() {
+        let rewriter = create_rewriter(
+            vec![create_pattern(
+                "pan",
+                ResponseRewritePatternKind::PAN,
+                ResponseRewriteAction::MASK,
+            )],
+            32,
+        );
+        let mut headers = HeaderMap::new();
+        assert!(!rewriter.is_applicable(&headers));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("Application/JSON; charset=utf-8"),
+        );
+        assert!(rewriter.is_applicable(&headers));
+        // The encoded body is never rewritten.
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        assert!(!rewriter.is_applicable(&headers));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        headers.remove(header::CONTENT_ENCODING);
+        assert!(!rewriter.is_applicable(&headers));
+
+        let rewriters = ResponseRewriters::new(&[UpstreamProperties {
+            url_prefix: String::from("http://orders.internal"),
+            response_rewrite: Some(ResponseRewriteProperties::default()),
+            ..Default::default()
+        }]);
+        assert!(rewriters.find("http://orders.internal/api").is_some());
+        assert!(rewriters.find("http://users.internal/api").is_none());
+    }
+}
@@ -0,0 +1,705 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::{
+    event_stream::{access_decision, matches_filter},
+    replay::IAccessEventSource,
+};
+use crate::access_recorder::AccessEventRecorder;
+use anyhow::Error;
+use arrow::{
+    array::{ArrayRef, Int32Array, StringArray, UInt16Array, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use axum::body::Bytes;
+use botwaf_server::{
+    config::config::{self, DataProtectionProperties, EventExportProperties},
+    modules::privacy::data_protector::DataProtector,
+};
+use botwaf_types::modules::forward::{
+    access_event::BotwafAccessEvent,
+    event_export::{EventExportFormat, EventExportRequest},
+    event_stream::{AccessDecision, EventStreamRequest},
+};
+use common_audit_log::audit_log;
+use futures::Stream;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use parquet::{arrow::ArrowWriter, format::KeyValue};
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+/// The API key scope of the access events export, see: AuthProperties::api_keys
+pub const EVENTS_EXPORT_SCOPE: &str = "events:export";
+
+// The footer key-value metadata of the parquet export which is truncated at the max rows.
+const PARQUET_TRUNCATED_KEY: &str = "botwaf.truncated";
+
+const CSV_HEADER: &str =
+    "start_time,req_id,method,scheme,host,port,path,query,client_ip,resp_status_code,duration,rule_id,decision\n";
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: AccessEventExporter = {
+        let config = config::get_config();
+        AccessEventExporter::new(
+            &config.services.event_export,
+            &config.services.data_protection,
+            config.services.blocked_status_or(StatusCode::FORBIDDEN),
+        )
+    };
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum EventExportError {
+    #[error("The export of the access events is disabled.")]
+    Disabled,
+    #[error("Invalid export range, the 'from' must be before the 'to'.")]
+    InvalidRange,
+    #[error("The export range of {0}s exceeds the max window of {1}s, please split into the smaller ranges.")]
+    WindowTooLarge(u64, u64),
+    #[error("The export limit {0} exceeds the max rows {1} per request.")]
+    LimitTooLarge(usize, usize),
+}
+
+/// The streaming export of the historical access events of the audit trail, the events are read in chunks by
+/// the cursor only as fast as the client consumes the response body, and the current data protection rules are
+/// re-applied to each exported event, see: DataProtector::protect
+pub struct AccessEventExporter {
+    config: EventExportProperties,
+    protector: DataProtector,
+    blocked_status_code: i32,
+}
+
+impl AccessEventExporter {
+    pub fn new(
+        config: &EventExportProperties,
+        protection: &DataProtectionProperties,
+        blocked_status: StatusCode,
+    ) -> Self {
+        AccessEventExporter {
+            config: config.to_owned(),
+            protector: DataProtector::new(protection),
+            blocked_status_code: blocked_status.as_u16() as i32,
+        }
+    }
+
+    pub fn get() -> &'static AccessEventExporter {
+        &SINGLE_INSTANCE
+    }
+
+    pub fn events_file(&self) -> &str {
+        &self.config.events_file
+    }
+
+    /// Validate the range and the limit of the request, returns the max exported rows.
+    pub fn validate(&self, param: &EventExportRequest) -> Result<usize, EventExportError> {
+        if !self.config.enabled {
+            return Err(EventExportError::Disabled);
+        }
+        if param.from >= param.to {
+            return Err(EventExportError::InvalidRange);
+        }
+        let window = Duration::from_millis(param.to - param.from);
+        if window > *self.config.max_window_secs {
+            return Err(EventExportError::WindowTooLarge(
+                window.as_secs_f64().ceil() as u64,
+                self.config.max_window_secs.as_secs(),
+            ));
+        }
+        match param.limit {
+            Some(limit) if limit > self.config.max_rows => {
+                Err(EventExportError::LimitTooLarge(limit, self.config.max_rows))
+            }
+            Some(limit) => Ok(limit),
+            None => Ok(self.config.max_rows),
+        }
+    }
+
+    pub fn content_type(format: EventExportFormat) -> &'static str {
+        match format {
+            EventExportFormat::JSONL => "application/x-ndjson",
+            EventExportFormat::CSV => "text/csv; charset=utf-8",
+            EventExportFormat::PARQUET => "application/vnd.apache.parquet",
+        }
+    }
+
+    /// The chunks of the response body, each is the matched rows of the next chunk of the source events. The
+    /// export is audited with the rows once finished or the client disconnected.
+    pub fn export(
+        &self,
+        source: Arc<dyn IAccessEventSource>,
+        param: EventExportRequest,
+        max_rows: usize,
+        principal: String,
+    ) -> impl Stream<Item = Result<Bytes, Error>> + Send + 'static {
+        let cursor = ExportCursor {
+            source,
+            protector: self.protector.to_owned(),
+            filter: EventStreamRequest {
+                decision: param.decision,
+                path_prefix: param.path_prefix.to_owned(),
+                client_ip: param.client_ip.to_owned(),
+                rule: param.rule.to_owned(),
+            },
+            format: param.format.unwrap_or_default(),
+            parquet: None,
+            param,
+            blocked_status_code: self.blocked_status_code,
+            chunk_size: self.config.chunk_size.max(1),
+            max_rows,
+            principal,
+            last_event_id: 0,
+            rows: 0,
+            started: false,
+            finished: false,
+        };
+        futures::stream::unfold(cursor, |mut cursor| async move {
+            match cursor.next_chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), cursor)),
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::error!("Failed to export the access events. cause: {}", e);
+                    cursor.finished = true;
+                    Some((Err(e), cursor))
+                }
+            }
+        })
+    }
+}
+
+struct ExportCursor {
+    source: Arc<dyn IAccessEventSource>,
+    protector: DataProtector,
+    filter: EventStreamRequest,
+    format: EventExportFormat,
+    // The encoder of the PARQUET format, which is created with the first chunk.
+    parquet: Option<ParquetEncoder>,
+    param: EventExportRequest,
+    blocked_status_code: i32,
+    chunk_size: usize,
+    max_rows: usize,
+    principal: String,
+    last_event_id: i64,
+    rows: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl ExportCursor {
+    /// The matched rows of the next source events, None if all exported.
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        let mut buf = String::new();
+        let mut batch = Vec::new();
+        if !self.started {
+            self.started = true;
+            match self.format {
+                EventExportFormat::CSV => buf.push_str(CSV_HEADER),
+                EventExportFormat::PARQUET => self.parquet = Some(ParquetEncoder::new()?),
+                EventExportFormat::JSONL => {}
+            }
+        }
+        loop {
+            if self.finished {
+                return self.take_chunk(buf, batch);
+            }
+            let events = self.source.next_batch(self.last_event_id, self.chunk_size).await?;
+            if events.is_empty() {
+                self.finished = true;
+                continue;
+            }
+            let mut matched = 0;
+            for (event_id, event) in events {
+                self.last_event_id = event_id;
+                if !self.matches(&event) {
+                    continue;
+                }
+                // There are more matched events than the max rows.
+                if self.rows >= self.max_rows {
+                    self.write_truncated(&mut buf);
+                    self.finished = true;
+                    break;
+                }
+                let event = self.protector.protect(&event);
+                match self.format {
+                    EventExportFormat::PARQUET => batch.push(event),
+                    _ => self.write_row(&mut buf, &event),
+                }
+                self.rows += 1;
+                matched += 1;
+            }
+            if matched > 0 {
+                return self.take_chunk(buf, batch);
+            }
+        }
+    }
+
+    /// The bytes of the chunk, i.e: the text rows, or the parquet row group of the batch rows (and the footer
+    /// once finished) drained from the encoder, None if nothing remains.
+    fn take_chunk(&mut self, buf: String, batch: Vec<BotwafAccessEvent>) -> Result<Option<Bytes>, Error> {
+        let Some(encoder) = self.parquet.as_mut() else {
+            return Ok((!buf.is_empty()).then(|| Bytes::from(buf)));
+        };
+        if !batch.is_empty() {
+            encoder.write(&batch, self.blocked_status_code)?;
+        }
+        if self.finished {
+            encoder.close()?;
+        }
+        let bytes = encoder.drain();
+        Ok((!bytes.is_empty()).then(|| Bytes::from(bytes)))
+    }
+
+    fn matches(&self, event: &BotwafAccessEvent) -> bool {
+        event.start_time >= self.param.from
+            && event.start_time < self.param.to
+            && matches_filter(&self.filter, event, self.blocked_status_code)
+    }
+
+    fn write_row(&self, buf: &mut String, event: &BotwafAccessEvent) {
+        match self.format {
+            EventExportFormat::JSONL => {
+                buf.push_str(&AccessEventRecorder::to_audit_line(event));
+            }
+            // The parquet rows are encoded by the batch, see: ExportCursor::take_chunk
+            EventExportFormat::CSV | EventExportFormat::PARQUET => {
+                let fields = [
+                    Cow::Owned(event.start_time.to_string()),
+                    csv_field(event.req_id.as_deref()),
+                    csv_field(Some(&event.method)),
+                    csv_field(event.scheme.as_deref()),
+                    csv_field(event.host.as_deref()),
+                    Cow::Owned(event.port.map(|p| p.to_string()).unwrap_or_default()),
+                    csv_field(Some(&event.path)),
+                    csv_field(event.query.as_deref()),
+                    csv_field(event.client_ip.as_deref()),
+                    Cow::Owned(event.resp_status_code.map(|s| s.to_string()).unwrap_or_default()),
+                    Cow::Owned(event.duration.map(|d| d.to_string()).unwrap_or_default()),
+                    csv_field(event.rule_id.as_deref()),
+                    Cow::Borrowed(decision_label(event, self.blocked_status_code)),
+                ];
+                buf.push_str(&fields.join(","));
+            }
+        }
+        buf.push('\n');
+    }
+
+    fn write_truncated(&mut self, buf: &mut String) {
+        let message = format!(
+            "The export was truncated at the max rows {}, please narrow the range or filters.",
+            self.max_rows
+        );
+        match self.format {
+            EventExportFormat::JSONL => {
+                let marker = serde_json::json!({"truncated": true, "rows": self.rows, "message": message});
+                buf.push_str(&marker.to_string());
+            }
+            EventExportFormat::CSV => {
+                buf.push_str("# ");
+                buf.push_str(&message);
+            }
+            // The parquet has no trailing rows, the marker is written as the key-value metadata of the footer.
+            EventExportFormat::PARQUET => {
+                if let Some(encoder) = self.parquet.as_mut() {
+                    encoder.append_metadata(PARQUET_TRUNCATED_KEY, &message);
+                }
+                return;
+            }
+        }
+        buf.push('\n');
+    }
+}
+
+// Audit the export once finished or the client disconnected (i.e: the body stream dropped).
+impl Drop for ExportCursor {
+    fn drop(&mut self) {
+        audit_export(&self.principal, &format!("{:?}", self.param), self.rows, self.finished);
+    }
+}
+
+#[audit_log("[EVENTS][EXPORT] principal: {principal}, filter: {filter}, rows: {rows}, completed: {completed}")]
+fn audit_export(principal: &str, filter: &str, rows: usize, completed: bool) {}
+
+fn decision_label(event: &BotwafAccessEvent, blocked_status_code: i32) -> &'static str {
+    match access_decision(event, blocked_status_code) {
+        AccessDecision::BLOCK => "BLOCK",
+        AccessDecision::PASS => "PASS",
+    }
+}
+
+/// The in-memory sink of the parquet writer, which is drained after each row group, so that the export is never
+/// buffered entirely but only the current chunk.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The parquet encoder of the exported rows, each chunk is flushed as a row group, and the footer is written
+/// once closed.
+struct ParquetEncoder {
+    writer: Option<ArrowWriter<SharedBuffer>>,
+    buffer: SharedBuffer,
+}
+
+impl ParquetEncoder {
+    fn new() -> Result<Self, Error> {
+        let buffer = SharedBuffer::default();
+        let writer = ArrowWriter::try_new(buffer.clone(), parquet_schema(), None)?;
+        Ok(ParquetEncoder {
+            writer: Some(writer),
+            buffer,
+        })
+    }
+
+    fn write(&mut self, events: &[BotwafAccessEvent], blocked_status_code: i32) -> Result<(), Error> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write(&to_record_batch(events, blocked_status_code)?)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn append_metadata(&mut self, key: &str, value: &str) {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.append_key_value_metadata(KeyValue::new(key.to_owned(), value.to_owned()));
+        }
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn drain(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buffer.0.lock().unwrap())
+    }
+}
+
+/// The parquet columns, which are the same as the CSV header.
+fn parquet_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("start_time", DataType::UInt64, false),
+        Field::new("req_id", DataType::Utf8, true),
+        Field::new("method", DataType::Utf8, false),
+        Field::new("scheme", DataType::Utf8, true),
+        Field::new("host", DataType::Utf8, true),
+        Field::new("port", DataType::UInt16, true),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("query", DataType::Utf8, true),
+        Field::new("client_ip", DataType::Utf8, true),
+        Field::new("resp_status_code", DataType::Int32, true),
+        Field::new("duration", DataType::UInt64, true),
+        Field::new("rule_id", DataType::Utf8, true),
+        Field::new("decision", DataType::Utf8, false),
+    ]))
+}
+
+fn to_record_batch(events: &[BotwafAccessEvent], blocked_status_code: i32) -> Result<RecordBatch, ArrowError> {
+    let strings = |f: fn(&BotwafAccessEvent) -> Option<&str>| -> ArrayRef {
+        Arc::new(StringArray::from(events.iter().map(f).collect::<Vec<_>>()))
+    };
+    RecordBatch::try_new(
+        parquet_schema(),
+        vec![
+            Arc::new(UInt64Array::from_iter_values(events.iter().map(|e| e.start_time))),
+            strings(|e| e.req_id.as_deref()),
+            strings(|e| Some(e.method.as_str())),
+            strings(|e| e.scheme.as_deref()),
+            strings(|e| e.host.as_deref()),
+            Arc::new(UInt16Array::from_iter(events.iter().map(|e| e.port))),
+            strings(|e| Some(e.path.as_str())),
+            strings(|e| e.query.as_deref()),
+            strings(|e| e.client_ip.as_deref()),
+            Arc::new(Int32Array::from_iter(events.iter().map(|e| e.resp_status_code))),
+            Arc::new(UInt64Array::from_iter(events.iter().map(|e| e.duration))),
+            strings(|e| e.rule_id.as_deref()),
+            Arc::new(StringArray::from_iter_values(
+                events.iter().map(|e| decision_label(e, blocked_status_code)),
+            )),
+        ],
+    )
+}
+
+/// Quote the CSV field which contains the delimiter, quote or line breaks (RFC 4180).
+fn csv_field(value: Option<&str>) -> Cow<'_, str> {
+    match value {
+        None => Cow::Borrowed(""),
+        Some(value) if value.contains([',', '"', '\n', '\r']) => {
+            Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+        }
+        Some(value) => Cow::Borrowed(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use async_trait::async_trait;
+    use botwaf_server::config::duration::DurationSecs;
+    use futures::StreamExt;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The seeded events source, which counts the read batches.
+    struct MockAccessEventSource {
+        events: Vec<BotwafAccessEvent>,
+        batches: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl IAccessEventSource for MockAccessEventSource {
+        async fn next_batch(&self, after_event_id: i64, limit: usize) -> Result<Vec<(i64, BotwafAccessEvent)>, Error> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            Ok(self
+                .events
+                .iter()
+                .enumerate()
+                .map(|(i, event)| (i as i64 + 1, event.to_owned()))
+                .skip(after_event_id as usize)
+                .take(limit)
+                .collect())
+        }
+    }
+
+    fn event(start_time: u64, path: &str, rule_id: Option<&str>) -> BotwafAccessEvent {
+        BotwafAccessEvent {
+            method: "GET".to_owned(),
+            scheme: Some("http".to_owned()),
+            host: Some("example.com".to_owned()),
+            port: None,
+            headers: None,
+            path: path.to_owned(),
+            query: Some("user=jack&password=hunter2".to_owned()),
+            body: None,
+            req_id: None,
+            client_ip: Some("203.0.113.77".to_owned()),
+            start_time,
+            resp_status_code: Some(if rule_id.is_some() { 403 } else { 200 }),
+            resp_headers: None,
+            resp_body: None,
+            duration: Some(3),
+            synthetic: false,
+            rule_id: rule_id.map(|r| r.to_owned()),
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    /// The events of the seconds 0..10, the even seconds are blocked by the rule 942100.
+    fn create_test_source() -> (Arc<dyn IAccessEventSource>, Arc<AtomicUsize>) {
+        let batches = Arc::new(AtomicUsize::new(0));
+        let events = (0..10)
+            .map(|i| event(i * 1000, &format!("/p{}", i), (i % 2 == 0).then_some("942100")))
+            .collect();
+        let source = MockAccessEventSource {
+            events,
+            batches: batches.to_owned(),
+        };
+        (Arc::new(source), batches)
+    }
+
+    fn create_test_exporter(max_rows: usize) -> AccessEventExporter {
+        let config = EventExportProperties {
+            chunk_size: 2,
+            max_window_secs: DurationSecs::from_secs(3600),
+            max_rows,
+            ..EventExportProperties::default()
+        };
+        AccessEventExporter::new(&config, &DataProtectionProperties::default(), StatusCode::FORBIDDEN)
+    }
+
+    fn create_test_request(from: u64, to: u64) -> EventExportRequest {
+        EventExportRequest {
+            from,
+            to,
+            ..EventExportRequest::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_window_and_limit() {
+        let exporter = create_test_exporter(100);
+        assert_eq!(exporter.validate(&create_test_request(0, 1000)), Ok(100));
+        assert_eq!(
+            exporter.validate(&create_test_request(1000, 1000)),
+            Err(EventExportError::InvalidRange)
+        );
+        assert_eq!(
+            exporter.validate(&create_test_request(0, 7_200_000)),
+            Err(EventExportError::WindowTooLarge(7200, 3600))
+        );
+        let mut param = create_test_request(0, 1000);
+        param.limit = Some(10);
+        assert_eq!(exporter.validate(&param), Ok(10));
+        param.limit = Some(101);
+        assert_eq!(
+            exporter.validate(&param),
+            Err(EventExportError::LimitTooLarge(101, 100))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_chunks_lazily() {
+        let exporter = create_test_exporter(100);
+        let (source, batches) = create_test_source();
+        // The seconds [2, 8) and blocked, i.e: the events 2, 4, 6.
+        let mut param = create_test_request(2000, 8000);
+        param.decision = Some(AccessDecision::BLOCK);
+        let mut stream = Box::pin(exporter.export(source, param, 100, "warehouse".to_owned()));
+
+        // Only the first chunk (the events 1 and 2, with the first matched) was read, never the whole source.
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(batches.load(Ordering::SeqCst), 2);
+        let first: BotwafAccessEvent = serde_json::from_slice(&first).unwrap();
+        assert_eq!(first.path, "/p2");
+        // The data protection rules are re-applied.
+        assert_eq!(first.query.as_deref(), Some("user=jack&password=******"));
+        assert_eq!(first.client_ip.as_deref(), Some("203.0.113.0"));
+
+        let rest: Vec<Bytes> = stream.map(|chunk| chunk.unwrap()).collect().await;
+        let paths: Vec<String> = rest
+            .iter()
+            .map(|chunk| serde_json::from_slice::<BotwafAccessEvent>(chunk).unwrap().path)
+            .collect();
+        assert_eq!(paths, vec!["/p4", "/p6"]);
+        assert_eq!(batches.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_export_truncated_at_max_rows() {
+        let exporter = create_test_exporter(100);
+        let (source, _) = create_test_source();
+        let mut param = create_test_request(0, 10_000);
+        param.format = Some(EventExportFormat::CSV);
+        let body: Vec<Bytes> = exporter
+            .export(source, param, 3, "warehouse".to_owned())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        let body = String::from_utf8(body.concat()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], CSV_HEADER.trim_end());
+        assert_eq!(
+            lines[1],
+            "0,,GET,http,example.com,,/p0,user=jack&password=******,203.0.113.0,403,3,942100,BLOCK"
+        );
+        assert!(lines[3].starts_with("2000,"));
+        assert!(lines[4].starts_with("# The export was truncated at the max rows 3"));
+    }
+
+    #[tokio::test]
+    async fn test_export_csv_matches_seeded_rows() {
+        let exporter = create_test_exporter(100);
+        let (source, _) = create_test_source();
+        let mut param = create_test_request(1000, 4000);
+        param.format = Some(EventExportFormat::CSV);
+        let body: Vec<Bytes> = exporter
+            .export(source, param, 100, "warehouse".to_owned())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(
+            String::from_utf8(body.concat()).unwrap(),
+            [
+                CSV_HEADER.trim_end(),
+                "1000,,GET,http,example.com,,/p1,user=jack&password=******,203.0.113.0,200,3,,PASS",
+                "2000,,GET,http,example.com,,/p2,user=jack&password=******,203.0.113.0,403,3,942100,BLOCK",
+                "3000,,GET,http,example.com,,/p3,user=jack&password=******,203.0.113.0,200,3,,PASS",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_parquet_row_groups() {
+        let exporter = create_test_exporter(100);
+        let (source, _) = create_test_source();
+        let mut param = create_test_request(0, 10_000);
+        param.format = Some(EventExportFormat::PARQUET);
+        let body: Vec<Bytes> = exporter
+            .export(source, param, 5, "warehouse".to_owned())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(body.concat())).unwrap();
+        // The chunks of the events [0, 2), [2, 4) and [4, 5), each is a row group.
+        assert_eq!(reader.metadata().num_row_groups(), 3);
+        let truncated = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == PARQUET_TRUNCATED_KEY))
+            .and_then(|kv| kv.value.to_owned());
+        assert!(truncated
+            .unwrap()
+            .starts_with("The export was truncated at the max rows 5"));
+
+        let batches: Vec<RecordBatch> = reader.build().unwrap().map(|batch| batch.unwrap()).collect();
+        let column = |name: &str| -> Vec<String> {
+            batches
+                .iter()
+                .flat_map(|batch| {
+                    let array = batch
+                        .column_by_name(name)
+                        .unwrap()
+                        .as_any()
+                        .downcast_ref::<StringArray>()
+                        .unwrap();
+                    array
+                        .iter()
+                        .map(|v| v.unwrap_or_default().to_owned())
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+        assert_eq!(column("path"), vec!["/p0", "/p1", "/p2", "/p3", "/p4"]);
+        assert_eq!(column("decision"), vec!["BLOCK", "PASS", "BLOCK", "PASS", "BLOCK"]);
+        assert_eq!(column("query")[0], "user=jack&password=******");
+    }
+
+    #[test]
+    fn test_csv_field_quoted() {
+        assert_eq!(csv_field(None), "");
+        assert_eq!(csv_field(Some("/a")), "/a");
+        assert_eq!(csv_field(Some("a,\"b\"")), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::{
+    event_export::{AccessEventExporter, EventExportError, EVENTS_EXPORT_SCOPE},
+    replay::AccessEventsFileSource,
+};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use botwaf_server::{
+    context::state::BotwafState,
+    util::{auths, web::CurrentUser},
+};
+use botwaf_types::{
+    modules::forward::event_export::{EventExportFormat, EventExportRequest},
+    RespBase,
+};
+use hyper::{header, StatusCode};
+use std::sync::Arc;
+use tower_http::compression::CompressionLayer;
+
+pub const EVENTS_EXPORT_URI: &str = "/api/v1/events/export";
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(handle_events_export), components(schemas(EventExportFormat)))]
+pub struct EventExportApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    // The gzip is negotiated by the Accept-Encoding, and compressed chunk by chunk as streaming.
+    Router::new()
+        .route(EVENTS_EXPORT_URI, get(handle_events_export))
+        .route_layer(CompressionLayer::new().gzip(true))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events/export",
+    params(EventExportRequest),
+    responses(
+        (status = 200, description = "The streaming export of the (PII scrubbed) access events as the JSON lines, CSV or Parquet, the trailing marker (or the footer metadata 'botwaf.truncated' of the Parquet) is appended if truncated at the max rows.", content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid range, or exceeded the max window or the max rows.", body = RespBase),
+        (status = 403, description = "Requires the admin, or the API key with the scope 'events:export'.", body = RespBase),
+    ),
+    tag = "Event"
+)]
+async fn handle_events_export(
+    State(state): State<BotwafState>,
+    CurrentUser(claims): CurrentUser,
+    Query(param): Query<EventExportRequest>,
+) -> impl IntoResponse {
+    if !auths::is_admin(&state.config, &claims) && !auths::has_scope(&claims, EVENTS_EXPORT_SCOPE) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(RespBase::errmsg(&format!(
+                "Forbidden, requires the admin, or the API key with the scope '{}'.",
+                EVENTS_EXPORT_SCOPE
+            ))),
+        )
+            .into_response();
+    }
+    let exporter = AccessEventExporter::get();
+    let max_rows = match exporter.validate(&param) {
+        Ok(max_rows) => max_rows,
+        Err(e @ EventExportError::Disabled) => {
+            return (StatusCode::NOT_FOUND, Json(RespBase::errmsg(&e.to_string()))).into_response()
+        }
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(RespBase::errmsg(&e.to_string()))).into_response(),
+    };
+    let principal = claims.uname;
+    let format = param.format.unwrap_or_default();
+    let source = Arc::new(AccessEventsFileSource::new(exporter.events_file()));
+    let body = Body::from_stream(exporter.export(source, param, max_rows, principal));
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, AccessEventExporter::content_type(format)),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,393 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::access_recorder::AccessEventRecorder;
+use axum::response::sse::{Event, Sse};
+use botwaf_server::{
+    config::config::{self, EventStreamProperties},
+    mgmt::apm::metrics::BOTWAF_EVENT_STREAM_CLIENTS,
+};
+use botwaf_types::modules::forward::{
+    access_event::BotwafAccessEvent,
+    event_stream::{AccessDecision, EventStreamRequest},
+};
+use futures::Stream;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{Instant, Interval, MissedTickBehavior},
+};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: AccessEventStream = AccessEventStream::new(&config::get_config().services.event_stream);
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum EventStreamError {
+    #[error("The live tail of the access events is disabled.")]
+    Disabled,
+    #[error("Too many live tail clients, the max is {0}.")]
+    TooManyClients(usize),
+}
+
+/// The live tail of the access events, the clients subscribe to the in-process access events bus which
+/// only ever carries the protected (PII scrubbed) events, see: AccessEventRecorder
+pub struct AccessEventStream {
+    config: EventStreamProperties,
+    clients: Arc<AtomicUsize>,
+}
+
+impl AccessEventStream {
+    pub fn new(config: &EventStreamProperties) -> Self {
+        AccessEventStream {
+            config: config.to_owned(),
+            clients: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn get() -> &'static AccessEventStream {
+        &SINGLE_INSTANCE
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::SeqCst)
+    }
+
+    /// Connect a client with the filters, which is rejected if exceeded the max clients.
+    pub fn connect(&self, filter: EventStreamRequest) -> Result<EventStreamClient, EventStreamError> {
+        if !self.config.enabled {
+            return Err(EventStreamError::Disabled);
+        }
+        let max_clients = self.config.max_clients;
+        self.clients
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max_clients).then_some(n + 1)
+            })
+            .map_err(|_| EventStreamError::TooManyClients(max_clients))?;
+        BOTWAF_EVENT_STREAM_CLIENTS.inc();
+
+        let heartbeat_period = (*self.config.heartbeat_secs).max(Duration::from_secs(1));
+        let mut heartbeat = tokio::time::interval_at(Instant::now() + heartbeat_period, heartbeat_period);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(EventStreamClient {
+            receiver: AccessEventRecorder::subscribe(),
+            filter,
+            blocked_status_code: config::get_config()
+                .services
+                .blocked_status_or(StatusCode::FORBIDDEN)
+                .as_u16() as i32,
+            max_events_per_sec: self.config.max_events_per_sec,
+            window: (Instant::now(), 0),
+            dropped: 0,
+            heartbeat,
+            deadline: Instant::now() + *self.config.max_duration_secs,
+            _guard: ClientGuard(self.clients.to_owned()),
+        })
+    }
+}
+
+// Release the client slot once the connection is closed (i.e: the stream dropped).
+struct ClientGuard(Arc<AtomicUsize>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+        BOTWAF_EVENT_STREAM_CLIENTS.dec();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventStreamItem {
+    // The JSON line of the matched access event.
+    Event(String),
+    // The number of the events dropped by the rate limiting or lagging since the last heartbeat.
+    Heartbeat(u64),
+}
+
+/// The connected client of the live tail, which is closed after the max duration.
+pub struct EventStreamClient {
+    receiver: broadcast::Receiver<Arc<BotwafAccessEvent>>,
+    filter: EventStreamRequest,
+    blocked_status_code: i32,
+    max_events_per_sec: u32,
+    // The (start, count) of the current rate limiting window.
+    window: (Instant, u32),
+    dropped: u64,
+    heartbeat: Interval,
+    deadline: Instant,
+    _guard: ClientGuard,
+}
+
+impl EventStreamClient {
+    /// The next matched event or heartbeat, None if expired or the events bus is closed.
+    pub async fn next(&mut self) -> Option<EventStreamItem> {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(self.deadline) => return None,
+                _ = self.heartbeat.tick() => {
+                    return Some(EventStreamItem::Heartbeat(std::mem::take(&mut self.dropped)));
+                }
+                received = self.receiver.recv() => match received {
+                    Ok(event) => {
+                        if !self.matches(&event) {
+                            continue;
+                        }
+                        if !self.try_acquire() {
+                            self.dropped += 1;
+                            continue;
+                        }
+                        return Some(EventStreamItem::Event(AccessEventRecorder::to_audit_line(&event)));
+                    }
+                    Err(RecvError::Lagged(skipped)) => self.dropped += skipped,
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+
+    /// The Server-Sent Events of the client, the heartbeats are sent as the comments.
+    pub fn into_sse(self) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        Sse::new(futures::stream::unfold(self, |mut client| async move {
+            client.next().await.map(|item| {
+                let event = match item {
+                    EventStreamItem::Event(line) => Event::default().data(line),
+                    EventStreamItem::Heartbeat(dropped) => {
+                        Event::default().comment(format!("heartbeat dropped={}", dropped))
+                    }
+                };
+                (Ok(event), client)
+            })
+        }))
+    }
+
+    fn matches(&self, event: &BotwafAccessEvent) -> bool {
+        matches_filter(&self.filter, event, self.blocked_status_code)
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window.0) >= Duration::from_secs(1) {
+            self.window = (now, 0);
+        }
+        if self.window.1 >= self.max_events_per_sec {
+            return false;
+        }
+        self.window.1 += 1;
+        true
+    }
+}
+
+/// The decision is derived from the matched rule and the blocked status code of the event.
+pub fn access_decision(event: &BotwafAccessEvent, blocked_status_code: i32) -> AccessDecision {
+    if event.rule_id.is_some() || event.resp_status_code == Some(blocked_status_code) {
+        AccessDecision::BLOCK
+    } else {
+        AccessDecision::PASS
+    }
+}
+
+/// Whether the (not synthetic) event matches all the specified filters, e.g: the live tail or the export.
+pub fn matches_filter(filter: &EventStreamRequest, event: &BotwafAccessEvent, blocked_status_code: i32) -> bool {
+    if event.synthetic {
+        return false;
+    }
+    if let Some(decision) = filter.decision {
+        if access_decision(event, blocked_status_code) != decision {
+            return false;
+        }
+    }
+    if let Some(path_prefix) = &filter.path_prefix {
+        if !event.path.starts_with(path_prefix.as_str()) {
+            return false;
+        }
+    }
+    if let Some(client_ip) = &filter.client_ip {
+        if event.client_ip.as_deref() != Some(client_ip.as_str()) {
+            return false;
+        }
+    }
+    if let Some(rule) = &filter.rule {
+        if event.rule_id.as_deref() != Some(rule.as_str()) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Query,
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
+    use botwaf_server::config::config::DataProtectionProperties;
+    use botwaf_server::config::duration::DurationSecs;
+    use botwaf_server::context::test_support::TestIncomingBuilder;
+    use tokio::net::TcpListener;
+
+    // The SSE endpoint without the authentication, see: event_stream_router::handle_events_stream
+    async fn spawn_stream_endpoint(stream: Arc<AccessEventStream>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/stream",
+            get(move |Query(param): Query<EventStreamRequest>| {
+                let stream = stream.clone();
+                async move {
+                    match stream.connect(param) {
+                        Ok(client) => client.into_sse().into_response(),
+                        Err(_) => StatusCode::TOO_MANY_REQUESTS.into_response(),
+                    }
+                }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/stream", addr)
+    }
+
+    async fn record(path: &str, status: StatusCode, rule_id: Option<&str>) {
+        let incoming = TestIncomingBuilder::new("GET", path)
+            .query("password=hunter2")
+            .client_ip("203.0.113.7")
+            .build();
+        AccessEventRecorder::new(&DataProtectionProperties::default(), 0)
+            .record_with_rule(&incoming, 0, status, rule_id.map(|r| r.to_owned()))
+            .await;
+    }
+
+    // Read the SSE data lines until the sentinel path is received.
+    async fn read_until(resp: &mut reqwest::Response, sentinel: &str) -> Vec<BotwafAccessEvent> {
+        let mut buffer = String::new();
+        while !buffer.contains(sentinel) {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), resp.chunk())
+                .await
+                .expect("Timeout to receive the events")
+                .unwrap()
+                .expect("The stream is closed");
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        buffer
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_filters_and_max_clients() {
+        let stream = Arc::new(AccessEventStream::new(&EventStreamProperties {
+            max_clients: 2,
+            ..EventStreamProperties::default()
+        }));
+        let url = spawn_stream_endpoint(stream.clone()).await;
+        let client = reqwest::Client::new();
+
+        let mut blocked = client
+            .get(format!("{}?decision=BLOCK&path-prefix=/ut-stream-a", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(blocked.status(), StatusCode::OK);
+        let mut other = client
+            .get(format!("{}?path-prefix=/ut-stream-b", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+        assert_eq!(stream.clients(), 2);
+
+        // The client cap is reached.
+        let rejected = client.get(&url).send().await.unwrap();
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        record("/ut-stream-a/login", StatusCode::FORBIDDEN, Some("942100")).await;
+        record("/ut-stream-a/home", StatusCode::OK, None).await;
+        record("/ut-stream-b/home", StatusCode::OK, None).await;
+        record("/ut-stream-a/end", StatusCode::FORBIDDEN, None).await;
+        record("/ut-stream-b/end", StatusCode::OK, None).await;
+
+        let events = read_until(&mut blocked, "/ut-stream-a/end").await;
+        let paths = events.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/ut-stream-a/login", "/ut-stream-a/end"]);
+        // The events are the protected representation.
+        assert!(events
+            .iter()
+            .all(|e| !e.query.to_owned().unwrap_or_default().contains("hunter2")));
+
+        let events = read_until(&mut other, "/ut-stream-b/end").await;
+        let paths = events.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/ut-stream-b/home", "/ut-stream-b/end"]);
+
+        // The slot is released once disconnected.
+        drop(blocked);
+        for _ in 0..50 {
+            if stream.clients() < 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(stream.clients(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_rate_limited_and_expired() {
+        let stream = AccessEventStream::new(&EventStreamProperties {
+            max_events_per_sec: 1,
+            heartbeat_secs: DurationSecs::from_secs(1),
+            max_duration_secs: DurationSecs::from_secs(2),
+            ..EventStreamProperties::default()
+        });
+        let mut client = stream
+            .connect(EventStreamRequest {
+                path_prefix: Some(String::from("/ut-stream-rate")),
+                ..EventStreamRequest::default()
+            })
+            .unwrap();
+        record("/ut-stream-rate/1", StatusCode::OK, None).await;
+        record("/ut-stream-rate/2", StatusCode::OK, None).await;
+
+        match client.next().await {
+            Some(EventStreamItem::Event(line)) => assert!(line.contains("/ut-stream-rate/1")),
+            item => panic!("Unexpected item: {:?}", item),
+        }
+        // The second event within the second is dropped, and reported by the heartbeat.
+        assert_eq!(client.next().await, Some(EventStreamItem::Heartbeat(1)));
+        // Disconnected after the max duration.
+        let mut items = Vec::new();
+        while let Some(item) = client.next().await {
+            items.push(item);
+        }
+        assert!(items.iter().all(|item| matches!(item, EventStreamItem::Heartbeat(0))));
+        drop(client);
+        assert_eq!(stream.clients(), 0);
+    }
+}
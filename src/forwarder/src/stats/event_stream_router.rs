@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::event_stream::{AccessEventStream, EventStreamError};
+use axum::{extract::Query, response::IntoResponse, routing::get, Json, Router};
+use botwaf_server::{context::state::BotwafState, util::web::RequireOperator};
+use botwaf_types::{
+    modules::forward::event_stream::{AccessDecision, EventStreamRequest},
+    RespBase,
+};
+use common_audit_log::audit_log;
+use hyper::StatusCode;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(handle_events_stream), components(schemas(AccessDecision)))]
+pub struct EventStreamApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/events/stream", get(handle_events_stream))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events/stream",
+    params(EventStreamRequest),
+    responses(
+        (status = 200, description = "The live tail of the protected access events as the Server-Sent Events of JSON lines.", content_type = "text/event-stream"),
+        (status = 429, description = "Too many connected clients.", body = RespBase),
+    ),
+    tag = "Event"
+)]
+async fn handle_events_stream(
+    RequireOperator(operator): RequireOperator,
+    Query(param): Query<EventStreamRequest>,
+) -> impl IntoResponse {
+    let filter = format!("{:?}", param);
+    match AccessEventStream::get().connect(param) {
+        Ok(client) => {
+            audit_stream_connected(&operator.uname, &filter);
+            client.into_sse().into_response()
+        }
+        Err(e @ EventStreamError::TooManyClients(_)) => {
+            (StatusCode::TOO_MANY_REQUESTS, Json(RespBase::errmsg(&e.to_string()))).into_response()
+        }
+        Err(e @ EventStreamError::Disabled) => {
+            (StatusCode::NOT_FOUND, Json(RespBase::errmsg(&e.to_string()))).into_response()
+        }
+    }
+}
+
+#[audit_log("[EVENTS][STREAM] principal: {principal}, filter: {filter}")]
+fn audit_stream_connected(principal: &str, filter: &str) {}
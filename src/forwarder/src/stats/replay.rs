@@ -0,0 +1,399 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::forwarder_base::{BotwafDecision, BotwafForwarderManager};
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_server::{config::config::ReplayProperties, modules::modsec::replay_result::ReplayResultManager};
+use botwaf_types::{
+    modules::{
+        forward::{access_event::BotwafAccessEvent, event_stream::AccessDecision},
+        modsec::replay::{ReplayEventsProgress, ReplayResult},
+    },
+    BaseBean,
+};
+use common_telemetry::info;
+use hyper::StatusCode;
+use modsecurity::{ModSecurity, Rules};
+use std::{
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    sync::Semaphore,
+    time::MissedTickBehavior,
+};
+
+/// The prefix of the access events lines of the audit trail, see: crate::access_writer::AuditLogAccessEventSink
+const ACCESS_EVENT_LINE_PREFIX: &str = "[Botwaf] [AccessEvent] - ";
+
+/// The historical access events in the id order.
+#[async_trait]
+pub trait IAccessEventSource: Send + Sync {
+    /// The events after the event id, at most the limit.
+    async fn next_batch(&self, after_event_id: i64, limit: usize) -> Result<Vec<(i64, BotwafAccessEvent)>, Error>;
+}
+
+/// The JSON lines of the access events audit trail, the (1-based) line number is the event id, and
+/// the lines of the other logs are skipped.
+pub struct AccessEventsFileSource {
+    path: PathBuf,
+    // The (event id, byte offset) of the last read line, which avoids rescanning the file from the
+    // beginning for the sequential batches.
+    cursor: Mutex<(i64, u64)>,
+}
+
+impl AccessEventsFileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AccessEventsFileSource {
+            path: path.into(),
+            cursor: Mutex::new((0, 0)),
+        }
+    }
+
+    fn parse_line(line: &str) -> Option<BotwafAccessEvent> {
+        let json = match line.find(ACCESS_EVENT_LINE_PREFIX) {
+            Some(pos) => &line[pos + ACCESS_EVENT_LINE_PREFIX.len()..],
+            None => line,
+        };
+        serde_json::from_str(json.trim()).ok()
+    }
+}
+
+#[async_trait]
+impl IAccessEventSource for AccessEventsFileSource {
+    async fn next_batch(&self, after_event_id: i64, limit: usize) -> Result<Vec<(i64, BotwafAccessEvent)>, Error> {
+        let (mut event_id, mut offset) = match *self.cursor.lock().unwrap() {
+            cursor if cursor.0 <= after_event_id => cursor,
+            _ => (0, 0),
+        };
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut reader = BufReader::new(file);
+
+        let mut events = Vec::new();
+        let mut line = String::new();
+        while events.len() < limit {
+            line.clear();
+            let read = reader.read_line(&mut line).await?;
+            // Notice: The partial (being written) last line is read again by the next batch.
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            event_id += 1;
+            offset += read as u64;
+            if event_id <= after_event_id {
+                continue;
+            }
+            if let Some(event) = Self::parse_line(&line) {
+                events.push((event_id, event));
+            }
+        }
+        *self.cursor.lock().unwrap() = (event_id, offset);
+        Ok(events)
+    }
+}
+
+/// Re-evaluate the historical access events against the current rules with the shared evaluation
+/// function, the results are recorded in the event id order as the checkpoint of the job.
+pub struct AccessEventsReplayJob {
+    config: ReplayProperties,
+    source: Arc<dyn IAccessEventSource>,
+    results: Arc<ReplayResultManager>,
+    modsec_engine: Arc<ModSecurity>,
+    modsec_rules: Arc<Rules>,
+    blocked_status_code: i32,
+    // The low priority permits of the evaluations, which never take the permits of the proxy
+    // transactions, see: crate::modsec_limiter::ModSecLimiter
+    permits: Arc<Semaphore>,
+}
+
+impl AccessEventsReplayJob {
+    pub fn new(
+        config: &ReplayProperties,
+        source: Arc<dyn IAccessEventSource>,
+        results: Arc<ReplayResultManager>,
+        modsec_engine: Arc<ModSecurity>,
+        modsec_rules: Arc<Rules>,
+        blocked_status: StatusCode,
+    ) -> Self {
+        AccessEventsReplayJob {
+            config: config.to_owned(),
+            source,
+            results,
+            modsec_engine,
+            modsec_rules,
+            blocked_status_code: blocked_status.as_u16() as i32,
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+        }
+    }
+
+    pub fn new_job_id() -> String {
+        format!("replay-{}", chrono::Utc::now().format("%Y%m%d%H%M%S"))
+    }
+
+    /// Replay the events after the checkpoint of the job, at most the limit events of this run.
+    pub async fn run(&self, job_id: &str, limit: Option<u64>) -> Result<ReplayEventsProgress, Error> {
+        let mut last_event_id = self.results.checkpoint(job_id).await?;
+        let mut replayed = 0u64;
+        info!(
+            "Replaying the access events of the job '{}' after {:?}",
+            job_id, last_event_id
+        );
+
+        let mut pacer = tokio::time::interval(Duration::from_secs(1) / self.config.rate_per_sec.max(1));
+        pacer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let batch_size = match limit {
+                Some(limit) if replayed >= limit => break,
+                Some(limit) => (limit - replayed).min(self.config.batch_size.max(1) as u64) as usize,
+                None => self.config.batch_size.max(1),
+            };
+            let events = self.source.next_batch(last_event_id.unwrap_or(0), batch_size).await?;
+            if events.is_empty() {
+                break;
+            }
+
+            let mut evaluations = Vec::with_capacity(events.len());
+            for (event_id, event) in events {
+                pacer.tick().await;
+                let permit = self.permits.to_owned().acquire_owned().await?;
+                let engine = self.modsec_engine.to_owned();
+                let rules = self.modsec_rules.to_owned();
+                let incoming = event.to_incoming();
+                let evaluation = tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    BotwafForwarderManager::evaluate(&engine, &rules, &incoming)
+                });
+                evaluations.push((event_id, event, evaluation));
+            }
+            // Notice: Recorded in the event id order, so that the checkpoint never skips the unrecorded events.
+            for (event_id, event, evaluation) in evaluations {
+                let decision = evaluation.await?;
+                self.results
+                    .record(self.to_result(job_id, event_id, &event, &decision))
+                    .await?;
+                last_event_id = Some(event_id);
+                replayed += 1;
+            }
+        }
+        info!(
+            "Replayed {} access events of the job '{}', checkpoint: {:?}",
+            replayed, job_id, last_event_id
+        );
+
+        Ok(ReplayEventsProgress {
+            job_id: job_id.to_owned(),
+            replayed,
+            last_event_id,
+        })
+    }
+
+    fn to_result(
+        &self,
+        job_id: &str,
+        event_id: i64,
+        event: &BotwafAccessEvent,
+        decision: &BotwafDecision,
+    ) -> ReplayResult {
+        // The original decision is derived the same as the live tail, see: crate::stats::event_stream
+        let old_decision = if event.rule_id.is_some() || event.resp_status_code == Some(self.blocked_status_code) {
+            AccessDecision::BLOCK
+        } else {
+            AccessDecision::PASS
+        };
+        let (new_decision, new_rule_ids) = match decision {
+            BotwafDecision::BLOCK { rule_id, .. } if event.rule_id.as_ref() != Some(rule_id) => {
+                (AccessDecision::BLOCK, Some(rule_id.to_owned()))
+            }
+            BotwafDecision::BLOCK { .. } => (AccessDecision::BLOCK, None),
+            BotwafDecision::PASS => (AccessDecision::PASS, None),
+        };
+        ReplayResult {
+            base: BaseBean::new_with_id(None),
+            job_id: Some(job_id.to_owned()),
+            event_id: Some(event_id),
+            old_decision: Some(old_decision),
+            old_rule_id: event.rule_id.to_owned(),
+            new_decision: Some(new_decision),
+            new_rule_ids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_server::{
+        config::config::SqliteAppDBProperties,
+        modules::modsec::{
+            body_processor::BODY_PROCESSOR_RULES, store::replay_results_sqlite::ReplayResultSQLiteRepository,
+        },
+    };
+    use std::{env, fs, io::Write};
+
+    const RULES: &str = r#"
+SecRuleEngine On
+SecRule ARGS "@detectSQLi" "id:3201,phase:2,deny,status:403,msg:'SQLi'"
+"#;
+
+    fn event(path: &str, query: Option<&str>, rule_id: Option<&str>) -> BotwafAccessEvent {
+        BotwafAccessEvent {
+            method: "GET".to_owned(),
+            scheme: Some("http".to_owned()),
+            host: Some("example.com".to_owned()),
+            port: None,
+            headers: None,
+            path: path.to_owned(),
+            query: query.map(|q| q.to_owned()),
+            body: None,
+            req_id: None,
+            client_ip: Some("10.0.0.1".to_owned()),
+            start_time: 0,
+            resp_status_code: Some(if rule_id.is_some() { 403 } else { 200 }),
+            resp_headers: None,
+            resp_body: None,
+            duration: None,
+            synthetic: false,
+            rule_id: rule_id.map(|r| r.to_owned()),
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    /// The seeded audit trail, the events 2 and 4 are SQLi which are matched by the rule 3201, and the
+    /// event 5 was blocked by the since removed rule 9999.
+    fn seed_events(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("botwaf-ut-replay-{}-{}.log", name, std::process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        let events = [
+            event("/index.html", None, None),
+            event("/products", Some("id=1%27%20OR%20%271%27%3D%271"), None),
+            event("/products", Some("id=1"), None),
+            event("/search", Some("q=1%27%20UNION%20SELECT%201,2,3--"), None),
+            event("/admin", None, Some("9999")),
+            event("/about", None, None),
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let json = serde_json::to_string(event).unwrap();
+            if i % 2 == 0 {
+                writeln!(
+                    file,
+                    "2026-10-16T00:00:00Z INFO botwaf::access: {}{}",
+                    ACCESS_EVENT_LINE_PREFIX, json
+                )
+                .unwrap();
+            } else {
+                writeln!(file, "{}", json).unwrap();
+            }
+        }
+        path
+    }
+
+    async fn create_job(name: &str, events_file: &PathBuf) -> (AccessEventsReplayJob, Arc<ReplayResultManager>) {
+        let dir = env::temp_dir().join(format!("botwaf-ut-replay-db-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = ReplayResultSQLiteRepository::new(&SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        })
+        .await
+        .unwrap();
+        let results = Arc::new(ReplayResultManager::new(Arc::new(repo)));
+
+        let mut modsec_rules = Rules::new();
+        modsec_rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        modsec_rules.add_plain(RULES).unwrap();
+        let config = ReplayProperties {
+            events_file: events_file.to_string_lossy().to_string(),
+            rate_per_sec: 1000,
+            concurrency: 2,
+            batch_size: 2,
+        };
+        let job = AccessEventsReplayJob::new(
+            &config,
+            Arc::new(AccessEventsFileSource::new(&config.events_file)),
+            results.to_owned(),
+            Arc::new(ModSecurity::default()),
+            Arc::new(modsec_rules),
+            StatusCode::FORBIDDEN,
+        );
+        (job, results)
+    }
+
+    #[tokio::test]
+    async fn test_file_source_next_batch() {
+        let path = seed_events("source");
+        let source = AccessEventsFileSource::new(&path);
+        let batch = source.next_batch(0, 4).await.unwrap();
+        assert_eq!(batch.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(batch[1].1.path, "/products");
+
+        let batch = source.next_batch(4, 4).await.unwrap();
+        assert_eq!(batch.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![5, 6]);
+        // Rescanned from the beginning for the earlier event id.
+        let batch = source.next_batch(1, 1).await.unwrap();
+        assert_eq!(batch[0].0, 2);
+        assert!(source.next_batch(6, 4).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_rule_matches_subset() {
+        let path = seed_events("subset");
+        let (job, results) = create_job("subset", &path).await;
+        let progress = job.run("job-subset", None).await.unwrap();
+        assert_eq!(progress.replayed, 6);
+        assert_eq!(progress.last_event_id, Some(6));
+
+        let summary = results.summary("job-subset").await.unwrap();
+        assert_eq!(summary.total, 6);
+        assert_eq!(summary.old_blocked, 1);
+        assert_eq!(summary.new_blocked, 2);
+        assert_eq!(summary.rules.len(), 2);
+        assert_eq!(summary.rules[0].rule_id, "3201");
+        assert_eq!(summary.rules[0].newly_blocked, 2);
+        assert_eq!(summary.rules[0].delta, 2);
+        assert_eq!(summary.rules[1].rule_id, "9999");
+        assert_eq!(summary.rules[1].no_longer_blocked, 1);
+        assert_eq!(summary.rules[1].delta, -1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_resumes_from_checkpoint() {
+        let path = seed_events("resume");
+        let (job, results) = create_job("resume", &path).await;
+        // Interrupted after the first 3 events.
+        let progress = job.run("job-resume", Some(3)).await.unwrap();
+        assert_eq!(progress.replayed, 3);
+        assert_eq!(results.checkpoint("job-resume").await.unwrap(), Some(3));
+
+        let progress = job.run("job-resume", None).await.unwrap();
+        assert_eq!(progress.replayed, 3);
+        assert_eq!(progress.last_event_id, Some(6));
+        let summary = results.summary("job-resume").await.unwrap();
+        assert_eq!(summary.total, 6);
+        assert_eq!(summary.new_blocked, 2);
+
+        // Nothing left to be replayed.
+        assert_eq!(job.run("job-resume", None).await.unwrap().replayed, 0);
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::replay::{AccessEventsFileSource, AccessEventsReplayJob};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use botwaf_server::{
+    context::state::BotwafState,
+    modules::modsec::replay_result::ReplayResultManager,
+    util::web::{RequireAdmin, ValidatedJson},
+};
+use botwaf_types::{
+    modules::modsec::replay::{ReplayEventsProgress, ReplayEventsRequest, ReplayRuleDelta, ReplaySummaryResponse},
+    RespBase,
+};
+use common_audit_log::audit_log;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+lazy_static! {
+    // The running jobs, the same job is never replayed simultaneously to keep the checkpoint consistent.
+    static ref RUNNING_JOBS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(handle_replay_events, handle_stats_replay),
+    components(schemas(ReplayEventsRequest, ReplayEventsProgress, ReplayRuleDelta, ReplaySummaryResponse))
+)]
+pub struct ReplayApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/modsec/replay-events", post(handle_replay_events))
+        .route("/api/v1/stats/replay/{job_id}", get(handle_stats_replay))
+}
+
+fn get_replay_result_manager() -> Result<Arc<ReplayResultManager>, axum::response::Response> {
+    ReplayResultManager::get().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The replay results is not initialized.")),
+        )
+            .into_response()
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/modsec/replay-events",
+    request_body = ReplayEventsRequest,
+    responses(
+        (status = 202, description = "Start the background job which replays the historical access events against the current rules, resumed from the checkpoint of the job.", body = ReplayEventsProgress),
+        (status = 409, description = "The job is running.", body = RespBase),
+    ),
+    tag = "Rules"
+)]
+async fn handle_replay_events(
+    State(state): State<BotwafState>,
+    RequireAdmin(admin): RequireAdmin,
+    ValidatedJson(param): ValidatedJson<ReplayEventsRequest>,
+) -> impl IntoResponse {
+    let results = match get_replay_result_manager() {
+        Ok(results) => results,
+        Err(resp) => return resp,
+    };
+    let job_id = param.job_id.unwrap_or_else(AccessEventsReplayJob::new_job_id);
+    let last_event_id = match results.checkpoint(&job_id).await {
+        Ok(last_event_id) => last_event_id,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    };
+    if !RUNNING_JOBS.lock().unwrap().insert(job_id.to_owned()) {
+        return (
+            StatusCode::CONFLICT,
+            Json(RespBase::errmsg(&format!("The replay job '{}' is running.", job_id))),
+        )
+            .into_response();
+    }
+
+    let config = &state.config.services;
+    let job = AccessEventsReplayJob::new(
+        &config.replay,
+        Arc::new(AccessEventsFileSource::new(&config.replay.events_file)),
+        results,
+        state.modsec_engine.to_owned(),
+        state.modsec_rules.load_full(),
+        config.blocked_status_or(StatusCode::FORBIDDEN),
+    );
+    audit_replay_started(&admin.uname, &job_id);
+
+    let running_job_id = job_id.to_owned();
+    tokio::spawn(async move {
+        if let Err(e) = job.run(&running_job_id, param.limit).await {
+            tracing::error!(
+                "Failed to replay the access events of the job '{}'. cause: {}",
+                running_job_id,
+                e
+            );
+        }
+        RUNNING_JOBS.lock().unwrap().remove(&running_job_id);
+    });
+    (
+        StatusCode::ACCEPTED,
+        Json(ReplayEventsProgress {
+            job_id,
+            replayed: 0,
+            last_event_id,
+        }),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/replay/{job_id}",
+    params(("job_id" = String, Path, description = "The id of the replay job.")),
+    responses((status = 200, description = "Getting the would-block delta per rule of the replay job.", body = ReplaySummaryResponse)),
+    tag = "Stats"
+)]
+async fn handle_stats_replay(_: RequireAdmin, Path(job_id): Path<String>) -> impl IntoResponse {
+    let results = match get_replay_result_manager() {
+        Ok(results) => results,
+        Err(resp) => return resp,
+    };
+    match results.summary(&job_id).await {
+        Ok(summary) => (StatusCode::OK, Json(summary)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[audit_log("[MODSEC][REPLAY] principal: {principal}, job: {job_id}")]
+fn audit_replay_started(principal: &str, job_id: &str) {}
@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::access_recorder::AccessEventRecorder;
+use botwaf_server::{
+    config::config::{self, TopKProperties},
+    mgmt::apm::metrics::BOTWAF_TOPK_MEMORY_BYTES,
+};
+use botwaf_types::modules::forward::{
+    access_event::BotwafAccessEvent,
+    topk::{TopKDimension, TopKEntry, TopKResponse, TopKWindow},
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, RwLock},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: RwLock<Arc<AccessTopKTracker>> =
+        RwLock::new(Arc::new(AccessTopKTracker::new(&config::get_config().services.top_k)));
+}
+
+// The sliding window is approximated by the ring of the fixed slots.
+const WINDOW_SLOTS: u64 = 12;
+
+struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+}
+
+impl CountMinSketch {
+    fn new(width: usize, depth: usize) -> Self {
+        Self {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+        }
+    }
+
+    fn index(&self, row: usize, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        row * self.width + (hasher.finish() % self.width as u64) as usize
+    }
+
+    /// Increment the key and returns the new estimated count.
+    fn add(&mut self, key: &str) -> u64 {
+        let mut estimated = u64::MAX;
+        for row in 0..self.depth {
+            let index = self.index(row, key);
+            self.counters[index] += 1;
+            estimated = estimated.min(self.counters[index]);
+        }
+        estimated
+    }
+
+    fn estimate(&self, key: &str) -> u64 {
+        (0..self.depth)
+            .map(|row| self.counters[self.index(row, key)])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+struct WindowSlot {
+    epoch: u64,
+    total: u64,
+    sketch: CountMinSketch,
+    // The bounded heavy hitters candidates with the estimated counts.
+    candidates: HashMap<String, u64>,
+}
+
+impl WindowSlot {
+    fn reset(&mut self, epoch: u64) {
+        self.epoch = epoch;
+        self.total = 0;
+        self.sketch.counters.fill(0);
+        self.candidates.clear();
+    }
+
+    fn observe(&mut self, key: &str, capacity: usize) {
+        self.total += 1;
+        let estimated = self.sketch.add(key);
+        if let Some(count) = self.candidates.get_mut(key) {
+            *count = estimated;
+            return;
+        }
+        if self.candidates.len() < capacity {
+            self.candidates.insert(key.to_owned(), estimated);
+            return;
+        }
+        // Replace the least frequent candidate if the key is heavier.
+        let min = self
+            .candidates
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(key, count)| (key.to_owned(), *count));
+        if let Some((min_key, min_count)) = min {
+            if estimated > min_count {
+                self.candidates.remove(&min_key);
+                self.candidates.insert(key.to_owned(), estimated);
+            }
+        }
+    }
+}
+
+struct SlidingTopK {
+    slot_seconds: u64,
+    slots: Vec<WindowSlot>,
+}
+
+impl SlidingTopK {
+    fn new(window: TopKWindow, config: &TopKProperties) -> Self {
+        Self {
+            slot_seconds: (window.seconds() / WINDOW_SLOTS).max(1),
+            slots: (0..WINDOW_SLOTS)
+                .map(|_| WindowSlot {
+                    epoch: 0,
+                    total: 0,
+                    sketch: CountMinSketch::new(config.width.max(1), config.depth.max(1)),
+                    candidates: HashMap::with_capacity(config.capacity),
+                })
+                .collect(),
+        }
+    }
+
+    fn observe(&mut self, key: &str, now_secs: u64, capacity: usize) {
+        let epoch = now_secs / self.slot_seconds;
+        let slot = &mut self.slots[(epoch % WINDOW_SLOTS) as usize];
+        if slot.epoch != epoch {
+            slot.reset(epoch);
+        }
+        slot.observe(key, capacity);
+    }
+
+    fn top(&self, now_secs: u64, limit: usize) -> (u64, Vec<TopKEntry>) {
+        let current = now_secs / self.slot_seconds;
+        let live = self
+            .slots
+            .iter()
+            .filter(|slot| slot.total > 0 && slot.epoch <= current && slot.epoch + WINDOW_SLOTS > current)
+            .collect::<Vec<_>>();
+        let total = live.iter().map(|slot| slot.total).sum();
+
+        // The counts of the whole window are the sum of the slots estimations.
+        let mut entries = HashMap::new();
+        for slot in &live {
+            for key in slot.candidates.keys() {
+                entries
+                    .entry(key.as_str())
+                    .or_insert_with(|| live.iter().map(|slot| slot.sketch.estimate(key)).sum::<u64>());
+            }
+        }
+        let mut entries = entries
+            .into_iter()
+            .map(|(key, count)| TopKEntry {
+                key: key.to_owned(),
+                count,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        entries.truncate(limit);
+        (total, entries)
+    }
+}
+
+/// The in-process streaming top-K of the access events by dimensions over the sliding windows, which is
+/// maintained by the access events bus subscriber, and never touches the database.
+pub struct AccessTopKTracker {
+    config: TopKProperties,
+    trackers: Mutex<HashMap<(TopKDimension, TopKWindow), SlidingTopK>>,
+}
+
+impl AccessTopKTracker {
+    pub fn new(config: &TopKProperties) -> Self {
+        let mut trackers = HashMap::new();
+        if config.enabled {
+            for dimension in TopKDimension::ALL {
+                for window in TopKWindow::ALL {
+                    trackers.insert((dimension, window), SlidingTopK::new(window, config));
+                }
+            }
+        }
+        BOTWAF_TOPK_MEMORY_BYTES.set(Self::memory_bytes(config) as i64);
+        Self {
+            config: config.to_owned(),
+            trackers: Mutex::new(trackers),
+        }
+    }
+
+    /// Getting the tracker of current config, which is reset cleanly if the config changed, e.g: config reload.
+    pub fn get() -> Arc<AccessTopKTracker> {
+        let config = config::get_config();
+        let current = SINGLE_INSTANCE.read().unwrap().to_owned();
+        if current.config == config.services.top_k {
+            return current;
+        }
+        let mut instance = SINGLE_INSTANCE.write().unwrap();
+        if instance.config != config.services.top_k {
+            tracing::info!("Resetting the top-K trackers with the changed config ...");
+            *instance = Arc::new(Self::new(&config.services.top_k));
+        }
+        instance.to_owned()
+    }
+
+    /// Start to maintain the trackers with the (protected) access events.
+    pub fn start() {
+        let mut receiver = AccessEventRecorder::subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => Self::get().observe(&event, chrono::Utc::now().timestamp() as u64),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("The top-K trackers lagged, skipped {} access events.", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// The fixed memory usage of the sketches and the candidates with the max key bytes.
+    pub fn memory_bytes(config: &TopKProperties) -> usize {
+        if !config.enabled {
+            return 0;
+        }
+        let sketch_bytes = config.width * config.depth * std::mem::size_of::<u64>();
+        let candidates_bytes = config.capacity * (config.max_key_bytes + std::mem::size_of::<(String, u64)>());
+        (sketch_bytes + candidates_bytes) * WINDOW_SLOTS as usize * TopKWindow::ALL.len() * TopKDimension::ALL.len()
+    }
+
+    pub fn observe(&self, event: &BotwafAccessEvent, now_secs: u64) {
+        if event.synthetic || !self.config.enabled {
+            return;
+        }
+        let mut trackers = self.trackers.lock().unwrap();
+        for dimension in TopKDimension::ALL {
+            let key = match Self::get_key(event, dimension) {
+                Some(key) if !key.is_empty() => Self::truncate_key(key, self.config.max_key_bytes),
+                _ => continue,
+            };
+            for window in TopKWindow::ALL {
+                if let Some(tracker) = trackers.get_mut(&(dimension, window)) {
+                    tracker.observe(key, now_secs, self.config.capacity);
+                }
+            }
+        }
+    }
+
+    pub fn top(&self, dimension: TopKDimension, window: TopKWindow, limit: usize, now_secs: u64) -> TopKResponse {
+        let (total, entries) = match self.trackers.lock().unwrap().get(&(dimension, window)) {
+            Some(tracker) => tracker.top(now_secs, limit),
+            None => (0, Vec::new()),
+        };
+        let width = self.config.width.max(1) as f64;
+        TopKResponse {
+            dimension,
+            window,
+            total,
+            error_bound: (std::f64::consts::E / width * total as f64).ceil() as u64,
+            confidence: 1.0 - (-(self.config.depth.max(1) as f64)).exp(),
+            entries,
+        }
+    }
+
+    fn get_key(event: &BotwafAccessEvent, dimension: TopKDimension) -> Option<&str> {
+        match dimension {
+            TopKDimension::PATH => Some(event.path.as_str()),
+            TopKDimension::IP => event.client_ip.as_deref(),
+            TopKDimension::RULE => event.rule_id.as_deref(),
+            TopKDimension::USER_AGENT => event.headers.as_ref().and_then(|headers| {
+                headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+                    .and_then(|(_, value)| value.as_deref())
+            }),
+        }
+    }
+
+    fn truncate_key(key: &str, max_bytes: usize) -> &str {
+        if key.len() <= max_bytes {
+            return key;
+        }
+        let mut end = max_bytes;
+        while !key.is_char_boundary(end) {
+            end -= 1;
+        }
+        &key[..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_event(path: &str) -> BotwafAccessEvent {
+        BotwafAccessEvent {
+            method: String::from("GET"),
+            scheme: None,
+            host: None,
+            port: None,
+            headers: None,
+            path: path.to_owned(),
+            query: None,
+            body: None,
+            req_id: None,
+            client_ip: None,
+            start_time: 0,
+            resp_status_code: Some(200),
+            resp_headers: None,
+            resp_body: None,
+            duration: None,
+            synthetic: false,
+            rule_id: None,
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    // The deterministic zipfian distributed ranks (0-based) by the inverse CDF.
+    fn zipfian_ranks(keys: usize, exponent: f64, count: usize) -> Vec<usize> {
+        let mut cumulative = Vec::with_capacity(keys);
+        let mut sum = 0.0;
+        for rank in 1..=keys {
+            sum += 1.0 / (rank as f64).powf(exponent);
+            cumulative.push(sum);
+        }
+        let mut seed: u64 = 42;
+        (0..count)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let uniform = (seed >> 11) as f64 / (1u64 << 53) as f64 * sum;
+                cumulative.partition_point(|c| *c < uniform).min(keys - 1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zipfian_accuracy_bounds() {
+        let config = TopKProperties::default();
+        let tracker = AccessTopKTracker::new(&config);
+
+        let mut exact = HashMap::new();
+        for rank in zipfian_ranks(1000, 1.2, 50_000) {
+            let path = format!("/path/{}", rank);
+            tracker.observe(&create_test_event(&path), 0);
+            *exact.entry(path).or_insert(0u64) += 1;
+        }
+        let mut exact_sorted = exact.iter().collect::<Vec<_>>();
+        exact_sorted.sort_by(|a, b| b.1.cmp(a.1));
+
+        let top = tracker.top(TopKDimension::PATH, TopKWindow::ONE_HOUR, 10, 0);
+        assert_eq!(top.total, 50_000);
+        assert_eq!(top.entries.len(), 10);
+        // The estimated counts are within the documented error bound.
+        for entry in &top.entries {
+            let true_count = exact[&entry.key];
+            assert!(entry.count >= true_count);
+            assert!(entry.count - true_count <= top.error_bound);
+        }
+        // The heavy hitters are never missed.
+        for (path, _) in exact_sorted.iter().take(5) {
+            assert!(top.entries.iter().any(|e| &e.key == *path));
+        }
+        let tenth_count = *exact_sorted[9].1;
+        for entry in &top.entries {
+            assert!(exact[&entry.key] + top.error_bound >= tenth_count);
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_expired() {
+        let tracker = AccessTopKTracker::new(&TopKProperties::default());
+        tracker.observe(&create_test_event("/login"), 0);
+
+        let top = tracker.top(TopKDimension::PATH, TopKWindow::FIVE_MINUTES, 10, 60);
+        assert_eq!(top.entries[0].key, "/login");
+        let top = tracker.top(TopKDimension::PATH, TopKWindow::FIVE_MINUTES, 10, 5 * 60);
+        assert!(top.entries.is_empty());
+        let top = tracker.top(TopKDimension::PATH, TopKWindow::ONE_HOUR, 10, 5 * 60);
+        assert_eq!(top.entries[0].count, 1);
+    }
+
+    #[test]
+    fn test_synthetic_events_excluded() {
+        let tracker = AccessTopKTracker::new(&TopKProperties::default());
+        let mut event = create_test_event("/botwaf-probe");
+        event.synthetic = true;
+        tracker.observe(&event, 0);
+        assert_eq!(
+            tracker.top(TopKDimension::PATH, TopKWindow::FIVE_MINUTES, 10, 0).total,
+            0
+        );
+    }
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::topk::AccessTopKTracker;
+use axum::{extract::Query, response::IntoResponse, routing::get, Json, Router};
+use botwaf_server::{context::state::BotwafState, util::web::RequireAdmin};
+use botwaf_types::modules::forward::topk::{TopKDimension, TopKEntry, TopKQueryRequest, TopKResponse, TopKWindow};
+use hyper::StatusCode;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(handle_stats_top),
+    components(schemas(TopKDimension, TopKWindow, TopKEntry, TopKResponse))
+)]
+pub struct TopKApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/stats/top", get(handle_stats_top))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/top",
+    params(TopKQueryRequest),
+    responses((status = 200, description = "Getting the estimated top-K of the access events by dimension and window.", body = TopKResponse)),
+    tag = "Stats"
+)]
+async fn handle_stats_top(_: RequireAdmin, Query(param): Query<TopKQueryRequest>) -> impl IntoResponse {
+    let tracker = AccessTopKTracker::get();
+    let limit = param.limit.unwrap_or(10).clamp(1, 1000);
+    let top = tracker.top(
+        param.dimension,
+        param.window,
+        limit,
+        chrono::Utc::now().timestamp() as u64,
+    );
+    (StatusCode::OK, Json(top)).into_response()
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_server::config::config::{self, MaxUriLengthRouteProperties};
+use globset::{Glob, GlobMatcher};
+use hyper::Uri;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: UriLengthLimiter = UriLengthLimiter::new(
+        config::get_config().services.max_uri_length,
+        &config::get_config().services.max_uri_length_routes
+    );
+}
+
+/// The limiter of the raw URI length (i.e: the path with query string), which mitigates the DoS and
+/// the rule evasion by the very long URIs, the per route overrides of the first matched path-glob wins.
+pub struct UriLengthLimiter {
+    max_length: usize,
+    routes: Vec<(GlobMatcher, usize)>,
+}
+
+impl UriLengthLimiter {
+    pub fn new(max_length: usize, routes: &[MaxUriLengthRouteProperties]) -> Self {
+        let routes = routes
+            .iter()
+            .filter_map(|route| match Glob::new(&route.path_glob) {
+                Ok(glob) => Some((glob.compile_matcher(), route.max_uri_length)),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to load the max URI length of {}, it will be ignored. cause: {}",
+                        route.path_glob,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        UriLengthLimiter { max_length, routes }
+    }
+
+    pub fn get() -> &'static UriLengthLimiter {
+        &SINGLE_INSTANCE
+    }
+
+    /// The max URI length of the path, 0 is unlimited.
+    pub fn max_length(&self, path: &str) -> usize {
+        self.routes
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map(|(_, max_length)| *max_length)
+            .unwrap_or(self.max_length)
+    }
+
+    /// Whether the raw URI (including the query string) exceeds the max length of the matched route.
+    pub fn is_exceeded(&self, uri: &Uri) -> bool {
+        let raw = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(uri.path());
+        let max_length = self.max_length(uri.path());
+        max_length > 0 && raw.len() > max_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_limiter() -> UriLengthLimiter {
+        UriLengthLimiter::new(
+            32,
+            &[MaxUriLengthRouteProperties {
+                path_glob: String::from("/api/search/**"),
+                max_uri_length: 64,
+            }],
+        )
+    }
+
+    #[test]
+    fn test_uri_length_includes_query_string() {
+        let limiter = create_limiter();
+        // The boundary length is passed.
+        let boundary = format!("/a?q={}", "x".repeat(32 - 5));
+        assert_eq!(boundary.len(), 32);
+        assert!(!limiter.is_exceeded(&boundary.parse().unwrap()));
+        // The short path with the long query string is exceeded.
+        assert!(limiter.is_exceeded(&format!("{}x", boundary).parse().unwrap()));
+    }
+
+    #[test]
+    fn test_uri_length_overridden_per_route() {
+        let limiter = create_limiter();
+        let uri = format!("/api/search/items?q={}", "x".repeat(40));
+        assert!(uri.len() > 32 && uri.len() <= 64);
+        assert!(!limiter.is_exceeded(&uri.parse().unwrap()));
+        assert!(limiter.is_exceeded(&format!("/api/other?q={}", "x".repeat(40)).parse().unwrap()));
+        // The unlimited.
+        assert!(!UriLengthLimiter::new(0, &[]).is_exceeded(&uri.parse().unwrap()));
+    }
+}
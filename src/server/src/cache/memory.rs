@@ -20,34 +20,58 @@
 
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
 
 use anyhow::{Error, Ok};
 use async_trait::async_trait;
 use moka::future::Cache;
+use moka::notification::RemovalCause;
 use moka::policy::EvictionPolicy;
 use regex::Regex;
 
 use crate::config::config::MemoryProperties;
+use crate::mgmt::apm::metrics::{
+    BOTWAF_CACHE_EVICTIONS_TOTAL, BOTWAF_CACHE_MEMORY_BYTES, BOTWAF_CACHE_REJECTED_ENTRIES_TOTAL,
+};
 
-use super::ICache;
+use super::{CacheEntryTooLargeError, ICache};
 
 pub struct StringMemoryCache {
     cache: Arc<Cache<String, String>>,
+    max_entry_bytes: Option<u64>,
+    // The current bytes (key + value) of the entries, which is decreased by the eviction listener.
+    bytes: Arc<AtomicI64>,
 }
 
 impl StringMemoryCache {
     pub fn new(config: &MemoryProperties) -> Self {
+        let bytes = Arc::new(AtomicI64::new(0));
         let mut builder = Cache::builder();
         if let Some(initial_capacity) = config.initial_capacity {
             builder = builder.initial_capacity(initial_capacity as usize);
         }
-        if let Some(max_capacity) = config.max_capacity {
-            builder = builder.max_capacity(max_capacity);
+        match (config.max_bytes, config.max_capacity) {
+            (Some(max_bytes), max_capacity) => {
+                // Each entry weighs at least the average budget per entry, so both the total bytes and the
+                // number of entries are bounded by the single weighted capacity.
+                let min_weight = max_capacity
+                    .filter(|c| *c > 0)
+                    .map(|c| (max_bytes / c).max(1))
+                    .unwrap_or(1);
+                builder = builder
+                    .weigher(move |key: &String, value: &String| {
+                        Self::entry_bytes(key, value).max(min_weight).min(u32::MAX as u64) as u32
+                    })
+                    .max_capacity(max_bytes);
+            }
+            (None, Some(max_capacity)) => {
+                builder = builder.max_capacity(max_capacity);
+            }
+            (None, None) => {}
         }
         if let Some(ttl) = config.ttl {
-            builder = builder.time_to_live(Duration::from_millis(ttl));
+            builder = builder.time_to_live(*ttl);
         }
         if let Some(eviction_policy) = &config.eviction_policy {
             match eviction_policy.to_uppercase().as_str() {
@@ -62,9 +86,49 @@ impl StringMemoryCache {
                 }
             }
         }
+        let listener_bytes = bytes.clone();
+        builder = builder.eviction_listener(move |key: Arc<String>, value: String, cause: RemovalCause| {
+            let size = Self::entry_bytes(&key, &value) as i64;
+            listener_bytes.fetch_sub(size, Ordering::Relaxed);
+            BOTWAF_CACHE_MEMORY_BYTES.sub(size);
+            let cause = match cause {
+                RemovalCause::Expired => "expired",
+                RemovalCause::Size => "size",
+                // The explicit invalidated or replaced entries are not the evictions.
+                RemovalCause::Explicit | RemovalCause::Replaced => return,
+            };
+            BOTWAF_CACHE_EVICTIONS_TOTAL.with_label_values(&[cause]).inc();
+        });
         StringMemoryCache {
             cache: Arc::new(builder.build()),
+            max_entry_bytes: config.max_entry_bytes,
+            bytes,
+        }
+    }
+
+    /// The current bytes (key + value) of the entries, the pending evictions are accounted lazily.
+    pub fn current_bytes(&self) -> i64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn entry_bytes(key: &str, value: &str) -> u64 {
+        (key.len() + value.len()) as u64
+    }
+
+    /// Insert the entry if not exceeds the max entry size, otherwise rejects with CacheEntryTooLargeError.
+    async fn insert(&self, key: String, value: String) -> Result<(), Error> {
+        let size = Self::entry_bytes(&key, &value);
+        if let Some(max) = self.max_entry_bytes {
+            if size > max {
+                BOTWAF_CACHE_REJECTED_ENTRIES_TOTAL.inc();
+                tracing::warn!("Rejected to cache the oversized entry of {} bytes, max: {}", size, max);
+                return Err(Error::new(CacheEntryTooLargeError { key, size, max }));
+            }
         }
+        self.bytes.fetch_add(size as i64, Ordering::Relaxed);
+        BOTWAF_CACHE_MEMORY_BYTES.add(size as i64);
+        self.cache.insert(key, value).await;
+        Ok(())
     }
 
     fn serialize_hash(hash: &HashMap<String, String>) -> String {
@@ -88,7 +152,7 @@ impl ICache<String> for StringMemoryCache {
     /// The `milliseconds` parameter is deprecated and will be ignored.
     #[allow(unused_variables)]
     async fn set(&self, key: String, value: String, milliseconds: Option<i32>) -> Result<bool, Error> {
-        self.cache.insert(key.clone(), value).await;
+        self.insert(key.clone(), value).await?;
         tracing::info!("Inserted to key: {}, expire: {:?}ms", key, milliseconds);
         Ok(true)
     }
@@ -97,7 +161,7 @@ impl ICache<String> for StringMemoryCache {
         if let Some(v) = value {
             match self.cache.contains_key(&key) {
                 false => {
-                    self.cache.insert(key.clone(), v).await;
+                    self.insert(key.clone(), v).await?;
                     return Ok(true);
                 }
                 true => {
@@ -151,7 +215,7 @@ impl ICache<String> for StringMemoryCache {
             for (field, value) in fv {
                 hash.insert(field, value); // override put
             }
-            self.cache.insert(key, Self::serialize_hash(&hash)).await;
+            self.insert(key, Self::serialize_hash(&hash)).await?;
             Ok(true)
         } else {
             Ok(false)
@@ -166,7 +230,7 @@ impl ICache<String> for StringMemoryCache {
         };
         if !hash.contains_key(&field) {
             hash.insert(field, value);
-            self.cache.insert(key, Self::serialize_hash(&hash)).await;
+            self.insert(key, Self::serialize_hash(&hash)).await?;
             Ok(true)
         } else {
             Ok(false)
@@ -191,7 +255,7 @@ impl ICache<String> for StringMemoryCache {
                 // Remove the field from the keys vector
                 hash.remove(&field);
                 // Update to cache.
-                self.cache.insert(key, Self::serialize_hash(&hash)).await;
+                self.insert(key, Self::serialize_hash(&hash)).await?;
                 Ok(true)
             }
             None => Ok(false),
@@ -244,7 +308,7 @@ impl ICache<String> for StringMemoryCache {
         };
 
         bytes[byte_offset] = new_byte;
-        self.cache.insert(key, String::from_utf8_lossy(&bytes).to_string()).await;
+        self.insert(key, String::from_utf8_lossy(&bytes).to_string()).await?;
 
         Ok(((old_byte >> (7 - bit_offset)) & 1) == 1)
     }
@@ -259,17 +323,84 @@ impl ICache<String> for StringMemoryCache {
 mod tests {
     use super::*;
     use crate::config::config::MemoryProperties;
+    use crate::config::duration::DurationMillis;
 
     fn create_test_cache() -> StringMemoryCache {
         let config = MemoryProperties {
             initial_capacity: Some(100),
             max_capacity: Some(1000),
-            ttl: Some(3600000),
+            max_bytes: None,
+            max_entry_bytes: None,
+            ttl: Some(DurationMillis::from_secs(3600)),
             eviction_policy: Some("LRU".to_string()),
         };
         StringMemoryCache::new(&config)
     }
 
+    #[tokio::test]
+    async fn test_max_bytes_bounded() {
+        let max_bytes = 64 * 1024;
+        let cache = StringMemoryCache::new(&MemoryProperties {
+            initial_capacity: Some(16),
+            max_capacity: Some(1000),
+            max_bytes: Some(max_bytes),
+            max_entry_bytes: Some(8 * 1024),
+            ttl: Some(DurationMillis::from_secs(3600)),
+            eviction_policy: Some("LRU".to_string()),
+        });
+        // Notice: Far more than the max bytes, but far less than the max capacity entries.
+        for i in 0..100 {
+            let value = "x".repeat(4 * 1024);
+            assert!(cache.set(format!("key{}", i), value, None).await.unwrap());
+        }
+        cache.cache.run_pending_tasks().await;
+
+        assert!(cache.cache.weighted_size() <= max_bytes);
+        assert!(cache.current_bytes() <= max_bytes as i64);
+        assert!(cache.current_bytes() > 0);
+        assert!(cache.cache.entry_count() < 100);
+    }
+
+    #[tokio::test]
+    async fn test_max_capacity_bounded_with_max_bytes() {
+        let cache = StringMemoryCache::new(&MemoryProperties {
+            initial_capacity: Some(16),
+            max_capacity: Some(10),
+            max_bytes: Some(1024 * 1024),
+            max_entry_bytes: None,
+            ttl: Some(DurationMillis::from_secs(3600)),
+            eviction_policy: Some("LRU".to_string()),
+        });
+        // The small entries are still bounded by the max capacity.
+        for i in 0..100 {
+            cache.set(format!("key{}", i), "v".to_string(), None).await.unwrap();
+        }
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.cache.entry_count() <= 10);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_entry_rejected() {
+        let cache = StringMemoryCache::new(&MemoryProperties {
+            initial_capacity: Some(16),
+            max_capacity: Some(1000),
+            max_bytes: Some(1024 * 1024),
+            max_entry_bytes: Some(1024),
+            ttl: Some(DurationMillis::from_secs(3600)),
+            eviction_policy: Some("LRU".to_string()),
+        });
+        let err = cache.set("big".to_string(), "x".repeat(2048), None).await.unwrap_err();
+        let err = err.downcast_ref::<CacheEntryTooLargeError>().unwrap();
+        assert_eq!(err.size, 2051);
+        assert_eq!(err.max, 1024);
+        assert_eq!(cache.get("big".to_string()).await.unwrap(), None);
+
+        // The hash growing beyond the max entry size is rejected too.
+        let field_values = Some(vec![("f".to_string(), "x".repeat(2048))]);
+        assert!(cache.hset("hash".to_string(), field_values).await.is_err());
+        assert_eq!(cache.current_bytes(), 0);
+    }
+
     #[tokio::test]
     async fn test_set_and_get() {
         let cache = create_test_cache();
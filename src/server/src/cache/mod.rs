@@ -26,6 +26,15 @@ use std::collections::HashMap;
 pub mod memory;
 pub mod redis;
 
+/// The rejection of the entry larger than the max entry size, which is never cached silently.
+#[derive(Debug, thiserror::Error)]
+#[error("The cache entry '{key}' of {size} bytes exceeds the max entry size {max} bytes")]
+pub struct CacheEntryTooLargeError {
+    pub key: String,
+    pub size: u64,
+    pub max: u64,
+}
+
 #[async_trait]
 pub trait ICache<T>: Send + Sync {
     async fn get(&self, key: String) -> Result<Option<T>, Error>
@@ -20,14 +20,15 @@
 
 use super::ICache;
 use crate::config::config::RedisProperties;
+use crate::mgmt::apm::dependencies::{instrument_dep, DEP_REDIS};
 use anyhow::Error;
 use async_trait::async_trait;
 use redis::{
     cluster::{ClusterClient, ClusterClientBuilder},
     cluster_async::ClusterConnection,
-    RedisResult,
+    Cmd, FromRedisValue, RedisResult,
 };
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc};
 
 pub struct StringRedisCache {
     client: Arc<ClusterClient>,
@@ -35,6 +36,12 @@ pub struct StringRedisCache {
 
 impl StringRedisCache {
     pub fn new(config: &RedisProperties) -> Self {
+        Self::try_new(config).expect("Failed to build redis cluster client")
+    }
+
+    /// Build the cluster client, notice that the nodes are not connected until the first command, so only the
+    /// invalid config (e.g: the empty or malformed nodes) is reported here.
+    pub fn try_new(config: &RedisProperties) -> Result<Self, Error> {
         // Safety replace to desensitized password.
         let mut desensitized = config.clone();
         desensitized.password = desensitized
@@ -51,96 +58,87 @@ impl StringRedisCache {
             builder = builder.password(config.password.clone().unwrap());
         }
         if config.connection_timeout.is_some() {
-            builder = builder.connection_timeout(Duration::from_millis(config.connection_timeout.clone().unwrap()));
+            builder = builder.connection_timeout(*config.connection_timeout.unwrap());
         }
         if config.response_timeout.is_some() {
-            builder = builder.response_timeout(Duration::from_millis(config.response_timeout.clone().unwrap()));
+            builder = builder.response_timeout(*config.response_timeout.unwrap());
         }
         if config.retries.is_some() {
             builder = builder.retries(config.retries.clone().unwrap());
         }
         if config.max_retry_wait.is_some() {
-            builder = builder.max_retry_wait(config.max_retry_wait.clone().unwrap());
+            builder = builder.max_retry_wait(config.max_retry_wait.unwrap().as_millis() as u64);
         }
         if config.min_retry_wait.is_some() {
-            builder = builder.min_retry_wait(config.min_retry_wait.clone().unwrap());
+            builder = builder.min_retry_wait(config.min_retry_wait.unwrap().as_millis() as u64);
         }
         if config.read_from_replicas.is_some() {
             builder = builder.read_from_replicas();
         }
-        let client = builder.build().expect("Failed to build redis cluster client");
+        let client = builder
+            .build()
+            .map_err(|e| Error::msg(format!("Invalid redis cluster nodes {:?}. cause: {}", config.nodes, e)))?;
         tracing::info!("Initialized the redis cluster client.");
 
-        StringRedisCache {
+        Ok(StringRedisCache {
             client: Arc::new(client),
-        }
+        })
     }
 
     async fn get_async_connection(&self) -> Result<ClusterConnection, Error> {
         self.client.get_async_connection().await.map_err(Error::from)
     }
+
+    // Query the command with the latency and failure recorded by the operation class, e.g: get, set, del
+    async fn query<T: FromRedisValue + Send>(&self, operation: &'static str, cmd: &Cmd) -> Result<T, Error> {
+        instrument_dep(DEP_REDIS, operation, async {
+            let mut con = self.get_async_connection().await?;
+            let result: RedisResult<T> = cmd.query_async(&mut con).await;
+            Ok(result?)
+        })
+        .await
+    }
 }
 
 #[async_trait]
 impl ICache<String> for StringRedisCache {
     async fn get(&self, key: String) -> Result<Option<String>, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<Option<String>> = redis::cmd("GET").arg(key).query_async(&mut con).await;
-        Ok(result?)
+        self.query("get", redis::cmd("GET").arg(key)).await
     }
 
     async fn set(&self, key: String, value: String, seonds: Option<i32>) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<String> = if let Some(seconds) = seonds {
-            redis::cmd("SETEX")
-                .arg(key)
-                .arg(seconds)
-                .arg(value)
-                .query_async(&mut con)
-                .await
+        let result: String = if let Some(seconds) = seonds {
+            self.query("set", redis::cmd("SETEX").arg(key).arg(seconds).arg(value))
+                .await?
         } else {
-            redis::cmd("SET").arg(key).arg(value).query_async(&mut con).await
+            self.query("set", redis::cmd("SET").arg(key).arg(value)).await?
         };
-        Ok(result.map(|s| s == "OK")?)
+        Ok(result == "OK")
     }
 
     async fn set_nx(&self, key: String, value: Option<String>) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<i64> = redis::cmd("SETNX").arg(key).arg(value).query_async(&mut con).await;
-        Ok(result.map(|s| s > 0)?)
+        let result: i64 = self.query("set", redis::cmd("SETNX").arg(key).arg(value)).await?;
+        Ok(result > 0)
     }
 
     async fn keys(&self, pattern: String) -> Result<Vec<String>, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<Vec<String>> = redis::cmd("KEYS").arg(pattern).query_async(&mut con).await;
-        Ok(result?)
+        self.query("get", redis::cmd("KEYS").arg(pattern)).await
     }
 
     async fn hget(&self, key: String, field: Option<String>) -> Result<Option<String>, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result = redis::cmd("HGET")
-            .arg(key)
-            .arg(field.unwrap_or_default())
-            .query_async(&mut con)
-            .await;
-        Ok(result?)
+        self.query("get", redis::cmd("HGET").arg(key).arg(field.unwrap_or_default()))
+            .await
     }
 
     async fn hget_all(&self, key: String) -> Result<Option<HashMap<String, String>>, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<Option<HashMap<String, String>>> =
-            redis::cmd("HGETALL").arg(key).query_async(&mut con).await;
-        Ok(result?)
+        self.query("get", redis::cmd("HGETALL").arg(key)).await
     }
 
     async fn hkeys(&self, key: String) -> Result<Vec<String>, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<Vec<String>> = redis::cmd("HKEYS").arg(key).query_async(&mut con).await;
-        Ok(result?)
+        self.query("get", redis::cmd("HKEYS").arg(key)).await
     }
 
     async fn hset(&self, key: String, field_values: Option<Vec<(String, String)>>) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("HSET");
         cmd.arg(key);
         if let Some(fvs) = field_values {
@@ -148,48 +146,43 @@ impl ICache<String> for StringRedisCache {
                 cmd.arg(field).arg(value);
             }
         }
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
-        Ok(result.map(|s| s >= 1)?)
+        let result: i64 = self.query("set", &cmd).await?;
+        Ok(result >= 1)
     }
 
     async fn hset_nx(&self, key: String, field: String, value: String) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("HSETNX");
         cmd.arg(key).arg(field).arg(value);
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
-        Ok(result.map(|s| s > 0)?)
+        let result: i64 = self.query("set", &cmd).await?;
+        Ok(result > 0)
     }
 
     async fn hdel(&self, key: String, field: String) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("HDEL");
         cmd.arg(key);
         cmd.arg(field);
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
-        Ok(result.map(|s| s >= 1)?)
+        let result: i64 = self.query("del", &cmd).await?;
+        Ok(result >= 1)
     }
 
     async fn expire(&self, key: String, milliseconds: i64) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("PEXPIRE");
         cmd.arg(key);
         cmd.arg(milliseconds);
         cmd.arg("NX");
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
-        Ok(result.map(|s| s > 0)?)
+        let result: i64 = self.query("set", &cmd).await?;
+        Ok(result > 0)
     }
 
     async fn get_bit(&self, key: String, offset: u64) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("GETBIT");
         cmd.arg(key);
         cmd.arg(offset);
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
-        Ok(result.map(|s| s >= 1)?)
+        let result: i64 = self.query("get", &cmd).await?;
+        Ok(result >= 1)
     }
 
     async fn set_bit(&self, key: String, offset: u64, value: bool) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
         let mut cmd = redis::cmd("SETBIT");
         cmd.arg(key);
         cmd.arg(offset);
@@ -197,13 +190,12 @@ impl ICache<String> for StringRedisCache {
             true => 1,
             false => 0,
         });
-        let result: RedisResult<i64> = cmd.query_async(&mut con).await;
+        let result: Result<i64, Error> = self.query("set", &cmd).await;
         Ok(result.map(|s| s >= 1).unwrap_or(false))
     }
 
     async fn del(&self, key: String) -> Result<bool, Error> {
-        let mut con = self.get_async_connection().await?;
-        let result: RedisResult<i32> = redis::cmd("DEL").arg(key).query_async(&mut con).await;
+        let result: Result<i32, Error> = self.query("del", redis::cmd("DEL").arg(key)).await;
         Ok(result.map(|n| n > 0).unwrap_or(false))
     }
 }
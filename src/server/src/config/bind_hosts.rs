@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+};
+
+/// The bind hosts of the listener, which is deserialized from the single host, the comma-separated hosts (e.g:
+/// the env overrides), or the list of hosts, e.g: "::" for the dual-stack, or ["127.0.0.1", "::1"] to listen
+/// on the both loopbacks, each host is bound by a separate listener feeding the same router.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BindHosts(Vec<String>);
+
+impl BindHosts {
+    pub fn new(hosts: Vec<String>) -> Self {
+        BindHosts(hosts)
+    }
+
+    pub fn single(host: &str) -> Self {
+        BindHosts(vec![host.to_owned()])
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Whether all the hosts are the loopback, e.g: localhost, 127.0.0.1, ::1
+    pub fn is_loopback(&self) -> bool {
+        !self.0.is_empty()
+            && self.0.iter().all(|host| {
+                host.eq_ignore_ascii_case("localhost")
+                    || Self::trim_brackets(host)
+                        .parse::<IpAddr>()
+                        .is_ok_and(|ip| ip.to_canonical().is_loopback())
+            })
+    }
+
+    /// Resolve the socket addresses of all the hosts with the port, the duplicated addresses are bound once,
+    /// e.g: the localhost is resolved to both 127.0.0.1 and ::1
+    pub fn to_socket_addrs(&self, port: u16) -> Result<Vec<SocketAddr>, anyhow::Error> {
+        if self.0.is_empty() {
+            return Err(anyhow::anyhow!("The bind hosts is empty"));
+        }
+        let mut addrs = Vec::new();
+        for host in &self.0 {
+            let resolved = (Self::trim_brackets(host), port)
+                .to_socket_addrs()
+                .map_err(|e| anyhow::anyhow!("Invalid bind host '{}'. {}", host, e))?;
+            for addr in resolved {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        Ok(addrs)
+    }
+
+    // The IPv6 literal may be bracketed, e.g: [::1]
+    fn trim_brackets(host: &str) -> &str {
+        let host = host.trim();
+        host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host)
+    }
+}
+
+impl PartialEq<&str> for BindHosts {
+    fn eq(&self, other: &&str) -> bool {
+        self.0.len() == 1 && self.0[0] == *other
+    }
+}
+
+impl fmt::Display for BindHosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+impl Serialize for BindHosts {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [host] => serializer.serialize_str(host),
+            hosts => hosts.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BindHosts {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BindHostsVisitor;
+
+        impl<'de> Visitor<'de> for BindHostsVisitor {
+            type Value = BindHosts;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a host, the comma-separated hosts or a list of hosts, e.g: '::', ['127.0.0.1', '::1']")
+            }
+
+            // Notice: The env overrides are always the strings, e.g: BOTWAF__SERVER__HOST=127.0.0.1,::1
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                let hosts = value
+                    .split(',')
+                    .map(|host| host.trim())
+                    .filter(|host| !host.is_empty())
+                    .map(|host| host.to_owned())
+                    .collect::<Vec<_>>();
+                if hosts.is_empty() {
+                    return Err(E::custom("the bind host is blank"));
+                }
+                Ok(BindHosts(hosts))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut hosts = Vec::new();
+                while let Some(host) = seq.next_element::<String>()? {
+                    if !host.trim().is_empty() {
+                        hosts.push(host.trim().to_owned());
+                    }
+                }
+                if hosts.is_empty() {
+                    return Err(de::Error::custom("the bind hosts is empty"));
+                }
+                Ok(BindHosts(hosts))
+            }
+        }
+
+        deserializer.deserialize_any(BindHostsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Listener {
+        host: BindHosts,
+    }
+
+    #[test]
+    fn test_single_and_list_round_trip() {
+        let listener: Listener = serde_json::from_str(r#"{"host":"0.0.0.0"}"#).unwrap();
+        assert_eq!(listener.host, "0.0.0.0");
+        assert_eq!(serde_json::to_string(&listener).unwrap(), r#"{"host":"0.0.0.0"}"#);
+
+        let listener: Listener = serde_json::from_str(r#"{"host":["127.0.0.1","::1"]}"#).unwrap();
+        assert_eq!(listener.host.hosts(), ["127.0.0.1", "::1"]);
+        assert_eq!(
+            serde_json::to_string(&listener).unwrap(),
+            r#"{"host":["127.0.0.1","::1"]}"#
+        );
+
+        // The env overrides are the comma-separated strings.
+        let listener: Listener = serde_json::from_str(r#"{"host":"127.0.0.1, ::1"}"#).unwrap();
+        assert_eq!(listener.host.hosts(), ["127.0.0.1", "::1"]);
+
+        assert!(serde_json::from_str::<Listener>(r#"{"host":" "}"#).is_err());
+        assert!(serde_json::from_str::<Listener>(r#"{"host":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_to_socket_addrs() {
+        let hosts = BindHosts::new(vec![
+            String::from("127.0.0.1"),
+            String::from("::"),
+            String::from("[::1]"),
+            String::from("127.0.0.1"),
+        ]);
+        let addrs = hosts.to_socket_addrs(9000).unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "127.0.0.1:9000".parse::<SocketAddr>().unwrap(),
+                "[::]:9000".parse().unwrap(),
+                "[::1]:9000".parse().unwrap(),
+            ]
+        );
+        assert!(BindHosts::single("not a host").to_socket_addrs(9000).is_err());
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(BindHosts::single("localhost").is_loopback());
+        assert!(BindHosts::new(vec![String::from("127.0.0.1"), String::from("[::1]")]).is_loopback());
+        assert!(BindHosts::single("::ffff:127.0.0.1").is_loopback());
+        assert!(!BindHosts::new(vec![String::from("127.0.0.1"), String::from("::")]).is_loopback());
+        assert!(!BindHosts::single("0.0.0.0").is_loopback());
+    }
+}
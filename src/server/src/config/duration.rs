@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use lazy_static::lazy_static;
+use serde::{
+    de::{self, IntoDeserializer, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::HashSet, fmt, ops::Deref, sync::Mutex, time::Duration};
+
+lazy_static! {
+    // The warned legacy values, since the config is deserialized again for each env override.
+    static ref LEGACY_WARNED: Mutex<HashSet<(u64, u64)>> = Mutex::new(HashSet::new());
+}
+
+/// The duration of the config, which is deserialized with the humantime format, e.g: "5s", "10m", "3600000ms",
+/// and the bare integers (deprecated) are interpreted with the legacy unit of the field for the backward
+/// compatibility, i.e: the milliseconds of the LEGACY_UNIT_MILLIS, see: DurationSecs and DurationMillis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConfigDuration<const LEGACY_UNIT_MILLIS: u64>(Duration);
+
+/// The duration of which the legacy bare integers are the seconds, e.g: 'connect-timeout: 10'
+pub type DurationSecs = ConfigDuration<1000>;
+
+/// The duration of which the legacy bare integers are the milliseconds, e.g: 'jwt-validity-ak: 3600000'
+pub type DurationMillis = ConfigDuration<1>;
+
+impl<const LEGACY_UNIT_MILLIS: u64> ConfigDuration<LEGACY_UNIT_MILLIS> {
+    pub const fn new(duration: Duration) -> Self {
+        ConfigDuration(duration)
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        ConfigDuration(Duration::from_secs(secs))
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        ConfigDuration(Duration::from_millis(millis))
+    }
+
+    pub const fn as_duration(&self) -> Duration {
+        self.0
+    }
+
+    fn from_legacy(value: u64) -> Self {
+        let duration = Duration::from_millis(value.saturating_mul(LEGACY_UNIT_MILLIS));
+        if LEGACY_WARNED.lock().unwrap().insert((value, LEGACY_UNIT_MILLIS)) {
+            eprintln!(
+                "WARNING: The bare integer duration '{}' is deprecated, which is interpreted as '{}' by the legacy unit of the field, please use the humantime format instead, e.g: '5s', '10m'",
+                value,
+                humantime_serde::re::humantime::format_duration(duration)
+            );
+        }
+        ConfigDuration(duration)
+    }
+}
+
+impl<const LEGACY_UNIT_MILLIS: u64> Deref for ConfigDuration<LEGACY_UNIT_MILLIS> {
+    type Target = Duration;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const LEGACY_UNIT_MILLIS: u64> From<Duration> for ConfigDuration<LEGACY_UNIT_MILLIS> {
+    fn from(duration: Duration) -> Self {
+        ConfigDuration(duration)
+    }
+}
+
+impl<const LEGACY_UNIT_MILLIS: u64> From<ConfigDuration<LEGACY_UNIT_MILLIS>> for Duration {
+    fn from(duration: ConfigDuration<LEGACY_UNIT_MILLIS>) -> Self {
+        duration.0
+    }
+}
+
+impl<const LEGACY_UNIT_MILLIS: u64> fmt::Display for ConfigDuration<LEGACY_UNIT_MILLIS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime_serde::re::humantime::format_duration(self.0))
+    }
+}
+
+impl<const LEGACY_UNIT_MILLIS: u64> Serialize for ConfigDuration<LEGACY_UNIT_MILLIS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        humantime_serde::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, const LEGACY_UNIT_MILLIS: u64> Deserialize<'de> for ConfigDuration<LEGACY_UNIT_MILLIS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DurationVisitor<const U: u64>;
+
+        impl<'de, const U: u64> Visitor<'de> for DurationVisitor<U> {
+            type Value = ConfigDuration<U>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a duration, e.g: '5s', '10m', '3600000ms' or the (deprecated) bare integer")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                Ok(ConfigDuration::from_legacy(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                u64::try_from(value)
+                    .map(ConfigDuration::from_legacy)
+                    .map_err(|_| E::custom(format!("invalid negative duration: {}", value)))
+            }
+
+            // Notice: The env overrides are always the strings, e.g: BOTWAF__SERVICES__FORWARD__CONNECT_TIMEOUT=10
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                let value = value.trim();
+                if let Ok(legacy) = value.parse::<u64>() {
+                    return Ok(ConfigDuration::from_legacy(legacy));
+                }
+                let deserializer: de::value::StrDeserializer<E> = value.into_deserializer();
+                humantime_serde::deserialize(deserializer).map(ConfigDuration)
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor::<LEGACY_UNIT_MILLIS>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Timeouts {
+        #[serde(rename = "connect-timeout")]
+        connect_timeout: DurationSecs,
+        #[serde(rename = "jwt-validity-ak")]
+        jwt_validity_ak: Option<DurationMillis>,
+    }
+
+    #[test]
+    fn test_humantime_round_trip() {
+        let timeouts: Timeouts =
+            serde_json::from_str(r#"{"connect-timeout":"1m 30s","jwt-validity-ak":"3600000ms"}"#).unwrap();
+        assert_eq!(*timeouts.connect_timeout, Duration::from_secs(90));
+        assert_eq!(timeouts.jwt_validity_ak, Some(DurationMillis::from_secs(3600)));
+
+        let json = serde_json::to_string(&timeouts).unwrap();
+        assert_eq!(json, r#"{"connect-timeout":"1m 30s","jwt-validity-ak":"1h"}"#);
+        assert_eq!(serde_json::from_str::<Timeouts>(&json).unwrap(), timeouts);
+    }
+
+    #[test]
+    fn test_legacy_bare_integers_with_field_unit() {
+        let timeouts: Timeouts = serde_json::from_str(r#"{"connect-timeout":10,"jwt-validity-ak":3600}"#).unwrap();
+        assert_eq!(timeouts.connect_timeout, DurationSecs::from_secs(10));
+        // The legacy milliseconds, i.e: 3.6s instead of an hour.
+        assert_eq!(timeouts.jwt_validity_ak, Some(DurationMillis::from_millis(3600)));
+
+        // The env overrides are the strings.
+        let timeouts: Timeouts = serde_json::from_str(r#"{"connect-timeout":"10","jwt-validity-ak":null}"#).unwrap();
+        assert_eq!(timeouts.connect_timeout, DurationSecs::from_secs(10));
+        assert_eq!(timeouts.jwt_validity_ak, None);
+    }
+
+    #[test]
+    fn test_invalid_durations() {
+        assert!(serde_json::from_str::<Timeouts>(r#"{"connect-timeout":"10 parsecs"}"#).is_err());
+        assert!(serde_json::from_str::<Timeouts>(r#"{"connect-timeout":-1}"#).is_err());
+        assert!(serde_json::from_str::<Timeouts>(r#"{"connect-timeout":true}"#).is_err());
+    }
+}
@@ -18,7 +18,10 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod bind_hosts;
 pub mod config;
 pub mod constant;
+pub mod duration;
 pub mod resources;
+pub mod secrets;
 pub mod swagger;
@@ -0,0 +1,429 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::config::config::{
+    AppConfigProperties, FileSecretsProperties, SecretsProperties, SecretsProviderType, VaultSecretsProperties,
+};
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use std::{collections::HashMap, env, fs, future::Future, path::Path, sync::RwLock};
+
+// The names of the secrets resolved from the provider, which override the configured values.
+pub const SECRET_JWT: &str = "jwt-secret";
+pub const SECRET_REDIS_PASSWORD: &str = "cache-redis-password";
+pub const SECRET_APPDB_POSTGRES_PASSWORD: &str = "appdb-postgres-password";
+pub const SECRET_APPDB_MONGODB_URL: &str = "appdb-mongodb-url";
+pub const SECRET_VECDB_PGVECTOR_PASSWORD: &str = "vecdb-pgvector-password";
+pub const SECRET_LLM_EMBEDDING_API_KEY: &str = "llm-embedding-api-key";
+pub const SECRET_LLM_GENERATE_API_KEY: &str = "llm-generate-api-key";
+pub const SECRET_UPSTREAM_SIGNALS: &str = "upstream-signals-secret";
+
+pub const SECRET_NAMES: &[&str] = &[
+    SECRET_JWT,
+    SECRET_REDIS_PASSWORD,
+    SECRET_APPDB_POSTGRES_PASSWORD,
+    SECRET_APPDB_MONGODB_URL,
+    SECRET_VECDB_PGVECTOR_PASSWORD,
+    SECRET_LLM_EMBEDDING_API_KEY,
+    SECRET_LLM_GENERATE_API_KEY,
+    SECRET_UPSTREAM_SIGNALS,
+];
+
+lazy_static! {
+    // The renewable leases of the fetched Vault secrets.
+    static ref VAULT_LEASES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+/// The external secrets backend, which is resolved synchronously on loading the config.
+pub trait SecretProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Fetch the secrets of the names, the absent secrets are omitted (i.e: keep the configured values).
+    fn fetch(&self, names: &[&str]) -> Result<HashMap<String, String>, Error>;
+}
+
+pub fn build_provider(config: &SecretsProperties) -> Box<dyn SecretProvider> {
+    match config.provider {
+        SecretsProviderType::ENV => Box::new(EnvSecretProvider {}),
+        SecretsProviderType::FILE => Box::new(FileSecretProvider::new(&config.file)),
+        SecretsProviderType::VAULT => Box::new(VaultSecretProvider::new(&config.vault)),
+    }
+}
+
+/// Resolve the secrets from the configured provider, and returns the config with the secrets overridden.
+pub fn resolve(config: &AppConfigProperties) -> Result<AppConfigProperties, Error> {
+    let provider = build_provider(&config.secrets);
+    let secrets = provider.fetch(SECRET_NAMES).map_err(|e| {
+        anyhow!(
+            "Failed to resolve the secrets from the {} provider. cause: {}",
+            provider.name(),
+            e
+        )
+    })?;
+    if !secrets.is_empty() {
+        let mut names = secrets.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        tracing::info!(
+            "Resolved the secrets from the {} provider: {:?}",
+            provider.name(),
+            names
+        );
+    }
+    Ok(apply(config, &secrets))
+}
+
+fn apply(config: &AppConfigProperties, secrets: &HashMap<String, String>) -> AppConfigProperties {
+    let mut config = config.to_owned();
+    let get = |name: &str| secrets.get(name).cloned();
+    if let Some(secret) = get(SECRET_JWT) {
+        config.auth.jwt_secret = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_REDIS_PASSWORD) {
+        config.cache.redis.password = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_APPDB_POSTGRES_PASSWORD) {
+        config.appdb.postgres.inner.password = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_APPDB_MONGODB_URL) {
+        config.appdb.mongodb.url = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_VECDB_PGVECTOR_PASSWORD) {
+        config.vecdb.pg_vector.inner.password = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_LLM_EMBEDDING_API_KEY) {
+        config.services.llm.embedding.api_key = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_LLM_GENERATE_API_KEY) {
+        config.services.llm.generate.api_key = Some(secret);
+    }
+    if let Some(secret) = get(SECRET_UPSTREAM_SIGNALS) {
+        config.services.forward.upstream_signals.signing_secret = Some(secret);
+    }
+    config
+}
+
+/// The default provider (i.e: the current behavior), the secrets are the configured values with the
+/// BOTWAF__ env overrides, e.g: BOTWAF__AUTH__JWT_SECRET
+pub struct EnvSecretProvider {}
+
+impl SecretProvider for EnvSecretProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn fetch(&self, _names: &[&str]) -> Result<HashMap<String, String>, Error> {
+        Ok(HashMap::new())
+    }
+}
+
+/// The secret per file named by the secret name, e.g: the docker or kubernetes mounted secrets.
+pub struct FileSecretProvider {
+    config: FileSecretsProperties,
+}
+
+impl FileSecretProvider {
+    pub fn new(config: &FileSecretsProperties) -> Self {
+        FileSecretProvider {
+            config: config.to_owned(),
+        }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn fetch(&self, names: &[&str]) -> Result<HashMap<String, String>, Error> {
+        let mut secrets = HashMap::new();
+        for name in names {
+            let path = Path::new(&self.config.dir).join(name);
+            if !path.exists() {
+                continue;
+            }
+            let secret = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read the secret file {}. cause: {}", path.display(), e))?;
+            secrets.insert(name.to_string(), secret.trim().to_owned());
+        }
+        Ok(secrets)
+    }
+}
+
+/// The HashiCorp Vault KV v2 secrets, see: https://developer.hashicorp.com/vault/api-docs/secret/kv/kv-v2
+pub struct VaultSecretProvider {
+    config: VaultSecretsProperties,
+}
+
+impl VaultSecretProvider {
+    pub fn new(config: &VaultSecretsProperties) -> Self {
+        VaultSecretProvider {
+            config: config.to_owned(),
+        }
+    }
+
+    /// Start renewing the token and the leases of the fetched secrets periodically if enabled.
+    pub fn start_renewal(config: &SecretsProperties) {
+        if config.provider != SecretsProviderType::VAULT || config.vault.renew_interval_secs.is_zero() {
+            return;
+        }
+        let provider = VaultSecretProvider::new(&config.vault);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(*provider.config.renew_interval_secs);
+            // Skip the immediate tick, the secrets are just fetched.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = provider.renew().await {
+                    tracing::error!("Failed to renew the Vault token and leases. cause: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Renew the token itself and the renewable leases of the fetched secrets.
+    pub async fn renew(&self) -> Result<(), Error> {
+        let client = self.build_client()?;
+        self.request(&client, reqwest::Method::POST, "auth/token/renew-self")?
+            .send()
+            .await?
+            .error_for_status()?;
+        let leases = VAULT_LEASES.read().unwrap().to_owned();
+        for lease_id in leases {
+            self.request(&client, reqwest::Method::PUT, "sys/leases/renew")?
+                .json(&serde_json::json!({ "lease_id": lease_id }))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self) -> Result<HashMap<String, String>, Error> {
+        let client = self.build_client()?;
+        let path = format!(
+            "{}/data/{}",
+            self.config.mount.trim_matches('/'),
+            self.config.path.trim_matches('/')
+        );
+        let resp = self
+            .request(&client, reqwest::Method::GET, &path)?
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+
+        if resp["renewable"].as_bool().unwrap_or(false) {
+            if let Some(lease_id) = resp["lease_id"].as_str().filter(|id| !id.is_empty()) {
+                VAULT_LEASES.write().unwrap().push(lease_id.to_owned());
+            }
+        }
+        let data = resp["data"]["data"]
+            .as_object()
+            .ok_or_else(|| anyhow!("No such KV v2 secret data of '{}'", path))?;
+        Ok(data
+            .iter()
+            .filter_map(|(name, value)| value.as_str().map(|v| (name.to_owned(), v.to_owned())))
+            .collect())
+    }
+
+    fn build_client(&self) -> Result<reqwest::Client, Error> {
+        Ok(reqwest::ClientBuilder::new().timeout(*self.config.timeout_ms).build()?)
+    }
+
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        path: &str,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let token = self
+            .config
+            .auth_token
+            .to_owned()
+            .or_else(|| env::var("VAULT_TOKEN").ok())
+            .ok_or_else(|| anyhow!("The Vault token is required, set by 'secrets.vault.auth-token' or VAULT_TOKEN"))?;
+        let url = format!("{}/v1/{}", self.config.address.trim_end_matches('/'), path);
+        let mut builder = client.request(method, url).header("X-Vault-Token", token);
+        if let Some(namespace) = &self.config.namespace {
+            builder = builder.header("X-Vault-Namespace", namespace);
+        }
+        Ok(builder)
+    }
+}
+
+impl SecretProvider for VaultSecretProvider {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    fn fetch(&self, names: &[&str]) -> Result<HashMap<String, String>, Error> {
+        let mut secrets = block_on(self.read())?;
+        secrets.retain(|name, _| names.contains(&name.as_str()));
+        Ok(secrets)
+    }
+}
+
+// Run the future on a dedicated thread with its own runtime, since the config is loaded synchronously both
+// inside and outside of the tokio runtime.
+fn block_on<F: Future + Send>(future: F) -> F::Output
+where
+    F::Output: Send,
+{
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to build the runtime of resolving secrets")
+                    .block_on(future)
+            })
+            .join()
+            .expect("Failed to join the thread of resolving secrets")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::AppConfig;
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+        Json, Router,
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    // The mock Vault of the KV v2 secret 'secret/botwaf' with the token 'test-token'.
+    async fn spawn_mock_vault(renewed: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/v1/secret/data/botwaf",
+                get(|headers: HeaderMap| async move {
+                    if headers.get("X-Vault-Token").and_then(|v| v.to_str().ok()) != Some("test-token") {
+                        return Err(StatusCode::FORBIDDEN);
+                    }
+                    Ok(Json(serde_json::json!({
+                        "lease_id": "",
+                        "renewable": false,
+                        "lease_duration": 0,
+                        "data": {
+                            "data": {
+                                "jwt-secret": "vault-jwt-secret",
+                                "appdb-postgres-password": "vault-appdb-password",
+                                "llm-generate-api-key": "vault-generate-api-key",
+                                "unknown-secret": "ignored",
+                            },
+                            "metadata": { "version": 3 }
+                        }
+                    })))
+                }),
+            )
+            .route(
+                "/v1/auth/token/renew-self",
+                post(move || {
+                    let renewed = renewed.clone();
+                    async move {
+                        renewed.fetch_add(1, Ordering::SeqCst);
+                        StatusCode::OK
+                    }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    fn create_vault_config(address: &str, token: &str) -> AppConfigProperties {
+        let mut config = AppConfigProperties::default();
+        config.appdb.postgres.inner.password = Some(String::from("configured-password"));
+        config.cache.redis.password = Some(String::from("configured-redis-password"));
+        config.secrets.provider = SecretsProviderType::VAULT;
+        config.secrets.vault = VaultSecretsProperties {
+            address: address.to_owned(),
+            auth_token: Some(token.to_owned()),
+            ..VaultSecretsProperties::default()
+        };
+        config
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resolve_secrets_from_mock_vault() {
+        let renewed = Arc::new(AtomicUsize::new(0));
+        let address = spawn_mock_vault(renewed.clone()).await;
+
+        let config = AppConfig::new(&create_vault_config(&address, "test-token"));
+        assert_eq!(config.auth_jwt_secret, "vault-jwt-secret");
+        assert_eq!(config.auth.jwt_secret.as_deref(), Some("vault-jwt-secret"));
+        assert_eq!(
+            config.appdb.postgres.inner.password.as_deref(),
+            Some("vault-appdb-password")
+        );
+        assert_eq!(
+            config.services.llm.generate.api_key.as_deref(),
+            Some("vault-generate-api-key")
+        );
+        // The absent secrets keep the configured values.
+        assert_eq!(
+            config.cache.redis.password.as_deref(),
+            Some("configured-redis-password")
+        );
+
+        VaultSecretProvider::new(&config.secrets.vault).renew().await.unwrap();
+        assert_eq!(renewed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resolve_secrets_failed_with_invalid_token() {
+        let address = spawn_mock_vault(Arc::new(AtomicUsize::new(0))).await;
+        let err = resolve(&create_vault_config(&address, "invalid-token")).unwrap_err();
+        assert!(err.to_string().contains("vault provider"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_secrets_from_files() {
+        let dir = env::temp_dir().join(format!("botwaf-ut-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(SECRET_JWT), "file-jwt-secret\n").unwrap();
+
+        let mut config = AppConfigProperties::default();
+        config.secrets.provider = SecretsProviderType::FILE;
+        config.secrets.file.dir = dir.to_string_lossy().to_string();
+        let resolved = resolve(&config).unwrap();
+        assert_eq!(resolved.auth.jwt_secret.as_deref(), Some("file-jwt-secret"));
+        assert_eq!(
+            resolved.appdb.postgres.inner.password,
+            config.appdb.postgres.inner.password
+        );
+    }
+
+    #[test]
+    fn test_resolve_secrets_from_env_keeps_configured() {
+        let mut config = AppConfigProperties::default();
+        config.auth.jwt_secret = Some(String::from("configured-jwt-secret"));
+        let resolved = resolve(&config).unwrap();
+        assert_eq!(resolved.auth.jwt_secret.as_deref(), Some("configured-jwt-secret"));
+    }
+}
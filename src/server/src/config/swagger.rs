@@ -18,12 +18,85 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use super::config::{self, AppConfig};
-use crate::modules::llm::route::knowledge_router::__path_handle_knowledge_upload;
-use botwaf_types::modules::llm::knowledge::KnowledgeUploadInfo;
+use super::config::AppConfig;
+use crate::modules::llm::route::knowledge_router::{
+    __path_handle_knowledge_cleanup, __path_handle_knowledge_namespaces, __path_handle_knowledge_upload,
+};
+use crate::modules::modsec::route::data_file_router::{
+    __path_handle_data_file_delete, __path_handle_data_file_save, __path_handle_data_files_list,
+};
+use crate::modules::modsec::route::rule_router::{
+    __path_handle_rule_changeset_add_change, __path_handle_rule_changeset_apply, __path_handle_rule_changeset_approve,
+    __path_handle_rule_changeset_create, __path_handle_rule_changeset_get, __path_handle_rule_changeset_rollback,
+    __path_handle_rule_false_positive, __path_handle_rule_promote, __path_handle_rule_rollback,
+    __path_handle_rule_test, __path_handle_rule_version_save, __path_handle_rule_versions_list,
+    __path_handle_rules_engine_capabilities, __path_handle_rules_list,
+};
+use crate::sys::route::auth_router::{
+    __path_handle_callback_github, __path_handle_callback_oidc, __path_handle_connect_github,
+    __path_handle_connect_oidc, __path_handle_logout, __path_handle_password_change, __path_handle_password_pubkey,
+    __path_handle_password_verify, __path_handle_wallet_ethers_verify,
+};
+use crate::sys::route::bootstrap_router::__path_handle_bootstrap;
+use crate::sys::route::dead_letter_router::{
+    __path_handle_dead_letter_get, __path_handle_dead_letters_list, __path_handle_dead_letters_replay,
+};
+use crate::sys::route::signing_key_router::{
+    __path_handle_signing_key_retire, __path_handle_signing_key_rotate, __path_handle_signing_keys_list,
+};
+use crate::sys::route::stats_router::__path_handle_dependency_stats;
+use crate::sys::route::user_router::{
+    __path_handle_delete_user, __path_handle_get_current_user, __path_handle_post_current_user,
+    __path_handle_query_users, __path_handle_reset_user_password, __path_handle_save_user,
+    __path_handle_set_user_status,
+};
+use axum::{http::header, routing::get, Router};
+use botwaf_types::modules::llm::knowledge::{KnowledgeNamespaceStats, KnowledgeUploadInfo, VectorCleanupResult};
+use botwaf_types::modules::modsec::data_file::{
+    DataFile, DataFileFormat, DeleteDataFileRequest, DeleteDataFileResponse, QueryDataFileResponse,
+    SaveDataFileRequest, SaveDataFileResponse,
+};
+use botwaf_types::modules::modsec::engine::{ModSecEngineCapabilities, ModSecEngineCapability};
+use botwaf_types::modules::modsec::rule::{
+    ModSecRuleInfo, ModSecRuleSource, ModSecRuleState, ModSecShadowStats, PromoteRuleRequest, PromoteRuleResponse,
+    ReportFalsePositiveRequest,
+};
+use botwaf_types::modules::modsec::rule_changeset::{
+    CreateRuleChangesetRequest, ModSecRuleChange, ModSecRuleChangeOp, ModSecRuleChangeset, ModSecRuleChangesetState,
+};
+use botwaf_types::modules::modsec::rule_test::{
+    RuleTestDecision, RuleTestResult, RuleTestSample, TestRuleRequest, TestRuleResponse,
+};
+use botwaf_types::modules::modsec::rule_version::{
+    ModSecRuleVersion, ModSecRuleVersionDiff, QueryRuleVersionResponse, RollbackRuleRequest, SaveRuleVersionRequest,
+};
+use botwaf_types::sys::auth::{
+    CallbackGithubRequest, CallbackOidcRequest, ChangePasswordRequest, EthersWalletLoginRequest, LoggedResponse,
+    LoginChallengeResponse, LogoutRequest, PasswordLoginRequest, PasswordPubKeyRequest, PasswordPubKeyResponse,
+    ResetPasswordRequest, TokenWrapper,
+};
+use botwaf_types::sys::bootstrap::{BootstrapRequest, BootstrapResponse};
+use botwaf_types::sys::dead_letter::{
+    DeadLetter, DeadLetterState, QueryDeadLetterResponse, ReplayDeadLetterRequest, ReplayDeadLetterResponse,
+    ReplayDeadLetterResult,
+};
+use botwaf_types::sys::event::{
+    BotwafEvent, ComponentFailedV1, EventPayload, EventSchema, FailOpenBudgetV1, ProbeFailedV1, RulePromotedV1,
+};
+use botwaf_types::sys::signing_key::{
+    QuerySigningKeyResponse, RotateSigningKeyRequest, RotateSigningKeyResponse, SigningKey, SigningKeyState,
+};
+use botwaf_types::sys::stats::{DependencyStats, QueryDependencyStatsResponse};
+use botwaf_types::sys::user::{
+    DeleteUserRequest, DeleteUserResponse, QueryUserResponse, SaveUserRequest, SaveUserRequestWith, SaveUserResponse,
+    SetUserStatusRequest, SetUserStatusResponse, User,
+};
+use botwaf_types::{PageResponse, RespBase};
+use lazy_static::lazy_static;
 use std::collections::BTreeMap;
+use std::sync::RwLock;
 use utoipa::openapi::{PathItem, Paths};
-use utoipa::OpenApi;
+use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
 #[derive(utoipa::OpenApi)]
@@ -42,24 +115,173 @@ use utoipa_swagger_ui::SwaggerUi;
     //security((), "my_auth" = ["read:items", "edit:items"], "token_jwt" = []),
     external_docs(url = "https://github.com/wl4g/botwaf", description = "More about our APIs"),
     paths(
+        // Authentication
+        handle_password_pubkey,
+        handle_password_verify,
+        handle_connect_oidc,
+        handle_connect_github,
+        handle_callback_oidc,
+        handle_callback_github,
+        handle_wallet_ethers_verify,
+        handle_logout,
+        handle_password_change,
+        // User
+        handle_get_current_user,
+        handle_post_current_user,
+        handle_query_users,
+        handle_save_user,
+        handle_delete_user,
+        handle_set_user_status,
+        handle_reset_user_password,
         // Knowledge
         handle_knowledge_upload,
+        handle_knowledge_namespaces,
+        handle_knowledge_cleanup,
+        // Rules
+        handle_rules_list,
+        handle_rules_engine_capabilities,
+        handle_rule_promote,
+        handle_rule_false_positive,
+        handle_rule_test,
+        handle_rule_version_save,
+        handle_rule_versions_list,
+        handle_rule_rollback,
+        handle_rule_changeset_create,
+        handle_rule_changeset_get,
+        handle_rule_changeset_add_change,
+        handle_rule_changeset_approve,
+        handle_rule_changeset_apply,
+        handle_rule_changeset_rollback,
+        handle_data_files_list,
+        handle_data_file_save,
+        handle_data_file_delete,
+        // Bootstrap
+        handle_bootstrap,
+        // Dead Letter
+        handle_dead_letters_list,
+        handle_dead_letter_get,
+        handle_dead_letters_replay,
+        // Signing Key
+        handle_signing_keys_list,
+        handle_signing_key_rotate,
+        handle_signing_key_retire,
+        // Stats
+        handle_dependency_stats,
     ),
     components(
         schemas(
+            // Common
+            RespBase,
+            PageResponse,
+            // Module of Authentication
+            PasswordPubKeyRequest,
+            PasswordPubKeyResponse,
+            PasswordLoginRequest,
+            LoginChallengeResponse,
+            CallbackOidcRequest,
+            CallbackGithubRequest,
+            EthersWalletLoginRequest,
+            LoggedResponse,
+            TokenWrapper,
+            LogoutRequest,
+            ChangePasswordRequest,
+            ResetPasswordRequest,
+            // Module of User
+            User,
+            QueryUserResponse,
+            SaveUserRequest,
+            SaveUserRequestWith,
+            SaveUserResponse,
+            DeleteUserRequest,
+            DeleteUserResponse,
+            SetUserStatusRequest,
+            SetUserStatusResponse,
             // Module of Knowledge
             KnowledgeUploadInfo,
+            KnowledgeNamespaceStats,
+            VectorCleanupResult,
+            // Module of Rules
+            ModSecRuleInfo,
+            ModSecRuleSource,
+            ModSecRuleState,
+            ModSecShadowStats,
+            ModSecEngineCapabilities,
+            ModSecEngineCapability,
+            PromoteRuleRequest,
+            PromoteRuleResponse,
+            ReportFalsePositiveRequest,
+            ModSecRuleVersion,
+            ModSecRuleVersionDiff,
+            SaveRuleVersionRequest,
+            RollbackRuleRequest,
+            QueryRuleVersionResponse,
+            ModSecRuleChangeset,
+            ModSecRuleChangesetState,
+            ModSecRuleChange,
+            ModSecRuleChangeOp,
+            CreateRuleChangesetRequest,
+            TestRuleRequest,
+            TestRuleResponse,
+            RuleTestSample,
+            RuleTestResult,
+            RuleTestDecision,
+            DataFile,
+            DataFileFormat,
+            QueryDataFileResponse,
+            SaveDataFileRequest,
+            SaveDataFileResponse,
+            DeleteDataFileRequest,
+            DeleteDataFileResponse,
+            // Module of Bootstrap
+            BootstrapRequest,
+            BootstrapResponse,
+            // Module of Dead Letter
+            DeadLetter,
+            DeadLetterState,
+            QueryDeadLetterResponse,
+            ReplayDeadLetterRequest,
+            ReplayDeadLetterResult,
+            ReplayDeadLetterResponse,
+            // Module of Event
+            EventSchema,
+            EventPayload,
+            BotwafEvent,
+            FailOpenBudgetV1,
+            ProbeFailedV1,
+            RulePromotedV1,
+            ComponentFailedV1,
+            // Module of Signing Key
+            SigningKey,
+            SigningKeyState,
+            QuerySigningKeyResponse,
+            RotateSigningKeyRequest,
+            RotateSigningKeyResponse,
+            // Module of Stats
+            DependencyStats,
+            QueryDependencyStatsResponse,
         )
-    ),
-    modifiers(&ApiPathPrefixer)
+    )
 )]
 struct ApiDoc;
 
-struct ApiPathPrefixer;
+lazy_static! {
+    // The API docs of the routers out of the server crate, e.g: forwarder, updater, verifier.
+    static ref ADDITION_API_DOCS: RwLock<Vec<utoipa::openapi::OpenApi>> = RwLock::new(Vec::new());
+}
+
+/// Register the API doc of the addition routers (e.g: forwarder, updater, verifier), which will be
+/// merged into the aggregated OpenAPI spec, should be called before the web server starting.
+pub fn register(openapi: utoipa::openapi::OpenApi) {
+    ADDITION_API_DOCS.write().unwrap().push(openapi);
+}
+
+struct ApiPathPrefixer<'a> {
+    ctx_path: &'a Option<String>,
+}
 
-impl utoipa::Modify for ApiPathPrefixer {
+impl Modify for ApiPathPrefixer<'_> {
     fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-        let ctx_path = &config::get_config().server.context_path;
+        let ctx_path = self.ctx_path.as_deref().filter(|cp| *cp != "/");
 
         let old_paths = std::mem::take(&mut openapi.paths);
         let mut new_paths_map: BTreeMap<String, PathItem> = old_paths
@@ -83,7 +305,23 @@ impl utoipa::Modify for ApiPathPrefixer {
     }
 }
 
-pub fn init(config: &AppConfig) -> SwaggerUi {
+/// Build the aggregated OpenAPI spec of the server and the registered addition routers, the
+/// paths are prefixed with the configured context path.
+pub fn build_openapi(config: &AppConfig) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    for addition in ADDITION_API_DOCS.read().unwrap().iter() {
+        openapi.merge(addition.to_owned());
+    }
+    ApiPathPrefixer {
+        ctx_path: &config.server.context_path,
+    }
+    .modify(&mut openapi);
+    openapi
+}
+
+/// Serving the raw OpenAPI spec at the configured 'openapi-url' regardless of whether the swagger
+/// UI is enabled, and the swagger UI router is merged only if enabled.
+pub fn init(config: &AppConfig) -> Router {
     // Manual build of OpenAPI.
     // use utoipa::openapi::{ ContactBuilder, InfoBuilder, LicenseBuilder, Paths };
     // let info = InfoBuilder::new()
@@ -112,12 +350,25 @@ pub fn init(config: &AppConfig) -> SwaggerUi {
     // let openapi = utoipa::openapi::OpenApi::new(info, paths);
 
     // Auto build of OpenAPI.
-    let openapi = ApiDoc::openapi();
+    let openapi = build_openapi(config);
+    let openapi_json = openapi.to_json().expect("Failed to serialize the OpenAPI spec");
 
     let swagger_ui_path = join_context_path(&config, config.swagger.ui_path.to_string());
     let openapi_url = join_context_path(&config, config.swagger.openapi_url.to_string());
 
-    SwaggerUi::new(swagger_ui_path).url(openapi_url, openapi)
+    let router = Router::new().route(
+        &openapi_url,
+        get(move || {
+            let openapi_json = openapi_json.to_owned();
+            async move { ([(header::CONTENT_TYPE, "application/json")], openapi_json) }
+        }),
+    );
+    if !config.swagger.enabled {
+        return router;
+    }
+    // The openapi.json is served by above route, so the swagger UI only refers it.
+    let openapi_value = serde_json::to_value(&openapi).unwrap_or_default();
+    router.merge(SwaggerUi::new(swagger_ui_path).external_url_unchecked(openapi_url, openapi_value))
 }
 
 pub fn join_context_path(config: &AppConfig, path: String) -> String {
@@ -18,3 +18,5 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 pub mod state;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_support;
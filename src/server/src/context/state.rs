@@ -20,15 +20,42 @@
 
 use crate::{
     cache::{memory::StringMemoryCache, redis::StringRedisCache, CacheContainer},
-    config::config::{self, AppConfig, AppDBType},
+    config::config::{AppConfig, AppDBType, OAuth2Properties, OidcProperties},
     mgmt::health::{MongoChecker, RedisClusterChecker, SQLiteChecker},
-    modules::llm::handler::llm_base::{ILLMHandler, LLMManager},
-    store::RepositoryContainer,
-    sys::store::{
-        users_mongo::UserMongoRepository, users_postgresql::UserPostgresRepository, users_sqlite::UserSQLiteRepository,
+    modules::{
+        forward::{forwarder::IForwarder, ipfilter::IPFilter},
+        llm::handler::{
+            llm_base::{ILLMHandler, LLMManager},
+            llm_langchain::LangchainLLMHandler,
+        },
+        modsec::{
+            data_file::DataFileManager,
+            rule_exclusion::RuleExclusions,
+            rule_loader,
+            rule_promotion::ShadowRule,
+            rule_swap::RuleSwapDebouncer,
+            store::{
+                data_files_mongo::DataFileMongoRepository, data_files_postgresql::DataFilePostgresRepository,
+                data_files_sqlite::DataFileSQLiteRepository,
+            },
+        },
+    },
+    store::{AsyncRepository, RepositoryContainer},
+    sys::{
+        bootstrap::{BootstrapManager, BootstrapSeed},
+        store::{
+            bootstrap_mongo::BootstrapMongoRepository, bootstrap_postgresql::BootstrapPostgresRepository,
+            bootstrap_sqlite::BootstrapSQLiteRepository, users_mongo::UserMongoRepository,
+            users_postgresql::UserPostgresRepository, users_sqlite::UserSQLiteRepository,
+        },
     },
+    util::token_verify_cache::TokenVerifyCache,
+};
+use arc_swap::ArcSwap;
+use botwaf_types::{
+    modules::modsec::{data_file::DataFile, rule::ModSecRuleInfo},
+    sys::{bootstrap::Bootstrap, user::User},
 };
-use botwaf_types::sys::user::User;
 use botwaf_utils::httpclients;
 use modsecurity::{ModSecurity, Rules};
 use oauth2::basic::BasicClient;
@@ -43,35 +70,144 @@ pub struct BotwafState {
     pub oidc_client: Option<Arc<openidconnect::core::CoreClient>>,
     pub github_client: Option<Arc<BasicClient>>,
     pub default_http_client: Arc<reqwest::Client>,
+    // The http client of the OIDC/OAuth2 providers with the outbound proxy, see: AuthProperties::http_proxy
+    pub oauth2_http_client: Arc<reqwest::Client>,
+    // The local verification cache of the access tokens in front of the logout blacklist.
+    pub token_verify_cache: Arc<TokenVerifyCache>,
     // The Health checker.
     pub sqlite_checker: SQLiteChecker,
     pub mongo_checker: MongoChecker,
     pub redis_cluster_checker: RedisClusterChecker,
     // The System Module repositories.
     pub user_repo: Arc<Mutex<RepositoryContainer<User>>>,
+    pub bootstrap_manager: Arc<BootstrapManager>,
     // The Service Module repositories.
+    pub data_file_repo: Arc<Mutex<RepositoryContainer<DataFile>>>,
+    // Notice: The rules are swappable, since recompiled when the referenced data files changed.
     pub modsec_engine: Arc<ModSecurity>,
-    pub modsec_rules: Arc<Rules>,
+    pub modsec_rules: Arc<ArcSwap<Rules>>,
+    pub modsec_rule_infos: Arc<ArcSwap<Vec<ModSecRuleInfo>>>,
+    pub modsec_rule_exclusions: Arc<ArcSwap<RuleExclusions>>,
+    // The SHADOW rules which are only evaluated and logged the would-block requests.
+    pub modsec_shadow_rules: Arc<ArcSwap<Vec<ShadowRule>>>,
+    // The debounced apply queue of the rule changes.
+    pub modsec_rule_swap: Arc<RuleSwapDebouncer>,
     pub llm_handler: Arc<dyn ILLMHandler + Send + Sync>,
+    // The data plane components, which are only wired by the forwarder (and standalone) commands.
+    pub ipfilter: Option<Arc<dyn IPFilter + Send + Sync>>,
+    pub forwarder: Option<Arc<dyn IForwarder + Send + Sync>>,
 }
 
-impl BotwafState {
-    pub async fn new(config: &Arc<AppConfig>) -> Self {
-        let cache_config = &config.cache;
+/// The builder of the state, which allows each component to be injected (e.g: the in-memory fakes of the
+/// tests), the others are built from the config with the default implementations.
+#[derive(Default)]
+pub struct BotwafStateBuilder {
+    config: Option<Arc<AppConfig>>,
+    string_cache: Option<Arc<CacheContainer<String>>>,
+    ipfilter: Option<Arc<dyn IPFilter + Send + Sync>>,
+    forwarder: Option<Arc<dyn IForwarder + Send + Sync>>,
+    llm_handler: Option<Arc<dyn ILLMHandler + Send + Sync>>,
+    rules: Option<(Rules, Vec<ModSecRuleInfo>)>,
+}
+
+impl BotwafStateBuilder {
+    pub fn with_config(mut self, config: &Arc<AppConfig>) -> Self {
+        self.config = Some(config.to_owned());
+        self
+    }
+
+    pub fn with_cache(mut self, cache: CacheContainer<String>) -> Self {
+        self.string_cache = Some(Arc::new(cache));
+        self
+    }
+
+    pub fn with_ipfilter(mut self, ipfilter: Arc<dyn IPFilter + Send + Sync>) -> Self {
+        self.ipfilter = Some(ipfilter);
+        self
+    }
+
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn IForwarder + Send + Sync>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    pub fn with_llm(mut self, llm_handler: Arc<dyn ILLMHandler + Send + Sync>) -> Self {
+        self.llm_handler = Some(llm_handler);
+        self
+    }
+
+    /// The compiled rules instead of loading from the config, notice that they are replaced by the config
+    /// rules once reloaded, e.g: the referenced data files changed.
+    pub fn with_rules(mut self, rules: Rules, rule_infos: Vec<ModSecRuleInfo>) -> Self {
+        self.rules = Some((rules, rule_infos));
+        self
+    }
+
+    /// Build the state, notice that all the components are attempted even if some failed, so that the returned
+    /// error reports all the misconfigured components at once, e.g: both the redis nodes and the DB.
+    pub async fn build(self) -> Result<BotwafState, anyhow::Error> {
+        let config = self
+            .config
+            .ok_or_else(|| anyhow::anyhow!("The config is required to build the Botwaf state"))?;
+        let mut errors = StateInitError::default();
+
+        // Requires the LLM handlers registered, e.g: LLMManager::init()
+        let llm_handler = match self.llm_handler {
+            Some(llm_handler) => Some(llm_handler),
+            None => errors.check(
+                "llm handler",
+                LLMManager::get_implementation(LangchainLLMHandler::NAME.to_owned()),
+            ),
+        };
 
         // Build cacher.
-        let cache_container = CacheContainer::new(
-            Box::new(StringMemoryCache::new(&cache_config.memory)),
-            Box::new(StringRedisCache::new(&cache_config.redis)),
-        );
+        let string_cache = match self.string_cache {
+            Some(string_cache) => Some(string_cache),
+            None => errors
+                .check("redis cache", StringRedisCache::try_new(&config.cache.redis))
+                .map(|redis_cache| {
+                    Arc::new(CacheContainer::new(
+                        Box::new(StringMemoryCache::new(&config.cache.memory)),
+                        Box::new(redis_cache),
+                    ))
+                }),
+        };
+        let config = &config;
 
         // Build auth clients.
+        let oauth2_http_client = errors
+            .check(
+                "oauth2 http client",
+                crate::util::oauth2::build_oauth2_http_client(&config.auth),
+            )
+            .unwrap_or_default();
+        // The relative redirect urls of clients are resolved with the 'auth.external-base-url'.
+        let oidc_config = OidcProperties {
+            redirect_url: config
+                .auth
+                .effective_redirect_url(config.auth.oidc.redirect_url.as_deref()),
+            ..config.auth.oidc.to_owned()
+        };
+        let github_config = OAuth2Properties {
+            redirect_url: config
+                .auth
+                .effective_redirect_url(config.auth.github.redirect_url.as_deref()),
+            ..(*config.auth.github).to_owned()
+        };
         let auth_clients = (
-            crate::util::oidcs::create_oidc_client(&config.auth.oidc)
-                .await
+            errors
+                .check(
+                    "oidc client",
+                    crate::util::oidcs::create_oidc_client(&oidc_config, &oauth2_http_client).await,
+                )
+                .flatten()
                 .map(|client| Arc::new(client)),
-            crate::util::oauth2::create_oauth2_client(&config.auth.github)
-                .await
+            errors
+                .check(
+                    "github client",
+                    crate::util::oauth2::create_oauth2_client(&github_config).await,
+                )
+                .flatten()
                 .map(|client| Arc::new(client)),
         );
 
@@ -80,63 +216,310 @@ impl BotwafState {
 
         // Build App DB repositories.
         let db_config = &config.appdb;
-        let user_repo = RepositoryContainer::new(
+        let user_repo = Arc::new(Mutex::new(RepositoryContainer::new(
             match db_config.db_type {
-                AppDBType::SQLITE => Some(Box::new(UserSQLiteRepository::new(&db_config.sqlite).await.unwrap())),
+                AppDBType::SQLITE => errors.repo("user repository", UserSQLiteRepository::new(&db_config.sqlite).await),
                 _ => None,
             },
             match db_config.db_type {
-                AppDBType::POSTGRESQL => Some(Box::new(
-                    UserPostgresRepository::new(&db_config.postgres).await.unwrap(),
-                )),
+                AppDBType::POSTGRESQL => errors.repo(
+                    "user repository",
+                    UserPostgresRepository::new(&db_config.postgres).await,
+                ),
                 _ => None,
             },
             match db_config.db_type {
-                AppDBType::MONGODB => Some(Box::new(UserMongoRepository::new(&db_config.mongodb).await.unwrap())),
+                AppDBType::MONGODB => {
+                    errors.repo("user repository", UserMongoRepository::new(&db_config.mongodb).await)
+                }
                 _ => None,
             },
-        );
+        )));
+
+        let bootstrap_repo = Arc::new(Mutex::new(RepositoryContainer::new(
+            match db_config.db_type {
+                AppDBType::SQLITE => errors.repo(
+                    "bootstrap repository",
+                    BootstrapSQLiteRepository::new(&db_config.sqlite).await,
+                ),
+                _ => None,
+            },
+            match db_config.db_type {
+                AppDBType::POSTGRESQL => errors.repo(
+                    "bootstrap repository",
+                    BootstrapPostgresRepository::new(&db_config.postgres).await,
+                ),
+                _ => None,
+            },
+            match db_config.db_type {
+                AppDBType::MONGODB => errors.repo(
+                    "bootstrap repository",
+                    BootstrapMongoRepository::new(&db_config.mongodb).await,
+                ),
+                _ => None,
+            },
+        )));
+
+        let data_file_repo = Arc::new(Mutex::new(RepositoryContainer::new(
+            match db_config.db_type {
+                AppDBType::SQLITE => errors.repo(
+                    "data file repository",
+                    DataFileSQLiteRepository::new(&db_config.sqlite).await,
+                ),
+                _ => None,
+            },
+            match db_config.db_type {
+                AppDBType::POSTGRESQL => errors.repo(
+                    "data file repository",
+                    DataFilePostgresRepository::new(&db_config.postgres).await,
+                ),
+                _ => None,
+            },
+            match db_config.db_type {
+                AppDBType::MONGODB => errors.repo(
+                    "data file repository",
+                    DataFileMongoRepository::new(&db_config.mongodb).await,
+                ),
+                _ => None,
+            },
+        )));
+
+        // Notice: Fail before the bootstrap seeding and the data files materializing, which use the repositories.
+        let (Some(llm_handler), Some(string_cache), true) = (llm_handler, string_cache, errors.is_empty()) else {
+            return Err(errors.into());
+        };
+
+        // Load the first-run bootstrap state, or seed the initial administrator for the automated installs.
+        let bootstrap_manager = Arc::new(BootstrapManager::new(
+            config,
+            user_repo.clone(),
+            bootstrap_repo,
+            BootstrapSeed::from_env(),
+        ));
+        if let Err(e) = bootstrap_manager.init().await {
+            tracing::error!("Failed to init the first-run bootstrap. cause: {}", e);
+        }
+
+        // Materialize the data files before the rules compilation.
+        if let Err(e) = DataFileManager::sync_from(&data_file_repo, config).await {
+            tracing::error!("Failed to materialize the data files. cause: {}", e);
+        }
 
         let modsec_engine = Arc::new(ModSecurity::default());
 
-        let mut rules = Rules::new();
-        for rule in config::get_config().services.static_rules.clone() {
-            if rule.kind == "RAW" {
-                tracing::info!(
-                    "Loading the security static rule: {} - {} - {}",
-                    rule.name,
-                    rule.kind,
-                    rule.value
-                );
-                rules.add_plain(rule.value.as_str()).expect("Failed to add rules");
+        let (rules, rule_infos, rule_exclusions, shadow_rules) = match self.rules {
+            Some((rules, rule_infos)) => {
+                let rule_exclusions = RuleExclusions::new(&[], &rule_infos, &config.services.data_files.dir, None);
+                (rules, rule_infos, rule_exclusions, Vec::new())
             }
-        }
-        let modsec_rules = Arc::new(rules);
+            None => BotwafState::compile_modsec_rules(config),
+        };
 
         let app_state = BotwafState {
             // Notice: Arc object clone only increments the reference counter, and does not copy the actual data block.
             config: config.clone(),
             // The basic operators.
-            string_cache: Arc::new(cache_container),
+            string_cache,
             oidc_client: auth_clients.0,
             github_client: auth_clients.1,
             default_http_client: Arc::new(http_client),
+            oauth2_http_client: Arc::new(oauth2_http_client),
+            token_verify_cache: Arc::new(TokenVerifyCache::new(&config.auth.token_verify_cache)),
             // The Health checker.
             sqlite_checker: SQLiteChecker::new(),
             mongo_checker: MongoChecker::new(),
             redis_cluster_checker: RedisClusterChecker::new(),
             // The System repositories.
-            user_repo: Arc::new(Mutex::new(user_repo)),
+            user_repo,
+            bootstrap_manager,
             // The Application repositories.
+            data_file_repo,
             modsec_engine,
-            modsec_rules,
-            llm_handler: LLMManager::get_default_implementation(),
+            modsec_rules: Arc::new(ArcSwap::from_pointee(rules)),
+            modsec_rule_infos: Arc::new(ArcSwap::from_pointee(rule_infos)),
+            modsec_rule_exclusions: Arc::new(ArcSwap::from_pointee(rule_exclusions)),
+            modsec_shadow_rules: Arc::new(ArcSwap::from_pointee(shadow_rules)),
+            modsec_rule_swap: Arc::new(RuleSwapDebouncer::new(*config.services.modsec.rule_swap_debounce)),
+            llm_handler,
+            ipfilter: self.ipfilter,
+            forwarder: self.forwarder,
         };
 
         // Build DI container.
         // let mut di_container = syrette::DIContainer::new();
         // di_container.bind::<dyn IUserHandler>().to::<UserHandler>()?;
 
-        app_state
+        Ok(app_state)
+    }
+}
+
+/// The failures of the state components initialization, each with the name of the failed component.
+#[derive(Debug, Default)]
+pub struct StateInitError {
+    pub failures: Vec<(&'static str, anyhow::Error)>,
+}
+
+impl StateInitError {
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn check<T>(&mut self, component: &'static str, result: Result<T, anyhow::Error>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::error!("Failed to init the {}. cause: {:#}", component, e);
+                self.failures.push((component, e));
+                None
+            }
+        }
+    }
+
+    fn repo<T, R>(
+        &mut self,
+        component: &'static str,
+        result: Result<R, anyhow::Error>,
+    ) -> Option<Box<dyn AsyncRepository<T>>>
+    where
+        R: AsyncRepository<T> + 'static,
+    {
+        self.check(component, result)
+            .map(|repo| Box::new(repo) as Box<dyn AsyncRepository<T>>)
+    }
+}
+
+impl std::fmt::Display for StateInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to build the Botwaf state, {} component(s) failed",
+            self.failures.len()
+        )?;
+        for (component, e) in &self.failures {
+            write!(f, "; the {}: {:#}", component, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for StateInitError {
+    // Notice: Only the first is chained, e.g: for classifying the unreachable dependency on the command exit.
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.failures
+            .first()
+            .map(|(_, e)| e.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl BotwafState {
+    pub async fn new(config: &Arc<AppConfig>) -> Result<Self, anyhow::Error> {
+        Self::builder().with_config(config).build().await
+    }
+
+    pub fn builder() -> BotwafStateBuilder {
+        BotwafStateBuilder::default()
+    }
+
+    /// Recompile the effective rules, e.g: the referenced data files changed, or the rule promoted.
+    pub fn reload_modsec_rules(&self) {
+        let (rules, rule_infos, rule_exclusions, shadow_rules) = Self::compile_modsec_rules(&self.config);
+        self.modsec_rules.store(Arc::new(rules));
+        self.modsec_rule_infos.store(Arc::new(rule_infos));
+        self.modsec_rule_exclusions.store(Arc::new(rule_exclusions));
+        self.modsec_shadow_rules.store(Arc::new(shadow_rules));
+    }
+
+    /// Schedule to recompile the effective rules with the debounced queue, the rapid changes (e.g: the bulk
+    /// rule saves) are coalesced into a single recompile and swap, see: RuleSwapDebouncer
+    pub fn schedule_reload_modsec_rules(&self) {
+        let state = self.clone();
+        self.modsec_rule_swap.request(move || state.reload_modsec_rules());
+    }
+
+    fn compile_modsec_rules(config: &AppConfig) -> (Rules, Vec<ModSecRuleInfo>, RuleExclusions, Vec<ShadowRule>) {
+        let (rules, rule_infos) = rule_loader::load_rules(config);
+        let rule_exclusions = RuleExclusions::new(
+            &config.services.rule_exclusions,
+            &rule_infos,
+            &config.services.data_files.dir,
+            config.services.modsec.engine_config.as_deref(),
+        );
+        let shadow_rules = ShadowRule::compile_all(&rule_infos, &config.services.data_files.dir);
+        (rules, rule_infos, rule_exclusions, shadow_rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::config::{AppConfigProperties, AppDBType, CacheProvider, LlmClassificationMode},
+        context::test_support::StaticLLMHandler,
+    };
+    use std::env;
+
+    fn create_config(name: &str, redis_nodes: Vec<String>) -> Arc<AppConfig> {
+        let dir = env::temp_dir().join(format!("botwaf-state-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut properties = AppConfigProperties::default();
+        properties.appdb.db_type = AppDBType::SQLITE;
+        properties.appdb.sqlite.dir = Some(dir.to_string_lossy().to_string());
+        properties.cache.provider = CacheProvider::MEMORY;
+        properties.cache.redis.nodes = redis_nodes;
+        properties.services.llm_classification.mode = LlmClassificationMode::OFF;
+        AppConfig::new(&properties)
+    }
+
+    fn create_builder(config: &Arc<AppConfig>) -> BotwafStateBuilder {
+        BotwafState::builder()
+            .with_config(config)
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(Rules::new(), Vec::new())
+    }
+
+    #[tokio::test]
+    async fn test_build_with_bad_cache_config_returns_error() {
+        let config = create_config("bad-cache", vec![String::from("not-a-redis-url")]);
+
+        let err = match create_builder(&config).build().await {
+            Ok(_) => panic!("The state should not be built with the malformed redis nodes"),
+            Err(e) => e,
+        };
+        let init_err = err
+            .downcast_ref::<StateInitError>()
+            .expect("Should be the state init error");
+        assert_eq!(init_err.failures.len(), 1);
+        assert_eq!(init_err.failures[0].0, "redis cache");
+        let message = err.to_string();
+        assert!(message.contains("the redis cache"), "{}", message);
+        assert!(message.contains("not-a-redis-url"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_build_aggregates_all_failed_components() {
+        let config = create_config("no-llm", Vec::new());
+
+        // Neither the injected LLM handler nor the registered, and the redis cluster without any nodes.
+        let err = match BotwafState::builder().with_config(&config).build().await {
+            Ok(_) => panic!("The state should not be built without the LLM handler"),
+            Err(e) => e,
+        };
+        let init_err = err
+            .downcast_ref::<StateInitError>()
+            .expect("Should be the state init error");
+        let components = init_err.failures.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+        assert_eq!(components, vec!["llm handler", "redis cache"]);
+    }
+
+    #[tokio::test]
+    async fn test_build_with_valid_config() {
+        let config = create_config("valid", vec![String::from("redis://127.0.0.1:6379")]);
+
+        // The redis nodes are not connected until the first command.
+        let state = create_builder(&config).build().await.unwrap();
+        assert!(state.oidc_client.is_none());
+        assert!(state.github_client.is_none());
     }
 }
@@ -0,0 +1,392 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+// The in-memory fakes of the state components, which allow the integration tests to run without
+// the external services (e.g: Redis, Postgres), enabled by the 'testing' feature.
+
+use crate::{
+    cache::{CacheContainer, ICache},
+    config::config::{AppConfig, AppConfigProperties, AppDBType, CacheProvider, LlmClassificationMode},
+    modules::{
+        forward::{forwarder::IForwarder, ipfilter::IPFilter},
+        llm::handler::llm_base::{ILLMHandler, LlmGeneration},
+    },
+};
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use axum::{body::Body, response::Response};
+use botwaf_types::modules::{
+    forward::{forwarder::HttpIncomingRequest, ipfilter::IPFilterEntry},
+    llm::knowledge::KnowledgeUploadInfo,
+};
+use hyper::{StatusCode, Version};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    fs::File,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The config with the local SQLite and memory cache, and the LLM classification disabled.
+pub fn create_test_config(name: &str) -> Arc<AppConfig> {
+    let dir = env::temp_dir().join(format!("botwaf-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Failed to create the test SQLite dir");
+
+    let mut properties = AppConfigProperties::default();
+    properties.appdb.db_type = AppDBType::SQLITE;
+    properties.appdb.sqlite.dir = Some(dir.to_string_lossy().to_string());
+    properties.cache.provider = CacheProvider::MEMORY;
+    properties.services.llm_classification.mode = LlmClassificationMode::OFF;
+    AppConfig::new(&properties)
+}
+
+/// The cache container of which both the memory and redis are the in-memory fakes.
+pub fn create_in_memory_cache() -> CacheContainer<String> {
+    CacheContainer::new(Box::new(InMemoryCache::default()), Box::new(InMemoryCache::default()))
+}
+
+/// The plain in-memory cache, notice that the expirations are ignored.
+#[derive(Default)]
+pub struct InMemoryCache {
+    values: Mutex<HashMap<String, String>>,
+    hashes: Mutex<HashMap<String, HashMap<String, String>>>,
+    bits: Mutex<HashMap<String, BTreeSet<u64>>>,
+    // The number of all the cache operations, e.g: to assert the round-trips of the Redis.
+    calls: Arc<AtomicU64>,
+}
+
+impl InMemoryCache {
+    /// The shared counter of the cache operations, which is still readable after boxed into the container.
+    pub fn calls(&self) -> Arc<AtomicU64> {
+        self.calls.clone()
+    }
+
+    fn count(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl ICache<String> for InMemoryCache {
+    async fn get(&self, key: String) -> Result<Option<String>, Error> {
+        self.count();
+        Ok(self.values.lock().unwrap().get(&key).cloned())
+    }
+
+    async fn set(&self, key: String, value: String, _seconds: Option<i32>) -> Result<bool, Error> {
+        self.count();
+        self.values.lock().unwrap().insert(key, value);
+        Ok(true)
+    }
+
+    async fn set_nx(&self, key: String, value: Option<String>) -> Result<bool, Error> {
+        self.count();
+        let mut values = self.values.lock().unwrap();
+        match value {
+            Some(v) if !values.contains_key(&key) => {
+                values.insert(key, v);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn keys(&self, pattern: String) -> Result<Vec<String>, Error> {
+        self.count();
+        let pattern = regex::Regex::new(&pattern)?;
+        let values = self.values.lock().unwrap();
+        let hashes = self.hashes.lock().unwrap();
+        Ok(values
+            .keys()
+            .chain(hashes.keys())
+            .filter(|k| pattern.is_match(k))
+            .cloned()
+            .collect())
+    }
+
+    async fn hget(&self, key: String, field: Option<String>) -> Result<Option<String>, Error> {
+        self.count();
+        let hashes = self.hashes.lock().unwrap();
+        Ok(field.and_then(|f| hashes.get(&key).and_then(|hash| hash.get(&f).cloned())))
+    }
+
+    async fn hget_all(&self, name: String) -> Result<Option<HashMap<String, String>>, Error> {
+        self.count();
+        Ok(self.hashes.lock().unwrap().get(&name).cloned())
+    }
+
+    async fn hkeys(&self, key: String) -> Result<Vec<String>, Error> {
+        self.count();
+        let hashes = self.hashes.lock().unwrap();
+        Ok(hashes
+            .get(&key)
+            .map(|hash| hash.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn hset(&self, key: String, field_values: Option<Vec<(String, String)>>) -> Result<bool, Error> {
+        self.count();
+        match field_values {
+            Some(fv) => {
+                self.hashes.lock().unwrap().entry(key).or_default().extend(fv);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn hset_nx(&self, key: String, field: String, value: String) -> Result<bool, Error> {
+        self.count();
+        let mut hashes = self.hashes.lock().unwrap();
+        let hash = hashes.entry(key).or_default();
+        if hash.contains_key(&field) {
+            return Ok(false);
+        }
+        hash.insert(field, value);
+        Ok(true)
+    }
+
+    async fn hdel(&self, key: String, field: String) -> Result<bool, Error> {
+        self.count();
+        let mut hashes = self.hashes.lock().unwrap();
+        Ok(hashes.get_mut(&key).and_then(|hash| hash.remove(&field)).is_some())
+    }
+
+    async fn expire(&self, key: String, _milliseconds: i64) -> Result<bool, Error> {
+        self.count();
+        Ok(self.values.lock().unwrap().contains_key(&key) || self.hashes.lock().unwrap().contains_key(&key))
+    }
+
+    async fn get_bit(&self, key: String, offset: u64) -> Result<bool, Error> {
+        self.count();
+        let bits = self.bits.lock().unwrap();
+        Ok(bits.get(&key).map(|b| b.contains(&offset)).unwrap_or(false))
+    }
+
+    async fn set_bit(&self, key: String, offset: u64, value: bool) -> Result<bool, Error> {
+        self.count();
+        let mut bits = self.bits.lock().unwrap();
+        let bits = bits.entry(key).or_default();
+        // Returns the original bit value like the redis SETBIT.
+        Ok(if value {
+            !bits.insert(offset)
+        } else {
+            bits.remove(&offset)
+        })
+    }
+
+    async fn del(&self, key: String) -> Result<bool, Error> {
+        self.count();
+        let removed = self.values.lock().unwrap().remove(&key).is_some();
+        let removed_hash = self.hashes.lock().unwrap().remove(&key).is_some();
+        let removed_bits = self.bits.lock().unwrap().remove(&key).is_some();
+        Ok(removed || removed_hash || removed_bits)
+    }
+}
+
+/// The in-memory IP filter, which only matches the exact client IP addresses.
+#[derive(Default)]
+pub struct InMemoryIPFilter {
+    entries: Mutex<HashMap<String, IPFilterEntry>>,
+}
+
+#[async_trait]
+impl IPFilter for InMemoryIPFilter {
+    async fn init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn is_blocked(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        let entries = self.entries.lock().unwrap();
+        Ok(incoming
+            .client_ip
+            .as_ref()
+            .and_then(|ip| entries.get(ip))
+            .map(|entry| !entry.is_expired())
+            .unwrap_or(false))
+    }
+
+    async fn block_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        match &incoming.client_ip {
+            Some(ip) => self.block(ip, None, None).await,
+            None => Ok(false),
+        }
+    }
+
+    async fn unblock_ip(&self, incoming: Arc<HttpIncomingRequest>) -> Result<bool, Error> {
+        match &incoming.client_ip {
+            Some(ip) => self.unblock(ip).await,
+            None => Ok(false),
+        }
+    }
+
+    async fn block(&self, ip: &str, ttl: Option<u64>, operator: Option<String>) -> Result<bool, Error> {
+        let entry = IPFilterEntry::new(ip.to_owned(), ttl, operator);
+        self.entries.lock().unwrap().insert(ip.to_owned(), entry);
+        Ok(true)
+    }
+
+    async fn unblock(&self, ip: &str) -> Result<bool, Error> {
+        Ok(self.entries.lock().unwrap().remove(ip).is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<IPFilterEntry>, Error> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries.values().filter(|e| !e.is_expired()).cloned().collect())
+    }
+}
+
+/// The builder of the incoming requests of the tests, i.e: 'GET /' of HTTP/1.1 without any headers by default,
+/// so that the tests only set the fields they care about.
+#[derive(Clone)]
+pub struct TestIncomingBuilder {
+    incoming: HttpIncomingRequest,
+}
+
+impl TestIncomingBuilder {
+    pub fn new(method: &str, path: &str) -> Self {
+        TestIncomingBuilder {
+            incoming: HttpIncomingRequest {
+                method: method.to_owned(),
+                scheme: None,
+                host: None,
+                port: None,
+                headers: HashMap::new(),
+                path: path.to_owned(),
+                query: None,
+                body: None,
+                client_ip: None,
+                peer_ip: None,
+                synthetic: false,
+                version: Version::HTTP_11,
+            },
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.incoming.host = Some(host.to_owned());
+        self
+    }
+
+    pub fn query(mut self, query: &str) -> Self {
+        self.incoming.query = Some(query.to_owned());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.incoming.headers.insert(name.to_owned(), Some(value.to_owned()));
+        self
+    }
+
+    pub fn headers(mut self, headers: &[(&str, &str)]) -> Self {
+        for (name, value) in headers {
+            self = self.header(name, value);
+        }
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.incoming.body = Some(body.to_owned().into());
+        self
+    }
+
+    pub fn client_ip(mut self, client_ip: &str) -> Self {
+        self.incoming.client_ip = Some(client_ip.to_owned());
+        self
+    }
+
+    pub fn peer_ip(mut self, peer_ip: &str) -> Self {
+        self.incoming.peer_ip = Some(peer_ip.to_owned());
+        self
+    }
+
+    pub fn synthetic(mut self, synthetic: bool) -> Self {
+        self.incoming.synthetic = synthetic;
+        self
+    }
+
+    pub fn build(self) -> HttpIncomingRequest {
+        self.incoming
+    }
+
+    pub fn build_arc(self) -> Arc<HttpIncomingRequest> {
+        Arc::new(self.incoming)
+    }
+}
+
+/// The forwarder which responds the static status and body instead of the upstreams, and records the
+/// forwarded requests.
+pub struct StaticForwarder {
+    status: StatusCode,
+    body: String,
+    forwarded: Mutex<Vec<Arc<HttpIncomingRequest>>>,
+}
+
+impl StaticForwarder {
+    pub fn new(status: StatusCode, body: &str) -> Arc<Self> {
+        Arc::new(StaticForwarder {
+            status,
+            body: body.to_owned(),
+            forwarded: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn forwarded(&self) -> Vec<Arc<HttpIncomingRequest>> {
+        self.forwarded.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl IForwarder for StaticForwarder {
+    async fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn http_forward(&self, incoming: Arc<HttpIncomingRequest>) -> Result<Response<Body>> {
+        self.forwarded.lock().unwrap().push(incoming);
+        Ok(Response::builder()
+            .status(self.status)
+            .body(Body::from(self.body.to_owned()))?)
+    }
+}
+
+/// The LLM handler which generates the static answer, and never embeds.
+pub struct StaticLLMHandler {
+    pub answer: String,
+}
+
+#[async_trait]
+impl ILLMHandler for StaticLLMHandler {
+    async fn init(&self) {}
+
+    async fn embedding(&self, _info: KnowledgeUploadInfo, _file: File) -> Result<KnowledgeUploadInfo, Error> {
+        Err(Error::msg("Unsupported the embedding"))
+    }
+
+    async fn generate(&self, _prompt: String) -> Result<LlmGeneration, Error> {
+        Ok(LlmGeneration {
+            content: self.answer.to_owned(),
+            provider: "static".to_owned(),
+        })
+    }
+}
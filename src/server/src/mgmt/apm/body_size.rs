@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use axum::body::{Body, Bytes};
+use hyper::body::{Body as HttpBody, Frame, SizeHint};
+use prometheus::Histogram;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The body wrapper which counts the bytes of the data frames as they flow, and observes the total size into
+/// the histogram once the body is finished or dropped, so the streamed bodies are never buffered for counting.
+pub struct ByteCountingBody {
+    inner: Body,
+    histogram: Histogram,
+    bytes: u64,
+    observed: bool,
+}
+
+impl ByteCountingBody {
+    pub fn new(inner: Body, histogram: Histogram) -> Self {
+        Self {
+            inner,
+            histogram,
+            bytes: 0,
+            observed: false,
+        }
+    }
+
+    pub fn wrap(inner: Body, histogram: Histogram) -> Body {
+        Body::new(Self::new(inner, histogram))
+    }
+
+    fn observe(&mut self) {
+        if !self.observed {
+            self.observed = true;
+            self.histogram.observe(self.bytes as f64);
+        }
+    }
+}
+
+impl HttpBody for ByteCountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.bytes += data.len() as u64;
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Poll::Ready(None) => {
+                this.observe();
+                Poll::Ready(None)
+            }
+            other => other,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for ByteCountingBody {
+    // Notice: The body may be not polled to the end, e.g: the client aborted, or the sender stopped once
+    // is_end_stream(), then the bytes flowed so far are observed.
+    fn drop(&mut self) {
+        self.observe();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use http_body_util::BodyExt;
+
+    fn new_histogram() -> Histogram {
+        Histogram::with_opts(
+            prometheus::HistogramOpts::new("test_body_bytes", "test")
+                .buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap()),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body_observed() {
+        let histogram = new_histogram();
+        let body = ByteCountingBody::wrap(Body::from(vec![b'a'; 1000]), histogram.clone());
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(bytes.len(), 1000);
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 1000.0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_counted_as_flow() {
+        let histogram = new_histogram();
+        let chunks = (0..10).map(|_| Ok::<_, std::io::Error>(Bytes::from(vec![b'a'; 4096])));
+        let body = ByteCountingBody::new(Body::from_stream(stream::iter(chunks)), histogram.clone());
+        let mut body = Box::pin(body);
+
+        // Only the first chunk flowed, then the client aborted.
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.data_ref().map(|d| d.len()), Some(4096));
+        assert_eq!(histogram.get_sample_count(), 0);
+        drop(body);
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 4096.0);
+    }
+}
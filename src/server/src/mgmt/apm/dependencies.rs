@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::metrics::{BOTWAF_DEPENDENCY_CALL_DURATION, BOTWAF_DEPENDENCY_ERRORS_TOTAL};
+use botwaf_types::sys::stats::{DependencyStats, QueryDependencyStatsResponse};
+use lazy_static::lazy_static;
+use prometheus::{core::Collector, proto::Metric};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// The external dependencies, which (with the operation classes) are the bounded label values of the metrics.
+pub const DEP_APPDB: &str = "appdb";
+pub const DEP_REDIS: &str = "redis";
+pub const DEP_VECTOR_STORE: &str = "vector_store";
+pub const DEP_LLM: &str = "llm";
+pub const DEP_UPSTREAM: &str = "upstream";
+pub const DEP_WEBHOOK: &str = "webhook";
+
+pub const DEFAULT_WINDOW_MINUTES: u32 = 5;
+pub const MAX_WINDOW_MINUTES: u32 = 60;
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // The per-minute snapshots of the cumulative dependency metrics, the oldest is at least the max window ago.
+    static ref SNAPSHOTS: Mutex<VecDeque<(Instant, DependencySnapshot)>> = Mutex::new(VecDeque::new());
+    static ref SAMPLER_STARTED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Record the latency and the failure of the external dependency call, e.g: instrument_dep("redis", "get", fut)
+///
+/// Notice: The labels must be of the bounded sets, i.e: the dependency constants above and the operation
+/// classes (e.g: get, set, select) rather than the keys, tables or URLs.
+pub async fn instrument_dep<T, E, F>(dependency: &'static str, operation: &'static str, fut: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = fut.await;
+    BOTWAF_DEPENDENCY_CALL_DURATION
+        .with_label_values(&[dependency, operation])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        BOTWAF_DEPENDENCY_ERRORS_TOTAL
+            .with_label_values(&[dependency, operation])
+            .inc();
+    }
+    result
+}
+
+#[derive(Clone, Debug, Default)]
+struct DependencySample {
+    calls: u64,
+    errors: u64,
+    sum_secs: f64,
+    // The upper bound and the cumulative count of the buckets (excluding the +Inf).
+    buckets: Vec<(f64, u64)>,
+}
+
+type DependencySnapshot = BTreeMap<(String, String), DependencySample>;
+
+/// Start the sampler of the per-minute snapshots in the background, which the windowed stats are the deltas
+/// against, and it's only started once.
+pub fn start_sampler() {
+    if SAMPLER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut snapshots = SNAPSHOTS.lock().unwrap();
+            snapshots.push_back((Instant::now(), take_snapshot()));
+            while snapshots.len() > MAX_WINDOW_MINUTES as usize + 1 {
+                snapshots.pop_front();
+            }
+        }
+    });
+}
+
+/// Aggregate the dependency calls of the last minutes from the in-process metrics, which doesn't require the
+/// metrics registered, i.e: the management server is disabled.
+pub fn dependency_stats(minutes: u32) -> QueryDependencyStatsResponse {
+    let minutes = minutes.clamp(1, MAX_WINDOW_MINUTES);
+    let current = take_snapshot();
+    // The latest snapshot over the window, otherwise the window is not reached yet since started.
+    let baseline = Instant::now()
+        .checked_sub(Duration::from_secs(minutes as u64 * 60))
+        .and_then(|cutoff| {
+            SNAPSHOTS
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .find(|(at, _)| *at <= cutoff)
+                .map(|(_, snapshot)| snapshot.to_owned())
+        });
+    let since_started = baseline.is_none();
+    let baseline = baseline.unwrap_or_default();
+
+    let dependencies = current
+        .iter()
+        .filter_map(|(key, sample)| {
+            let stats = aggregate(&key.0, &key.1, sample, baseline.get(key));
+            (stats.calls > 0 || stats.errors > 0).then_some(stats)
+        })
+        .collect();
+    QueryDependencyStatsResponse {
+        minutes,
+        since_started,
+        dependencies,
+    }
+}
+
+fn take_snapshot() -> DependencySnapshot {
+    let mut snapshot = DependencySnapshot::new();
+    for family in BOTWAF_DEPENDENCY_CALL_DURATION.collect() {
+        for metric in family.get_metric() {
+            let histogram = metric.get_histogram();
+            let sample = snapshot.entry(get_labels(metric)).or_default();
+            sample.calls = histogram.sample_count();
+            sample.sum_secs = histogram.sample_sum();
+            sample.buckets = histogram
+                .get_bucket()
+                .iter()
+                .map(|b| (b.upper_bound(), b.cumulative_count()))
+                .collect();
+        }
+    }
+    for family in BOTWAF_DEPENDENCY_ERRORS_TOTAL.collect() {
+        for metric in family.get_metric() {
+            snapshot.entry(get_labels(metric)).or_default().errors = metric.get_counter().value() as u64;
+        }
+    }
+    snapshot
+}
+
+fn get_labels(metric: &Metric) -> (String, String) {
+    let get_label = |name: &str| {
+        metric
+            .get_label()
+            .iter()
+            .find(|l| l.name() == name)
+            .map(|l| l.value().to_owned())
+            .unwrap_or_default()
+    };
+    (get_label("dependency"), get_label("operation"))
+}
+
+fn aggregate(
+    dependency: &str,
+    operation: &str,
+    current: &DependencySample,
+    baseline: Option<&DependencySample>,
+) -> DependencyStats {
+    let baseline = baseline.cloned().unwrap_or_default();
+    let calls = current.calls.saturating_sub(baseline.calls);
+    let errors = current.errors.saturating_sub(baseline.errors);
+    let buckets = current
+        .buckets
+        .iter()
+        .enumerate()
+        .map(|(i, (upper_bound, count))| {
+            let base = baseline.buckets.get(i).map(|(_, c)| *c).unwrap_or(0);
+            (*upper_bound, count.saturating_sub(base))
+        })
+        .collect::<Vec<_>>();
+    let (error_rate, avg_ms) = if calls > 0 {
+        (
+            errors as f64 / calls as f64,
+            (current.sum_secs - baseline.sum_secs).max(0.0) * 1000.0 / calls as f64,
+        )
+    } else {
+        (0.0, 0.0)
+    };
+    DependencyStats {
+        dependency: dependency.to_owned(),
+        operation: operation.to_owned(),
+        calls,
+        errors,
+        error_rate,
+        avg_ms,
+        p50_ms: estimate_quantile(&buckets, calls, 0.50) * 1000.0,
+        p95_ms: estimate_quantile(&buckets, calls, 0.95) * 1000.0,
+        p99_ms: estimate_quantile(&buckets, calls, 0.99) * 1000.0,
+    }
+}
+
+// Estimate the quantile by the linear interpolation within the bucket of the rank (same as the promql
+// histogram_quantile), the observations over the last bucket are regarded as its upper bound.
+fn estimate_quantile(buckets: &[(f64, u64)], total: u64, quantile: f64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    let rank = quantile * total as f64;
+    let (mut lower_bound, mut lower_count) = (0.0, 0);
+    for (upper_bound, count) in buckets {
+        if *count as f64 >= rank {
+            let in_bucket = (*count - lower_count) as f64;
+            if in_bucket <= 0.0 {
+                return *upper_bound;
+            }
+            return lower_bound + (upper_bound - lower_bound) * (rank - lower_count as f64) / in_bucket;
+        }
+        (lower_bound, lower_count) = (*upper_bound, *count);
+    }
+    lower_bound
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_stats<'a>(
+        resp: &'a QueryDependencyStatsResponse,
+        dependency: &str,
+        operation: &str,
+    ) -> Option<&'a DependencyStats> {
+        resp.dependencies
+            .iter()
+            .find(|s| s.dependency == dependency && s.operation == operation)
+    }
+
+    #[tokio::test]
+    async fn test_instrument_dep_records_by_labels() {
+        let result: Result<&str, anyhow::Error> = instrument_dep(DEP_REDIS, "get", async { Ok("value") }).await;
+        assert_eq!(result.unwrap(), "value");
+
+        let histogram = BOTWAF_DEPENDENCY_CALL_DURATION.with_label_values(&["redis", "get"]);
+        assert!(histogram.get_sample_count() >= 1);
+        // The labels are exactly the dependency and the operation class.
+        let family = BOTWAF_DEPENDENCY_CALL_DURATION.collect().remove(0);
+        let metric = family
+            .get_metric()
+            .iter()
+            .find(|m| get_labels(m) == ("redis".to_owned(), "get".to_owned()))
+            .unwrap();
+        let names = metric.get_label().iter().map(|l| l.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["dependency", "operation"]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_dependency_shows_up_in_stats() {
+        for _ in 0..3 {
+            let result: Result<(), anyhow::Error> =
+                instrument_dep("fake", "query", async { Err(anyhow::anyhow!("connection refused")) }).await;
+            assert!(result.is_err());
+        }
+        let _ = instrument_dep("fake", "query", async { Ok::<_, anyhow::Error>(()) }).await;
+
+        let resp = dependency_stats(DEFAULT_WINDOW_MINUTES);
+        assert_eq!(resp.minutes, DEFAULT_WINDOW_MINUTES);
+        assert!(resp.since_started);
+        let stats = find_stats(&resp, "fake", "query").unwrap();
+        assert_eq!(stats.calls, 4);
+        assert_eq!(stats.errors, 3);
+        assert_eq!(stats.error_rate, 0.75);
+        assert!(stats.p99_ms <= 1.0);
+        // Never called dependencies are excluded.
+        assert!(find_stats(&resp, "fake", "never").is_none());
+    }
+
+    #[test]
+    fn test_stats_deltas_against_baseline() {
+        let baseline = DependencySample {
+            calls: 10,
+            errors: 1,
+            sum_secs: 1.0,
+            buckets: vec![(0.1, 10), (1.0, 10)],
+        };
+        let current = DependencySample {
+            calls: 20,
+            errors: 3,
+            sum_secs: 6.0,
+            buckets: vec![(0.1, 10), (1.0, 20)],
+        };
+        let stats = aggregate("appdb", "select", &current, Some(&baseline));
+        assert_eq!((stats.calls, stats.errors), (10, 2));
+        assert_eq!(stats.error_rate, 0.2);
+        assert_eq!(stats.avg_ms, 500.0);
+        // All the calls of the window are in the (0.1, 1.0] bucket.
+        assert!((stats.p50_ms - 550.0).abs() < 1e-6);
+        assert!((stats.p99_ms - 991.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_estimate_quantile() {
+        let buckets = vec![(0.01, 50), (0.1, 90), (1.0, 100)];
+        assert_eq!(estimate_quantile(&buckets, 0, 0.5), 0.0);
+        assert!((estimate_quantile(&buckets, 100, 0.5) - 0.01).abs() < 1e-9);
+        assert!((estimate_quantile(&buckets, 100, 0.7) - 0.055).abs() < 1e-9);
+        // Over the last bucket.
+        assert_eq!(estimate_quantile(&[(0.01, 0)], 5, 0.5), 0.01);
+    }
+}
@@ -20,15 +20,18 @@
 
 use crate::config::config::AppConfig;
 use lazy_static::lazy_static;
-use prometheus::{Counter, Encoder, Histogram, Registry, TextEncoder};
+use prometheus::{
+    CounterVec, Encoder, GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
 use std::sync::Arc;
 
 lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
 
-    pub static ref MY_HTTP_REQUESTS_TOTAL: Counter = Counter::new(
-        "my_http_requests_total",
-        "My Total number of HTTP requests"
+    pub static ref MY_HTTP_REQUESTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("my_http_requests_total", "My Total number of HTTP requests"),
+        &["protocol"]
     ).expect("My metric can be created");
 
     pub static ref MY_HTTP_REQUEST_DURATION: Histogram = Histogram::with_opts(
@@ -37,6 +40,247 @@ lazy_static! {
             "My HTTP request duration in seconds"
         )
     ).expect("My metric can be created");
+
+    pub static ref BOTWAF_PROBE_SUCCESS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("botwaf_probe_success", "Whether the last synthetic probe got the expected decision (1) or not (0)"),
+        &["probe"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_EMERGENCY_RULES_ACTIVE: IntGauge = IntGauge::new(
+        "botwaf_emergency_rules_active",
+        "Whether the embedded emergency rules are active (1) or not (0)"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_FORWARD_ERRORS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_forward_errors_total", "Total number of the upstream forwarding errors"),
+        &["kind"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_HEADER_LIMIT_REJECTED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_header_limit_rejected_total", "Total number of the requests rejected with 431 by the exceeded header limit"),
+        &["limit"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_MIRROR_REQUESTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_mirror_requests_total", "Total number of the mirrored requests by outcome"),
+        &["upstream", "outcome"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_MIRROR_REQUEST_DURATION: HistogramVec = HistogramVec::new(
+        prometheus::HistogramOpts::new(
+            "botwaf_mirror_request_duration_seconds",
+            "The mirrored request duration in seconds"
+        ),
+        &["upstream"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_PLUGIN_FAILURES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_plugin_failures_total", "Total number of the wasm plugin failures by kind"),
+        &["plugin", "kind"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_MODSEC_CONCURRENT_TRANSACTIONS: IntGauge = IntGauge::new(
+        "botwaf_modsec_concurrent_transactions",
+        "The number of the current concurrent ModSecurity transactions"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_MODSEC_QUEUE_DEPTH: IntGauge = IntGauge::new(
+        "botwaf_modsec_queue_depth",
+        "The number of the requests waiting for the ModSecurity transaction"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_EVENT_WRITER_DROPPED_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_event_writer_dropped_total",
+        "Total number of the access events dropped since the writer channel is full"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_EVENT_WRITER_QUEUE_DEPTH: IntGauge = IntGauge::new(
+        "botwaf_event_writer_queue_depth",
+        "The number of the access events pending in the writer channel"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_TOPK_MEMORY_BYTES: IntGauge = IntGauge::new(
+        "botwaf_topk_memory_bytes",
+        "The fixed memory usage of the streaming top-K trackers in bytes"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_AUTH_GATE_REJECTED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_auth_gate_rejected_total", "Total number of the authentication requests rejected by the pre-auth gate"),
+        &["endpoint", "reason"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_AUTH_FAILED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_auth_failed_total", "Total number of the failed authentication attempts"),
+        &["endpoint"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_AUTH_FAILED_COST_SECONDS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("botwaf_auth_failed_cost_seconds_total", "Total processing seconds spent on the failed authentication attempts"),
+        &["endpoint"]
+    ).expect("My metric can be created");
+
+    // The body size buckets from 64B to 16MiB (4x growth), see: ByteCountingBody
+    pub static ref BOTWAF_HTTP_REQUEST_BODY_BYTES: Histogram = Histogram::with_opts(
+        prometheus::HistogramOpts::new(
+            "botwaf_http_request_body_bytes",
+            "The inbound request body size in bytes"
+        ).buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap())
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_HTTP_RESPONSE_BODY_BYTES: Histogram = Histogram::with_opts(
+        prometheus::HistogramOpts::new(
+            "botwaf_http_response_body_bytes",
+            "The outbound response body size in bytes"
+        ).buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap())
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_FAIL_OPEN_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_fail_open_total", "Total number of the fail-open decisions by subsystem"),
+        &["subsystem"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_FAIL_OPEN_ESCALATED: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("botwaf_fail_open_escalated", "Whether the fail-open budget of the subsystem is escalated (1) or not (0)"),
+        &["subsystem"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_SHADOW_RULE_HITS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_shadow_rule_hits_total", "Total number of the would-block requests by the shadow rules"),
+        &["rule"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_RULE_SWAPS_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_rule_swaps_total",
+        "Total number of the rules recompiled and swapped"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_RULE_SWAP_COALESCED_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_rule_swap_coalesced_total",
+        "Total number of the rule changes coalesced into the pending swap"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_CACHE_MEMORY_BYTES: IntGauge = IntGauge::new(
+        "botwaf_cache_memory_bytes",
+        "Current bytes (key + value) of the entries in the memory cache"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_CACHE_EVICTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_cache_evictions_total", "Total number of the memory cache evictions by cause"),
+        &["cause"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_CACHE_REJECTED_ENTRIES_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_cache_rejected_entries_total",
+        "Total number of the memory cache entries rejected by exceeding the max entry size"
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_STRIPPED_RESPONSE_HEADERS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_stripped_response_headers_total", "Total number of the stripped upstream response headers by name"),
+        &["upstream", "header"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_RESPONSE_REWRITES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_response_rewrites_total", "Total number of the response body replacements by upstream and pattern"),
+        &["upstream", "pattern"]
+    ).expect("My metric can be created");
+
+    pub static ref BOTWAF_RESPONSE_REWRITE_ABANDONED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_response_rewrite_abandoned_total", "Total number of the response rewriting abandoned by exceeding the time budget"),
+        &["upstream"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_DEAD_LETTER_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_dead_letter_total", "Total number of the failed async works written into the dead letters by kind"),
+        &["kind"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_DEAD_LETTER_DROPPED_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_dead_letter_dropped_total",
+        "Total number of the dead letters dropped since the writer channel is full or the store is failed"
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_DEAD_LETTER_REPLAYS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_dead_letter_replays_total", "Total number of the dead letters replays by kind and result"),
+        &["kind", "result"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_TOKEN_VERIFY_CACHE_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_token_verify_cache_total", "Total number of the access token verifications by local cache result"),
+        &["result"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_TOKEN_REVOCATION_LAG_SECONDS: Histogram = Histogram::with_opts(
+        prometheus::HistogramOpts::new(
+            "botwaf_token_revocation_lag_seconds",
+            "The propagation lag in seconds of the token revocations to the local verification cache"
+        )
+    ).expect("My metric can be created");
+    // The RSA keypair generation buckets from 5ms to ~5s (2x growth), see: LoginKeyPairPool
+    pub static ref BOTWAF_LOGIN_KEYPAIR_GENERATION_SECONDS: Histogram = Histogram::with_opts(
+        prometheus::HistogramOpts::new(
+            "botwaf_login_keypair_generation_seconds",
+            "The latency in seconds of generating the login RSA keypair on the blocking threads"
+        ).buckets(prometheus::exponential_buckets(0.005, 2.0, 11).unwrap())
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_LOGIN_KEYPAIR_POOL_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_login_keypair_pool_total", "Total number of the login RSA keypairs taken by the pool result"),
+        &["result"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_LOGIN_CHALLENGE_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_login_challenge_total", "Total number of the password login challenges by result"),
+        &["result"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_BLOCKED_REQUESTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_blocked_requests_total", "Total number of the blocked requests by source"),
+        &["source"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_UPDATER_RUNS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_updater_runs_total", "Total number of the updater runs by trigger"),
+        &["trigger"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_EMBEDDING_DEDUP_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_embedding_dedup_total", "Total number of the access events filtered before embedding by namespace and decision (novel|duplicate)"),
+        &["namespace", "decision"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_EMBEDDING_DEDUP_RATIO: GaugeVec = GaugeVec::new(
+        Opts::new("botwaf_embedding_dedup_ratio", "The ratio of the duplicate access events skipped embedding of the last filtered batch by namespace"),
+        &["namespace"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_SIGNATURE_FAILURES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_signature_failures_total", "Total number of the rejected signed requests by reason"),
+        &["reason"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_EVENT_STREAM_CLIENTS: IntGauge = IntGauge::new(
+        "botwaf_event_stream_clients",
+        "Number of the connected live tail clients of the access events"
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_MODSEC_SKIPPED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_modsec_skipped_total", "Total number of the requests skipped the ModSecurity by method"),
+        &["method"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL: IntCounter = IntCounter::new(
+        "botwaf_llm_classify_dropped_total",
+        "Total number of the ASYNC LLM classifications dropped since the concurrency cap is reached"
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_LLM_PROVIDER_CALLS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_llm_provider_calls_total", "Total number of the LLM provider calls by kind, provider and result"),
+        &["kind", "provider", "result"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_COMPONENT_RESTARTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_component_restarts_total", "Total number of the supervised component restarts"),
+        &["component"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_COMPONENT_FAILED: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("botwaf_component_failed", "Whether the supervised component is failed permanently (1) or not (0)"),
+        &["component"]
+    ).expect("My metric can be created");
+    // The latency buckets from 1ms to 30s of the external dependency calls, see: dependencies::instrument_dep
+    pub static ref BOTWAF_DEPENDENCY_CALL_DURATION: HistogramVec = HistogramVec::new(
+        prometheus::HistogramOpts::new(
+            "botwaf_dependency_call_duration_seconds",
+            "The external dependency call duration in seconds by dependency and operation"
+        ).buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+        &["dependency", "operation"]
+    ).expect("My metric can be created");
+    pub static ref BOTWAF_DEPENDENCY_ERRORS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("botwaf_dependency_errors_total", "Total number of the failed external dependency calls by dependency and operation"),
+        &["dependency", "operation"]
+    ).expect("My metric can be created");
     // Register more metrics...
 }
 
@@ -57,5 +301,152 @@ pub async fn init_metrics(config: &Arc<AppConfig>) {
         REGISTRY
             .register(Box::new(MY_HTTP_REQUEST_DURATION.clone()))
             .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_PROBE_SUCCESS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EMERGENCY_RULES_ACTIVE.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_FORWARD_ERRORS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_HEADER_LIMIT_REJECTED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_MIRROR_REQUESTS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_MIRROR_REQUEST_DURATION.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_PLUGIN_FAILURES_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_MODSEC_CONCURRENT_TRANSACTIONS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_MODSEC_QUEUE_DEPTH.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EVENT_WRITER_DROPPED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EVENT_WRITER_QUEUE_DEPTH.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_TOPK_MEMORY_BYTES.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_AUTH_GATE_REJECTED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_AUTH_FAILED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_AUTH_FAILED_COST_SECONDS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_HTTP_REQUEST_BODY_BYTES.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_HTTP_RESPONSE_BODY_BYTES.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_FAIL_OPEN_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_FAIL_OPEN_ESCALATED.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_SHADOW_RULE_HITS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_RULE_SWAPS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_RULE_SWAP_COALESCED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_CACHE_MEMORY_BYTES.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_CACHE_EVICTIONS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_CACHE_REJECTED_ENTRIES_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_STRIPPED_RESPONSE_HEADERS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_RESPONSE_REWRITES_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_RESPONSE_REWRITE_ABANDONED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_DEAD_LETTER_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_DEAD_LETTER_DROPPED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_DEAD_LETTER_REPLAYS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_TOKEN_VERIFY_CACHE_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_TOKEN_REVOCATION_LAG_SECONDS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_LOGIN_KEYPAIR_GENERATION_SECONDS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_LOGIN_KEYPAIR_POOL_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_LOGIN_CHALLENGE_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_BLOCKED_REQUESTS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_UPDATER_RUNS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EMBEDDING_DEDUP_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EMBEDDING_DEDUP_RATIO.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_SIGNATURE_FAILURES_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_EVENT_STREAM_CLIENTS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_MODSEC_SKIPPED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_LLM_CLASSIFY_DROPPED_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_LLM_PROVIDER_CALLS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_COMPONENT_RESTARTS_TOTAL.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_COMPONENT_FAILED.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_DEPENDENCY_CALL_DURATION.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(BOTWAF_DEPENDENCY_ERRORS_TOTAL.clone()))
+            .expect("collector can be registered");
     }
 }
@@ -19,14 +19,18 @@
 // This includes modifications and derived works.
 
 use crate::config::config::AppConfig;
+use crate::config::secrets::VaultSecretProvider;
 use crate::mgmt::apm::otel::create_otel_tracer;
 use std::sync::Arc;
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::layer::SubscriberExt;
 
+pub mod body_size;
+pub mod dependencies;
 pub mod logging;
 pub mod metrics;
 pub mod otel;
+pub mod shutdown_summary;
 
 pub async fn init_components(config: &Arc<AppConfig>) {
     // Setup logging+tracing layers.
@@ -64,7 +68,7 @@ pub async fn init_components(config: &Arc<AppConfig>) {
             console_subscriber::ConsoleLayer::builder()
                 .with_default_env()
                 .server_addr(server_addr)
-                .retention(std::time::Duration::from_secs(config.mgmt.tokio_console.retention))
+                .retention(*config.mgmt.tokio_console.retention)
                 .spawn(),
         );
         // set the subscriber as the default for the application
@@ -76,6 +80,12 @@ pub async fn init_components(config: &Arc<AppConfig>) {
 
     // Setup custom metrics.
     metrics::init_metrics(config).await;
+    dependencies::start_sampler();
+
+    // Keep the Vault token and the leases of the resolved secrets alive.
+    VaultSecretProvider::start_renewal(&config.secrets);
+
+    shutdown_summary::mark_started();
 
     // Setup profiling.
     // common_telemetry::pyroscope_agent::init_profiling(config).await;
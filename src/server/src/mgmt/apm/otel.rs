@@ -18,10 +18,10 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::config::config::AppConfig;
+use crate::config::config::{AppConfig, OtelProperties, OtelProtocol};
 use opentelemetry::{global, KeyValue};
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_otlp::{new_exporter, ExportConfig, Protocol};
+use opentelemetry_otlp::{new_exporter, ExportConfig, Protocol, SpanExporterBuilder};
 use opentelemetry_sdk::runtime::Tokio;
 use opentelemetry_sdk::trace::Config;
 use opentelemetry_sdk::trace::Tracer;
@@ -29,22 +29,15 @@ use opentelemetry_sdk::Resource;
 use std::sync::Arc;
 use std::time::Duration;
 
+const DEFAULT_OTEL_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub async fn create_otel_tracer(config: &Arc<AppConfig>) -> Option<Tracer> {
     let mut tracer = None;
 
     if config.mgmt.enabled && config.mgmt.otel.enabled {
         let _tracer = opentelemetry_otlp::new_pipeline()
             .tracing()
-            .with_exporter(new_exporter().tonic().with_export_config(ExportConfig {
-                endpoint: config.mgmt.otel.endpoint.to_string(),
-                protocol: match config.mgmt.otel.protocol.to_lowercase().as_str() {
-                    "http/protobuf" => Protocol::HttpBinary,
-                    "grpc" => Protocol::Grpc,
-                    "http/json" => Protocol::HttpJson,
-                    _ => Protocol::HttpBinary,
-                },
-                timeout: Duration::from_millis(config.mgmt.otel.timeout.unwrap()),
-            }))
+            .with_exporter(build_span_exporter(&config.mgmt.otel))
             .with_trace_config(
                 // Notice: More OTEL custom configuration use to environment: OTEL_SPAN_xxx, see to: opentelemetry_sdk::trace::config::default()
                 Config::default().with_resource(Resource::new(vec![KeyValue::new(
@@ -65,3 +58,56 @@ pub async fn create_otel_tracer(config: &Arc<AppConfig>) -> Option<Tracer> {
 
     tracer
 }
+
+/// Build the span exporter of the configured protocol, i.e: the tonic exporter for the grpc, otherwise
+/// the http exporter.
+fn build_span_exporter(otel: &OtelProperties) -> SpanExporterBuilder {
+    let export_config = ExportConfig {
+        endpoint: otel.endpoint.to_string(),
+        protocol: match otel.protocol {
+            OtelProtocol::GRPC => Protocol::Grpc,
+            OtelProtocol::HTTP_PROTOBUF => Protocol::HttpBinary,
+            OtelProtocol::HTTP_JSON => Protocol::HttpJson,
+        },
+        timeout: otel.timeout.map(|t| *t).unwrap_or(DEFAULT_OTEL_TIMEOUT),
+    };
+    match otel.protocol {
+        OtelProtocol::GRPC => new_exporter().tonic().with_export_config(export_config).into(),
+        OtelProtocol::HTTP_PROTOBUF | OtelProtocol::HTTP_JSON => {
+            new_exporter().http().with_export_config(export_config).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::duration::DurationMillis;
+
+    #[test]
+    fn test_build_span_exporter_of_protocol() {
+        let otel = OtelProperties {
+            endpoint: String::from("http://localhost:4318"),
+            protocol: OtelProtocol::HTTP_PROTOBUF,
+            timeout: Some(DurationMillis::from_secs(3)),
+            ..OtelProperties::default()
+        };
+        assert!(matches!(build_span_exporter(&otel), SpanExporterBuilder::Http(_)));
+
+        let otel = OtelProperties {
+            protocol: OtelProtocol::GRPC,
+            timeout: None,
+            ..OtelProperties::default()
+        };
+        assert!(matches!(build_span_exporter(&otel), SpanExporterBuilder::Tonic(_)));
+    }
+
+    #[test]
+    fn test_unknown_protocol_rejected() {
+        assert_eq!(
+            serde_json::from_str::<OtelProtocol>("\"http/protobuf\"").unwrap(),
+            OtelProtocol::HTTP_PROTOBUF
+        );
+        assert!(serde_json::from_str::<OtelProtocol>("\"udp\"").is_err());
+    }
+}
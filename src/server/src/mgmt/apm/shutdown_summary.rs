@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::metrics::{
+    BOTWAF_BLOCKED_REQUESTS_TOTAL, BOTWAF_FORWARD_ERRORS_TOTAL, BOTWAF_UPDATER_RUNS_TOTAL, MY_HTTP_REQUESTS_TOTAL,
+};
+use lazy_static::lazy_static;
+use prometheus::{core::Collector, Encoder, TextEncoder};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Instant};
+
+lazy_static! {
+    static ref STARTED_AT: Instant = Instant::now();
+}
+
+/// Mark the component is started, which the uptime of the shutdown summary is measured since.
+pub fn mark_started() {
+    lazy_static::initialize(&STARTED_AT);
+}
+
+/// The final summary of the component statistics at graceful shutdown, which is pulled from the prometheus
+/// counters of this process, helps the post-mortems for the short-lived runs.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ShutdownSummary {
+    pub component: String,
+    pub uptime_secs: u64,
+    pub requests_served: u64,
+    pub blocked_total: u64,
+    // The blocked requests by source, e.g: ipfilter, plugin, rule
+    pub blocked: BTreeMap<String, u64>,
+    pub upstream_errors_total: u64,
+    // The upstream forwarding errors by kind, e.g: connect_timeout, tls_handshake
+    pub upstream_errors: BTreeMap<String, u64>,
+    pub updater_runs_total: u64,
+    // The updater runs by trigger, e.g: scheduled, manual
+    pub updater_runs: BTreeMap<String, u64>,
+}
+
+impl ShutdownSummary {
+    pub fn collect(component: &str) -> Self {
+        let blocked = Self::read_counters(&*BOTWAF_BLOCKED_REQUESTS_TOTAL);
+        let upstream_errors = Self::read_counters(&*BOTWAF_FORWARD_ERRORS_TOTAL);
+        let updater_runs = Self::read_counters(&*BOTWAF_UPDATER_RUNS_TOTAL);
+        ShutdownSummary {
+            component: component.to_owned(),
+            uptime_secs: STARTED_AT.elapsed().as_secs(),
+            requests_served: Self::read_counters(&*MY_HTTP_REQUESTS_TOTAL).values().sum(),
+            blocked_total: blocked.values().sum(),
+            blocked,
+            upstream_errors_total: upstream_errors.values().sum(),
+            upstream_errors,
+            updater_runs_total: updater_runs.values().sum(),
+            updater_runs,
+        }
+    }
+
+    /// Emit the final structured log of the summary.
+    pub fn log(&self) {
+        tracing::info!(
+            component = %self.component,
+            uptime_secs = self.uptime_secs,
+            requests_served = self.requests_served,
+            blocked_total = self.blocked_total,
+            upstream_errors_total = self.upstream_errors_total,
+            updater_runs_total = self.updater_runs_total,
+            summary = %serde_json::to_string(self).unwrap_or_default(),
+            "Shutdown summary of the Botwaf {}",
+            self.component
+        );
+    }
+
+    // Read the counters by the first label value, which doesn't require the counters registered, i.e: the
+    // management server is disabled.
+    fn read_counters(collector: &dyn Collector) -> BTreeMap<String, u64> {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&collector.collect(), &mut buffer) {
+            tracing::warn!("Failed to read the counters. cause: {}", e);
+            return BTreeMap::new();
+        }
+        let mut counters = BTreeMap::new();
+        // e.g: botwaf_blocked_requests_total{source="rule"} 12
+        for line in String::from_utf8_lossy(&buffer).lines().filter(|l| !l.starts_with('#')) {
+            let (series, value) = match line.rsplit_once(' ') {
+                Some((series, value)) => (series, value.parse::<f64>().unwrap_or(0.0)),
+                None => continue,
+            };
+            let label = series
+                .split_once("=\"")
+                .and_then(|(_, rest)| rest.split_once('"'))
+                .map(|(label, _)| label.to_owned())
+                .unwrap_or_default();
+            *counters.entry(label).or_insert(0) += value as u64;
+        }
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_summary_reads_counters() {
+        let before = ShutdownSummary::collect("forwarder");
+
+        MY_HTTP_REQUESTS_TOTAL.with_label_values(&["HTTP/1.1"]).inc_by(3.0);
+        BOTWAF_BLOCKED_REQUESTS_TOTAL.with_label_values(&["rule"]).inc_by(2);
+        BOTWAF_BLOCKED_REQUESTS_TOTAL.with_label_values(&["ipfilter"]).inc();
+        BOTWAF_FORWARD_ERRORS_TOTAL
+            .with_label_values(&["connect_timeout"])
+            .inc();
+        BOTWAF_UPDATER_RUNS_TOTAL.with_label_values(&["manual"]).inc();
+
+        let after = ShutdownSummary::collect("forwarder");
+        assert_eq!(after.component, "forwarder");
+        assert!(after.uptime_secs >= before.uptime_secs);
+        assert!(after.requests_served >= before.requests_served + 3);
+        assert!(after.blocked_total >= before.blocked_total + 3);
+        assert!(after.blocked["rule"] >= 2);
+        assert!(after.blocked["ipfilter"] >= 1);
+        assert!(after.upstream_errors["connect_timeout"] >= 1);
+        assert!(after.updater_runs_total >= before.updater_runs_total + 1);
+    }
+}
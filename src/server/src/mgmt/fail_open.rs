@@ -0,0 +1,356 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{self, FailOpenBudgetProperties};
+use crate::mgmt::apm::metrics::{BOTWAF_FAIL_OPEN_ESCALATED, BOTWAF_FAIL_OPEN_TOTAL};
+use crate::sys::dead_letter::WebhookDelivery;
+use axum::{response::IntoResponse, Json};
+use botwaf_types::sys::event::{BotwafEvent, FailOpenBudgetV1};
+use botwaf_utils::httpclients;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+pub const FAIL_OPEN_IPFILTER: &str = "ipfilter";
+pub const FAIL_OPEN_LLM_CLASSIFIER: &str = "llm-classifier";
+
+// The number of slots of the sliding window.
+const WINDOW_SLOTS: u64 = 12;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<FailOpenBudget> =
+        Arc::new(FailOpenBudget::new(&config::get_config().services.fail_open_budget));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum FailOpenBudgetStatus {
+    // The fail-open occurrences are within the budget.
+    OK,
+    // The budget is exceeded, and the escalation policies are applied, e.g: fail-closed
+    ESCALATED,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailOpenBudgetState {
+    pub subsystem: String,
+    pub status: FailOpenBudgetStatus,
+    // The fail-open occurrences within the current window.
+    #[serde(rename = "failOpens")]
+    pub fail_opens: u64,
+    #[serde(rename = "maxFailOpens")]
+    pub max_fail_opens: u64,
+    #[serde(rename = "escalatedTime")]
+    pub escalated_time: Option<i64>,
+    #[serde(rename = "failClosed")]
+    pub fail_closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct BudgetTracker {
+    // The (slot index, count) ring of the sliding window.
+    slots: Vec<(u64, u64)>,
+    escalated_time: Option<i64>,
+}
+
+impl BudgetTracker {
+    fn add(&mut self, slot: u64) {
+        let index = (slot % WINDOW_SLOTS) as usize;
+        if self.slots.is_empty() {
+            self.slots = vec![(0, 0); WINDOW_SLOTS as usize];
+        }
+        match &mut self.slots[index] {
+            (s, count) if *s == slot => *count += 1,
+            entry => *entry = (slot, 1),
+        }
+    }
+
+    fn count(&self, slot: u64) -> u64 {
+        self.slots
+            .iter()
+            .filter(|(s, _)| *s + WINDOW_SLOTS > slot && *s <= slot)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// The central accounting of the fail-open decisions reported by the subsystems, which escalates the subsystem
+/// if exceeded the budget within the window, e.g: running without the IP filter since the redis is down.
+pub struct FailOpenBudget {
+    config: FailOpenBudgetProperties,
+    trackers: Mutex<BTreeMap<String, BudgetTracker>>,
+}
+
+impl FailOpenBudget {
+    pub fn get() -> Arc<FailOpenBudget> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(config: &FailOpenBudgetProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            trackers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Report a fail-open occurrence of the subsystem, and returns whether to fail-closed instead.
+    pub fn report(&self, subsystem: &str) -> bool {
+        self.report_at(subsystem, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Whether the subsystem is escalated to fail-closed, the recovery is also evaluated without any reports.
+    pub fn is_fail_closed(&self, subsystem: &str) -> bool {
+        self.is_fail_closed_at(subsystem, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Whether any subsystem is escalated and the readiness policy is enabled.
+    pub fn is_unready(&self) -> bool {
+        self.config.readiness
+            && self
+                .states()
+                .iter()
+                .any(|s| s.status == FailOpenBudgetStatus::ESCALATED)
+    }
+
+    pub fn states(&self) -> Vec<FailOpenBudgetState> {
+        self.states_at(chrono::Utc::now().timestamp_millis())
+    }
+
+    fn report_at(&self, subsystem: &str, now: i64) -> bool {
+        BOTWAF_FAIL_OPEN_TOTAL.with_label_values(&[subsystem]).inc();
+        if !self.config.enabled {
+            return false;
+        }
+        let slot = self.slot_of(now);
+        let mut trackers = self.trackers.lock().unwrap();
+        let tracker = trackers.entry(subsystem.to_owned()).or_default();
+        tracker.add(slot);
+        self.evaluate(subsystem, tracker, now);
+        self.config.fail_closed && tracker.escalated_time.is_some()
+    }
+
+    fn is_fail_closed_at(&self, subsystem: &str, now: i64) -> bool {
+        if !self.config.enabled || !self.config.fail_closed {
+            return false;
+        }
+        let mut trackers = self.trackers.lock().unwrap();
+        match trackers.get_mut(subsystem) {
+            Some(tracker) => {
+                self.evaluate(subsystem, tracker, now);
+                tracker.escalated_time.is_some()
+            }
+            None => false,
+        }
+    }
+
+    fn states_at(&self, now: i64) -> Vec<FailOpenBudgetState> {
+        let mut trackers = self.trackers.lock().unwrap();
+        trackers
+            .iter_mut()
+            .map(|(subsystem, tracker)| {
+                self.evaluate(subsystem, tracker, now);
+                let escalated = tracker.escalated_time.is_some();
+                FailOpenBudgetState {
+                    subsystem: subsystem.to_owned(),
+                    status: if escalated {
+                        FailOpenBudgetStatus::ESCALATED
+                    } else {
+                        FailOpenBudgetStatus::OK
+                    },
+                    fail_opens: tracker.count(self.slot_of(now)),
+                    max_fail_opens: self.config.max_fail_opens,
+                    escalated_time: tracker.escalated_time,
+                    fail_closed: escalated && self.config.fail_closed,
+                }
+            })
+            .collect()
+    }
+
+    fn slot_of(&self, now: i64) -> u64 {
+        let slot_millis = (self.config.window_secs.as_millis() as u64 / WINDOW_SLOTS).max(1);
+        now.max(0) as u64 / slot_millis
+    }
+
+    // Notice: The hysteresis of escalated and recovered thresholds (and the min escalated duration) prevents
+    // the flapping between the fail-open and fail-closed.
+    fn evaluate(&self, subsystem: &str, tracker: &mut BudgetTracker, now: i64) {
+        let fail_opens = tracker.count(self.slot_of(now));
+        match tracker.escalated_time {
+            None if fail_opens > self.config.max_fail_opens => {
+                tracker.escalated_time = Some(now);
+                BOTWAF_FAIL_OPEN_ESCALATED.with_label_values(&[subsystem]).set(1);
+                tracing::error!(
+                    "The fail-open budget of '{}' is exceeded ({} > {} within {}), the protection may not be working! fail-closed: {}",
+                    subsystem,
+                    fail_opens,
+                    self.config.max_fail_opens,
+                    self.config.window_secs,
+                    self.config.fail_closed
+                );
+                self.notify(subsystem, FailOpenBudgetStatus::ESCALATED, fail_opens, now);
+            }
+            Some(escalated_time)
+                if fail_opens <= self.config.recover_fail_opens
+                    && now - escalated_time >= self.config.min_escalated_secs.as_millis() as i64 =>
+            {
+                tracker.escalated_time = None;
+                BOTWAF_FAIL_OPEN_ESCALATED.with_label_values(&[subsystem]).set(0);
+                tracing::warn!(
+                    "The fail-open budget of '{}' is recovered ({} <= {} within {}).",
+                    subsystem,
+                    fail_opens,
+                    self.config.recover_fail_opens,
+                    self.config.window_secs
+                );
+                self.notify(subsystem, FailOpenBudgetStatus::OK, fail_opens, now);
+            }
+            _ => {}
+        }
+    }
+
+    fn notify(&self, subsystem: &str, status: FailOpenBudgetStatus, fail_opens: u64, now: i64) {
+        let webhook_url = match &self.config.notify_webhook_url {
+            Some(url) => url.to_owned(),
+            None => return,
+        };
+        let event = FailOpenBudgetV1 {
+            subsystem: subsystem.to_owned(),
+            status: match status {
+                FailOpenBudgetStatus::OK => String::from("OK"),
+                FailOpenBudgetStatus::ESCALATED => String::from("ESCALATED"),
+            },
+            fail_opens,
+            max_fail_opens: self.config.max_fail_opens,
+            escalated_time: if status == FailOpenBudgetStatus::ESCALATED {
+                Some(now)
+            } else {
+                None
+            },
+            fail_closed: status == FailOpenBudgetStatus::ESCALATED && self.config.fail_closed,
+        };
+        // The failed notification is written into the dead letters for replay.
+        WebhookDelivery::new(&webhook_url, BotwafEvent::FailOpenBudgetV1(event)).spawn(
+            httpclients::build_default(),
+            &format!("fail-open budget of '{}'", subsystem),
+        );
+    }
+}
+
+pub async fn handle_fail_open_budget() -> impl IntoResponse {
+    Json(FailOpenBudget::get().states())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::duration::DurationSecs;
+
+    fn create_budget() -> FailOpenBudget {
+        FailOpenBudget::new(&FailOpenBudgetProperties {
+            enabled: true,
+            window_secs: DurationSecs::from_secs(60),
+            max_fail_opens: 100,
+            recover_fail_opens: 10,
+            min_escalated_secs: DurationSecs::from_secs(120),
+            fail_closed: true,
+            readiness: true,
+            notify_webhook_url: None,
+        })
+    }
+
+    #[test]
+    fn test_redis_outage_escalate_and_recover() {
+        let budget = create_budget();
+        let start = 1_800_000_000_000i64;
+
+        // The transient redis errors are within the budget.
+        for i in 0..50 {
+            assert!(!budget.report_at(FAIL_OPEN_IPFILTER, start + i * 100));
+        }
+        assert!(!budget.is_fail_closed_at(FAIL_OPEN_IPFILTER, start + 5_000));
+
+        // The sustained redis outage (20 failures/s) exceeds the budget and escalates to fail-closed.
+        let outage = start + 120_000;
+        let mut escalated_at = None;
+        for i in 0..600 {
+            let now = outage + i * 50;
+            if budget.report_at(FAIL_OPEN_IPFILTER, now) && escalated_at.is_none() {
+                escalated_at = Some(now);
+            }
+        }
+        let escalated_at = escalated_at.expect("should be escalated");
+        assert!(escalated_at < outage + 10_000);
+        let states = budget.states_at(outage + 30_000);
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].status, FailOpenBudgetStatus::ESCALATED);
+        assert!(states[0].fail_closed);
+        assert!(budget.config.readiness);
+
+        // The other subsystems are not affected.
+        assert!(!budget.is_fail_closed_at(FAIL_OPEN_LLM_CLASSIFIER, outage + 30_000));
+
+        // The redis recovered, but it's still fail-closed within the min escalated duration (hysteresis).
+        let recovered = outage + 30_000;
+        assert!(budget.is_fail_closed_at(FAIL_OPEN_IPFILTER, recovered + 70_000));
+
+        // The occasional failures under the recover threshold after the min escalated duration.
+        for i in 0..5 {
+            budget.report_at(FAIL_OPEN_IPFILTER, escalated_at + 130_000 + i * 1000);
+        }
+        assert!(!budget.is_fail_closed_at(FAIL_OPEN_IPFILTER, escalated_at + 140_000));
+        assert_eq!(
+            budget.states_at(escalated_at + 140_000)[0].status,
+            FailOpenBudgetStatus::OK
+        );
+    }
+
+    #[test]
+    fn test_no_flapping_around_the_threshold() {
+        let budget = create_budget();
+        let start = 1_800_000_000_000i64;
+        for i in 0..101 {
+            budget.report_at(FAIL_OPEN_IPFILTER, start + i * 10);
+        }
+        assert!(budget.is_fail_closed_at(FAIL_OPEN_IPFILTER, start + 2_000));
+
+        // Fluctuating just under the escalation threshold doesn't recover, since above the recover threshold.
+        for minute in 1..5 {
+            for i in 0..60 {
+                budget.report_at(FAIL_OPEN_IPFILTER, start + minute * 60_000 + i * 1000);
+            }
+            assert!(budget.is_fail_closed_at(FAIL_OPEN_IPFILTER, start + minute * 60_000 + 59_999));
+        }
+    }
+
+    #[test]
+    fn test_disabled_never_fail_closed() {
+        let mut config = create_budget().config;
+        config.enabled = false;
+        let budget = FailOpenBudget::new(&config);
+        let start = 1_800_000_000_000i64;
+        for i in 0..1000 {
+            assert!(!budget.report_at(FAIL_OPEN_IPFILTER, start + i));
+        }
+        assert!(budget.states_at(start + 1000).is_empty());
+    }
+}
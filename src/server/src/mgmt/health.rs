@@ -20,6 +20,8 @@
 
 use crate::config::config::{AppDBType, CacheProvider};
 use crate::context::state::BotwafState;
+use crate::mgmt::fail_open::{FailOpenBudget, FailOpenBudgetStatus};
+use crate::mgmt::supervisor::ComponentSupervisor;
 use async_trait::async_trait;
 use axum::{extract::State, response::IntoResponse, routing::get, Router};
 use botwaf_types::{sys::user::User, PageRequest};
@@ -172,6 +174,66 @@ impl HealthChecker for RedisClusterChecker {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct FailOpenBudgetChecker {}
+
+impl FailOpenBudgetChecker {
+    pub fn new() -> Self {
+        FailOpenBudgetChecker {}
+    }
+}
+
+#[async_trait]
+impl HealthChecker for FailOpenBudgetChecker {
+    async fn check(&self, _state: &BotwafState) -> HealthCheckResult {
+        let budget = FailOpenBudget::get();
+        let details = budget
+            .states()
+            .into_iter()
+            .map(|s| {
+                let status = match s.status {
+                    FailOpenBudgetStatus::ESCALATED => "ESCALATED",
+                    FailOpenBudgetStatus::OK => "OK",
+                };
+                (format!("fail-open-{}", s.subsystem), status.to_string())
+            })
+            .collect();
+        // Only report DOWN when enabled the readiness policy, so that the orchestrator can take the instance out.
+        let status = if budget.is_unready() { "DOWN" } else { "UP" };
+        HealthCheckResult {
+            status: status.to_string(),
+            details,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ComponentSupervisorChecker {}
+
+impl ComponentSupervisorChecker {
+    pub fn new() -> Self {
+        ComponentSupervisorChecker {}
+    }
+}
+
+#[async_trait]
+impl HealthChecker for ComponentSupervisorChecker {
+    async fn check(&self, _state: &BotwafState) -> HealthCheckResult {
+        let supervisor = ComponentSupervisor::get();
+        let details = supervisor
+            .states()
+            .into_iter()
+            .map(|s| (format!("component-{}", s.name), format!("{:?}", s.status)))
+            .collect();
+        // Only report DOWN when enabled the readiness policy, e.g: the updater exceeded the restart budget.
+        let status = if supervisor.is_unready() { "DOWN" } else { "UP" };
+        HealthCheckResult {
+            status: status.to_string(),
+            details,
+        }
+    }
+}
+
 pub fn init() -> Router<BotwafState> {
     Router::new().route(HEALTHZ_URI, get(handle_healthz))
     // .route(STARTUP_HEALTHZ_URI, get(handle_healthz_startup))
@@ -203,5 +265,17 @@ async fn handle_healthz(State(state): State<BotwafState>) -> impl IntoResponse {
         result.status = "DOWN".to_string();
     }
 
+    let fail_open_check = FailOpenBudgetChecker::new().check(&state).await;
+    result.details.extend(fail_open_check.details);
+    if fail_open_check.status == "DOWN" {
+        result.status = "DOWN".to_string();
+    }
+
+    let supervisor_check = ComponentSupervisorChecker::new().check(&state).await;
+    result.details.extend(supervisor_check.details);
+    if supervisor_check.status == "DOWN" {
+        result.status = "DOWN".to_string();
+    }
+
     (StatusCode::OK, serde_json::to_string(&result).unwrap())
 }
@@ -19,4 +19,8 @@
 // This includes modifications and derived works.
 
 pub mod apm;
+pub mod auth;
+pub mod fail_open;
 pub mod health;
+pub mod routes;
+pub mod supervisor;
@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config;
+use crate::modules::modsec::engine_capability::EngineCapabilities;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use botwaf_types::sys::event::BotwafEvent;
+use std::collections::BTreeSet;
+use tower::ServiceExt;
+
+pub const MGMT_METRICS_URI: &str = "/metrics1";
+pub const MGMT_APM_METRICS_URI: &str = "/metrics2";
+pub const MGMT_FAIL_OPEN_BUDGET_URI: &str = "/fail-open-budget";
+pub const MGMT_COMPONENTS_URI: &str = "/components";
+pub const MGMT_CONFIG_URI: &str = "/config";
+pub const MGMT_EVENTS_SCHEMA_URI: &str = "/events/schema";
+pub const MGMT_ENGINE_CAPABILITIES_URI: &str = "/engine/capabilities";
+
+/// The path prefixes of the management only routes (i.e: metrics, debug, config, schema), which must never be
+/// mounted on the public server, e.g: the '/metrics' or '/debug/prof/cpu' of the public router is rejected.
+pub const MGMT_ONLY_PATH_PREFIXES: [&str; 7] = [
+    "/metrics",
+    "/debug",
+    MGMT_CONFIG_URI,
+    MGMT_EVENTS_SCHEMA_URI,
+    MGMT_FAIL_OPEN_BUDGET_URI,
+    MGMT_COMPONENTS_URI,
+    MGMT_ENGINE_CAPABILITIES_URI,
+];
+
+/// The route path registered on both the public and management routers, which is rejected on startup.
+#[derive(Debug, thiserror::Error)]
+#[error("The route '{path}' of the public server overlaps the management server, the management routes (e.g: metrics, debug, config, schema) must never be mounted on the public server")]
+pub struct RouteOverlapError {
+    pub path: String,
+}
+
+/// The header marks the probe requests matched a mounted route, see: is_route_mounted
+const ROUTE_PROBE_HEADER: &str = "x-botwaf-route-probe";
+
+const ROUTE_PROBE_METHODS: [Method; 5] = [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH];
+
+/// Short-circuit the matched requests, so that the handlers (and its extractors) are never invoked on probing.
+async fn route_probe_middleware(_: Request, _: Next) -> Response {
+    (StatusCode::NO_CONTENT, [(ROUTE_PROBE_HEADER, "matched")]).into_response()
+}
+
+/// Whether the path is matched by any route of the router in any method, the router must be probed by the
+/// mounted routes rather than the documented APIs, as the undocumented routes are also served.
+async fn is_route_mounted(probing_router: &Router, path: &str) -> bool {
+    for method in ROUTE_PROBE_METHODS {
+        let request = match Request::builder().method(method).uri(path).body(Body::empty()) {
+            Ok(request) => request,
+            Err(_) => return false,
+        };
+        if let Ok(resp) = probing_router.to_owned().oneshot(request).await {
+            if resp.headers().contains_key(ROUTE_PROBE_HEADER) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Assert the public and management routers are disjoint, i.e: none of the management paths and the management
+/// only prefixes (with or without the context path) is mounted on the public router, which is probed by the
+/// actually mounted routes (including the undocumented and the addition routes), and it must have routes.
+pub async fn check_routes_disjoint(
+    public_router: &Router,
+    mgmt_paths: &[&str],
+    context_path: Option<&str>,
+) -> Result<(), RouteOverlapError> {
+    let context_path = context_path.unwrap_or("").trim_end_matches('/');
+    let mut probe_paths = mgmt_paths.iter().map(|path| path.to_string()).collect::<BTreeSet<_>>();
+    for prefix in MGMT_ONLY_PATH_PREFIXES {
+        probe_paths.insert(prefix.to_string());
+        // The wildcard routes under the prefix, e.g: '/debug/{*path}'
+        probe_paths.insert(format!("{}/_", prefix));
+    }
+    if !context_path.is_empty() {
+        let prefixed = probe_paths
+            .iter()
+            .map(|path| format!("{}{}", context_path, path))
+            .collect::<Vec<_>>();
+        probe_paths.extend(prefixed);
+    }
+
+    let probing_router = public_router
+        .to_owned()
+        .route_layer(middleware::from_fn(route_probe_middleware));
+    for path in probe_paths {
+        if is_route_mounted(&probing_router, &path).await {
+            return Err(RouteOverlapError { path });
+        }
+    }
+    Ok(())
+}
+
+/// Getting the effective configuration with the secrets redacted as '***'.
+pub async fn handle_config() -> impl IntoResponse {
+    Json(config::get_config().inner.to_redacted_value())
+}
+
+/// Getting the versioned JSON schemas of the outbound notification events.
+pub async fn handle_events_schema() -> impl IntoResponse {
+    Json(BotwafEvent::schemas())
+}
+
+/// Getting the version and capabilities of the ModSecurity engine build, probed at startup.
+pub async fn handle_engine_capabilities() -> impl IntoResponse {
+    Json(EngineCapabilities::get().capabilities().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::{AppConfig, AppConfigProperties};
+    use crate::config::swagger;
+    use axum::routing::{get, post};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    const MGMT_PATHS: [&str; 4] = [
+        MGMT_METRICS_URI,
+        MGMT_CONFIG_URI,
+        MGMT_EVENTS_SCHEMA_URI,
+        "/debug/prof/cpu",
+    ];
+
+    fn public_router() -> Router {
+        let mut props = AppConfigProperties::default();
+        props.server.context_path = Some(String::from("/botwaf"));
+        swagger::init(&AppConfig::new(&props)).route("/botwaf/api/v1/rules", get(|| async { "rules" }))
+    }
+
+    #[tokio::test]
+    async fn test_public_routes_disjoint_with_mgmt_only() {
+        assert!(check_routes_disjoint(&public_router(), &MGMT_PATHS, Some("/botwaf"))
+            .await
+            .is_ok());
+
+        for (route, expected) in [
+            ("/metrics", "/metrics"),
+            ("/botwaf/metrics1", "/botwaf/metrics1"),
+            ("/botwaf/debug/prof/cpu", "/botwaf/debug/prof/cpu"),
+            ("/events/schema", "/events/schema"),
+            ("/debug/{*path}", "/debug/_"),
+        ] {
+            let overlapped = public_router().route(route, post(|| async { "overlapped" }));
+            let err = check_routes_disjoint(&overlapped, &MGMT_PATHS, Some("/botwaf"))
+                .await
+                .unwrap_err();
+            assert_eq!(err.path, expected);
+        }
+        // The similar but not management only path.
+        let similar = Router::new()
+            .route("/api/v1/config", get(|| async { "config" }))
+            .route("/metricsx", get(|| async { "metricsx" }));
+        assert!(check_routes_disjoint(&similar, &MGMT_PATHS, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_undocumented_mounted_route_rejected_without_invoking() {
+        // The route is mounted but not documented in the OpenAPI spec, e.g: a debug route of the addition router.
+        let invoked = Arc::new(AtomicUsize::new(0));
+        let counter = invoked.clone();
+        let undocumented = Router::new().route(
+            MGMT_CONFIG_URI,
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { "config" }
+            }),
+        );
+        let router = public_router().merge(undocumented);
+
+        let err = check_routes_disjoint(&router, &MGMT_PATHS, Some("/botwaf"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.path, MGMT_CONFIG_URI);
+        assert_eq!(invoked.load(Ordering::SeqCst), 0);
+    }
+}
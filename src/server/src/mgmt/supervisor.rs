@@ -0,0 +1,399 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{self, SupervisorProperties};
+use crate::mgmt::apm::metrics::{BOTWAF_COMPONENT_FAILED, BOTWAF_COMPONENT_RESTARTS_TOTAL};
+use crate::sys::dead_letter::WebhookDelivery;
+use anyhow::Error;
+use axum::{response::IntoResponse, Json};
+use botwaf_types::sys::event::{BotwafEvent, ComponentFailedV1};
+use botwaf_utils::httpclients;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinError;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<ComponentSupervisor> =
+        Arc::new(ComponentSupervisor::new(&config::get_config().services.supervisor));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum RestartPolicy {
+    // Never restarted, e.g: the web server, whose failure takes the process down.
+    NEVER,
+    // Restarted with the exponential backoff within the restart budget.
+    ON_FAILURE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum ComponentStatus {
+    STARTING,
+    RUNNING,
+    // Waiting for the next restart attempt.
+    BACKING_OFF,
+    // Not restarted anymore, i.e: the policy NEVER or exceeded the restart budget.
+    FAILED_PERMANENTLY,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentState {
+    pub name: String,
+    pub policy: RestartPolicy,
+    pub status: ComponentStatus,
+    // The total restarts since the process started.
+    #[serde(rename = "restartCount")]
+    pub restart_count: u32,
+    // The unix timestamp (in millis) of the next restart attempt while backing off.
+    #[serde(rename = "nextAttemptTime")]
+    pub next_attempt_time: Option<i64>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+}
+
+struct ComponentTracker {
+    state: ComponentState,
+    // The times of the restarts within the restart window.
+    restarts: VecDeque<i64>,
+}
+
+/// The supervisor of the components in the standalone mode, which restarts the failed component by its restart
+/// policy, e.g: the updater is failed to start since the transient vector DB outage.
+pub struct ComponentSupervisor {
+    config: SupervisorProperties,
+    components: Mutex<BTreeMap<String, ComponentTracker>>,
+}
+
+impl ComponentSupervisor {
+    pub fn get() -> Arc<ComponentSupervisor> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(config: &SupervisorProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            components: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn states(&self) -> Vec<ComponentState> {
+        let components = self.components.lock().unwrap();
+        components.values().map(|tracker| tracker.state.to_owned()).collect()
+    }
+
+    /// Whether any component is failed permanently and the readiness policy is enabled.
+    pub fn is_unready(&self) -> bool {
+        self.config.readiness
+            && self
+                .states()
+                .iter()
+                .any(|s| s.status == ComponentStatus::FAILED_PERMANENTLY)
+    }
+
+    /// Record the component which is running without being restarted, e.g: the web server.
+    pub fn mark_running(&self, name: &str, policy: RestartPolicy) {
+        self.update(name, policy, |state| state.status = ComponentStatus::RUNNING);
+    }
+
+    /// Start the component by the restart policy, the start future is resolved with Ok once started (e.g: the
+    /// schedulers are registered), and the failed (Err or panicked) is restarted with the exponential backoff.
+    /// Returns the last error once failed permanently.
+    /// Notice: The start must be idempotent, i.e: skip the already started parts (e.g: the registered schedulers
+    /// and the channel consumers), since the restart re-runs it entirely.
+    pub async fn supervise<F, Fut>(&self, name: &str, policy: RestartPolicy, start: F) -> Result<(), Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        loop {
+            self.update(name, policy, |state| {
+                state.status = ComponentStatus::STARTING;
+                state.next_attempt_time = None;
+            });
+            // Run in the separated task, so that the panic is caught as the failure of the component.
+            let error = match tokio::spawn(start()).await {
+                Ok(Ok(())) => {
+                    self.update(name, policy, |state| state.status = ComponentStatus::RUNNING);
+                    return Ok(());
+                }
+                Ok(Err(e)) => e,
+                Err(e) => Error::msg(Self::panic_message(e)),
+            };
+            match self.on_failure_at(name, policy, &error, chrono::Utc::now().timestamp_millis()) {
+                Some(backoff) => {
+                    tracing::warn!(
+                        "Failed to start the component '{}', restarting after {:?}. cause: {:#}",
+                        name,
+                        backoff,
+                        error
+                    );
+                    tokio::time::sleep(backoff).await;
+                }
+                None => return Err(error),
+            }
+        }
+    }
+
+    // Returns the backoff of the next restart, or None if failed permanently.
+    fn on_failure_at(&self, name: &str, policy: RestartPolicy, error: &Error, now: i64) -> Option<Duration> {
+        let window = self.config.restart_window.as_millis() as i64;
+        let mut components = self.components.lock().unwrap();
+        let tracker = Self::tracker(&mut components, name, policy);
+        tracker.state.last_error = Some(format!("{:#}", error));
+        while tracker.restarts.front().is_some_and(|time| now - time >= window) {
+            tracker.restarts.pop_front();
+        }
+
+        let restarts = tracker.restarts.len() as u32;
+        if policy == RestartPolicy::NEVER || restarts >= self.config.max_restarts {
+            tracker.state.status = ComponentStatus::FAILED_PERMANENTLY;
+            tracker.state.next_attempt_time = None;
+            BOTWAF_COMPONENT_FAILED.with_label_values(&[name]).set(1);
+            tracing::error!(
+                "The component '{}' is failed permanently ({} restarts within {}), the protection may not be working! cause: {:#}",
+                name,
+                restarts,
+                self.config.restart_window,
+                error
+            );
+            self.notify(&tracker.state, restarts);
+            return None;
+        }
+
+        let backoff = self.backoff_of(restarts);
+        tracker.restarts.push_back(now);
+        tracker.state.status = ComponentStatus::BACKING_OFF;
+        tracker.state.restart_count += 1;
+        tracker.state.next_attempt_time = Some(now + backoff.as_millis() as i64);
+        BOTWAF_COMPONENT_RESTARTS_TOTAL.with_label_values(&[name]).inc();
+        Some(backoff)
+    }
+
+    // The exponential backoff by the restarts within the window, i.e: initial-backoff * 2^restarts
+    fn backoff_of(&self, restarts: u32) -> Duration {
+        self.config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(restarts))
+            .min(*self.config.max_backoff)
+    }
+
+    fn update(&self, name: &str, policy: RestartPolicy, f: impl FnOnce(&mut ComponentState)) {
+        let mut components = self.components.lock().unwrap();
+        f(&mut Self::tracker(&mut components, name, policy).state);
+    }
+
+    fn tracker<'a>(
+        components: &'a mut BTreeMap<String, ComponentTracker>,
+        name: &str,
+        policy: RestartPolicy,
+    ) -> &'a mut ComponentTracker {
+        components.entry(name.to_owned()).or_insert_with(|| ComponentTracker {
+            state: ComponentState {
+                name: name.to_owned(),
+                policy,
+                status: ComponentStatus::STARTING,
+                restart_count: 0,
+                next_attempt_time: None,
+                last_error: None,
+            },
+            restarts: VecDeque::new(),
+        })
+    }
+
+    fn panic_message(error: JoinError) -> String {
+        match error.try_into_panic() {
+            Ok(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                format!("The component is panicked. {}", message)
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn notify(&self, state: &ComponentState, restarts: u32) {
+        let webhook_url = match &self.config.notify_webhook_url {
+            Some(url) => url.to_owned(),
+            None => return,
+        };
+        let event = ComponentFailedV1 {
+            component: state.name.to_owned(),
+            restarts,
+            max_restarts: self.config.max_restarts,
+            last_error: state.last_error.to_owned().unwrap_or_default(),
+        };
+        // The failed notification is written into the dead letters for replay.
+        WebhookDelivery::new(&webhook_url, BotwafEvent::ComponentFailedV1(event)).spawn(
+            httpclients::build_default(),
+            &format!("failure of component '{}'", state.name),
+        );
+    }
+}
+
+pub async fn handle_components() -> impl IntoResponse {
+    Json(ComponentSupervisor::get().states())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::duration::DurationSecs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn create_supervisor(initial_backoff_ms: u64, max_restarts: u32) -> ComponentSupervisor {
+        ComponentSupervisor::new(&SupervisorProperties {
+            initial_backoff: DurationSecs::from_millis(initial_backoff_ms),
+            max_backoff: DurationSecs::from_millis(initial_backoff_ms * 4),
+            max_restarts,
+            restart_window: DurationSecs::from_secs(60),
+            readiness: true,
+            notify_webhook_url: None,
+        })
+    }
+
+    fn state_of(supervisor: &ComponentSupervisor, name: &str) -> ComponentState {
+        supervisor.states().into_iter().find(|s| s.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_backoff_schedule_and_budget_exhaustion() {
+        let supervisor = create_supervisor(100, 5);
+        let error = Error::msg("vector DB is unavailable");
+        let start = 1_800_000_000_000i64;
+
+        // The backoff is doubled for each restart, and capped by the max-backoff.
+        let backoffs = (0..5)
+            .map(|i| supervisor.on_failure_at("updater", RestartPolicy::ON_FAILURE, &error, start + i * 1_000))
+            .collect::<Vec<_>>();
+        let expected = [100, 200, 400, 400, 400].map(|ms| Some(Duration::from_millis(ms)));
+        assert_eq!(backoffs, expected);
+        let state = state_of(&supervisor, "updater");
+        assert_eq!(state.status, ComponentStatus::BACKING_OFF);
+        assert_eq!(state.restart_count, 5);
+        assert_eq!(state.next_attempt_time, Some(start + 4_000 + 400));
+        assert!(!supervisor.is_unready());
+
+        // Exceeded the restart budget within the window.
+        assert_eq!(
+            supervisor.on_failure_at("updater", RestartPolicy::ON_FAILURE, &error, start + 5_000),
+            None
+        );
+        let state = state_of(&supervisor, "updater");
+        assert_eq!(state.status, ComponentStatus::FAILED_PERMANENTLY);
+        assert_eq!(state.next_attempt_time, None);
+        assert_eq!(state.last_error.as_deref(), Some("vector DB is unavailable"));
+        assert!(supervisor.is_unready());
+    }
+
+    #[test]
+    fn test_restart_budget_window_slides() {
+        let supervisor = create_supervisor(100, 2);
+        let error = Error::msg("transient");
+        let start = 1_800_000_000_000i64;
+
+        // The restarts out of the window are not counted, and the backoff is reset.
+        for i in 0..10 {
+            let backoff = supervisor.on_failure_at("verifier", RestartPolicy::ON_FAILURE, &error, start + i * 61_000);
+            assert_eq!(backoff, Some(Duration::from_millis(100)));
+        }
+        assert_eq!(state_of(&supervisor, "verifier").restart_count, 10);
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_killed_component() {
+        let supervisor = create_supervisor(10, 5);
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        // The fake component is killed twice, and then started.
+        let result = supervisor
+            .supervise("fake", RestartPolicy::ON_FAILURE, || {
+                let attempts = attempts.clone();
+                async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                        panic!("killed");
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let state = state_of(&supervisor, "fake");
+        assert_eq!(state.status, ComponentStatus::RUNNING);
+        assert_eq!(state.restart_count, 2);
+        assert!(state.last_error.unwrap().contains("killed"));
+        assert!(!supervisor.is_unready());
+    }
+
+    #[tokio::test]
+    async fn test_supervise_exhausts_restart_budget() {
+        let supervisor = create_supervisor(10, 3);
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let result = supervisor
+            .supervise("fake", RestartPolicy::ON_FAILURE, || {
+                let attempts = attempts.clone();
+                async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::msg("killed"))
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().to_string(), "killed");
+        // The first attempt and the 3 restarts.
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+        let state = state_of(&supervisor, "fake");
+        assert_eq!(state.status, ComponentStatus::FAILED_PERMANENTLY);
+        assert_eq!(state.restart_count, 3);
+        assert!(supervisor.is_unready());
+    }
+
+    #[tokio::test]
+    async fn test_supervise_never_restart() {
+        let supervisor = create_supervisor(10, 3);
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let result = supervisor
+            .supervise("web-server", RestartPolicy::NEVER, || {
+                let attempts = attempts.clone();
+                async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::msg("address in use"))
+                }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            state_of(&supervisor, "web-server").status,
+            ComponentStatus::FAILED_PERMANENTLY
+        );
+    }
+}
@@ -0,0 +1,512 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::config::config::KnowledgeUploadProperties;
+use anyhow::{anyhow, Error};
+use axum::body::Bytes;
+use botwaf_utils::ulids;
+use futures::{Stream, StreamExt};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+use unicode_normalization::UnicodeNormalization;
+
+pub const CONTENT_TYPE_TEXT: &str = "text/plain";
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+pub const CONTENT_TYPE_CSV: &str = "text/csv";
+pub const CONTENT_TYPE_GZIP: &str = "application/gzip";
+pub const CONTENT_TYPE_BINARY: &str = "application/octet-stream";
+pub const CONTENT_TYPE_EXECUTABLE: &str = "application/x-executable";
+
+/// The bytes of the file head buffered to sniff the content type, the rest is written through.
+pub const SNIFF_HEAD_BYTES: usize = 8 * 1024;
+
+// The magic bytes of the executables, i.e: ELF, PE(MZ), Mach-O (32/64 bits and both endians, and the fat
+// binary which shares the magic with the java class), WebAssembly and the script with the shebang.
+const EXECUTABLE_MAGICS: [&[u8]; 9] = [
+    b"\x7fELF",
+    b"MZ",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    b"\0asm",
+    b"#!",
+];
+
+/// The uploaded knowledge file which passed the validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeUploadFile {
+    /// The sanitized original filename, which is only for display.
+    pub file_name: String,
+    /// The collision-free name of the stored file, i.e: the ULID with the original extension.
+    pub storage_name: String,
+    /// The content type sniffed from the magic bytes.
+    pub content_type: String,
+    pub size: u64,
+}
+
+/// The failure of saving the uploaded knowledge file, which is mapped to the response status.
+#[derive(Debug, thiserror::Error)]
+pub enum KnowledgeUploadError {
+    // The file is rejected by the validation, e.g: the overlong filename, the executable content.
+    #[error("Rejected file: {0}")]
+    Rejected(Error),
+    #[error("Rejected file: exceeded the max size of {0} bytes")]
+    TooLarge(u64),
+    #[error("Failed to read file: {0}")]
+    Read(String),
+    #[error("Failed to save file: {0}")]
+    Save(Error),
+}
+
+/// Validate the uploaded knowledge file, the error message is the precise reason of the rejection.
+pub fn validate_upload(
+    config: &KnowledgeUploadProperties,
+    file_name: Option<&str>,
+    declared_type: Option<&str>,
+    data: &[u8],
+) -> Result<KnowledgeUploadFile, Error> {
+    validate_upload_head(config, file_name, declared_type, data, false)
+}
+
+/// Validate the uploaded knowledge file by the head of the content, the size is of the head which should be
+/// accumulated while the rest is written through.
+pub fn validate_upload_head(
+    config: &KnowledgeUploadProperties,
+    file_name: Option<&str>,
+    declared_type: Option<&str>,
+    head: &[u8],
+    truncated: bool,
+) -> Result<KnowledgeUploadFile, Error> {
+    let file_name = sanitize_filename(file_name.unwrap_or_default(), config.max_filename_length)?;
+    let content_type = check_content_type_head(config, declared_type, head, truncated)?;
+    let storage_name = match extension_of(&file_name) {
+        Some(ext) => format!("{}.{}", ulids::new_ulid(), ext),
+        None => ulids::new_ulid(),
+    };
+    Ok(KnowledgeUploadFile {
+        file_name,
+        storage_name,
+        content_type,
+        size: head.len() as u64,
+    })
+}
+
+/// Stream the uploaded file (e.g: the multipart field) into the directory by the generated storage name, only
+/// the head is buffered for the validation, and the partially written file is removed on failure.
+pub async fn save_upload_stream<S, E>(
+    config: &KnowledgeUploadProperties,
+    file_name: Option<&str>,
+    declared_type: Option<&str>,
+    stream: S,
+    dir: &Path,
+) -> Result<(KnowledgeUploadFile, PathBuf), KnowledgeUploadError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Display,
+{
+    let mut stream = std::pin::pin!(stream);
+
+    let mut head = Vec::with_capacity(SNIFF_HEAD_BYTES);
+    let mut truncated = false;
+    while !truncated {
+        match stream.next().await {
+            Some(chunk) => head.extend_from_slice(&chunk.map_err(|e| KnowledgeUploadError::Read(e.to_string()))?),
+            None => break,
+        }
+        truncated = head.len() >= SNIFF_HEAD_BYTES;
+    }
+    if head.len() as u64 > config.max_file_bytes {
+        return Err(KnowledgeUploadError::TooLarge(config.max_file_bytes));
+    }
+    let mut upload_file = validate_upload_head(config, file_name, declared_type, &head, truncated)
+        .map_err(KnowledgeUploadError::Rejected)?;
+
+    let path = dir.join(&upload_file.storage_name);
+    let result = async {
+        let mut file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| KnowledgeUploadError::Save(e.into()))?;
+        file.write_all(&head)
+            .await
+            .map_err(|e| KnowledgeUploadError::Save(e.into()))?;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| KnowledgeUploadError::Read(e.to_string()))?;
+            upload_file.size += chunk.len() as u64;
+            if upload_file.size > config.max_file_bytes {
+                return Err(KnowledgeUploadError::TooLarge(config.max_file_bytes));
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| KnowledgeUploadError::Save(e.into()))?;
+        }
+        file.flush().await.map_err(|e| KnowledgeUploadError::Save(e.into()))
+    }
+    .await;
+    match result {
+        Ok(_) => Ok((upload_file, path)),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+/// Sanitize the attacker-controlled filename of the multipart, i.e: the unicode is NFKC normalized (so that the
+/// fullwidth separators are also stripped), the control and bidi characters are removed, and only the last path
+/// component is kept, e.g: '../../etc/passwd' is 'passwd'
+pub fn sanitize_filename(raw: &str, max_length: usize) -> Result<String, Error> {
+    let normalized = raw
+        .nfkc()
+        .filter(|c| !c.is_control() && !is_invisible_format_char(*c))
+        .collect::<String>();
+    let base_name = normalized
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        // Notice: The drive prefix of the windows path, e.g: 'C:passwd'
+        .rsplit(':')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .trim_start_matches('.')
+        .trim();
+    if base_name.is_empty() {
+        return Err(anyhow!(
+            "The filename '{}' is empty after sanitized",
+            raw.escape_default()
+        ));
+    }
+    let length = base_name.chars().count();
+    if length > max_length {
+        return Err(anyhow!(
+            "The filename is too long, {} characters exceed the limit of {}",
+            length,
+            max_length
+        ));
+    }
+    Ok(base_name.to_owned())
+}
+
+/// Sniff the content type from the magic bytes, notice that the CSV is not distinguishable from the plain text.
+pub fn sniff_content_type(data: &[u8]) -> &'static str {
+    sniff_content_type_head(data, false)
+}
+
+/// Sniff the content type from the head of the content, i.e: the truncated multi-byte character at the end is
+/// tolerated, and the JSON is determined by the leading bracket only.
+pub fn sniff_content_type_head(data: &[u8], truncated: bool) -> &'static str {
+    if data.starts_with(b"\x1f\x8b") {
+        return CONTENT_TYPE_GZIP;
+    }
+    if EXECUTABLE_MAGICS.iter().any(|magic| data.starts_with(magic)) {
+        return CONTENT_TYPE_EXECUTABLE;
+    }
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => Ok(text),
+        // The incomplete character at the end of the head.
+        Err(e) if truncated && e.error_len().is_none() => Ok(std::str::from_utf8(&data[..e.valid_up_to()]).unwrap()),
+        Err(e) => Err(e),
+    };
+    match text {
+        Ok(text) if !text.contains('\0') => {
+            let trimmed = text.trim_start();
+            if (trimmed.starts_with('{') || trimmed.starts_with('['))
+                && (truncated || serde_json::from_str::<serde_json::Value>(text).is_ok())
+            {
+                CONTENT_TYPE_JSON
+            } else {
+                CONTENT_TYPE_TEXT
+            }
+        }
+        _ => CONTENT_TYPE_BINARY,
+    }
+}
+
+/// Check the sniffed content type is allowed and consistent with the declared, and returns the effective content
+/// type, e.g: the text declared as 'text/csv' is 'text/csv'
+pub fn check_content_type(
+    config: &KnowledgeUploadProperties,
+    declared_type: Option<&str>,
+    data: &[u8],
+) -> Result<String, Error> {
+    check_content_type_head(config, declared_type, data, false)
+}
+
+/// Check the content type by the head of the content, see: sniff_content_type_head
+pub fn check_content_type_head(
+    config: &KnowledgeUploadProperties,
+    declared_type: Option<&str>,
+    head: &[u8],
+    truncated: bool,
+) -> Result<String, Error> {
+    let sniffed = sniff_content_type_head(head, truncated);
+    if sniffed == CONTENT_TYPE_EXECUTABLE {
+        return Err(anyhow!("The executable content is not allowed"));
+    }
+    // Notice: The parameters are ignored, e.g: 'text/csv; charset=utf-8'
+    let declared = declared_type
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty() && t != CONTENT_TYPE_BINARY)
+        .map(|t| match t.as_str() {
+            "application/x-gzip" => CONTENT_TYPE_GZIP.to_owned(),
+            _ => t,
+        });
+    let effective = match declared.as_deref() {
+        None => sniffed,
+        Some(declared) if declared == sniffed => sniffed,
+        // The JSON and CSV are also the plain text.
+        Some(CONTENT_TYPE_TEXT) if sniffed == CONTENT_TYPE_JSON => CONTENT_TYPE_TEXT,
+        Some(CONTENT_TYPE_CSV) if sniffed == CONTENT_TYPE_TEXT || sniffed == CONTENT_TYPE_JSON => CONTENT_TYPE_CSV,
+        Some(declared) => {
+            return Err(anyhow!(
+                "The declared content type '{}' mismatches the sniffed '{}'",
+                declared,
+                sniffed
+            ))
+        }
+    };
+    if !config
+        .allowed_content_types
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(effective))
+    {
+        return Err(anyhow!(
+            "The content type '{}' is not allowed, expected one of {:?}",
+            effective,
+            config.allowed_content_types
+        ));
+    }
+    Ok(effective.to_owned())
+}
+
+// The bidi controls (e.g: the RTL override which disguises 'exe.txt' as 'txt.exe') and the zero width characters.
+fn is_invisible_format_char(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+}
+
+fn extension_of(file_name: &str) -> Option<String> {
+    file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .filter(|ext| !ext.is_empty() && ext.len() <= 16 && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_filename_strips_directories() {
+        assert_eq!(sanitize_filename("../../etc/passwd", 255).unwrap(), "passwd");
+        assert_eq!(sanitize_filename("..\\..\\windows\\win.ini", 255).unwrap(), "win.ini");
+        assert_eq!(sanitize_filename("C:samples.txt", 255).unwrap(), "samples.txt");
+        // The fullwidth solidus is normalized to '/'
+        assert_eq!(sanitize_filename("..／..／samples.txt", 255).unwrap(), "samples.txt");
+        // The hidden file is not allowed.
+        assert_eq!(sanitize_filename(".bashrc", 255).unwrap(), "bashrc");
+        assert!(sanitize_filename("../", 255).is_err());
+        assert!(sanitize_filename("..", 255).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_filename_removes_bidi_controls() {
+        // The RTL override disguises as 'samplestxt.exe'
+        assert_eq!(
+            sanitize_filename("samples\u{202E}exe.txt", 255).unwrap(),
+            "samplesexe.txt"
+        );
+        assert_eq!(
+            sanitize_filename("sam\u{200B}ples\r\n.txt", 255).unwrap(),
+            "samples.txt"
+        );
+        // The composed and decomposed forms are the same.
+        assert_eq!(
+            sanitize_filename("cafe\u{0301}.txt", 255).unwrap(),
+            sanitize_filename("caf\u{00E9}.txt", 255).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sanitize_filename_overlong() {
+        let name = format!("{}.txt", "a".repeat(1000));
+        let err = sanitize_filename(&name, 255).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The filename is too long, 1004 characters exceed the limit of 255"
+        );
+        // The limit is of the characters rather than the bytes.
+        assert!(sanitize_filename(&"文".repeat(255), 255).is_ok());
+    }
+
+    #[test]
+    fn test_sniff_content_type() {
+        assert_eq!(sniff_content_type(b"\x1f\x8b\x08\x00"), CONTENT_TYPE_GZIP);
+        assert_eq!(sniff_content_type(b"\x7fELF\x02\x01"), CONTENT_TYPE_EXECUTABLE);
+        assert_eq!(sniff_content_type(b"MZ\x90\x00"), CONTENT_TYPE_EXECUTABLE);
+        assert_eq!(sniff_content_type(b"#!/bin/sh\nrm -rf /"), CONTENT_TYPE_EXECUTABLE);
+        assert_eq!(sniff_content_type(b" [{\"q\": \"1 OR 1=1\"}]"), CONTENT_TYPE_JSON);
+        assert_eq!(sniff_content_type(b"[not json"), CONTENT_TYPE_TEXT);
+        assert_eq!(sniff_content_type("\u{FEFF}GET /?q=1".as_bytes()), CONTENT_TYPE_TEXT);
+        assert_eq!(sniff_content_type(b"abc\0def"), CONTENT_TYPE_BINARY);
+    }
+
+    #[test]
+    fn test_check_content_type_mismatched() {
+        let config = KnowledgeUploadProperties::default();
+        let err = check_content_type(&config, Some("application/json"), b"GET /?q=1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The declared content type 'application/json' mismatches the sniffed 'text/plain'"
+        );
+        let err = check_content_type(&config, Some("text/plain"), b"\x7fELF\x02\x01").unwrap_err();
+        assert_eq!(err.to_string(), "The executable content is not allowed");
+        let err = check_content_type(&config, None, b"\xff\xfe\0\x01").unwrap_err();
+        assert!(err.to_string().contains("'application/octet-stream' is not allowed"));
+    }
+
+    #[test]
+    fn test_check_content_type_compatible() {
+        let config = KnowledgeUploadProperties::default();
+        let csv = b"uri,label\n/?q=1,NORMAL\n";
+        assert_eq!(
+            check_content_type(&config, Some("text/csv; charset=utf-8"), csv).unwrap(),
+            "text/csv"
+        );
+        assert_eq!(check_content_type(&config, None, csv).unwrap(), "text/plain");
+        assert_eq!(
+            check_content_type(&config, Some("application/octet-stream"), csv).unwrap(),
+            "text/plain"
+        );
+        assert_eq!(
+            check_content_type(&config, Some("text/plain"), b"{}").unwrap(),
+            "text/plain"
+        );
+        assert_eq!(
+            check_content_type(&config, Some("application/x-gzip"), b"\x1f\x8b\x08\x00").unwrap(),
+            "application/gzip"
+        );
+        // The gzip is not allowed if removed from the allowlist.
+        let config = KnowledgeUploadProperties {
+            allowed_content_types: vec![String::from("text/plain")],
+            ..Default::default()
+        };
+        assert!(check_content_type(&config, None, b"\x1f\x8b\x08\x00").is_err());
+    }
+
+    #[test]
+    fn test_validate_upload() {
+        let config = KnowledgeUploadProperties::default();
+        let first = validate_upload(&config, Some("../samples.TXT"), Some("text/plain"), b"GET /\n").unwrap();
+        let second = validate_upload(&config, Some("../samples.TXT"), Some("text/plain"), b"GET /\n").unwrap();
+        assert_eq!(first.file_name, "samples.TXT");
+        assert_eq!(first.content_type, "text/plain");
+        assert_eq!(first.size, 6);
+        assert!(first.storage_name.ends_with(".txt"));
+        assert_eq!(first.storage_name.len(), 26 + 4);
+        // The duplicate names are stored separately.
+        assert_ne!(first.storage_name, second.storage_name);
+    }
+
+    #[test]
+    fn test_sniff_content_type_head_truncated() {
+        let json = b"[{\"uri\": \"/?q=1\", \"label\": \"NORMAL\"},";
+        assert_eq!(sniff_content_type_head(json, true), CONTENT_TYPE_JSON);
+        assert_eq!(sniff_content_type_head(json, false), CONTENT_TYPE_TEXT);
+        // The multi-byte character cut off at the end of the head.
+        let text = "GET /?q=\u{4e2d}".as_bytes();
+        let head = &text[..text.len() - 1];
+        assert_eq!(sniff_content_type_head(head, true), CONTENT_TYPE_TEXT);
+        assert_eq!(sniff_content_type_head(head, false), CONTENT_TYPE_BINARY);
+    }
+
+    fn create_temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("botwaf-knowledge-upload-{}", ulids::new_ulid()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_chunks(total: usize, chunk_size: usize) -> Vec<Result<Bytes, std::io::Error>> {
+        let line = b"GET /index.html?q=botwaf HTTP/1.1,NORMAL\n";
+        let data = line.iter().cycle().take(total).copied().collect::<Vec<_>>();
+        data.chunks(chunk_size).map(|c| Ok(Bytes::copy_from_slice(c))).collect()
+    }
+
+    // Notice: The single threaded runtime, so that the other tasks are starved if the upload blocks.
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_save_upload_stream_multi_mb_without_blocking() {
+        let (dir, total) = (create_temp_dir(), 8 * 1024 * 1024);
+        let config = KnowledgeUploadProperties::default();
+
+        // The concurrent requests served while uploading.
+        let served = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let ticker = {
+            let served = served.to_owned();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                    served.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+            })
+        };
+        let stream = futures::stream::iter(create_chunks(total, 64 * 1024));
+        let (file, path) = save_upload_stream(&config, Some("samples.csv"), Some("text/csv"), stream, &dir)
+            .await
+            .unwrap();
+        ticker.abort();
+
+        assert_eq!(file.content_type, CONTENT_TYPE_CSV);
+        assert_eq!(file.size, total as u64);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), total as u64);
+        assert!(served.load(std::sync::atomic::Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_save_upload_stream_rejected() {
+        let dir = create_temp_dir();
+        let config = KnowledgeUploadProperties {
+            max_file_bytes: 100 * 1024,
+            ..Default::default()
+        };
+
+        let stream = futures::stream::iter(create_chunks(1024 * 1024, 16 * 1024));
+        let err = save_upload_stream(&config, Some("samples.txt"), None, stream, &dir)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KnowledgeUploadError::TooLarge(_)));
+
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from_static(b"\x7fELF\x02\x01"))]);
+        let err = save_upload_stream(&config, Some("samples.txt"), None, stream, &dir)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KnowledgeUploadError::Rejected(_)));
+
+        // The partially written file is removed.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
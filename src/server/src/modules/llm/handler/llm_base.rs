@@ -32,7 +32,14 @@ use std::{
 pub trait ILLMHandler {
     async fn init(&self);
     async fn embedding(&self, mut info: KnowledgeUploadInfo, file: File) -> Result<KnowledgeUploadInfo, anyhow::Error>;
-    async fn generate(&self, prompt: String) -> Result<String, anyhow::Error>;
+    async fn generate(&self, prompt: String) -> Result<LlmGeneration, anyhow::Error>;
+}
+
+/// The content generated by LLM, and the name of the provider which served it as the provenance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LlmGeneration {
+    pub content: String,
+    pub provider: String,
 }
 
 lazy_static! {
@@ -54,17 +61,23 @@ impl LLMManager {
         &SINGLE_INSTANCE
     }
 
-    pub async fn init() {
+    /// Initialize the LLM handlers, the component (e.g: the command name) registers its embedding model in the
+    /// vector DB to guard against the conflicting models of the other components.
+    pub async fn init(component: &str) {
         let config = &config::get_config().services.llm;
 
+        // The standalone also starts the web server, which is initialized with the same LLM handlers.
+        if Self::get_implementation(LangchainLLMHandler::NAME.to_owned()).is_ok() {
+            tracing::debug!("Already initialized the LLM handler '{}'", LangchainLLMHandler::NAME);
+            return;
+        }
         tracing::info!("Initializing implementation langChain LLM ...");
+        let handler = LangchainLLMHandler::new(config, component).await;
         match Self::get()
             .write() // If acquire fails, then it block until acquired.
             .unwrap() // If acquire fails, then it should panic.
-            .register(
-                LangchainLLMHandler::NAME.to_owned(),
-                LangchainLLMHandler::new(config).await,
-            ) {
+            .register(LangchainLLMHandler::NAME.to_owned(), handler)
+        {
             Ok(registered) => {
                 tracing::info!("Initializing langChain LLM ...");
                 let _ = registered.init().await;
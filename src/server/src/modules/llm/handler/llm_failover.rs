@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::mgmt::apm::dependencies::{instrument_dep, DEP_LLM};
+use crate::mgmt::apm::metrics::BOTWAF_LLM_PROVIDER_CALLS_TOTAL;
+use anyhow::Error;
+use std::{
+    error::Error as StdError,
+    future::Future,
+    io::ErrorKind,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The ordered LLM providers with the automatic failover, the call failed with the request-level failure (e.g:
+/// timeout, 5xx, connection refused) is retried against the next provider, and the failed provider is cooled
+/// down before tried again. The other failures (e.g: 4xx of the bad request) are returned without the failover.
+pub struct LlmProviderChain<P> {
+    kind: &'static str,
+    providers: Vec<LlmProvider<P>>,
+    cooldown: Duration,
+    call_timeout: Duration,
+}
+
+struct LlmProvider<P> {
+    name: String,
+    client: P,
+    cooldown_until: Mutex<Option<Instant>>,
+}
+
+impl<P> LlmProvider<P> {
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.lock().unwrap().is_some_and(|until| until > now)
+    }
+}
+
+impl<P> LlmProviderChain<P> {
+    pub const KIND_EMBEDDING: &'static str = "embedding";
+    pub const KIND_GENERATE: &'static str = "generate";
+
+    pub fn new(kind: &'static str, providers: Vec<(String, P)>, cooldown: Duration, call_timeout: Duration) -> Self {
+        Self {
+            kind,
+            providers: providers
+                .into_iter()
+                .map(|(name, client)| LlmProvider {
+                    name,
+                    client,
+                    cooldown_until: Mutex::new(None),
+                })
+                .collect(),
+            cooldown,
+            call_timeout,
+        }
+    }
+
+    /// Call the providers in turn until served, returns the result and the name of the provider which served it
+    /// for the provenance. The cooling down providers are tried at last rather than failing the call directly.
+    pub async fn call<R, F, Fut>(&self, f: F) -> Result<(R, String), Error>
+    where
+        P: Clone,
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let now = Instant::now();
+        let (ready, cooling): (Vec<_>, Vec<_>) = self.providers.iter().partition(|p| !p.is_cooling_down(now));
+
+        let mut last_error = None;
+        for provider in ready.into_iter().chain(cooling) {
+            let result = instrument_dep(DEP_LLM, self.kind, async {
+                match tokio::time::timeout(self.call_timeout, f(provider.client.clone())).await {
+                    Ok(result) => result,
+                    Err(elapsed) => Err(Error::new(elapsed)),
+                }
+            })
+            .await;
+            match result {
+                Ok(value) => {
+                    *provider.cooldown_until.lock().unwrap() = None;
+                    self.record(provider, "success");
+                    return Ok((value, provider.name.to_owned()));
+                }
+                Err(e) if is_failover_error(&e) => {
+                    tracing::warn!(
+                        "Failed to call the {} LLM provider '{}', cooling down and failing over. cause: {:#}",
+                        self.kind,
+                        provider.name,
+                        e
+                    );
+                    *provider.cooldown_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+                    self.record(provider, "failover");
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    self.record(provider, "error");
+                    return Err(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::msg(format!("No available {} LLM providers.", self.kind))))
+    }
+
+    fn record(&self, provider: &LlmProvider<P>, result: &str) {
+        BOTWAF_LLM_PROVIDER_CALLS_TOTAL
+            .with_label_values(&[self.kind, provider.name.as_str(), result])
+            .inc();
+    }
+}
+
+/// The request-level failure converted from the boxed error which is not 'Send', e.g: of the vector store.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct LlmRequestError(String);
+
+/// Convert the boxed error which is not 'Send', and keep whether it's worth failing over.
+pub fn from_boxed_error(error: Box<dyn StdError>) -> Error {
+    if std::iter::successors(Some(error.as_ref()), |cause| cause.source()).any(is_failover_cause) {
+        Error::new(LlmRequestError(error.to_string()))
+    } else {
+        Error::msg(error.to_string())
+    }
+}
+
+/// Whether the failure is of the request-level which is worth failing over to the next provider, i.e: the
+/// timeout, the 5xx and the connection failures, by any cause in the error chain.
+pub fn is_failover_error(error: &Error) -> bool {
+    error.chain().any(is_failover_cause)
+}
+
+fn is_failover_cause(cause: &(dyn StdError + 'static)) -> bool {
+    if cause.is::<tokio::time::error::Elapsed>() || cause.is::<LlmRequestError>() {
+        return true;
+    }
+    if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+        return e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error());
+    }
+    if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+        return matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::TimedOut
+        );
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::post, Router};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    // The mock OpenAI compatible completions endpoint, which responds the status after the delay.
+    async fn spawn_mock_provider(status: StatusCode, delay: Duration, hits: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let hits = hits.clone();
+                async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    (status, format!("served by {}", addr))
+                }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1", addr)
+    }
+
+    async fn complete(api_uri: String) -> Result<String, Error> {
+        let resp = reqwest::Client::new()
+            .post(format!("{}/chat/completions", api_uri))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(resp.text().await?)
+    }
+
+    fn create_chain(providers: Vec<(&str, String)>, cooldown: Duration) -> LlmProviderChain<String> {
+        let providers = providers
+            .into_iter()
+            .map(|(name, uri)| (name.to_owned(), uri))
+            .collect();
+        LlmProviderChain::new(
+            LlmProviderChain::<String>::KIND_GENERATE,
+            providers,
+            cooldown,
+            Duration::from_millis(500),
+        )
+    }
+
+    fn calls_total(provider: &str, result: &str) -> u64 {
+        BOTWAF_LLM_PROVIDER_CALLS_TOTAL
+            .with_label_values(&["generate", provider, result])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_server_error_and_record_provenance() {
+        let (primary_hits, fallback_hits) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let primary = spawn_mock_provider(StatusCode::BAD_GATEWAY, Duration::ZERO, primary_hits.clone()).await;
+        let fallback = spawn_mock_provider(StatusCode::OK, Duration::ZERO, fallback_hits.clone()).await;
+        let chain = create_chain(
+            vec![("primary-5xx", primary), ("fallback-5xx", fallback.to_owned())],
+            Duration::from_secs(60),
+        );
+
+        let (result, provider) = chain.call(|uri| complete(uri)).await.unwrap();
+        assert!(fallback.contains(result.trim_start_matches("served by ")));
+        assert_eq!(provider, "fallback-5xx");
+        assert_eq!(primary_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(calls_total("primary-5xx", "failover"), 1);
+        assert_eq!(calls_total("fallback-5xx", "success"), 1);
+
+        // The failed primary is skipped while cooling down.
+        let (_, provider) = chain.call(|uri| complete(uri)).await.unwrap();
+        assert_eq!(provider, "fallback-5xx");
+        assert_eq!(primary_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(fallback_hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_connection_refused_and_timeout() {
+        // The port is released after bound, so that the connection is refused.
+        let refused = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}/v1", listener.local_addr().unwrap())
+        };
+        let hits = Arc::new(AtomicUsize::new(0));
+        let slow = spawn_mock_provider(StatusCode::OK, Duration::from_secs(5), hits.clone()).await;
+        let fallback = spawn_mock_provider(StatusCode::OK, Duration::ZERO, hits.clone()).await;
+        let chain = create_chain(
+            vec![
+                ("primary-refused", refused),
+                ("primary-slow", slow),
+                ("fallback-ok", fallback),
+            ],
+            Duration::from_secs(60),
+        );
+
+        let (_, provider) = chain.call(|uri| complete(uri)).await.unwrap();
+        assert_eq!(provider, "fallback-ok");
+        assert_eq!(calls_total("primary-refused", "failover"), 1);
+        assert_eq!(calls_total("primary-slow", "failover"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_on_client_error() {
+        let (primary_hits, fallback_hits) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let primary = spawn_mock_provider(StatusCode::BAD_REQUEST, Duration::ZERO, primary_hits.clone()).await;
+        let fallback = spawn_mock_provider(StatusCode::OK, Duration::ZERO, fallback_hits.clone()).await;
+        let chain = create_chain(
+            vec![("primary-4xx", primary), ("fallback-4xx", fallback)],
+            Duration::from_secs(60),
+        );
+
+        assert!(chain.call(|uri| complete(uri)).await.is_err());
+        assert_eq!(fallback_hits.load(Ordering::SeqCst), 0);
+        assert_eq!(calls_total("primary-4xx", "error"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_cooling_down_providers_after_cooldown() {
+        let (primary_hits, fallback_hits) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let primary = spawn_mock_provider(StatusCode::SERVICE_UNAVAILABLE, Duration::ZERO, primary_hits.clone()).await;
+        let fallback =
+            spawn_mock_provider(StatusCode::SERVICE_UNAVAILABLE, Duration::ZERO, fallback_hits.clone()).await;
+        let chain = create_chain(
+            vec![("primary-cooldown", primary), ("fallback-cooldown", fallback)],
+            Duration::from_millis(100),
+        );
+
+        // All the providers are failed and cooling down, but still tried rather than failing directly.
+        assert!(is_failover_error(&chain.call(|uri| complete(uri)).await.unwrap_err()));
+        assert!(chain.call(|uri| complete(uri)).await.is_err());
+        assert_eq!(primary_hits.load(Ordering::SeqCst), 2);
+        assert_eq!(fallback_hits.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(!chain.providers[0].is_cooling_down(Instant::now()));
+    }
+
+    #[test]
+    fn test_from_boxed_error_keeps_failover_cause() {
+        let refused: Box<dyn StdError> = Box::new(std::io::Error::from(ErrorKind::ConnectionRefused));
+        assert!(is_failover_error(&from_boxed_error(refused)));
+
+        let invalid: Box<dyn StdError> = "invalid metadata".into();
+        assert!(!is_failover_error(&from_boxed_error(invalid)));
+    }
+}
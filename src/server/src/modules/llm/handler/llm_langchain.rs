@@ -18,135 +18,220 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use super::llm_base::ILLMHandler;
-use crate::config::config::{self, LlmProperties};
-use anyhow::{Ok, Result};
+use super::knowledge_upload::CONTENT_TYPE_GZIP;
+use super::llm_base::{ILLMHandler, LlmGeneration};
+use super::llm_failover::{from_boxed_error, LlmProviderChain};
+use super::vector_maintenance::{
+    EmbeddingRegistration, IVectorMaintenanceHandler, PgVectorMaintenanceHandler, METADATA_CREATE_AT,
+    METADATA_EMBEDDING_MODEL, METADATA_KNOWLEDGE_ID, METADATA_NAMESPACE,
+};
+use crate::config::config::{self, EmbeddingLLMProperties, GenerateLLMProperties, LlmProperties};
+use crate::mgmt::apm::dependencies::{instrument_dep, DEP_VECTOR_STORE};
+use anyhow::{Error, Ok, Result};
 use botwaf_types::modules::llm::knowledge::{KnowledgeCategory, KnowledgeStatus, KnowledgeUploadInfo};
+use flate2::read::GzDecoder;
 use langchain_rust::{
-    chain::{Chain, ConversationalRetrieverChainBuilder},
     embedding::openai::OpenAiEmbedder,
     fmt_message, fmt_template,
-    language_models::options::CallOptions,
+    language_models::{llm::LLM, options::CallOptions},
     llm::{OpenAI, OpenAIConfig},
-    memory::WindowBufferMemory,
     message_formatter,
-    prompt::HumanMessagePromptTemplate,
+    prompt::{HumanMessagePromptTemplate, MessageFormatter},
     prompt_args,
     schemas::{Document, FunctionCallBehavior, Message},
-    template_jinja2,
-    vectorstore::{pgvector::StoreBuilder, Retriever, VecStoreOptions, VectorStore},
+    vectorstore::{pgvector::StoreBuilder, VecStoreOptions, VectorStore},
 };
 use std::{
     collections::HashMap,
     fs::File,
+    future::Future,
     io::{BufRead, BufReader},
     sync::Arc,
+    time::Duration,
 };
 
 /// see:https://github.com/wl4g-ai/langchain-rust/blob/main/examples/conversational_retriever_chain_with_vector_store.rs
 pub struct LangchainLLMHandler {
+    embedding_providers: LlmProviderChain<EmbeddingProvider>,
+    generate_providers: LlmProviderChain<OpenAI<OpenAIConfig>>,
+    // The timeout of each vector store operation, see: PgVectorDBProperties::statement_timeout
+    vector_store_timeout: Duration,
+}
+
+/// The vector store operation is timed out (e.g: the hung vector DB), which is distinguished from the failures
+/// by downcasting the error, e.g: error.downcast_ref::<VectorStoreTimeout>()
+#[derive(Debug, thiserror::Error)]
+#[error("Timed out the vector store '{operation}' after {timeout:?}")]
+pub struct VectorStoreTimeout {
+    pub operation: &'static str,
+    pub timeout: Duration,
+}
+
+/// Run the vector store operation with the timeout, so that the hung vector DB never stalls the caller.
+pub async fn with_vector_store_timeout<T, F>(operation: &'static str, timeout: Duration, fut: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match tokio::time::timeout(timeout, fut).await {
+        std::result::Result::Ok(result) => result,
+        Err(_) => Err(Error::new(VectorStoreTimeout { operation, timeout })),
+    }
+}
+
+/// The vector store with the embedder of the embedding provider, all the providers share the same collection.
+#[derive(Clone)]
+struct EmbeddingProvider {
+    model: String,
     pgvec_store: Arc<Box<dyn VectorStore>>,
-    openai_llm: OpenAI<OpenAIConfig>,
 }
 
 impl LangchainLLMHandler {
     pub const NAME: &'static str = "LANGCHAIN";
 
     #[allow(unused)]
-    pub async fn new(config: &LlmProperties) -> Arc<Self> {
-        // Create the embedding openai config.
+    pub async fn new(config: &LlmProperties, component: &str) -> Arc<Self> {
         let llm_config = &config::get_config().services.llm;
-        let mut embedding_openai_config =
-            OpenAIConfig::new().with_api_base(&config::get_config().services.llm.embedding.api_uri);
-        if let Some(api_key) = &config::get_config().services.llm.embedding.api_key {
-            // Default used by 'OPENAI_KEY' and 'OPENAI_BASE_URL'.
-            // Not require API key to run model by Ollama default.
-            embedding_openai_config = embedding_openai_config.with_api_key(api_key);
-        }
-        if let Some(org_id) = &config::get_config().services.llm.embedding.org_id {
-            embedding_openai_config = embedding_openai_config.with_org_id(org_id);
-        }
-        if let Some(project_id) = &config::get_config().services.llm.embedding.project_id {
-            embedding_openai_config = embedding_openai_config.with_org_id(project_id);
-        }
-
         let vecdb_config = &config::get_config().vecdb;
+        // The statement timeout is also applied on the server side, so that the timed out queries are cancelled.
         let pgconn_url = format!(
-            "postgresql://{}:{}@{}:{}/{}?schema={}",
+            "postgresql://{}:{}@{}:{}/{}?schema={}&options=-c%20statement_timeout%3D{}",
             vecdb_config.pg_vector.username,
             vecdb_config.pg_vector.password.to_owned().unwrap_or_default(),
             vecdb_config.pg_vector.host,
             vecdb_config.pg_vector.port,
             vecdb_config.pg_vector.database,
             vecdb_config.pg_vector.schema,
+            vecdb_config.pg_vector.statement_timeout.as_millis(),
         );
-        // Create the knowledge vector store for PG vector.
-        let pgvec_store = StoreBuilder::new()
-            .embedder(OpenAiEmbedder::new(embedding_openai_config))
-            .pre_delete_collection(false)
-            .connection_url(pgconn_url.as_str())
-            .vector_dimensions(1536)
-            .build()
+        let connect_timeout = *vecdb_config.pg_vector.connect_timeout;
+        // Provision the extension and the tables before building, which fails with the opaque error otherwise.
+        // The fallback providers are of the same vector dimensions, see: LlmProperties::validate_providers
+        let embedding_config = &llm_config.embedding;
+        let maintenance = PgVectorMaintenanceHandler::get();
+        if let Err(e) = maintenance.provision(embedding_config.vector_dimensions).await {
+            panic!("Failed to provision the vector store. cause: {}", e);
+        }
+        // Refuse to start with the conflicting embedding model of the other components sharing the vector DB.
+        let registration = EmbeddingRegistration::new(component, embedding_config);
+        if let Err(e) = maintenance
+            .register_embedding(&registration, vecdb_config.embedding_mismatch)
             .await
-            .unwrap();
+        {
+            panic!("Failed to register the embedding model. cause: {}", e);
+        }
+        let pre_delete = match maintenance.resolve_pre_delete(embedding_config).await {
+            std::result::Result::Ok(pre_delete) => pre_delete,
+            Err(e) => panic!("Failed to check the vector store collection. cause: {}", e),
+        };
 
+        // Create the knowledge vector store for PG vector of each embedding provider, only the primary
+        // is pre-deleted the collection.
+        let mut embedding_providers = Vec::new();
+        for (i, provider) in llm_config.embedding_providers().into_iter().enumerate() {
+            let build = StoreBuilder::new()
+                .embedder(OpenAiEmbedder::new(Self::build_embedding_openai_config(provider)))
+                .pre_delete_collection(pre_delete && i == 0)
+                .connection_url(pgconn_url.as_str())
+                .vector_dimensions(provider.vector_dimensions as i32)
+                .build();
+            let pgvec_store = match tokio::time::timeout(connect_timeout, build).await {
+                std::result::Result::Ok(std::result::Result::Ok(pgvec_store)) => pgvec_store,
+                std::result::Result::Ok(Err(e)) => panic!(
+                    "Failed to build the pgvector store of '{}'. cause: {}",
+                    provider.provider_name(),
+                    e
+                ),
+                Err(_) => panic!(
+                    "Timed out connecting the pgvector store of '{}' after {:?}",
+                    provider.provider_name(),
+                    connect_timeout
+                ),
+            };
+            let embedding_provider = EmbeddingProvider {
+                model: provider.model.to_owned(),
+                pgvec_store: Arc::new(Box::new(pgvec_store)),
+            };
+            embedding_providers.push((provider.provider_name(), embedding_provider));
+        }
+
+        // Create the call LLM client for openai compability of each generate provider.
+        let generate_providers = llm_config
+            .generate_providers()
+            .into_iter()
+            .map(|provider| (provider.provider_name(), Self::build_generate_openai_llm(provider)))
+            .collect();
+
+        // Create the this updater handler instance.
+        Arc::new(Self {
+            embedding_providers: LlmProviderChain::new(
+                LlmProviderChain::<EmbeddingProvider>::KIND_EMBEDDING,
+                embedding_providers,
+                *llm_config.failover_cooldown,
+                *llm_config.failover_call_timeout,
+            ),
+            generate_providers: LlmProviderChain::new(
+                LlmProviderChain::<OpenAI<OpenAIConfig>>::KIND_GENERATE,
+                generate_providers,
+                *llm_config.failover_cooldown,
+                *llm_config.failover_call_timeout,
+            ),
+            vector_store_timeout: *vecdb_config.pg_vector.statement_timeout,
+        })
+    }
+
+    fn build_embedding_openai_config(config: &EmbeddingLLMProperties) -> OpenAIConfig {
+        let mut embedding_openai_config = OpenAIConfig::new().with_api_base(&config.api_uri);
+        if let Some(api_key) = &config.api_key {
+            // Default used by 'OPENAI_KEY' and 'OPENAI_BASE_URL'.
+            // Not require API key to run model by Ollama default.
+            embedding_openai_config = embedding_openai_config.with_api_key(api_key);
+        }
+        if let Some(org_id) = &config.org_id {
+            embedding_openai_config = embedding_openai_config.with_org_id(org_id);
+        }
+        if let Some(project_id) = &config.project_id {
+            embedding_openai_config = embedding_openai_config.with_org_id(project_id);
+        }
+        embedding_openai_config
+    }
+
+    fn build_generate_openai_llm(config: &GenerateLLMProperties) -> OpenAI<OpenAIConfig> {
         // Create call LLM config for openai compability.
-        let mut call_openai_config = OpenAIConfig::new().with_api_base(&llm_config.generate.api_uri);
-        if let Some(api_key) = &llm_config.generate.api_key {
+        let mut call_openai_config = OpenAIConfig::new().with_api_base(&config.api_uri);
+        if let Some(api_key) = &config.api_key {
             call_openai_config = call_openai_config.with_api_key(api_key);
         }
-        if let Some(org_id) = &llm_config.generate.org_id {
+        if let Some(org_id) = &config.org_id {
             call_openai_config = call_openai_config.with_org_id(org_id);
         }
-        if let Some(project_id) = &llm_config.generate.project_id {
+        if let Some(project_id) = &config.project_id {
             call_openai_config = call_openai_config.with_org_id(project_id);
         }
 
-        // Create the call LLM client for openai compability.
         let call_opts = CallOptions::new()
-            .with_max_tokens(config::get_config().services.llm.generate.max_tokens)
-            .with_temperature(config::get_config().services.llm.generate.temperature)
-            .with_candidate_count(config::get_config().services.llm.generate.candidate_count)
+            .with_max_tokens(config.max_tokens)
+            .with_temperature(config.temperature)
+            .with_candidate_count(config.candidate_count)
             // TODO: whether the support configuration of this items?
             .with_functions(Vec::new())
             .with_stop_words(Vec::new())
-            .with_top_k(config::get_config().services.llm.generate.top_k)
-            .with_top_p(config::get_config().services.llm.generate.top_p)
+            .with_top_k(config.top_k)
+            .with_top_p(config.top_p)
             // .with_seed(0)
             .with_function_call_behavior(FunctionCallBehavior::Auto);
-        let openai_llm = OpenAI::new(call_openai_config)
-            .with_model(config::get_config().services.llm.generate.model.to_owned())
-            .with_options(call_opts);
-
-        // Create the this updater handler instance.
-        Arc::new(Self {
-            pgvec_store: Arc::new(Box::new(pgvec_store)),
-            openai_llm,
-        })
+        OpenAI::new(call_openai_config)
+            .with_model(config.model.to_owned())
+            .with_options(call_opts)
     }
-}
-
-#[async_trait::async_trait]
-impl ILLMHandler for LangchainLLMHandler {
-    async fn init(&self) {}
-
-    async fn embedding(&self, mut info: KnowledgeUploadInfo, file: File) -> Result<KnowledgeUploadInfo, anyhow::Error> {
-        info.status = KnowledgeStatus::RECEIVED;
 
-        // TODO: Update to upload table.
-        // ...
-
-        info.status = KnowledgeStatus::PERSISTING;
-        // TODO: Upload to Object Storage for backup raw file
-        // ...
-
-        info.status = KnowledgeStatus::PREPARING;
-        // TODO: Update to upload table.
-        // ...
-
-        // Parse file into documents
-        let reader = BufReader::new(file);
+    // Parse the file into the documents of the non-empty lines, which is blocking (e.g: the gzip decompressing).
+    fn read_documents(info: &KnowledgeUploadInfo, file: File) -> Vec<Document> {
+        let reader: Box<dyn BufRead> = match info.content_type.as_deref() {
+            Some(CONTENT_TYPE_GZIP) => Box::new(BufReader::new(GzDecoder::new(file))),
+            _ => Box::new(BufReader::new(file)),
+        };
         let mut documents = Vec::new();
+        let namespace = format!("{:?}", info.category);
 
         for (line_num, line_result) in reader.lines().enumerate() {
             if let std::result::Result::Ok(content) = line_result {
@@ -158,6 +243,11 @@ impl ILLMHandler for LangchainLLMHandler {
                 let mut metadata = HashMap::new();
                 metadata.insert("filename".to_string(), info.name.clone().into());
                 metadata.insert("linenum".to_string(), line_num.to_string().into());
+                // The maintenance metadata, see: vector_maintenance::namespace_stats/cleanup
+                // The embedding model is of the provider which served, see below.
+                metadata.insert(METADATA_KNOWLEDGE_ID.to_string(), info.id.clone().into());
+                metadata.insert(METADATA_NAMESPACE.to_string(), namespace.clone().into());
+                metadata.insert(METADATA_CREATE_AT.to_string(), info.create_at.into());
 
                 // Addidtion the user-provided labels.
                 for (key, value) in &info.labels {
@@ -167,6 +257,46 @@ impl ILLMHandler for LangchainLLMHandler {
                 documents.push(Document::new(&content).with_metadata(metadata));
             }
         }
+        documents
+    }
+}
+
+#[async_trait::async_trait]
+impl ILLMHandler for LangchainLLMHandler {
+    async fn init(&self) {
+        let maintenance = PgVectorMaintenanceHandler::get();
+        if let Err(e) = maintenance.init().await {
+            tracing::error!("Failed to init the vector store maintenance. cause: {}", e);
+            return;
+        }
+        if let Err(e) =
+            PgVectorMaintenanceHandler::start_scheduler(maintenance, &config::get_config().vecdb.maintenance).await
+        {
+            tracing::error!("Failed to start the vector store maintenance scheduler. cause: {}", e);
+        }
+    }
+
+    async fn embedding(&self, mut info: KnowledgeUploadInfo, file: File) -> Result<KnowledgeUploadInfo, anyhow::Error> {
+        let maintenance = PgVectorMaintenanceHandler::get();
+
+        info.status = KnowledgeStatus::RECEIVED;
+        if let Err(e) = maintenance.save_knowledge(&info).await {
+            tracing::warn!("Failed to save knowledge record {}. cause: {}", info.id, e);
+        }
+
+        info.status = KnowledgeStatus::PERSISTING;
+        // TODO: Upload to Object Storage for backup raw file
+        // ...
+
+        info.status = KnowledgeStatus::PREPARING;
+        // TODO: Update to upload table.
+        // ...
+
+        // Parse file into documents, which is read on the blocking threads to avoid starving the runtime.
+        let documents = {
+            let info = info.clone();
+            tokio::task::spawn_blocking(move || Self::read_documents(&info, file)).await?
+        };
 
         let store_options = match info.category {
             KnowledgeCategory::NORMAL => {
@@ -185,108 +315,117 @@ impl ILLMHandler for LangchainLLMHandler {
         // TODO: Update to upload table.
         // ...
 
-        match self.pgvec_store.add_documents(&documents, &store_options).await {
-            std::result::Result::Ok(_) => {
-                tracing::info!("Embedding success.");
+        let result = self
+            .embedding_providers
+            .call(|provider| {
+                let (documents, store_options) = (&documents, &store_options);
+                async move {
+                    let documents = documents
+                        .iter()
+                        .cloned()
+                        .map(|mut doc| {
+                            doc.metadata
+                                .insert(METADATA_EMBEDDING_MODEL.to_string(), provider.model.clone().into());
+                            doc
+                        })
+                        .collect::<Vec<_>>();
+                    instrument_dep(
+                        DEP_VECTOR_STORE,
+                        "add",
+                        with_vector_store_timeout("add", self.vector_store_timeout, async {
+                            provider
+                                .pgvec_store
+                                .add_documents(&documents, store_options)
+                                .await
+                                .map_err(from_boxed_error)
+                        }),
+                    )
+                    .await
+                }
+            })
+            .await;
+        match result {
+            std::result::Result::Ok((_, provider)) => {
+                tracing::info!("Embedding success by the provider '{}'.", provider);
                 info.status = KnowledgeStatus::EMBEDDED;
-                // TODO: update to upload table.
-                // ...
             }
             Err(e) => {
                 tracing::error!("Embedding failed: {}", e);
+                // The partially written embeddings will be removed by the maintenance cleanup.
                 info.status = KnowledgeStatus::FAILED;
-                // TODO: update to upload table.
-                // ...
             }
         }
+        if let Err(e) = maintenance.save_knowledge(&info).await {
+            tracing::warn!("Failed to update knowledge record {}. cause: {}", info.id, e);
+        }
 
         Ok(info)
     }
 
-    async fn generate(&self, prompt: String) -> Result<String, anyhow::Error> {
-        // Native OpenAI to completions.
-        // let messages = vec![ChatCompletionMessage {
-        //     role: ChatCompletionMessageRole::System,
-        //     content: Some("You are a helpful assistant.".to_string()),
-        //     name: None,
-        //     function_call: None,
-        //     tool_call_id: None,
-        //     tool_calls: None,
-        // }];
-        // let embedding_result = ChatCompletion::builder(&config::get_config().botwaf.llm.embedding.model, messages.clone())
-        //     .credentials(self.embedding_openai_config.as_ref().to_owned())
-        //     .create()
-        //     .await;
-        // match embedding_result {
-        //     Ok(ret) => {
-        //         let msg = ret.choices.first().unwrap().message.clone();
-        //         // Assistant: Sure! Here's a random crab fact: ...
-        //         tracing::info!("{:#?}: {}", msg.role, msg.content.unwrap().trim());
-        //         // send to LLM to analyze
-        //         // update the ModSecurity rule to state according LLM analysis result
-        //     }
-        //     Err(e) => {
-        //         tracing::error!("Failed to LLM embedding: {:?}", e);
-        //         return;
-        //     }
-        // }
-
-        // TODO: retriever & generate.
-        let prompt= message_formatter![
-                    fmt_message!(Message::new_system_message("You are a helpful assistant")),
-                    fmt_template!(HumanMessagePromptTemplate::new(template_jinja2!("
-Use the following pieces of context to answer the question at the end. If you don't know the answer, just say that you don't know, don't try to make up an answer.
-
-{{context}}
-
-Question:{{question}}
-
-Helpful Answer:
-        ", "context","question")))
-                ];
-
+    async fn generate(&self, prompt: String) -> Result<LlmGeneration, anyhow::Error> {
         let opts = VecStoreOptions::new()
             .with_name_space("botwaf") // TODO: namespace
             .with_score_threshold(0.3 as f32); // TODO: score threshold
 
-        let retriever = Retriever::new(
-            Arc::try_unwrap(self.pgvec_store.to_owned())
-                .unwrap_or_else(|_| panic!("Failed to initial retriever with pgvec store.")),
-            1024,
-        )
-        .with_options(opts);
-        let chain = ConversationalRetrieverChainBuilder::new()
-            .llm(self.openai_llm.to_owned())
-            .rephrase_question(true)
-            .return_source_documents(true)
-            .memory(WindowBufferMemory::new(100).into())
-            .retriever(retriever)
-            //If you want to use the default prompt remove the .prompt()
-            //Keep in mind if you want to change the prompt; this chain need the {{context}} variable
-            .prompt(prompt)
-            .build()
-            .expect("Error building ConversationalChain");
-
-        let input_variables = prompt_args! {
-            "question" => "Hi",
-            "input" => "Who is the writer of 20,000 Leagues Under the Sea, and what is my name?",
-            "history" => vec![
-                Message::new_human_message("My name is: Luis"),
-                Message::new_ai_message("Hi Luis"),
-            ],
-        };
-
-        let result = chain.invoke(input_variables).await;
-        if let std::result::Result::Ok(result) = result {
-            println!("Result: {:?}", result);
-        }
-
-        todo!()
+        // Retrieve the context documents, which is also failed over since the question is embedded.
+        let (documents, _) = self
+            .embedding_providers
+            .call(|provider| {
+                let (prompt, opts) = (&prompt, &opts);
+                async move {
+                    instrument_dep(
+                        DEP_VECTOR_STORE,
+                        "search",
+                        with_vector_store_timeout("search", self.vector_store_timeout, async {
+                            provider
+                                .pgvec_store
+                                .similarity_search(prompt, 4, opts) // TODO: limit
+                                .await
+                                .map_err(from_boxed_error)
+                        }),
+                    )
+                    .await
+                }
+            })
+            .await?;
+        let context = documents
+            .iter()
+            .map(|doc| doc.page_content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // The prompts are loaded from the external files and reloaded on config hot reload, see: LlmPrompts
+        let prompts = &config::get_config().llm_prompts;
+        let messages = message_formatter![
+            fmt_message!(Message::new_system_message(&prompts.system_prompt)),
+            fmt_template!(HumanMessagePromptTemplate::new(prompts.retrieval_template()))
+        ]
+        .format_messages(prompt_args! {
+            "context" => context,
+            "question" => prompt,
+        })
+        .map_err(Error::new)?;
+
+        let (content, provider) = self
+            .generate_providers
+            .call(|llm| {
+                let messages = &messages;
+                async move {
+                    llm.generate(messages)
+                        .await
+                        .map(|result| result.generation)
+                        .map_err(Error::new)
+                }
+            })
+            .await?;
+        Ok(LlmGeneration { content, provider })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{with_vector_store_timeout, VectorStoreTimeout};
+    use std::time::{Duration, Instant};
     // use super::*;
 
     // #[tokio::test]
@@ -331,4 +470,33 @@ mod tests {
 
     //     todo!()
     // }
+
+    #[tokio::test]
+    async fn test_slow_vector_store_operation_timed_out() {
+        let timeout = Duration::from_millis(50);
+        let started = Instant::now();
+        // Simulate the hung vector DB, which never responds in the test.
+        let err = with_vector_store_timeout("search", timeout, async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            anyhow::Ok(Vec::<String>::new())
+        })
+        .await
+        .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The timeout is distinguished from the failures.
+        let timed_out = err.downcast_ref::<VectorStoreTimeout>().unwrap();
+        assert_eq!(timed_out.operation, "search");
+        assert_eq!(timed_out.timeout, timeout);
+
+        let err = with_vector_store_timeout("add", timeout, async {
+            Err::<(), _>(anyhow::Error::msg("connection refused"))
+        })
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<VectorStoreTimeout>().is_none());
+
+        let result = with_vector_store_timeout("add", timeout, async { anyhow::Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+    }
 }
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::LlmProperties;
+use anyhow::{anyhow, Result};
+use langchain_rust::{
+    prompt::{PromptFromatter, PromptTemplate, TemplateFormat},
+    prompt_args,
+};
+use std::{fs, io::ErrorKind};
+
+pub const RETRIEVAL_CONTEXT_VARIABLE: &str = "context";
+pub const RETRIEVAL_QUESTION_VARIABLE: &str = "question";
+
+pub const DEFAULT_RETRIEVAL_PROMPT: &str = "
+Use the following pieces of context to answer the question at the end. If you don't know the answer, just say that you don't know, don't try to make up an answer.
+
+{{context}}
+
+Question:{{question}}
+
+Helpful Answer:
+        ";
+
+/// The resolved LLM prompts, which are loaded from the external files on startup and config hot reload.
+#[derive(Debug, Clone)]
+pub struct LlmPrompts {
+    pub system_prompt: String,
+    pub retrieval_prompt: String,
+}
+
+impl LlmPrompts {
+    /// The inline system prompt and the built-in retrieval template without any external files.
+    pub fn inline(config: &LlmProperties) -> Self {
+        Self {
+            system_prompt: config.generate.system_prompt.to_owned(),
+            retrieval_prompt: DEFAULT_RETRIEVAL_PROMPT.to_owned(),
+        }
+    }
+
+    /// Load the external prompt files, fallback to the inline/default prompt if the file is missing,
+    /// but the unreadable file or invalid retrieval template will be rejected.
+    pub fn load(config: &LlmProperties) -> Result<Self> {
+        let mut prompts = Self::inline(config);
+        if let Some(system_prompt) = Self::read_prompt_file(config.generate.system_prompt_file.as_deref())? {
+            prompts.system_prompt = system_prompt;
+        }
+        if let Some(retrieval_prompt) = Self::read_prompt_file(config.retrieval.prompt_file.as_deref())? {
+            Self::validate_retrieval_template(&retrieval_prompt)?;
+            prompts.retrieval_prompt = retrieval_prompt;
+        }
+        Ok(prompts)
+    }
+
+    pub fn retrieval_template(&self) -> PromptTemplate {
+        PromptTemplate::new(
+            self.retrieval_prompt.to_owned(),
+            vec![
+                RETRIEVAL_CONTEXT_VARIABLE.to_owned(),
+                RETRIEVAL_QUESTION_VARIABLE.to_owned(),
+            ],
+            TemplateFormat::Jinja2,
+        )
+    }
+
+    /// Validate the retrieval template compiles and contains the required variables.
+    pub fn validate_retrieval_template(template: &str) -> Result<()> {
+        for variable in [RETRIEVAL_CONTEXT_VARIABLE, RETRIEVAL_QUESTION_VARIABLE] {
+            let pattern = regex::Regex::new(&format!(r"\{{\{{\s*{}\s*\}}\}}", variable)).unwrap();
+            if !pattern.is_match(template) {
+                return Err(anyhow!(
+                    "The retrieval prompt template missing required variable '{{{{{}}}}}'",
+                    variable
+                ));
+            }
+        }
+        let prompts = Self {
+            system_prompt: String::new(),
+            retrieval_prompt: template.to_owned(),
+        };
+        prompts
+            .retrieval_template()
+            .format(prompt_args! {
+                RETRIEVAL_CONTEXT_VARIABLE => "",
+                RETRIEVAL_QUESTION_VARIABLE => "",
+            })
+            .map_err(|e| anyhow!("The retrieval prompt template failed to compile. cause: {}", e))?;
+        Ok(())
+    }
+
+    fn read_prompt_file(path: Option<&str>) -> Result<Option<String>> {
+        let path = match path {
+            Some(path) if !path.trim().is_empty() => path,
+            _ => return Ok(None),
+        };
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                tracing::warn!(
+                    "The prompt file {} not found, fallback to the inline/default prompt.",
+                    path
+                );
+                Ok(None)
+            }
+            Err(e) => Err(anyhow!("Failed to read the prompt file {}. cause: {}", path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn write_prompt_file(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("botwaf-prompt-test-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_load_valid_external_templates() {
+        let mut config = LlmProperties::default();
+        config.generate.system_prompt_file = Some(write_prompt_file("system.txt", "You are a WAF analyst."));
+        config.retrieval.prompt_file = Some(write_prompt_file(
+            "retrieval.j2",
+            "Known samples:\n{{ context }}\n\nRequest: {{question}}\nVerdict:",
+        ));
+
+        let prompts = LlmPrompts::load(&config).unwrap();
+        assert_eq!(prompts.system_prompt, "You are a WAF analyst.");
+        assert!(prompts.retrieval_prompt.starts_with("Known samples:"));
+    }
+
+    #[test]
+    fn test_load_missing_files_fallback() {
+        let mut config = LlmProperties::default();
+        config.generate.system_prompt_file = Some("/nonexistent/botwaf/system.txt".to_owned());
+        config.retrieval.prompt_file = Some("/nonexistent/botwaf/retrieval.j2".to_owned());
+
+        let prompts = LlmPrompts::load(&config).unwrap();
+        assert_eq!(prompts.system_prompt, config.generate.system_prompt);
+        assert_eq!(prompts.retrieval_prompt, DEFAULT_RETRIEVAL_PROMPT);
+    }
+
+    #[test]
+    fn test_reject_template_missing_variable() {
+        let mut config = LlmProperties::default();
+        config.retrieval.prompt_file = Some(write_prompt_file("missing-question.j2", "Context: {{context}}"));
+
+        let err = LlmPrompts::load(&config).unwrap_err();
+        assert!(err.to_string().contains("'{{question}}'"));
+    }
+}
@@ -18,5 +18,9 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod knowledge_upload;
 pub mod llm_base;
+pub mod llm_failover;
 pub mod llm_langchain;
+pub mod llm_prompt;
+pub mod vector_maintenance;
@@ -0,0 +1,576 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{
+    self, EmbeddingLLMProperties, EmbeddingMismatchPolicy, PgVectorDBProperties, VectorMaintenanceProperties,
+};
+use anyhow::{anyhow, Error, Ok};
+use async_trait::async_trait;
+use botwaf_types::modules::llm::knowledge::{
+    KnowledgeNamespaceStats, KnowledgeUploadInfo, VectorCleanupResult, VectorIndexType, VectorReindexReport,
+    VectorReindexRequest,
+};
+use common_audit_log::audit_log;
+use lazy_static::lazy_static;
+use sqlx::{postgres::PgPoolOptions, PgPool, Row};
+use std::{sync::Arc, time::Instant};
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+/// The tables name of the langchain pgvector store, see: langchain_rust::vectorstore::pgvector::StoreBuilder
+pub const COLLECTION_TABLE_NAME: &'static str = "langchain_pg_collection";
+pub const EMBEDDING_TABLE_NAME: &'static str = "langchain_pg_embedding";
+/// The knowledge records table, used to track the embedding source state.
+pub const KNOWLEDGE_TABLE_NAME: &'static str = "botwaf_knowledge";
+pub const EMBEDDING_INDEX_NAME: &'static str = "botwaf_langchain_pg_embedding_idx";
+/// The active embedding model and dimensions registered by each component sharing the vector DB.
+pub const EMBEDDING_REGISTRY_TABLE_NAME: &'static str = "botwaf_embedding_registry";
+
+// The embedding metadata keys of written by the LLM handler.
+pub const METADATA_KNOWLEDGE_ID: &'static str = "knowledge_id";
+pub const METADATA_NAMESPACE: &'static str = "namespace";
+pub const METADATA_EMBEDDING_MODEL: &'static str = "embedding_model";
+pub const METADATA_CREATE_AT: &'static str = "create_at";
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<PgVectorMaintenanceHandler> =
+        PgVectorMaintenanceHandler::new(&config::get_config().vecdb.pg_vector);
+}
+
+#[async_trait]
+pub trait IVectorMaintenanceHandler: Send + Sync {
+    async fn init(&self) -> Result<(), Error>;
+
+    /// Provision the vector store schema idempotently, i.e: the pgvector extension (if the role permits) and the
+    /// collection tables, so that the misconfigured database fails fast with the actionable error.
+    async fn provision(&self, vector_dimensions: usize) -> Result<(), Error>;
+
+    /// Whether to pre-delete the collection when building the store, the non-empty collection is only deleted
+    /// with the force flag.
+    async fn resolve_pre_delete(&self, config: &EmbeddingLLMProperties) -> Result<bool, Error>;
+
+    /// Register the active embedding model and dimensions of the component, which guards against embedding and
+    /// searching with the conflicting models in the shared vector DB, returns the conflicting registrations of the
+    /// other components if allowed by the policy.
+    async fn register_embedding(
+        &self,
+        registration: &EmbeddingRegistration,
+        policy: EmbeddingMismatchPolicy,
+    ) -> Result<Vec<EmbeddingRegistration>, Error>;
+
+    async fn save_knowledge(&self, info: &KnowledgeUploadInfo) -> Result<(), Error>;
+
+    async fn delete_knowledge(&self, knowledge_id: String) -> Result<u64, Error>;
+
+    async fn namespace_stats(&self) -> Result<Vec<KnowledgeNamespaceStats>, Error>;
+
+    async fn cleanup(&self, dry_run: bool) -> Result<VectorCleanupResult, Error>;
+
+    async fn reindex(&self, param: VectorReindexRequest) -> Result<VectorReindexReport, Error>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingRegistration {
+    pub component: String,
+    pub model: String,
+    pub vector_dimensions: usize,
+}
+
+impl EmbeddingRegistration {
+    pub fn new(component: &str, config: &EmbeddingLLMProperties) -> Self {
+        Self {
+            component: component.to_owned(),
+            model: config.model.to_owned(),
+            vector_dimensions: config.vector_dimensions,
+        }
+    }
+
+    pub fn conflicts_with(&self, other: &EmbeddingRegistration) -> bool {
+        self.component != other.component
+            && (self.model != other.model || self.vector_dimensions != other.vector_dimensions)
+    }
+}
+
+/// Check the active embedding registration against the registrations of the other components, returns the
+/// conflicting registrations if allowed by the policy.
+pub fn check_embedding_conflicts(
+    registration: &EmbeddingRegistration,
+    registrations: &[EmbeddingRegistration],
+    policy: EmbeddingMismatchPolicy,
+) -> Result<Vec<EmbeddingRegistration>, Error> {
+    let conflicts: Vec<EmbeddingRegistration> = registrations
+        .iter()
+        .filter(|r| registration.conflicts_with(r))
+        .cloned()
+        .collect();
+    if conflicts.is_empty() {
+        return Ok(conflicts);
+    }
+    let details = conflicts
+        .iter()
+        .map(|r| format!("'{}' with '{}' ({} dimensions)", r.component, r.model, r.vector_dimensions))
+        .collect::<Vec<String>>()
+        .join(", ");
+    match policy {
+        EmbeddingMismatchPolicy::REJECT => Err(anyhow!(
+            "The embedding model '{}' ({} dimensions) of the component '{}' conflicts with the registered \
+             components: {}, please use the same 'services.llm.embedding' for all the components, or remove the \
+             stale registration by: DELETE FROM {} WHERE component = '<name>';",
+            registration.model,
+            registration.vector_dimensions,
+            registration.component,
+            details,
+            EMBEDDING_REGISTRY_TABLE_NAME
+        )),
+        EmbeddingMismatchPolicy::WARN => {
+            tracing::warn!(
+                "The embedding model '{}' ({} dimensions) of the component '{}' conflicts with the registered \
+                 components: {}, the similarity search may returns the irrelevant results.",
+                registration.model,
+                registration.vector_dimensions,
+                registration.component,
+                details
+            );
+            Ok(conflicts)
+        }
+    }
+}
+
+pub struct PgVectorMaintenanceHandler {
+    pool: PgPool,
+    database: String,
+    username: String,
+}
+
+impl PgVectorMaintenanceHandler {
+    pub fn new(config: &PgVectorDBProperties) -> Arc<Self> {
+        let db_url = format!(
+            "postgres://{}:{}@{}:{}/{}?options=-c%20search_path%3D{}%20-c%20statement_timeout%3D{}",
+            config.username,
+            config.password.as_deref().unwrap_or(""),
+            config.host,
+            config.port,
+            config.database,
+            config.schema,
+            config.statement_timeout.as_millis(),
+        );
+        // Notice: Lazy connect to avoid blocking the startup when vector DB is unavailable.
+        let pool = PgPoolOptions::new()
+            .min_connections(config.min_connections.unwrap_or(1))
+            .max_connections(config.max_connections.unwrap_or(10))
+            .acquire_timeout(*config.connect_timeout)
+            .connect_lazy(&db_url)
+            .expect("Failed to create the pgvector maintenance pool");
+        Arc::new(Self {
+            pool,
+            database: config.database.to_owned(),
+            username: config.username.to_owned(),
+        })
+    }
+
+    pub fn get() -> Arc<PgVectorMaintenanceHandler> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    /// Schedule the cleanup of orphaned embeddings with cron.
+    pub async fn start_scheduler(this: Arc<Self>, config: &VectorMaintenanceProperties) -> Result<(), Error> {
+        if !config.enabled {
+            tracing::info!("Skipping the vector store maintenance scheduler.");
+            return Ok(());
+        }
+        let dry_run = config.dry_run;
+        let scheduler = JobScheduler::new_with_channel_size(config.channel_size).await?;
+        let job = Job::new_async(config.cron.as_str(), move |_uuid, _lock| {
+            let that = this.clone();
+            Box::pin(async move {
+                match that.cleanup(dry_run).await {
+                    std::result::Result::Ok(result) => tracing::info!("Scheduled vector cleanup result: {:?}", result),
+                    Err(e) => tracing::error!("Failed to scheduled vector cleanup. cause: {}", e),
+                }
+            })
+        })?;
+        scheduler.add(job).await?;
+        scheduler.start().await?;
+        tracing::info!("Started the vector store maintenance scheduler with cron '{}'", config.cron);
+        Ok(())
+    }
+
+    fn build_index_statement(param: &VectorReindexRequest) -> String {
+        match param.index_type {
+            VectorIndexType::HNSW => format!(
+                "CREATE INDEX {} ON {} USING hnsw (embedding {}) WITH (m = {}, ef_construction = {})",
+                EMBEDDING_INDEX_NAME, EMBEDDING_TABLE_NAME, param.ops, param.m, param.ef_construction
+            ),
+            VectorIndexType::IVFFLAT => format!(
+                "CREATE INDEX {} ON {} USING ivfflat (embedding {}) WITH (lists = {})",
+                EMBEDDING_INDEX_NAME, EMBEDDING_TABLE_NAME, param.ops, param.lists
+            ),
+        }
+    }
+
+    /// Run the sample workload of nearest neighbor queries and returns the average latency in millis.
+    async fn sample_latency(&self, samples: u32) -> Result<f64, Error> {
+        let sample_ids: Vec<String> = sqlx::query(&format!(
+            "SELECT uuid::TEXT AS id FROM {} ORDER BY random() LIMIT $1",
+            EMBEDDING_TABLE_NAME
+        ))
+        .bind(samples as i64)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|row| row.get::<String, _>("id"))
+        .collect();
+        if sample_ids.is_empty() {
+            return Ok(0.0);
+        }
+
+        let sql = format!(
+            "SELECT uuid FROM {table} ORDER BY embedding <=> (SELECT embedding FROM {table} WHERE uuid::TEXT = $1) LIMIT 10",
+            table = EMBEDDING_TABLE_NAME
+        );
+        let start = Instant::now();
+        for id in sample_ids.iter() {
+            sqlx::query(&sql).bind(id).fetch_all(&self.pool).await?;
+        }
+        Ok(start.elapsed().as_secs_f64() * 1000.0 / sample_ids.len() as f64)
+    }
+
+    async fn provision_extension(&self) -> Result<(), Error> {
+        let installed: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'vector')")
+            .fetch_one(&self.pool)
+            .await?;
+        if installed {
+            return Ok(());
+        }
+        // Notice: Requires the superuser (or the database owner since PG13 trusted extensions), and the pgvector
+        // package installed on the database server.
+        if let Err(e) = sqlx::query("CREATE EXTENSION IF NOT EXISTS vector").execute(&self.pool).await {
+            return Err(anyhow!(
+                "The pgvector extension is not installed in the vector database '{}', and the role '{}' could not \
+                 create it. Please install the pgvector package on the database server, and run \
+                 'CREATE EXTENSION vector;' as the superuser. cause: {}",
+                self.database,
+                self.username,
+                e
+            ));
+        }
+        tracing::info!("Created the pgvector extension in the vector database '{}'", self.database);
+        Ok(())
+    }
+
+    /// The dimensions of the existing embedding column, i.e: the type modifier of the vector type.
+    async fn embedding_dimensions(&self) -> Result<Option<i32>, Error> {
+        Ok(sqlx::query_scalar(&format!(
+            "SELECT a.atttypmod FROM pg_attribute a WHERE a.attrelid = to_regclass('{}') AND a.attname = 'embedding'",
+            EMBEDDING_TABLE_NAME
+        ))
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn count_embeddings(&self, predicate: String) -> Result<u64, Error> {
+        let sql = format!("SELECT COUNT(*) AS cnt FROM {} e WHERE {}", EMBEDDING_TABLE_NAME, predicate);
+        Ok(sqlx::query(&sql).fetch_one(&self.pool).await?.get::<i64, _>("cnt") as u64)
+    }
+
+    /// The predicate of stale embeddings which knowledge record is FAILED or deleted.
+    fn stale_predicate() -> String {
+        format!(
+            "e.cmetadata ? '{key}' AND NOT EXISTS (SELECT 1 FROM {knowledge} k WHERE k.id = e.cmetadata->>'{key}' \
+             AND k.status <> 'FAILED' AND k.del_flag = 0)",
+            key = METADATA_KNOWLEDGE_ID,
+            knowledge = KNOWLEDGE_TABLE_NAME
+        )
+    }
+
+    fn orphaned_predicate() -> String {
+        format!(
+            "NOT EXISTS (SELECT 1 FROM {} c WHERE c.uuid = e.collection_id)",
+            COLLECTION_TABLE_NAME
+        )
+    }
+}
+
+#[async_trait]
+impl IVectorMaintenanceHandler for PgVectorMaintenanceHandler {
+    async fn init(&self) -> Result<(), Error> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id VARCHAR(64) PRIMARY KEY,
+                name VARCHAR(255) NOT NULL,
+                category VARCHAR(32) NOT NULL,
+                status VARCHAR(32) NOT NULL,
+                del_flag INTEGER NOT NULL DEFAULT 0,
+                create_time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                update_time TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )",
+            KNOWLEDGE_TABLE_NAME
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                component VARCHAR(64) PRIMARY KEY,
+                model VARCHAR(255) NOT NULL,
+                vector_dimensions INTEGER NOT NULL,
+                update_time TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )",
+            EMBEDDING_REGISTRY_TABLE_NAME
+        ))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn provision(&self, vector_dimensions: usize) -> Result<(), Error> {
+        self.provision_extension().await?;
+
+        // The same schema as created by the langchain pgvector store, see: StoreBuilder::build
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                name VARCHAR,
+                cmetadata JSONB,
+                uuid UUID NOT NULL PRIMARY KEY,
+                UNIQUE (name)
+            )",
+            COLLECTION_TABLE_NAME
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                collection_id UUID REFERENCES {}(uuid) ON DELETE CASCADE,
+                embedding VECTOR({}),
+                document VARCHAR,
+                cmetadata JSONB,
+                custom_id VARCHAR,
+                uuid UUID NOT NULL PRIMARY KEY
+            )",
+            EMBEDDING_TABLE_NAME, COLLECTION_TABLE_NAME, vector_dimensions
+        ))
+        .execute(&self.pool)
+        .await?;
+        match self.embedding_dimensions().await? {
+            Some(dimensions) if dimensions > 0 && dimensions as usize != vector_dimensions => {
+                return Err(anyhow!(
+                    "The dimensions of the existing embeddings table '{}' is {}, but the configured \
+                     'services.llm.embedding.vector-dimensions' is {}, please re-create the table or use the \
+                     matched embedding model.",
+                    EMBEDDING_TABLE_NAME,
+                    dimensions,
+                    vector_dimensions
+                ));
+            }
+            _ => {}
+        }
+
+        self.init().await
+    }
+
+    async fn resolve_pre_delete(&self, config: &EmbeddingLLMProperties) -> Result<bool, Error> {
+        if !config.pre_delete_collection {
+            return Ok(false);
+        }
+        let documents = self.count_embeddings(String::from("TRUE")).await?;
+        if documents == 0 {
+            return Ok(true);
+        }
+        if !config.pre_delete_collection_force {
+            tracing::warn!(
+                "Skipped the pre-delete of the non-empty collection with {} embeddings in the vector database '{}', \
+                 please make sure that's the expected database, and set up 'pre-delete-collection-force: true' to \
+                 delete it.",
+                documents,
+                self.database
+            );
+            return Ok(false);
+        }
+        tracing::warn!(
+            "Pre-deleting the non-empty collection with {} embeddings in the vector database '{}' by force.",
+            documents,
+            self.database
+        );
+        Ok(true)
+    }
+
+    async fn register_embedding(
+        &self,
+        registration: &EmbeddingRegistration,
+        policy: EmbeddingMismatchPolicy,
+    ) -> Result<Vec<EmbeddingRegistration>, Error> {
+        // Notice: Lock the registry to serialize the check and the upsert of the components starting concurrently.
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "LOCK TABLE {} IN SHARE ROW EXCLUSIVE MODE",
+            EMBEDDING_REGISTRY_TABLE_NAME
+        ))
+        .execute(&mut *tx)
+        .await?;
+        let registrations: Vec<EmbeddingRegistration> = sqlx::query(&format!(
+            "SELECT component, model, vector_dimensions FROM {} ORDER BY component",
+            EMBEDDING_REGISTRY_TABLE_NAME
+        ))
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|row| EmbeddingRegistration {
+            component: row.get("component"),
+            model: row.get("model"),
+            vector_dimensions: row.get::<i32, _>("vector_dimensions") as usize,
+        })
+        .collect();
+        // The rejected registration is rolled back when the transaction is dropped.
+        let conflicts = check_embedding_conflicts(registration, &registrations, policy)?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (component, model, vector_dimensions) VALUES ($1, $2, $3) \
+             ON CONFLICT (component) DO UPDATE SET model = EXCLUDED.model, \
+             vector_dimensions = EXCLUDED.vector_dimensions, update_time = NOW()",
+            EMBEDDING_REGISTRY_TABLE_NAME
+        ))
+        .bind(&registration.component)
+        .bind(&registration.model)
+        .bind(registration.vector_dimensions as i32)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        tracing::info!(
+            "Registered the embedding model '{}' ({} dimensions) of the component '{}'",
+            registration.model,
+            registration.vector_dimensions,
+            registration.component
+        );
+        Ok(conflicts)
+    }
+
+    async fn save_knowledge(&self, info: &KnowledgeUploadInfo) -> Result<(), Error> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (id, name, category, status) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (id) DO UPDATE SET status = EXCLUDED.status, update_time = NOW()",
+            KNOWLEDGE_TABLE_NAME
+        ))
+        .bind(&info.id)
+        .bind(&info.name)
+        .bind(format!("{:?}", info.category))
+        .bind(format!("{:?}", info.status))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[audit_log("[KNOWLEDGE][DELETE] id: {knowledge_id}")]
+    async fn delete_knowledge(&self, knowledge_id: String) -> Result<u64, Error> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET del_flag = 1, update_time = NOW() WHERE id = $1",
+            KNOWLEDGE_TABLE_NAME
+        ))
+        .bind(&knowledge_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn namespace_stats(&self) -> Result<Vec<KnowledgeNamespaceStats>, Error> {
+        let sql = format!(
+            "SELECT COALESCE(e.cmetadata->>'{ns}', c.name) AS namespace,
+                    COUNT(e.uuid) AS documents,
+                    COALESCE(SUM(pg_column_size(e.*)), 0)::BIGINT AS storage_bytes,
+                    MAX(e.cmetadata->>'{model}') AS embedding_model,
+                    MAX((e.cmetadata->>'{create_at}')::BIGINT) AS last_write_time
+             FROM {embedding} e LEFT JOIN {collection} c ON c.uuid = e.collection_id
+             GROUP BY 1 ORDER BY 1",
+            ns = METADATA_NAMESPACE,
+            model = METADATA_EMBEDDING_MODEL,
+            create_at = METADATA_CREATE_AT,
+            embedding = EMBEDDING_TABLE_NAME,
+            collection = COLLECTION_TABLE_NAME
+        );
+        let rows = sqlx::query(&sql).fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| KnowledgeNamespaceStats {
+                namespace: row.try_get::<Option<String>, _>("namespace").ok().flatten().unwrap_or_default(),
+                documents: row.get("documents"),
+                storage_bytes: row.get("storage_bytes"),
+                embedding_model: row.try_get("embedding_model").ok().flatten(),
+                last_write_time: row
+                    .try_get::<Option<i64>, _>("last_write_time")
+                    .ok()
+                    .flatten()
+                    .map(|t| t as u64),
+            })
+            .collect())
+    }
+
+    #[audit_log("[VECTOR][CLEANUP] dry_run: {dry_run}")]
+    async fn cleanup(&self, dry_run: bool) -> Result<VectorCleanupResult, Error> {
+        let orphaned = self.count_embeddings(Self::orphaned_predicate()).await?;
+        let stale = self.count_embeddings(Self::stale_predicate()).await?;
+
+        let mut removed = 0;
+        if !dry_run {
+            let sql = format!(
+                "DELETE FROM {} e WHERE ({}) OR ({})",
+                EMBEDDING_TABLE_NAME,
+                Self::orphaned_predicate(),
+                Self::stale_predicate()
+            );
+            removed = sqlx::query(&sql).execute(&self.pool).await?.rows_affected();
+        }
+
+        let result = VectorCleanupResult {
+            dry_run,
+            orphaned,
+            stale,
+            removed,
+        };
+        tracing::info!("Cleanup vector store result: {:?}", result);
+        Ok(result)
+    }
+
+    #[audit_log("[VECTOR][REINDEX] ops: {param.ops}, dry_run: {param.dry_run}")]
+    async fn reindex(&self, param: VectorReindexRequest) -> Result<VectorReindexReport, Error> {
+        let statement = Self::build_index_statement(&param);
+        let before_avg_millis = self.sample_latency(param.samples).await?;
+
+        let mut after_avg_millis = None;
+        if !param.dry_run {
+            tracing::info!("Rebuilding the vector index: {}", statement);
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(&format!("DROP INDEX IF EXISTS {}", EMBEDDING_INDEX_NAME))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&statement).execute(&mut *tx).await?;
+            tx.commit().await?;
+            sqlx::query(&format!("ANALYZE {}", EMBEDDING_TABLE_NAME))
+                .execute(&self.pool)
+                .await?;
+            after_avg_millis = Some(self.sample_latency(param.samples).await?);
+        }
+
+        Ok(VectorReindexReport {
+            index_name: EMBEDDING_INDEX_NAME.to_owned(),
+            statement,
+            dry_run: param.dry_run,
+            samples: param.samples,
+            before_avg_millis,
+            after_avg_millis,
+        })
+    }
+}
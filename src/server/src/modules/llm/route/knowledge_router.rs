@@ -19,30 +19,77 @@
 // This includes modifications and derived works.
 
 use crate::context::state::BotwafState;
+use crate::modules::llm::handler::knowledge_upload::{self, KnowledgeUploadError};
+use crate::modules::llm::handler::vector_maintenance::{IVectorMaintenanceHandler, PgVectorMaintenanceHandler};
 use axum::{
-    extract::{Multipart, State},
+    extract::{DefaultBodyLimit, Multipart, Query, State},
     response::IntoResponse,
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
-use botwaf_types::modules::llm::knowledge::KnowledgeUploadInfo;
+use botwaf_types::modules::llm::knowledge::{
+    KnowledgeNamespaceStats, KnowledgeUploadInfo, VectorCleanupRequest, VectorCleanupResult,
+};
+use botwaf_types::RespBase;
 use hyper::StatusCode;
-use sqlx::types::uuid;
 use std::fs::{self, File};
-use std::io::Write;
 use std::path::PathBuf;
 use tokio::fs::create_dir_all;
-use uuid::Uuid;
 
 pub fn init() -> Router<BotwafState> {
-    Router::new().route("/api/v1/knowledge/upload", post(handle_knowledge_upload))
+    Router::new()
+        // Notice: The size of the uploaded file is limited while streaming, see: KnowledgeUploadProperties
+        .route(
+            "/api/v1/knowledge/upload",
+            post(handle_knowledge_upload).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/v1/knowledge/namespaces", get(handle_knowledge_namespaces))
+        .route("/api/v1/knowledge/jobs/cleanup", post(handle_knowledge_cleanup))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/knowledge/namespaces",
+    responses((status = 200, description = "Getting the vector store namespaces statistics.", body = [KnowledgeNamespaceStats])),
+    tag = "Knowledge"
+)]
+async fn handle_knowledge_namespaces(State(_state): State<BotwafState>) -> impl IntoResponse {
+    match PgVectorMaintenanceHandler::get().namespace_stats().await {
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/knowledge/jobs/cleanup",
+    params(VectorCleanupRequest),
+    responses((status = 200, description = "Cleanup the orphaned embeddings of failed or deleted knowledge.", body = VectorCleanupResult)),
+    tag = "Knowledge"
+)]
+async fn handle_knowledge_cleanup(
+    State(_state): State<BotwafState>,
+    Query(param): Query<VectorCleanupRequest>,
+) -> impl IntoResponse {
+    // Notice: Defaults to dry-run to avoid deleting by mistake.
+    match PgVectorMaintenanceHandler::get()
+        .cleanup(param.dry_run.unwrap_or(true))
+        .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response(),
+    }
 }
 
 #[utoipa::path(
     post,
     path = "/api/v1/knowledge/upload",
     request_body = KnowledgeUploadInfo,
-    responses((status = 200, description = "Upload Knowledge.", body = KnowledgeUploadInfo)),
+    responses(
+        (status = 200, description = "Upload Knowledge.", body = KnowledgeUploadInfo),
+        (status = 413, description = "The file exceeds the max size."),
+        (status = 422, description = "The file is rejected, e.g: the overlong filename, the executable or mismatched content type."),
+    ),
     tag = "Knowledge"
 )]
 async fn handle_knowledge_upload(State(state): State<BotwafState>, mut multipart: Multipart) -> impl IntoResponse {
@@ -60,26 +107,42 @@ async fn handle_knowledge_upload(State(state): State<BotwafState>, mut multipart
 
     // Extract file and metadata from multipart form
     let mut file_path = None;
+    let mut upload_file = None;
     let mut knowledge_data = None;
+    let upload_config = &state.config.services.llm.knowledge_upload;
 
     // Loop through each field in the multipart form.
     while let Ok(Some(field)) = multipart.next_field().await {
         match field.name() {
             Some("file") => {
                 // Process file upload
-                let file_name = field.file_name().unwrap_or("unknown").to_string();
-                let unique_filename = format!("{}-{}", Uuid::new_v4(), file_name);
-                let file_path_str = temp_dir_path.join(&unique_filename).to_string_lossy().to_string();
-
-                match field.bytes().await {
-                    Ok(data) => {
-                        if let Err(e) = File::create(&file_path_str).and_then(|mut f| f.write_all(&data)) {
-                            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save file: {}", e))
-                                .into_response();
-                        }
-                        file_path = Some(file_path_str);
+                // Notice: The filename is attacker-controlled, so the file is stored by the generated name.
+                let file_name = field.file_name().map(|n| n.to_owned());
+                let declared_type = field.content_type().map(|t| t.to_owned());
+
+                // Stream into the temp file without buffering the whole file.
+                match knowledge_upload::save_upload_stream(
+                    upload_config,
+                    file_name.as_deref(),
+                    declared_type.as_deref(),
+                    field,
+                    &temp_dir_path,
+                )
+                .await
+                {
+                    Ok((file, path)) => {
+                        file_path = Some(path.to_string_lossy().to_string());
+                        upload_file = Some(file);
+                    }
+                    Err(e) => {
+                        let status = match e {
+                            KnowledgeUploadError::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                            KnowledgeUploadError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+                            KnowledgeUploadError::Read(_) => StatusCode::BAD_REQUEST,
+                            KnowledgeUploadError::Save(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                        return (status, e.to_string()).into_response();
                     }
-                    Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read file: {}", e)).into_response(),
                 }
             }
             Some("metadata") => {
@@ -107,13 +170,19 @@ async fn handle_knowledge_upload(State(state): State<BotwafState>, mut multipart
         Some(path) => path,
         None => return (StatusCode::BAD_REQUEST, "No file uploaded".to_string()).into_response(),
     };
-    let knowledge_info = match knowledge_data {
+    // Create cleanup guard for temp file
+    let _cleanup_guard = CleanupGuard::new(&file_path);
+
+    let mut knowledge_info = match knowledge_data {
         Some(data) => data,
         None => return (StatusCode::BAD_REQUEST, "No metadata provided".to_string()).into_response(),
     };
-
-    // Create cleanup guard for temp file
-    let _cleanup_guard = CleanupGuard::new(&file_path);
+    if let Some(file) = upload_file {
+        knowledge_info.file_name = Some(file.file_name);
+        knowledge_info.storage_name = Some(file.storage_name);
+        knowledge_info.content_type = Some(file.content_type);
+        knowledge_info.size = Some(file.size);
+    }
 
     // Process file content and create documents
     let file = match File::open(&file_path) {
@@ -135,6 +204,10 @@ async fn handle_knowledge_upload(State(state): State<BotwafState>, mut multipart
                 "name": &info.name,
                 "status": info.status,
                 "lines": info.lines,
+                "file_name": &info.file_name,
+                "storage_name": &info.storage_name,
+                "content_type": &info.content_type,
+                "size": info.size,
             });
             (StatusCode::OK, Json(response)).into_response()
         }
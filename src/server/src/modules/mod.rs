@@ -18,4 +18,7 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod forward;
 pub mod llm;
+pub mod modsec;
+pub mod privacy;
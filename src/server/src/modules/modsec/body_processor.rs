@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+/// The built-in directives that enable request body inspection and hint libmodsecurity which body
+/// processor to use for the request `Content-Type`, so that `ARGS`/`ARGS_POST`/`FILES` based rules
+/// see the parsed parameters instead of only the raw `REQUEST_BODY`.
+///
+/// Notice: The rule ids `200000-200002` are aligned with `modsecurity.conf-recommended`.
+pub const BODY_PROCESSOR_RULES: &'static str = r#"
+SecRequestBodyAccess On
+SecRule REQUEST_HEADERS:Content-Type "@rx ^application/x-www-form-urlencoded" "id:200000,phase:1,t:none,t:lowercase,pass,nolog,ctl:requestBodyProcessor=URLENCODED"
+SecRule REQUEST_HEADERS:Content-Type "@rx ^application/(?:[a-z0-9.+-]+\+)?json" "id:200001,phase:1,t:none,t:lowercase,pass,nolog,ctl:requestBodyProcessor=JSON"
+SecRule REQUEST_HEADERS:Content-Type "@rx ^multipart/form-data" "id:200002,phase:1,t:none,t:lowercase,pass,nolog,ctl:requestBodyProcessor=MULTIPART"
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestBodyProcessor {
+    URLENCODED,
+    JSON,
+    MULTIPART,
+    RAW,
+}
+
+impl RequestBodyProcessor {
+    /// Detect the body processor by the request `Content-Type` header value, e.g:
+    /// `application/json; charset=utf-8` => `JSON`, unknown or missing types fall back to `RAW`.
+    pub fn detect(content_type: Option<&str>) -> Self {
+        let mime = content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|ct| ct.trim().to_lowercase())
+            .unwrap_or_default();
+        match mime.as_str() {
+            "application/x-www-form-urlencoded" => RequestBodyProcessor::URLENCODED,
+            "multipart/form-data" => RequestBodyProcessor::MULTIPART,
+            m if m == "application/json" || (m.starts_with("application/") && m.ends_with("+json")) => {
+                RequestBodyProcessor::JSON
+            }
+            _ => RequestBodyProcessor::RAW,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestBodyProcessor::URLENCODED => "URLENCODED",
+            RequestBodyProcessor::JSON => "JSON",
+            RequestBodyProcessor::MULTIPART => "MULTIPART",
+            RequestBodyProcessor::RAW => "RAW",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modsecurity::{ModSecurity, Rules};
+
+    #[test]
+    fn test_detect_body_processor() {
+        assert_eq!(
+            RequestBodyProcessor::detect(Some("application/x-www-form-urlencoded")),
+            RequestBodyProcessor::URLENCODED
+        );
+        assert_eq!(
+            RequestBodyProcessor::detect(Some("Application/JSON; charset=UTF-8")),
+            RequestBodyProcessor::JSON
+        );
+        assert_eq!(
+            RequestBodyProcessor::detect(Some("application/problem+json")),
+            RequestBodyProcessor::JSON
+        );
+        assert_eq!(
+            RequestBodyProcessor::detect(Some("multipart/form-data; boundary=----abc")),
+            RequestBodyProcessor::MULTIPART
+        );
+        assert_eq!(RequestBodyProcessor::detect(Some("text/plain")), RequestBodyProcessor::RAW);
+        assert_eq!(RequestBodyProcessor::detect(None), RequestBodyProcessor::RAW);
+    }
+
+    #[test]
+    fn test_form_urlencoded_sqli_matches_args_rule() {
+        let modsec = ModSecurity::default();
+        let mut rules = Rules::new();
+        rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        rules
+            .add_plain(
+                r#"
+SecRuleEngine On
+SecRule ARGS:username "@detectSQLi" "id:3001,phase:2,deny,status:403,msg:'SQLi in ARGS'"
+"#,
+            )
+            .unwrap();
+
+        let body = b"username=admin%27+OR+%271%27%3D%271&password=x";
+        let mut transaction = modsec.transaction_builder().with_rules(&rules).build().unwrap();
+        transaction.process_uri("/login", "POST", "1.1").unwrap();
+        transaction
+            .add_request_header("Content-Type", "application/x-www-form-urlencoded")
+            .unwrap();
+        transaction
+            .add_request_header("Content-Length", &body.len().to_string())
+            .unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.append_request_body(body).unwrap();
+        transaction.process_request_body().unwrap();
+
+        let intervention = transaction.intervention().expect("Expected the ARGS rule to intervene");
+        assert_eq!(intervention.status(), 403);
+    }
+
+    #[test]
+    fn test_json_benign_not_blocked() {
+        let modsec = ModSecurity::default();
+        let mut rules = Rules::new();
+        rules.add_plain(BODY_PROCESSOR_RULES).unwrap();
+        rules
+            .add_plain(
+                r#"
+SecRuleEngine On
+SecRule ARGS "@detectSQLi" "id:3002,phase:2,deny,status:403,msg:'SQLi in ARGS'"
+"#,
+            )
+            .unwrap();
+
+        let body = br#"{"username":"alice","age":18}"#;
+        let mut transaction = modsec.transaction_builder().with_rules(&rules).build().unwrap();
+        transaction.process_uri("/api/v1/users", "POST", "1.1").unwrap();
+        transaction.add_request_header("Content-Type", "application/json").unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.append_request_body(body).unwrap();
+        transaction.process_request_body().unwrap();
+
+        assert!(transaction.intervention().is_none());
+    }
+}
@@ -0,0 +1,431 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{
+    config::config::{AppConfig, DataFilesProperties},
+    context::state::BotwafState,
+    modules::modsec::rule_version::RuleVersionManager,
+    store::RepositoryContainer,
+};
+use anyhow::{Error, Result};
+use botwaf_types::{
+    modules::modsec::data_file::{DataFile, DataFileFormat, DATA_FILE_REF_PREFIX},
+    PageRequest,
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs,
+    net::IpAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Mutex;
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+lazy_static! {
+    // The logical name of the data file, which is also the materialized file name.
+    static ref DATA_FILE_NAME_REGEX: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9._-]{0,127}$").unwrap();
+    // The references of the data files in the rules, e.g: @pmFromFile botwaf-data:bad-user-agents.txt
+    static ref DATA_FILE_REF_REGEX: Regex =
+        Regex::new(&format!(r"{}([A-Za-z0-9][A-Za-z0-9._-]{{0,127}})", regex::escape(DATA_FILE_REF_PREFIX))).unwrap();
+}
+
+/// The max length of the per line of the patterns data file.
+const MAX_PATTERN_LINE_LENGTH: usize = 4096;
+
+/// The managed data files of the ModSecurity rules (e.g: @pmFromFile/@ipMatchFromFile), which persisted
+/// in the AppDB and materialized to the per instance directory before the rules compilation, so that the
+/// rules can reference the data files by the logical name instead of the absolute path differs per host.
+pub struct DataFileManager {}
+
+impl DataFileManager {
+    /// Rewrite the logical name references of the rule into the absolute path of the materialized files.
+    pub fn resolve_refs(value: &str, dir: &str) -> String {
+        DATA_FILE_REF_REGEX
+            .replace_all(value, |caps: &regex::Captures| {
+                Path::new(dir).join(&caps[1]).to_string_lossy().to_string()
+            })
+            .to_string()
+    }
+
+    /// Find the logical names of referenced data files by the rule, which are not materialized yet.
+    pub fn find_missing_refs(value: &str, dir: &str) -> Vec<String> {
+        DATA_FILE_REF_REGEX
+            .captures_iter(value)
+            .map(|caps| caps[1].to_string())
+            .filter(|name| !Path::new(dir).join(name).is_file())
+            .collect()
+    }
+
+    /// Validate the data file name, size and the content for the expected format.
+    pub fn validate(config: &DataFilesProperties, name: &str, format: DataFileFormat, content: &str) -> Result<()> {
+        if !DATA_FILE_NAME_REGEX.is_match(name) {
+            return Err(Error::msg(format!(
+                "Invalid the data file name '{}', only allowed the characters [A-Za-z0-9._-]",
+                name
+            )));
+        }
+        if content.len() > config.max_size_bytes {
+            return Err(Error::msg(format!(
+                "The data file '{}' size {} exceeds the max size {} bytes",
+                name,
+                content.len(),
+                config.max_size_bytes
+            )));
+        }
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            // Notice: The empty lines and comments are ignored by libmodsecurity.
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let valid = match format {
+                DataFileFormat::PATTERNS => !line.contains('\0') && line.len() <= MAX_PATTERN_LINE_LENGTH,
+                DataFileFormat::IPS => Self::is_ip_or_cidr(line),
+            };
+            if !valid {
+                return Err(Error::msg(format!(
+                    "Invalid the data file '{}' line {} for the format {:?}: {}",
+                    name,
+                    i + 1,
+                    format,
+                    line
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_ip_or_cidr(value: &str) -> bool {
+        match value.split_once('/') {
+            Some((addr, prefix)) => match (addr.parse::<IpAddr>(), prefix.parse::<u8>()) {
+                (Ok(addr), Ok(prefix)) => prefix <= if addr.is_ipv4() { 32 } else { 128 },
+                _ => false,
+            },
+            None => value.parse::<IpAddr>().is_ok(),
+        }
+    }
+
+    pub fn checksum(content: &str) -> String {
+        hex::encode(Sha256::digest(content.as_bytes()))
+    }
+
+    /// Materialize the data files into the directory, and returns the number of changed files.
+    pub fn materialize(dir: &str, files: &[DataFile]) -> Result<usize> {
+        fs::create_dir_all(dir)?;
+        let mut changed = 0;
+        for file in files {
+            // Notice: The empty content is persisted as null by the dynamic store.
+            let (name, content) = match (&file.name, file.content.as_deref()) {
+                (Some(name), content) if DATA_FILE_NAME_REGEX.is_match(name) => (name, content.unwrap_or("")),
+                _ => {
+                    tracing::warn!(
+                        "Skipping to materialize the invalid data file of id: {:?}",
+                        file.base.id
+                    );
+                    continue;
+                }
+            };
+            let path = Path::new(dir).join(name);
+            if let Ok(existing) = fs::read_to_string(&path) {
+                if Self::checksum(&existing) == Self::checksum(content) {
+                    continue;
+                }
+            }
+            // Write to the temporary file then rename, to avoid the compilation read the partial content.
+            let tmp_path = Path::new(dir).join(format!(".{}.tmp", name));
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, &path)?;
+            tracing::info!("Materialized the data file '{}' to {}", name, path.display());
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// Remove the orphaned files which no longer exist in the DB and not modified over the retention.
+    pub fn cleanup_orphans(dir: &str, names: &HashSet<String>, retention: Duration) -> Result<usize> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() || names.contains(&file_name) {
+                continue;
+            }
+            let elapsed = entry
+                .metadata()?
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+            if elapsed >= retention {
+                fs::remove_file(entry.path())?;
+                tracing::info!("Removed the orphaned data file: {}", entry.path().display());
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Load all the data files from the repository and materialize them, and returns the number of changed files.
+    pub async fn sync_from(repo: &Mutex<RepositoryContainer<DataFile>>, config: &AppConfig) -> Result<usize> {
+        let mut files = Vec::new();
+        let mut page = PageRequest {
+            num: Some(1),
+            limit: Some(1000),
+        };
+        {
+            let repo = repo.lock().await;
+            loop {
+                let (_, data) = repo.get(config).select(DataFile::default(), page.clone()).await?;
+                let size = data.len();
+                files.extend(data);
+                if size < page.get_limit() as usize {
+                    break;
+                }
+                page.num = page.num.map(|n| n + 1);
+            }
+        }
+
+        let data_files = &config.services.data_files;
+        let changed = Self::materialize(&data_files.dir, &files)?;
+        let names = files.iter().filter_map(|f| f.name.to_owned()).collect::<HashSet<_>>();
+        let retention = *data_files.orphan_retention_secs;
+        let removed = Self::cleanup_orphans(&data_files.dir, &names, retention)?;
+        if removed > 0 {
+            tracing::info!("Cleaned the {} orphaned data files from {}", removed, data_files.dir);
+        }
+        Ok(changed)
+    }
+
+    /// Synchronize the data files of the state, and recompile the rules if any data file changed.
+    pub async fn sync(state: &BotwafState) -> Result<usize> {
+        let changed = Self::sync_from(&state.data_file_repo, &state.config).await?;
+        if changed > 0 {
+            tracing::info!("The {} data files changed, recompiling the rules ...", changed);
+            state.schedule_reload_modsec_rules();
+        }
+        Ok(changed)
+    }
+
+    /// Schedule the retention job to re-materialize the changed data files and clean the orphaned files.
+    pub async fn start_scheduler(state: BotwafState) -> Result<()> {
+        let config = state.config.services.data_files.to_owned();
+        let scheduler = JobScheduler::new_with_channel_size(config.channel_size).await?;
+        let job = Job::new_async(config.cron.as_str(), move |_uuid, _lock| {
+            let state = state.clone();
+            Box::pin(async move {
+                if let Err(e) = Self::sync(&state).await {
+                    tracing::error!("Failed to synchronize the data files. cause: {}", e);
+                }
+                // Notice: The managed rules updated through the other instances are synchronized along with.
+                if let Some(rule_versions) = RuleVersionManager::get() {
+                    if let Err(e) = rule_versions.sync(&state).await {
+                        tracing::error!("Failed to synchronize the managed rules. cause: {}", e);
+                    }
+                }
+            })
+        })?;
+        scheduler.add(job).await?;
+        scheduler.start().await?;
+        tracing::info!("Started the data files retention scheduler with cron '{}'", config.cron);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::config::{AppConfigProperties, AppDBType, StaticRule},
+        modules::modsec::{rule_loader, store::data_files_sqlite::DataFileSQLiteRepository},
+    };
+    use botwaf_types::modules::modsec::{data_file::SaveDataFileRequest, rule::ModSecRuleState};
+    use modsecurity::{ModSecurity, Rules};
+    use std::env;
+
+    fn create_test_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("botwaf-ut-data-file-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().to_string()
+    }
+
+    fn is_blocked(rules: &Rules, user_agent: &str) -> bool {
+        let modsec = ModSecurity::default();
+        let mut transaction = modsec.transaction_builder().with_rules(rules).build().unwrap();
+        transaction.process_uri("/index.html", "GET", "1.1").unwrap();
+        transaction.add_request_header("Host", "localhost").unwrap();
+        transaction.add_request_header("User-Agent", user_agent).unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.process_request_body().unwrap();
+        transaction.intervention().is_some()
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = DataFilesProperties {
+            max_size_bytes: 32,
+            ..DataFilesProperties::default()
+        };
+        let validate = |name, format, content| DataFileManager::validate(&config, name, format, content);
+
+        assert!(validate(
+            "bad-user-agents.txt",
+            DataFileFormat::PATTERNS,
+            "# bots\nsqlmap\n\nnikto"
+        )
+        .is_ok());
+        assert!(validate("../passwd", DataFileFormat::PATTERNS, "sqlmap").is_err());
+        assert!(validate(".hidden", DataFileFormat::PATTERNS, "sqlmap").is_err());
+        assert!(validate("big.txt", DataFileFormat::PATTERNS, &"x".repeat(33)).is_err());
+        assert!(validate("nul.txt", DataFileFormat::PATTERNS, "bad\0").is_err());
+
+        assert!(validate("ips.txt", DataFileFormat::IPS, "10.0.0.1\n192.168.0.0/16\n::1/128").is_ok());
+        assert!(validate("ips.txt", DataFileFormat::IPS, "10.0.0.0/33").is_err());
+        assert!(validate("ips.txt", DataFileFormat::IPS, "sqlmap").is_err());
+    }
+
+    #[test]
+    fn test_resolve_and_find_missing_refs() {
+        let dir = create_test_dir("refs");
+        let value = r#"SecRule REQUEST_HEADERS:User-Agent "@pmFromFile botwaf-data:bad-user-agents.txt" "id:2001""#;
+
+        let resolved = DataFileManager::resolve_refs(value, &dir);
+        let path = Path::new(&dir)
+            .join("bad-user-agents.txt")
+            .to_string_lossy()
+            .to_string();
+        assert!(resolved.contains(&format!("@pmFromFile {}\"", path)));
+        assert!(!resolved.contains(DATA_FILE_REF_PREFIX));
+
+        assert_eq!(
+            DataFileManager::find_missing_refs(value, &dir),
+            vec!["bad-user-agents.txt"]
+        );
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "sqlmap").unwrap();
+        assert!(DataFileManager::find_missing_refs(value, &dir).is_empty());
+    }
+
+    #[test]
+    fn test_cleanup_orphans() {
+        let dir = create_test_dir("orphans");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(Path::new(&dir).join("kept.txt"), "sqlmap").unwrap();
+        fs::write(Path::new(&dir).join("orphaned.txt"), "nikto").unwrap();
+        let names = HashSet::from([String::from("kept.txt")]);
+
+        // The orphaned files within the retention are kept.
+        assert_eq!(
+            DataFileManager::cleanup_orphans(&dir, &names, Duration::from_secs(3600)).unwrap(),
+            0
+        );
+        assert!(Path::new(&dir).join("orphaned.txt").exists());
+
+        assert_eq!(
+            DataFileManager::cleanup_orphans(&dir, &names, Duration::ZERO).unwrap(),
+            1
+        );
+        assert!(!Path::new(&dir).join("orphaned.txt").exists());
+        assert!(Path::new(&dir).join("kept.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_pm_from_file_rule_before_and_after_updated() {
+        let dir = create_test_dir("pmf");
+        let mut props = AppConfigProperties::default();
+        props.appdb.db_type = AppDBType::SQLITE;
+        props.appdb.sqlite.dir = Some(Path::new(&dir).join("db").to_string_lossy().to_string());
+        props.services.data_files.dir = Path::new(&dir).join("data").to_string_lossy().to_string();
+        props.services.static_rules = vec![StaticRule {
+            name: String::from("bad-user-agents"),
+            kind: String::from("RAW"),
+            severity: String::from("high"),
+            desc: String::from("Block the bad user agents."),
+            value: String::from(
+                r#"SecRule REQUEST_HEADERS:User-Agent "@pmFromFile botwaf-data:bad-user-agents.txt" "id:2001,phase:1,deny,status:403,msg:'Bad User-Agent'""#,
+            ),
+            state: ModSecRuleState::ACTIVE,
+        }];
+        let config = AppConfig::new(&props);
+        let repo = Mutex::new(RepositoryContainer::new(
+            Some(Box::new(
+                DataFileSQLiteRepository::new(&config.appdb.sqlite).await.unwrap(),
+            )),
+            None,
+            None,
+        ));
+
+        // The rule is skipped gracefully before the data file uploaded.
+        let (_, infos) = rule_loader::load_rules(&config);
+        assert!(infos.iter().all(|info| info.name != "bad-user-agents"));
+
+        let mut param = SaveDataFileRequest {
+            id: None,
+            version: None,
+            name: String::from("bad-user-agents.txt"),
+            format: DataFileFormat::PATTERNS,
+            content: String::from("# The scanners\nsqlmap\nnikto\n"),
+        };
+        DataFileManager::validate(&config.services.data_files, &param.name, param.format, &param.content).unwrap();
+        let id = repo
+            .lock()
+            .await
+            .get(&config)
+            .insert(param.to_data_file())
+            .await
+            .unwrap();
+        assert_eq!(DataFileManager::sync_from(&repo, &config).await.unwrap(), 1);
+
+        let (rules, infos) = rule_loader::load_rules(&config);
+        assert!(infos.iter().any(|info| info.name == "bad-user-agents"));
+        assert!(is_blocked(&rules, "sqlmap/1.7.2#stable"));
+        assert!(!is_blocked(&rules, "curl/8.4.0"));
+
+        // Unchanged data files are not re-materialized.
+        assert_eq!(DataFileManager::sync_from(&repo, &config).await.unwrap(), 0);
+
+        // Update the list, and then recompile the rules.
+        let current = repo.lock().await.get(&config).select_by_id(id, None).await.unwrap();
+        param.id = Some(id);
+        param.version = current.base.version;
+        param.content = String::from("curl\n");
+        repo.lock()
+            .await
+            .get(&config)
+            .update(param.to_data_file())
+            .await
+            .unwrap();
+        assert_eq!(DataFileManager::sync_from(&repo, &config).await.unwrap(), 1);
+
+        let (rules, _) = rule_loader::load_rules(&config);
+        assert!(is_blocked(&rules, "curl/8.4.0"));
+        assert!(!is_blocked(&rules, "sqlmap/1.7.2#stable"));
+
+        repo.lock().await.get(&config).delete_by_id(id).await.unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use botwaf_types::modules::modsec::engine::{ModSecEngineCapabilities, ModSecEngineCapability};
+use lazy_static::lazy_static;
+use modsecurity::{ModSecurity, Rules};
+use std::{env, fs, sync::Arc};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<EngineCapabilities> = Arc::new(EngineCapabilities::probe());
+}
+
+/// The tiny rule to probe whether the engine build supports the capability, since the bindings don't expose
+/// the build flags of libmodsecurity.
+struct CapabilityProbe {
+    name: &'static str,
+    // The operators (or directives) which require the capability, matched case-insensitively.
+    operators: &'static [&'static str],
+    rule: fn() -> String,
+    // If set, the compile error explains the capability is unsupported only if containing the hint, e.g: the
+    // remote file is unreachable but the engine built with curl.
+    unsupported_hint: Option<&'static str>,
+}
+
+const PROBES: &[CapabilityProbe] = &[
+    CapabilityProbe {
+        name: "libinjection",
+        operators: &["@detectXSS", "@detectSQLi"],
+        rule: || {
+            String::from(
+                "SecRule ARGS \"@detectXSS\" \"id:9990001,phase:2,pass,nolog\"\n\
+                 SecRule ARGS \"@detectSQLi\" \"id:9990002,phase:2,pass,nolog\"",
+            )
+        },
+        unsupported_hint: None,
+    },
+    CapabilityProbe {
+        name: "lua",
+        operators: &["SecRuleScript"],
+        rule: || {
+            let script = env::temp_dir().join(format!("botwaf-engine-probe-{}.lua", std::process::id()));
+            if let Err(e) = fs::write(&script, "function main()\n    return nil\nend\n") {
+                tracing::warn!(
+                    "Failed to write the lua probe script: {}, cause: {}",
+                    script.display(),
+                    e
+                );
+            }
+            format!("SecRuleScript {} \"id:9990003,phase:2,pass,nolog\"", script.display())
+        },
+        unsupported_hint: None,
+    },
+    CapabilityProbe {
+        name: "curl",
+        operators: &["SecRemoteRules", "@pmFromFile http", "@ipMatchFromFile http"],
+        rule: || {
+            String::from(
+                "SecRule ARGS \"@pmFromFile https://127.0.0.1:1/botwaf-probe.txt\" \"id:9990004,phase:2,pass,nolog\"",
+            )
+        },
+        unsupported_hint: Some("curl"),
+    },
+];
+
+/// The cached capabilities of the ModSecurity engine build, probed once at startup.
+pub struct EngineCapabilities {
+    inner: ModSecEngineCapabilities,
+}
+
+impl EngineCapabilities {
+    pub fn get() -> Arc<EngineCapabilities> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    fn probe() -> Self {
+        let version = ModSecurity::default().whoami();
+        let capabilities = Self::probe_with(Some(version), |rule| {
+            Rules::new().add_plain(rule).map(|_| ()).map_err(|e| e.to_string())
+        });
+        tracing::info!(
+            "Probed the ModSecurity engine: {:?}, capabilities: {:?}",
+            capabilities.inner.version,
+            capabilities
+                .inner
+                .capabilities
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.supported))
+                .collect::<Vec<_>>()
+        );
+        capabilities
+    }
+
+    /// Probe the capabilities by compiling the probe rules with the given function, which could be stubbed.
+    pub fn probe_with(version: Option<String>, compile: impl Fn(&str) -> Result<(), String>) -> Self {
+        let capabilities = PROBES
+            .iter()
+            .map(|probe| {
+                let cause = compile(&(probe.rule)()).err().filter(|e| match probe.unsupported_hint {
+                    Some(hint) => e.to_lowercase().contains(hint),
+                    None => true,
+                });
+                ModSecEngineCapability {
+                    name: probe.name.to_owned(),
+                    supported: cause.is_none(),
+                    operators: probe.operators.iter().map(|op| op.to_string()).collect(),
+                    cause,
+                }
+            })
+            .collect();
+        Self {
+            inner: ModSecEngineCapabilities {
+                version,
+                capabilities,
+                probed_at: chrono::Utc::now().timestamp_millis(),
+            },
+        }
+    }
+
+    pub fn capabilities(&self) -> &ModSecEngineCapabilities {
+        &self.inner
+    }
+
+    /// Find the operators used by the rule content but unsupported by this engine build.
+    pub fn unsupported_operators(&self, content: &str) -> Vec<String> {
+        let content = content.to_lowercase();
+        self.inner
+            .capabilities
+            .iter()
+            .filter(|c| !c.supported)
+            .flat_map(|c| c.operators.iter())
+            .filter(|op| content.contains(&op.to_lowercase()))
+            .cloned()
+            .collect()
+    }
+
+    /// Annotate the compile error of the rule if the probe explains the failure.
+    pub fn annotate(&self, content: &str, error: &str) -> String {
+        let unsupported = self.unsupported_operators(content);
+        if unsupported.is_empty() {
+            return error.to_owned();
+        }
+        let reasons = unsupported
+            .iter()
+            .map(|op| format!("operator {} unsupported by this engine build", op))
+            .collect::<Vec<_>>();
+        format!("{}; {}", reasons.join("; "), error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The stub engine built without libinjection and curl.
+    fn stub_capabilities() -> EngineCapabilities {
+        EngineCapabilities::probe_with(Some(String::from("ModSecurity v3.0.9 (stub)")), |rule| {
+            if rule.contains("@detectXSS") {
+                Err(String::from("Rules error. Operator not found: detectXSS"))
+            } else if rule.contains("@pmFromFile") {
+                Err(String::from("Failed to download: Not compiled with curl support"))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    #[test]
+    fn test_probe_with_stubbed_results() {
+        let capabilities = stub_capabilities();
+        let inner = capabilities.capabilities();
+        assert_eq!(inner.version.as_deref(), Some("ModSecurity v3.0.9 (stub)"));
+        let supported = |name: &str| inner.capabilities.iter().find(|c| c.name == name).unwrap().supported;
+        assert!(!supported("libinjection"));
+        assert!(supported("lua"));
+        assert!(!supported("curl"));
+
+        // The unreachable remote file doesn't mean the engine built without curl.
+        let capabilities = EngineCapabilities::probe_with(None, |rule| match rule.contains("@pmFromFile") {
+            true => Err(String::from("Failed to download: Couldn't connect to server")),
+            false => Ok(()),
+        });
+        assert!(capabilities.capabilities().capabilities.iter().all(|c| c.supported));
+    }
+
+    #[test]
+    fn test_annotate_unsupported_operator() {
+        let capabilities = stub_capabilities();
+        let content = "SecRule ARGS \"@detectxss\" \"id:1001,phase:2,deny,status:403\"";
+        assert_eq!(
+            capabilities.unsupported_operators(content),
+            vec![String::from("@detectXSS")]
+        );
+        assert_eq!(
+            capabilities.annotate(content, "Operator not found"),
+            "operator @detectXSS unsupported by this engine build; Operator not found"
+        );
+
+        // The failure is not explained by the probe.
+        let content = "SecRulo ARGS \"@rx union\" \"id:1002\"";
+        assert!(capabilities.unsupported_operators(content).is_empty());
+        assert_eq!(capabilities.annotate(content, "Invalid input"), "Invalid input");
+    }
+}
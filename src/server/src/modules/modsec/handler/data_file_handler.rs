@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::modules::modsec::data_file::DataFileManager;
+use anyhow::{Error, Ok};
+use async_trait::async_trait;
+use botwaf_types::modules::modsec::data_file::{
+    DataFile, DeleteDataFileRequest, QueryDataFileRequest, SaveDataFileRequest,
+};
+use botwaf_types::{PageRequest, PageResponse};
+use common_audit_log::audit_log;
+
+#[async_trait]
+pub trait IDataFileHandler: Send {
+    async fn find(
+        &self,
+        param: QueryDataFileRequest,
+        page: PageRequest,
+    ) -> Result<(PageResponse, Vec<DataFile>), Error>;
+
+    async fn save(&self, param: SaveDataFileRequest) -> Result<i64, Error>;
+
+    async fn delete(&self, param: DeleteDataFileRequest) -> Result<u64, Error>;
+}
+
+pub struct DataFileHandler<'a> {
+    state: &'a BotwafState,
+}
+
+impl<'a> DataFileHandler<'a> {
+    pub fn new(state: &'a BotwafState) -> Self {
+        Self { state }
+    }
+
+    /// Re-materialize the data files and recompile the rules immediately on this instance, and the other
+    /// instances will be synchronized by the retention job.
+    async fn sync(&self) {
+        if let Err(e) = DataFileManager::sync(self.state).await {
+            tracing::error!("Failed to synchronize the data files. cause: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> IDataFileHandler for DataFileHandler<'a> {
+    #[audit_log("[DATA_FILE][FIND] name: {param.name.clone().unwrap_or_default()}")]
+    async fn find(
+        &self,
+        param: QueryDataFileRequest,
+        page: PageRequest,
+    ) -> Result<(PageResponse, Vec<DataFile>), Error> {
+        let repo = self.state.data_file_repo.lock().await;
+        let (page, data) = repo.get(&self.state.config).select(param.to_data_file(), page).await?;
+        // Notice: The content is excluded from the list, which may be large.
+        let data = data
+            .into_iter()
+            .map(|mut data_file| {
+                data_file.content = None;
+                data_file
+            })
+            .collect();
+        Ok((page, data))
+    }
+
+    #[audit_log("[DATA_FILE][SAVE] name: {param.name}")]
+    async fn save(&self, param: SaveDataFileRequest) -> Result<i64, Error> {
+        DataFileManager::validate(
+            &self.state.config.services.data_files,
+            &param.name,
+            param.format,
+            &param.content,
+        )?;
+
+        let mut data_file = param.to_data_file();
+        data_file.checksum = Some(DataFileManager::checksum(&param.content));
+        let id = {
+            let repo = self.state.data_file_repo.lock().await;
+            if param.id.is_some() {
+                repo.get(&self.state.config).update(data_file).await?
+            } else {
+                repo.get(&self.state.config).insert(data_file).await?
+            }
+        };
+        if id <= 0 {
+            return Err(Error::msg(format!("Failed to save the data file '{}'", param.name)));
+        }
+        self.sync().await;
+        Ok(id)
+    }
+
+    #[audit_log("[DATA_FILE][DELETE] id: {param.id}")]
+    async fn delete(&self, param: DeleteDataFileRequest) -> Result<u64, Error> {
+        let count = {
+            let repo = self.state.data_file_repo.lock().await;
+            repo.get(&self.state.config).delete_by_id(param.id).await?
+        };
+        self.sync().await;
+        Ok(count)
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::{
+    config::config::{AppConfig, AppDBType},
+    modules::modsec::store::{
+        replay_results_mongo::ReplayResultMongoRepository, replay_results_postgresql::ReplayResultPostgresRepository,
+        replay_results_sqlite::ReplayResultSQLiteRepository, IReplayResultRepository,
+    },
+};
+use anyhow::Error;
+use arc_swap::ArcSwapOption;
+use botwaf_types::modules::{
+    forward::event_stream::AccessDecision,
+    modsec::replay::{ReplayResult, ReplayRuleDelta, ReplaySummaryResponse},
+};
+use common_telemetry::info;
+use lazy_static::lazy_static;
+use std::{collections::HashMap, sync::Arc};
+
+/// The page size of the results aggregated into the summary.
+const SUMMARY_PAGE_SIZE: u32 = 1000;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ArcSwapOption<ReplayResultManager> = ArcSwapOption::empty();
+}
+
+/// The results of the access events replay jobs, which are the checkpoints of the jobs as well.
+pub struct ReplayResultManager {
+    repo: Arc<dyn IReplayResultRepository>,
+}
+
+impl ReplayResultManager {
+    pub fn new(repo: Arc<dyn IReplayResultRepository>) -> Self {
+        ReplayResultManager { repo }
+    }
+
+    pub async fn init(config: &AppConfig) -> Result<(), Error> {
+        if SINGLE_INSTANCE.load().is_some() {
+            return Ok(());
+        }
+        let manager = Arc::new(Self::new(Self::build_repository(config).await?));
+        SINGLE_INSTANCE.store(Some(manager));
+        info!("Initialized the replay results manager.");
+        Ok(())
+    }
+
+    pub fn get() -> Option<Arc<ReplayResultManager>> {
+        SINGLE_INSTANCE.load_full()
+    }
+
+    /// The last replayed event id of the job, which the job is resumed after.
+    pub async fn checkpoint(&self, job_id: &str) -> Result<Option<i64>, Error> {
+        self.repo.last_event_id(job_id).await
+    }
+
+    pub async fn record(&self, result: ReplayResult) -> Result<i64, Error> {
+        self.repo.insert(result).await
+    }
+
+    /// Aggregate the would-block delta per rule of the job, i.e: the events newly blocked by the rule
+    /// minus the events originally blocked by the rule but not any longer.
+    pub async fn summary(&self, job_id: &str) -> Result<ReplaySummaryResponse, Error> {
+        let mut summary = ReplaySummaryResponse {
+            job_id: job_id.to_owned(),
+            ..Default::default()
+        };
+        let mut deltas: HashMap<String, ReplayRuleDelta> = HashMap::new();
+        let mut after_event_id = 0;
+        loop {
+            let results = self.repo.find_after(job_id, after_event_id, SUMMARY_PAGE_SIZE).await?;
+            for result in results.iter() {
+                Self::aggregate(&mut summary, &mut deltas, result);
+            }
+            match results.last().and_then(|r| r.event_id) {
+                Some(last) if results.len() as u32 == SUMMARY_PAGE_SIZE => after_event_id = last,
+                _ => break,
+            }
+        }
+
+        let mut rules = deltas
+            .into_values()
+            .map(|mut d| {
+                d.delta = d.newly_blocked as i64 - d.no_longer_blocked as i64;
+                d
+            })
+            .collect::<Vec<_>>();
+        rules.sort_by(|a, b| {
+            b.delta
+                .abs()
+                .cmp(&a.delta.abs())
+                .then_with(|| a.rule_id.cmp(&b.rule_id))
+        });
+        summary.rules = rules;
+        Ok(summary)
+    }
+
+    fn aggregate(summary: &mut ReplaySummaryResponse, deltas: &mut HashMap<String, ReplayRuleDelta>, r: &ReplayResult) {
+        summary.total += 1;
+        if r.old_decision == Some(AccessDecision::BLOCK) {
+            summary.old_blocked += 1;
+        }
+        if r.new_decision == Some(AccessDecision::BLOCK) {
+            summary.new_blocked += 1;
+        }
+        let new_rule_ids = r
+            .new_rule_ids
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+            .collect::<Vec<_>>();
+        for rule_id in new_rule_ids.iter() {
+            Self::delta_of(deltas, rule_id).newly_blocked += 1;
+        }
+        // The original rule is no longer blocking, if passed now or blocked by the other rules.
+        if let (Some(AccessDecision::BLOCK), Some(old_rule_id)) = (r.old_decision, r.old_rule_id.as_deref()) {
+            if r.new_decision == Some(AccessDecision::PASS) || !new_rule_ids.is_empty() {
+                Self::delta_of(deltas, old_rule_id).no_longer_blocked += 1;
+            }
+        }
+    }
+
+    fn delta_of<'a>(deltas: &'a mut HashMap<String, ReplayRuleDelta>, rule_id: &str) -> &'a mut ReplayRuleDelta {
+        deltas.entry(rule_id.to_owned()).or_insert_with(|| ReplayRuleDelta {
+            rule_id: rule_id.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    async fn build_repository(config: &AppConfig) -> Result<Arc<dyn IReplayResultRepository>, Error> {
+        let db_config = &config.appdb;
+        Ok(match db_config.db_type {
+            AppDBType::SQLITE => Arc::new(ReplayResultSQLiteRepository::new(&db_config.sqlite).await?),
+            AppDBType::POSTGRESQL => Arc::new(ReplayResultPostgresRepository::new(&db_config.postgres).await?),
+            AppDBType::MONGODB => Arc::new(ReplayResultMongoRepository::new(&db_config.mongodb).await?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::SqliteAppDBProperties;
+    use botwaf_types::BaseBean;
+    use std::{env, fs};
+
+    async fn create_manager(name: &str) -> ReplayResultManager {
+        let dir = env::temp_dir().join(format!("botwaf-ut-replay-result-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = ReplayResultSQLiteRepository::new(&SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        })
+        .await
+        .unwrap();
+        ReplayResultManager::new(Arc::new(repo))
+    }
+
+    fn result(
+        job_id: &str,
+        event_id: i64,
+        old: (AccessDecision, Option<&str>),
+        new: (AccessDecision, Option<&str>),
+    ) -> ReplayResult {
+        ReplayResult {
+            base: BaseBean::new_with_id(None),
+            job_id: Some(job_id.to_owned()),
+            event_id: Some(event_id),
+            old_decision: Some(old.0),
+            old_rule_id: old.1.map(|s| s.to_owned()),
+            new_decision: Some(new.0),
+            new_rule_ids: new.1.map(|s| s.to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_of_job() {
+        let manager = create_manager("checkpoint").await;
+        assert_eq!(manager.checkpoint("job-1").await.unwrap(), None);
+
+        for event_id in [1, 2, 3] {
+            let pass = (AccessDecision::PASS, None);
+            manager.record(result("job-1", event_id, pass, pass)).await.unwrap();
+        }
+        manager
+            .record(result(
+                "job-2",
+                9,
+                (AccessDecision::PASS, None),
+                (AccessDecision::PASS, None),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(manager.checkpoint("job-1").await.unwrap(), Some(3));
+        assert_eq!(manager.checkpoint("job-2").await.unwrap(), Some(9));
+    }
+
+    #[tokio::test]
+    async fn test_summary_would_block_delta_per_rule() {
+        let manager = create_manager("summary").await;
+        let pass = (AccessDecision::PASS, None);
+        // Newly blocked by 942100.
+        manager
+            .record(result("job", 1, pass, (AccessDecision::BLOCK, Some("942100"))))
+            .await
+            .unwrap();
+        manager
+            .record(result("job", 2, pass, (AccessDecision::BLOCK, Some("942100"))))
+            .await
+            .unwrap();
+        // Originally blocked by 941100, but passed now.
+        manager
+            .record(result("job", 3, (AccessDecision::BLOCK, Some("941100")), pass))
+            .await
+            .unwrap();
+        // Unchanged.
+        manager
+            .record(result(
+                "job",
+                4,
+                (AccessDecision::BLOCK, Some("930100")),
+                (AccessDecision::BLOCK, None),
+            ))
+            .await
+            .unwrap();
+        manager.record(result("job", 5, pass, pass)).await.unwrap();
+
+        let summary = manager.summary("job").await.unwrap();
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.old_blocked, 2);
+        assert_eq!(summary.new_blocked, 3);
+        assert_eq!(
+            summary.rules,
+            vec![
+                ReplayRuleDelta {
+                    rule_id: "942100".to_owned(),
+                    newly_blocked: 2,
+                    no_longer_blocked: 0,
+                    delta: 2,
+                },
+                ReplayRuleDelta {
+                    rule_id: "941100".to_owned(),
+                    newly_blocked: 0,
+                    no_longer_blocked: 1,
+                    delta: -1,
+                },
+            ]
+        );
+    }
+}
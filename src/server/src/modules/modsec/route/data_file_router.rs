@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::modules::modsec::handler::data_file_handler::{DataFileHandler, IDataFileHandler};
+use crate::store::VersionConflictError;
+use crate::util::web::{ValidatedJson, ValidatedQuery};
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use botwaf_types::modules::modsec::data_file::{
+    DeleteDataFileRequest, DeleteDataFileResponse, QueryDataFileRequest, QueryDataFileResponse, SaveDataFileRequest,
+    SaveDataFileResponse,
+};
+use botwaf_types::{PageRequest, RespBase};
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/data-files", get(handle_data_files_list))
+        .route("/api/v1/data-files", post(handle_data_file_save))
+        .route("/api/v1/data-files/delete", post(handle_data_file_delete))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/data-files",
+    params(QueryDataFileRequest, PageRequest),
+    responses((status = 200, description = "Getting the managed data files of the rules (without content).", body = QueryDataFileResponse)),
+    tag = "Rules"
+)]
+async fn handle_data_files_list(
+    State(state): State<BotwafState>,
+    ValidatedQuery(param): ValidatedQuery<QueryDataFileRequest>,
+    ValidatedQuery(page): ValidatedQuery<PageRequest>,
+) -> impl IntoResponse {
+    match get_data_file_handler(&state).find(param, page).await {
+        Ok((page, data)) => Ok(Json(QueryDataFileResponse::new(page, data))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/data-files",
+    request_body = SaveDataFileRequest,
+    responses(
+        (status = 200, description = "Upload or update the data file, and recompile the rules.", body = SaveDataFileResponse),
+        (status = 400, description = "The data file exceeds the max size or the content is invalid for the format.", body = RespBase),
+        (status = 409, description = "Conflict to update data file with the stale version.")
+    ),
+    tag = "Rules"
+)]
+async fn handle_data_file_save(
+    State(state): State<BotwafState>,
+    ValidatedJson(param): ValidatedJson<SaveDataFileRequest>,
+) -> impl IntoResponse {
+    match get_data_file_handler(&state).save(param).await {
+        Ok(id) => (StatusCode::OK, Json(SaveDataFileResponse::new(id))).into_response(),
+        Err(e) if e.downcast_ref::<VersionConflictError>().is_some() => StatusCode::CONFLICT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/data-files/delete",
+    request_body = DeleteDataFileRequest,
+    responses((status = 200, description = "Delete the data file, the materialized file is cleaned by the retention job.", body = DeleteDataFileResponse)),
+    tag = "Rules"
+)]
+async fn handle_data_file_delete(
+    State(state): State<BotwafState>,
+    Json(param): Json<DeleteDataFileRequest>,
+) -> impl IntoResponse {
+    match get_data_file_handler(&state).delete(param).await {
+        Ok(result) => Ok(Json(DeleteDataFileResponse::new(result))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn get_data_file_handler(state: &BotwafState) -> Box<dyn IDataFileHandler + '_> {
+    Box::new(DataFileHandler::new(state))
+}
@@ -0,0 +1,469 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::modules::modsec::engine_capability::EngineCapabilities;
+use crate::modules::modsec::rule_changeset::RuleChangesetManager;
+use crate::modules::modsec::rule_loader;
+use crate::modules::modsec::rule_promotion::RulePromotionManager;
+use crate::modules::modsec::rule_test::RuleSandbox;
+use crate::modules::modsec::rule_version::RuleVersionManager;
+use crate::util::web::{RequireAdmin, RequireOperator, ValidatedJson, ValidatedQuery};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use botwaf_types::modules::modsec::engine::ModSecEngineCapabilities;
+use botwaf_types::modules::modsec::rule::{
+    ModSecRuleInfo, ModSecRuleState, ModSecShadowStats, PromoteRuleRequest, PromoteRuleResponse, QueryRulesRequest,
+    ReportFalsePositiveRequest,
+};
+use botwaf_types::modules::modsec::rule_changeset::{
+    CreateRuleChangesetRequest, ModSecRuleChange, ModSecRuleChangeset,
+};
+use botwaf_types::modules::modsec::rule_test::{TestRuleRequest, TestRuleResponse};
+use botwaf_types::modules::modsec::rule_version::{
+    ModSecRuleVersion, QueryRuleVersionResponse, RollbackRuleRequest, SaveRuleVersionRequest,
+};
+use botwaf_types::RespBase;
+use hyper::StatusCode;
+use std::sync::Arc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/rules", get(handle_rules_list))
+        .route(
+            "/api/v1/rules/engine-capabilities",
+            get(handle_rules_engine_capabilities),
+        )
+        .route("/api/v1/rules/promote", post(handle_rule_promote))
+        .route("/api/v1/rules/false-positive", post(handle_rule_false_positive))
+        .route("/api/v1/rules/test", post(handle_rule_test))
+        .route("/api/v1/rules/versions", post(handle_rule_version_save))
+        .route("/api/v1/rules/{name}/versions", get(handle_rule_versions_list))
+        .route("/api/v1/rules/{name}/rollback", post(handle_rule_rollback))
+        .route("/api/v1/rules/changesets", post(handle_rule_changeset_create))
+        .route("/api/v1/rules/changesets/{id}", get(handle_rule_changeset_get))
+        .route(
+            "/api/v1/rules/changesets/{id}/changes",
+            post(handle_rule_changeset_add_change),
+        )
+        .route(
+            "/api/v1/rules/changesets/{id}/approve",
+            post(handle_rule_changeset_approve),
+        )
+        .route("/api/v1/rules/changesets/{id}/apply", post(handle_rule_changeset_apply))
+        .route(
+            "/api/v1/rules/changesets/{id}/rollback",
+            post(handle_rule_changeset_rollback),
+        )
+}
+
+/// The response header of the compiled hash of the effective managed rules, which should be equal to the
+/// compiled hash of the last applied changeset, see: rule_loader::compiled_hash()
+pub const RULES_COMPILED_HASH_HEADER: &str = "X-Botwaf-Rules-Hash";
+
+fn get_rule_version_manager() -> Result<Arc<RuleVersionManager>, axum::response::Response> {
+    RuleVersionManager::get().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The rule versions is not initialized.")),
+        )
+            .into_response()
+    })
+}
+
+// The changesets are drafted by the operators, but approved, applied and rolled back by the admins.
+// (see: web::RequireOperator and web::RequireAdmin of the handlers)
+fn get_rule_changeset_manager() -> Result<Arc<RuleChangesetManager>, axum::response::Response> {
+    RuleChangesetManager::get().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The rule changesets is not initialized.")),
+        )
+            .into_response()
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rules",
+    params(QueryRulesRequest),
+    responses((status = 200, description = "Getting the current effective ModSecurity rules and sources, with the shadow statistics of the SHADOW rules, and the compiled hash of the managed rules in the header 'X-Botwaf-Rules-Hash'.", body = [ModSecRuleInfo])),
+    tag = "Rules"
+)]
+async fn handle_rules_list(
+    State(state): State<BotwafState>,
+    ValidatedQuery(param): ValidatedQuery<QueryRulesRequest>,
+) -> impl IntoResponse {
+    let promotion = RulePromotionManager::get();
+    let rule_infos = state.modsec_rule_infos.load();
+    let infos = rule_infos
+        .iter()
+        .filter(|info| param.changeset.is_none() || info.changeset_id == param.changeset)
+        .map(|info| {
+            let mut info = info.to_owned();
+            if info.state == ModSecRuleState::SHADOW {
+                info.shadow_stats = promotion.stats(&info.name);
+            }
+            info
+        })
+        .collect::<Vec<ModSecRuleInfo>>();
+    (
+        StatusCode::OK,
+        [(RULES_COMPILED_HASH_HEADER, rule_loader::compiled_hash_of(&rule_infos))],
+        Json(infos),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rules/engine-capabilities",
+    responses((status = 200, description = "Getting the version and capabilities of the ModSecurity engine build, to warn the rules using the unsupported operators before activation.", body = ModSecEngineCapabilities)),
+    tag = "Rules"
+)]
+async fn handle_rules_engine_capabilities() -> impl IntoResponse {
+    let capabilities: ModSecEngineCapabilities = EngineCapabilities::get().capabilities().to_owned();
+    (StatusCode::OK, Json(capabilities)).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/promote",
+    request_body = PromoteRuleRequest,
+    responses(
+        (status = 200, description = "Promote the rule to the next lifecycle state (CANDIDATE -> SHADOW -> ACTIVE), and recompile the rules.", body = PromoteRuleResponse),
+        (status = 400, description = "The rule is already ACTIVE, or the SHADOW rule has not met the promotion threshold.", body = RespBase),
+        (status = 404, description = "The rule is not found.")
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_promote(
+    State(state): State<BotwafState>,
+    ValidatedJson(param): ValidatedJson<PromoteRuleRequest>,
+) -> impl IntoResponse {
+    let current = match state
+        .modsec_rule_infos
+        .load()
+        .iter()
+        .find(|info| info.name == param.name)
+    {
+        Some(info) => info.state,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    match RulePromotionManager::get().promote(&param.name, current) {
+        Ok(promoted) => {
+            state.schedule_reload_modsec_rules();
+            (
+                StatusCode::OK,
+                Json(PromoteRuleResponse {
+                    name: param.name,
+                    state: promoted,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/false-positive",
+    request_body = ReportFalsePositiveRequest,
+    responses(
+        (status = 200, description = "Report a would-block request of the SHADOW rule is false positive, which lowers the precision.", body = ModSecShadowStats),
+        (status = 400, description = "The rule is not in the SHADOW state.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_false_positive(
+    ValidatedJson(param): ValidatedJson<ReportFalsePositiveRequest>,
+) -> impl IntoResponse {
+    match RulePromotionManager::get().report_false_positive(&param.name) {
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/test",
+    request_body = TestRuleRequest,
+    responses(
+        (status = 200, description = "Compile the rule in isolation and replay the sample requests against it, returns the actual vs expected decision per sample and the overall pass/fail, the effective rules are never changed.", body = TestRuleResponse),
+        (status = 400, description = "The rule is not compilable, or the referenced data files are unavailable.", body = RespBase),
+        (status = 403, description = "Forbidden, requires the operator role.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_test(
+    State(state): State<BotwafState>,
+    _: RequireOperator,
+    ValidatedJson(param): ValidatedJson<TestRuleRequest>,
+) -> impl IntoResponse {
+    let sandbox = match RuleSandbox::compile(&param.rule_text, &state.config.services.data_files.dir) {
+        Ok(sandbox) => sandbox,
+        Err(e) => return (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    };
+    match sandbox.run(&param) {
+        Ok(resp) => (StatusCode::OK, Json(resp)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/versions",
+    request_body = SaveRuleVersionRequest,
+    responses(
+        (status = 200, description = "Create or update the managed rule by appending the new head version, and recompile the rules.", body = ModSecRuleVersion),
+        (status = 400, description = "The rule is not compilable, or the change comment is missing on the update.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_version_save(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    ValidatedJson(param): ValidatedJson<SaveRuleVersionRequest>,
+) -> impl IntoResponse {
+    let manager = match get_rule_version_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.save(param).await {
+        Ok(version) => {
+            state.schedule_reload_modsec_rules();
+            (StatusCode::OK, Json(version)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rules/{name}/versions",
+    params(("name" = String, Path, description = "The name of the managed rule.")),
+    responses(
+        (status = 200, description = "Getting the versions of the managed rule, with the unified diff against the previous version.", body = QueryRuleVersionResponse),
+        (status = 404, description = "The rule is not found.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_versions_list(_: RequireAdmin, Path(name): Path<String>) -> impl IntoResponse {
+    let manager = match get_rule_version_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.versions(&name).await {
+        Ok(Some(versions)) => (StatusCode::OK, Json(versions)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(RespBase::errmsg("The rule is not found."))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/{name}/rollback",
+    params(("name" = String, Path, description = "The name of the managed rule."), RollbackRuleRequest),
+    responses(
+        (status = 200, description = "Rollback the rule by appending the new head version equal to the version (never rewrites the history), and recompile the rules.", body = ModSecRuleVersion),
+        (status = 400, description = "The version is already the head.", body = RespBase),
+        (status = 404, description = "The rule version is not found.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_rollback(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    Path(name): Path<String>,
+    ValidatedQuery(param): ValidatedQuery<RollbackRuleRequest>,
+) -> impl IntoResponse {
+    let manager = match get_rule_version_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.rollback(&name, param.version, param.comment).await {
+        Ok(Some(version)) => {
+            state.schedule_reload_modsec_rules();
+            (StatusCode::OK, Json(version)).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The rule version is not found.")),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/changesets",
+    request_body = CreateRuleChangesetRequest,
+    responses(
+        (status = 200, description = "Create the DRAFT changeset, which collects the multi rules changes to be applied as a unit.", body = ModSecRuleChangeset),
+        (status = 403, description = "Requires the operator role.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_create(
+    _: RequireOperator,
+    ValidatedJson(param): ValidatedJson<CreateRuleChangesetRequest>,
+) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.create(param).await {
+        Ok(changeset) => (StatusCode::OK, Json(changeset)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rules/changesets/{id}",
+    params(("id" = i64, Path, description = "The id of the changeset.")),
+    responses(
+        (status = 200, description = "Getting the changeset with the changes, the baseline and the compiled hash.", body = ModSecRuleChangeset),
+        (status = 404, description = "The changeset is not found.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_get(_: RequireOperator, Path(id): Path<i64>) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.find(id).await {
+        Ok(changeset) => (StatusCode::OK, Json(changeset)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/changesets/{id}/changes",
+    params(("id" = i64, Path, description = "The id of the changeset.")),
+    request_body = ModSecRuleChange,
+    responses(
+        (status = 200, description = "Add the rule change into the DRAFT changeset, which replaces the previous change of the same rule.", body = ModSecRuleChangeset),
+        (status = 400, description = "The changeset is not DRAFT.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_add_change(
+    _: RequireOperator,
+    Path(id): Path<i64>,
+    ValidatedJson(param): ValidatedJson<ModSecRuleChange>,
+) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.add_change(id, param).await {
+        Ok(changeset) => (StatusCode::OK, Json(changeset)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/changesets/{id}/approve",
+    params(("id" = i64, Path, description = "The id of the changeset.")),
+    responses(
+        (status = 200, description = "Approve the DRAFT changeset, which is validated and compiled with the current rules as a whole.", body = ModSecRuleChangeset),
+        (status = 400, description = "The changeset is not DRAFT, or any rule is invalid.", body = RespBase),
+        (status = 403, description = "Requires the admin role.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_approve(_: RequireAdmin, Path(id): Path<i64>) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.approve(id).await {
+        Ok(changeset) => (StatusCode::OK, Json(changeset)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/changesets/{id}/apply",
+    params(("id" = i64, Path, description = "The id of the changeset.")),
+    responses(
+        (status = 200, description = "Apply the APPROVED changeset as a unit (all-or-nothing), and recompile the rules once.", body = ModSecRuleChangeset),
+        (status = 400, description = "The changeset is not APPROVED, or any rule is invalid.", body = RespBase),
+        (status = 403, description = "Requires the admin role.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_apply(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.apply(id).await {
+        Ok(changeset) => {
+            state.schedule_reload_modsec_rules();
+            (StatusCode::OK, Json(changeset)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/rules/changesets/{id}/rollback",
+    params(("id" = i64, Path, description = "The id of the changeset.")),
+    responses(
+        (status = 200, description = "Rollback the APPLIED changeset as a unit, i.e: the changed rules are reverted to the heads before applied, and recompile the rules once.", body = ModSecRuleChangeset),
+        (status = 400, description = "The changeset is not APPLIED, or any rule was changed after applied.", body = RespBase),
+        (status = 403, description = "Requires the admin role.", body = RespBase)
+    ),
+    tag = "Rules"
+)]
+async fn handle_rule_changeset_rollback(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let manager = match get_rule_changeset_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.rollback(id).await {
+        Ok(changeset) => {
+            state.schedule_reload_modsec_rules();
+            (StatusCode::OK, Json(changeset)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
@@ -0,0 +1,394 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::rule_version::RuleVersionManager;
+use crate::{
+    config::config::{AppConfig, AppDBType},
+    modules::modsec::store::{
+        rule_changesets_mongo::RuleChangesetMongoRepository,
+        rule_changesets_postgresql::RuleChangesetPostgresRepository,
+        rule_changesets_sqlite::RuleChangesetSQLiteRepository,
+    },
+    store::AsyncRepository,
+    util::auths::SecurityContext,
+};
+use anyhow::Error;
+use arc_swap::ArcSwapOption;
+use botwaf_types::{
+    modules::modsec::rule_changeset::{
+        CreateRuleChangesetRequest, ModSecRuleChange, ModSecRuleChangeset, ModSecRuleChangesetState,
+    },
+    BaseBean,
+};
+use common_audit_log::audit_log;
+use common_telemetry::info;
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ArcSwapOption<RuleChangesetManager> = ArcSwapOption::empty();
+}
+
+/// The changesets of the managed rules, which collects the multi rules changes as the draft, and applies (or rolls
+/// back) them as a unit, so that the related rules are never partially effective. The review state is
+/// DRAFT -> APPROVED -> APPLIED (-> ROLLED_BACK), see: RuleVersionManager::apply_changes()
+pub struct RuleChangesetManager {
+    versions: Arc<RuleVersionManager>,
+    repo: Arc<dyn AsyncRepository<ModSecRuleChangeset>>,
+    // Serialize the state transitions within the instance, so that a changeset is never applied twice.
+    transition_lock: Mutex<()>,
+}
+
+impl RuleChangesetManager {
+    pub fn new(versions: Arc<RuleVersionManager>, repo: Arc<dyn AsyncRepository<ModSecRuleChangeset>>) -> Self {
+        RuleChangesetManager {
+            versions,
+            repo,
+            transition_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn init(config: &AppConfig) -> Result<(), Error> {
+        if SINGLE_INSTANCE.load().is_some() {
+            return Ok(());
+        }
+        let versions = RuleVersionManager::get().ok_or_else(|| Error::msg("The rule versions is not initialized"))?;
+        let manager = Self::new(versions, Self::build_repository(config).await?);
+        info!("Initialized the rule changesets manager.");
+        SINGLE_INSTANCE.store(Some(Arc::new(manager)));
+        Ok(())
+    }
+
+    pub fn get() -> Option<Arc<RuleChangesetManager>> {
+        SINGLE_INSTANCE.load_full()
+    }
+
+    pub async fn find(&self, id: i64) -> Result<ModSecRuleChangeset, Error> {
+        self.repo
+            .select_by_id(id, None)
+            .await
+            .map_err(|e| Error::msg(format!("The changeset {} is not found. cause: {}", id, e)))
+    }
+
+    /// Create the draft changeset with the initial changes.
+    pub async fn create(&self, param: CreateRuleChangesetRequest) -> Result<ModSecRuleChangeset, Error> {
+        let changeset = ModSecRuleChangeset {
+            base: BaseBean::new_with_id(None),
+            name: Some(param.name),
+            description: param.desc,
+            state: Some(ModSecRuleChangesetState::DRAFT),
+            changes: Some(serde_json::to_string(&param.changes)?),
+            ..ModSecRuleChangeset::default()
+        };
+        let id = self.repo.insert(changeset).await?;
+        info!("Created the rule changeset {} with {} changes", id, param.changes.len());
+        self.find(id).await
+    }
+
+    /// Add the change into the draft changeset, which replaces the previous change of the same rule.
+    pub async fn add_change(&self, id: i64, change: ModSecRuleChange) -> Result<ModSecRuleChangeset, Error> {
+        let _guard = self.transition_lock.lock().await;
+        let mut changeset = self.find_in_state(id, ModSecRuleChangesetState::DRAFT).await?;
+        let mut changes = changeset.parse_changes()?;
+        changes.retain(|c| c.name != change.name);
+        changes.push(change);
+        changeset.changes = Some(serde_json::to_string(&changes)?);
+        self.repo.update_fields(changeset, &["changes"]).await?;
+        self.find(id).await
+    }
+
+    /// Approve the draft changeset, which is validated and compiled with the current rules as a whole, but
+    /// not applied yet.
+    pub async fn approve(&self, id: i64) -> Result<ModSecRuleChangeset, Error> {
+        let _guard = self.transition_lock.lock().await;
+        let mut changeset = self.find_in_state(id, ModSecRuleChangesetState::DRAFT).await?;
+        let changes = changeset.parse_changes()?;
+        if changes.is_empty() {
+            return Err(Error::msg(format!("The changeset {} has no any changes", id)));
+        }
+        changeset.compiled_hash = Some(self.versions.preview_changes(&changes).await?);
+        changeset.state = Some(ModSecRuleChangesetState::APPROVED);
+        changeset.approved_by = SecurityContext::get_instance().get_current_uname().await;
+        self.repo
+            .update_fields(changeset, &["state", "compiled_hash", "approved_by"])
+            .await?;
+        self.find(id).await
+    }
+
+    /// Apply the approved changeset as a unit, i.e: all the changes are effective by the single recompile and
+    /// swap of the rules, or nothing if any change is invalid (e.g: the rules changed since approved).
+    pub async fn apply(&self, id: i64) -> Result<ModSecRuleChangeset, Error> {
+        let _guard = self.transition_lock.lock().await;
+        let mut changeset = self.find_in_state(id, ModSecRuleChangesetState::APPROVED).await?;
+        let changes = changeset.parse_changes()?;
+        let comment = format!(
+            "Applied the changeset {}: {}",
+            id,
+            changeset.name.as_deref().unwrap_or_default()
+        );
+        let applied = self.versions.apply_changes(id, &changes, &comment).await?;
+
+        let applied_by = SecurityContext::get_instance().get_current_uname().await;
+        let rules = applied.baseline.keys().cloned().collect::<Vec<_>>().join(",");
+        audit_changeset(
+            "APPLY",
+            id,
+            &rules,
+            &applied.compiled_hash,
+            applied_by.as_deref().unwrap_or_default(),
+        );
+        changeset.state = Some(ModSecRuleChangesetState::APPLIED);
+        changeset.baseline = Some(serde_json::to_string(&applied.baseline)?);
+        changeset.compiled_hash = Some(applied.compiled_hash);
+        changeset.applied_by = applied_by;
+        self.repo
+            .update_fields(changeset, &["state", "baseline", "compiled_hash", "applied_by"])
+            .await?;
+        self.find(id).await
+    }
+
+    /// Rollback the applied changeset as a unit, i.e: the changed rules are reverted to the heads before applied.
+    pub async fn rollback(&self, id: i64) -> Result<ModSecRuleChangeset, Error> {
+        let _guard = self.transition_lock.lock().await;
+        let mut changeset = self.find_in_state(id, ModSecRuleChangesetState::APPLIED).await?;
+        let baseline = changeset.parse_baseline()?;
+        let comment = format!(
+            "Rolled back the changeset {}: {}",
+            id,
+            changeset.name.as_deref().unwrap_or_default()
+        );
+        let reverted = self.versions.revert_changes(id, &baseline, &comment).await?;
+
+        let by = SecurityContext::get_instance().get_current_uname().await;
+        let rules = reverted.baseline.keys().cloned().collect::<Vec<_>>().join(",");
+        audit_changeset(
+            "ROLLBACK",
+            id,
+            &rules,
+            &reverted.compiled_hash,
+            by.as_deref().unwrap_or_default(),
+        );
+        changeset.state = Some(ModSecRuleChangesetState::ROLLED_BACK);
+        changeset.compiled_hash = Some(reverted.compiled_hash);
+        self.repo.update_fields(changeset, &["state", "compiled_hash"]).await?;
+        self.find(id).await
+    }
+
+    async fn find_in_state(&self, id: i64, state: ModSecRuleChangesetState) -> Result<ModSecRuleChangeset, Error> {
+        let changeset = self.find(id).await?;
+        if changeset.state != Some(state) {
+            return Err(Error::msg(format!(
+                "The changeset {} is {:?}, but requires {:?}",
+                id,
+                changeset.state.unwrap_or_default(),
+                state
+            )));
+        }
+        Ok(changeset)
+    }
+
+    async fn build_repository(config: &AppConfig) -> Result<Arc<dyn AsyncRepository<ModSecRuleChangeset>>, Error> {
+        let db_config = &config.appdb;
+        Ok(match db_config.db_type {
+            AppDBType::SQLITE => Arc::new(RuleChangesetSQLiteRepository::new(&db_config.sqlite).await?),
+            AppDBType::POSTGRESQL => Arc::new(RuleChangesetPostgresRepository::new(&db_config.postgres).await?),
+            AppDBType::MONGODB => Arc::new(RuleChangesetMongoRepository::new(&db_config.mongodb).await?),
+        })
+    }
+}
+
+#[audit_log("[RULES][CHANGESET][{action}] id: {id}, rules: {rules}, compiled_hash: {compiled_hash}, by: {by}")]
+fn audit_changeset(action: &str, id: i64, rules: &str, compiled_hash: &str, by: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::{AppConfigProperties, SqliteAppDBProperties};
+    use crate::modules::modsec::{rule_loader, store::rule_versions_sqlite::RuleVersionSQLiteRepository};
+    use botwaf_types::modules::modsec::{rule_changeset::ModSecRuleChangeOp, rule_version::SaveRuleVersionRequest};
+    use std::{env, fs};
+
+    async fn create_manager(name: &str) -> RuleChangesetManager {
+        let dir = env::temp_dir().join(format!("botwaf-ut-rule-changeset-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let sqlite = SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        };
+        let versions = RuleVersionManager::new(
+            &AppConfig::new(&AppConfigProperties::default()),
+            Arc::new(RuleVersionSQLiteRepository::new(&sqlite).await.unwrap()),
+        );
+        let repo = RuleChangesetSQLiteRepository::new(&sqlite).await.unwrap();
+        RuleChangesetManager::new(Arc::new(versions), Arc::new(repo))
+    }
+
+    fn rule(id: u32, pattern: &str) -> String {
+        format!("SecRule ARGS \"@rx {}\" \"id:{},phase:2,deny,status:403\"", pattern, id)
+    }
+
+    fn save_change(name: &str, content: &str) -> ModSecRuleChange {
+        ModSecRuleChange {
+            op: ModSecRuleChangeOp::SAVE,
+            name: name.to_owned(),
+            content: Some(content.to_owned()),
+            severity: Some(String::from("high")),
+            desc: None,
+        }
+    }
+
+    async fn save_rule(manager: &RuleChangesetManager, name: &str, content: &str) {
+        manager
+            .versions
+            .save(SaveRuleVersionRequest {
+                name: name.to_owned(),
+                content: content.to_owned(),
+                severity: String::from("high"),
+                desc: None,
+                comment: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    // The effective (i.e: not deleted) managed rules, in the name order.
+    fn effective_rules(manager: &RuleChangesetManager) -> Vec<(String, String)> {
+        manager
+            .versions
+            .heads()
+            .iter()
+            .filter(|h| !RuleVersionManager::is_deleted(h))
+            .map(|h| (h.rule_name.to_owned().unwrap(), h.content.to_owned().unwrap()))
+            .collect()
+    }
+
+    fn hash_of(rules: &[(String, String)]) -> String {
+        rule_loader::compiled_hash(rules.iter().map(|(name, content)| (name.as_str(), content.as_str())))
+    }
+
+    #[tokio::test]
+    async fn test_changeset_with_invalid_rule_applies_nothing() {
+        let manager = create_manager("invalid").await;
+        save_rule(&manager, "block_union", &rule(2001, "union")).await;
+        let before = manager.versions.heads();
+
+        let changes = vec![
+            save_change("block_union", &rule(2001, "(?i)union")),
+            save_change("block_sleep", &rule(2002, "sleep\\(")),
+            save_change("block_broken", "SecRulo ARGS \"@rx x\" \"id:2003\""),
+        ];
+        let changeset = manager
+            .create(CreateRuleChangesetRequest {
+                name: String::from("sqli"),
+                desc: None,
+                changes: changes.to_owned(),
+            })
+            .await
+            .unwrap();
+        let id = changeset.base.id.unwrap();
+
+        // The approval is rejected, and the changeset remains DRAFT.
+        let e = manager.approve(id).await.unwrap_err();
+        assert!(e.to_string().contains("block_broken"), "{}", e);
+        assert_eq!(
+            manager.find(id).await.unwrap().state,
+            Some(ModSecRuleChangesetState::DRAFT)
+        );
+        assert!(manager.apply(id).await.is_err());
+
+        // Nothing is appended even the valid changes, i.e: never partially applied.
+        assert!(manager.versions.apply_changes(id, &changes, "sqli").await.is_err());
+        assert_eq!(manager.versions.heads(), before);
+        assert!(manager.versions.versions("block_sleep").await.unwrap().is_none());
+        let union = manager.versions.versions("block_union").await.unwrap().unwrap();
+        assert_eq!(union.head, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_changeset_apply_then_rollback() {
+        let manager = create_manager("rollback").await;
+        save_rule(&manager, "block_admin", &rule(2010, "admin")).await;
+        save_rule(&manager, "block_union", &rule(2001, "union")).await;
+        let before = effective_rules(&manager);
+
+        // Collecting the changes into the draft.
+        let changeset = manager
+            .create(CreateRuleChangesetRequest {
+                name: String::from("sqli"),
+                desc: Some(String::from("Harden the SQLi rules")),
+                changes: vec![save_change("block_union", &rule(2001, "(?i)union"))],
+            })
+            .await
+            .unwrap();
+        let id = changeset.base.id.unwrap();
+        manager
+            .add_change(id, save_change("block_sleep", &rule(2002, "sleep\\(")))
+            .await
+            .unwrap();
+        let delete = ModSecRuleChange {
+            op: ModSecRuleChangeOp::DELETE,
+            name: String::from("block_admin"),
+            content: None,
+            severity: None,
+            desc: None,
+        };
+        let changeset = manager.add_change(id, delete).await.unwrap();
+        assert_eq!(changeset.parse_changes().unwrap().len(), 3);
+
+        let approved = manager.approve(id).await.unwrap();
+        assert_eq!(approved.state, Some(ModSecRuleChangesetState::APPROVED));
+        // Not effective until applied.
+        assert_eq!(effective_rules(&manager), before);
+
+        let applied = manager.apply(id).await.unwrap();
+        assert_eq!(applied.state, Some(ModSecRuleChangesetState::APPLIED));
+        let after = effective_rules(&manager);
+        assert_eq!(
+            after,
+            vec![
+                (String::from("block_sleep"), rule(2002, "sleep\\(")),
+                (String::from("block_union"), rule(2001, "(?i)union")),
+            ]
+        );
+        // The compiled hash is verifiable against the effective rules.
+        assert_eq!(applied.compiled_hash, approved.compiled_hash);
+        assert_eq!(applied.compiled_hash, Some(hash_of(&after)));
+        assert!(manager.versions.heads().iter().all(|h| h.changeset_id == Some(id)));
+        assert_eq!(
+            applied.parse_baseline().unwrap().into_iter().collect::<Vec<_>>(),
+            vec![
+                (String::from("block_admin"), Some(1)),
+                (String::from("block_sleep"), None),
+                (String::from("block_union"), Some(1)),
+            ]
+        );
+
+        // Rolled back as a unit, including the created and deleted rules.
+        let rolled_back = manager.rollback(id).await.unwrap();
+        assert_eq!(rolled_back.state, Some(ModSecRuleChangesetState::ROLLED_BACK));
+        assert_eq!(effective_rules(&manager), before);
+        assert_eq!(rolled_back.compiled_hash, Some(hash_of(&before)));
+        assert!(manager.rollback(id).await.is_err());
+
+        // The history is never rewritten.
+        let union = manager.versions.versions("block_union").await.unwrap().unwrap();
+        assert_eq!(union.head, Some(3));
+    }
+}
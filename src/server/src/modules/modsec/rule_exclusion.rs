@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{body_processor::BODY_PROCESSOR_RULES, data_file::DataFileManager, rule_loader};
+use crate::config::config::RuleExclusionProperties;
+use anyhow::{Error, Result};
+use botwaf_types::modules::modsec::rule::{ModSecRuleInfo, ModSecRuleState};
+use botwaf_utils::paths;
+use globset::{Glob, GlobMatcher};
+use modsecurity::Rules;
+use std::sync::Arc;
+
+/// The per route exclusions of the ModSecurity rules, which is equivalent to the `ctl:ruleRemoveById`.
+///
+/// Notice: Since the rule removal of libmodsecurity is applied on the rule set, the effective rules are
+/// compiled with `SecRuleRemoveById` for each exclusion in advance, and the first matched path-glob wins.
+pub struct RuleExclusions {
+    items: Vec<(GlobMatcher, Arc<Rules>)>,
+}
+
+impl RuleExclusions {
+    pub fn new(
+        exclusions: &[RuleExclusionProperties],
+        infos: &[ModSecRuleInfo],
+        data_dir: &str,
+        engine_config: Option<&str>,
+    ) -> Self {
+        let compile = |exclusion: &RuleExclusionProperties| Self::compile(exclusion, infos, data_dir, engine_config);
+        let items = exclusions
+            .iter()
+            .filter_map(|exclusion| match compile(exclusion) {
+                Ok(item) => {
+                    tracing::info!(
+                        "Loaded the rule exclusion of {} with {:?}",
+                        exclusion.path_glob,
+                        exclusion.rule_ids
+                    );
+                    Some(item)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to load the rule exclusion of {}, it will be ignored. cause: {}",
+                        exclusion.path_glob,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        RuleExclusions { items }
+    }
+
+    /// Find the effective rules with exclusions of the first matched path-glob, which is matched by the normalized
+    /// path, e.g: '/api/admin/../users' is served as '/users' by the upstream and never excluded by '/api/admin/**'.
+    /// Notice: The ambiguous path (e.g: double encoded) is never excluded, i.e: evaluated with the full rules.
+    pub fn find(&self, path: &str) -> Option<Arc<Rules>> {
+        let normalized = paths::normalize_path(path);
+        if normalized.ambiguous {
+            return None;
+        }
+        self.items
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(&normalized.path))
+            .map(|(_, rules)| rules.to_owned())
+    }
+
+    /// Build the `SecRuleRemoveById` directive with the rule ids or ranges, e.g: SecRuleRemoveById 1000 1001-1010
+    pub fn to_remove_directive(rule_ids: &[String]) -> Result<String> {
+        if rule_ids.is_empty() {
+            return Err(Error::msg("The rule ids is empty"));
+        }
+        let mut ids = Vec::new();
+        for rule_id in rule_ids {
+            let rule_id = rule_id.trim();
+            let (start, end) = rule_id.split_once('-').unwrap_or((rule_id, rule_id));
+            match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+                (Ok(start), Ok(end)) if start < end => ids.push(format!("{}-{}", start, end)),
+                (Ok(start), Ok(end)) if start == end => ids.push(start.to_string()),
+                _ => return Err(Error::msg(format!("Invalid the rule id or range '{}'", rule_id))),
+            }
+        }
+        Ok(format!("SecRuleRemoveById {}", ids.join(" ")))
+    }
+
+    fn compile(
+        exclusion: &RuleExclusionProperties,
+        infos: &[ModSecRuleInfo],
+        data_dir: &str,
+        engine_config: Option<&str>,
+    ) -> Result<(GlobMatcher, Arc<Rules>)> {
+        let matcher = Glob::new(&exclusion.path_glob)?.compile_matcher();
+        let directive = Self::to_remove_directive(&exclusion.rule_ids)?;
+
+        let mut rules = Rules::new();
+        rules
+            .add_plain(BODY_PROCESSOR_RULES)
+            .map_err(|e| Error::msg(e.to_string()))?;
+        // Notice: The SHADOW rules are evaluated separately, see: rule_promotion::ShadowRule
+        for info in infos.iter().filter(|info| info.state == ModSecRuleState::ACTIVE) {
+            rules
+                .add_plain(DataFileManager::resolve_refs(&info.value, data_dir).as_str())
+                .map_err(|e| Error::msg(e.to_string()))?;
+        }
+        rules.add_plain(&directive).map_err(|e| Error::msg(e.to_string()))?;
+        rule_loader::apply_engine_config(&mut rules, engine_config)?;
+        Ok((matcher, Arc::new(rules)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_types::modules::modsec::rule::ModSecRuleSource;
+    use modsecurity::ModSecurity;
+
+    fn is_blocked(rules: &Rules, uri: &str) -> bool {
+        let modsec = ModSecurity::default();
+        let mut transaction = modsec.transaction_builder().with_rules(rules).build().unwrap();
+        transaction.process_uri(uri, "GET", "1.1").unwrap();
+        transaction.add_request_header("Host", "localhost").unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.process_request_body().unwrap();
+        transaction.intervention().is_some()
+    }
+
+    fn create_rule_info(value: &str) -> ModSecRuleInfo {
+        ModSecRuleInfo {
+            name: String::from("test"),
+            kind: String::from("RAW"),
+            severity: String::from("high"),
+            desc: String::from("test"),
+            value: value.to_owned(),
+            source: ModSecRuleSource::STATIC,
+            read_only: false,
+            state: ModSecRuleState::ACTIVE,
+            shadow_stats: None,
+            generated_by: None,
+            version_id: None,
+            changeset_id: None,
+            unsupported_operators: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_to_remove_directive() {
+        let ids = vec![
+            String::from("1000"),
+            String::from(" 1001-1010 "),
+            String::from("2000-2000"),
+        ];
+        assert_eq!(
+            RuleExclusions::to_remove_directive(&ids).unwrap(),
+            "SecRuleRemoveById 1000 1001-1010 2000"
+        );
+        assert!(RuleExclusions::to_remove_directive(&[String::from("1010-1001")]).is_err());
+        assert!(RuleExclusions::to_remove_directive(&[String::from("abc")]).is_err());
+        assert!(RuleExclusions::to_remove_directive(&[]).is_err());
+    }
+
+    #[test]
+    fn test_excluded_rule_only_on_configured_path() {
+        let infos = vec![create_rule_info(
+            r#"SecRuleEngine On
+SecRule REQUEST_URI "@rx admin" "id:1000,phase:1,deny,status:403,msg:'Forbidden Admin Path Detected'"
+SecRule REQUEST_URI "@rx \.env$" "id:1004,phase:1,deny,status:403,msg:'Sensitive .env File Detected'""#,
+        )];
+        let exclusions = vec![RuleExclusionProperties {
+            path_glob: String::from("/api/admin/**"),
+            rule_ids: vec![String::from("999-1001")],
+        }];
+        let exclusions = RuleExclusions::new(&exclusions, &infos, "", None);
+
+        // The excluded rule doesn't block on the configured path, but the other rules are still effective.
+        let rules = exclusions.find("/api/admin/users").expect("should be matched");
+        assert!(!is_blocked(&rules, "/api/admin/users"));
+        assert!(is_blocked(&rules, "/api/admin/.env"));
+
+        // The excluded rule still blocks elsewhere.
+        assert!(exclusions.find("/admin").is_none());
+        let mut global = Rules::new();
+        global.add_plain(infos[0].value.as_str()).unwrap();
+        assert!(is_blocked(&global, "/admin"));
+    }
+
+    #[test]
+    fn test_traversal_out_of_excluded_path_not_excluded() {
+        let infos = vec![create_rule_info(
+            r#"SecRuleEngine On
+SecRule REQUEST_URI "@rx admin" "id:1000,phase:1,deny,status:403,msg:'Forbidden Admin Path Detected'""#,
+        )];
+        let exclusions = vec![RuleExclusionProperties {
+            path_glob: String::from("/api/admin/**"),
+            rule_ids: vec![String::from("1000")],
+        }];
+        let exclusions = RuleExclusions::new(&exclusions, &infos, "", None);
+
+        // The traversal (plain or encoded) out of the excluded path is served elsewhere by the upstream.
+        for path in [
+            "/api/admin/../users",
+            "/api/admin/..%2f..%2fusers",
+            "/api/admin/%2e%2e/users",
+            "/api/admin\\..\\users",
+        ] {
+            assert!(exclusions.find(path).is_none(), "{}", path);
+        }
+        // The ambiguous path is never excluded, even though under the excluded path.
+        assert!(exclusions.find("/api/admin/%252e%252e/users").is_none());
+        assert!(exclusions.find("/api/admin/%zz").is_none());
+        // The equivalent forms of the excluded path are still excluded.
+        for path in ["//api/admin/users", "/api/./admin/users", "/api/%61dmin/users"] {
+            assert!(exclusions.find(path).is_some(), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{
+    body_processor::BODY_PROCESSOR_RULES, data_file::DataFileManager, engine_capability::EngineCapabilities,
+    rule_promotion::RulePromotionManager, rule_version::RuleVersionManager,
+};
+use crate::{config::config::AppConfig, mgmt::apm::metrics::BOTWAF_EMERGENCY_RULES_ACTIVE};
+use anyhow::{anyhow, Error};
+use botwaf_types::modules::modsec::rule::{ModSecRuleInfo, ModSecRuleSource, ModSecRuleState};
+use modsecurity::Rules;
+use sha2::{Digest, Sha256};
+
+/// The minimal curated emergency rule set (SQLi/XSS/path-traversal/protocol-violation) compiled into
+/// the binary, see: `emergency_rules.conf`.
+pub const EMERGENCY_RULES: &'static str = include_str!("emergency_rules.conf");
+
+pub const EMERGENCY_RULES_NAME: &'static str = "botwaf_emergency";
+
+// The directives not allowed in the engine config, which should be configured as the rules instead.
+const ENGINE_CONFIG_DISALLOWED_DIRECTIVES: &[&str] = &[
+    "secrule",
+    "secrulescript",
+    "secaction",
+    "secmarker",
+    "include",
+    "secruleremovebyid",
+    "secruleremovebymsg",
+    "secruleremovebytag",
+    "secruleupdateactionbyid",
+    "secruleupdatetargetbyid",
+    "secruleupdatetargetbymsg",
+    "secruleupdatetargetbytag",
+];
+
+/// Validate the engine-level directives of 'services.modsec.engine-config', which must be compilable and
+/// without any rules or includes.
+pub fn validate_engine_config(engine_config: &str) -> Result<(), Error> {
+    // Notice: The directive could be continued on the next lines with the trailing backslash.
+    let joined = engine_config.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in joined
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let directive = line.split_whitespace().next().unwrap_or_default().to_lowercase();
+        if ENGINE_CONFIG_DISALLOWED_DIRECTIVES.contains(&directive.as_str()) {
+            return Err(anyhow!("The directive is not allowed in the engine config: {}", line));
+        }
+    }
+    Rules::new()
+        .add_plain(engine_config)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to compile the engine config. cause: {}", e))
+}
+
+/// Apply the engine-level directives after the rules, so they take precedence over the same directives in
+/// the rules, e.g: the 'SecRuleEngine On' of the static rules is overridden by 'SecRuleEngine DetectionOnly'.
+pub fn apply_engine_config(rules: &mut Rules, engine_config: Option<&str>) -> Result<(), Error> {
+    if let Some(engine_config) = engine_config.filter(|c| !c.trim().is_empty()) {
+        rules
+            .add_plain(engine_config)
+            .map_err(|e| anyhow!("Failed to add the engine config. cause: {}", e))?;
+    }
+    Ok(())
+}
+
+/// The hash of the compiled MANAGED rules set, i.e: the names and contents of the ACTIVE managed rules in the
+/// name order, which verifies the effective rules are exactly the same as the applied changeset.
+pub fn compiled_hash<'a>(rules: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut rules = rules.into_iter().collect::<Vec<_>>();
+    rules.sort();
+    let mut hasher = Sha256::new();
+    for (name, content) in rules {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update(content.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// The compiled hash of the effective rule infos, see: compiled_hash()
+pub fn compiled_hash_of(infos: &[ModSecRuleInfo]) -> String {
+    compiled_hash(
+        infos
+            .iter()
+            .filter(|info| info.source == ModSecRuleSource::MANAGED && info.state == ModSecRuleState::ACTIVE)
+            .map(|info| (info.name.as_str(), info.value.as_str())),
+    )
+}
+
+/// Loading the effective ModSecurity rules from all sources, and falls back to the embedded emergency
+/// rules if there is no any rules effective, unless disabled by `services.emergency-rules: false`.
+///
+/// Notice: Only the ACTIVE rules are compiled, but the infos include the CANDIDATE and SHADOW rules,
+/// see: rule_promotion::ShadowRule
+pub fn load_rules(config: &AppConfig) -> (Rules, Vec<ModSecRuleInfo>) {
+    let mut rules = Rules::new();
+    // Enable the request body inspection with content-type aware body processors.
+    rules
+        .add_plain(BODY_PROCESSOR_RULES)
+        .expect("Failed to add body processor rules");
+
+    // Notice: The engine capabilities are probed on the first loading, i.e: at startup.
+    let engine = EngineCapabilities::get();
+    let data_dir = config.services.data_files.dir.as_str();
+    let mut infos = Vec::new();
+    for rule in config.services.static_rules.iter() {
+        if rule.kind == "RAW" {
+            tracing::info!(
+                "Loading the security static rule: {} - {} - {}",
+                rule.name,
+                rule.kind,
+                rule.value
+            );
+            // Skip the rule if the referenced data files are unavailable, instead of failing all the rules.
+            let missing = DataFileManager::find_missing_refs(&rule.value, data_dir);
+            if !missing.is_empty() {
+                tracing::error!(
+                    "Skipping the security static rule: {}, because the referenced data files {:?} are unavailable.",
+                    rule.name,
+                    missing
+                );
+                continue;
+            }
+            let state = RulePromotionManager::get().effective_state(&rule.name, rule.state);
+            if state == ModSecRuleState::ACTIVE {
+                rules
+                    .add_plain(DataFileManager::resolve_refs(&rule.value, data_dir).as_str())
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "Failed to add rules. cause: {}",
+                            engine.annotate(&rule.value, &e.to_string())
+                        )
+                    });
+            }
+            infos.push(ModSecRuleInfo {
+                name: rule.name.to_owned(),
+                kind: rule.kind.to_owned(),
+                severity: rule.severity.to_owned(),
+                desc: rule.desc.to_owned(),
+                value: rule.value.to_owned(),
+                source: ModSecRuleSource::STATIC,
+                read_only: false,
+                state,
+                shadow_stats: None,
+                generated_by: None,
+                version_id: None,
+                changeset_id: None,
+                unsupported_operators: engine.unsupported_operators(&rule.value),
+            });
+        }
+    }
+
+    // The head versions of the managed rules, which were validated on saved, see: RuleVersionManager::save()
+    let heads = RuleVersionManager::get().map(|m| m.heads()).unwrap_or_default();
+    for head in heads.iter().filter(|h| !RuleVersionManager::is_deleted(h)) {
+        let (name, value) = match (head.rule_name.as_deref(), head.content.as_deref()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => continue,
+        };
+        if infos.iter().any(|info| info.name == name) {
+            tracing::warn!(
+                "Skipping the managed rule: {}, because conflicts with the static rule.",
+                name
+            );
+            continue;
+        }
+        let missing = DataFileManager::find_missing_refs(value, data_dir);
+        if !missing.is_empty() {
+            tracing::error!(
+                "Skipping the managed rule: {}, because the referenced data files {:?} are unavailable.",
+                name,
+                missing
+            );
+            continue;
+        }
+        let state = RulePromotionManager::get().effective_state(name, ModSecRuleState::ACTIVE);
+        if state == ModSecRuleState::ACTIVE {
+            if let Err(e) = rules.add_plain(DataFileManager::resolve_refs(value, data_dir).as_str()) {
+                tracing::error!(
+                    "Skipping the managed rule: {}, because failed to add. cause: {}",
+                    name,
+                    engine.annotate(value, &e.to_string())
+                );
+                continue;
+            }
+        }
+        infos.push(ModSecRuleInfo {
+            name: name.to_owned(),
+            kind: String::from("RAW"),
+            severity: head.severity.to_owned().unwrap_or_default(),
+            desc: head.description.to_owned().unwrap_or_default(),
+            value: value.to_owned(),
+            source: ModSecRuleSource::MANAGED,
+            read_only: false,
+            state,
+            shadow_stats: None,
+            generated_by: None,
+            version_id: head.base.id,
+            changeset_id: head.changeset_id,
+            unsupported_operators: engine.unsupported_operators(value),
+        });
+    }
+
+    let no_active = !infos.iter().any(|info| info.state == ModSecRuleState::ACTIVE);
+    if no_active && config.services.emergency_rules.unwrap_or(true) {
+        tracing::warn!(
+            "==================================================================================\n\
+             No any effective security rules loaded, falling back to the embedded EMERGENCY rules!\n\
+             Please check the rules DB and 'services.static-rules' configuration as soon as possible.\n\
+             If you really want a pass-through proxy, set up 'services.emergency-rules: false'.\n\
+             =================================================================================="
+        );
+        rules
+            .add_plain(EMERGENCY_RULES)
+            .expect("Failed to add the embedded emergency rules");
+        infos.push(ModSecRuleInfo {
+            name: EMERGENCY_RULES_NAME.to_owned(),
+            kind: String::from("RAW"),
+            severity: String::from("critical"),
+            desc: String::from("The embedded emergency rules, loaded since no any other rules are effective."),
+            value: EMERGENCY_RULES.to_owned(),
+            source: ModSecRuleSource::EMBEDDED,
+            read_only: true,
+            state: ModSecRuleState::ACTIVE,
+            shadow_stats: None,
+            generated_by: None,
+            version_id: None,
+            changeset_id: None,
+            unsupported_operators: Vec::new(),
+        });
+        BOTWAF_EMERGENCY_RULES_ACTIVE.set(1);
+    } else {
+        if no_active {
+            tracing::warn!("No any effective security rules loaded, and the emergency rules is disabled, running as pass-through proxy.");
+        }
+        BOTWAF_EMERGENCY_RULES_ACTIVE.set(0);
+    }
+
+    // Notice: The engine config is validated on the config loaded, see: config::load()
+    apply_engine_config(&mut rules, config.services.modsec.engine_config.as_deref())
+        .expect("Failed to add the engine config");
+
+    (rules, infos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::{AppConfigProperties, StaticRule};
+    use modsecurity::ModSecurity;
+
+    fn is_blocked(rules: &Rules, uri: &str) -> bool {
+        let modsec = ModSecurity::default();
+        let mut transaction = modsec.transaction_builder().with_rules(rules).build().unwrap();
+        transaction.process_uri(uri, "GET", "1.1").unwrap();
+        transaction.add_request_header("Host", "localhost").unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.process_request_body().unwrap();
+        transaction.intervention().is_some()
+    }
+
+    #[test]
+    fn test_empty_config_still_blocks_attack() {
+        // Empty config without any static rules and DB.
+        let config = AppConfig::new(&AppConfigProperties::default());
+        assert!(config.services.static_rules.is_empty());
+
+        let (rules, infos) = load_rules(&config);
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].source, ModSecRuleSource::EMBEDDED);
+        assert!(infos[0].read_only);
+        assert_eq!(BOTWAF_EMERGENCY_RULES_ACTIVE.get(), 1);
+
+        assert!(is_blocked(&rules, "/login?id=1%27%20OR%20%271%27%3D%271"));
+        assert!(is_blocked(&rules, "/search?q=%3Cscript%3Ealert(1)%3C%2Fscript%3E"));
+        assert!(is_blocked(&rules, "/download?file=..%2F..%2Fetc%2Fpasswd"));
+        assert!(!is_blocked(&rules, "/search?q=hello%20world"));
+
+        // The pass-through proxy if the emergency rules disabled.
+        let mut props = AppConfigProperties::default();
+        props.services.emergency_rules = Some(false);
+        let (rules, infos) = load_rules(&AppConfig::new(&props));
+        assert!(infos.is_empty());
+        assert_eq!(BOTWAF_EMERGENCY_RULES_ACTIVE.get(), 0);
+        assert!(!is_blocked(&rules, "/login?id=1%27%20OR%20%271%27%3D%271"));
+    }
+
+    #[test]
+    fn test_engine_config_detection_only_not_block() {
+        let mut props = AppConfigProperties::default();
+        props.services.static_rules = vec![StaticRule {
+            name: String::from("admin"),
+            kind: String::from("RAW"),
+            severity: String::from("high"),
+            desc: String::from("test"),
+            value: String::from(
+                r#"SecRuleEngine On
+SecRule REQUEST_URI "@rx admin" "id:1000,phase:1,deny,status:403,msg:'Forbidden Admin Path Detected'""#,
+            ),
+            state: ModSecRuleState::ACTIVE,
+        }];
+        let (rules, _) = load_rules(&AppConfig::new(&props));
+        assert!(is_blocked(&rules, "/admin"));
+
+        // The engine config takes precedence over the 'SecRuleEngine On' of the rule.
+        props.services.modsec.engine_config = Some(String::from("SecRuleEngine DetectionOnly"));
+        let (rules, _) = load_rules(&AppConfig::new(&props));
+        assert!(!is_blocked(&rules, "/admin"));
+    }
+
+    #[test]
+    fn test_validate_engine_config() {
+        assert!(validate_engine_config("SecRuleEngine DetectionOnly\nSecRequestBodyLimit 13107200").is_ok());
+        assert!(validate_engine_config("# comment only\n").is_ok());
+        assert!(validate_engine_config("SecRuleEngine Maybe").is_err());
+        assert!(validate_engine_config(r#"SecRule ARGS "@rx x" "id:1,deny""#).is_err());
+        assert!(validate_engine_config("SecRuleEngine On\n  SecAction \\\n  \"id:2,deny\"").is_err());
+        assert!(validate_engine_config("Include /etc/passwd").is_err());
+        assert!(validate_engine_config("SecRuleRemoveById 1000").is_err());
+    }
+}
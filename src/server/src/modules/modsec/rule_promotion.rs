@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{body_processor::BODY_PROCESSOR_RULES, data_file::DataFileManager};
+use crate::config::config::{self, RulePromotionProperties};
+use crate::mgmt::apm::metrics::BOTWAF_SHADOW_RULE_HITS_TOTAL;
+use crate::sys::dead_letter::WebhookDelivery;
+use anyhow::{anyhow, Error};
+use botwaf_types::{
+    modules::modsec::rule::{ModSecRuleInfo, ModSecRuleState, ModSecShadowStats},
+    sys::event::{BotwafEvent, RulePromotedV1},
+};
+use botwaf_utils::httpclients;
+use lazy_static::lazy_static;
+use modsecurity::Rules;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<RulePromotionManager> =
+        Arc::new(RulePromotionManager::new(&config::get_config().services.rule_promotion));
+}
+
+/// The compiled SHADOW rule, which is evaluated separately to attribute the would-block requests to the rule.
+pub struct ShadowRule {
+    pub name: String,
+    pub rules: Arc<Rules>,
+}
+
+impl ShadowRule {
+    /// Notice: The engine config (e.g: SecRuleEngine DetectionOnly) is not applied to the shadow rules, so the
+    /// would-block requests are still observed, see: rule_loader::apply_engine_config
+    pub fn compile_all(infos: &[ModSecRuleInfo], data_dir: &str) -> Vec<ShadowRule> {
+        infos
+            .iter()
+            .filter(|info| info.state == ModSecRuleState::SHADOW)
+            .filter_map(|info| {
+                let mut rules = Rules::new();
+                let result = rules
+                    .add_plain(BODY_PROCESSOR_RULES)
+                    .and_then(|_| rules.add_plain(DataFileManager::resolve_refs(&info.value, data_dir).as_str()));
+                match result {
+                    Ok(_) => Some(ShadowRule {
+                        name: info.name.to_owned(),
+                        rules: Arc::new(rules),
+                    }),
+                    Err(e) => {
+                        tracing::error!("Failed to compile the shadow rule: {}, cause: {}", info.name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RuleLifecycle {
+    state: ModSecRuleState,
+    hits: u64,
+    false_positives: u64,
+    since: i64,
+}
+
+impl RuleLifecycle {
+    fn new(state: ModSecRuleState, since: i64) -> Self {
+        Self {
+            state,
+            hits: 0,
+            false_positives: 0,
+            since,
+        }
+    }
+
+    fn precision(&self) -> f64 {
+        if self.hits == 0 {
+            return 0.0;
+        }
+        (self.hits - self.false_positives.min(self.hits)) as f64 / self.hits as f64
+    }
+
+    fn to_stats(&self) -> ModSecShadowStats {
+        ModSecShadowStats {
+            hits: self.hits,
+            false_positives: self.false_positives,
+            precision: self.precision(),
+            since: self.since,
+        }
+    }
+}
+
+/// The promotion of the rules lifecycle state (CANDIDATE -> SHADOW -> ACTIVE), the promoted state overrides
+/// the configured state of the rule until restarted.
+pub struct RulePromotionManager {
+    config: RulePromotionProperties,
+    rules: Mutex<HashMap<String, RuleLifecycle>>,
+}
+
+impl RulePromotionManager {
+    pub fn get() -> Arc<RulePromotionManager> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(config: &RulePromotionProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            rules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The effective state of the rule, and starts the observation of the SHADOW rule.
+    pub fn effective_state(&self, name: &str, configured: ModSecRuleState) -> ModSecRuleState {
+        self.effective_state_at(name, configured, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Record the would-block request of the SHADOW rule, and returns whether promoted to ACTIVE, which
+    /// requires to recompile the rules.
+    pub fn record_hit(&self, name: &str) -> bool {
+        self.record_hit_at(name, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Report the would-block request of the SHADOW rule is false positive.
+    pub fn report_false_positive(&self, name: &str) -> Result<ModSecShadowStats, Error> {
+        let mut rules = self.rules.lock().unwrap();
+        match rules.get_mut(name).filter(|r| r.state == ModSecRuleState::SHADOW) {
+            Some(rule) => {
+                rule.false_positives += 1;
+                Ok(rule.to_stats())
+            }
+            None => Err(anyhow!("The rule '{}' is not in the SHADOW state", name)),
+        }
+    }
+
+    /// Promote the rule to the next state manually, the SHADOW rule is only promoted to ACTIVE if met
+    /// the threshold.
+    pub fn promote(&self, name: &str, current: ModSecRuleState) -> Result<ModSecRuleState, Error> {
+        self.promote_at(name, current, chrono::Utc::now().timestamp_millis())
+    }
+
+    pub fn stats(&self, name: &str) -> Option<ModSecShadowStats> {
+        let rules = self.rules.lock().unwrap();
+        rules
+            .get(name)
+            .filter(|r| r.state == ModSecRuleState::SHADOW)
+            .map(|r| r.to_stats())
+    }
+
+    fn effective_state_at(&self, name: &str, configured: ModSecRuleState, now: i64) -> ModSecRuleState {
+        let mut rules = self.rules.lock().unwrap();
+        match rules.get(name) {
+            Some(rule) if Self::rank(rule.state) >= Self::rank(configured) => rule.state,
+            _ => {
+                if configured == ModSecRuleState::SHADOW {
+                    rules.insert(name.to_owned(), RuleLifecycle::new(configured, now));
+                } else {
+                    rules.remove(name);
+                }
+                configured
+            }
+        }
+    }
+
+    fn record_hit_at(&self, name: &str, now: i64) -> bool {
+        BOTWAF_SHADOW_RULE_HITS_TOTAL.with_label_values(&[name]).inc();
+        let mut rules = self.rules.lock().unwrap();
+        match rules.get_mut(name).filter(|r| r.state == ModSecRuleState::SHADOW) {
+            Some(rule) => {
+                rule.hits += 1;
+                if self.config.auto_promote && self.is_qualified(rule, now) {
+                    rule.state = ModSecRuleState::ACTIVE;
+                    tracing::warn!(
+                        "Promoted the shadow rule '{}' to ACTIVE, hits: {}, precision: {:.4}",
+                        name,
+                        rule.hits,
+                        rule.precision()
+                    );
+                    self.notify(name, ModSecRuleState::SHADOW, rule, true);
+                    return true;
+                }
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn promote_at(&self, name: &str, current: ModSecRuleState, now: i64) -> Result<ModSecRuleState, Error> {
+        let mut rules = self.rules.lock().unwrap();
+        match current {
+            ModSecRuleState::CANDIDATE => {
+                let rule = RuleLifecycle::new(ModSecRuleState::SHADOW, now);
+                self.notify(name, ModSecRuleState::CANDIDATE, &rule, false);
+                rules.insert(name.to_owned(), rule);
+                Ok(ModSecRuleState::SHADOW)
+            }
+            ModSecRuleState::SHADOW => {
+                let rule = rules
+                    .entry(name.to_owned())
+                    .or_insert_with(|| RuleLifecycle::new(ModSecRuleState::SHADOW, now));
+                if !self.is_qualified(rule, now) {
+                    return Err(anyhow!(
+                        "The rule '{}' is not qualified to promote, requires at least {} hits with precision {} over {}, but {:?}",
+                        name,
+                        self.config.min_hits,
+                        self.config.min_precision,
+                        self.config.window_secs,
+                        rule.to_stats()
+                    ));
+                }
+                rule.state = ModSecRuleState::ACTIVE;
+                self.notify(name, ModSecRuleState::SHADOW, rule, false);
+                Ok(ModSecRuleState::ACTIVE)
+            }
+            ModSecRuleState::ACTIVE => Err(anyhow!("The rule '{}' is already ACTIVE", name)),
+        }
+    }
+
+    fn is_qualified(&self, rule: &RuleLifecycle, now: i64) -> bool {
+        now - rule.since >= self.config.window_secs.as_millis() as i64
+            && rule.hits >= self.config.min_hits
+            && rule.precision() >= self.config.min_precision
+    }
+
+    fn notify(&self, name: &str, from_state: ModSecRuleState, rule: &RuleLifecycle, auto_promoted: bool) {
+        let webhook_url = match &self.config.notify_webhook_url {
+            Some(url) => url,
+            None => return,
+        };
+        let event = RulePromotedV1 {
+            name: name.to_owned(),
+            from_state: format!("{:?}", from_state),
+            to_state: format!("{:?}", rule.state),
+            hits: rule.hits,
+            false_positives: rule.false_positives,
+            precision: rule.precision(),
+            auto_promoted,
+        };
+        // The failed notification is written into the dead letters for replay.
+        WebhookDelivery::new(webhook_url, BotwafEvent::RulePromotedV1(event))
+            .spawn(httpclients::build_default(), &format!("promotion of rule '{}'", name));
+    }
+
+    fn rank(state: ModSecRuleState) -> u8 {
+        match state {
+            ModSecRuleState::CANDIDATE => 0,
+            ModSecRuleState::SHADOW => 1,
+            ModSecRuleState::ACTIVE => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::{AppConfig, AppConfigProperties, StaticRule};
+    use crate::config::duration::DurationSecs;
+    use crate::modules::modsec::rule_loader;
+    use modsecurity::ModSecurity;
+
+    fn is_blocked(rules: &Rules, uri: &str) -> bool {
+        let modsec = ModSecurity::default();
+        let mut transaction = modsec.transaction_builder().with_rules(rules).build().unwrap();
+        transaction.process_uri(uri, "GET", "1.1").unwrap();
+        transaction.add_request_header("Host", "localhost").unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.process_request_body().unwrap();
+        transaction.intervention().is_some()
+    }
+
+    fn create_manager() -> RulePromotionManager {
+        RulePromotionManager::new(&RulePromotionProperties {
+            auto_promote: true,
+            window_secs: DurationSecs::from_secs(60),
+            min_hits: 10,
+            min_precision: 0.9,
+            notify_webhook_url: None,
+        })
+    }
+
+    #[test]
+    fn test_shadow_rule_would_block_but_not_block() {
+        let mut props = AppConfigProperties::default();
+        props.services.emergency_rules = Some(false);
+        props.services.static_rules = vec![StaticRule {
+            name: String::from("shadow-admin-path"),
+            kind: String::from("RAW"),
+            severity: String::from("high"),
+            desc: String::from("The new rule to block the admin paths."),
+            value: String::from(
+                r#"SecRuleEngine On
+SecRule REQUEST_URI "@rx admin" "id:3001,phase:1,deny,status:403,msg:'Admin Path'""#,
+            ),
+            state: ModSecRuleState::SHADOW,
+        }];
+        let config = AppConfig::new(&props);
+
+        let (rules, infos) = rule_loader::load_rules(&config);
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].state, ModSecRuleState::SHADOW);
+        assert!(!is_blocked(&rules, "/admin/users"));
+
+        let shadow_rules = ShadowRule::compile_all(&infos, &config.services.data_files.dir);
+        assert_eq!(shadow_rules.len(), 1);
+        assert!(is_blocked(&shadow_rules[0].rules, "/admin/users"));
+        assert!(!is_blocked(&shadow_rules[0].rules, "/index.html"));
+    }
+
+    #[test]
+    fn test_shadow_rule_promoted_after_threshold() {
+        let manager = create_manager();
+        let start = 1_800_000_000_000i64;
+        assert_eq!(
+            manager.effective_state_at("r1", ModSecRuleState::SHADOW, start),
+            ModSecRuleState::SHADOW
+        );
+
+        // The enough hits but not over the observation window yet.
+        for i in 0..20 {
+            assert!(!manager.record_hit_at("r1", start + i * 1000));
+        }
+        assert_eq!(manager.stats("r1").unwrap().hits, 20);
+
+        // The precision is below the threshold, i.e: 17/20 < 0.9
+        for _ in 0..3 {
+            manager.report_false_positive("r1").unwrap();
+        }
+        assert!(!manager.record_hit_at("r1", start + 61_000));
+        assert!(manager
+            .promote_at("r1", ModSecRuleState::SHADOW, start + 61_000)
+            .is_err());
+
+        // The precision is recovered by more true positives, i.e: 27/30 >= 0.9
+        let mut promoted = false;
+        for i in 0..20 {
+            if manager.record_hit_at("r1", start + 62_000 + i * 1000) {
+                promoted = true;
+                break;
+            }
+        }
+        assert!(promoted);
+        assert!(manager.stats("r1").is_none());
+        assert_eq!(
+            manager.effective_state_at("r1", ModSecRuleState::SHADOW, start + 100_000),
+            ModSecRuleState::ACTIVE
+        );
+        assert!(manager.report_false_positive("r1").is_err());
+    }
+
+    #[test]
+    fn test_candidate_promoted_to_shadow_manually() {
+        let manager = create_manager();
+        let start = 1_800_000_000_000i64;
+        assert_eq!(
+            manager.effective_state_at("r2", ModSecRuleState::CANDIDATE, start),
+            ModSecRuleState::CANDIDATE
+        );
+        // The candidate rule is not evaluated.
+        assert!(!manager.record_hit_at("r2", start));
+
+        assert_eq!(
+            manager.promote_at("r2", ModSecRuleState::CANDIDATE, start).unwrap(),
+            ModSecRuleState::SHADOW
+        );
+        assert_eq!(
+            manager.effective_state_at("r2", ModSecRuleState::CANDIDATE, start + 1000),
+            ModSecRuleState::SHADOW
+        );
+        assert_eq!(manager.stats("r2").unwrap().since, start);
+        assert!(manager.promote_at("r2", ModSecRuleState::ACTIVE, start).is_err());
+    }
+}
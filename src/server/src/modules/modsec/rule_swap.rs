@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::mgmt::apm::metrics::{BOTWAF_RULE_SWAPS_TOTAL, BOTWAF_RULE_SWAP_COALESCED_TOTAL};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+/// The debounced apply queue of the rule changes, which coalesces the multiple changes within the window into
+/// a single recompile and swap, to avoid thrashing the compilation under the rapid updates (e.g: bulk saves).
+///
+/// Notice: The pending changes are taken before the swap starts (which always recompiles from the latest
+/// sources), so the changes requested during the swap are applied by the next swap, and never be lost.
+pub struct RuleSwapDebouncer {
+    window: Duration,
+    // The number of the changes requested since the last swap started.
+    pending: AtomicUsize,
+    // Serialize the swaps, so that a stale compilation never overrides the newer one.
+    swapping: Mutex<()>,
+}
+
+impl RuleSwapDebouncer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: AtomicUsize::new(0),
+            swapping: Mutex::new(()),
+        }
+    }
+
+    /// Request to apply the rule changes, the swap is executed after the window elapsed since the first
+    /// pending change, the later changes within the window are coalesced. The zero window swaps immediately.
+    pub fn request<F>(self: &Arc<Self>, swap: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.window.is_zero() {
+            swap();
+            BOTWAF_RULE_SWAPS_TOTAL.inc();
+            return;
+        }
+        if self.pending.fetch_add(1, Ordering::SeqCst) > 0 {
+            // The swap is already scheduled, which will apply this change too.
+            BOTWAF_RULE_SWAP_COALESCED_TOTAL.inc();
+            return;
+        }
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(this.window).await;
+            let _guard = this.swapping.lock().await;
+            let changes = this.pending.swap(0, Ordering::SeqCst);
+            tracing::info!("Swapping the rules with the {} coalesced changes ...", changes);
+            swap();
+            BOTWAF_RULE_SWAPS_TOTAL.inc();
+        });
+    }
+
+    /// The number of the changes which are pending to swap.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counting_swap(swaps: &Arc<AtomicUsize>) -> impl FnOnce() + Send + 'static {
+        let swaps = Arc::clone(swaps);
+        move || {
+            swaps.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rapid_changes_coalesced_into_one_swap() {
+        let debouncer = Arc::new(RuleSwapDebouncer::new(Duration::from_millis(100)));
+        let swaps = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..3 {
+            debouncer.request(counting_swap(&swaps));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(swaps.load(Ordering::SeqCst), 0);
+        assert_eq!(debouncer.pending(), 3);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(swaps.load(Ordering::SeqCst), 1);
+        assert_eq!(debouncer.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_change_after_swap_started_not_lost() {
+        let debouncer = Arc::new(RuleSwapDebouncer::new(Duration::from_millis(100)));
+        let swaps = Arc::new(AtomicUsize::new(0));
+
+        debouncer.request(counting_swap(&swaps));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(swaps.load(Ordering::SeqCst), 1);
+
+        // The later change is applied by the next swap.
+        debouncer.request(counting_swap(&swaps));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(swaps.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_zero_window_swaps_immediately() {
+        let debouncer = Arc::new(RuleSwapDebouncer::new(Duration::ZERO));
+        let swaps = Arc::new(AtomicUsize::new(0));
+
+        debouncer.request(counting_swap(&swaps));
+        debouncer.request(counting_swap(&swaps));
+        assert_eq!(swaps.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{body_processor::BODY_PROCESSOR_RULES, data_file::DataFileManager, engine_capability::EngineCapabilities};
+use anyhow::{anyhow, Error};
+use botwaf_types::modules::modsec::rule_test::{
+    RuleTestDecision, RuleTestResult, RuleTestSample, TestRuleRequest, TestRuleResponse,
+};
+use lazy_static::lazy_static;
+use modsecurity::{ModSecurity, Rules};
+use regex::Regex;
+
+lazy_static! {
+    static ref RULE_ID_REGEX: Regex = Regex::new(r#"\[id "\s*(\d+)\s*"\]"#).unwrap();
+}
+
+/// The sandbox of the rule testing, which compiles the rule in isolation (i.e: without the effective rules
+/// and the engine config) and replays the sample requests against it.
+pub struct RuleSandbox {
+    engine: ModSecurity,
+    rules: Rules,
+}
+
+impl RuleSandbox {
+    /// Compile the rule with the body processors, the rule engine is always on, so that the rule is evaluated
+    /// even if the engine config is DetectionOnly.
+    pub fn compile(rule_text: &str, data_dir: &str) -> Result<Self, Error> {
+        let missing = DataFileManager::find_missing_refs(rule_text, data_dir);
+        if !missing.is_empty() {
+            return Err(anyhow!("The referenced data files {:?} are unavailable", missing));
+        }
+        let mut rules = Rules::new();
+        rules
+            .add_plain("SecRuleEngine On")
+            .and_then(|_| rules.add_plain(BODY_PROCESSOR_RULES))
+            .and_then(|_| rules.add_plain(DataFileManager::resolve_refs(rule_text, data_dir).as_str()))
+            .map_err(|e| {
+                let cause = EngineCapabilities::get().annotate(rule_text, &e.to_string());
+                anyhow!("Failed to compile the rule. cause: {}", cause)
+            })?;
+        Ok(RuleSandbox {
+            engine: ModSecurity::default(),
+            rules,
+        })
+    }
+
+    /// Replay all the samples, the overall is passed only if all the samples got the expected decisions.
+    pub fn run(&self, param: &TestRuleRequest) -> Result<TestRuleResponse, Error> {
+        let results = param
+            .samples
+            .iter()
+            .map(|sample| {
+                let (actual, rule_id) = self.evaluate(sample)?;
+                Ok(RuleTestResult {
+                    method: sample.method.to_owned(),
+                    path: sample.path.to_owned(),
+                    expected: sample.expected,
+                    actual,
+                    rule_id,
+                    passed: actual == sample.expected,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(TestRuleResponse {
+            passed: results.iter().all(|r| r.passed),
+            results,
+        })
+    }
+
+    /// Evaluate the sample the same as the forwarder, i.e: only the 401/403 interventions are blocked,
+    /// see: BotwafForwarderManager::evaluate
+    fn evaluate(&self, sample: &RuleTestSample) -> Result<(RuleTestDecision, Option<String>), Error> {
+        let mut transaction = self.engine.transaction_builder().with_rules(&self.rules).build()?;
+        transaction.process_uri(&sample.path, &sample.method.to_uppercase(), "1.1")?;
+        for (key, value) in sample.headers.iter() {
+            transaction.add_request_header(key, value)?;
+        }
+        transaction.process_request_headers()?;
+        if let Some(body) = &sample.body {
+            transaction.append_request_body(body.as_bytes())?;
+        }
+        transaction.process_request_body()?;
+
+        match transaction.intervention() {
+            Some(intervention) if intervention.status() == 401 || intervention.status() == 403 => {
+                let log = intervention.log().map(|msg| msg.to_string()).unwrap_or_default();
+                let rule_id = RULE_ID_REGEX.captures(&log).map(|caps| caps[1].to_string());
+                Ok((RuleTestDecision::BLOCK, rule_id))
+            }
+            _ => Ok((RuleTestDecision::ALLOW, None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SQLI_RULE: &str = r#"SecRule ARGS "@detectSQLi" "id:3301,phase:2,deny,status:403,msg:'SQLi'""#;
+
+    fn sample(method: &str, path: &str, body: Option<&str>, expected: RuleTestDecision) -> RuleTestSample {
+        let mut headers = BTreeMap::new();
+        if body.is_some() {
+            headers.insert(
+                String::from("Content-Type"),
+                String::from("application/x-www-form-urlencoded"),
+            );
+        }
+        RuleTestSample {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            headers,
+            body: body.map(|b| b.to_owned()),
+            expected,
+        }
+    }
+
+    #[test]
+    fn test_rule_blocks_and_allows_samples() {
+        let sandbox = RuleSandbox::compile(SQLI_RULE, "/nonexistent").unwrap();
+        let param = TestRuleRequest {
+            rule_text: SQLI_RULE.to_owned(),
+            samples: vec![
+                sample("GET", "/orders?id=1", None, RuleTestDecision::ALLOW),
+                sample(
+                    "GET",
+                    "/orders?id=1%27%20OR%20%271%27%3D%271",
+                    None,
+                    RuleTestDecision::BLOCK,
+                ),
+                sample(
+                    "POST",
+                    "/login",
+                    Some("username=admin%27+OR+%271%27%3D%271"),
+                    RuleTestDecision::BLOCK,
+                ),
+            ],
+        };
+        let resp = sandbox.run(&param).unwrap();
+        assert!(resp.passed, "{:?}", resp);
+        assert_eq!(resp.results[1].rule_id.as_deref(), Some("3301"));
+        assert_eq!(resp.results[0].rule_id, None);
+
+        // The mismatched expectation fails the overall.
+        let param = TestRuleRequest {
+            samples: vec![
+                sample("GET", "/orders?id=1", None, RuleTestDecision::ALLOW),
+                sample("GET", "/orders?id=2", None, RuleTestDecision::BLOCK),
+            ],
+            ..param
+        };
+        let resp = sandbox.run(&param).unwrap();
+        assert!(!resp.passed);
+        assert!(resp.results[0].passed);
+        assert_eq!(resp.results[1].actual, RuleTestDecision::ALLOW);
+        assert!(!resp.results[1].passed);
+    }
+
+    #[test]
+    fn test_uncompilable_rule_rejected() {
+        let err = RuleSandbox::compile(r#"SecRule ARGS "@unknownOp x" "id:3302,deny""#, "/nonexistent")
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("Failed to compile the rule"), "{}", err);
+    }
+}
@@ -0,0 +1,668 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::{
+    data_file::DataFileManager, engine_capability::EngineCapabilities, rule_loader,
+    rule_promotion::RulePromotionManager,
+};
+use crate::{
+    config::config::{AppConfig, AppDBType, RuleVersionsProperties},
+    context::state::BotwafState,
+    modules::modsec::store::{
+        rule_versions_mongo::RuleVersionMongoRepository, rule_versions_postgresql::RuleVersionPostgresRepository,
+        rule_versions_sqlite::RuleVersionSQLiteRepository, IRuleVersionRepository,
+    },
+};
+use anyhow::Error;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use botwaf_types::{
+    modules::modsec::{
+        rule::ModSecRuleState,
+        rule_changeset::{ModSecRuleChange, ModSecRuleChangeOp},
+        rule_version::{ModSecRuleVersion, ModSecRuleVersionDiff, QueryRuleVersionResponse, SaveRuleVersionRequest},
+    },
+    BaseBean, RecordStatus,
+};
+use botwaf_utils::text_diffs;
+use common_telemetry::info;
+use lazy_static::lazy_static;
+use modsecurity::Rules;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ArcSwapOption<RuleVersionManager> = ArcSwapOption::empty();
+}
+
+/// The version history of the managed rules, every create/update/rollback appends the immutable version (i.e: the
+/// new head), the history is never rewritten except purged for the retention, and the heads are effective except
+/// the disabled heads (i.e: the deleted rules).
+///
+/// Notice: The heads are cached for the synchronous rules compilation, see: rule_loader::load_rules()
+pub struct RuleVersionManager {
+    config: RuleVersionsProperties,
+    data_dir: String,
+    // The managed rules are not allowed to shadow the config static rules of the same name.
+    static_rule_names: HashSet<String>,
+    repo: Arc<dyn IRuleVersionRepository>,
+    heads: ArcSwap<Vec<ModSecRuleVersion>>,
+    // Serialize the appending within the instance, the concurrent appending across the instances is rejected
+    // by the unique index of (rule_name, version_no).
+    append_lock: Mutex<()>,
+}
+
+impl RuleVersionManager {
+    pub fn new(config: &AppConfig, repo: Arc<dyn IRuleVersionRepository>) -> Self {
+        RuleVersionManager {
+            config: config.services.rule_versions.to_owned(),
+            data_dir: config.services.data_files.dir.to_owned(),
+            static_rule_names: config.services.static_rules.iter().map(|r| r.name.to_owned()).collect(),
+            repo,
+            heads: ArcSwap::from_pointee(Vec::new()),
+            append_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn init(config: &AppConfig) -> Result<(), Error> {
+        if SINGLE_INSTANCE.load().is_some() {
+            return Ok(());
+        }
+        let manager = Arc::new(Self::new(config, Self::build_repository(config).await?));
+        manager.refresh().await?;
+        info!(
+            "Initialized the rule versions manager with {} managed rules.",
+            manager.heads().len()
+        );
+        SINGLE_INSTANCE.store(Some(manager));
+        Ok(())
+    }
+
+    pub fn get() -> Option<Arc<RuleVersionManager>> {
+        SINGLE_INSTANCE.load_full()
+    }
+
+    /// The cached head versions of the managed rules.
+    pub fn heads(&self) -> Arc<Vec<ModSecRuleVersion>> {
+        self.heads.load_full()
+    }
+
+    /// Whether the head version is the deleted rule, see: ModSecRuleChangeOp::DELETE
+    pub fn is_deleted(version: &ModSecRuleVersion) -> bool {
+        version.base.status == Some(RecordStatus::Disabled.value())
+    }
+
+    /// Reload the head versions from the repository, and returns whether any head changed.
+    pub async fn refresh(&self) -> Result<bool, Error> {
+        let heads = self.repo.find_heads().await?;
+        let ids = |heads: &[ModSecRuleVersion]| heads.iter().map(|h| h.base.id).collect::<Vec<_>>();
+        let changed = ids(&heads) != ids(&self.heads.load());
+        self.heads.store(Arc::new(heads));
+        Ok(changed)
+    }
+
+    /// Synchronize the head versions (e.g: updated through the other instances), and recompile the rules if
+    /// any head changed.
+    pub async fn sync(&self, state: &BotwafState) -> Result<bool, Error> {
+        let changed = self.refresh().await?;
+        if changed {
+            info!("The managed rules changed, recompiling the rules ...");
+            state.schedule_reload_modsec_rules();
+        }
+        Ok(changed)
+    }
+
+    /// Validate the content of the rule is compilable, and the referenced data files are available.
+    pub fn validate(&self, content: &str) -> Result<(), Error> {
+        let missing = DataFileManager::find_missing_refs(content, &self.data_dir);
+        if !missing.is_empty() {
+            return Err(Error::msg(format!(
+                "The referenced data files {:?} are unavailable",
+                missing
+            )));
+        }
+        Rules::new()
+            .add_plain(DataFileManager::resolve_refs(content, &self.data_dir).as_str())
+            .map(|_| ())
+            .map_err(|e| {
+                let cause = EngineCapabilities::get().annotate(content, &e.to_string());
+                Error::msg(format!("Failed to compile the rule. cause: {}", cause))
+            })
+    }
+
+    /// Create or update the managed rule, which appends the new head version, the change comment is required
+    /// on the update.
+    pub async fn save(&self, param: SaveRuleVersionRequest) -> Result<ModSecRuleVersion, Error> {
+        self.check_name(&param.name)?;
+        self.validate(&param.content)?;
+
+        let _guard = self.append_lock.lock().await;
+        let head = self.repo.find_versions(&param.name).await?.into_iter().next();
+        let comment = param.comment.filter(|c| !c.trim().is_empty());
+        if head.is_some() && comment.is_none() {
+            return Err(Error::msg("The change comment is required on updating the rule"));
+        }
+        let version = ModSecRuleVersion {
+            base: BaseBean::new_with_id(None),
+            rule_name: Some(param.name),
+            version_no: None,
+            content: Some(param.content),
+            severity: Some(param.severity),
+            description: param.desc,
+            comment,
+            changeset_id: None,
+        };
+        self.append(head.as_ref(), version).await
+    }
+
+    /// Rollback the rule to the version, which appends the new head equal to the version instead of rewriting
+    /// the history, returns none if the version is not found.
+    pub async fn rollback(
+        &self,
+        name: &str,
+        version_no: i64,
+        comment: Option<String>,
+    ) -> Result<Option<ModSecRuleVersion>, Error> {
+        let _guard = self.append_lock.lock().await;
+        let versions = self.repo.find_versions(name).await?;
+        let target = match versions.iter().find(|v| v.version_no == Some(version_no)) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let head = versions.first();
+        if head.and_then(|h| h.version_no) == Some(version_no) {
+            return Err(Error::msg(format!(
+                "The version {} is already the head of rule '{}'",
+                version_no, name
+            )));
+        }
+        let comment = comment.unwrap_or_else(|| format!("Rollback to the version {}", version_no));
+        let version = Self::copy_version(target, &comment, None);
+        self.append(head, version).await.map(Some)
+    }
+
+    /// Apply the changes of the changeset as a unit, i.e: the changes are validated and compiled with the other
+    /// heads as a whole (all-or-nothing) before appending, and the heads are refreshed once all appended, so the
+    /// rules are never recompiled with the partial changes.
+    pub async fn apply_changes(
+        &self,
+        changeset_id: i64,
+        changes: &[ModSecRuleChange],
+        comment: &str,
+    ) -> Result<AppliedRuleChanges, Error> {
+        let _guard = self.append_lock.lock().await;
+        let heads = self.repo.find_heads().await?;
+        let versions = self.propose(&heads, changeset_id, changes, comment)?;
+        let compiled_hash = self.compile_all(&heads, &versions)?;
+
+        let baseline = versions
+            .iter()
+            .filter_map(|v| v.rule_name.to_owned())
+            .map(|name| {
+                let head = heads.iter().find(|h| h.rule_name.as_deref() == Some(name.as_str()));
+                (name, head.and_then(|h| h.version_no))
+            })
+            .collect();
+        let versions = self.append_all(&heads, versions).await?;
+        Ok(AppliedRuleChanges {
+            versions,
+            baseline,
+            compiled_hash,
+        })
+    }
+
+    /// Validate the changes with the current heads as a whole without appending, returns the compiled hash if
+    /// they were applied, see: apply_changes()
+    pub async fn preview_changes(&self, changes: &[ModSecRuleChange]) -> Result<String, Error> {
+        let heads = self.repo.find_heads().await?;
+        let versions = self.propose(&heads, 0, changes, "")?;
+        self.compile_all(&heads, &versions)
+    }
+
+    /// Revert the rules changed by the changeset to the baseline heads (the absent rules are deleted) as a unit,
+    /// which is rejected if any rule was changed again after the changeset applied.
+    pub async fn revert_changes(
+        &self,
+        changeset_id: i64,
+        baseline: &BTreeMap<String, Option<i64>>,
+        comment: &str,
+    ) -> Result<AppliedRuleChanges, Error> {
+        let _guard = self.append_lock.lock().await;
+        let heads = self.repo.find_heads().await?;
+
+        let mut versions = Vec::with_capacity(baseline.len());
+        for (name, version_no) in baseline {
+            let head = heads
+                .iter()
+                .find(|h| h.rule_name.as_deref() == Some(name.as_str()))
+                .filter(|h| h.changeset_id == Some(changeset_id))
+                .ok_or_else(|| {
+                    Error::msg(format!(
+                        "The rule '{}' was changed after the changeset applied, please rollback it individually",
+                        name
+                    ))
+                })?;
+            let version = match version_no {
+                Some(version_no) => {
+                    let versions = self.repo.find_versions(name).await?;
+                    let target = versions
+                        .iter()
+                        .find(|v| v.version_no == Some(*version_no))
+                        .ok_or_else(|| {
+                            Error::msg(format!(
+                                "The version {} of rule '{}' was purged by the retention",
+                                version_no, name
+                            ))
+                        })?;
+                    Self::copy_version(target, comment, None)
+                }
+                None => Self::copy_deleted(head, comment, None),
+            };
+            versions.push(version);
+        }
+        let compiled_hash = self.compile_all(&heads, &versions)?;
+
+        let versions = self.append_all(&heads, versions).await?;
+        Ok(AppliedRuleChanges {
+            versions,
+            baseline: baseline.to_owned(),
+            compiled_hash,
+        })
+    }
+
+    /// The versions of the rule with the unified diff against the previous version, returns none if the rule
+    /// is not found.
+    pub async fn versions(&self, name: &str) -> Result<Option<QueryRuleVersionResponse>, Error> {
+        let versions = self.repo.find_versions(name).await?;
+        if versions.is_empty() {
+            return Ok(None);
+        }
+        let head = versions.first().and_then(|v| v.version_no);
+        // Notice: The versions are in the descending order, so the previous version is the next one.
+        let diffs = versions
+            .iter()
+            .enumerate()
+            .map(|(i, version)| ModSecRuleVersionDiff {
+                version: version.to_owned(),
+                diff: versions
+                    .get(i + 1)
+                    .map(|previous| Self::diff(previous, version, self.config.diff_context_lines))
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        Ok(Some(QueryRuleVersionResponse {
+            name: name.to_owned(),
+            head,
+            versions: diffs,
+        }))
+    }
+
+    pub fn diff(previous: &ModSecRuleVersion, version: &ModSecRuleVersion, context: usize) -> String {
+        text_diffs::unified_diff(
+            previous.content.as_deref().unwrap_or_default(),
+            version.content.as_deref().unwrap_or_default(),
+            &format!("v{}", previous.version_no.unwrap_or_default()),
+            &format!("v{}", version.version_no.unwrap_or_default()),
+            context,
+        )
+    }
+
+    // The new head versions of the changes, each change is validated individually.
+    fn propose(
+        &self,
+        heads: &[ModSecRuleVersion],
+        changeset_id: i64,
+        changes: &[ModSecRuleChange],
+        comment: &str,
+    ) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let mut versions = Vec::with_capacity(changes.len());
+        for change in changes {
+            if versions
+                .iter()
+                .any(|v: &ModSecRuleVersion| v.rule_name.as_deref() == Some(change.name.as_str()))
+            {
+                return Err(Error::msg(format!(
+                    "The rule '{}' is changed more than once in the changeset",
+                    change.name
+                )));
+            }
+            self.check_name(&change.name)?;
+            let head = heads
+                .iter()
+                .find(|h| h.rule_name.as_deref() == Some(change.name.as_str()))
+                .filter(|h| !Self::is_deleted(h));
+            let version = match change.op {
+                ModSecRuleChangeOp::SAVE => {
+                    let (content, severity) = match (change.content.as_deref(), change.severity.as_deref()) {
+                        (Some(content), Some(severity)) => (content, severity),
+                        _ => {
+                            return Err(Error::msg(format!(
+                                "The content and severity of the rule '{}' are required",
+                                change.name
+                            )))
+                        }
+                    };
+                    self.validate(content)
+                        .map_err(|e| Error::msg(format!("The rule '{}' is invalid. {}", change.name, e)))?;
+                    ModSecRuleVersion {
+                        base: BaseBean::new_with_id(None),
+                        rule_name: Some(change.name.to_owned()),
+                        version_no: None,
+                        content: Some(content.to_owned()),
+                        severity: Some(severity.to_owned()),
+                        description: change.desc.to_owned(),
+                        comment: Some(comment.to_owned()),
+                        changeset_id: Some(changeset_id),
+                    }
+                }
+                ModSecRuleChangeOp::DELETE => match head {
+                    Some(head) => Self::copy_deleted(head, comment, Some(changeset_id)),
+                    None => return Err(Error::msg(format!("The rule '{}' is not found", change.name))),
+                },
+            };
+            versions.push(version);
+        }
+        Ok(versions)
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), Error> {
+        if self.static_rule_names.contains(name) {
+            return Err(Error::msg(format!(
+                "The rule '{}' conflicts with the static rule of 'services.static-rules'",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    // The new head equal to the version, including the disabled status of the deleted rule.
+    fn copy_version(target: &ModSecRuleVersion, comment: &str, changeset_id: Option<i64>) -> ModSecRuleVersion {
+        ModSecRuleVersion {
+            base: BaseBean {
+                status: target.base.status,
+                ..BaseBean::new_with_id(None)
+            },
+            rule_name: target.rule_name.to_owned(),
+            version_no: None,
+            content: target.content.to_owned(),
+            severity: target.severity.to_owned(),
+            description: target.description.to_owned(),
+            comment: Some(comment.to_owned()),
+            changeset_id,
+        }
+    }
+
+    // The new disabled head of the deleted rule, the content is retained for the diff and rollback.
+    fn copy_deleted(head: &ModSecRuleVersion, comment: &str, changeset_id: Option<i64>) -> ModSecRuleVersion {
+        let mut version = Self::copy_version(head, comment, changeset_id);
+        version.base.status = Some(RecordStatus::Disabled.value());
+        version
+    }
+
+    /// Compile the ACTIVE rules of the heads with the versions applied as a whole, which rejects the rules
+    /// conflicted with each other (e.g: the duplicated rule ids), returns the compiled hash.
+    fn compile_all(&self, heads: &[ModSecRuleVersion], versions: &[ModSecRuleVersion]) -> Result<String, Error> {
+        let changed = |name: Option<&str>| versions.iter().any(|v| v.rule_name.as_deref() == name);
+        let promotion = RulePromotionManager::get();
+        let effective = heads
+            .iter()
+            .filter(|h| !changed(h.rule_name.as_deref()))
+            .chain(versions.iter())
+            .filter(|v| !Self::is_deleted(v))
+            .filter_map(|v| v.rule_name.as_deref().zip(v.content.as_deref()))
+            .filter(|(name, _)| promotion.effective_state(name, ModSecRuleState::ACTIVE) == ModSecRuleState::ACTIVE)
+            .collect::<Vec<_>>();
+
+        let mut rules = Rules::new();
+        for (name, content) in effective.iter() {
+            rules
+                .add_plain(DataFileManager::resolve_refs(content, &self.data_dir).as_str())
+                .map_err(|e| {
+                    let cause = EngineCapabilities::get().annotate(content, &e.to_string());
+                    Error::msg(format!(
+                        "Failed to compile the rule '{}' with the others as a whole. cause: {}",
+                        name, cause
+                    ))
+                })?;
+        }
+        Ok(rule_loader::compiled_hash(effective))
+    }
+
+    // Append the versions as a unit, the appended versions are reverted if any failed, and the heads are refreshed
+    // once all appended (or reverted).
+    async fn append_all(
+        &self,
+        heads: &[ModSecRuleVersion],
+        versions: Vec<ModSecRuleVersion>,
+    ) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let find_head = |name: Option<&str>| heads.iter().find(|h| h.rule_name.as_deref() == name);
+        let mut appended: Vec<ModSecRuleVersion> = Vec::with_capacity(versions.len());
+        for version in versions {
+            match self.insert(find_head(version.rule_name.as_deref()), version).await {
+                Ok(version) => appended.push(version),
+                Err(e) => {
+                    for version in appended.iter() {
+                        let comment = "Revert the partially appended changes";
+                        let revert = match find_head(version.rule_name.as_deref()) {
+                            Some(head) => Self::copy_version(head, comment, None),
+                            None => Self::copy_deleted(version, comment, None),
+                        };
+                        if let Err(e) = self.insert(Some(version), revert).await {
+                            tracing::error!(
+                                "Failed to revert the partially appended version of rule '{}'. cause: {}",
+                                version.rule_name.as_deref().unwrap_or_default(),
+                                e
+                            );
+                        }
+                    }
+                    self.refresh().await?;
+                    return Err(e);
+                }
+            }
+        }
+        self.refresh().await?;
+        Ok(appended)
+    }
+
+    async fn append(
+        &self,
+        head: Option<&ModSecRuleVersion>,
+        version: ModSecRuleVersion,
+    ) -> Result<ModSecRuleVersion, Error> {
+        let version = self.insert(head, version).await?;
+        self.refresh().await?;
+        Ok(version)
+    }
+
+    // Insert the version as the new head, without refreshing the heads.
+    async fn insert(
+        &self,
+        head: Option<&ModSecRuleVersion>,
+        mut version: ModSecRuleVersion,
+    ) -> Result<ModSecRuleVersion, Error> {
+        let name = version.rule_name.to_owned().unwrap_or_default();
+        let version_no = head.and_then(|h| h.version_no).unwrap_or_default() + 1;
+        version.version_no = Some(version_no);
+        let id = self.repo.insert(version).await?;
+        info!("Appended the version {} of rule '{}'", version_no, name);
+
+        // Purge the oldest versions beyond the retention, but the head is always retained.
+        let max_versions = self.config.max_versions.max(1) as i64;
+        if version_no > max_versions {
+            let purged = self.repo.purge_before(&name, version_no - max_versions + 1).await?;
+            if purged > 0 {
+                info!("Purged the {} oldest versions of rule '{}'", purged, name);
+            }
+        }
+        self.repo.select_by_id(id, None).await
+    }
+
+    async fn build_repository(config: &AppConfig) -> Result<Arc<dyn IRuleVersionRepository>, Error> {
+        let db_config = &config.appdb;
+        Ok(match db_config.db_type {
+            AppDBType::SQLITE => Arc::new(RuleVersionSQLiteRepository::new(&db_config.sqlite).await?),
+            AppDBType::POSTGRESQL => Arc::new(RuleVersionPostgresRepository::new(&db_config.postgres).await?),
+            AppDBType::MONGODB => Arc::new(RuleVersionMongoRepository::new(&db_config.mongodb).await?),
+        })
+    }
+}
+
+/// The versions appended as a unit by the changeset, see: RuleVersionManager::apply_changes()
+#[derive(Clone, Debug)]
+pub struct AppliedRuleChanges {
+    pub versions: Vec<ModSecRuleVersion>,
+    // The head version numbers before applied, none if the rule was absent.
+    pub baseline: BTreeMap<String, Option<i64>>,
+    // The compiled hash of the managed rules after applied, see: rule_loader::compiled_hash()
+    pub compiled_hash: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::{AppConfigProperties, SqliteAppDBProperties};
+    use std::{env, fs};
+
+    async fn create_manager(name: &str, max_versions: usize) -> RuleVersionManager {
+        let dir = env::temp_dir().join(format!("botwaf-ut-rule-version-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = RuleVersionSQLiteRepository::new(&SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        })
+        .await
+        .unwrap();
+        let mut properties = AppConfigProperties::default();
+        properties.services.rule_versions.max_versions = max_versions;
+        RuleVersionManager::new(&AppConfig::new(&properties), Arc::new(repo))
+    }
+
+    fn save_request(content: &str, comment: Option<&str>) -> SaveRuleVersionRequest {
+        SaveRuleVersionRequest {
+            name: String::from("block_sqli"),
+            content: content.to_owned(),
+            severity: String::from("high"),
+            desc: None,
+            comment: comment.map(|c| c.to_owned()),
+        }
+    }
+
+    const RULE_V1: &str = "SecRule ARGS \"@rx union\" \"id:1001,phase:2,deny,status:403\"\n\
+                           SecRule ARGS \"@rx select\" \"id:1002,phase:2,deny,status:403\"";
+    const RULE_V2: &str = "SecRule ARGS \"@rx (?i)union\" \"id:1001,phase:2,deny,status:403\"\n\
+                           SecRule ARGS \"@rx select\" \"id:1002,phase:2,deny,status:403\"\n\
+                           SecRule ARGS \"@rx sleep\\(\" \"id:1003,phase:2,deny,status:403\"";
+
+    #[tokio::test]
+    async fn test_save_requires_comment_on_update() {
+        let manager = create_manager("comment", 50).await;
+        let v1 = manager.save(save_request(RULE_V1, None)).await.unwrap();
+        assert_eq!(v1.version_no, Some(1));
+
+        assert!(manager.save(save_request(RULE_V2, None)).await.is_err());
+        assert!(manager.save(save_request(RULE_V2, Some("  "))).await.is_err());
+        // The invalid rule is rejected without appending the version.
+        assert!(manager
+            .save(save_request("SecRulo ARGS \"@rx union\" \"id:1004\"", Some("broken")))
+            .await
+            .is_err());
+
+        let v2 = manager
+            .save(save_request(RULE_V2, Some("Case insensitive")))
+            .await
+            .unwrap();
+        assert_eq!(v2.version_no, Some(2));
+        assert_eq!(manager.heads().len(), 1);
+        assert_eq!(manager.heads()[0].version_no, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_versions_with_multi_line_diff() {
+        let manager = create_manager("diff", 50).await;
+        manager.save(save_request(RULE_V1, None)).await.unwrap();
+        manager
+            .save(save_request(RULE_V2, Some("Case insensitive")))
+            .await
+            .unwrap();
+
+        let response = manager.versions("block_sqli").await.unwrap().unwrap();
+        assert_eq!(response.head, Some(2));
+        assert_eq!(response.versions.len(), 2);
+        assert_eq!(
+            response.versions[0].diff,
+            "--- v1\n\
+             +++ v2\n\
+             @@ -1,2 +1,3 @@\n\
+             -SecRule ARGS \"@rx union\" \"id:1001,phase:2,deny,status:403\"\n\
+             +SecRule ARGS \"@rx (?i)union\" \"id:1001,phase:2,deny,status:403\"\n \
+             SecRule ARGS \"@rx select\" \"id:1002,phase:2,deny,status:403\"\n\
+             +SecRule ARGS \"@rx sleep\\(\" \"id:1003,phase:2,deny,status:403\"\n"
+        );
+        assert_eq!(response.versions[1].diff, "");
+        assert!(manager.versions("not_exists").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_creates_new_head() {
+        let manager = create_manager("rollback", 50).await;
+        manager.save(save_request(RULE_V1, None)).await.unwrap();
+        manager
+            .save(save_request(RULE_V2, Some("Case insensitive")))
+            .await
+            .unwrap();
+
+        let v3 = manager.rollback("block_sqli", 1, None).await.unwrap().unwrap();
+        assert_eq!(v3.version_no, Some(3));
+        assert_eq!(v3.content.as_deref(), Some(RULE_V1));
+        assert_eq!(v3.comment.as_deref(), Some("Rollback to the version 1"));
+
+        // The history is never rewritten.
+        let response = manager.versions("block_sqli").await.unwrap().unwrap();
+        let version_nos = response
+            .versions
+            .iter()
+            .map(|v| v.version.version_no)
+            .collect::<Vec<_>>();
+        assert_eq!(version_nos, vec![Some(3), Some(2), Some(1)]);
+        assert_eq!(response.versions[2].version.content.as_deref(), Some(RULE_V1));
+        assert_eq!(response.versions[1].version.content.as_deref(), Some(RULE_V2));
+        assert_eq!(manager.heads()[0].base.id, v3.base.id);
+
+        // The head and the unknown versions.
+        assert!(manager.rollback("block_sqli", 3, None).await.is_err());
+        assert!(manager.rollback("block_sqli", 9, None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retention_purges_oldest_versions() {
+        let manager = create_manager("retention", 2).await;
+        manager.save(save_request(RULE_V1, None)).await.unwrap();
+        manager.save(save_request(RULE_V2, Some("v2"))).await.unwrap();
+        manager.save(save_request(RULE_V1, Some("v3"))).await.unwrap();
+
+        let response = manager.versions("block_sqli").await.unwrap().unwrap();
+        let version_nos = response
+            .versions
+            .iter()
+            .map(|v| v.version.version_no)
+            .collect::<Vec<_>>();
+        assert_eq!(version_nos, vec![Some(3), Some(2)]);
+    }
+}
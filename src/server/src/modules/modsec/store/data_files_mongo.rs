@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::DATA_FILE_TABLE_NAME;
+use crate::config::config::MongoAppDBProperties;
+use crate::store::mongo::MongoRepository;
+use crate::store::AsyncRepository;
+use crate::util::auths::SecurityContext;
+use crate::{dynamic_mongo_insert, dynamic_mongo_query, dynamic_mongo_update};
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_types::modules::modsec::data_file::DataFile;
+use botwaf_types::{datetime::UtcDateTime, PageRequest, PageResponse, RecordStatus};
+use mongodb::bson::{doc, to_bson};
+use mongodb::Collection;
+use std::sync::Arc;
+
+pub struct DataFileMongoRepository {
+    #[allow(unused)]
+    inner: Arc<MongoRepository<DataFile>>,
+    collection: Collection<DataFile>,
+}
+
+impl DataFileMongoRepository {
+    pub async fn new(config: &MongoAppDBProperties) -> Result<Self, Error> {
+        let inner = Arc::new(MongoRepository::new(config).await?);
+        let collection = inner.get_database().collection(DATA_FILE_TABLE_NAME);
+        Ok(DataFileMongoRepository { inner, collection })
+    }
+}
+
+#[async_trait]
+impl AsyncRepository<DataFile> for DataFileMongoRepository {
+    async fn select(&self, data_file: DataFile, page: PageRequest) -> Result<(PageResponse, Vec<DataFile>), Error> {
+        dynamic_mongo_query!(data_file, self.collection, "update_time", page, DataFile)
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<DataFile, Error> {
+        let mut filter = doc! { "id": id };
+        if let Some(status) = status {
+            filter.insert("status", status.value());
+        }
+        let data_file = self
+            .collection
+            .find_one(filter)
+            .await?
+            .ok_or_else(|| Error::msg("Data file not found"))?;
+        Ok(data_file)
+    }
+
+    async fn insert(&self, mut data_file: DataFile) -> Result<i64, Error> {
+        dynamic_mongo_insert!(data_file, self.collection)
+    }
+
+    async fn update(&self, mut data_file: DataFile) -> Result<i64, Error> {
+        dynamic_mongo_update!(data_file, self.collection)
+    }
+
+    async fn update_fields(&self, mut data_file: DataFile, update_fields: &[&str]) -> Result<i64, Error> {
+        dynamic_mongo_update!(data_file, self.collection, Some(update_fields))
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        let result = self.collection.delete_many(doc! {}).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        let filter = doc! { "id": id };
+        let result = self.collection.delete_one(filter).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let filter = doc! { "id": id };
+        let update = doc! {
+            "$set": { "status": status.value(), "update_by": update_by, "update_time": to_bson(&UtcDateTime::now())? },
+            "$inc": { "version": 1 },
+        };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count)
+    }
+}
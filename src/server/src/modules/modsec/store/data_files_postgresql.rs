@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::DATA_FILE_TABLE_NAME;
+use crate::config::config::PostgresAppDBProperties;
+use crate::dynamic_postgres_insert;
+use crate::dynamic_postgres_query;
+use crate::dynamic_postgres_update;
+use crate::store::postgres::PostgresRepository;
+use crate::store::AsyncRepository;
+use crate::util::auths::SecurityContext;
+use anyhow::{Error, Ok};
+use async_trait::async_trait;
+use botwaf_types::datetime::UtcDateTime;
+use botwaf_types::modules::modsec::data_file::DataFile;
+use botwaf_types::PageRequest;
+use botwaf_types::PageResponse;
+use botwaf_types::RecordStatus;
+use common_telemetry::info;
+
+pub struct DataFilePostgresRepository {
+    inner: PostgresRepository<DataFile>,
+}
+
+impl DataFilePostgresRepository {
+    pub async fn new(config: &PostgresAppDBProperties) -> Result<Self, Error> {
+        Ok(DataFilePostgresRepository {
+            inner: PostgresRepository::new(config).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncRepository<DataFile> for DataFilePostgresRepository {
+    async fn select(&self, data_file: DataFile, page: PageRequest) -> Result<(PageResponse, Vec<DataFile>), Error> {
+        let result = dynamic_postgres_query!(
+            data_file,
+            DATA_FILE_TABLE_NAME,
+            self.inner.get_pool(),
+            "update_time",
+            page,
+            DataFile
+        )?;
+        info!("query data files: {:?}", result.0);
+        Ok((result.0, result.1))
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<DataFile, Error> {
+        let query = match status {
+            Some(_) => format!(
+                "SELECT * FROM {} WHERE id = $1 and del_flag = 0 and status = $2",
+                DATA_FILE_TABLE_NAME
+            ),
+            None => format!("SELECT * FROM {} WHERE id = $1 and del_flag = 0", DATA_FILE_TABLE_NAME),
+        };
+        let mut operator = sqlx::query_as::<_, DataFile>(query.as_str()).bind(id);
+        if let Some(status) = status {
+            operator = operator.bind(status.value());
+        }
+        let data_file = operator.fetch_one(self.inner.get_pool()).await?;
+        Ok(data_file)
+    }
+
+    async fn insert(&self, mut data_file: DataFile) -> Result<i64, Error> {
+        let inserted_id = dynamic_postgres_insert!(data_file, DATA_FILE_TABLE_NAME, self.inner.get_pool())?;
+        info!("Inserted data_file.id: {:?}", inserted_id);
+        Ok(inserted_id)
+    }
+
+    async fn update(&self, mut data_file: DataFile) -> Result<i64, Error> {
+        let updated_id = dynamic_postgres_update!(data_file, DATA_FILE_TABLE_NAME, self.inner.get_pool())?;
+        info!("Updated data_file.id: {:?}", updated_id);
+        Ok(updated_id)
+    }
+
+    async fn update_fields(&self, mut data_file: DataFile, update_fields: &[&str]) -> Result<i64, Error> {
+        let updated_id = dynamic_postgres_update!(
+            data_file,
+            DATA_FILE_TABLE_NAME,
+            self.inner.get_pool(),
+            Some(update_fields)
+        )?;
+        info!("Updated data_file.id: {:?}", updated_id);
+        Ok(updated_id)
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        let delete_result = sqlx::query(format!("DELETE FROM {}", DATA_FILE_TABLE_NAME).as_str())
+            .execute(self.inner.get_pool())
+            .await?;
+
+        info!("Deleted result: {:?}", delete_result);
+        Ok(delete_result.rows_affected())
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        let delete_result =
+            sqlx::query(format!("DELETE FROM {} WHERE id = $1 and del_flag = 0", DATA_FILE_TABLE_NAME).as_str())
+                .bind(id)
+                .execute(self.inner.get_pool())
+                .await?;
+
+        info!("Deleted result: {:?}", delete_result);
+        Ok(delete_result.rows_affected())
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let update_result = sqlx::query(
+            format!(
+                "UPDATE {} SET status = $1, update_by = $2, update_time = $3, version = COALESCE(version, 0) + 1 WHERE id = $4 and del_flag = 0",
+                DATA_FILE_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(status.value())
+        .bind(update_by)
+        .bind(UtcDateTime::now())
+        .bind(id)
+        .execute(self.inner.get_pool())
+        .await?;
+
+        info!("Updated status result: {:?}", update_result);
+        Ok(update_result.rows_affected())
+    }
+}
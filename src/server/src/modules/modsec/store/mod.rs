@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+pub mod data_files_mongo;
+pub mod data_files_postgresql;
+pub mod data_files_sqlite;
+pub mod replay_results_mongo;
+pub mod replay_results_postgresql;
+pub mod replay_results_sqlite;
+pub mod rule_changesets_mongo;
+pub mod rule_changesets_postgresql;
+pub mod rule_changesets_sqlite;
+pub mod rule_versions_mongo;
+pub mod rule_versions_postgresql;
+pub mod rule_versions_sqlite;
+
+use crate::store::AsyncRepository;
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_types::modules::modsec::{replay::ReplayResult, rule_version::ModSecRuleVersion};
+
+/// The table (or collection) name of the managed rules data files.
+pub const DATA_FILE_TABLE_NAME: &'static str = "botwaf_data_file";
+pub const REPLAY_RESULT_TABLE_NAME: &'static str = "botwaf_replay_result";
+pub const RULE_VERSION_TABLE_NAME: &'static str = "botwaf_rule_version";
+pub const RULE_CHANGESET_TABLE_NAME: &'static str = "botwaf_rule_changeset";
+
+/// The replay results repository, see: crate::modules::modsec::replay_result::ReplayResultManager
+#[async_trait]
+pub trait IReplayResultRepository: AsyncRepository<ReplayResult> + Sync {
+    /// The checkpoint of the replay job, i.e: the max replayed event id.
+    async fn last_event_id(&self, job_id: &str) -> Result<Option<i64>, Error>;
+
+    /// The results of the replay job after the event id, in the event id order.
+    async fn find_after(&self, job_id: &str, after_event_id: i64, limit: u32) -> Result<Vec<ReplayResult>, Error>;
+}
+
+/// The immutable versions of the managed rules, see: crate::modules::modsec::rule_version::RuleVersionManager
+#[async_trait]
+pub trait IRuleVersionRepository: AsyncRepository<ModSecRuleVersion> + Sync {
+    /// The versions of the rule, in the descending order of the version number.
+    async fn find_versions(&self, rule_name: &str) -> Result<Vec<ModSecRuleVersion>, Error>;
+
+    /// The head (i.e: the max version number) versions of all the rules.
+    async fn find_heads(&self) -> Result<Vec<ModSecRuleVersion>, Error>;
+
+    /// Purge the versions of the rule before the version number, for the retention.
+    async fn purge_before(&self, rule_name: &str, version_no: i64) -> Result<u64, Error>;
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{IRuleVersionRepository, RULE_VERSION_TABLE_NAME};
+use crate::config::config::MongoAppDBProperties;
+use crate::store::mongo::MongoRepository;
+use crate::store::AsyncRepository;
+use crate::util::auths::SecurityContext;
+use crate::{dynamic_mongo_insert, dynamic_mongo_query, dynamic_mongo_update};
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_types::modules::modsec::rule_version::ModSecRuleVersion;
+use botwaf_types::{datetime::UtcDateTime, PageRequest, PageResponse, RecordStatus};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, to_bson};
+use mongodb::Collection;
+use std::sync::Arc;
+
+pub struct RuleVersionMongoRepository {
+    #[allow(unused)]
+    inner: Arc<MongoRepository<ModSecRuleVersion>>,
+    collection: Collection<ModSecRuleVersion>,
+}
+
+impl RuleVersionMongoRepository {
+    pub async fn new(config: &MongoAppDBProperties) -> Result<Self, Error> {
+        let inner = Arc::new(MongoRepository::new(config).await?);
+        let collection = inner.get_database().collection(RULE_VERSION_TABLE_NAME);
+        Ok(RuleVersionMongoRepository { inner, collection })
+    }
+}
+
+#[async_trait]
+impl AsyncRepository<ModSecRuleVersion> for RuleVersionMongoRepository {
+    async fn select(
+        &self,
+        rule_version: ModSecRuleVersion,
+        page: PageRequest,
+    ) -> Result<(PageResponse, Vec<ModSecRuleVersion>), Error> {
+        dynamic_mongo_query!(rule_version, self.collection, "update_time", page, ModSecRuleVersion)
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<ModSecRuleVersion, Error> {
+        let mut filter = doc! { "id": id };
+        if let Some(status) = status {
+            filter.insert("status", status.value());
+        }
+        let rule_version = self
+            .collection
+            .find_one(filter)
+            .await?
+            .ok_or_else(|| Error::msg("Rule version not found"))?;
+        Ok(rule_version)
+    }
+
+    async fn insert(&self, mut rule_version: ModSecRuleVersion) -> Result<i64, Error> {
+        dynamic_mongo_insert!(rule_version, self.collection)
+    }
+
+    async fn update(&self, mut rule_version: ModSecRuleVersion) -> Result<i64, Error> {
+        dynamic_mongo_update!(rule_version, self.collection)
+    }
+
+    async fn update_fields(&self, mut rule_version: ModSecRuleVersion, update_fields: &[&str]) -> Result<i64, Error> {
+        dynamic_mongo_update!(rule_version, self.collection, Some(update_fields))
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        let result = self.collection.delete_many(doc! {}).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        let filter = doc! { "id": id };
+        let result = self.collection.delete_one(filter).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let filter = doc! { "id": id };
+        let update = doc! {
+            "$set": { "status": status.value(), "update_by": update_by, "update_time": to_bson(&UtcDateTime::now())? },
+            "$inc": { "version": 1 },
+        };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count)
+    }
+}
+
+#[async_trait]
+impl IRuleVersionRepository for RuleVersionMongoRepository {
+    async fn find_versions(&self, rule_name: &str) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let filter = doc! { "rule_name": rule_name, "del_flag": 0 };
+        let rule_versions: Vec<ModSecRuleVersion> = self
+            .collection
+            .find(filter)
+            .sort(doc! { "version_no": -1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(rule_versions)
+    }
+
+    async fn find_heads(&self) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let filter = doc! { "del_flag": 0 };
+        let rule_versions: Vec<ModSecRuleVersion> = self
+            .collection
+            .find(filter)
+            .sort(doc! { "rule_name": 1, "version_no": -1 })
+            .await?
+            .try_collect()
+            .await?;
+        // Notice: Sorted by the version number descending per rule, so the first of each rule is the head.
+        let mut heads: Vec<ModSecRuleVersion> = Vec::new();
+        for rule_version in rule_versions {
+            if heads
+                .last()
+                .map(|h| h.rule_name != rule_version.rule_name)
+                .unwrap_or(true)
+            {
+                heads.push(rule_version);
+            }
+        }
+        Ok(heads)
+    }
+
+    async fn purge_before(&self, rule_name: &str, version_no: i64) -> Result<u64, Error> {
+        let filter = doc! { "rule_name": rule_name, "version_no": { "$lt": version_no } };
+        let result = self.collection.delete_many(filter).await?;
+        Ok(result.deleted_count)
+    }
+}
@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{IRuleVersionRepository, RULE_VERSION_TABLE_NAME};
+use crate::config::config::PostgresAppDBProperties;
+use crate::dynamic_postgres_insert;
+use crate::dynamic_postgres_query;
+use crate::dynamic_postgres_update;
+use crate::store::postgres::PostgresRepository;
+use crate::store::AsyncRepository;
+use crate::util::auths::SecurityContext;
+use anyhow::{Error, Ok};
+use async_trait::async_trait;
+use botwaf_types::datetime::UtcDateTime;
+use botwaf_types::modules::modsec::rule_version::ModSecRuleVersion;
+use botwaf_types::PageRequest;
+use botwaf_types::PageResponse;
+use botwaf_types::RecordStatus;
+use common_telemetry::info;
+
+pub struct RuleVersionPostgresRepository {
+    inner: PostgresRepository<ModSecRuleVersion>,
+}
+
+impl RuleVersionPostgresRepository {
+    pub async fn new(config: &PostgresAppDBProperties) -> Result<Self, Error> {
+        Ok(RuleVersionPostgresRepository {
+            inner: PostgresRepository::new(config).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncRepository<ModSecRuleVersion> for RuleVersionPostgresRepository {
+    async fn select(
+        &self,
+        rule_version: ModSecRuleVersion,
+        page: PageRequest,
+    ) -> Result<(PageResponse, Vec<ModSecRuleVersion>), Error> {
+        let result = dynamic_postgres_query!(
+            rule_version,
+            RULE_VERSION_TABLE_NAME,
+            self.inner.get_pool(),
+            "update_time",
+            page,
+            ModSecRuleVersion
+        )?;
+        info!("query rule versions: {:?}", result.0);
+        Ok((result.0, result.1))
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<ModSecRuleVersion, Error> {
+        let query = match status {
+            Some(_) => format!(
+                "SELECT * FROM {} WHERE id = $1 and del_flag = 0 and status = $2",
+                RULE_VERSION_TABLE_NAME
+            ),
+            None => format!(
+                "SELECT * FROM {} WHERE id = $1 and del_flag = 0",
+                RULE_VERSION_TABLE_NAME
+            ),
+        };
+        let mut operator = sqlx::query_as::<_, ModSecRuleVersion>(query.as_str()).bind(id);
+        if let Some(status) = status {
+            operator = operator.bind(status.value());
+        }
+        let rule_version = operator.fetch_one(self.inner.get_pool()).await?;
+        Ok(rule_version)
+    }
+
+    async fn insert(&self, mut rule_version: ModSecRuleVersion) -> Result<i64, Error> {
+        let inserted_id = dynamic_postgres_insert!(rule_version, RULE_VERSION_TABLE_NAME, self.inner.get_pool())?;
+        info!("Inserted rule_version.id: {:?}", inserted_id);
+        Ok(inserted_id)
+    }
+
+    async fn update(&self, mut rule_version: ModSecRuleVersion) -> Result<i64, Error> {
+        let updated_id = dynamic_postgres_update!(rule_version, RULE_VERSION_TABLE_NAME, self.inner.get_pool())?;
+        info!("Updated rule_version.id: {:?}", updated_id);
+        Ok(updated_id)
+    }
+
+    async fn update_fields(&self, mut rule_version: ModSecRuleVersion, update_fields: &[&str]) -> Result<i64, Error> {
+        let updated_id = dynamic_postgres_update!(
+            rule_version,
+            RULE_VERSION_TABLE_NAME,
+            self.inner.get_pool(),
+            Some(update_fields)
+        )?;
+        info!("Updated rule_version.id: {:?}", updated_id);
+        Ok(updated_id)
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        let delete_result = sqlx::query(format!("DELETE FROM {}", RULE_VERSION_TABLE_NAME).as_str())
+            .execute(self.inner.get_pool())
+            .await?;
+
+        info!("Deleted result: {:?}", delete_result);
+        Ok(delete_result.rows_affected())
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        let delete_result =
+            sqlx::query(format!("DELETE FROM {} WHERE id = $1 and del_flag = 0", RULE_VERSION_TABLE_NAME).as_str())
+                .bind(id)
+                .execute(self.inner.get_pool())
+                .await?;
+
+        info!("Deleted result: {:?}", delete_result);
+        Ok(delete_result.rows_affected())
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let update_result = sqlx::query(
+            format!(
+                "UPDATE {} SET status = $1, update_by = $2, update_time = $3, version = COALESCE(version, 0) + 1 WHERE id = $4 and del_flag = 0",
+                RULE_VERSION_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(status.value())
+        .bind(update_by)
+        .bind(UtcDateTime::now())
+        .bind(id)
+        .execute(self.inner.get_pool())
+        .await?;
+
+        info!("Updated status result: {:?}", update_result);
+        Ok(update_result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl IRuleVersionRepository for RuleVersionPostgresRepository {
+    async fn find_versions(&self, rule_name: &str) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let rule_versions = sqlx::query_as::<_, ModSecRuleVersion>(
+            format!(
+                "SELECT * FROM {} WHERE rule_name = $1 and del_flag = 0 ORDER BY version_no DESC",
+                RULE_VERSION_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(rule_name)
+        .fetch_all(self.inner.get_pool())
+        .await?;
+        Ok(rule_versions)
+    }
+
+    async fn find_heads(&self) -> Result<Vec<ModSecRuleVersion>, Error> {
+        let rule_versions = sqlx::query_as::<_, ModSecRuleVersion>(
+            format!(
+                "SELECT * FROM {0} v WHERE v.del_flag = 0 and v.version_no = (SELECT MAX(version_no) FROM {0} WHERE rule_name = v.rule_name and del_flag = 0) ORDER BY v.rule_name ASC",
+                RULE_VERSION_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .fetch_all(self.inner.get_pool())
+        .await?;
+        Ok(rule_versions)
+    }
+
+    async fn purge_before(&self, rule_name: &str, version_no: i64) -> Result<u64, Error> {
+        let delete_result = sqlx::query(
+            format!(
+                "DELETE FROM {} WHERE rule_name = $1 and version_no < $2",
+                RULE_VERSION_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(rule_name)
+        .bind(version_no)
+        .execute(self.inner.get_pool())
+        .await?;
+
+        info!("Purged the rule versions result: {:?}", delete_result);
+        Ok(delete_result.rows_affected())
+    }
+}
@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{DataProtectionProperties, IpProtectionMode, ScrubMode};
+use botwaf_types::modules::forward::access_event::BotwafAccessEvent;
+use botwaf_utils::secrets::SecretHelper;
+use regex::{Captures, Regex};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// The data protector of the access events, which anonymizes or pseudonymizes the PII before
+/// the events are persisted or published. Notice: The blocking decision must always be made with
+/// the raw incoming request, never with the protected event.
+#[derive(Clone, Debug)]
+pub struct DataProtector {
+    config: DataProtectionProperties,
+    // Matches the sensitive names, e.g: password, x-api-key
+    name_regex: Regex,
+    // Matches the sensitive pairs of query-like string, e.g: ?password=hunter2&...
+    pair_regex: Regex,
+    // Matches the sensitive fields of JSON string, e.g: {"password":"hunter2"}
+    json_regex: Regex,
+}
+
+impl DataProtector {
+    pub const HMAC_PREFIX: &'static str = "hmac:";
+
+    pub fn new(config: &DataProtectionProperties) -> Self {
+        let patterns = if config.sensitive_patterns.is_empty() {
+            // Nothing should be matched.
+            String::from("[^\\s\\S]")
+        } else {
+            config.sensitive_patterns.join("|")
+        };
+        let name_regex = Regex::new(&format!("(?i)(?:{})", patterns)).expect("Invalid data protection patterns");
+        let pair_regex = Regex::new(&format!(r"(?i)([\w.\-\[\]]*(?:{})[\w.\-\[\]]*)=([^&;\s]*)", patterns))
+            .expect("Invalid data protection patterns");
+        let json_regex = Regex::new(&format!(
+            r#"(?i)("[^"]*(?:{})[^"]*"\s*:\s*)("(?:[^"\\]|\\.)*"|[^,}}\]\s]+)"#,
+            patterns
+        ))
+        .expect("Invalid data protection patterns");
+        DataProtector {
+            config: config.to_owned(),
+            name_regex,
+            pair_regex,
+            json_regex,
+        }
+    }
+
+    /// Returns the protected copy of the access event, which is safe to persist or publish. It's idempotent, so
+    /// that the current rules could be re-applied to the persisted events, e.g: on export.
+    pub fn protect(&self, event: &BotwafAccessEvent) -> BotwafAccessEvent {
+        let mut protected = event.to_owned();
+        protected.client_ip = event.client_ip.as_ref().map(|ip| self.protect_ip(ip));
+        protected.query = event.query.as_ref().and_then(|q| self.scrub_query(q));
+        protected.headers = event.headers.as_ref().map(|h| self.scrub_headers(h));
+        protected.body = event.body.as_ref().and_then(|b| self.scrub_body(b));
+        protected.resp_headers = event.resp_headers.as_ref().map(|h| self.scrub_headers(h));
+        protected.resp_body = event.resp_body.as_ref().and_then(|b| self.scrub_body(b));
+        protected
+    }
+
+    pub fn protect_ip(&self, ip: &str) -> String {
+        // The already pseudonymized address is never hashed again.
+        if ip.starts_with(Self::HMAC_PREFIX) {
+            return ip.to_owned();
+        }
+        match self.config.client_ip {
+            IpProtectionMode::NONE => ip.to_owned(),
+            IpProtectionMode::TRUNCATE => Self::truncate_ip(ip).unwrap_or(self.config.mask.to_owned()),
+            IpProtectionMode::HMAC => match &self.config.hmac_key {
+                Some(key) if !key.is_empty() => {
+                    format!(
+                        "{}{}",
+                        Self::HMAC_PREFIX,
+                        SecretHelper::hmac_sha256_hex(key.as_bytes(), ip.as_bytes())
+                    )
+                }
+                // Never fallback to the raw address without the key.
+                _ => {
+                    tracing::warn!(
+                        "The data protection hmac-key is not configured, fallback to truncate the client IP."
+                    );
+                    Self::truncate_ip(ip).unwrap_or(self.config.mask.to_owned())
+                }
+            },
+        }
+    }
+
+    /// Zero the last octet of IPv4 or the last 80 bits of IPv6.
+    fn truncate_ip(ip: &str) -> Option<String> {
+        match ip.trim().parse::<IpAddr>().ok()? {
+            IpAddr::V4(v4) => {
+                let o = v4.octets();
+                Some(Ipv4Addr::new(o[0], o[1], o[2], 0).to_string())
+            }
+            IpAddr::V6(v6) => {
+                let s = v6.segments();
+                Some(Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0).to_string())
+            }
+        }
+    }
+
+    pub fn scrub_query(&self, query: &str) -> Option<String> {
+        if self.config.query == ScrubMode::NONE {
+            return Some(query.to_owned());
+        }
+        let pairs = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .filter_map(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                if !self.name_regex.is_match(name) {
+                    return Some(pair.to_owned());
+                }
+                match self.config.query {
+                    ScrubMode::DROP => None,
+                    _ => Some(format!("{}={}", name, self.config.mask)),
+                }
+            })
+            .collect::<Vec<String>>();
+        if pairs.is_empty() {
+            None
+        } else {
+            Some(pairs.join("&"))
+        }
+    }
+
+    pub fn scrub_headers(&self, headers: &HashMap<String, Option<String>>) -> HashMap<String, Option<String>> {
+        if self.config.headers == ScrubMode::NONE {
+            return headers.to_owned();
+        }
+        headers
+            .iter()
+            .filter_map(|(name, value)| {
+                if self.name_regex.is_match(name) {
+                    match self.config.headers {
+                        ScrubMode::DROP => None,
+                        _ => Some((name.to_owned(), Some(self.config.mask.to_owned()))),
+                    }
+                } else {
+                    // The value may also carry the sensitive pairs, e.g: Referer: https://x.com/login?password=xx
+                    let value = value.as_ref().map(|v| self.mask_pairs(v));
+                    Some((name.to_owned(), value))
+                }
+            })
+            .collect()
+    }
+
+    /// Redact the body sample, the DROP mode will discard the whole sample if any sensitive field is found.
+    pub fn scrub_body(&self, body: &str) -> Option<String> {
+        match self.config.body {
+            ScrubMode::NONE => Some(body.to_owned()),
+            ScrubMode::MASK => Some(self.mask_json(&self.mask_pairs(body))),
+            ScrubMode::DROP => {
+                if self.pair_regex.is_match(body) || self.json_regex.is_match(body) {
+                    None
+                } else {
+                    Some(body.to_owned())
+                }
+            }
+        }
+    }
+
+    fn mask_pairs(&self, text: &str) -> String {
+        self.pair_regex
+            .replace_all(text, |caps: &Captures| format!("{}={}", &caps[1], self.config.mask))
+            .to_string()
+    }
+
+    fn mask_json(&self, text: &str) -> String {
+        self.json_regex
+            .replace_all(text, |caps: &Captures| format!("{}\"{}\"", &caps[1], self.config.mask))
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_event() -> BotwafAccessEvent {
+        let mut headers = HashMap::new();
+        headers.insert("Authorization".to_string(), Some("Bearer abc123".to_string()));
+        headers.insert("User-Agent".to_string(), Some("curl/8.0".to_string()));
+        headers.insert(
+            "Referer".to_string(),
+            Some("https://example.com/login?user=jack&password=hunter2".to_string()),
+        );
+        BotwafAccessEvent {
+            method: "POST".to_string(),
+            scheme: Some("http".to_string()),
+            host: Some("example.com".to_string()),
+            port: Some(80),
+            headers: Some(headers),
+            path: "/login".to_string(),
+            query: Some("user=jack&password=hunter2".to_string()),
+            body: Some(r#"{"user":"jack","password":"hunter2","api_key":12345}"#.to_string()),
+            req_id: None,
+            client_ip: Some("203.0.113.77".to_string()),
+            start_time: 0,
+            resp_status_code: None,
+            resp_headers: None,
+            resp_body: None,
+            duration: None,
+            synthetic: false,
+            rule_id: None,
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    #[test]
+    fn test_protect_mask_everywhere() {
+        let protector = DataProtector::new(&DataProtectionProperties::default());
+        let event = create_test_event();
+        let protected = protector.protect(&event);
+
+        assert_eq!(protected.client_ip, Some("203.0.113.0".to_string()));
+        assert_eq!(protected.query, Some("user=jack&password=******".to_string()));
+        let headers = protected.headers.as_ref().unwrap();
+        assert_eq!(headers.get("Authorization").unwrap(), &Some("******".to_string()));
+        assert_eq!(headers.get("User-Agent").unwrap(), &Some("curl/8.0".to_string()));
+        assert_eq!(
+            protected.body,
+            Some(r#"{"user":"jack","password":"******","api_key":"******"}"#.to_string())
+        );
+
+        let serialized = serde_json::to_string(&protected).unwrap();
+        assert!(!serialized.contains("hunter2"));
+        assert!(!serialized.contains("abc123"));
+        // The raw event is untouched for the blocking decision.
+        assert_eq!(event.query, Some("user=jack&password=hunter2".to_string()));
+    }
+
+    #[test]
+    fn test_protect_drop_mode() {
+        let mut config = DataProtectionProperties::default();
+        config.query = ScrubMode::DROP;
+        config.headers = ScrubMode::DROP;
+        config.body = ScrubMode::DROP;
+        let protector = DataProtector::new(&config);
+        let protected = protector.protect(&create_test_event());
+
+        assert_eq!(protected.query, Some("user=jack".to_string()));
+        assert!(!protected.headers.as_ref().unwrap().contains_key("Authorization"));
+        assert_eq!(protected.body, None);
+        assert_eq!(protector.scrub_query("password=hunter2"), None);
+    }
+
+    #[test]
+    fn test_protect_ip_modes() {
+        let mut config = DataProtectionProperties::default();
+        let protector = DataProtector::new(&config);
+        assert_eq!(
+            protector.protect_ip("2001:db8:85a3:1234:5678:8a2e:370:7334"),
+            "2001:db8:85a3::"
+        );
+        assert_eq!(protector.protect_ip("not-an-ip"), "******");
+
+        config.client_ip = IpProtectionMode::HMAC;
+        config.hmac_key = Some("changeit".to_string());
+        let protector = DataProtector::new(&config);
+        let first = protector.protect_ip("203.0.113.77");
+        assert!(first.starts_with(DataProtector::HMAC_PREFIX));
+        assert!(!first.contains("203.0.113"));
+        // The same address has the same pseudonym for the correlation.
+        assert_eq!(first, protector.protect_ip("203.0.113.77"));
+        assert_ne!(first, protector.protect_ip("203.0.113.78"));
+        assert_eq!(first, protector.protect_ip(&first));
+
+        config.client_ip = IpProtectionMode::NONE;
+        assert_eq!(DataProtector::new(&config).protect_ip("203.0.113.77"), "203.0.113.77");
+    }
+}
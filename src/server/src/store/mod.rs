@@ -25,18 +25,133 @@ pub mod postgres;
 pub mod sqlite;
 
 use crate::config::config::{AppConfigProperties, AppDBType};
+use crate::mgmt::apm::dependencies::{instrument_dep, DEP_APPDB};
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_types::{PageRequest, PageResponse};
+use botwaf_types::{PageRequest, PageResponse, PersistableBean, RecordStatus};
+use serde_json::{Map, Value};
+
+/// The optimistic locking conflict error, which no row is affected due to a stale version.
+#[derive(Debug, thiserror::Error)]
+#[error("Conflict to update '{table}' id: {id}, the version {version} is stale")]
+pub struct VersionConflictError {
+    pub table: String,
+    pub id: i64,
+    pub version: i64,
+}
+
+/// The field of the bean which JSON type has no SQL column mapping (e.g: array or nested object), which is
+/// rejected instead of silently dropped, see: PersistableBean
+#[derive(Debug, thiserror::Error)]
+#[error("Unsupported type '{json_type}' of the field '{field}' to persist into '{table}', it should be mapped or declared as the transient field")]
+pub struct UnsupportedFieldTypeError {
+    pub table: String,
+    pub field: String,
+    pub json_type: &'static str,
+}
+
+/// Serialize the bean into the persistable fields of the dynamic store macros, which are the null (skipped),
+/// bool, number (i64 or f64) or string values, and the transient fields are excluded.
+pub fn to_persistable_fields<T: PersistableBean>(bean: &T, table: &str) -> Result<Map<String, Value>, Error> {
+    let mut fields = match serde_json::to_value(bean)? {
+        Value::Object(fields) => fields,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "The bean of '{}' must be serialized as the object",
+                table
+            ))
+        }
+    };
+    fields.retain(|key, _| !T::TRANSIENT_FIELDS.contains(&key.as_str()));
+    for (key, value) in fields.iter() {
+        let json_type = match value {
+            Value::Null | Value::Bool(_) | Value::String(_) => continue,
+            Value::Number(n) if n.is_i64() || n.is_f64() => continue,
+            Value::Number(_) => "number(u64)",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        return Err(Error::from(UnsupportedFieldTypeError {
+            table: table.to_string(),
+            field: key.to_owned(),
+            json_type,
+        }));
+    }
+    Ok(fields)
+}
+
+/// The audit fields maintained by the BaseBean::pre_update(), which are always set by the partial update.
+pub const UPDATE_AUDIT_FIELDS: [&str; 3] = ["update_by", "update_time", "version"];
+
+/// The field to be partially updated is not the persistable field of the bean (e.g: misspelled or transient).
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown field '{field}' to partially update '{table}', it should be the persistable field of the bean")]
+pub struct UnknownUpdateFieldError {
+    pub table: String,
+    pub field: String,
+}
+
+/// Validate the field names of the partial update against the serialized fields of the bean, the id is
+/// immutable which is always the condition of the update.
+pub fn validate_update_fields<'a>(
+    keys: impl IntoIterator<Item = &'a str>,
+    update_fields: &[&str],
+    table: &str,
+) -> Result<(), Error> {
+    let keys = keys.into_iter().collect::<Vec<_>>();
+    match update_fields
+        .iter()
+        .find(|field| **field == "id" || !keys.contains(field))
+    {
+        Some(field) => Err(Error::from(UnknownUpdateFieldError {
+            table: table.to_string(),
+            field: field.to_string(),
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Whether the field is set by the dynamic update macros, there are two modes:
+///
+/// - None (e.g: PUT): The "set non-empty" heuristic, the null or empty fields are skipped and left unchanged,
+///   hence a field can never be cleared to null by the full bean update.
+/// - Some(update_fields) (e.g: PATCH): Only the named fields (plus the non-empty audit fields) are set, including
+///   the explicit null or empty values, and the unspecified fields are left unchanged even if they're non-empty.
+pub fn is_updated_field(key: &str, is_empty: bool, update_fields: Option<&[&str]>) -> bool {
+    match update_fields {
+        None => !is_empty,
+        Some(update_fields) => update_fields.contains(&key) || (!is_empty && UPDATE_AUDIT_FIELDS.contains(&key)),
+    }
+}
+
+/// Select the persistable fields to be set by the dynamic update macros, the null value of the result should be
+/// set as the SQL NULL, see: is_updated_field()
+pub fn to_update_fields(
+    fields: Map<String, Value>,
+    update_fields: Option<&[&str]>,
+    table: &str,
+) -> Result<Map<String, Value>, Error> {
+    if let Some(update_fields) = update_fields {
+        validate_update_fields(fields.keys().map(|key| key.as_str()), update_fields, table)?;
+    }
+    Ok(fields
+        .into_iter()
+        .filter(|(key, value)| {
+            let is_empty = value.is_null() || value.as_str().is_some_and(|v| v.is_empty());
+            is_updated_field(key, is_empty, update_fields)
+        })
+        .collect())
+}
 
 #[async_trait] // solution2: async fn + dyn polymorphism problem.
-pub trait AsyncRepository<T>: Send {
+pub trait AsyncRepository<T>: Send + Sync {
     // solution1: async fn + dyn polymorphism problem.
     // fn select(&self) -> Box<dyn Future<Output = Result<Page<T>, Error>> + Send>;
     async fn select(&self, mut param: T, page: PageRequest) -> Result<(PageResponse, Vec<T>), Error>
     where
         T: 'static + Send + Sync;
-    async fn select_by_id(&self, id: i64) -> Result<T, Error>
+    // Notice: The status is optional filtered, e.g: Some(RecordStatus::Active) to exclude the disabled records.
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<T, Error>
     where
         T: 'static + Send + Sync;
     async fn insert(&self, mut param: T) -> Result<i64, Error>
@@ -45,8 +160,28 @@ pub trait AsyncRepository<T>: Send {
     async fn update(&self, mut param: T) -> Result<i64, Error>
     where
         T: 'static + Send + Sync;
+    // Partially update the named fields only (e.g: PATCH), which are set even if null (i.e: cleared) or empty,
+    // and the unspecified fields are left unchanged, versus the update() which sets the non-empty fields only.
+    async fn update_fields(&self, param: T, update_fields: &[&str]) -> Result<i64, Error>
+    where
+        T: 'static + Send + Sync,
+    {
+        let _ = (param, update_fields);
+        Err(anyhow::anyhow!("update_fields not implemented"))
+    }
     async fn delete_all(&self) -> Result<u64, Error>;
     async fn delete_by_id(&self, id: i64) -> Result<u64, Error>;
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error>;
+    // Get the id of the existing record matched by the unique key column, or insert the param atomically (within
+    // a transaction), returns the id and whether it's inserted, e.g: the first login of the OAuth2 users.
+    // Notice: The concurrent insertions of the same key are resolved by the unique constraint of the key column.
+    async fn get_or_insert(&self, key_column: &str, key: &str, param: T) -> Result<(i64, bool), Error>
+    where
+        T: 'static + Send + Sync,
+    {
+        let _ = (key, param);
+        Err(anyhow::anyhow!("get_or_insert by '{}' not implemented", key_column))
+    }
 }
 
 pub struct RepositoryContainer<T>
@@ -68,9 +203,9 @@ where
         mongo_repo: Option<Box<dyn AsyncRepository<T>>>,
     ) -> Self {
         RepositoryContainer {
-            sqlite_repo,
-            postgres_repo,
-            mongo_repo,
+            sqlite_repo: sqlite_repo.map(InstrumentedRepository::wrap),
+            postgres_repo: postgres_repo.map(InstrumentedRepository::wrap),
+            mongo_repo: mongo_repo.map(InstrumentedRepository::wrap),
         }
     }
 
@@ -103,3 +238,266 @@ where
         }
     }
 }
+
+/// The repository with the latency and failure of the AppDB calls recorded by the operation class, i.e: select,
+/// insert, update, delete, see: dependencies::instrument_dep
+struct InstrumentedRepository<T> {
+    inner: Box<dyn AsyncRepository<T>>,
+}
+
+impl<T> InstrumentedRepository<T>
+where
+    T: 'static + Send + Sync,
+{
+    fn wrap(inner: Box<dyn AsyncRepository<T>>) -> Box<dyn AsyncRepository<T>> {
+        Box::new(InstrumentedRepository { inner })
+    }
+}
+
+#[async_trait]
+impl<T> AsyncRepository<T> for InstrumentedRepository<T>
+where
+    T: 'static + Send + Sync,
+{
+    async fn select(&self, param: T, page: PageRequest) -> Result<(PageResponse, Vec<T>), Error> {
+        instrument_dep(DEP_APPDB, "select", self.inner.select(param, page)).await
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<T, Error> {
+        instrument_dep(DEP_APPDB, "select", self.inner.select_by_id(id, status)).await
+    }
+
+    async fn insert(&self, param: T) -> Result<i64, Error> {
+        instrument_dep(DEP_APPDB, "insert", self.inner.insert(param)).await
+    }
+
+    async fn update(&self, param: T) -> Result<i64, Error> {
+        instrument_dep(DEP_APPDB, "update", self.inner.update(param)).await
+    }
+
+    async fn update_fields(&self, param: T, update_fields: &[&str]) -> Result<i64, Error> {
+        instrument_dep(DEP_APPDB, "update", self.inner.update_fields(param, update_fields)).await
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        instrument_dep(DEP_APPDB, "delete", self.inner.delete_all()).await
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        instrument_dep(DEP_APPDB, "delete", self.inner.delete_by_id(id)).await
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        instrument_dep(DEP_APPDB, "update", self.inner.set_status(id, status)).await
+    }
+
+    async fn get_or_insert(&self, key_column: &str, key: &str, param: T) -> Result<(i64, bool), Error> {
+        instrument_dep(DEP_APPDB, "insert", self.inner.get_or_insert(key_column, key, param)).await
+    }
+}
+
+/// Assert the entity is persistable by the dynamic store macros, i.e: all the fields (including the none by
+/// default) are mapped into the SQL columns, all the entities must be registered into the test below.
+///
+/// Notice: The type of the none field is probed by deserializing the scalar candidates, as the serialized none
+/// is always null, e.g: the Option<Vec<String>> only accepts the array.
+#[cfg(test)]
+pub fn assert_entity_persistable<T>()
+where
+    T: PersistableBean + Default + serde::de::DeserializeOwned,
+{
+    let type_name = std::any::type_name::<T>();
+    if let Err(e) = to_persistable_fields(&T::default(), type_name) {
+        panic!("The entity is not persistable. {}", e);
+    }
+    let fields = match serde_json::to_value(T::default()).unwrap() {
+        Value::Object(fields) => fields,
+        _ => panic!("The entity '{}' must be serialized as the object", type_name),
+    };
+    let candidates = [
+        Value::from("botwaf"),
+        Value::from("2024-01-01T00:00:00Z"),
+        Value::from(0),
+        Value::from(0.5),
+        Value::from(true),
+    ];
+    for (key, _) in fields
+        .iter()
+        .filter(|(key, value)| value.is_null() && !T::TRANSIENT_FIELDS.contains(&key.as_str()))
+    {
+        let mut errors = Vec::new();
+        let accepted = candidates.iter().any(|candidate| {
+            let mut probe = fields.to_owned();
+            probe.insert(key.to_owned(), candidate.to_owned());
+            match serde_json::from_value::<T>(Value::Object(probe)) {
+                Ok(_) => true,
+                // The unit enum is persisted as the string.
+                Err(e) if e.to_string().contains("unknown variant") => true,
+                Err(e) => {
+                    errors.push(e.to_string());
+                    false
+                }
+            }
+        });
+        assert!(
+            accepted,
+            "The field '{}' of entity '{}' is not persistable (neither bool, number nor string), it should be mapped or declared as the transient field. {:?}",
+            key, type_name, errors
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use botwaf_types::{
+        modules::modsec::{data_file::DataFile, replay::ReplayResult, rule_version::ModSecRuleVersion},
+        sys::{bootstrap::Bootstrap, dead_letter::DeadLetter, signing_key::SigningKey, user::User},
+        BaseBean,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    struct TestAddress {
+        city: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct TestTaggedBean {
+        #[serde(flatten)]
+        base: BaseBean,
+        name: Option<String>,
+        score: Option<f64>,
+        tags: Option<Vec<String>>,
+    }
+
+    impl Default for TestTaggedBean {
+        fn default() -> Self {
+            TestTaggedBean {
+                base: BaseBean::new_empty(),
+                name: None,
+                score: None,
+                tags: None,
+            }
+        }
+    }
+
+    impl PersistableBean for TestTaggedBean {}
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    struct TestNestedBean {
+        name: Option<String>,
+        address: Option<TestAddress>,
+    }
+
+    impl PersistableBean for TestNestedBean {}
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    struct TestTransientBean {
+        name: Option<String>,
+        address: Option<TestAddress>,
+    }
+
+    impl PersistableBean for TestTransientBean {
+        const TRANSIENT_FIELDS: &'static [&'static str] = &["address"];
+    }
+
+    #[test]
+    fn test_all_entities_persistable() {
+        assert_entity_persistable::<User>();
+        assert_entity_persistable::<Bootstrap>();
+        assert_entity_persistable::<DeadLetter>();
+        assert_entity_persistable::<SigningKey>();
+        assert_entity_persistable::<DataFile>();
+        assert_entity_persistable::<ReplayResult>();
+        assert_entity_persistable::<ModSecRuleVersion>();
+    }
+
+    #[test]
+    fn test_to_persistable_fields_rejects_vector_field() {
+        let bean = TestTaggedBean {
+            name: Some(String::from("botwaf")),
+            score: Some(0.5),
+            tags: Some(vec![String::from("sqli")]),
+            ..Default::default()
+        };
+        let err = to_persistable_fields(&bean, "test_tagged").unwrap_err();
+        let err = err.downcast_ref::<UnsupportedFieldTypeError>().unwrap();
+        assert_eq!(err.field, "tags");
+        assert_eq!(err.json_type, "array");
+
+        // The none vector is null, which is still persistable.
+        let bean = TestTaggedBean { tags: None, ..bean };
+        let fields = to_persistable_fields(&bean, "test_tagged").unwrap();
+        assert_eq!(fields.get("score"), Some(&Value::from(0.5)));
+    }
+
+    #[test]
+    fn test_to_persistable_fields_rejects_nested_struct() {
+        let bean = TestNestedBean {
+            name: Some(String::from("botwaf")),
+            address: Some(TestAddress::default()),
+        };
+        let err = to_persistable_fields(&bean, "test_nested").unwrap_err();
+        let err = err.downcast_ref::<UnsupportedFieldTypeError>().unwrap();
+        assert_eq!((err.field.as_str(), err.json_type), ("address", "object"));
+        assert!(err.to_string().contains("'address'"));
+    }
+
+    #[test]
+    fn test_to_persistable_fields_excludes_transient_fields() {
+        let bean = TestTransientBean {
+            name: Some(String::from("botwaf")),
+            address: Some(TestAddress::default()),
+        };
+        let fields = to_persistable_fields(&bean, "test_transient").unwrap();
+        assert_eq!(fields.keys().collect::<Vec<_>>(), vec!["name"]);
+        assert_entity_persistable::<TestTransientBean>();
+    }
+
+    #[test]
+    fn test_to_update_fields_clears_named_field_to_null() {
+        let mut base = BaseBean::new_with_id(Some(1));
+        base.update_by = Some(String::from("admin"));
+        let bean = TestTaggedBean {
+            base,
+            name: None,
+            score: Some(0.5),
+            tags: None,
+        };
+        let fields = to_persistable_fields(&bean, "test_tagged").unwrap();
+
+        // The non-empty heuristic never clears the name.
+        let updated = to_update_fields(fields.to_owned(), None, "test_tagged").unwrap();
+        assert!(!updated.contains_key("name"));
+        assert_eq!(updated.get("score"), Some(&Value::from(0.5)));
+
+        // The explicit null of the named field is kept, and the unspecified non-empty score is left unchanged.
+        let updated = to_update_fields(fields, Some(&["name"]), "test_tagged").unwrap();
+        assert_eq!(updated.get("name"), Some(&Value::Null));
+        assert!(!updated.contains_key("score"));
+        assert!(!updated.contains_key("create_time"));
+        assert_eq!(updated.get("update_by"), Some(&Value::from("admin")));
+    }
+
+    #[test]
+    fn test_to_update_fields_rejects_unknown_field() {
+        let fields = to_persistable_fields(&TestTransientBean::default(), "test_transient").unwrap();
+        for field in ["nmae", "address", "id"] {
+            let err = to_update_fields(fields.to_owned(), Some(&[field]), "test_transient").unwrap_err();
+            assert_eq!(err.downcast_ref::<UnknownUpdateFieldError>().unwrap().field, field);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The field 'tags' of entity")]
+    fn test_assert_entity_persistable_vector_field() {
+        assert_entity_persistable::<TestTaggedBean>();
+    }
+
+    #[test]
+    #[should_panic(expected = "The field 'address' of entity")]
+    fn test_assert_entity_persistable_nested_struct() {
+        assert_entity_persistable::<TestNestedBean>();
+    }
+}
@@ -22,7 +22,7 @@ use super::AsyncRepository;
 use crate::config::config::MongoAppDBProperties;
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_types::{PageRequest, PageResponse};
+use botwaf_types::{PageRequest, PageResponse, RecordStatus};
 use mongodb::options::{ReadConcern, WriteConcern};
 use mongodb::{options::ClientOptions, Client, Database};
 use std::any::Any;
@@ -72,7 +72,7 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for MongoRepository<T> {
         unimplemented!("select not implemented for MongoRepository")
     }
 
-    async fn select_by_id(&self, id: i64) -> Result<T, Error> {
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<T, Error> {
         unimplemented!("select_by_id not implemented for MongoRepository")
     }
 
@@ -91,6 +91,10 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for MongoRepository<T> {
     async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
         unimplemented!("delete_by_id not implemented for MongoRepository")
     }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        unimplemented!("set_status not implemented for MongoRepository")
+    }
 }
 
 #[macro_export]
@@ -114,6 +118,9 @@ macro_rules! dynamic_mongo_query {
         if let Some(id) = $bean.base.id {
             filter.insert("id", id);
         }
+        if let Some(status) = $bean.base.status {
+            filter.insert("status", status);
+        }
 
         let options = mongodb::options::FindOptions::builder()
             .skip($page.get_offset() as u64)
@@ -178,6 +185,11 @@ macro_rules! dynamic_mongo_insert {
 #[macro_export]
 macro_rules! dynamic_mongo_update {
     ($bean:expr, $collection:expr) => {
+        $crate::dynamic_mongo_update!($bean, $collection, None)
+    };
+    // The partial update of the named fields (Some(&[..])) including the explicit null, versus the non-empty
+    // fields (None), see: crate::store::is_updated_field()
+    ($bean:expr, $collection:expr, $update_fields:expr) => {
         {
             use mongodb::bson::{doc, to_bson, Bson};
             use crate::util::auths::SecurityContext;
@@ -204,9 +216,17 @@ macro_rules! dynamic_mongo_update {
                 }
             }
 
+            let update_fields: Option<&[&str]> = $update_fields;
+            if let Some(update_fields) = update_fields {
+                crate::store::validate_update_fields(
+                    obj.keys().map(|key| key.as_str()),
+                    update_fields,
+                    $collection.name(),
+                )?;
+            }
             let mut update_doc = mongodb::bson::Document::new();
             for (key, value) in obj.iter() {
-                if !is_empty_value(value) {
+                if crate::store::is_updated_field(key, is_empty_value(value), update_fields) {
                     update_doc.insert(key, value.clone());
                 }
             }
@@ -20,11 +20,13 @@
 
 use super::AsyncRepository;
 use crate::config::config::PostgresAppDBProperties;
+use crate::sys::store::users_postgresql::UserPostgresRepository;
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_types::{PageRequest, PageResponse};
+use botwaf_types::{PageRequest, PageResponse, RecordStatus};
+use futures::future::BoxFuture;
 use sqlx::migrate::MigrateDatabase;
-use sqlx::{PgPool, Postgres};
+use sqlx::{PgPool, Postgres, Transaction};
 use std::any::Any;
 use std::marker::PhantomData;
 
@@ -73,6 +75,15 @@ impl<T: Any + Send + Sync> PostgresRepository<T> {
     }
 
     async fn init_migration(pool: PgPool) -> PgPool {
+        // Notice: Refuse to migrate the unique name/email keys until the conflicted users are resolved.
+        let conflicts = UserPostgresRepository::report_identity_conflicts(&pool).await;
+        if !conflicts.is_empty() {
+            panic!(
+                "Refused to migrate, found the users conflicted case-insensitively, please resolve them first (e.g: merge into one user and delete the others): {}",
+                conflicts.join("; ")
+            );
+        }
+
         let results = sqlx::migrate!("../../tooling/deploy/migrations").run(&pool).await;
         tracing::info!("Migration result: {:?}", results);
         match results {
@@ -87,6 +98,19 @@ impl<T: Any + Send + Sync> PostgresRepository<T> {
     pub fn get_pool(&self) -> &PgPool {
         &self.pool
     }
+
+    /// Run the multi-step operations within a transaction, which is committed if succeeded, otherwise rolled
+    /// back (on dropped), e.g: the get-or-create of the users on the OAuth2 callbacks.
+    pub async fn with_transaction<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: for<'c> FnOnce(&'c mut Transaction<'static, Postgres>) -> BoxFuture<'c, Result<R, Error>> + Send,
+        R: Send,
+    {
+        let mut tx = self.pool.begin().await?;
+        let result = f(&mut tx).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 }
 
 #[allow(unused)]
@@ -96,7 +120,7 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for PostgresRepository<T> {
         unimplemented!("select not implemented for PostgresRepository")
     }
 
-    async fn select_by_id(&self, id: i64) -> Result<T, Error> {
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<T, Error> {
         unimplemented!("select_by_id not implemented for PostgresRepository")
     }
 
@@ -116,21 +140,29 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for PostgresRepository<T> {
     async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
         unimplemented!("delete_by_id not implemented for PostgresRepository")
     }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        unimplemented!("set_status not implemented for PostgresRepository")
+    }
 }
 
+/// The dynamic paging query by the non-empty fields of the bean, the soft deleted (i.e: del_flag != 0) records
+/// are always excluded, unless explicitly included (e.g: the admin/audit views), e.g:
+/// dynamic_postgres_query!(user, "sys_user", pool, "update_time", page, include_deleted = true, User)
 #[macro_export]
 macro_rules! dynamic_postgres_query {
-    ($bean:expr, $table:expr, $pool:expr, $order_by:expr, $page:expr, $($t:ty),+) => {
+    ($bean:expr, $table:expr, $pool:expr, $order_by:expr, $page:expr, include_deleted = $include_deleted:expr, $($t:ty),+) => {
         {
-            use chrono::{DateTime, Utc};
+            use botwaf_types::datetime::UtcDateTime;
             use botwaf_utils::types::GenericValue;
             // Notice:
             // 1. (SQLite) Because the ORM library is not used for the time being, the fields are dynamically
             // parsed based on serde_json, so the #[serde(rename="xx")] annotation is effective.
             // 2. (MongoDB) The underlying BSON serialization is also based on serde, so using #[serde(rename="xx")] is also valid
             // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
-            let serialized = serde_json::to_value(&$bean).unwrap();
-            let obj = serialized.as_object().unwrap();
+            // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+            let serialized = crate::store::to_persistable_fields(&$bean, $table)?;
+            let obj = &serialized;
             let mut fields = Vec::new();
             let mut params = Vec::new();
             let mut index = 0;
@@ -143,8 +175,7 @@ macro_rules! dynamic_postgres_query {
                         // e.g: "SELECT COUNT(1) as count FROM my_table WHERE create_time = $1 AND update_time = $2"
                         fields.push(format!("{} = ${}", key, index));
                         if key == "create_time" || key == "update_time" {
-                            let dt = DateTime::parse_from_rfc3339(v)?;
-                            params.push(GenericValue::DateTime(dt.with_timezone(&Utc)));
+                            params.push(GenericValue::DateTime(UtcDateTime::parse(v)?.0));
                         } else {
                             params.push(GenericValue::String(v.to_string()));
                         }
@@ -152,15 +183,25 @@ macro_rules! dynamic_postgres_query {
                 }
             }
             if let Some(id) = $bean.base.id {
-                fields.push("id = ?".to_string());
+                index += 1;
+                fields.push(format!("id = ${}", index));
                 params.push(GenericValue::Int64(id));
             }
+            if let Some(status) = $bean.base.status {
+                index += 1;
+                fields.push(format!("status = ${}", index));
+                params.push(GenericValue::Int32(status));
+            }
+            if !$include_deleted {
+                fields.push("del_flag = 0".to_string());
+            }
             let where_clause = if fields.is_empty() {
                 "1=1".to_string()
             } else {
                 fields.join(" AND ")
             };
             // Queries to get total count.
+            tracing::debug!("Dynamic query of '{}' with the columns: {:?}", $table, fields);
             let total_query = format!("SELECT COUNT(1) FROM {} WHERE {}", $table, where_clause);
             use sqlx::Row;
             let mut total_operator = sqlx::query(&total_query);
@@ -169,6 +210,8 @@ macro_rules! dynamic_postgres_query {
                     total_operator = total_operator.bind(v);
                 } else if let GenericValue::Int64(v) = param {
                     total_operator = total_operator.bind(v);
+                } else if let GenericValue::Int32(v) = param {
+                    total_operator = total_operator.bind(v);
                 } else if let GenericValue::Float64(v) = param {
                     total_operator = total_operator.bind(v);
                 } else if let GenericValue::String(v) = param {
@@ -188,6 +231,8 @@ macro_rules! dynamic_postgres_query {
                     operator = operator.bind(v);
                 } else if let GenericValue::Int64(v) = param {
                     operator = operator.bind(v);
+                } else if let GenericValue::Int32(v) = param {
+                    operator = operator.bind(v);
                 } else if let GenericValue::Float64(v) = param {
                     operator = operator.bind(v);
                 } else if let GenericValue::String(v) = param {
@@ -210,13 +255,16 @@ macro_rules! dynamic_postgres_query {
             }
         }
     };
+    ($bean:expr, $table:expr, $pool:expr, $order_by:expr, $page:expr, $($t:ty),+) => {
+        $crate::dynamic_postgres_query!($bean, $table, $pool, $order_by, $page, include_deleted = false, $($t),+)
+    };
 }
 
 #[macro_export]
 macro_rules! dynamic_postgres_insert {
     ($bean:expr, $table:expr, $pool:expr) => {
         {
-            use chrono::{DateTime, Utc};
+            use botwaf_types::datetime::UtcDateTime;
             use botwaf_utils::types::GenericValue;
             use crate::util::auths::SecurityContext;
 
@@ -229,8 +277,9 @@ macro_rules! dynamic_postgres_insert {
             // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
             $bean.base.pre_insert(insert_by).await;
 
-            let serialized = serde_json::to_value($bean).unwrap();
-            let obj = serialized.as_object().unwrap();
+            // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+            let serialized = crate::store::to_persistable_fields(&$bean, $table)?;
+            let obj = &serialized;
 
             let mut fields = Vec::new();
             let mut values = Vec::new();
@@ -240,28 +289,27 @@ macro_rules! dynamic_postgres_insert {
                     if value.is_boolean() {
                         let v = value.as_bool().unwrap();
                         fields.push(key.as_str());
-                        values.push("?");
+                        values.push(format!("${}", values.len() + 1));
                         params.push(GenericValue::Bool(v));
                     } else if value.is_number() {
                         if value.is_i64() {
                             let v = value.as_i64().unwrap();
                             fields.push(key.as_str());
-                            values.push("?");
+                            values.push(format!("${}", values.len() + 1));
                             params.push(GenericValue::Int64(v));
                         } else if value.is_f64() {
                             let v = value.as_f64().unwrap();
                             fields.push(key.as_str());
-                            values.push("?");
+                            values.push(format!("${}", values.len() + 1));
                             params.push(GenericValue::Float64(v));
                         }
                     } else if value.is_string() {
                         let v = value.as_str().unwrap_or("");
                         if !v.is_empty() {
                             fields.push(key.as_str());
-                            values.push("?");
+                            values.push(format!("${}", values.len() + 1));
                             if key == "create_time" || key == "update_time" {
-                                let dt = DateTime::parse_from_rfc3339(v)?;
-                                params.push(GenericValue::DateTime(dt.with_timezone(&Utc)));
+                                params.push(GenericValue::DateTime(UtcDateTime::parse(v)?.0));
                             } else {
                                 params.push(GenericValue::String(v.to_string()));
                             }
@@ -273,9 +321,11 @@ macro_rules! dynamic_postgres_insert {
                 return Ok(-1);
             }
 
-            // e.g: 'INSERT INTO ch_ethereum_checkpoint ( ID, last_processed_block ) VALUES ( 2, 12345 ) ON CONFLICT ( ID ) DO UPDATE SET update_time = CURRENT_TIMESTAMP(11) RETURNING ID;'
+            // e.g: 'INSERT INTO ch_ethereum_checkpoint ( ID, last_processed_block ) VALUES ( $1, $2 ) ON CONFLICT ( ID ) DO UPDATE SET update_time = now() RETURNING ID;'
+            // Notice: The now() is the transaction start time with timestamptz, which is stored as UTC.
+            tracing::debug!("Dynamic insert into '{}' with the columns: {:?}", $table, fields);
             let query = format!("INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (id) DO UPDATE SET {} RETURNING id",
-                $table, fields.join(","), values.join(","), "update_time = CURRENT_TIMESTAMP(13)");
+                $table, fields.join(","), values.join(","), "update_time = now()");
 
             let mut operator = sqlx::query(&query);
             for param in params.iter() {
@@ -307,10 +357,18 @@ macro_rules! dynamic_postgres_insert {
 #[macro_export]
 macro_rules! dynamic_postgres_update {
     ($bean:expr, $table:expr, $pool:expr) => {
+        $crate::dynamic_postgres_update!($bean, $table, $pool, None)
+    };
+    // The partial update of the named fields (Some(&[..])) including the explicit null, versus the non-empty
+    // fields (None), see: crate::store::is_updated_field()
+    ($bean:expr, $table:expr, $pool:expr, $update_fields:expr) => {
         {
+            use botwaf_types::datetime::UtcDateTime;
             use botwaf_utils::types::GenericValue;
             use crate::util::auths::SecurityContext;
 
+            // The expected version before increased for optimistic locking.
+            let expected_version = $bean.base.version;
             let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
             $bean.base.pre_update(update_by).await;
 
@@ -320,27 +378,39 @@ macro_rules! dynamic_postgres_update {
             // 2. (MongoDB) The underlying BSON serialization is also based on serde, so using #[serde(rename="xx")] is also valid
             // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
             let id = $bean.base.id.unwrap();
-            let serialized = serde_json::to_value($bean).unwrap();
-            let obj = serialized.as_object().unwrap();
+            // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+            let serialized = crate::store::to_update_fields(
+                crate::store::to_persistable_fields(&$bean, $table)?,
+                $update_fields,
+                $table,
+            )?;
+            let obj = &serialized;
 
+            // Notice: The placeholders are indexed by the params, as the explicit null is set without the param.
             let mut fields = Vec::new();
             let mut params = Vec::new();
             for (key, value) in obj {
-                if !value.is_null() {
-                    if value.is_boolean() {
-                        let v = value.as_bool().unwrap();
-                        fields.push(format!("{} = ?", key));
-                        params.push(GenericValue::Bool(v));
-                    } else if value.is_number() {
-                        let v = value.as_i64().unwrap();
-                        fields.push(format!("{} = ?", key));
-                        params.push(GenericValue::Int64(v));
-                    } else if value.is_string() {
-                        let v = value.as_str().unwrap_or("");
-                        if !v.is_empty() {
-                            fields.push(format!("{} = ?", key));
-                            params.push(GenericValue::String(v.to_string()));
-                        }
+                if value.is_null() {
+                    // The explicit null of the partial update.
+                    fields.push(format!("{} = NULL", key));
+                } else if value.is_boolean() {
+                    let v = value.as_bool().unwrap();
+                    fields.push(format!("{} = ${}", key, params.len() + 1));
+                    params.push(GenericValue::Bool(v));
+                } else if value.is_number() {
+                    fields.push(format!("{} = ${}", key, params.len() + 1));
+                    // The persistable numbers are either i64 or f64, see: crate::store::to_persistable_fields()
+                    params.push(match value.as_i64() {
+                        Some(v) => GenericValue::Int64(v),
+                        None => GenericValue::Float64(value.as_f64().unwrap_or_default()),
+                    });
+                } else if value.is_string() {
+                    let v = value.as_str().unwrap_or("");
+                    fields.push(format!("{} = ${}", key, params.len() + 1));
+                    if key == "create_time" || key == "update_time" {
+                        params.push(GenericValue::DateTime(UtcDateTime::parse(v)?.0));
+                    } else {
+                        params.push(GenericValue::String(v.to_string()));
                     }
                 }
             }
@@ -348,23 +418,53 @@ macro_rules! dynamic_postgres_update {
                 return Ok(0);
             }
 
-            let query = format!("UPDATE {} SET {} WHERE id = ?", $table, fields.join(", "));
+            tracing::debug!("Dynamic update of '{}' with the columns: {:?}", $table, fields);
+            let id_index = params.len() + 1;
+            let query = match expected_version {
+                Some(_) => format!(
+                    "UPDATE {} SET {} WHERE id = ${} AND version = ${}",
+                    $table,
+                    fields.join(", "),
+                    id_index,
+                    id_index + 1
+                ),
+                // Compatible with the legacy clients without version, which is always increase the version.
+                None => format!(
+                    "UPDATE {} SET {}, version = COALESCE(version, 0) + 1 WHERE id = ${}",
+                    $table,
+                    fields.join(", "),
+                    id_index
+                ),
+            };
             let mut operator = sqlx::query(&query);
             for param in params.iter() {
                 if let GenericValue::Bool(v) = param {
                     operator = operator.bind(v);
                 } else if let GenericValue::Int64(v) = param {
                     operator = operator.bind(v);
+                } else if let GenericValue::Float64(v) = param {
+                    operator = operator.bind(v);
                 } else if let GenericValue::String(v) = param {
                     operator = operator.bind(v);
+                } else if let GenericValue::DateTime(v) = param {
+                    operator = operator.bind(v);
                 }
             }
             operator = operator.bind(id);
+            if let Some(version) = expected_version {
+                operator = operator.bind(version);
+            }
 
             match operator.execute($pool).await {
                 std::result::Result::Ok(result) => {
                     if result.rows_affected() > 0 {
                         return Ok(id);
+                    } else if let Some(version) = expected_version {
+                        return Err(Error::from(crate::store::VersionConflictError {
+                            table: $table.to_string(),
+                            id,
+                            version,
+                        }));
                     } else {
                         return Ok(-1);
                     }
@@ -20,10 +20,12 @@
 
 use super::AsyncRepository;
 use crate::config::config::SqliteAppDBProperties;
+use crate::sys::store::users_sqlite::UserSQLiteRepository;
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_types::{PageRequest, PageResponse};
-use sqlx::{migrate::MigrateDatabase, Pool, Sqlite, SqlitePool};
+use botwaf_types::{PageRequest, PageResponse, RecordStatus};
+use futures::future::BoxFuture;
+use sqlx::{migrate::MigrateDatabase, Pool, Sqlite, SqlitePool, Transaction};
 use std::any::Any;
 use std::fs;
 use std::marker::PhantomData;
@@ -99,6 +101,15 @@ impl<T: Any + Send + Sync> SQLiteRepository<T> {
         //   }
         // }
 
+        // Notice: Refuse to migrate the unique name/email keys until the conflicted users are resolved.
+        let conflicts = UserSQLiteRepository::report_identity_conflicts(&pool).await;
+        if !conflicts.is_empty() {
+            panic!(
+                "Refused to migrate, found the users conflicted case-insensitively, please resolve them first (e.g: merge into one user and delete the others): {}",
+                conflicts.join("; ")
+            );
+        }
+
         let results = sqlx::migrate!("../../tooling/deploy/migrations").run(&pool).await;
         debug!("Migration result: {:?}", results);
         match results {
@@ -113,6 +124,19 @@ impl<T: Any + Send + Sync> SQLiteRepository<T> {
     pub fn get_pool(&self) -> &SqlitePool {
         &self.pool
     }
+
+    /// Run the multi-step operations within a transaction, which is committed if succeeded, otherwise rolled
+    /// back (on dropped), e.g: the get-or-create of the users on the OAuth2 callbacks.
+    pub async fn with_transaction<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: for<'c> FnOnce(&'c mut Transaction<'static, Sqlite>) -> BoxFuture<'c, Result<R, Error>> + Send,
+        R: Send,
+    {
+        let mut tx = self.pool.begin().await?;
+        let result = f(&mut tx).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 }
 
 #[allow(unused)]
@@ -122,7 +146,7 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for SQLiteRepository<T> {
         unimplemented!("select not implemented for SQLiteRepository")
     }
 
-    async fn select_by_id(&self, id: i64) -> Result<T, Error> {
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<T, Error> {
         unimplemented!("select_by_id not implemented for SQLiteRepository")
     }
 
@@ -142,6 +166,10 @@ impl<T: Any + Send + Sync> AsyncRepository<T> for SQLiteRepository<T> {
     async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
         unimplemented!("delete_by_id not implemented for SQLiteRepository")
     }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        unimplemented!("set_status not implemented for SQLiteRepository")
+    }
 }
 
 #[macro_export]
@@ -153,8 +181,9 @@ macro_rules! dynamic_sqlite_query {
               // parsed based on serde_json, so the #[serde(rename="xx")] annotation is effective.
               // 2. (MongoDB) The underlying BSON serialization is also based on serde, so using #[serde(rename="xx")] is also valid
               // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
-              let serialized = serde_json::to_value(&$bean).unwrap();
-              let obj = serialized.as_object().unwrap();
+              // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+              let serialized = crate::store::to_persistable_fields(&$bean, $table)?;
+              let obj = &serialized;
 
               let mut fields = Vec::new();
               let mut params = Vec::new();
@@ -171,6 +200,10 @@ macro_rules! dynamic_sqlite_query {
                   fields.push("id = ?".to_string());
                   params.push(id.to_string());
               }
+              if let Some(status) = $bean.base.status {
+                  fields.push("status = ?".to_string());
+                  params.push(status.to_string());
+              }
               let where_clause = if fields.is_empty() {
                   "1=1".to_string()
               } else {
@@ -178,6 +211,7 @@ macro_rules! dynamic_sqlite_query {
               };
 
               // Queries to get total count.
+              tracing::debug!("Dynamic query of '{}' with the columns: {:?}", $table, fields);
               let total_query = format!("SELECT COUNT(1) FROM {} WHERE {}", $table, where_clause);
               use sqlx::Row;
               let total_count = sqlx::query(&total_query)
@@ -227,8 +261,9 @@ macro_rules! dynamic_sqlite_insert {
             // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
             $bean.base.pre_insert(insert_by).await;
 
-            let serialized = serde_json::to_value($bean).unwrap();
-            let obj = serialized.as_object().unwrap();
+            // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+            let serialized = crate::store::to_persistable_fields(&$bean, $table)?;
+            let obj = &serialized;
 
             let mut fields = Vec::new();
             let mut values = Vec::new();
@@ -241,10 +276,13 @@ macro_rules! dynamic_sqlite_insert {
                         values.push("?");
                         params.push(GenericValue::Bool(v));
                     } else if value.is_number() {
-                        let v = value.as_i64().unwrap();
                         fields.push(key.as_str());
                         values.push("?");
-                        params.push(GenericValue::Int64(v));
+                        // The persistable numbers are either i64 or f64, see: crate::store::to_persistable_fields()
+                        params.push(match value.as_i64() {
+                            Some(v) => GenericValue::Int64(v),
+                            None => GenericValue::Float64(value.as_f64().unwrap_or_default()),
+                        });
                     } else if value.is_string() {
                         let v = value.as_str().unwrap_or("");
                         if !v.is_empty() {
@@ -259,6 +297,7 @@ macro_rules! dynamic_sqlite_insert {
                 return Ok(-1);
             }
 
+            tracing::debug!("Dynamic insert into '{}' with the columns: {:?}", $table, fields);
             let query = format!("INSERT OR IGNORE INTO {} ({}) VALUES ({})", $table, fields.join(","), values.join(","));
 
             let mut operator = sqlx::query(&query);
@@ -267,6 +306,8 @@ macro_rules! dynamic_sqlite_insert {
                     operator = operator.bind(v);
                 } else if let GenericValue::Int64(v) = param {
                     operator = operator.bind(v);
+                } else if let GenericValue::Float64(v) = param {
+                    operator = operator.bind(v);
                 } else if let GenericValue::String(v) = param {
                     operator = operator.bind(v);
                 }
@@ -289,10 +330,17 @@ macro_rules! dynamic_sqlite_insert {
 #[macro_export]
 macro_rules! dynamic_sqlite_update {
     ($bean:expr, $table:expr, $pool:expr) => {
+        $crate::dynamic_sqlite_update!($bean, $table, $pool, None)
+    };
+    // The partial update of the named fields (Some(&[..])) including the explicit null, versus the non-empty
+    // fields (None), see: crate::store::is_updated_field()
+    ($bean:expr, $table:expr, $pool:expr, $update_fields:expr) => {
         {
             use botwaf_utils::types::GenericValue;
             use crate::util::auths::SecurityContext;
 
+            // The expected version before increased for optimistic locking.
+            let expected_version = $bean.base.version;
             let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
             $bean.base.pre_update(update_by).await;
 
@@ -302,51 +350,77 @@ macro_rules! dynamic_sqlite_update {
             // 2. (MongoDB) The underlying BSON serialization is also based on serde, so using #[serde(rename="xx")] is also valid
             // TODO: It is recommended to use an ORM framework, see: https://github.com/diesel-rs/diesel
             let id = $bean.base.id.unwrap();
-            let serialized = serde_json::to_value($bean).unwrap();
-            let obj = serialized.as_object().unwrap();
+            // Notice: The fields without SQL column mapping (e.g: arrays) are rejected instead of silently dropped.
+            let serialized = crate::store::to_update_fields(
+                crate::store::to_persistable_fields(&$bean, $table)?,
+                $update_fields,
+                $table,
+            )?;
+            let obj = &serialized;
 
             let mut fields = Vec::new();
             let mut params = Vec::new();
             for (key, value) in obj {
-                if !value.is_null() {
-                    if value.is_boolean() {
-                        let v = value.as_bool().unwrap();
-                        fields.push(format!("{} = ?", key));
-                        params.push(GenericValue::Bool(v));
-                    } else if value.is_number() {
-                        let v = value.as_i64().unwrap();
-                        fields.push(format!("{} = ?", key));
-                        params.push(GenericValue::Int64(v));
-                    } else if value.is_string() {
-                        let v = value.as_str().unwrap_or("");
-                        if !v.is_empty() {
-                            fields.push(format!("{} = ?", key));
-                            params.push(GenericValue::String(v.to_string()));
-                        }
-                    }
+                if value.is_null() {
+                    // The explicit null of the partial update.
+                    fields.push(format!("{} = NULL", key));
+                } else if value.is_boolean() {
+                    let v = value.as_bool().unwrap();
+                    fields.push(format!("{} = ?", key));
+                    params.push(GenericValue::Bool(v));
+                } else if value.is_number() {
+                    fields.push(format!("{} = ?", key));
+                    // The persistable numbers are either i64 or f64, see: crate::store::to_persistable_fields()
+                    params.push(match value.as_i64() {
+                        Some(v) => GenericValue::Int64(v),
+                        None => GenericValue::Float64(value.as_f64().unwrap_or_default()),
+                    });
+                } else if value.is_string() {
+                    fields.push(format!("{} = ?", key));
+                    params.push(GenericValue::String(value.as_str().unwrap_or("").to_string()));
                 }
             }
             if fields.is_empty() {
                 return Ok(0);
             }
 
-            let query = format!("UPDATE {} SET {} WHERE id = ?", $table, fields.join(", "));
+            tracing::debug!("Dynamic update of '{}' with the columns: {:?}", $table, fields);
+            let query = match expected_version {
+                Some(_) => format!("UPDATE {} SET {} WHERE id = ? AND version = ?", $table, fields.join(", ")),
+                // Compatible with the legacy clients without version, which is always increase the version.
+                None => format!(
+                    "UPDATE {} SET {}, version = COALESCE(version, 0) + 1 WHERE id = ?",
+                    $table,
+                    fields.join(", ")
+                ),
+            };
             let mut operator = sqlx::query(&query);
             for param in params.iter() {
                 if let GenericValue::Bool(v) = param {
                     operator = operator.bind(v);
                 } else if let GenericValue::Int64(v) = param {
                     operator = operator.bind(v);
+                } else if let GenericValue::Float64(v) = param {
+                    operator = operator.bind(v);
                 } else if let GenericValue::String(v) = param {
                     operator = operator.bind(v);
                 }
             }
             operator = operator.bind(id);
+            if let Some(version) = expected_version {
+                operator = operator.bind(version);
+            }
 
             match operator.execute($pool).await {
                 std::result::Result::Ok(result) => {
                     if result.rows_affected() > 0 {
                         return Ok(id);
+                    } else if let Some(version) = expected_version {
+                        return Err(Error::from(crate::store::VersionConflictError {
+                            table: $table.to_string(),
+                            id,
+                            version,
+                        }));
                     } else {
                         return Ok(-1);
                     }
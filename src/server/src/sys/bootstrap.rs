@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{config::config::AppConfig, store::RepositoryContainer, sys::identities, util::auths};
+use anyhow::{anyhow, Error};
+use arc_swap::ArcSwapOption;
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use botwaf_types::{
+    sys::{
+        bootstrap::{Bootstrap, BootstrapRequest, BootstrapResponse},
+        user::User,
+    },
+    BaseBean, PageRequest, RespBase,
+};
+use common_telemetry::{info, warn};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use std::{
+    env, fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::sync::Mutex;
+
+pub const BOOTSTRAP_ADMIN_PASSWORD_FILE_ENV: &str = "BOTWAF_BOOTSTRAP_ADMIN_PASSWORD_FILE";
+pub const BOOTSTRAP_ADMIN_USERNAME_ENV: &str = "BOTWAF_BOOTSTRAP_ADMIN_USERNAME";
+pub const DEFAULT_BOOTSTRAP_ADMIN_USERNAME: &str = "admin";
+const BOOTSTRAP_CREATE_BY: &str = "bootstrap";
+
+lazy_static! {
+    // The initial administrator granted by the completed bootstrap, see: auths::is_admin
+    static ref BOOTSTRAP_ADMIN: ArcSwapOption<String> = ArcSwapOption::empty();
+}
+
+#[derive(Debug)]
+pub enum BootstrapRejection {
+    // The bootstrap is completed, or never opened, e.g: not a fresh install or seeded by env.
+    Closed,
+    Throttled { retry_after_secs: u64 },
+    Invalid(String),
+    Internal(Error),
+}
+
+impl IntoResponse for BootstrapRejection {
+    fn into_response(self) -> Response {
+        match self {
+            BootstrapRejection::Closed => (
+                StatusCode::GONE,
+                Json(RespBase::errmsg("The bootstrap is completed or not available")),
+            )
+                .into_response(),
+            BootstrapRejection::Throttled { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(RespBase::errmsg("Too many bootstrap requests")),
+            )
+                .into_response(),
+            BootstrapRejection::Invalid(errmsg) => {
+                (StatusCode::BAD_REQUEST, Json(RespBase::errmsg(&errmsg))).into_response()
+            }
+            BootstrapRejection::Internal(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(RespBase::error(e))).into_response()
+            }
+        }
+    }
+}
+
+/// The non-interactive bootstrap for the automated installs, e.g: the password file mounted from a k8s secret.
+#[derive(Debug, Clone)]
+pub struct BootstrapSeed {
+    pub username: String,
+    pub password_file: String,
+}
+
+impl BootstrapSeed {
+    pub fn from_env() -> Option<Self> {
+        let password_file = env::var(BOOTSTRAP_ADMIN_PASSWORD_FILE_ENV)
+            .ok()
+            .filter(|f| !f.trim().is_empty())?;
+        let username = env::var(BOOTSTRAP_ADMIN_USERNAME_ENV)
+            .ok()
+            .filter(|u| !u.trim().is_empty())
+            .unwrap_or(DEFAULT_BOOTSTRAP_ADMIN_USERNAME.to_owned());
+        Some(Self {
+            username,
+            password_file,
+        })
+    }
+}
+
+/// The first-run bootstrap of the initial administrator, which is only open on a fresh install (i.e: no users
+/// and no admin-users configured) and permanently closed once completed by the persisted flag.
+pub struct BootstrapManager {
+    config: Arc<AppConfig>,
+    user_repo: Arc<Mutex<RepositoryContainer<User>>>,
+    bootstrap_repo: Arc<Mutex<RepositoryContainer<Bootstrap>>>,
+    seed: Option<BootstrapSeed>,
+    completed: AtomicBool,
+    // Serialize the bootstrap attempts, and the last attempt time of all clients for the global throttling.
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+impl BootstrapManager {
+    pub fn new(
+        config: &Arc<AppConfig>,
+        user_repo: Arc<Mutex<RepositoryContainer<User>>>,
+        bootstrap_repo: Arc<Mutex<RepositoryContainer<Bootstrap>>>,
+        seed: Option<BootstrapSeed>,
+    ) -> Self {
+        Self {
+            config: config.to_owned(),
+            user_repo,
+            bootstrap_repo,
+            seed,
+            completed: AtomicBool::new(false),
+            last_attempt: Mutex::new(None),
+        }
+    }
+
+    pub fn get_admin() -> Option<Arc<String>> {
+        BOOTSTRAP_ADMIN.load_full()
+    }
+
+    /// Load the persisted completed flag, and seed the initial administrator if configured by env.
+    pub async fn init(&self) -> Result<(), Error> {
+        if self.load_completed().await? {
+            return Ok(());
+        }
+        let seed = match &self.seed {
+            Some(seed) => seed,
+            None => return Ok(()),
+        };
+        if !self.is_fresh_install().await? {
+            warn!(
+                "Skip the bootstrap seeding of '{}', it's not a fresh install.",
+                seed.username
+            );
+            return Ok(());
+        }
+        let password = fs::read_to_string(&seed.password_file).map_err(|e| {
+            anyhow!(
+                "Failed to read the bootstrap password file '{}'. {}",
+                seed.password_file,
+                e
+            )
+        })?;
+        let param = BootstrapRequest {
+            username: seed.username.to_owned(),
+            password: Some(password.trim_end_matches(['\r', '\n']).to_owned()),
+            oidc_claims_sub: None,
+        };
+        let result = self.complete(param).await?;
+        info!("Seeded the bootstrap administrator '{}' from the env.", result.name);
+        Ok(())
+    }
+
+    /// Whether the bootstrap endpoint is open, it's never opened if disabled or seeded by env.
+    pub async fn is_open(&self) -> Result<bool, Error> {
+        if !self.config.auth.bootstrap.enabled || self.seed.is_some() || self.completed.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        if self.load_completed().await? {
+            return Ok(false);
+        }
+        self.is_fresh_install().await
+    }
+
+    pub async fn bootstrap(&self, param: BootstrapRequest) -> Result<BootstrapResponse, BootstrapRejection> {
+        if !self.config.auth.bootstrap.enabled || self.seed.is_some() || self.completed.load(Ordering::Acquire) {
+            return Err(BootstrapRejection::Closed);
+        }
+        let mut last_attempt = self.last_attempt.lock().await;
+        let min_interval = *self.config.auth.bootstrap.min_interval_secs;
+        if let Some(last) = *last_attempt {
+            let elapsed = last.elapsed();
+            if elapsed < min_interval {
+                let retry_after_secs = (min_interval - elapsed).as_secs().max(1);
+                return Err(BootstrapRejection::Throttled { retry_after_secs });
+            }
+        }
+        *last_attempt = Some(Instant::now());
+
+        match self.is_open().await {
+            Ok(true) => {}
+            Ok(false) => return Err(BootstrapRejection::Closed),
+            Err(e) => return Err(BootstrapRejection::Internal(e)),
+        }
+        if param.password.is_none() && param.oidc_claims_sub.is_none() {
+            return Err(BootstrapRejection::Invalid(
+                "Missing the password or oidc_claims_sub of the administrator".to_owned(),
+            ));
+        }
+        self.complete(param).await.map_err(BootstrapRejection::Internal)
+    }
+
+    async fn complete(&self, param: BootstrapRequest) -> Result<BootstrapResponse, Error> {
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(
+            None,
+            Some(BOOTSTRAP_CREATE_BY.to_owned()),
+            Some(BOOTSTRAP_CREATE_BY.to_owned()),
+        );
+        user.name = Some(param.username.to_owned());
+        // The password is stored as same as the saved users, i.e: the Argon2 hash of the login password.
+        user.password = param
+            .password
+            .as_deref()
+            .map(|password| auths::hash_stored_password(auths::hash_login_password(password).as_bytes()))
+            .transpose()?;
+        user.oidc_claims_sub = param.oidc_claims_sub.to_owned();
+        identities::normalize_user(&self.config, &mut user);
+        let username = user.name.to_owned().unwrap_or_default();
+        let uid = self.user_repo.lock().await.get(&self.config).insert(user).await?;
+
+        // Notice: The flag is persisted after the user is created, so a failure in between is retryable,
+        // since the users table is no longer empty the bootstrap is closed anyway.
+        let mut bootstrap = Bootstrap::default();
+        bootstrap.base = BaseBean::new_with_by(
+            None,
+            Some(BOOTSTRAP_CREATE_BY.to_owned()),
+            Some(BOOTSTRAP_CREATE_BY.to_owned()),
+        );
+        bootstrap.admin_name = Some(username.to_owned());
+        self.bootstrap_repo
+            .lock()
+            .await
+            .get(&self.config)
+            .insert(bootstrap)
+            .await?;
+
+        self.grant(&username);
+        info!("Completed the bootstrap of the administrator '{}'", username);
+        Ok(BootstrapResponse::new(uid, username))
+    }
+
+    async fn load_completed(&self) -> Result<bool, Error> {
+        let mut page = PageRequest::default();
+        page.limit = Some(1);
+        let (_, bootstraps) = self
+            .bootstrap_repo
+            .lock()
+            .await
+            .get(&self.config)
+            .select(Bootstrap::default(), page)
+            .await?;
+        match bootstraps.first().and_then(|b| b.admin_name.to_owned()) {
+            Some(admin_name) => {
+                self.grant(&admin_name);
+                Ok(true)
+            }
+            None => Ok(!bootstraps.is_empty()),
+        }
+    }
+
+    async fn is_fresh_install(&self) -> Result<bool, Error> {
+        // Notice: The configured admin users are considered as the existing administrators.
+        let admin_users = self.config.auth.admin_users.as_deref().unwrap_or_default();
+        if !admin_users.is_empty() {
+            return Ok(false);
+        }
+        let mut page = PageRequest::default();
+        page.limit = Some(1);
+        // Including the disabled users, the status is not filtered.
+        let (_, users) = self
+            .user_repo
+            .lock()
+            .await
+            .get(&self.config)
+            .select(User::default(), page)
+            .await?;
+        Ok(users.is_empty())
+    }
+
+    fn grant(&self, admin_name: &str) {
+        self.completed.store(true, Ordering::Release);
+        BOOTSTRAP_ADMIN.store(Some(Arc::new(admin_name.to_owned())));
+    }
+}
@@ -0,0 +1,538 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{
+    config::config::{AppConfig, AppDBType, DeadLetterProperties},
+    mgmt::apm::{
+        dependencies::{instrument_dep, DEP_WEBHOOK},
+        metrics::{BOTWAF_DEAD_LETTER_DROPPED_TOTAL, BOTWAF_DEAD_LETTER_REPLAYS_TOTAL, BOTWAF_DEAD_LETTER_TOTAL},
+    },
+    sys::store::{
+        dead_letters_mongo::DeadLetterMongoRepository, dead_letters_postgresql::DeadLetterPostgresRepository,
+        dead_letters_sqlite::DeadLetterSQLiteRepository, IDeadLetterRepository,
+    },
+};
+use anyhow::{anyhow, Error};
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use botwaf_types::{
+    datetime::UtcDateTime,
+    sys::{
+        dead_letter::{DeadLetter, DeadLetterState, ReplayDeadLetterResult},
+        event::{BotwafEvent, EventPayload},
+    },
+    BaseBean, PageRequest, PageResponse,
+};
+use botwaf_utils::httpclients;
+use common_telemetry::{info, warn};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+pub const DEAD_LETTER_KIND_WEBHOOK: &str = "WEBHOOK";
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ArcSwapOption<DeadLetterManager> = ArcSwapOption::empty();
+    static ref REPLAYER_MAP: RwLock<HashMap<String, Arc<dyn IDeadLetterReplayer>>> =
+        RwLock::new(register_builtin_replayers());
+}
+
+fn register_builtin_replayers() -> HashMap<String, Arc<dyn IDeadLetterReplayer>> {
+    let mut map: HashMap<String, Arc<dyn IDeadLetterReplayer>> = HashMap::new();
+    map.insert(DEAD_LETTER_KIND_WEBHOOK.to_owned(), Arc::new(WebhookReplayer {}));
+    map
+}
+
+/// The replayer of a dead letters kind, which re-enqueues the payload through the original pipeline.
+#[async_trait]
+pub trait IDeadLetterReplayer: Send + Sync {
+    async fn replay(&self, payload: &str) -> Result<(), Error>;
+}
+
+/// The webhook delivery of the versioned event payload, e.g: the fail-open budget and synthetic probe notifications.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    pub url: String,
+    pub body: serde_json::Value,
+}
+
+impl WebhookDelivery {
+    pub fn new(url: &str, event: BotwafEvent) -> Self {
+        WebhookDelivery {
+            url: url.to_owned(),
+            body: serde_json::to_value(EventPayload::new(event)).unwrap_or_default(),
+        }
+    }
+
+    /// Post the webhook, the non-success status is regarded as failed.
+    pub async fn deliver(&self, client: &reqwest::Client) -> Result<(), Error> {
+        instrument_dep(DEP_WEBHOOK, "post", async {
+            client
+                .post(&self.url)
+                .json(&self.body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deliver in the background, the failed delivery is written into the dead letters for replay.
+    pub fn spawn(self, client: reqwest::Client, subject: &str) {
+        let subject = subject.to_owned();
+        tokio::spawn(async move {
+            if let Err(e) = self.deliver(&client).await {
+                tracing::error!("Failed to notify the {}. cause: {}", subject, e);
+                DeadLetterManager::push(DEAD_LETTER_KIND_WEBHOOK, &self, &e.to_string(), 1);
+            }
+        });
+    }
+}
+
+struct WebhookReplayer {}
+
+#[async_trait]
+impl IDeadLetterReplayer for WebhookReplayer {
+    async fn replay(&self, payload: &str) -> Result<(), Error> {
+        let delivery: WebhookDelivery = serde_json::from_str(payload)?;
+        delivery.deliver(&httpclients::build_default()).await
+    }
+}
+
+enum WriterMessage {
+    DeadLetter(DeadLetter),
+    // Insert the pending dead letters, and then acknowledge.
+    Flush(oneshot::Sender<()>),
+}
+
+/// The persistent dead letters of the failed async works, the writing is best-effort with the bounded channel
+/// which never blocks the hot path, and the entries are replayed through the registered replayer of the kind.
+pub struct DeadLetterManager {
+    config: DeadLetterProperties,
+    repo: Arc<dyn IDeadLetterRepository>,
+    sender: mpsc::Sender<WriterMessage>,
+}
+
+impl DeadLetterManager {
+    pub fn new(config: &DeadLetterProperties, repo: Arc<dyn IDeadLetterRepository>) -> Self {
+        let (sender, receiver) = mpsc::channel(config.channel_size.max(1));
+        tokio::spawn(Self::run_writer(receiver, repo.clone()));
+        DeadLetterManager {
+            config: config.to_owned(),
+            repo,
+            sender,
+        }
+    }
+
+    pub async fn init(config: &AppConfig) -> Result<(), Error> {
+        if SINGLE_INSTANCE.load().is_some() {
+            return Ok(());
+        }
+        if !config.services.dead_letter.enabled {
+            info!("The dead letters is disabled, the failed async works are dropped.");
+            return Ok(());
+        }
+        let manager = Arc::new(Self::new(
+            &config.services.dead_letter,
+            Self::build_repository(config).await?,
+        ));
+        manager.start_purger();
+        SINGLE_INSTANCE.store(Some(manager));
+        Ok(())
+    }
+
+    pub fn get() -> Option<Arc<DeadLetterManager>> {
+        SINGLE_INSTANCE.load_full()
+    }
+
+    pub fn register_replayer(kind: &str, replayer: Arc<dyn IDeadLetterReplayer>) {
+        REPLAYER_MAP.write().unwrap().insert(kind.to_owned(), replayer);
+    }
+
+    /// Write the failed work into the dead letters if enabled, returns false if dropped.
+    pub fn push(kind: &str, payload: &impl Serialize, error: &str, attempts: i64) -> bool {
+        match Self::get() {
+            Some(manager) => manager.write(kind, payload, error, attempts),
+            None => false,
+        }
+    }
+
+    /// Submit the dead letter to be inserted asynchronously (best-effort), returns false if dropped.
+    pub fn write(&self, kind: &str, payload: &impl Serialize, error: &str, attempts: i64) -> bool {
+        let payload = match serde_json::to_string(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize the dead letter of '{}'. cause: {}", kind, e);
+                BOTWAF_DEAD_LETTER_DROPPED_TOTAL.inc();
+                return false;
+            }
+        };
+        let dead_letter = DeadLetter {
+            base: BaseBean::new_with_id(None),
+            kind: Some(kind.to_owned()),
+            payload: Some(payload),
+            error: Some(error.to_owned()),
+            attempts: Some(attempts),
+            replays: Some(0),
+            state: Some(DeadLetterState::PENDING),
+            last_failed_time: Some(chrono::Utc::now().timestamp_millis()),
+        };
+        match self.sender.try_send(WriterMessage::DeadLetter(dead_letter)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                BOTWAF_DEAD_LETTER_DROPPED_TOTAL.inc();
+                false
+            }
+        }
+    }
+
+    /// Wait for the all submitted dead letters to be inserted.
+    pub async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(WriterMessage::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    pub async fn find(&self, param: DeadLetter, page: PageRequest) -> Result<(PageResponse, Vec<DeadLetter>), Error> {
+        self.repo.select(param, page).await
+    }
+
+    pub async fn get_by_id(&self, id: i64) -> Result<DeadLetter, Error> {
+        self.repo.select_by_id(id, None).await
+    }
+
+    /// Replay the dead letters through the original pipelines, the failed replays are kept until the max replays.
+    pub async fn replay(&self, ids: &[i64]) -> Vec<ReplayDeadLetterResult> {
+        let mut results = Vec::with_capacity(ids.len());
+        for id in ids {
+            results.push(match self.replay_one(*id).await {
+                Ok(result) => result,
+                Err(e) => ReplayDeadLetterResult {
+                    id: *id,
+                    replayed: false,
+                    state: None,
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+        results
+    }
+
+    async fn replay_one(&self, id: i64) -> Result<ReplayDeadLetterResult, Error> {
+        let mut dead_letter = self.get_by_id(id).await?;
+        match dead_letter.state {
+            Some(DeadLetterState::REPLAYED) => {
+                return Ok(ReplayDeadLetterResult {
+                    id,
+                    replayed: true,
+                    state: dead_letter.state,
+                    error: None,
+                })
+            }
+            Some(DeadLetterState::FAILED) => {
+                return Ok(ReplayDeadLetterResult {
+                    id,
+                    replayed: false,
+                    state: dead_letter.state,
+                    error: Some(format!("Permanently failed after {:?} replays", dead_letter.replays)),
+                })
+            }
+            _ => {}
+        }
+
+        let kind = dead_letter.kind.to_owned().unwrap_or_default();
+        let replayer = REPLAYER_MAP
+            .read()
+            .unwrap()
+            .get(&kind)
+            .cloned()
+            .ok_or_else(|| anyhow!("No replayer of the dead letter kind '{}'", kind))?;
+        let payload = dead_letter.payload.to_owned().unwrap_or_default();
+
+        // Retry with the exponential backoff.
+        let mut attempts = 0;
+        let mut error = None;
+        for attempt in 0..self.config.replay_attempts.max(1) {
+            if attempt > 0 {
+                let backoff = self.config.replay_backoff_ms.saturating_mul(1 << (attempt - 1).min(16));
+                tokio::time::sleep(backoff).await;
+            }
+            attempts += 1;
+            match replayer.replay(&payload).await {
+                Ok(()) => {
+                    error = None;
+                    break;
+                }
+                Err(e) => error = Some(e.to_string()),
+            }
+        }
+
+        dead_letter.attempts = Some(dead_letter.attempts.unwrap_or(0) + attempts);
+        match &error {
+            None => {
+                dead_letter.state = Some(DeadLetterState::REPLAYED);
+                BOTWAF_DEAD_LETTER_REPLAYS_TOTAL
+                    .with_label_values(&[&kind, "success"])
+                    .inc();
+            }
+            Some(e) => {
+                let replays = dead_letter.replays.unwrap_or(0) + 1;
+                dead_letter.replays = Some(replays);
+                dead_letter.error = Some(e.to_owned());
+                dead_letter.last_failed_time = Some(chrono::Utc::now().timestamp_millis());
+                // The poison message is never replayed again.
+                if replays >= self.config.max_replays as i64 {
+                    warn!(
+                        "The dead letter {} is permanently failed after {} replays.",
+                        id, replays
+                    );
+                    dead_letter.state = Some(DeadLetterState::FAILED);
+                }
+                BOTWAF_DEAD_LETTER_REPLAYS_TOTAL
+                    .with_label_values(&[&kind, "failure"])
+                    .inc();
+            }
+        }
+        let state = dead_letter.state;
+        self.repo.update(dead_letter).await?;
+
+        Ok(ReplayDeadLetterResult {
+            id,
+            replayed: error.is_none(),
+            state,
+            error,
+        })
+    }
+
+    /// Purge the expired and the oldest beyond the max entries.
+    pub async fn purge(&self) -> Result<u64, Error> {
+        let expired_before =
+            UtcDateTime(chrono::Utc::now() - chrono::Duration::seconds(self.config.retention_secs.as_secs() as i64));
+        self.repo.purge(expired_before, self.config.max_entries).await
+    }
+
+    fn start_purger(self: &Arc<Self>) {
+        let this = self.clone();
+        let interval = (*self.config.purge_interval_secs).max(Duration::from_secs(1));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match this.purge().await {
+                    Ok(count) if count > 0 => info!("Purged the {} dead letters.", count),
+                    Ok(_) => {}
+                    Err(e) => tracing::error!("Failed to purge the dead letters. cause: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn build_repository(config: &AppConfig) -> Result<Arc<dyn IDeadLetterRepository>, Error> {
+        let db_config = &config.appdb;
+        Ok(match db_config.db_type {
+            AppDBType::SQLITE => Arc::new(DeadLetterSQLiteRepository::new(&db_config.sqlite).await?),
+            AppDBType::POSTGRESQL => Arc::new(DeadLetterPostgresRepository::new(&db_config.postgres).await?),
+            AppDBType::MONGODB => Arc::new(DeadLetterMongoRepository::new(&db_config.mongodb).await?),
+        })
+    }
+
+    async fn run_writer(mut receiver: mpsc::Receiver<WriterMessage>, repo: Arc<dyn IDeadLetterRepository>) {
+        while let Some(message) = receiver.recv().await {
+            match message {
+                WriterMessage::DeadLetter(dead_letter) => {
+                    let kind = dead_letter.kind.to_owned().unwrap_or_default();
+                    // Notice: The failure of the dead letters store itself is only logged.
+                    match repo.insert(dead_letter).await {
+                        Ok(_) => BOTWAF_DEAD_LETTER_TOTAL.with_label_values(&[&kind]).inc(),
+                        Err(e) => {
+                            BOTWAF_DEAD_LETTER_DROPPED_TOTAL.inc();
+                            tracing::error!("Failed to write the dead letter of '{}'. cause: {}", kind, e);
+                        }
+                    }
+                }
+                WriterMessage::Flush(ack) => {
+                    let _ = ack.send(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::SqliteAppDBProperties;
+    use crate::config::duration::DurationMillis;
+    use axum::{http::StatusCode, routing::post, Router};
+    use botwaf_types::sys::{dead_letter::QueryDeadLetterRequest, event::FailOpenBudgetV1};
+    use std::{
+        env, fs,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    async fn create_manager(name: &str, max_replays: u32) -> DeadLetterManager {
+        let dir = env::temp_dir().join(format!("botwaf-ut-dead-letter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = DeadLetterSQLiteRepository::new(&SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        })
+        .await
+        .unwrap();
+        let config = DeadLetterProperties {
+            max_replays,
+            replay_attempts: 2,
+            replay_backoff_ms: DurationMillis::from_millis(1),
+            ..DeadLetterProperties::default()
+        };
+        DeadLetterManager::new(&config, Arc::new(repo))
+    }
+
+    // The webhook endpoint which responds 503 until recovered.
+    async fn spawn_webhook_endpoint(recovered: Arc<AtomicBool>, received: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/webhook",
+            post(move || {
+                let (recovered, received) = (recovered.clone(), received.clone());
+                async move {
+                    if !recovered.load(Ordering::SeqCst) {
+                        return StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                    received.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::OK
+                }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/webhook", addr)
+    }
+
+    async fn push_failed_webhook(manager: &DeadLetterManager, url: &str) -> i64 {
+        let delivery = WebhookDelivery::new(
+            url,
+            BotwafEvent::FailOpenBudgetV1(FailOpenBudgetV1 {
+                subsystem: String::from("ipfilter"),
+                status: String::from("ESCALATED"),
+                fail_opens: 100,
+                max_fail_opens: 50,
+                escalated_time: Some(1760572800000),
+                fail_closed: false,
+            }),
+        );
+        let error = delivery.deliver(&httpclients::build_default()).await.unwrap_err();
+        assert!(manager.write(DEAD_LETTER_KIND_WEBHOOK, &delivery, &error.to_string(), 1));
+        manager.flush().await;
+
+        let param = QueryDeadLetterRequest {
+            kind: Some(DEAD_LETTER_KIND_WEBHOOK.to_owned()),
+            state: Some(DeadLetterState::PENDING),
+        };
+        let (_, data) = manager
+            .find(param.to_dead_letter(), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].attempts, Some(1));
+        data[0].base.id.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_failed_webhook_after_recovered() {
+        let (recovered, received) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let url = spawn_webhook_endpoint(recovered.clone(), received.clone()).await;
+        let manager = create_manager("replay", 3).await;
+        let id = push_failed_webhook(&manager, &url).await;
+
+        // The endpoint is still down, keep pending for the next replay.
+        let result = manager.replay(&[id]).await.remove(0);
+        assert!(!result.replayed);
+        assert_eq!(result.state, Some(DeadLetterState::PENDING));
+        assert_eq!(manager.get_by_id(id).await.unwrap().replays, Some(1));
+
+        recovered.store(true, Ordering::SeqCst);
+        let result = manager.replay(&[id]).await.remove(0);
+        assert!(result.replayed);
+        assert_eq!(result.state, Some(DeadLetterState::REPLAYED));
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        // The replayed is never delivered again.
+        assert!(manager.replay(&[id]).await.remove(0).replayed);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        // The original attempt + the 2 failed attempts + the successful attempt.
+        assert_eq!(manager.get_by_id(id).await.unwrap().attempts, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_poison_permanently_failed_after_max_replays() {
+        let (recovered, received) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let url = spawn_webhook_endpoint(recovered.clone(), received.clone()).await;
+        let manager = create_manager("poison", 2).await;
+        let id = push_failed_webhook(&manager, &url).await;
+
+        assert_eq!(manager.replay(&[id]).await[0].state, Some(DeadLetterState::PENDING));
+        assert_eq!(manager.replay(&[id]).await[0].state, Some(DeadLetterState::FAILED));
+
+        // The poison is not replayed even if the endpoint recovered.
+        recovered.store(true, Ordering::SeqCst);
+        let result = manager.replay(&[id]).await.remove(0);
+        assert!(!result.replayed);
+        assert_eq!(result.state, Some(DeadLetterState::FAILED));
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_purge_beyond_max_entries() {
+        let manager = create_manager("purge", 3).await;
+        for i in 0..5 {
+            assert!(manager.write(DEAD_LETTER_KIND_WEBHOOK, &i, "failed", 1));
+        }
+        manager.flush().await;
+
+        let manager = DeadLetterManager {
+            config: DeadLetterProperties {
+                max_entries: 2,
+                ..manager.config.to_owned()
+            },
+            repo: manager.repo.clone(),
+            sender: manager.sender.clone(),
+        };
+        assert_eq!(manager.purge().await.unwrap(), 3);
+        let (_, data) = manager
+            .find(DeadLetter::default(), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 2);
+    }
+}
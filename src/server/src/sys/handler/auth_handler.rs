@@ -20,11 +20,15 @@
 
 use super::user_handler::{IUserHandler, UserHandler};
 use crate::util::auths;
+use crate::util::login_keypairs::LoginKeyPairPool;
 use crate::{config::config::AppConfig, context::state::BotwafState};
 use anyhow::{anyhow, Error, Ok};
 use async_trait::async_trait;
 use botwaf_types::{
-    sys::auth::{EthersWalletLoginRequest, GithubUserInfo, LogoutRequest, PasswordLoginRequest, PasswordPubKeyRequest},
+    sys::auth::{
+        ChangePasswordRequest, EthersWalletLoginRequest, GithubUserInfo, LogoutRequest, PasswordLoginRequest,
+        PasswordPubKeyRequest, ResetPasswordRequest,
+    },
     sys::user::{SaveUserRequest, User},
 };
 use botwaf_utils::rsa_ciphers::RSACipher;
@@ -41,6 +45,7 @@ use tower_cookies::cookie::{time::Duration, CookieBuilder, SameSite};
 pub const AUTH_NONCE_PREFIX: &'static str = "auth:nonce:";
 pub const LOGIN_PRIVATE_KEY_PREFIX: &'static str = "login:privatekey:";
 pub const LOGOUT_BLACKLIST_PREFIX: &'static str = "logout:blacklist:";
+pub const TOKEN_EPOCH_PREFIX: &'static str = "auth:token:epoch:";
 
 lazy_static! {
     pub static ref LANG_CLAIMS_NAME_KEY: LanguageTag = LanguageTag::new("name".to_owned());
@@ -52,8 +57,16 @@ pub enum PrincipalType {
     OIDC,
     Github,
     EtherWallet,
+    ClientCert,
+    TrustedHeader,
+    ApiKey,
 }
 
+/// The current password is not matched on the self password change.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid current password")]
+pub struct InvalidPasswordError;
+
 #[async_trait]
 pub trait IAuthHandler: Send {
     async fn handle_password_pubkey(&self, param: PasswordPubKeyRequest) -> Result<String, Error>;
@@ -82,11 +95,19 @@ pub trait IAuthHandler: Send {
 
     async fn handle_logout(&self, param: LogoutRequest) -> Result<(), Error>;
 
+    async fn handle_password_change(&self, uid: i64, param: ChangePasswordRequest) -> Result<(), Error>;
+
+    async fn handle_password_reset(&self, uid: i64, param: ResetPasswordRequest) -> Result<(), Error>;
+
+    async fn get_token_epoch(&self, uid: i64) -> Result<i64, Error>;
+
     fn build_auth_nonce_key(&self, nonce: &str) -> String;
 
     fn build_login_private_key(&self, fingerprint_token: &str) -> String;
 
     fn build_logout_blacklist_key(&self, access_token: &str) -> String;
+
+    fn build_token_epoch_key(&self, uid: i64) -> String;
 }
 
 pub struct AuthHandler<'a> {
@@ -97,12 +118,91 @@ impl<'a> AuthHandler<'a> {
     pub fn new(state: &'a BotwafState) -> Self {
         Self { state }
     }
+
+    /// Verify the login user is not disabled and the password is matched.
+    pub fn verify_login_user(user: &User, hashed_password: &[u8]) -> Result<(), Error> {
+        if user.base.is_disabled() {
+            return Err(anyhow!("The user is disabled"));
+        }
+        if auths::verify_stored_password(hashed_password, user.password.as_deref().unwrap_or_default()) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid password"))
+        }
+    }
+
+    // Decrypt the enveloped passwords by the login private key of the fingerprint token.
+    async fn decrypt_passwords(&self, fingerprint_token: &str, ciphertexts: &[&str]) -> Result<Vec<Vec<u8>>, Error> {
+        let cache = self.state.string_cache.get(&self.state.config);
+        let base64_private_key = cache
+            .get(self.build_login_private_key(fingerprint_token))
+            .await?
+            .ok_or_else(|| {
+                anyhow!("No login private key, The operation takes too long? Please refresh and try again.")
+            })?;
+        let pair =
+            RSACipher::from_base64(&base64_private_key).map_err(|e| anyhow!("Invalid login private key. {}", e))?;
+        ciphertexts
+            .iter()
+            .map(|c| {
+                pair.decrypt_from_base64(c)
+                    .map_err(|e| anyhow!("Unable decryption password. {}", e))
+            })
+            .collect()
+    }
+
+    async fn get_user(&self, uid: i64) -> Result<Arc<User>, Error> {
+        UserHandler::new(self.state)
+            .get(Some(uid), None, None, None, None, None, None, None)
+            .await?
+            .ok_or_else(|| anyhow!("No found user of {}", uid))
+    }
+
+    // Store the new password hashed by Argon2, and invalidate the existing sessions of the user by bumping
+    // the token epoch (milliseconds), i.e: the tokens issued before are rejected, see: auths::get_issued_millis
+    async fn update_password(&self, user: &User, new_password: &[u8]) -> Result<(), Error> {
+        if new_password.is_empty() {
+            return Err(anyhow!("The new password is empty"));
+        }
+        let uid = user.base.id.ok_or_else(|| anyhow!("The user id is required"))?;
+        let epoch = Utc::now().timestamp_millis();
+        // Notice: The epoch is persisted with the password (the source of truth), the cached is only for the
+        // verifying without the store on each request.
+        UserHandler::new(self.state)
+            .update_password(
+                uid,
+                user.base.version,
+                auths::hash_stored_password(new_password)?,
+                epoch,
+            )
+            .await?;
+        self.revoke_token_epoch(uid, epoch).await
+    }
+
+    /// Invalidate the existing sessions of the user by bumping the token epoch (milliseconds), e.g: the user
+    /// is disabled, the stored epoch is updated first so that the tokens are rejected even if evicted.
+    pub async fn revoke_tokens(&self, uid: i64) -> Result<(), Error> {
+        let epoch = Utc::now().timestamp_millis();
+        UserHandler::new(self.state).update_token_epoch(uid, epoch).await?;
+        self.revoke_token_epoch(uid, epoch).await
+    }
+
+    async fn revoke_token_epoch(&self, uid: i64, epoch: i64) -> Result<(), Error> {
+        let cache = self.state.string_cache.get(&self.state.config);
+        let key = self.build_token_epoch_key(uid);
+        cache.set(key.to_owned(), epoch.to_string(), None).await?;
+        self.state
+            .token_verify_cache
+            .revoke(cache, key, epoch.to_string())
+            .await
+    }
 }
 
 #[async_trait]
 impl<'a> IAuthHandler for AuthHandler<'a> {
     async fn handle_password_pubkey(&self, param: PasswordPubKeyRequest) -> Result<String, Error> {
-        let pair = RSACipher::new(2048).unwrap();
+        // The keypair is taken from the pre-generated pool, or generated on the blocking threads.
+        let pair = LoginKeyPairPool::get().take().await?;
         // Storage private key to cache.
         let cache = self.state.string_cache.get(&self.state.config);
         let key = self.build_login_private_key(&param.fingerprint_token);
@@ -137,20 +237,32 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                             }
                         };
 
-                        // Getting user from database.
+                        // Getting user from database, the username is looked up case-insensitively.
                         let handler = UserHandler::new(self.state);
-                        match handler.get(None, None, None, None, None, None, None, None).await {
+                        match handler
+                            .get(
+                                None,
+                                Some(param.username.to_owned()),
+                                None,
+                                None,
+                                None,
+                                None,
+                                None,
+                                None,
+                            )
+                            .await
+                        {
                             std::result::Result::Ok(user) => match user {
-                                Some(user) => {
-                                    let store_hashed_password = user.password.clone().unwrap_or_default().into_bytes();
-                                    if auths::constant_time_eq(&hashed_password, &store_hashed_password) {
+                                Some(user) => match Self::verify_login_user(&user, &hashed_password) {
+                                    std::result::Result::Ok(_) => {
                                         tracing::debug!("Login success for: {:?}", param);
                                         Ok(user)
-                                    } else {
-                                        tracing::error!("Login failed for: {:?}", param);
-                                        Err(anyhow!("Invalid password"))
                                     }
-                                }
+                                    Err(e) => {
+                                        tracing::error!("Login failed for: {:?}, cause: {}", param, e);
+                                        Err(e)
+                                    }
+                                },
                                 None => {
                                     let errmsg = format!(
                                         "No login user, Please confirm that the login account is correct. {:?}",
@@ -227,37 +339,36 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
 
         let handler = UserHandler::new(self.state);
 
-        // 1. Get user by oidc uid
-        let user = handler
-            .get(None, None, None, None, Some(oidc_sub.to_string()), None, None, None)
-            .await
-            .unwrap();
+        // 1. Get or create the user by oidc uid atomically, which auto registers the user on the first login, and
+        // the concurrent first logins of the same user create exactly one user.
+        // Notice: The name/email are normalized and checked case-insensitively on create, so the IdP claims
+        // only differs in casing with the registered user are rejected instead of duplicated.
+        let create_param = SaveUserRequest {
+            id: None,
+            version: None,
+            name: oidc_preferred_name.to_owned(),
+            email: oidc_email.to_owned(),
+            phone: None,
+            password: None,
+            oidc_claims_sub: Some(oidc_sub.to_string()),
+            oidc_claims_name: oidc_preferred_name.to_owned(),
+            oidc_claims_email: oidc_email.to_owned(),
+            github_claims_sub: None,
+            github_claims_name: None,
+            github_claims_email: None,
+            google_claims_sub: None,
+            google_claims_name: None,
+            google_claims_email: None,
+            ethers_address: None,
+            lang: None,
+        };
+        let (uid, created) = handler.get_or_create("oidc_claims_sub", oidc_sub, create_param).await?;
 
-        // 2. If user exists, update user github subject ID.
-        let save_param;
-        if user.is_some() {
-            save_param = SaveUserRequest {
-                id: user.unwrap().base.id,
-                name: oidc_preferred_name.to_owned(),
-                email: None,
-                phone: None,
-                password: None,
-                oidc_claims_sub: Some(oidc_sub.to_string()),
-                oidc_claims_name: oidc_preferred_name,
-                oidc_claims_email: oidc_email,
-                github_claims_sub: None,
-                github_claims_name: None,
-                github_claims_email: None,
-                google_claims_sub: None,
-                google_claims_name: None,
-                google_claims_email: None,
-                ethers_address: None,
-                lang: None,
-            };
-        } else {
-            // 3. If user not exists, create user by github login, which auto register user.
-            save_param = SaveUserRequest {
-                id: None,
+        // 2. If user exists, update the user oidc claims.
+        if !created {
+            let save_param = SaveUserRequest {
+                id: Some(uid),
+                version: None,
                 name: oidc_preferred_name.to_owned(),
                 email: None,
                 phone: None,
@@ -274,12 +385,9 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                 ethers_address: None,
                 lang: None,
             };
+            handler.save(save_param).await?;
         }
-
-        match handler.save(save_param).await {
-            std::result::Result::Ok(uid) => Ok(uid),
-            Err(e) => Err(e),
-        }
+        Ok(uid)
     }
 
     async fn handle_auth_callback_github(&self, userinfo: GithubUserInfo) -> Result<i64, Error> {
@@ -289,37 +397,37 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
 
         let handler = UserHandler::new(self.state);
 
-        // 1. Get user by github_uid
-        let user = handler
-            .get(None, None, None, None, None, Some(github_sub.to_string()), None, None)
-            .await
-            .unwrap();
-
-        // 2. If user exists, update user github subject ID.
-        let save_param;
-        if user.is_some() {
-            save_param = SaveUserRequest {
-                id: user.unwrap().base.id,
-                name: Some(github_uname.to_string()),
-                email: None,
-                phone: None,
-                password: None,
-                oidc_claims_sub: None,
-                oidc_claims_name: None,
-                oidc_claims_email: None,
-                github_claims_sub: Some(github_sub.to_string()),
-                github_claims_name: Some(github_uname.to_string()),
-                github_claims_email: github_email,
-                google_claims_sub: None,
-                google_claims_name: None,
-                google_claims_email: None,
-                ethers_address: None,
-                lang: None,
-            };
-        } else {
-            // 3. If user not exists, create user by github login, which auto register user.
-            save_param = SaveUserRequest {
-                id: None,
+        // 1. Get or create the user by github uid atomically, which auto registers the user on the first login,
+        // and the concurrent first logins of the same user create exactly one user.
+        let github_sub = github_sub.to_string();
+        let create_param = SaveUserRequest {
+            id: None,
+            version: None,
+            name: Some(github_uname.to_string()),
+            email: github_email.to_owned(),
+            phone: None,
+            password: None,
+            oidc_claims_sub: None,
+            oidc_claims_name: None,
+            oidc_claims_email: None,
+            github_claims_sub: Some(github_sub.to_owned()),
+            github_claims_name: Some(github_uname.to_string()),
+            github_claims_email: github_email.to_owned(),
+            google_claims_sub: None,
+            google_claims_name: None,
+            google_claims_email: None,
+            ethers_address: None,
+            lang: None,
+        };
+        let (uid, created) = handler
+            .get_or_create("github_claims_sub", &github_sub, create_param)
+            .await?;
+
+        // 2. If user exists, update the user github claims.
+        if !created {
+            let save_param = SaveUserRequest {
+                id: Some(uid),
+                version: None,
                 name: Some(github_uname.to_string()),
                 email: None,
                 phone: None,
@@ -327,7 +435,7 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                 oidc_claims_sub: None,
                 oidc_claims_name: None,
                 oidc_claims_email: None,
-                github_claims_sub: Some(github_sub.to_string()),
+                github_claims_sub: Some(github_sub),
                 github_claims_name: Some(github_uname.to_string()),
                 github_claims_email: github_email,
                 google_claims_sub: None,
@@ -336,12 +444,9 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                 ethers_address: None,
                 lang: None,
             };
+            handler.save(save_param).await?;
         }
-
-        match handler.save(save_param).await {
-            std::result::Result::Ok(uid) => Ok(uid),
-            Err(e) => Err(e),
-        }
+        Ok(uid)
     }
 
     async fn handle_wallet_verify_ethers(&self, param: EthersWalletLoginRequest) -> Result<i64, Error> {
@@ -367,6 +472,7 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                     if user.is_some() {
                         save_param = SaveUserRequest {
                             id: user.unwrap().base.id,
+                            version: None,
                             name: Some(uname.to_owned()),
                             email: None,
                             phone: None,
@@ -387,6 +493,7 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
                         // 4. If user not exists, create user by github login, which auto register user.
                         save_param = SaveUserRequest {
                             id: None,
+                            version: None,
                             name: Some(uname.to_owned()),
                             email: None,
                             phone: None,
@@ -432,22 +539,40 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
         headers: &header::HeaderMap,
     ) -> hyper::Response<axum::body::Body> {
         // TODO: 附加更多自定义 JWT 信息
-        let extra_claims = HashMap::new();
+        let mut extra_claims = HashMap::new();
+        // The language of the user is carried by the token for the localized responses, see: auths::get_user_lang
+        if uid > 0 {
+            match UserHandler::new(self.state)
+                .get(Some(uid), None, None, None, None, None, None, None)
+                .await
+            {
+                Ok(user) => {
+                    if let Some(lang) = user.and_then(|u| u.lang.to_owned()).filter(|l| !l.trim().is_empty()) {
+                        extra_claims.insert(auths::LANG_CLAIM_NAME.to_owned(), lang);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to get the language of user {}. cause: {}", uid, e),
+            }
+        }
         let ak = auths::create_jwt(config, &ptype, uid, uname, email, false, Some(extra_claims));
         let rk = auths::create_jwt(config, &ptype, uid, uname, email, true, None);
 
         let ak_cookie = CookieBuilder::new(&config.auth_jwt_ak_name, ak)
             .path("/")
-            .max_age(Duration::milliseconds(config.auth.jwt_validity_ak.unwrap() as i64))
-            //.secure(true) // true: indicates that only https requests will carry
+            .max_age(Duration::milliseconds(
+                config.auth.jwt_validity_ak.unwrap().as_millis() as i64
+            ))
+            .secure(auths::is_secure_request(config, headers))
             .http_only(true)
             .same_site(SameSite::Strict)
             .build();
 
         let rk_cookie = CookieBuilder::new(&config.auth_jwt_rk_name, rk)
             .path("/")
-            .max_age(Duration::milliseconds(config.auth.jwt_validity_rk.unwrap() as i64))
-            //.secure(true) // true: indicates that only https requests will carry
+            .max_age(Duration::milliseconds(
+                config.auth.jwt_validity_rk.unwrap().as_millis() as i64
+            ))
+            .secure(auths::is_secure_request(config, headers))
             .http_only(true)
             .same_site(SameSite::Strict)
             .build();
@@ -458,7 +583,11 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
             config.auth.success_url.to_owned().unwrap().as_str(),
             StatusCode::OK,
             "Authenticated",
-            Some((Some(ak_cookie), Some(rk_cookie), None)),
+            Some((
+                Some(ak_cookie),
+                Some(rk_cookie),
+                Some(auths::create_csrf_cookie(config, headers)),
+            )),
         )
     }
 
@@ -474,9 +603,13 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
         };
         let key = self.build_logout_blacklist_key(ak.as_str());
         let value = Utc::now().timestamp_millis().to_string();
-        match cache.set(key, value, Some(3600_000)).await {
+        match cache.set(key.to_owned(), value.to_owned(), Some(3600_000)).await {
             std::result::Result::Ok(_) => {
                 info!("Logout success for {}", ak);
+                // Propagate the revocation to the local verification caches of all replicas.
+                if let Err(e) = self.state.token_verify_cache.revoke(cache, key, value).await {
+                    tracing::warn!("Failed to propagate the token revocation for {}, cause: {}", ak, e);
+                }
                 Ok(())
             }
             Err(e) => {
@@ -486,6 +619,51 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
         }
     }
 
+    async fn handle_password_change(&self, uid: i64, param: ChangePasswordRequest) -> Result<(), Error> {
+        let passwords = self
+            .decrypt_passwords(
+                &param.fingerprint_token,
+                &[&param.current_password, &param.new_password],
+            )
+            .await?;
+        let user = self.get_user(uid).await?;
+        if let Err(e) = Self::verify_login_user(&user, &passwords[0]) {
+            tracing::warn!("Rejected the password change for {}, cause: {}", uid, e);
+            return Err(InvalidPasswordError.into());
+        }
+        self.update_password(&user, &passwords[1]).await?;
+        info!("Changed password for {}", uid);
+        Ok(())
+    }
+
+    async fn handle_password_reset(&self, uid: i64, param: ResetPasswordRequest) -> Result<(), Error> {
+        let passwords = self
+            .decrypt_passwords(&param.fingerprint_token, &[&param.new_password])
+            .await?;
+        let user = self.get_user(uid).await?;
+        self.update_password(&user, &passwords[0]).await?;
+        info!("Reset password for {}", uid);
+        Ok(())
+    }
+
+    // Get the token epoch of the user from the cache, or reload from the user store if evicted, the user
+    // without the epoch (i.e: never changed the password) is cached as 0, so that never reloaded on each request.
+    async fn get_token_epoch(&self, uid: i64) -> Result<i64, Error> {
+        let cache = self.state.string_cache.get(&self.state.config);
+        let key = self.build_token_epoch_key(uid);
+        let epoch = match self.state.token_verify_cache.get(cache, key.to_owned()).await? {
+            Some(epoch) => Some(epoch),
+            None => {
+                let stored = self.get_user(uid).await?.token_epoch.unwrap_or_default();
+                self.state
+                    .token_verify_cache
+                    .fill(cache, key, stored.to_string())
+                    .await?
+            }
+        };
+        Ok(epoch.and_then(|epoch| epoch.parse::<i64>().ok()).unwrap_or_default())
+    }
+
     fn build_auth_nonce_key(&self, nonce: &str) -> String {
         format!("{}:{}", AUTH_NONCE_PREFIX, nonce)
     }
@@ -497,4 +675,8 @@ impl<'a> IAuthHandler for AuthHandler<'a> {
     fn build_logout_blacklist_key(&self, access_token: &str) -> String {
         format!("{}:{}", LOGOUT_BLACKLIST_PREFIX, access_token)
     }
+
+    fn build_token_epoch_key(&self, uid: i64) -> String {
+        format!("{}:{}", TOKEN_EPOCH_PREFIX, uid)
+    }
 }
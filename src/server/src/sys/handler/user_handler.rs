@@ -19,10 +19,14 @@
 // This includes modifications and derived works.
 
 use crate::context::state::BotwafState;
+use crate::sys::handler::auth_handler::AuthHandler;
+use crate::sys::identities;
 use anyhow::{Error, Ok};
 use async_trait::async_trait;
-use botwaf_types::sys::user::{DeleteUserRequest, QueryUserRequest, SaveUserRequest, SaveUserRequestWith, User};
-use botwaf_types::{BaseBean, PageRequest, PageResponse};
+use botwaf_types::sys::user::{
+    DeleteUserRequest, QueryUserRequest, SaveUserRequest, SaveUserRequestWith, SetUserStatusRequest, User,
+};
+use botwaf_types::{BaseBean, PageRequest, PageResponse, RecordStatus};
 use common_audit_log::audit_log;
 use std::sync::Arc;
 
@@ -57,7 +61,21 @@ pub trait IUserHandler: Send {
 
     async fn save(&self, param: SaveUserRequest) -> Result<i64, Error>;
 
+    async fn get_or_create(&self, key_column: &str, key: &str, param: SaveUserRequest) -> Result<(i64, bool), Error>;
+
     async fn delete(&self, param: DeleteUserRequest) -> Result<u64, Error>;
+
+    async fn set_status(&self, param: SetUserStatusRequest) -> Result<u64, Error>;
+
+    async fn update_password(
+        &self,
+        id: i64,
+        version: Option<i64>,
+        password: String,
+        token_epoch: i64,
+    ) -> Result<i64, Error>;
+
+    async fn update_token_epoch(&self, id: i64, token_epoch: i64) -> Result<i64, Error>;
 }
 
 pub struct UserHandler<'a> {
@@ -83,8 +101,11 @@ impl<'a> IUserHandler for UserHandler<'a> {
         google_claims_sub: Option<String>,
         ethers_address: Option<String>,
     ) -> Result<Option<Arc<User>>, Error> {
+        // Notice: Including the disabled users, e.g: to avoid the duplicated creation on the OAuth2 callback.
+        let mut base = BaseBean::new_with_id(id);
+        base.status = None;
         let param = User {
-            base: BaseBean::new_with_id(id),
+            base,
             name,
             email,
             phone,
@@ -100,12 +121,16 @@ impl<'a> IUserHandler for UserHandler<'a> {
             google_claims_email: None,
             ethers_address,
             lang: None,
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         };
 
+        // Notice: The name/email are looked up case-insensitively by the normalized keys.
         let repo = self.state.user_repo.lock().await;
         let res = repo
             .get(&self.state.config)
-            .select(param, PageRequest::default())
+            .select(identities::to_lookup(param), PageRequest::default())
             .await
             .unwrap()
             .1;
@@ -148,6 +173,7 @@ impl<'a> IUserHandler for UserHandler<'a> {
             std::result::Result::Ok(Some(user)) => {
                 let mut save_param = SaveUserRequest {
                     id: None,
+                    version: user.base.version,
                     name: param.name,
                     email: param.email,
                     phone: param.phone,
@@ -181,6 +207,7 @@ impl<'a> IUserHandler for UserHandler<'a> {
             std::result::Result::Ok(None) => {
                 let save_param = SaveUserRequest {
                     id: None,
+                    version: None,
                     name: param.name,
                     email: param.email,
                     phone: param.phone,
@@ -215,17 +242,54 @@ impl<'a> IUserHandler for UserHandler<'a> {
     #[audit_log("[USER][FIND] name: {param.name.clone().unwrap_or_default()}")]
     async fn find(&self, param: QueryUserRequest, page: PageRequest) -> Result<(PageResponse, Vec<User>), Error> {
         let repo = self.state.user_repo.lock().await;
-        repo.get(&self.state.config).select(param.to_user(), page).await
+        repo.get(&self.state.config)
+            .select(identities::to_lookup(param.to_user()), page)
+            .await
     }
 
     #[audit_log("[USER][ADD] name: {param.name.clone().unwrap_or_default()}")]
     async fn save(&self, param: SaveUserRequest) -> Result<i64, Error> {
+        let mut user = param.to_user();
+        identities::normalize_user(&self.state.config, &mut user);
+
         let repo = self.state.user_repo.lock().await;
+        identities::check_conflicts(repo.get(&self.state.config), &user).await?;
         if param.id.is_some() {
-            repo.get(&self.state.config).update(param.to_user()).await
+            repo.get(&self.state.config).update(user).await
         } else {
-            repo.get(&self.state.config).insert(param.to_user()).await
+            repo.get(&self.state.config).insert(user).await
+        }
+    }
+
+    // Get the user by the unique claims subject or create it atomically, e.g: the first login of the OAuth2 users,
+    // the concurrent logins of the same new user create exactly one user.
+    #[audit_log("[USER][GET_OR_CREATE] {key_column}: {key}")]
+    async fn get_or_create(&self, key_column: &str, key: &str, param: SaveUserRequest) -> Result<(i64, bool), Error> {
+        let mut user = param.to_user();
+        identities::normalize_user(&self.state.config, &mut user);
+
+        let repo = self.state.user_repo.lock().await;
+        let repo = repo.get(&self.state.config);
+        // Notice: The existing user is returned directly, which is always conflicted with itself.
+        let mut lookup = User::default();
+        lookup.base.status = None;
+        match key_column {
+            "oidc_claims_sub" => lookup.oidc_claims_sub = Some(key.to_owned()),
+            "github_claims_sub" => lookup.github_claims_sub = Some(key.to_owned()),
+            "google_claims_sub" => lookup.google_claims_sub = Some(key.to_owned()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported the unique key column '{}' of user",
+                    key_column
+                ))
+            }
+        }
+        let (_, existing) = repo.select(lookup, PageRequest::default()).await?;
+        if let Some(id) = existing.first().and_then(|u| u.base.id) {
+            return Ok((id, false));
         }
+        identities::check_conflicts(repo, &user).await?;
+        repo.get_or_insert(key_column, key, user).await
     }
 
     #[audit_log("[USER][DELETE] id: {param.id}")]
@@ -233,4 +297,49 @@ impl<'a> IUserHandler for UserHandler<'a> {
         let repo = self.state.user_repo.lock().await;
         repo.get(&self.state.config).delete_by_id(param.id).await
     }
+
+    #[audit_log("[USER][STATUS] id: {param.id}, status: {param.status.value()}")]
+    async fn set_status(&self, param: SetUserStatusRequest) -> Result<u64, Error> {
+        let updated = {
+            let repo = self.state.user_repo.lock().await;
+            repo.get(&self.state.config).set_status(param.id, param.status).await?
+        };
+        // The tokens issued before are rejected once the user is disabled, same as the password changed.
+        if updated > 0 && param.status == RecordStatus::Disabled {
+            AuthHandler::new(self.state).revoke_tokens(param.id).await?;
+        }
+        Ok(updated)
+    }
+
+    // Update the stored password and the token epoch as a whole, i.e: the tokens issued before are rejected
+    // once the password is changed, even if the cached epoch is evicted.
+    #[audit_log("[USER][PASSWORD] id: {id}")]
+    async fn update_password(
+        &self,
+        id: i64,
+        version: Option<i64>,
+        password: String,
+        token_epoch: i64,
+    ) -> Result<i64, Error> {
+        let mut user = User::default();
+        user.base.id = Some(id);
+        user.base.version = version;
+        user.password = Some(password);
+        user.token_epoch = Some(token_epoch);
+
+        let repo = self.state.user_repo.lock().await;
+        repo.get(&self.state.config)
+            .update_fields(user, &["password", "token_epoch"])
+            .await
+    }
+
+    #[audit_log("[USER][TOKEN_EPOCH] id: {id}")]
+    async fn update_token_epoch(&self, id: i64, token_epoch: i64) -> Result<i64, Error> {
+        let mut user = User::default();
+        user.base.id = Some(id);
+        user.token_epoch = Some(token_epoch);
+
+        let repo = self.state.user_repo.lock().await;
+        repo.get(&self.state.config).update_fields(user, &["token_epoch"]).await
+    }
 }
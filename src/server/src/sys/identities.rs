@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{config::config::AppConfig, store::AsyncRepository};
+use anyhow::{anyhow, Error};
+use botwaf_types::{sys::user::User, PageRequest};
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalize the username for the comparison, i.e: NFKC + casefold, e.g: 'Ａｌｉｃｅ' and 'ALICE' are both 'alice'
+pub fn normalize_username(name: &str) -> String {
+    // Notice: The lowercase mapping is re-normalized, since it may produce the non NFKC sequences.
+    name.trim().nfkc().collect::<String>().to_lowercase().nfkc().collect()
+}
+
+/// Normalize the email for the storage, the domain is always lowercased and the local part is preserved
+/// per config 'auth.preserve-email-local-case', e.g: 'Alice@Example.COM' is 'Alice@example.com'
+pub fn normalize_email(email: &str, preserve_local_case: bool) -> String {
+    let email = email.trim().nfkc().collect::<String>();
+    match email.rsplit_once('@') {
+        Some((local, domain)) if preserve_local_case => format!("{}@{}", local, domain.to_lowercase()),
+        _ => email.to_lowercase(),
+    }
+}
+
+/// The key of the email for the case-insensitive comparison, e.g: 'Alice@Example.COM' is 'alice@example.com'
+pub fn email_key(email: &str) -> String {
+    normalize_email(email, false)
+}
+
+/// Normalize the name/email of the user to save, and compute the keys for the case-insensitive lookup and uniqueness.
+pub fn normalize_user(config: &AppConfig, user: &mut User) {
+    let preserve_local_case = config.auth.preserve_email_local_case.unwrap_or(true);
+    if let Some(name) = user.name.as_ref().filter(|n| !n.trim().is_empty()) {
+        // Notice: The casing of the username is preserved for display, only the key is casefolded.
+        user.name_key = Some(normalize_username(name));
+        user.name = Some(name.trim().nfkc().collect());
+    }
+    if let Some(email) = user.email.as_ref().filter(|e| !e.trim().is_empty()) {
+        user.email_key = Some(email_key(email));
+        user.email = Some(normalize_email(email, preserve_local_case));
+    }
+}
+
+/// Convert the name/email conditions of the query to the normalized keys, which makes the lookup case-insensitive.
+pub fn to_lookup(mut user: User) -> User {
+    if let Some(name) = user.name.take().filter(|n| !n.trim().is_empty()) {
+        user.name_key = Some(normalize_username(&name));
+    }
+    if let Some(email) = user.email.take().filter(|e| !e.trim().is_empty()) {
+        user.email_key = Some(email_key(&email));
+    }
+    user
+}
+
+/// Check the normalized name/email of the user to save is not registered by the other users, the clear error
+/// message is returned if it only differs in casing, e.g: 'Alice@example.com' and 'alice@example.com'
+pub async fn check_conflicts(repo: &dyn AsyncRepository<User>, user: &User) -> Result<(), Error> {
+    let candidates = [
+        ("username", user.name.as_ref(), user.name_key.as_ref(), true),
+        ("email", user.email.as_ref(), user.email_key.as_ref(), false),
+    ];
+    for (field, value, key, is_name) in candidates {
+        let (value, key) = match (value, key) {
+            (Some(value), Some(key)) => (value, key),
+            _ => continue,
+        };
+        // Notice: Including the disabled users, which still occupy the identity.
+        let mut param = User::default();
+        if is_name {
+            param.name_key = Some(key.to_owned());
+        } else {
+            param.email_key = Some(key.to_owned());
+        }
+        let (_, existing) = repo.select(param, PageRequest::default()).await?;
+        let existing = existing
+            .iter()
+            .find(|e| user.base.id.is_none() || e.base.id != user.base.id);
+        if let Some(existing) = existing {
+            let existing_value = if is_name {
+                existing.name.to_owned()
+            } else {
+                existing.email.to_owned()
+            }
+            .unwrap_or_default();
+            if existing_value != *value {
+                return Err(anyhow!(
+                    "The {} '{}' is already registered with different casing as '{}'",
+                    field,
+                    value,
+                    existing_value
+                ));
+            }
+            return Err(anyhow!("The {} '{}' is already registered", field, value));
+        }
+    }
+    Ok(())
+}
@@ -18,6 +18,10 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod bootstrap;
+pub mod dead_letter;
 pub mod handler;
+pub mod identities;
 pub mod route;
+pub mod signing_key;
 pub mod store;
@@ -18,40 +18,50 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::util::auths::{self, AuthUserClaims, SecurityContext};
-use crate::util::web::ValidatedJson;
+use crate::cache::CacheEntryTooLargeError;
+use crate::sys::route::bootstrap_router::BOOTSTRAP_URI;
+use crate::util::auth_gate::{AuthFailure, AuthGate, FieldSpec, GateRejection};
+use crate::util::auths::{self, AuthUserClaims, ClientCertIdentity, SecurityContext};
+use crate::util::i18n;
+use crate::util::login_challenge::{ChallengeRejection, LoginChallenge};
+use crate::util::oauth2::async_http_client;
+use crate::util::web::{CurrentUser, ValidatedJson};
 use crate::{
-    config::{config::DEFAULT_404_HTML, resources::handle_static},
+    config::{
+        config::{self, AppConfig, DEFAULT_404_HTML},
+        resources::handle_static,
+    },
     context::state::BotwafState,
-    sys::handler::auth_handler::{AuthHandler, IAuthHandler, PrincipalType},
+    sys::handler::auth_handler::{AuthHandler, IAuthHandler, InvalidPasswordError, PrincipalType},
 };
 use axum::{
     body::Body,
-    extract::{Query, Request, State},
-    http::{header, StatusCode},
+    extract::{Json, Query, Request, State},
+    http::{header, Method, StatusCode},
     middleware::Next,
-    response::{Html, IntoResponse},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use botwaf_types::{
     sys::auth::{
-        CallbackGithubRequest, CallbackOidcRequest, EthersWalletLoginRequest, GithubUserInfo, LogoutRequest,
-        PasswordLoginRequest, PasswordPubKeyRequest, PasswordPubKeyResponse,
+        CallbackGithubRequest, CallbackOidcRequest, ChangePasswordRequest, EthersWalletLoginRequest, GithubUserInfo,
+        LoginChallengeResponse, LogoutRequest, PasswordLoginRequest, PasswordPubKeyRequest, PasswordPubKeyResponse,
+        OAUTH2_ERROR_ACCESS_DENIED,
     },
     RespBase,
 };
-use botwaf_utils::{self, webs};
+use botwaf_utils::{self, inets, webs};
 use common_telemetry::info;
 use hyper::HeaderMap;
 use oauth2::{AuthorizationCode, CsrfToken, Scope, TokenResponse};
 use openidconnect::{
     core::{CoreAuthenticationFlow, CoreTokenResponse, CoreUserInfoClaims},
-    reqwest::async_http_client,
     Nonce,
 };
 use std::result::Result;
 use std::result::Result::Ok;
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 use tower_cookies::{
     cookie::{
         time::{self, Duration},
@@ -69,9 +79,10 @@ pub const AUTH_CALLBACK_OIDC_URI: &str = "/auth/callback/oidc";
 pub const AUTH_CALLBACK_GITHUB_URI: &str = "/auth/callback/github";
 pub const AUTH_WALLET_ETHERS_VERIFY_URI: &str = "/auth/wallet/ethers/verify";
 pub const AUTH_LOGOUT_URI: &str = "/auth/logout";
+pub const AUTH_PASSWORD_CHANGE_URI: &str = "/auth/password/change";
 pub const STATIC_RESOURCES_PREFIX_URI: &str = "/static";
 
-pub const EXCLUDED_PREFIX_PATHS: [&str; 8] = [
+pub const EXCLUDED_PREFIX_PATHS: [&str; 9] = [
     AUTH_PASSWORD_PUBKEY_URI,
     AUTH_PASSWORD_VERIFY_URI,
     AUTH_CONNECT_OIDC_URI,
@@ -79,6 +90,7 @@ pub const EXCLUDED_PREFIX_PATHS: [&str; 8] = [
     AUTH_CALLBACK_OIDC_URI,
     AUTH_CALLBACK_GITHUB_URI,
     AUTH_WALLET_ETHERS_VERIFY_URI,
+    BOOTSTRAP_URI,
     STATIC_RESOURCES_PREFIX_URI,
 ];
 
@@ -96,15 +108,27 @@ pub fn init() -> Router<BotwafState> {
         .route(AUTH_CALLBACK_GITHUB_URI, get(handle_callback_github))
         .route(AUTH_WALLET_ETHERS_VERIFY_URI, post(handle_wallet_ethers_verify))
         .route(AUTH_LOGOUT_URI, get(handle_logout))
+        .route(AUTH_PASSWORD_CHANGE_URI, post(handle_password_change))
         .route(static_resources_uri.as_str(), get(handle_static))
         //.without_v07_checks()
+        .route_layer(axum::middleware::from_fn_with_state(
+            AuthGate::get(),
+            pre_auth_gate_middleware,
+        ))
         .fallback(handle_page_404) // Global auto internal forwarding when not found.
         .layer(CookieManagerLayer::new())
 }
 
 // ----- Global Authentication interceptors. -----
 
-pub async fn auth_middleware(State(state): State<BotwafState>, req: Request<Body>, next: Next) -> impl IntoResponse {
+pub async fn auth_middleware(
+    State(state): State<BotwafState>,
+    mut req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    // 0. Never honor the forwarded scheme and host from the untrusted peers, e.g: for the Secure flag of cookies.
+    auths::strip_untrusted_forwarded(&state.config, &mut req);
+
     let uri = req.uri();
     let path = auths::clean_context_path(&state.config.server.context_path, uri.path());
 
@@ -114,7 +138,17 @@ pub async fn auth_middleware(State(state): State<BotwafState>, req: Request<Body
     }
 
     // 2. Verify for bearer token.
-    let (is_authenticated, claims) = if let Some(auth_header) = req.headers().get("Authorization") {
+    let mut with_cookie = false;
+    let trusted_claims =
+        auths::authenticate_trusted_header(&state.config, req.headers(), req.extensions().get::<SocketAddr>());
+    let (is_authenticated, claims) = if trusted_claims.is_some() {
+        // 2.0 with the identity header of the trusted pre-authenticated gateway, skips the JWT validation.
+        (true, trusted_claims)
+    } else if req.headers().contains_key(auths::API_KEY_HEADER_NAME) {
+        // 2.0.1 with the static API key of the machine clients, which is never fallback to the other credentials.
+        let claims = auths::authenticate_api_key(&state.config, req.headers());
+        (claims.is_some(), claims)
+    } else if let Some(auth_header) = req.headers().get("Authorization") {
         // 2.1 with Header
         if let std::result::Result::Ok(auth_str) = auth_header.to_str() {
             if auth_str.starts_with("Bearer ") {
@@ -138,19 +172,35 @@ pub async fn auth_middleware(State(state): State<BotwafState>, req: Request<Body
             })
             .unwrap_or(None);
         if ak.is_some() {
+            with_cookie = true;
             validate_token(&state, ak.unwrap().as_str()).await
         } else {
-            (false, None)
+            // 2.3 with the verified client certificate of mTLS.
+            let claims = auths::authenticate_client_cert(&state.config, req.extensions().get::<ClientCertIdentity>());
+            (claims.is_some(), claims)
         }
     };
 
     if is_authenticated {
-        // 3. Bind authenticated info to context.
+        // 3. Verify the double-submit CSRF token for the state-changing requests authenticated by cookie,
+        // the bearer header is not sent automatically by the browsers, so it's not CSRF-prone.
+        if with_cookie
+            && auths::is_csrf_required(&state.config, req.method())
+            && !auths::verify_csrf_token(req.headers())
+        {
+            tracing::warn!(
+                "Rejected the request with missing or mismatched CSRF token for {}",
+                path
+            );
+            let user_lang = claims.as_ref().and_then(|c| auths::get_claims_lang(c));
+            let locale = i18n::negotiate(user_lang, req.headers());
+            return (StatusCode::FORBIDDEN, i18n::translate(locale, "Invalid CSRF token")).into_response();
+        }
+
         info!("Authenticated user: {:?}", claims);
-        SecurityContext::get_instance().bind(claims).await;
 
         // If logged in, and redirect to home page
-        if path == ROOT_URI {
+        if should_redirect_root(&state.config, path) {
             return auths::auth_resp_redirect_or_json(
                 &state.config,
                 &req.headers(),
@@ -161,11 +211,17 @@ pub async fn auth_middleware(State(state): State<BotwafState>, req: Request<Body
             );
         }
 
-        // 4. Pass to call next routes.
-        return next.run(req).await;
+        // 4. Bind authenticated info to the request, i.e: the extensions for the role extractors (see:
+        // web::RequireAdmin) and the context scoped to the request task, never shared across the requests.
+        if let Some(claims) = &claims {
+            req.extensions_mut().insert(claims.to_owned());
+        }
+
+        // 5. Pass to call next routes.
+        return SecurityContext::get_instance().scope(claims, next.run(req)).await;
     }
 
-    // 5. Unauthenticated Response.
+    // 6. Unauthenticated Response.
     auths::auth_resp_redirect_or_json(
         &state.config,
         &req.headers(),
@@ -176,6 +232,27 @@ pub async fn auth_middleware(State(state): State<BotwafState>, req: Request<Body
     )
 }
 
+/// Whether the authenticated request at root path should be redirected to the success url.
+/// Notice: Skipped if disabled or the target path is the current path to prevent redirect loops.
+pub fn should_redirect_root(config: &AppConfig, path: &str) -> bool {
+    if path != ROOT_URI || !config.auth.root_redirect.unwrap_or(true) {
+        return false;
+    }
+    match &config.auth.success_url {
+        Some(success_url) => {
+            let target = auths::join_context_path(config, success_url.to_owned());
+            // The absolute url is may be other hosts, which will not loop to self.
+            if url::Url::parse(&target).is_ok() {
+                return true;
+            }
+            let target_path = target.split(|c| c == '?' || c == '#').next().unwrap_or_default();
+            let current_path = auths::join_context_path(config, path.to_owned());
+            target_path != current_path
+        }
+        None => false,
+    }
+}
+
 async fn validate_token(state: &BotwafState, ak: &str) -> (bool, Option<AuthUserClaims>) {
     // 1. Verify the token is valid.
     match auths::validate_jwt(&state.config, ak) {
@@ -183,12 +260,23 @@ async fn validate_token(state: &BotwafState, ak: &str) -> (bool, Option<AuthUser
             let exp = time::OffsetDateTime::from_unix_timestamp(claims.exp as i64).unwrap();
             let now = time::OffsetDateTime::now_utc();
             if exp > now {
-                // 2. Verify whether the token is in the cancelled blacklist.
+                // 2. Verify whether the token is in the cancelled blacklist (or the local verdict if enabled).
                 let cache = state.string_cache.get(&state.config);
-                match cache.get(get_auth_handler(state).build_logout_blacklist_key(ak)).await {
-                    std::result::Result::Ok(logout) => {
+                let key = get_auth_handler(state).build_logout_blacklist_key(ak);
+                match state.token_verify_cache.is_revoked(cache, key).await {
+                    std::result::Result::Ok(true) => {
                         tracing::warn!("Invalid the token because in blacklist for {}", ak);
-                        (logout.is_none(), Some(claims))
+                        (false, Some(claims))
+                    }
+                    std::result::Result::Ok(false) => {
+                        // 3. Verify whether the token is issued before the token epoch, e.g: the password changed.
+                        if is_before_token_epoch(state, &claims).await {
+                            tracing::warn!("Invalid the token because issued before the token epoch for {}", ak);
+                            (false, Some(claims))
+                        } else {
+                            tracing::debug!("Valid the token because not in blacklist for {}", ak);
+                            (true, Some(claims))
+                        }
                     }
                     Err(_) => {
                         tracing::debug!("Valid the token because not in blacklist for {}", ak);
@@ -207,6 +295,22 @@ async fn validate_token(state: &BotwafState, ak: &str) -> (bool, Option<AuthUser
     }
 }
 
+// Whether the token is issued before the token epoch (milliseconds) of the user, i.e: the token issued
+// after the password changed (even in the same second) is still valid.
+async fn is_before_token_epoch(state: &BotwafState, claims: &AuthUserClaims) -> bool {
+    // The principals without the local user have no password, e.g: the API keys and the client certificates.
+    if claims.uid <= 0 {
+        return false;
+    }
+    match get_auth_handler(state).get_token_epoch(claims.uid).await {
+        Ok(epoch) => auths::get_issued_millis(claims) < epoch,
+        Err(e) => {
+            tracing::warn!("Failed to get the token epoch for {}, cause: {}", claims.uid, e);
+            false
+        }
+    }
+}
+
 // // Notice: The settings of middlewares are in order, which will affect the priority of route matching.
 // // The later the higher the priority? For example, if auth_middleware is set at the end, it will
 // // enter when requesting '/', otherwise it will not enter if it is set at the front, and will
@@ -236,6 +340,182 @@ async fn handle_page_404() -> impl IntoResponse {
     (StatusCode::NOT_FOUND, Html(DEFAULT_404_HTML))
 }
 
+// ----- Pre-authentication gate. -----
+
+const FINGERPRINT_FIELD_NAME: &str = "fpToken";
+
+// The expected body fields of the gated endpoints, which are validated before any expensive crypto.
+const PASSWORD_PUBKEY_FIELDS: &[FieldSpec] = &[FieldSpec {
+    name: FINGERPRINT_FIELD_NAME,
+    max_len: 128,
+    hex_len: None,
+}];
+const PASSWORD_CHANGE_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "currentPassword",
+        max_len: 1024,
+        hex_len: None,
+    },
+    FieldSpec {
+        name: "newPassword",
+        max_len: 1024,
+        hex_len: None,
+    },
+    FieldSpec {
+        name: FINGERPRINT_FIELD_NAME,
+        max_len: 128,
+        hex_len: None,
+    },
+];
+const PASSWORD_VERIFY_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "username",
+        max_len: 64,
+        hex_len: None,
+    },
+    FieldSpec {
+        name: "password",
+        max_len: 1024,
+        hex_len: None,
+    },
+    FieldSpec {
+        name: FINGERPRINT_FIELD_NAME,
+        max_len: 128,
+        hex_len: None,
+    },
+];
+const BOOTSTRAP_FIELDS: &[FieldSpec] = &[FieldSpec {
+    name: "username",
+    max_len: 64,
+    hex_len: None,
+}];
+const WALLET_ETHERS_VERIFY_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "address",
+        max_len: 42,
+        hex_len: Some(40),
+    },
+    FieldSpec {
+        name: "signature",
+        max_len: 132,
+        hex_len: Some(130),
+    },
+    FieldSpec {
+        name: "message",
+        max_len: 1024,
+        hex_len: None,
+    },
+];
+
+/// The cheap pre-authentication gate of the /auth/* POST endpoints and the OAuth2 callbacks, which rejects
+/// the throttled or malformed requests before any expensive signature/JWT work, see: AuthGate
+pub async fn pre_auth_gate_middleware(
+    State(gate): State<Arc<AuthGate>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    if !gate.is_enabled() {
+        return next.run(req).await;
+    }
+    let config = config::get_config();
+    let endpoint = auths::clean_context_path(&config.server.context_path, req.uri().path()).to_owned();
+    let fields = match (req.method(), endpoint.as_str()) {
+        (&Method::POST, AUTH_PASSWORD_PUBKEY_URI) => Some(PASSWORD_PUBKEY_FIELDS),
+        (&Method::POST, AUTH_PASSWORD_VERIFY_URI) => Some(PASSWORD_VERIFY_FIELDS),
+        (&Method::POST, AUTH_PASSWORD_CHANGE_URI) => Some(PASSWORD_CHANGE_FIELDS),
+        (&Method::POST, AUTH_WALLET_ETHERS_VERIFY_URI) => Some(WALLET_ETHERS_VERIFY_FIELDS),
+        (&Method::POST, BOOTSTRAP_URI) => Some(BOOTSTRAP_FIELDS),
+        (&Method::POST, path) if path.starts_with("/auth/") => Some(&[][..]),
+        (&Method::GET, AUTH_CALLBACK_OIDC_URI | AUTH_CALLBACK_GITHUB_URI) => None,
+        _ => return next.run(req).await,
+    };
+
+    // 1. Throttle by the client IP before reading the body, and the stricter bucket of the login pubkey which
+    // generates the RSA keypair on each request.
+    let client_ip = get_client_ip(&req);
+    if let Err(rejection) = gate.acquire_ip(&client_ip) {
+        return pre_auth_gate_reject(&endpoint, rejection);
+    }
+    if endpoint == AUTH_PASSWORD_PUBKEY_URI {
+        if let Err(rejection) = gate.acquire_pubkey_ip(&client_ip) {
+            return pre_auth_gate_reject(&endpoint, rejection);
+        }
+    }
+
+    // 2. Validate the size and shape of the body, and throttle by the fingerprint token if present.
+    let req = match fields {
+        Some(fields) => {
+            let (parts, body) = req.into_parts();
+            let bytes = match axum::body::to_bytes(body, gate.max_body_bytes()).await {
+                Ok(bytes) => bytes,
+                Err(_) => return pre_auth_gate_reject(&endpoint, GateRejection::PayloadTooLarge),
+            };
+            match gate.validate_body(&bytes, fields, Some(FINGERPRINT_FIELD_NAME)) {
+                Ok(Some(fingerprint)) => {
+                    if let Err(rejection) = gate.acquire_fingerprint(&fingerprint) {
+                        return pre_auth_gate_reject(&endpoint, rejection);
+                    }
+                }
+                Ok(None) => {}
+                Err(rejection) => return pre_auth_gate_reject(&endpoint, rejection),
+            }
+            Request::from_parts(parts, Body::from(bytes))
+        }
+        None => req,
+    };
+
+    // 3. Account the cost of the failed attempts.
+    let started = Instant::now();
+    let response = next.run(req).await;
+    if response.extensions().get::<AuthFailure>().is_some() {
+        AuthGate::record_failure(&endpoint, started.elapsed());
+    }
+    response
+}
+
+fn pre_auth_gate_reject(endpoint: &str, rejection: GateRejection) -> Response<Body> {
+    AuthGate::record_rejection(endpoint, &rejection);
+    tracing::warn!(
+        "Rejected the authentication request by pre-auth gate for {}: {:?}",
+        endpoint,
+        rejection
+    );
+    match rejection {
+        GateRejection::RateLimited { retry_after_secs } => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after_secs.to_string())],
+            RespBase::errmsg("Too many authentication requests").to_json(),
+        )
+            .into_response(),
+        GateRejection::PayloadTooLarge => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            RespBase::errmsg("The authentication request body is too large").to_json(),
+        )
+            .into_response(),
+        GateRejection::Malformed(errmsg) => {
+            (StatusCode::BAD_REQUEST, RespBase::errmsg(errmsg.as_str()).to_json()).into_response()
+        }
+    }
+}
+
+// Notice: The X-Forwarded-For is trusted as same as the HttpIncomingRequest, which should be overwritten by the
+// trusted frontend proxy, otherwise the attackers could rotate it to bypass the per IP buckets (but not the
+// per fingerprint buckets).
+fn get_client_ip(req: &Request<Body>) -> String {
+    req.headers()
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .or_else(|| req.headers().get("X-Real-IP").and_then(|v| v.to_str().ok()))
+        .map(inets::canonicalize_ip)
+        .or_else(|| {
+            req.extensions()
+                .get::<SocketAddr>()
+                .map(|addr| addr.ip().to_canonical().to_string())
+        })
+        .unwrap_or_default()
+}
+
 // ----- Simple Password Login. -----
 
 #[utoipa::path(
@@ -250,13 +530,15 @@ async fn handle_password_pubkey(
     State(state): State<BotwafState>,
     ValidatedJson(param): ValidatedJson<PasswordPubKeyRequest>,
 ) -> impl IntoResponse {
-    let base64_pubkey = get_auth_handler(&state).handle_password_pubkey(param).await.ok();
-    let result = serde_json::to_string(
-        &(PasswordPubKeyResponse {
-            pubkey: base64_pubkey.unwrap(),
-        }),
-    )
-    .unwrap();
+    let base64_pubkey = match get_auth_handler(&state).handle_password_pubkey(param).await {
+        Ok(pubkey) => pubkey,
+        // e.g: The oversized fingerprint token is rejected by the cache.
+        Err(e) if e.downcast_ref::<CacheEntryTooLargeError>().is_some() => {
+            return (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response();
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    };
+    let result = serde_json::to_string(&(PasswordPubKeyResponse { pubkey: base64_pubkey })).unwrap();
     (StatusCode::OK, result.to_string()).into_response()
 }
 
@@ -269,7 +551,10 @@ async fn handle_password_pubkey(
         content_type = "application/json",
         example = json!({"username": null, "password": null, "fingerprint_token": null}),
     ),
-    responses((status = 200, description = "Password login.")),
+    responses(
+        (status = 200, description = "Password login."),
+        (status = 428, description = "The challenge is required under attack, the client should show the challenge widget and retry with the 'challengeToken'.", body = LoginChallengeResponse),
+    ),
     tag = "Authentication"
 )]
 pub async fn handle_password_verify(
@@ -277,6 +562,7 @@ pub async fn handle_password_verify(
     request: axum::extract::Request<Body>,
 ) -> impl IntoResponse {
     let headers = &request.headers().clone();
+    let client_ip = get_client_ip(&request);
     let body = request.into_body();
 
     let param: PasswordLoginRequest = match serde_json::from_slice(
@@ -309,8 +595,15 @@ pub async fn handle_password_verify(
         }
     };
 
+    // Interpose the challenge once the failed logins of the client IP reached the threshold.
+    let challenge = LoginChallenge::get();
+    if let Err(rejection) = challenge.check(&client_ip, param.challenge_token.as_deref()).await {
+        return login_challenge_reject(&challenge, rejection);
+    }
+
     match get_auth_handler(&state).handle_password_verify(param).await {
         Ok(user) => {
+            challenge.record_success(&client_ip);
             get_auth_handler(&state)
                 .handle_login_success(
                     &state.config,
@@ -323,10 +616,74 @@ pub async fn handle_password_verify(
                 .await
         }
         Err(e) => {
+            challenge.record_failure(&client_ip);
             let errmsg = format!("Failed to login. {:?}", e.to_string());
             tracing::warn!("{}", errmsg);
             let result = RespBase::errmsg(errmsg.as_str());
-            (StatusCode::OK, serde_json::to_string(&result).unwrap()).into_response()
+            let mut response = (StatusCode::OK, serde_json::to_string(&result).unwrap()).into_response();
+            response.extensions_mut().insert(AuthFailure);
+            response
+        }
+    }
+}
+
+fn login_challenge_reject(challenge: &LoginChallenge, rejection: ChallengeRejection) -> Response<Body> {
+    let errmsg = match rejection {
+        ChallengeRejection::Required => "The challenge is required, please complete the challenge and retry.",
+        ChallengeRejection::Failed => "The challenge is not verified, please complete the challenge again.",
+    };
+    let result = LoginChallengeResponse {
+        errcode: StatusCode::PRECONDITION_REQUIRED.as_u16() as i16,
+        errmsg: errmsg.to_owned(),
+        challenge: rejection.code().to_owned(),
+        provider: format!("{:?}", challenge.provider()),
+        site_key: challenge.site_key().map(|s| s.to_owned()),
+    };
+    (StatusCode::PRECONDITION_REQUIRED, Json(result)).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = AUTH_PASSWORD_CHANGE_URI,
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Change the password of current user, the existing sessions are invalidated.", body = RespBase),
+        (status = 403, description = "The current password is invalid.", body = RespBase),
+    ),
+    tag = "Authentication"
+)]
+async fn handle_password_change(
+    State(state): State<BotwafState>,
+    CurrentUser(claims): CurrentUser,
+    Json(param): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    // Only the local users have the password, e.g: excluding the client certificate principals.
+    if claims.uid <= 0 {
+        return (
+            StatusCode::FORBIDDEN,
+            RespBase::errmsg("Forbidden, requires the local user.").to_json(),
+        )
+            .into_response();
+    }
+    let uid = claims.uid;
+    // Throttle the attempts of the current password by the user, as the per IP and fingerprint buckets of the
+    // pre-auth gate are bypassed by rotating them.
+    let gate = AuthGate::get();
+    if gate.is_enabled() {
+        if let Err(rejection) = gate.acquire_user(uid) {
+            return pre_auth_gate_reject(AUTH_PASSWORD_CHANGE_URI, rejection);
+        }
+    }
+    match get_auth_handler(&state).handle_password_change(uid, param).await {
+        Ok(_) => (StatusCode::OK, RespBase::success().to_json()).into_response(),
+        Err(e) if e.downcast_ref::<InvalidPasswordError>().is_some() => {
+            let mut response = (StatusCode::FORBIDDEN, RespBase::error(e).to_json()).into_response();
+            response.extensions_mut().insert(AuthFailure);
+            response
+        }
+        Err(e) => {
+            tracing::warn!("Failed to change password for {}, cause: {}", uid, e);
+            (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response()
         }
     }
 }
@@ -367,8 +724,10 @@ async fn handle_connect_oidc(State(state): State<BotwafState>, headers: header::
                     let csrf_cookie = CookieBuilder::new("_csrf_token", csrf_token.secret())
                         .path("/")
                         .http_only(true)
-                        //.secure(true) // true: indicates that only https requests will carry
-                        .max_age(Duration::milliseconds(state.config.auth.jwt_validity_ak.unwrap() as i64))
+                        .secure(auths::is_secure_request(&state.config, &headers))
+                        .max_age(Duration::milliseconds(
+                            state.config.auth.jwt_validity_ak.unwrap().as_millis() as i64,
+                        ))
                         .build();
                     return auths::auth_resp_redirect_or_json(
                         &state.config,
@@ -444,14 +803,22 @@ async fn handle_connect_github(State(state): State<BotwafState>, headers: header
 #[utoipa::path(
     get,
     path = AUTH_CALLBACK_OIDC_URI,
-    responses((status = 200, description = "Callback for OIDC.")),
+    responses(
+        (status = 200, description = "Callback for OIDC."),
+        (status = 401, description = "The provider responded the error, e.g: the user denied the consent."),
+    ),
     tag = "Authentication"
 )]
 async fn handle_callback_oidc(
     State(state): State<BotwafState>,
-    Query(param): Query<CallbackOidcRequest>,
+    Query(pairs): Query<Vec<(String, String)>>,
     headers: header::HeaderMap,
 ) -> impl IntoResponse {
+    let param = CallbackOidcRequest::from_query_pairs(&pairs);
+    if let Some(error) = &param.error {
+        return callback_error_reject(&state, &headers, "oidc", error, param.error_description.as_deref());
+    }
+
     match &state.oidc_client {
         Some(client) => {
             let code = match param.code {
@@ -470,7 +837,7 @@ async fn handle_callback_oidc(
 
             let token_result: Result<CoreTokenResponse, _> = client
                 .exchange_code(AuthorizationCode::new(code))
-                .request_async(async_http_client)
+                .request_async(|request| async_http_client(state.oauth2_http_client.as_ref().clone(), request))
                 .await;
 
             match token_result {
@@ -548,7 +915,10 @@ async fn handle_callback_oidc(
                         }
                     };
 
-                    let userinfo: CoreUserInfoClaims = match userinfo_request.request_async(async_http_client).await {
+                    let userinfo: CoreUserInfoClaims = match userinfo_request
+                        .request_async(|request| async_http_client(state.oauth2_http_client.as_ref().clone(), request))
+                        .await
+                    {
                         Ok(info) => info,
                         Err(e) => {
                             return auths::auth_resp_redirect_or_json(
@@ -633,22 +1003,74 @@ async fn handle_callback_oidc(
     }
 }
 
+// Redirect to the login with the friendly message, when the provider responded the error on callback, e.g: the
+// user denied the consent.
+fn callback_error_reject(
+    state: &BotwafState,
+    headers: &HeaderMap,
+    provider: &str,
+    error: &str,
+    error_description: Option<&str>,
+) -> Response<Body> {
+    tracing::info!(
+        "The {} authorization callback responded the error: {}, description: {:?}",
+        provider,
+        error,
+        error_description
+    );
+    let message = if error == OAUTH2_ERROR_ACCESS_DENIED {
+        "The authorization is denied, please login again"
+    } else {
+        "The authorization is failed, please login again"
+    };
+    auths::auth_resp_redirect_or_json(
+        &state.config,
+        headers,
+        &state.config.auth.login_url.to_owned().unwrap(),
+        StatusCode::UNAUTHORIZED,
+        message,
+        None,
+    )
+}
+
 #[utoipa::path(
     get,
     path = AUTH_CALLBACK_GITHUB_URI,
-    responses((status = 200, description = "Callback for github.")),
+    responses(
+        (status = 200, description = "Callback for github."),
+        (status = 401, description = "The provider responded the error, e.g: the user denied the consent."),
+    ),
     tag = "Authentication"
 )]
 async fn handle_callback_github(
     State(state): State<BotwafState>,
-    Query(param): Query<CallbackGithubRequest>,
+    Query(pairs): Query<Vec<(String, String)>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
+    let param = CallbackGithubRequest::from_query_pairs(&pairs);
+    if let Some(error) = &param.error {
+        return callback_error_reject(&state, &headers, "github", error, param.error_description.as_deref());
+    }
+
     match &state.github_client {
         Some(client) => {
+            let code = match param.code {
+                Some(code) => code,
+                None => {
+                    return auths::auth_resp_redirect_or_json(
+                        &state.config,
+                        &headers,
+                        &state.config.auth.login_url.to_owned().unwrap(),
+                        StatusCode::BAD_REQUEST,
+                        format!("Missing authentication code").as_str(),
+                        None,
+                    );
+                }
+            };
+
             let token_result = client
-                .exchange_code(AuthorizationCode::new(param.code.expect("Missing authorization code")))
-                .request_async(oauth2::reqwest::async_http_client)
+                .exchange_code(AuthorizationCode::new(code))
+                .request_async(|request| async_http_client(state.oauth2_http_client.as_ref().clone(), request))
                 .await;
 
             match token_result {
@@ -663,7 +1085,7 @@ async fn handle_callback_github(
 
                     // see:https://docs.github.com/en/rest/users/users?apiVersion=2022-11-28#get-a-user
                     let resp = match state
-                        .default_http_client
+                        .oauth2_http_client
                         .get(&url)
                         // see:https://docs.github.com/en/rest/using-the-rest-api/getting-started-with-the-rest-api?apiVersion=2022-11-28#user-agent-required
                         .header(reqwest::header::USER_AGENT, "The-Rust-App-Reqwest/1.0")
@@ -843,7 +1265,9 @@ pub async fn handle_wallet_ethers_verify(
             let errmsg = format!("Failed to login. {:?}", e.to_string());
             tracing::warn!("{}", errmsg);
             let result = RespBase::errmsg(errmsg.as_str());
-            (StatusCode::OK, serde_json::to_string(&result).unwrap()).into_response()
+            let mut response = (StatusCode::OK, serde_json::to_string(&result).unwrap()).into_response();
+            response.extensions_mut().insert(AuthFailure);
+            response
         }
     }
 }
@@ -874,9 +1298,11 @@ async fn handle_logout(
         Ok(_) => {
             let removal_ak = CookieBuilder::new(state.config.auth_jwt_ak_name.to_string(), "_")
                 .removal()
+                .secure(auths::is_secure_request(&state.config, &headers))
                 .build();
             let removal_rk = CookieBuilder::new(state.config.auth_jwt_rk_name.to_string(), "_")
                 .removal()
+                .secure(auths::is_secure_request(&state.config, &headers))
                 .build();
 
             auths::auth_resp_redirect_or_json(
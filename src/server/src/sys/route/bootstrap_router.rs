@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::sys::route::auth_router::pre_auth_gate_middleware;
+use crate::util::auth_gate::AuthGate;
+use crate::util::web::ValidatedJson;
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::post,
+    Router,
+};
+use botwaf_types::sys::bootstrap::{BootstrapRequest, BootstrapResponse};
+
+pub const BOOTSTRAP_URI: &str = "/api/v1/bootstrap";
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route(BOOTSTRAP_URI, post(handle_bootstrap))
+        // Notice: The anonymous bootstrap is throttled by the per IP pre-auth gate before the global throttling.
+        .route_layer(axum::middleware::from_fn_with_state(
+            AuthGate::get(),
+            pre_auth_gate_middleware,
+        ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/bootstrap",
+    request_body = BootstrapRequest,
+    responses(
+        (status = 200, description = "Created the initial administrator.", body = BootstrapResponse),
+        (status = 410, description = "The bootstrap is completed or not available."),
+        (status = 429, description = "Too many bootstrap requests."),
+    ),
+    tag = "Bootstrap"
+)]
+pub async fn handle_bootstrap(
+    State(state): State<BotwafState>,
+    ValidatedJson(param): ValidatedJson<BootstrapRequest>,
+) -> impl IntoResponse {
+    match state.bootstrap_manager.bootstrap(param).await {
+        Ok(result) => (StatusCode::OK, Json(result)).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::sys::dead_letter::DeadLetterManager;
+use crate::util::web::{RequireAdmin, ValidatedJson, ValidatedQuery};
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use botwaf_types::sys::dead_letter::{
+    DeadLetter, QueryDeadLetterRequest, QueryDeadLetterResponse, ReplayDeadLetterRequest, ReplayDeadLetterResponse,
+};
+use botwaf_types::{PageRequest, RespBase};
+use std::sync::Arc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/dead-letters", get(handle_dead_letters_list))
+        .route("/api/v1/dead-letters/{id}", get(handle_dead_letter_get))
+        .route("/api/v1/dead-letters/replay", post(handle_dead_letters_replay))
+}
+
+fn get_dead_letter_manager() -> Result<Arc<DeadLetterManager>, axum::response::Response> {
+    DeadLetterManager::get().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The dead letters is disabled.")),
+        )
+            .into_response()
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/dead-letters",
+    params(QueryDeadLetterRequest, PageRequest),
+    responses((status = 200, description = "Getting the dead letters of the failed async works.", body = QueryDeadLetterResponse)),
+    tag = "DeadLetter"
+)]
+async fn handle_dead_letters_list(
+    _: RequireAdmin,
+    ValidatedQuery(param): ValidatedQuery<QueryDeadLetterRequest>,
+    ValidatedQuery(page): ValidatedQuery<PageRequest>,
+) -> impl IntoResponse {
+    let manager = match get_dead_letter_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.find(param.to_dead_letter(), page).await {
+        Ok((page, data)) => Json(QueryDeadLetterResponse::new(page, data)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/dead-letters/{id}",
+    params(("id" = i64, Path, description = "The id of dead letter.")),
+    responses(
+        (status = 200, description = "Inspect the dead letter with the payload and the last error.", body = DeadLetter),
+        (status = 404, description = "Not found the dead letter.", body = RespBase),
+    ),
+    tag = "DeadLetter"
+)]
+async fn handle_dead_letter_get(_: RequireAdmin, Path(id): Path<i64>) -> impl IntoResponse {
+    let manager = match get_dead_letter_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.get_by_id(id).await {
+        Ok(dead_letter) => Json(dead_letter).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/dead-letters/replay",
+    request_body = ReplayDeadLetterRequest,
+    responses((status = 200, description = "Replay the dead letters through the original pipelines with the exponential backoff.", body = ReplayDeadLetterResponse)),
+    tag = "DeadLetter"
+)]
+async fn handle_dead_letters_replay(
+    _: RequireAdmin,
+    ValidatedJson(param): ValidatedJson<ReplayDeadLetterRequest>,
+) -> impl IntoResponse {
+    let manager = match get_dead_letter_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    let results = manager.replay(&param.ids).await;
+    Json(ReplayDeadLetterResponse { results }).into_response()
+}
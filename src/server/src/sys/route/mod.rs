@@ -19,4 +19,8 @@
 // This includes modifications and derived works.
 
 pub mod auth_router;
+pub mod bootstrap_router;
+pub mod dead_letter_router;
+pub mod signing_key_router;
+pub mod stats_router;
 pub mod user_router;
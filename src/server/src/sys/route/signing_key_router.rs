@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::context::state::BotwafState;
+use crate::sys::signing_key::SigningKeyManager;
+use crate::util::web::{RequireAdmin, ValidatedJson, ValidatedQuery};
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use botwaf_types::sys::signing_key::{
+    QuerySigningKeyRequest, QuerySigningKeyResponse, RotateSigningKeyRequest, RotateSigningKeyResponse, SigningKey,
+};
+use botwaf_types::{PageRequest, RespBase};
+use std::sync::Arc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new()
+        .route("/api/v1/signing-keys", get(handle_signing_keys_list))
+        .route("/api/v1/signing-keys/rotate", post(handle_signing_key_rotate))
+        .route("/api/v1/signing-keys/{id}/retire", post(handle_signing_key_retire))
+}
+
+fn get_signing_key_manager() -> Result<Arc<SigningKeyManager>, axum::response::Response> {
+    SigningKeyManager::get().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(RespBase::errmsg("The request signing keys is not initialized.")),
+        )
+            .into_response()
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/signing-keys",
+    params(QuerySigningKeyRequest, PageRequest),
+    responses((status = 200, description = "Getting the request signing keys, the secrets are never returned.", body = QuerySigningKeyResponse)),
+    tag = "SigningKey"
+)]
+async fn handle_signing_keys_list(
+    _: RequireAdmin,
+    ValidatedQuery(param): ValidatedQuery<QuerySigningKeyRequest>,
+    ValidatedQuery(page): ValidatedQuery<PageRequest>,
+) -> impl IntoResponse {
+    let manager = match get_signing_key_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.find(param.to_signing_key(), page).await {
+        Ok((page, data)) => Json(QuerySigningKeyResponse::new(page, data)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/signing-keys/rotate",
+    request_body = RotateSigningKeyRequest,
+    responses((status = 200, description = "Add the new active signing key and retire the oldest beyond the two active keys, the secret is only returned once.", body = RotateSigningKeyResponse)),
+    tag = "SigningKey"
+)]
+async fn handle_signing_key_rotate(
+    _: RequireAdmin,
+    ValidatedJson(param): ValidatedJson<RotateSigningKeyRequest>,
+) -> impl IntoResponse {
+    let manager = match get_signing_key_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.rotate(&param.key_id, param.secret).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/signing-keys/{id}/retire",
+    params(("id" = i64, Path, description = "The id of signing key.")),
+    responses(
+        (status = 200, description = "Retire the signing key immediately.", body = SigningKey),
+        (status = 404, description = "Not found the signing key.", body = RespBase),
+    ),
+    tag = "SigningKey"
+)]
+async fn handle_signing_key_retire(_: RequireAdmin, Path(id): Path<i64>) -> impl IntoResponse {
+    let manager = match get_signing_key_manager() {
+        Ok(manager) => manager,
+        Err(resp) => return resp,
+    };
+    match manager.retire(id).await {
+        Ok(signing_key) => Json(signing_key).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, RespBase::error(e).to_json()).into_response(),
+    }
+}
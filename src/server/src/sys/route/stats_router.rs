@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::context::state::BotwafState;
+use crate::mgmt::apm::dependencies::{self, DEFAULT_WINDOW_MINUTES};
+use crate::util::web::RequireOperator;
+use axum::{
+    extract::{Json, Query},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use botwaf_types::sys::stats::{QueryDependencyStatsRequest, QueryDependencyStatsResponse};
+use botwaf_types::RespBase;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/stats/dependencies", get(handle_dependency_stats))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/dependencies",
+    params(QueryDependencyStatsRequest),
+    responses(
+        (status = 200, description = "Getting the latency and error rate of the external dependencies of the last minutes.", body = QueryDependencyStatsResponse),
+        (status = 403, description = "Forbidden, requires the operator role.", body = RespBase),
+    ),
+    tag = "Stats"
+)]
+async fn handle_dependency_stats(
+    _: RequireOperator,
+    Query(param): Query<QueryDependencyStatsRequest>,
+) -> impl IntoResponse {
+    Json(dependencies::dependency_stats(
+        param.minutes.unwrap_or(DEFAULT_WINDOW_MINUTES),
+    ))
+    .into_response()
+}
@@ -18,20 +18,22 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use crate::store::VersionConflictError;
+use crate::sys::handler::auth_handler::{AuthHandler, IAuthHandler};
 use crate::sys::handler::user_handler::UserHandler;
-use crate::util::auths::SecurityContext;
-use crate::util::web::ValidatedJson;
+use crate::util::web::{CurrentUser, RequireAdmin, ValidatedJson, ValidatedQuery};
 use crate::{context::state::BotwafState, sys::handler::user_handler::IUserHandler};
 use axum::{
-    extract::{Json, Query, State},
+    extract::{Json, Path, State},
     http::StatusCode,
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
-use botwaf_types::sys::user::{DeleteUserRequest, QueryUserRequest, SaveUserRequest, User};
+use botwaf_types::sys::auth::ResetPasswordRequest;
+use botwaf_types::sys::user::{DeleteUserRequest, QueryUserRequest, SaveUserRequest, SetUserStatusRequest, User};
 use botwaf_types::{
-    sys::user::{DeleteUserResponse, QueryUserResponse, SaveUserRequestWith, SaveUserResponse},
+    sys::user::{DeleteUserResponse, QueryUserResponse, SaveUserRequestWith, SaveUserResponse, SetUserStatusResponse},
     PageRequest, RespBase,
 };
 use common_telemetry::info;
@@ -43,6 +45,8 @@ pub fn init() -> Router<BotwafState> {
         .route("/sys/user/query", get(handle_query_users))
         .route("/sys/user/save", post(handle_save_user))
         .route("/sys/user/delete", post(handle_delete_user))
+        .route("/sys/user/status", post(handle_set_user_status))
+        .route("/api/v1/users/{id}/password", post(handle_reset_user_password))
 }
 
 #[utoipa::path(
@@ -51,13 +55,14 @@ pub fn init() -> Router<BotwafState> {
     responses((status = 200, description = "Getting for current user.", body = User)),
     tag = "User"
 )]
-async fn handle_get_current_user(State(state): State<BotwafState>) -> impl IntoResponse {
-    let cur_user = SecurityContext::get_instance().get().await;
+async fn handle_get_current_user(
+    State(state): State<BotwafState>,
+    CurrentUser(cur_user): CurrentUser,
+) -> impl IntoResponse {
     info!("Getting for current user: {:?}", cur_user);
 
-    let cur_user_uid = cur_user.map(|u| u.uid);
     match get_user_handler(&state)
-        .get(cur_user_uid, None, None, None, None, None, None, None)
+        .get(Some(cur_user.uid), None, None, None, None, None, None, None)
         .await
     {
         Ok(result) => match result {
@@ -77,14 +82,13 @@ async fn handle_get_current_user(State(state): State<BotwafState>) -> impl IntoR
 )]
 async fn handle_post_current_user(
     State(state): State<BotwafState>,
+    CurrentUser(cur_user): CurrentUser,
     ValidatedJson(param): ValidatedJson<SaveUserRequestWith>,
 ) -> impl IntoResponse {
-    let cur_user = SecurityContext::get_instance().get().await;
     info!("Configure for current user: {:?}", cur_user);
 
-    let cur_user_uid = cur_user.map(|u| u.uid);
     match get_user_handler(&state)
-        .set(cur_user_uid, None, None, None, None, None, None, None, param)
+        .set(Some(cur_user.uid), None, None, None, None, None, None, None, param)
         .await
     {
         Ok(_) => (StatusCode::OK, RespBase::success().to_json()).into_response(),
@@ -101,8 +105,8 @@ async fn handle_post_current_user(
 )]
 async fn handle_query_users(
     State(state): State<BotwafState>,
-    Query(param): Query<QueryUserRequest>,
-    Query(page): Query<PageRequest>,
+    ValidatedQuery(param): ValidatedQuery<QueryUserRequest>,
+    ValidatedQuery(page): ValidatedQuery<PageRequest>,
 ) -> impl IntoResponse {
     match get_user_handler(&state).find(param, page).await {
         Ok((page, data)) => Ok(Json(QueryUserResponse::new(page, data))),
@@ -114,7 +118,10 @@ async fn handle_query_users(
     post,
     path = "/sys/user/save",
     request_body = SaveUserRequest,
-    responses((status = 200, description = "Save for user.", body = SaveUserResponse)),
+    responses(
+        (status = 200, description = "Save for user.", body = SaveUserResponse),
+        (status = 409, description = "Conflict to update user with the stale version.")
+    ),
     tag = "User"
 )]
 async fn handle_save_user(
@@ -123,6 +130,7 @@ async fn handle_save_user(
 ) -> impl IntoResponse {
     match get_user_handler(&state).save(param).await {
         Ok(result) => Ok(Json(SaveUserResponse::new(result))),
+        Err(e) if e.downcast_ref::<VersionConflictError>().is_some() => Err(StatusCode::CONFLICT),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
@@ -144,6 +152,46 @@ async fn handle_delete_user(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/sys/user/status",
+    request_body = SetUserStatusRequest,
+    responses((status = 200, description = "Enable or disable for user.", body = SetUserStatusResponse)),
+    tag = "User"
+)]
+async fn handle_set_user_status(
+    State(state): State<BotwafState>,
+    Json(param): Json<SetUserStatusRequest>,
+) -> impl IntoResponse {
+    match get_user_handler(&state).set_status(param).await {
+        Ok(result) => Ok(Json(SetUserStatusResponse::new(result))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/password",
+    params(("id" = i64, Path, description = "The id of user.")),
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Reset the password of user, the existing sessions are invalidated.", body = RespBase),
+        (status = 403, description = "Forbidden, requires the admin role.", body = RespBase),
+    ),
+    tag = "User"
+)]
+async fn handle_reset_user_password(
+    State(state): State<BotwafState>,
+    _: RequireAdmin,
+    Path(id): Path<i64>,
+    Json(param): Json<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    match AuthHandler::new(&state).handle_password_reset(id, param).await {
+        Ok(_) => (StatusCode::OK, RespBase::success().to_json()).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, RespBase::error(e).to_json()).into_response(),
+    }
+}
+
 fn get_user_handler(state: &BotwafState) -> Box<dyn IUserHandler + '_> {
     Box::new(UserHandler::new(state))
 }
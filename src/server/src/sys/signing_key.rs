@@ -0,0 +1,228 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use crate::{
+    config::config::{AppConfig, AppDBType},
+    sys::store::{
+        signing_keys_mongo::SigningKeyMongoRepository, signing_keys_postgresql::SigningKeyPostgresRepository,
+        signing_keys_sqlite::SigningKeySQLiteRepository, ISigningKeyRepository,
+    },
+};
+use anyhow::{anyhow, Error};
+use arc_swap::ArcSwapOption;
+use botwaf_types::{
+    sys::signing_key::{RotateSigningKeyResponse, SigningKey, SigningKeyState},
+    BaseBean, PageRequest, PageResponse,
+};
+use botwaf_utils::secrets::SecretHelper;
+use common_telemetry::info;
+use lazy_static::lazy_static;
+use moka::future::Cache;
+use std::{sync::Arc, time::Duration};
+
+/// The max active keys per key id, which allows the clients to roll over the new key without the downtime.
+pub const MAX_ACTIVE_KEYS: usize = 2;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: ArcSwapOption<SigningKeyManager> = ArcSwapOption::empty();
+}
+
+/// The request signing keys, the active secrets are cached locally to avoid querying the DB on each request.
+pub struct SigningKeyManager {
+    repo: Arc<dyn ISigningKeyRepository>,
+    cache: Cache<String, Arc<Vec<String>>>,
+}
+
+impl SigningKeyManager {
+    pub fn new(repo: Arc<dyn ISigningKeyRepository>, cache_ttl: Duration) -> Self {
+        SigningKeyManager {
+            repo,
+            cache: Cache::builder().max_capacity(10_000).time_to_live(cache_ttl).build(),
+        }
+    }
+
+    pub async fn init(config: &AppConfig) -> Result<(), Error> {
+        if SINGLE_INSTANCE.load().is_some() {
+            return Ok(());
+        }
+        let manager = Arc::new(Self::new(
+            Self::build_repository(config).await?,
+            *config.services.request_signing.key_cache_ttl_secs,
+        ));
+        SINGLE_INSTANCE.store(Some(manager));
+        info!("Initialized the request signing keys manager.");
+        Ok(())
+    }
+
+    pub fn get() -> Option<Arc<SigningKeyManager>> {
+        SINGLE_INSTANCE.load_full()
+    }
+
+    /// The signing keys with the secrets masked.
+    pub async fn find(&self, param: SigningKey, page: PageRequest) -> Result<(PageResponse, Vec<SigningKey>), Error> {
+        let (page, data) = self.repo.select(param, page).await?;
+        Ok((
+            page,
+            data.into_iter().map(|key| SigningKey { secret: None, ..key }).collect(),
+        ))
+    }
+
+    /// The secrets of the active keys of the key id, the newest first.
+    pub async fn find_active_secrets(&self, key_id: &str) -> Result<Arc<Vec<String>>, Error> {
+        if let Some(secrets) = self.cache.get(key_id).await {
+            return Ok(secrets);
+        }
+        let secrets = Arc::new(
+            self.repo
+                .find_active(key_id)
+                .await?
+                .into_iter()
+                .filter_map(|key| key.secret)
+                .collect::<Vec<_>>(),
+        );
+        self.cache.insert(key_id.to_owned(), secrets.clone()).await;
+        Ok(secrets)
+    }
+
+    /// Add the new active key, and retire the oldest active keys beyond the max active keys.
+    pub async fn rotate(&self, key_id: &str, secret: Option<String>) -> Result<RotateSigningKeyResponse, Error> {
+        let secret = secret.unwrap_or_else(|| SecretHelper::generate_secret_base64(32));
+        let id = self
+            .repo
+            .insert(SigningKey {
+                base: BaseBean::new_with_id(None),
+                key_id: Some(key_id.to_owned()),
+                secret: Some(secret.to_owned()),
+                state: Some(SigningKeyState::ACTIVE),
+            })
+            .await?;
+
+        let mut retired = Vec::new();
+        for key in self.repo.find_active(key_id).await?.into_iter().skip(MAX_ACTIVE_KEYS) {
+            if let Some(retired_id) = key.base.id {
+                self.repo
+                    .update(SigningKey {
+                        state: Some(SigningKeyState::RETIRED),
+                        ..key
+                    })
+                    .await?;
+                retired.push(retired_id);
+            }
+        }
+        self.cache.invalidate(key_id).await;
+        info!("Rotated the signing key '{}', retired: {:?}", key_id, retired);
+
+        Ok(RotateSigningKeyResponse {
+            id,
+            key_id: key_id.to_owned(),
+            secret,
+            retired,
+        })
+    }
+
+    /// Retire the key immediately, e.g: the secret is leaked.
+    pub async fn retire(&self, id: i64) -> Result<SigningKey, Error> {
+        let key = self.repo.select_by_id(id, None).await?;
+        let key_id = key
+            .key_id
+            .to_owned()
+            .ok_or_else(|| anyhow!("The signing key {} has no key id", id))?;
+        let retired = SigningKey {
+            state: Some(SigningKeyState::RETIRED),
+            ..key
+        };
+        self.repo.update(retired.to_owned()).await?;
+        self.cache.invalidate(&key_id).await;
+        Ok(SigningKey {
+            secret: None,
+            ..retired
+        })
+    }
+
+    async fn build_repository(config: &AppConfig) -> Result<Arc<dyn ISigningKeyRepository>, Error> {
+        let db_config = &config.appdb;
+        Ok(match db_config.db_type {
+            AppDBType::SQLITE => Arc::new(SigningKeySQLiteRepository::new(&db_config.sqlite).await?),
+            AppDBType::POSTGRESQL => Arc::new(SigningKeyPostgresRepository::new(&db_config.postgres).await?),
+            AppDBType::MONGODB => Arc::new(SigningKeyMongoRepository::new(&db_config.mongodb).await?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::SqliteAppDBProperties;
+    use std::{env, fs};
+
+    async fn create_manager(name: &str) -> SigningKeyManager {
+        let dir = env::temp_dir().join(format!("botwaf-ut-signing-key-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = SigningKeySQLiteRepository::new(&SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        })
+        .await
+        .unwrap();
+        SigningKeyManager::new(Arc::new(repo), Duration::from_secs(30))
+    }
+
+    #[tokio::test]
+    async fn test_rotate_retires_beyond_max_active_keys() {
+        let manager = create_manager("rotate").await;
+        let first = manager.rotate("orders-service", None).await.unwrap();
+        assert!(first.retired.is_empty());
+        assert!(first.secret.len() >= 32);
+        assert_eq!(manager.find_active_secrets("orders-service").await.unwrap().len(), 1);
+
+        let second = manager.rotate("orders-service", None).await.unwrap();
+        assert!(second.retired.is_empty());
+        // The cache is invalidated by the rotation, both keys are accepted during the roll over.
+        assert_eq!(
+            *manager.find_active_secrets("orders-service").await.unwrap(),
+            vec![second.secret.to_owned(), first.secret.to_owned()]
+        );
+
+        let third = manager.rotate("orders-service", None).await.unwrap();
+        assert_eq!(third.retired, vec![first.id]);
+        assert_eq!(
+            *manager.find_active_secrets("orders-service").await.unwrap(),
+            vec![third.secret.to_owned(), second.secret.to_owned()]
+        );
+
+        let (_, data) = manager
+            .find(SigningKey::default(), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 3);
+        assert!(data.iter().all(|key| key.secret.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_retire_key_immediately() {
+        let manager = create_manager("retire").await;
+        let rotated = manager.rotate("billing-service", Some("s".repeat(32))).await.unwrap();
+        assert_eq!(rotated.secret, "s".repeat(32));
+        assert_eq!(manager.find_active_secrets("billing-service").await.unwrap().len(), 1);
+
+        let retired = manager.retire(rotated.id).await.unwrap();
+        assert_eq!(retired.state, Some(SigningKeyState::RETIRED));
+        assert!(retired.secret.is_none());
+        assert!(manager.find_active_secrets("billing-service").await.unwrap().is_empty());
+    }
+}
@@ -18,6 +18,44 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod bootstrap_mongo;
+pub mod bootstrap_postgresql;
+pub mod bootstrap_sqlite;
+pub mod dead_letters_mongo;
+pub mod dead_letters_postgresql;
+pub mod dead_letters_sqlite;
+pub mod signing_keys_mongo;
+pub mod signing_keys_postgresql;
+pub mod signing_keys_sqlite;
 pub mod users_mongo;
 pub mod users_postgresql;
 pub mod users_sqlite;
+
+use crate::store::AsyncRepository;
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_types::{
+    datetime::UtcDateTime,
+    sys::{dead_letter::DeadLetter, signing_key::SigningKey},
+};
+
+pub const BOOTSTRAP_TABLE_NAME: &'static str = "sys_bootstrap";
+pub const DEAD_LETTER_TABLE_NAME: &'static str = "sys_dead_letter";
+pub const SIGNING_KEY_TABLE_NAME: &'static str = "sys_signing_key";
+
+/// The unique claims subject columns of the users, which are the keys of the get-or-create on the OAuth2 callbacks.
+pub const USER_CLAIMS_SUB_COLUMNS: &[&'static str] = &["oidc_claims_sub", "github_claims_sub", "google_claims_sub"];
+
+/// The dead letters repository, which is bounded by the retention purging.
+#[async_trait]
+pub trait IDeadLetterRepository: AsyncRepository<DeadLetter> + Sync {
+    /// Delete the dead letters created before the expired time, and the oldest beyond the max entries.
+    async fn purge(&self, expired_before: UtcDateTime, max_entries: u64) -> Result<u64, Error>;
+}
+
+/// The request signing keys repository, see: crate::sys::signing_key::SigningKeyManager
+#[async_trait]
+pub trait ISigningKeyRepository: AsyncRepository<SigningKey> + Sync {
+    /// The active keys of the key id, the newest first.
+    async fn find_active(&self, key_id: &str) -> Result<Vec<SigningKey>, Error>;
+}
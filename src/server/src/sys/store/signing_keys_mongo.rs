@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::{ISigningKeyRepository, SIGNING_KEY_TABLE_NAME};
+use crate::config::config::MongoAppDBProperties;
+use crate::store::mongo::MongoRepository;
+use crate::store::AsyncRepository;
+use crate::util::auths::SecurityContext;
+use crate::{dynamic_mongo_insert, dynamic_mongo_query, dynamic_mongo_update};
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_types::sys::signing_key::SigningKey;
+use botwaf_types::{datetime::UtcDateTime, PageRequest, PageResponse, RecordStatus};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, to_bson};
+use mongodb::Collection;
+use std::sync::Arc;
+
+pub struct SigningKeyMongoRepository {
+    #[allow(unused)]
+    inner: Arc<MongoRepository<SigningKey>>,
+    collection: Collection<SigningKey>,
+}
+
+impl SigningKeyMongoRepository {
+    pub async fn new(config: &MongoAppDBProperties) -> Result<Self, Error> {
+        let inner = Arc::new(MongoRepository::new(config).await?);
+        let collection = inner.get_database().collection(SIGNING_KEY_TABLE_NAME);
+        Ok(SigningKeyMongoRepository { inner, collection })
+    }
+}
+
+#[async_trait]
+impl AsyncRepository<SigningKey> for SigningKeyMongoRepository {
+    async fn select(
+        &self,
+        signing_key: SigningKey,
+        page: PageRequest,
+    ) -> Result<(PageResponse, Vec<SigningKey>), Error> {
+        dynamic_mongo_query!(signing_key, self.collection, "update_time", page, SigningKey)
+    }
+
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<SigningKey, Error> {
+        let mut filter = doc! { "id": id };
+        if let Some(status) = status {
+            filter.insert("status", status.value());
+        }
+        let signing_key = self
+            .collection
+            .find_one(filter)
+            .await?
+            .ok_or_else(|| Error::msg("Signing key not found"))?;
+        Ok(signing_key)
+    }
+
+    async fn insert(&self, mut signing_key: SigningKey) -> Result<i64, Error> {
+        dynamic_mongo_insert!(signing_key, self.collection)
+    }
+
+    async fn update(&self, mut signing_key: SigningKey) -> Result<i64, Error> {
+        dynamic_mongo_update!(signing_key, self.collection)
+    }
+
+    async fn update_fields(&self, mut signing_key: SigningKey, update_fields: &[&str]) -> Result<i64, Error> {
+        dynamic_mongo_update!(signing_key, self.collection, Some(update_fields))
+    }
+
+    async fn delete_all(&self) -> Result<u64, Error> {
+        let result = self.collection.delete_many(doc! {}).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_by_id(&self, id: i64) -> Result<u64, Error> {
+        let filter = doc! { "id": id };
+        let result = self.collection.delete_one(filter).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let filter = doc! { "id": id };
+        let update = doc! {
+            "$set": { "status": status.value(), "update_by": update_by, "update_time": to_bson(&UtcDateTime::now())? },
+            "$inc": { "version": 1 },
+        };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count)
+    }
+}
+
+#[async_trait]
+impl ISigningKeyRepository for SigningKeyMongoRepository {
+    async fn find_active(&self, key_id: &str) -> Result<Vec<SigningKey>, Error> {
+        let filter = doc! { "key_id": key_id, "state": "ACTIVE", "del_flag": 0 };
+        let signing_keys: Vec<SigningKey> = self
+            .collection
+            .find(filter)
+            .sort(doc! { "create_time": -1, "id": -1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(signing_keys)
+    }
+}
@@ -21,14 +21,17 @@
 use crate::config::config::MongoAppDBProperties;
 use crate::store::mongo::MongoRepository;
 use crate::store::AsyncRepository;
+use crate::sys::store::USER_CLAIMS_SUB_COLUMNS;
+use crate::util::auths::SecurityContext;
 use crate::{dynamic_mongo_insert, dynamic_mongo_query, dynamic_mongo_update};
 use anyhow::Error;
 use async_trait::async_trait;
 use botwaf_types::sys::user::User;
-use botwaf_types::{PageRequest, PageResponse};
+use botwaf_types::{datetime::UtcDateTime, PageRequest, PageResponse, RecordStatus};
 use common_telemetry::info;
-use mongodb::bson::doc;
-use mongodb::Collection;
+use mongodb::bson::{doc, to_bson};
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, IndexModel};
 use std::sync::Arc;
 
 pub struct UserMongoRepository {
@@ -41,6 +44,22 @@ impl UserMongoRepository {
     pub async fn new(config: &MongoAppDBProperties) -> Result<Self, Error> {
         let inner = Arc::new(MongoRepository::new(config).await?);
         let collection = inner.get_database().collection("sys_user");
+
+        // The unique normalized name/email keys for the case-insensitive uniqueness (see: sys::identities), and
+        // the unique claims subjects for the get-or-create on the OAuth2 callbacks.
+        for key in ["name_key", "email_key"].iter().chain(USER_CLAIMS_SUB_COLUMNS).copied() {
+            let options = IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { key: { "$type": "string" } })
+                .build();
+            let index = IndexModel::builder().keys(doc! { key: 1 }).options(options).build();
+            collection.create_index(index).await.map_err(|e| {
+                Error::msg(format!(
+                    "Failed to create the unique index of sys_user.{}, please resolve the conflicted users first. cause: {}",
+                    key, e
+                ))
+            })?;
+        }
         Ok(UserMongoRepository { inner, collection })
     }
 }
@@ -58,8 +77,11 @@ impl AsyncRepository<User> for UserMongoRepository {
         }
     }
 
-    async fn select_by_id(&self, id: i64) -> Result<User, Error> {
-        let filter = doc! { "id": id };
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<User, Error> {
+        let mut filter = doc! { "id": id };
+        if let Some(status) = status {
+            filter.insert("status", status.value());
+        }
         let user = self
             .collection
             .find_one(filter)
@@ -76,6 +98,10 @@ impl AsyncRepository<User> for UserMongoRepository {
         dynamic_mongo_update!(user, self.collection)
     }
 
+    async fn update_fields(&self, mut user: User, update_fields: &[&str]) -> Result<i64, Error> {
+        dynamic_mongo_update!(user, self.collection, Some(update_fields))
+    }
+
     async fn delete_all(&self) -> Result<u64, Error> {
         let result = self.collection.delete_many(doc! {}).await?;
         Ok(result.deleted_count)
@@ -86,4 +112,64 @@ impl AsyncRepository<User> for UserMongoRepository {
         let result = self.collection.delete_one(filter).await?;
         Ok(result.deleted_count)
     }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let filter = doc! { "id": id };
+        let update = doc! {
+            "$set": { "status": status.value(), "update_by": update_by, "update_time": to_bson(&UtcDateTime::now())? },
+            "$inc": { "version": 1 },
+        };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count)
+    }
+
+    async fn get_or_insert(&self, key_column: &str, key: &str, mut user: User) -> Result<(i64, bool), Error> {
+        if !USER_CLAIMS_SUB_COLUMNS.contains(&key_column) {
+            return Err(Error::msg(format!(
+                "Unsupported the unique key column '{}' of sys_user",
+                key_column
+            )));
+        }
+        // Notice: The concurrent insertion of the same key is rejected by the unique index, then get it again.
+        let filter = doc! { key_column: key, "del_flag": 0 };
+        if let Some(existing) = self.collection.find_one(filter.clone()).await? {
+            return Ok((existing_id(&existing, key_column, key)?, false));
+        }
+        let inserted: Result<i64, Error> = async { dynamic_mongo_insert!(user, self.collection) }.await;
+        match inserted {
+            Ok(id) => Ok((id, true)),
+            Err(e) => match self.collection.find_one(filter).await? {
+                Some(existing) => Ok((existing_id(&existing, key_column, key)?, false)),
+                None => Err(e),
+            },
+        }
+    }
+}
+
+// The id of the existing user, the document without id (e.g: inserted by hand) is rejected rather than returning
+// the invalid uid which would be logged in as the principal.
+fn existing_id(existing: &User, key_column: &str, key: &str) -> Result<i64, Error> {
+    existing.base.id.ok_or_else(|| {
+        Error::msg(format!(
+            "The existing user of {} '{}' has no id in sys_user",
+            key_column, key
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_existing_user_without_id_rejected() {
+        let mut existing = User::default();
+        existing.base.id = Some(1001);
+        assert_eq!(existing_id(&existing, "oidc_claims_sub", "sub-1").unwrap(), 1001);
+
+        existing.base.id = None;
+        let err = existing_id(&existing, "oidc_claims_sub", "sub-1").unwrap_err();
+        assert!(err.to_string().contains("has no id"), "{}", err);
+    }
 }
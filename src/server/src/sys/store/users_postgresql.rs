@@ -24,12 +24,17 @@ use crate::dynamic_postgres_query;
 use crate::dynamic_postgres_update;
 use crate::store::postgres::PostgresRepository;
 use crate::store::AsyncRepository;
-use anyhow::{Error, Ok};
+use crate::sys::store::USER_CLAIMS_SUB_COLUMNS;
+use crate::util::auths::SecurityContext;
+use anyhow::{anyhow, Error, Ok};
 use async_trait::async_trait;
+use botwaf_types::datetime::UtcDateTime;
 use botwaf_types::sys::user::User;
 use botwaf_types::PageRequest;
 use botwaf_types::PageResponse;
+use botwaf_types::RecordStatus;
 use common_telemetry::info;
+use sqlx::{PgPool, Row};
 
 pub struct UserPostgresRepository {
     inner: PostgresRepository<User>,
@@ -41,6 +46,63 @@ impl UserPostgresRepository {
             inner: PostgresRepository::new(config).await?,
         })
     }
+
+    /// Select the users by the non-empty fields, the soft deleted users are only included if explicitly requested,
+    /// e.g: the admin/audit views.
+    pub async fn select_with_deleted(
+        &self,
+        user: User,
+        page: PageRequest,
+        include_deleted: bool,
+    ) -> Result<(PageResponse, Vec<User>), Error> {
+        let result = dynamic_postgres_query!(
+            user,
+            "sys_user",
+            self.inner.get_pool(),
+            "update_time",
+            page,
+            include_deleted = include_deleted,
+            User
+        )?;
+        info!("query users: {:?}", result);
+        Ok((result.0, result.1))
+    }
+
+    /// Report the users conflicted case-insensitively by the name/email (or by the claims subjects), which refuses
+    /// the migration of the unique keys, see: v20261016-5/sys.user_identity_keys.ddl.sql and
+    /// v20261016-9/sys.user_claims_sub_keys.ddl.sql
+    pub async fn report_identity_conflicts(pool: &PgPool) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        let mut keys = vec![
+            ("name", String::from("lower(trim(name))")),
+            ("email", String::from("lower(trim(email))")),
+        ];
+        keys.extend(USER_CLAIMS_SUB_COLUMNS.iter().map(|c| (*c, c.to_string())));
+        for (column, key) in keys {
+            let query = format!(
+                "SELECT {1} AS k, string_agg(CAST(id AS TEXT), ',') AS ids FROM sys_user WHERE {0} IS NOT NULL AND trim({0}) != '' \
+                 AND del_flag = 0 GROUP BY {1} HAVING count(1) > 1",
+                column, key
+            );
+            let rows = match sqlx::query(&query).fetch_all(pool).await {
+                std::result::Result::Ok(rows) => rows,
+                Err(e) => {
+                    // e.g: The table not exists yet on the fresh install.
+                    tracing::debug!("Skip to report the users conflicted by {}. cause: {}", column, e);
+                    continue;
+                }
+            };
+            for row in rows {
+                conflicts.push(format!(
+                    "{} '{}' of users [{}]",
+                    column,
+                    row.get::<String, _>("k"),
+                    row.get::<String, _>("ids")
+                ));
+            }
+        }
+        conflicts
+    }
 }
 
 #[async_trait]
@@ -137,16 +199,19 @@ impl AsyncRepository<User> for UserPostgresRepository {
         //     Err(error) => Err(error.into()),
         // }
 
-        let result = dynamic_postgres_query!(user, "sys_user", self.inner.get_pool(), "update_time", page, User)?;
-        info!("query users: {:?}", result);
-        Ok((result.0, result.1))
+        self.select_with_deleted(user, page, false).await
     }
 
-    async fn select_by_id(&self, id: i64) -> Result<User, Error> {
-        let user = sqlx::query_as::<_, User>("SELECT * FROM sys_user WHERE id = $1 and del_flag = 0")
-            .bind(id)
-            .fetch_one(self.inner.get_pool())
-            .await?;
+    async fn select_by_id(&self, id: i64, status: Option<RecordStatus>) -> Result<User, Error> {
+        let query = match status {
+            Some(_) => "SELECT * FROM sys_user WHERE id = $1 and del_flag = 0 and status = $2",
+            None => "SELECT * FROM sys_user WHERE id = $1 and del_flag = 0",
+        };
+        let mut operator = sqlx::query_as::<_, User>(query).bind(id);
+        if let Some(status) = status {
+            operator = operator.bind(status.value());
+        }
+        let user = operator.fetch_one(self.inner.get_pool()).await?;
 
         info!("query user: {:?}", user);
         Ok(user)
@@ -222,6 +287,12 @@ impl AsyncRepository<User> for UserPostgresRepository {
         Ok(updated_id)
     }
 
+    async fn update_fields(&self, mut user: User, update_fields: &[&str]) -> Result<i64, Error> {
+        let updated_id = dynamic_postgres_update!(user, "sys_user", self.inner.get_pool(), Some(update_fields))?;
+        info!("Updated user.id: {:?}", updated_id);
+        Ok(updated_id)
+    }
+
     async fn delete_all(&self) -> Result<u64, Error> {
         let delete_result = sqlx::query("DELETE FROM sys_user")
             .execute(self.inner.get_pool())
@@ -240,4 +311,62 @@ impl AsyncRepository<User> for UserPostgresRepository {
         info!("Deleted result: {:?}", delete_result);
         Ok(delete_result.rows_affected())
     }
+
+    async fn set_status(&self, id: i64, status: RecordStatus) -> Result<u64, Error> {
+        let update_by = SecurityContext::get_instance().get_current_uname_for_store().await;
+        let update_result = sqlx::query(
+            "UPDATE sys_user SET status = $1, update_by = $2, update_time = $3, version = COALESCE(version, 0) + 1 WHERE id = $4 and del_flag = 0",
+        )
+        .bind(status.value())
+        .bind(update_by)
+        .bind(UtcDateTime::now())
+        .bind(id)
+        .execute(self.inner.get_pool())
+        .await?;
+
+        info!("Updated status result: {:?}", update_result);
+        Ok(update_result.rows_affected())
+    }
+
+    async fn get_or_insert(&self, key_column: &str, key: &str, user: User) -> Result<(i64, bool), Error> {
+        if !USER_CLAIMS_SUB_COLUMNS.contains(&key_column) {
+            return Err(anyhow!(
+                "Unsupported the unique key column '{}' of sys_user",
+                key_column
+            ));
+        }
+        let query = format!("SELECT id FROM sys_user WHERE {} = $1 AND del_flag = 0", key_column);
+        // Notice: Retry once if conflicted with the concurrent insertion of the same key (unique violation),
+        // which is visible after the other transaction committed.
+        let mut last_error = None;
+        for _ in 0..2 {
+            let (query, sub, user) = (query.to_owned(), key.to_owned(), user.clone());
+            let result = self
+                .inner
+                .with_transaction(|tx| {
+                    Box::pin(async move {
+                        let existing = sqlx::query_scalar::<_, i64>(&query)
+                            .bind(sub)
+                            .fetch_optional(&mut **tx)
+                            .await?;
+                        if let Some(id) = existing {
+                            return Ok((id, false));
+                        }
+                        let mut user = user;
+                        let inserted_id: Result<i64, Error> =
+                            async { dynamic_postgres_insert!(user, "sys_user", &mut **tx) }.await;
+                        Ok((inserted_id?, true))
+                    })
+                })
+                .await;
+            match result {
+                std::result::Result::Ok(result) => {
+                    info!("Got or inserted user.id: {:?} by {}: {}", result, key_column, key);
+                    return Ok(result);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("Failed to get or insert user by {}: {}", key_column, key)))
+    }
 }
@@ -0,0 +1,394 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{self, PreAuthGateProperties};
+use crate::mgmt::apm::metrics::{
+    BOTWAF_AUTH_FAILED_COST_SECONDS_TOTAL, BOTWAF_AUTH_FAILED_TOTAL, BOTWAF_AUTH_GATE_REJECTED_TOTAL,
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<AuthGate> = Arc::new(AuthGate::new(&config::get_config().auth.pre_auth_gate));
+}
+
+/// The marker of the failed authentication response, which is used to account the cost of the failed attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthFailure;
+
+/// The expected field of the authentication request JSON body, which is validated before any crypto.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub max_len: usize,
+    // The exactly number of hex digits with the optional '0x' prefix, e.g: the wallet address and signature.
+    pub hex_len: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GateRejection {
+    RateLimited { retry_after_secs: u64 },
+    PayloadTooLarge,
+    Malformed(String),
+}
+
+impl GateRejection {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            GateRejection::RateLimited { .. } => "rate_limited",
+            GateRejection::PayloadTooLarge => "payload_too_large",
+            GateRejection::Malformed(_) => "malformed",
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    // The number of consecutive rejections, which is used to exponential backoff the Retry-After.
+    rejections: u32,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: now,
+            rejections: 0,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, refill_per_sec: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_per_sec).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+/// The cheap pre-authentication gate, e.g: throttle the credential stuffing against password/OIDC/wallet login
+/// by the per IP and per fingerprint token buckets, which are separated from the general rate limits.
+pub struct AuthGate {
+    config: PreAuthGateProperties,
+    ip_buckets: Mutex<HashMap<String, TokenBucket>>,
+    fingerprint_buckets: Mutex<HashMap<String, TokenBucket>>,
+    pubkey_buckets: Mutex<HashMap<String, TokenBucket>>,
+    user_buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl AuthGate {
+    pub fn get() -> Arc<AuthGate> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(config: &PreAuthGateProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            ip_buckets: Mutex::new(HashMap::new()),
+            fingerprint_buckets: Mutex::new(HashMap::new()),
+            pubkey_buckets: Mutex::new(HashMap::new()),
+            user_buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn max_body_bytes(&self) -> usize {
+        self.config.max_body_bytes
+    }
+
+    /// Acquire a token of the client IP bucket, which is checked before reading the body.
+    pub fn acquire_ip(&self, client_ip: &str) -> Result<(), GateRejection> {
+        self.acquire_bucket(
+            &self.ip_buckets,
+            client_ip,
+            self.config.ip_capacity,
+            self.config.ip_refill_per_sec,
+        )
+    }
+
+    /// Acquire a token of the fingerprint bucket, e.g: the distributed attacks with the same fingerprint.
+    pub fn acquire_fingerprint(&self, fingerprint: &str) -> Result<(), GateRejection> {
+        self.acquire_bucket(
+            &self.fingerprint_buckets,
+            fingerprint,
+            self.config.fingerprint_capacity,
+            self.config.fingerprint_refill_per_sec,
+        )
+    }
+
+    /// Acquire a token of the stricter client IP bucket of the login pubkey endpoint, as each request generates
+    /// the RSA keypair, which is checked in addition to the general client IP bucket.
+    pub fn acquire_pubkey_ip(&self, client_ip: &str) -> Result<(), GateRejection> {
+        self.acquire_bucket(
+            &self.pubkey_buckets,
+            client_ip,
+            self.config.pubkey_ip_capacity,
+            self.config.pubkey_ip_refill_per_sec,
+        )
+    }
+
+    /// Acquire a token of the authenticated user bucket, which is checked on each attempt of verifying the current
+    /// password, e.g: brute force the current password by the hijacked session across the rotated IPs.
+    pub fn acquire_user(&self, uid: i64) -> Result<(), GateRejection> {
+        self.acquire_bucket(
+            &self.user_buckets,
+            &uid.to_string(),
+            self.config.user_capacity,
+            self.config.user_refill_per_sec,
+        )
+    }
+
+    fn acquire_bucket(
+        &self,
+        buckets: &Mutex<HashMap<String, TokenBucket>>,
+        key: &str,
+        capacity: u32,
+        refill_per_sec: f64,
+    ) -> Result<(), GateRejection> {
+        let now = Instant::now();
+        let capacity = capacity as f64;
+        let mut buckets = buckets.lock().unwrap();
+        if buckets.len() >= self.config.max_tracked_keys && !buckets.contains_key(key) {
+            // Evict the idle buckets, which are equivalent to the new buckets.
+            buckets.retain(|_, bucket| {
+                bucket.refill(capacity, refill_per_sec, now);
+                bucket.tokens < capacity
+            });
+        }
+        let bucket = buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| TokenBucket::new(capacity, now));
+        bucket.refill(capacity, refill_per_sec, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.rejections = 0;
+            Ok(())
+        } else {
+            let retry_after_secs = self.retry_after_secs(bucket.rejections);
+            bucket.rejections = bucket.rejections.saturating_add(1);
+            Err(GateRejection::RateLimited { retry_after_secs })
+        }
+    }
+
+    fn retry_after_secs(&self, rejections: u32) -> u64 {
+        let factor = 1u64.checked_shl(rejections).unwrap_or(u64::MAX);
+        self.config
+            .base_retry_after_secs
+            .as_secs()
+            .max(1)
+            .saturating_mul(factor)
+            .min(self.config.max_retry_after_secs.as_secs())
+    }
+
+    /// Validate the size and shape of the JSON body, and returns the fingerprint token if present.
+    pub fn validate_body(
+        &self,
+        body: &[u8],
+        fields: &[FieldSpec],
+        fingerprint_field: Option<&str>,
+    ) -> Result<Option<String>, GateRejection> {
+        if body.len() > self.config.max_body_bytes {
+            return Err(GateRejection::PayloadTooLarge);
+        }
+        let value: serde_json::Value =
+            serde_json::from_slice(body).map_err(|_| GateRejection::Malformed("Invalid JSON body".to_owned()))?;
+        let obj = value
+            .as_object()
+            .ok_or_else(|| GateRejection::Malformed("The JSON body must be an object".to_owned()))?;
+        for spec in fields {
+            let field = obj
+                .get(spec.name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| GateRejection::Malformed(format!("Missing the string field '{}'", spec.name)))?;
+            if field.is_empty() || field.len() > spec.max_len {
+                return Err(GateRejection::Malformed(format!(
+                    "Invalid length of field '{}'",
+                    spec.name
+                )));
+            }
+            if let Some(hex_len) = spec.hex_len {
+                let digits = field.strip_prefix("0x").unwrap_or(field);
+                if digits.len() != hex_len || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(GateRejection::Malformed(format!(
+                        "Invalid hex of field '{}'",
+                        spec.name
+                    )));
+                }
+            }
+        }
+        Ok(fingerprint_field
+            .and_then(|name| obj.get(name))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_owned()))
+    }
+
+    pub fn record_rejection(endpoint: &str, rejection: &GateRejection) {
+        BOTWAF_AUTH_GATE_REJECTED_TOTAL
+            .with_label_values(&[endpoint, rejection.reason()])
+            .inc();
+    }
+
+    /// Account the processing time of the failed attempt, as the approximate CPU cost for the attacks visibility.
+    pub fn record_failure(endpoint: &str, elapsed: Duration) {
+        BOTWAF_AUTH_FAILED_TOTAL.with_label_values(&[endpoint]).inc();
+        BOTWAF_AUTH_FAILED_COST_SECONDS_TOTAL
+            .with_label_values(&[endpoint])
+            .inc_by(elapsed.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::duration::DurationSecs;
+
+    fn mock_config() -> PreAuthGateProperties {
+        PreAuthGateProperties {
+            ip_capacity: 3,
+            ip_refill_per_sec: 0.0,
+            fingerprint_capacity: 2,
+            fingerprint_refill_per_sec: 0.0,
+            pubkey_ip_capacity: 2,
+            pubkey_ip_refill_per_sec: 0.0,
+            user_capacity: 2,
+            user_refill_per_sec: 0.0,
+            base_retry_after_secs: DurationSecs::from_secs(1),
+            max_retry_after_secs: DurationSecs::from_secs(8),
+            ..PreAuthGateProperties::default()
+        }
+    }
+
+    #[test]
+    fn test_ip_bucket_exponential_retry_after() {
+        let gate = AuthGate::new(&mock_config());
+        for _ in 0..3 {
+            assert!(gate.acquire_ip("10.0.0.1").is_ok());
+        }
+        let retry_afters: Vec<u64> = (0..5)
+            .map(|_| match gate.acquire_ip("10.0.0.1") {
+                Err(GateRejection::RateLimited { retry_after_secs }) => retry_after_secs,
+                other => panic!("Unexpected: {:?}", other),
+            })
+            .collect();
+        assert_eq!(retry_afters, vec![1, 2, 4, 8, 8]);
+        // The other IPs are not affected.
+        assert!(gate.acquire_ip("10.0.0.2").is_ok());
+    }
+
+    #[test]
+    fn test_fingerprint_bucket_independent_of_ip() {
+        let gate = AuthGate::new(&mock_config());
+        assert!(gate.acquire_fingerprint("fp-1").is_ok());
+        assert!(gate.acquire_fingerprint("fp-1").is_ok());
+        assert!(gate.acquire_fingerprint("fp-1").is_err());
+        assert!(gate.acquire_fingerprint("fp-2").is_ok());
+    }
+
+    #[test]
+    fn test_pubkey_ip_bucket_stricter_than_ip() {
+        let gate = AuthGate::new(&mock_config());
+        assert!(gate.acquire_pubkey_ip("10.0.0.1").is_ok());
+        assert!(gate.acquire_pubkey_ip("10.0.0.1").is_ok());
+        assert_eq!(
+            gate.acquire_pubkey_ip("10.0.0.1"),
+            Err(GateRejection::RateLimited { retry_after_secs: 1 })
+        );
+        // The general IP bucket is still available for the other auth endpoints.
+        assert!(gate.acquire_ip("10.0.0.1").is_ok());
+        assert!(gate.acquire_pubkey_ip("10.0.0.2").is_ok());
+    }
+
+    #[test]
+    fn test_user_bucket_independent_of_ip_and_fingerprint() {
+        let gate = AuthGate::new(&mock_config());
+        assert!(gate.acquire_user(1).is_ok());
+        assert!(gate.acquire_user(1).is_ok());
+        assert_eq!(
+            gate.acquire_user(1),
+            Err(GateRejection::RateLimited { retry_after_secs: 1 })
+        );
+        // The other users, client IPs and fingerprints are not affected.
+        assert!(gate.acquire_user(2).is_ok());
+        assert!(gate.acquire_ip("10.0.0.1").is_ok());
+        assert!(gate.acquire_fingerprint("fp-1").is_ok());
+    }
+
+    #[test]
+    fn test_evict_idle_buckets() {
+        let mut config = mock_config();
+        config.max_tracked_keys = 2;
+        config.ip_refill_per_sec = 1000.0;
+        let gate = AuthGate::new(&config);
+        assert!(gate.acquire_ip("10.0.0.1").is_ok());
+        assert!(gate.acquire_ip("10.0.0.2").is_ok());
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(gate.acquire_ip("10.0.0.3").is_ok());
+        assert_eq!(gate.ip_buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_body_shape() {
+        let gate = AuthGate::new(&mock_config());
+        let fields = [
+            FieldSpec {
+                name: "address",
+                max_len: 42,
+                hex_len: Some(40),
+            },
+            FieldSpec {
+                name: "message",
+                max_len: 16,
+                hex_len: None,
+            },
+        ];
+        let address = format!("0x{}", "a".repeat(40));
+        let body = serde_json::json!({"address": address, "message": "hello", "fpToken": "fp-1"}).to_string();
+        assert_eq!(
+            gate.validate_body(body.as_bytes(), &fields, Some("fpToken")),
+            Ok(Some("fp-1".to_owned()))
+        );
+
+        let body = serde_json::json!({"address": "0xZZ", "message": "hello"}).to_string();
+        assert!(matches!(
+            gate.validate_body(body.as_bytes(), &fields, None),
+            Err(GateRejection::Malformed(_))
+        ));
+        let body = serde_json::json!({"address": address}).to_string();
+        assert!(matches!(
+            gate.validate_body(body.as_bytes(), &fields, None),
+            Err(GateRejection::Malformed(_))
+        ));
+        assert!(matches!(
+            gate.validate_body(b"[1,2]", &fields, None),
+            Err(GateRejection::Malformed(_))
+        ));
+        let oversized = vec![b' '; gate.max_body_bytes() + 1];
+        assert_eq!(
+            gate.validate_body(&oversized, &fields, None),
+            Err(GateRejection::PayloadTooLarge)
+        );
+    }
+}
@@ -18,22 +18,29 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use super::{auth_gate::AuthFailure, i18n};
 use crate::{
     config::config::AppConfig,
-    sys::{handler::auth_handler::PrincipalType, route::auth_router::EXCLUDED_PREFIX_PATHS},
+    sys::{
+        bootstrap::BootstrapManager, handler::auth_handler::PrincipalType, route::auth_router::EXCLUDED_PREFIX_PATHS,
+    },
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
 };
 use axum::body::Body;
 use botwaf_types::sys::auth::{LoggedResponse, TokenWrapper};
-use botwaf_utils::{base64s::Base64Helper, webs};
+use botwaf_utils::{base64s::Base64Helper, inets, secrets::SecretHelper, webs};
 use chrono::{Duration, Utc};
 use common_telemetry::{debug, error, warn};
-use hyper::{HeaderMap, Response, StatusCode, Uri};
+use hyper::{header, header::HeaderValue, HeaderMap, Method, Request, Response, StatusCode, Uri};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
-use tower_cookies::cookie::Cookie;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc};
+use tower_cookies::cookie::{time, Cookie, CookieBuilder, SameSite};
 
 lazy_static! {
     // singleton instance.
@@ -42,6 +49,21 @@ lazy_static! {
 
 pub static DEFAULT_BY: &'static str = "0";
 
+// The claim name of the user language in the access token, see: handle_login_success
+pub static LANG_CLAIM_NAME: &'static str = "lang";
+
+// The double-submit CSRF token cookie and request header names.
+pub static CSRF_COOKIE_NAME: &'static str = "csrf";
+pub static CSRF_HEADER_NAME: &'static str = "X-CSRF-Token";
+
+// The request header of the static API keys, and the claim name of the granted scopes (space separated).
+pub static API_KEY_HEADER_NAME: &'static str = "X-API-Key";
+pub static SCOPES_CLAIM_NAME: &'static str = "scopes";
+
+// The forwarded scheme and host request headers of the TLS-terminating proxies, only honored from the trusted.
+pub static X_FORWARDED_PROTO_HEADER_NAME: &'static str = "X-Forwarded-Proto";
+pub static X_FORWARDED_HOST_HEADER_NAME: &'static str = "X-Forwarded-Host";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AuthUserClaims {
     pub ptype: PrincipalType,
@@ -49,6 +71,12 @@ pub struct AuthUserClaims {
     pub uname: String,
     pub email: String,
     pub exp: usize,
+    #[serde(default)]
+    pub iat: usize,
+    // The issued at (milliseconds), which is compared with the token epoch of the user, e.g: changed password,
+    // since the 'iat' of seconds can't distinguish the re-login in the same second, see: get_issued_millis
+    #[serde(default)]
+    pub iat_ms: i64,
     pub ext: Option<HashMap<String, String>>,
 }
 
@@ -61,11 +89,12 @@ pub fn create_jwt(
     is_refresh: bool,
     extra_claims: Option<HashMap<String, String>>,
 ) -> String {
-    let expiration = Utc::now()
+    let now = Utc::now();
+    let expiration = now
         .checked_add_signed(Duration::milliseconds(if is_refresh {
-            config.auth.jwt_validity_rk.unwrap() as i64
+            config.auth.jwt_validity_rk.unwrap().as_millis() as i64
         } else {
-            config.auth.jwt_validity_ak.unwrap() as i64
+            config.auth.jwt_validity_ak.unwrap().as_millis() as i64
         }))
         .expect("valid timestamp")
         .timestamp();
@@ -76,6 +105,8 @@ pub fn create_jwt(
         uname: uname.to_owned(),
         email: email.to_owned(),
         exp: expiration as usize,
+        iat: now.timestamp() as usize,
+        iat_ms: now.timestamp_millis(),
         ext: extra_claims,
     };
 
@@ -84,6 +115,15 @@ pub fn create_jwt(
     encode(&header, &claims, &EncodingKey::from_secret(secret)).expect("Failed to encode jwt")
 }
 
+/// The issued at (milliseconds) of the token, or of the seconds for the tokens without 'iat_ms'.
+pub fn get_issued_millis(claims: &AuthUserClaims) -> i64 {
+    if claims.iat_ms > 0 {
+        claims.iat_ms
+    } else {
+        claims.iat as i64 * 1000
+    }
+}
+
 pub fn validate_jwt(config: &Arc<AppConfig>, token: &str) -> Result<AuthUserClaims, jsonwebtoken::errors::Error> {
     let secret = &Base64Helper::decode(&config.auth_jwt_secret.to_owned()).unwrap();
     let validation = Validation::new(config.auth_jwt_algorithm);
@@ -99,38 +139,114 @@ pub fn auth_resp_redirect_or_json(
     message: &str,
     cookies: Option<(Option<Cookie>, Option<Cookie>, Option<Cookie>)>,
 ) -> Response<Body> {
-    let (ak, rk, _) = match &cookies {
+    let (ak, rk, csrf) = match &cookies {
         Some(triple) => (
             triple.to_owned().0.map(|c| TokenWrapper {
                 value: c.value().to_string(),
-                expires_in: config.auth.jwt_validity_ak.unwrap(),
+                expires_in: config.auth.jwt_validity_ak.unwrap().as_millis() as u64,
             }),
             triple.to_owned().1.map(|c| TokenWrapper {
                 value: c.value().to_string(),
-                expires_in: config.auth.jwt_validity_rk.unwrap(),
+                expires_in: config.auth.jwt_validity_rk.unwrap().as_millis() as u64,
             }),
             triple.2.to_owned(),
         ),
         None => (None, None, None),
     };
 
+    // Notice: Only the json message is localized, the redirect url fragment is the troubleshooting key.
+    let new_ak = cookies.as_ref().and_then(|triple| triple.0.as_ref()).map(|c| c.value());
+    let locale = i18n::negotiate(get_user_lang(config, headers, new_ak).as_deref(), headers);
+
     let json = LoggedResponse {
         errcode: status.as_u16() as i16,
-        errmsg: message.to_string(),
+        errmsg: i18n::translate(locale, message).to_string(),
         access_token: ak,
         refresh_token: rk,
-        redirect_url: Some(join_context_path(&config, redirect_url.to_owned())),
+        // Only the double-submit CSRF token, not the others e.g: the OAuth2 state.
+        csrf_token: csrf
+            .filter(|c| c.name() == CSRF_COOKIE_NAME)
+            .map(|c| c.value().to_string()),
+        redirect_url: Some(to_external_url(&config, headers, redirect_url.to_owned())),
     };
     let json_str = serde_json::to_string(&json).unwrap();
 
-    webs::response_redirect_or_json(
+    let mut response = webs::response_redirect_or_json(
         status,
         headers,
         cookies,
         &json.redirect_url.unwrap(),
         &message,
         &json_str,
-    )
+    );
+    if let Ok(locale) = HeaderValue::from_str(locale) {
+        response.headers_mut().insert(header::CONTENT_LANGUAGE, locale);
+    }
+    // Notice: The failure status may be lost after redirected, so mark it for the cost accounting of pre-auth gate.
+    if !status.is_success() {
+        response.extensions_mut().insert(AuthFailure);
+    }
+    response
+}
+
+/// Get the language of the authenticated user from the access token claims, i.e: the newly issued on the login,
+/// or of the request by the bearer header or cookie.
+pub fn get_user_lang(config: &Arc<AppConfig>, headers: &HeaderMap, new_ak: Option<&str>) -> Option<String> {
+    let request_ak = || {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).to_owned())
+            .or_else(|| {
+                headers
+                    .get(header::COOKIE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| webs::get_cookie_from_str(value, &config.auth_jwt_ak_name))
+            })
+    };
+    new_ak
+        .map(|ak| ak.to_owned())
+        .or_else(request_ak)
+        .and_then(|ak| validate_jwt(config, &ak).ok())
+        .and_then(|claims| get_claims_lang(&claims).map(|lang| lang.to_owned()))
+}
+
+pub fn get_claims_lang(claims: &AuthUserClaims) -> Option<&str> {
+    claims
+        .ext
+        .as_ref()
+        .and_then(|ext| ext.get(LANG_CLAIM_NAME))
+        .map(|lang| lang.as_str())
+}
+
+/// Hash the plain password as same as the login page, i.e: base64(sha256(password)), see: static/login.html
+pub fn hash_login_password(password: &str) -> String {
+    Base64Helper::encode(&Sha256::digest(password.as_bytes()))
+}
+
+/// Hash the login password (i.e: the client hashed) with Argon2 to be stored, e.g: the PHC string '$argon2id$...'
+pub fn hash_stored_password(login_password: &[u8]) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(login_password, &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash the password. {}", e))
+}
+
+/// Verify the login password against the stored, which is the Argon2 PHC string, or the legacy stored as same as
+/// the login password, e.g: created by the first-run bootstrap of the earlier versions.
+pub fn verify_stored_password(login_password: &[u8], stored_password: &str) -> bool {
+    if stored_password.starts_with("$argon2") {
+        match PasswordHash::new(stored_password) {
+            Ok(hash) => Argon2::default().verify_password(login_password, &hash).is_ok(),
+            Err(e) => {
+                warn!("Invalid the stored Argon2 password hash. {}", e);
+                false
+            }
+        }
+    } else {
+        constant_time_eq(login_password, stored_password.as_bytes())
+    }
 }
 
 // Time-constant safety message comparison.
@@ -141,6 +257,34 @@ pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
     a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
+/// Create the double-submit CSRF token cookie, which must be readable by the browser scripts.
+pub fn create_csrf_cookie<'a>(config: &AppConfig, headers: &HeaderMap) -> Cookie<'a> {
+    CookieBuilder::new(CSRF_COOKIE_NAME, SecretHelper::generate_secret_base64(32))
+        .path("/")
+        .max_age(time::Duration::milliseconds(
+            config.auth.jwt_validity_rk.unwrap().as_millis() as i64,
+        ))
+        .secure(is_secure_request(config, headers))
+        .http_only(false)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+/// Whether the state-changing request requires the CSRF token verification.
+pub fn is_csrf_required(config: &AppConfig, method: &Method) -> bool {
+    config.auth.csrf_protection.unwrap_or(true) && !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Verify the double-submit CSRF token, the header must match the cookie.
+pub fn verify_csrf_token(headers: &HeaderMap) -> bool {
+    let cookie = webs::get_cookie_from_headers(CSRF_COOKIE_NAME, headers);
+    let header = headers.get(CSRF_HEADER_NAME).and_then(|v| v.to_str().ok());
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() => constant_time_eq(cookie.as_bytes(), header.as_bytes()),
+        _ => false,
+    }
+}
+
 pub fn clean_context_path<'a>(ctx_path: &'a Option<String>, path: &'a str) -> &'a str {
     match &ctx_path {
         // Remove the prefix context path.
@@ -150,6 +294,78 @@ pub fn clean_context_path<'a>(ctx_path: &'a Option<String>, path: &'a str) -> &'
     }
 }
 
+/// Join the context path and resolve against the effective external base URL, which is the absolute URL for the
+/// redirects, or the relative if the base URL is underivable (e.g: without the Host header).
+pub fn to_external_url(config: &AppConfig, headers: &HeaderMap, path: String) -> String {
+    let path = join_context_path(config, path);
+    if !path.starts_with('/') || path.starts_with("//") {
+        return path;
+    }
+    match external_base_url(config, headers) {
+        Some(base) => format!("{}{}", base, path),
+        None => path,
+    }
+}
+
+/// The effective external base URL, i.e: the configured 'auth.external-base-url', otherwise derived from the
+/// forwarded scheme and host of the trusted proxies, fallback to the server TLS and the Host header.
+/// Notice: The forwarded headers of the untrusted peers were already stripped, see: strip_untrusted_forwarded
+pub fn external_base_url(config: &AppConfig, headers: &HeaderMap) -> Option<String> {
+    if let Some(base) = config.auth.get_external_base_url() {
+        return Some(base.to_owned());
+    }
+    let host = first_header_value(headers, X_FORWARDED_HOST_HEADER_NAME)
+        .or_else(|| first_header_value(headers, header::HOST.as_str()))?;
+    Some(format!("{}://{}", external_scheme(config, headers), host))
+}
+
+/// The scheme seen by the browser, which is https behind the TLS-terminating load balancer even though the
+/// server only sees the plaintext.
+pub fn external_scheme(config: &AppConfig, headers: &HeaderMap) -> String {
+    if let Some(base) = config.auth.get_external_base_url() {
+        return base.split("://").next().unwrap_or_default().to_lowercase();
+    }
+    first_header_value(headers, X_FORWARDED_PROTO_HEADER_NAME)
+        .map(|proto| proto.to_lowercase())
+        .filter(|proto| proto == "http" || proto == "https")
+        .unwrap_or_else(|| {
+            let scheme = if config.server.tls.enabled { "https" } else { "http" };
+            scheme.to_owned()
+        })
+}
+
+/// Whether to set the Secure flag of the cookies, i.e: the browser is served over https.
+pub fn is_secure_request(config: &AppConfig, headers: &HeaderMap) -> bool {
+    external_scheme(config, headers) == "https"
+}
+
+/// Strip the forwarded scheme and host headers unless from the trusted proxies, since they could be set by anyone,
+/// so that the derived external URLs and the Secure flag of the cookies are never spoofed.
+pub fn strip_untrusted_forwarded(config: &AppConfig, req: &mut Request<Body>) {
+    if !is_trusted_proxy(config, req.extensions().get::<SocketAddr>()) {
+        req.headers_mut().remove(X_FORWARDED_PROTO_HEADER_NAME);
+        req.headers_mut().remove(X_FORWARDED_HOST_HEADER_NAME);
+    }
+}
+
+/// Whether the connection peer (i.e: the remote address, not the X-Forwarded-For) is in the trusted proxies.
+pub fn is_trusted_proxy(config: &AppConfig, peer: Option<&SocketAddr>) -> bool {
+    peer.is_some_and(|peer| {
+        let ip = peer.ip();
+        config.auth.trusted_proxies.iter().any(|p| inets::is_ip_in_cidr(&ip, p))
+    })
+}
+
+// The first value of the comma separated header, e.g: 'X-Forwarded-Proto: https, http' of the multi hops.
+fn first_header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
 pub fn join_context_path(config: &AppConfig, path: String) -> String {
     // Absolute URI not needs to join context path.
     let schema = url::Url::parse(path.as_str())
@@ -175,7 +391,16 @@ pub fn is_anonymous_request(config: &Arc<AppConfig>, uri: &Uri) -> bool {
     if EXCLUDED_PREFIX_PATHS.iter().any(|p| path.starts_with(p)) {
         return true;
     }
-    // 1.2 According to the configuration of anonymous authentication path.
+    // 1.2 According to the configuration of protected path, which takes precedence over the anonymous path.
+    if config
+        .auth_protected_glob_matcher
+        .as_ref()
+        .map(|glob| glob.is_match(path))
+        .unwrap_or(false)
+    {
+        return false;
+    }
+    // 1.3 According to the configuration of anonymous authentication path.
     if config
         .auth_anonymous_glob_matcher
         .as_ref()
@@ -188,45 +413,258 @@ pub fn is_anonymous_request(config: &Arc<AppConfig>, uri: &Uri) -> bool {
     false
 }
 
-#[derive(Clone, Debug)]
-pub struct SecurityContext {
-    pub current_user: Arc<RwLock<Option<AuthUserClaims>>>,
+/// The identity of the verified peer certificate of the TLS connection, which is inserted into the request
+/// extensions by the listener only, so it cannot be spoofed by the request headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertIdentity {
+    // The subject distinguished name, e.g: CN=svc-a,O=Example
+    pub subject: String,
+    pub common_name: Option<String>,
+    // The DNS, URI and email subject alternative names.
+    pub sans: Vec<String>,
 }
 
+impl ClientCertIdentity {
+    pub fn from_der(der: &[u8]) -> Result<Self, openssl::error::ErrorStack> {
+        let cert = openssl::x509::X509::from_der(der)?;
+        let entries = cert
+            .subject_name()
+            .entries()
+            .map(|entry| {
+                let name = entry.object().nid().short_name().unwrap_or("UNKNOWN").to_owned();
+                let value = entry.data().as_utf8().map(|v| v.to_string()).unwrap_or_default();
+                (name, value)
+            })
+            .collect::<Vec<_>>();
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.dnsname().or(name.uri()).or(name.email()).map(|n| n.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            subject: entries
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(","),
+            common_name: entries
+                .iter()
+                .find(|(name, _)| name == "CN")
+                .map(|(_, value)| value.to_owned()),
+            sans,
+        })
+    }
+
+    /// Returns the allowlisted principal of this identity, matched by the subject, common name or any SAN.
+    pub fn match_allowlist(&self, allowlist: &[String]) -> Option<String> {
+        let candidates = std::iter::once(&self.subject)
+            .chain(self.common_name.iter())
+            .chain(self.sans.iter());
+        for candidate in candidates {
+            if allowlist.iter().any(|allowed| allowed == candidate) {
+                return Some(candidate.to_owned());
+            }
+        }
+        None
+    }
+}
+
+/// Authenticate the verified client certificate identity by the configured allowlist, the claims are bound
+/// to the security context directly without minting the JWT.
+pub fn authenticate_client_cert(config: &AppConfig, identity: Option<&ClientCertIdentity>) -> Option<AuthUserClaims> {
+    let identity = identity?;
+    let principal = match identity.match_allowlist(&config.auth.client_cert_allowlist) {
+        Some(principal) => principal,
+        None => {
+            warn!("Rejected the unknown client certificate subject: {}", identity.subject);
+            return None;
+        }
+    };
+    let expiration = Utc::now()
+        + Duration::milliseconds(
+            config
+                .auth
+                .jwt_validity_ak
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(3600_000),
+        );
+    let mut ext = HashMap::new();
+    ext.insert("subject".to_owned(), identity.subject.to_owned());
+    Some(AuthUserClaims {
+        ptype: PrincipalType::ClientCert,
+        uid: 0,
+        uname: principal,
+        email: String::from(""),
+        exp: expiration.timestamp() as usize,
+        iat: Utc::now().timestamp() as usize,
+        iat_ms: Utc::now().timestamp_millis(),
+        ext: Some(ext),
+    })
+}
+
+/// Authenticate the identity header set by the pre-authenticated gateway, which is only trusted if the
+/// connection peer is in the trusted proxies, otherwise the header is ignored as it could be set by anyone.
+pub fn authenticate_trusted_header(
+    config: &AppConfig,
+    headers: &HeaderMap,
+    peer: Option<&SocketAddr>,
+) -> Option<AuthUserClaims> {
+    let header_name = config.auth.trusted_identity_header.as_ref()?;
+    let principal = headers
+        .get(header_name.as_str())
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())?;
+    let trusted_peer = peer.filter(|peer| is_trusted_proxy(config, Some(peer)));
+    let peer = match trusted_peer {
+        Some(peer) => peer,
+        None => {
+            warn!(
+                "Ignored the identity header '{}' from the untrusted peer: {:?}",
+                header_name, peer
+            );
+            return None;
+        }
+    };
+    let expiration = Utc::now()
+        + Duration::milliseconds(
+            config
+                .auth
+                .jwt_validity_ak
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(3600_000),
+        );
+    let mut ext = HashMap::new();
+    ext.insert("proxy".to_owned(), peer.ip().to_string());
+    Some(AuthUserClaims {
+        ptype: PrincipalType::TrustedHeader,
+        uid: 0,
+        uname: principal.to_owned(),
+        email: String::from(""),
+        exp: expiration.timestamp() as usize,
+        iat: Utc::now().timestamp() as usize,
+        iat_ms: Utc::now().timestamp_millis(),
+        ext: Some(ext),
+    })
+}
+
+/// Authenticate the static API key of the machine clients, which is bound as the principal of the key name with
+/// the granted scopes only, see: has_scope
+pub fn authenticate_api_key(config: &AppConfig, headers: &HeaderMap) -> Option<AuthUserClaims> {
+    let api_key = headers.get(API_KEY_HEADER_NAME).and_then(|v| v.to_str().ok())?;
+    let matched = config
+        .auth
+        .api_keys
+        .iter()
+        .find(|k| constant_time_eq(k.api_key.as_bytes(), api_key.as_bytes()));
+    let matched = match matched {
+        Some(matched) => matched,
+        None => {
+            warn!("Rejected the unknown API key by the header '{}'", API_KEY_HEADER_NAME);
+            return None;
+        }
+    };
+    let expiration = Utc::now()
+        + Duration::milliseconds(
+            config
+                .auth
+                .jwt_validity_ak
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(3600_000),
+        );
+    let mut ext = HashMap::new();
+    ext.insert(SCOPES_CLAIM_NAME.to_owned(), matched.scopes.join(" "));
+    Some(AuthUserClaims {
+        ptype: PrincipalType::ApiKey,
+        uid: 0,
+        uname: matched.name.to_owned(),
+        email: String::from(""),
+        exp: expiration.timestamp() as usize,
+        iat: Utc::now().timestamp() as usize,
+        iat_ms: Utc::now().timestamp_millis(),
+        ext: Some(ext),
+    })
+}
+
+/// Whether the principal is the API key with the granted scope, e.g: events:export
+pub fn has_scope(claims: &AuthUserClaims, scope: &str) -> bool {
+    matches!(claims.ptype, PrincipalType::ApiKey)
+        && claims
+            .ext
+            .as_ref()
+            .and_then(|ext| ext.get(SCOPES_CLAIM_NAME))
+            .is_some_and(|scopes| scopes.split(' ').any(|s| s == scope))
+}
+
+/// Whether the principal (i.e: of the request, see: web::RequireAdmin) is the admin.
+pub fn is_admin(config: &AppConfig, claims: &AuthUserClaims) -> bool {
+    if !is_user_principal(claims) {
+        return false;
+    }
+    // Notice: The initial administrator created by the first-run bootstrap is also granted.
+    let mut admin_users = config.auth.admin_users.to_owned().unwrap_or_default();
+    if let Some(bootstrap_admin) = BootstrapManager::get_admin() {
+        admin_users.push(bootstrap_admin.to_string());
+    }
+    is_any_principal_of(claims, &admin_users)
+}
+
+/// Whether the principal is the operator, i.e: the operator users or the admin.
+pub fn is_operator(config: &AppConfig, claims: &AuthUserClaims) -> bool {
+    if is_admin(config, claims) {
+        return true;
+    }
+    if !is_user_principal(claims) {
+        return false;
+    }
+    let operator_users = config.auth.operator_users.to_owned().unwrap_or_default();
+    is_any_principal_of(claims, &operator_users)
+}
+
+// Notice: Only the local (password) and OIDC users are granted the roles, since the names of the other principals
+// (e.g: the client cert SAN, the trusted identity header or the API key name) are never bound to the user store,
+// which could be same as the admin user or email, i.e: the roles are never granted by the name collision.
+fn is_user_principal(claims: &AuthUserClaims) -> bool {
+    matches!(claims.ptype, PrincipalType::Password | PrincipalType::OIDC)
+}
+
+fn is_any_principal_of(claims: &AuthUserClaims, users: &[String]) -> bool {
+    [&claims.uname, &claims.email]
+        .iter()
+        .filter(|principal| !principal.is_empty())
+        .any(|principal| users.iter().any(|u| u.eq_ignore_ascii_case(principal)))
+}
+
+tokio::task_local! {
+    // The authenticated principal of the request, which is scoped to the request task, see: auth_middleware
+    static CURRENT_USER: Option<AuthUserClaims>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SecurityContext {}
+
 impl SecurityContext {
     pub fn new() -> Self {
-        SecurityContext {
-            current_user: Arc::new(RwLock::new(None)),
-        }
+        SecurityContext {}
     }
 
     pub fn get_instance() -> Arc<SecurityContext> {
         SECURITY_CONTEXT.clone()
     }
 
-    pub async fn bind(&self, user: Option<AuthUserClaims>) {
+    /// Run the request with the authenticated principal, which is only visible to the request task itself,
+    /// i.e: never overwritten by the other concurrent requests.
+    pub async fn scope<F: Future>(&self, user: Option<AuthUserClaims>, f: F) -> F::Output {
         debug!("Binding from user: {:?}", user);
-        match user {
-            Some(user) => {
-                // Notice: 必须在此函数中执行 write() 获取写锁, 若在外部 routes/auths.rs#auth_middleware() 中获取写锁,
-                // 则当在 routes/users.rs#handle_query_users() 中获取读锁时会产生死锁, 因为 RwLock 的释放机制是超出作用域自动释放,
-                // 在 auth_middleware() 中写锁的生命周期包含了 handle_query_users() 即没有释放.
-                let mut current_user = self.current_user.write().await;
-                *current_user = Some(user);
-            }
-            None => {}
-        }
-        debug!("Binded from user: {:?}", self.get().await);
+        CURRENT_USER.scope(user, f).await
     }
 
     pub async fn get(&self) -> Option<AuthUserClaims> {
-        match self.current_user.try_read() {
-            Ok(read_guard) => read_guard.clone(),
-            Err(e) => {
-                error!("Unable to acquire read lock. reason: {:?}", e);
-                None
-            }
-        }
+        CURRENT_USER.try_with(|user| user.clone()).ok().flatten()
     }
 
     pub async fn get_current_uid(&self) -> Option<i64> {
@@ -266,9 +704,4 @@ impl SecurityContext {
             .or(SecurityContext::get_instance().get_current_uname().await)
             .or(Some(DEFAULT_BY.to_string()));
     }
-
-    pub async fn clear(&self) {
-        let mut write_guard = self.current_user.write().await;
-        *write_guard = None;
-    }
 }
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{self, ServerLimitsProperties};
+use crate::mgmt::apm::metrics::BOTWAF_HEADER_LIMIT_REJECTED_TOTAL;
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use botwaf_types::RespBase;
+use hyper::{HeaderMap, StatusCode};
+
+pub const LIMIT_MAX_HEADER_BYTES: &str = "server.limits.max-header-bytes";
+pub const LIMIT_MAX_HEADER_COUNT: &str = "server.limits.max-header-count";
+pub const LIMIT_UPSTREAM_MAX_HEADER_BYTES: &str = "services.forward.upstreams[].max-request-header-bytes";
+
+/// The exceeded limit of the request headers, which is responded with 431 and the standard JSON error naming
+/// the limit, so that the clients (e.g: the huge cookies of SSO) know which limit is exceeded.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Request Header Fields Too Large, exceeded the limit '{limit}' of {max} (actual: {actual})")]
+pub struct HeaderLimitExceeded {
+    pub limit: &'static str,
+    pub max: usize,
+    pub actual: usize,
+}
+
+impl IntoResponse for HeaderLimitExceeded {
+    fn into_response(self) -> Response {
+        BOTWAF_HEADER_LIMIT_REJECTED_TOTAL
+            .with_label_values(&[self.limit])
+            .inc();
+        (
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Json(RespBase::errmsg(&self.to_string())),
+        )
+            .into_response()
+    }
+}
+
+/// The total bytes of the request header names and values.
+pub fn header_bytes(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
+/// Check the count and the total bytes of the request headers, 0 is unlimited.
+pub fn check_header_limits(headers: &HeaderMap, limits: &ServerLimitsProperties) -> Result<(), HeaderLimitExceeded> {
+    let count = headers.len();
+    if limits.max_header_count > 0 && count > limits.max_header_count {
+        return Err(HeaderLimitExceeded {
+            limit: LIMIT_MAX_HEADER_COUNT,
+            max: limits.max_header_count,
+            actual: count,
+        });
+    }
+    let bytes = header_bytes(headers);
+    if limits.max_header_bytes > 0 && bytes > limits.max_header_bytes {
+        return Err(HeaderLimitExceeded {
+            limit: LIMIT_MAX_HEADER_BYTES,
+            max: limits.max_header_bytes,
+            actual: bytes,
+        });
+    }
+    Ok(())
+}
+
+/// Reject the requests exceeded the header limits of the listener before any evaluation, notice that the
+/// transport accepts the multiple of the limits, see: WebListener::serve_connection
+pub async fn header_limits_middleware(req: Request<Body>, next: Next) -> Response {
+    if let Err(e) = check_header_limits(req.headers(), &config::get_config().server.limits) {
+        tracing::warn!("[Botwaf] [HeaderTooLarge] - {}, {}", req.uri().path(), e);
+        return e.into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn create_test_router() -> Router {
+        Router::new()
+            .route("/sso/callback", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(header_limits_middleware))
+    }
+
+    async fn read_json(resp: Response) -> serde_json::Value {
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_large_cookie_rejected_with_431() {
+        let max_header_bytes = config::get_config().server.limits.max_header_bytes;
+        assert!(max_header_bytes < 64 * 1024);
+        let rejected = BOTWAF_HEADER_LIMIT_REJECTED_TOTAL
+            .with_label_values(&[LIMIT_MAX_HEADER_BYTES])
+            .get();
+
+        // The 64 KB cookie of the SSO deployments.
+        let req = Request::builder()
+            .uri("/sso/callback")
+            .header("cookie", format!("sso_session={}", "x".repeat(64 * 1024)))
+            .body(Body::empty())
+            .unwrap();
+        let resp = create_test_router().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        let body = read_json(resp).await;
+        let errmsg = body["errmsg"].as_str().unwrap();
+        assert!(errmsg.contains(LIMIT_MAX_HEADER_BYTES), "{}", errmsg);
+        assert!(errmsg.contains(&max_header_bytes.to_string()), "{}", errmsg);
+        assert!(body.get("errcode").is_some());
+        assert!(
+            BOTWAF_HEADER_LIMIT_REJECTED_TOTAL
+                .with_label_values(&[LIMIT_MAX_HEADER_BYTES])
+                .get()
+                > rejected
+        );
+
+        // The small cookie is passed.
+        let req = Request::builder()
+            .uri("/sso/callback")
+            .header("cookie", "sso_session=abc")
+            .body(Body::empty())
+            .unwrap();
+        let resp = create_test_router().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_check_header_count_and_unlimited() {
+        let mut headers = HeaderMap::new();
+        for i in 0..3 {
+            headers.insert(
+                format!("x-custom-{}", i).parse::<hyper::header::HeaderName>().unwrap(),
+                "v".parse().unwrap(),
+            );
+        }
+        let limits = ServerLimitsProperties {
+            max_header_bytes: 0,
+            max_header_count: 2,
+            max_event_header_bytes: 0,
+        };
+        let err = check_header_limits(&headers, &limits).unwrap_err();
+        assert_eq!(err.limit, LIMIT_MAX_HEADER_COUNT);
+        assert_eq!(err.actual, 3);
+
+        let limits = ServerLimitsProperties {
+            max_header_count: 0,
+            ..limits
+        };
+        assert!(check_header_limits(&headers, &limits).is_ok());
+    }
+}
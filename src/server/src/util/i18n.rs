@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use hyper::{header, HeaderMap};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+pub const DEFAULT_LOCALE: &'static str = "en";
+
+// The locales of the message catalogs, the English is the default without the catalog.
+pub const SUPPORTED_LOCALES: [&'static str; 3] = [DEFAULT_LOCALE, "zh-CN", "ja"];
+
+// The English messages are the keys (i.e: like the msgid of gettext), so that the messages without translated
+// (e.g: the dynamic error details) are fallback to the English as is.
+const ZH_CN_MESSAGES: &[(&'static str, &'static str)] = &[
+    ("ok", "成功"),
+    ("Logged", "已登录"),
+    ("Logout", "未登录或登录已过期"),
+    ("Authenticated", "认证成功"),
+    ("Bad Parameters", "参数错误"),
+    ("Invalid CSRF token", "无效的 CSRF 令牌"),
+    ("Unable to read password login request failed", "无法读取密码登录请求"),
+    ("Invalid password login parameter json", "无效的密码登录参数"),
+    (
+        "Unable to read ethers wallet login request failed",
+        "无法读取钱包登录请求",
+    ),
+    ("Invalid ethers wallet login parameter json", "无效的钱包登录参数"),
+    ("Missing authentication code", "缺少授权码"),
+    ("OIDC client not configured", "未配置 OIDC 客户端"),
+    ("Oidc client not configured", "未配置 OIDC 客户端"),
+    ("Github oauth2 client not configured", "未配置 Github 客户端"),
+    ("Github client not configured", "未配置 Github 客户端"),
+    ("Failed to bind oidc user", "绑定 OIDC 用户失败"),
+    ("Failed to bind github user", "绑定 Github 用户失败"),
+    (
+        "The authorization is denied, please login again",
+        "授权已被拒绝, 请重新登录",
+    ),
+    (
+        "The authorization is failed, please login again",
+        "授权失败, 请重新登录",
+    ),
+];
+
+const JA_MESSAGES: &[(&'static str, &'static str)] = &[
+    ("ok", "成功"),
+    ("Logged", "ログイン済みです"),
+    ("Logout", "ログインしていないか、ログインの有効期限が切れています"),
+    ("Authenticated", "認証に成功しました"),
+    ("Bad Parameters", "パラメータが不正です"),
+    ("Invalid CSRF token", "CSRF トークンが無効です"),
+    (
+        "Unable to read password login request failed",
+        "パスワードログインのリクエストを読み取れません",
+    ),
+    (
+        "Invalid password login parameter json",
+        "パスワードログインのパラメータが無効です",
+    ),
+    (
+        "Unable to read ethers wallet login request failed",
+        "ウォレットログインのリクエストを読み取れません",
+    ),
+    (
+        "Invalid ethers wallet login parameter json",
+        "ウォレットログインのパラメータが無効です",
+    ),
+    ("Missing authentication code", "認可コードがありません"),
+    ("OIDC client not configured", "OIDC クライアントが設定されていません"),
+    ("Oidc client not configured", "OIDC クライアントが設定されていません"),
+    (
+        "Github oauth2 client not configured",
+        "Github クライアントが設定されていません",
+    ),
+    (
+        "Github client not configured",
+        "Github クライアントが設定されていません",
+    ),
+    ("Failed to bind oidc user", "OIDC ユーザーの連携に失敗しました"),
+    ("Failed to bind github user", "Github ユーザーの連携に失敗しました"),
+    (
+        "The authorization is denied, please login again",
+        "認可が拒否されました。もう一度ログインしてください",
+    ),
+    (
+        "The authorization is failed, please login again",
+        "認可に失敗しました。もう一度ログインしてください",
+    ),
+];
+
+lazy_static! {
+    static ref CATALOGS: HashMap<&'static str, HashMap<&'static str, &'static str>> = HashMap::from([
+        ("zh-CN", ZH_CN_MESSAGES.iter().copied().collect()),
+        ("ja", JA_MESSAGES.iter().copied().collect()),
+    ]);
+}
+
+/// Translate the English message into the locale, fallback to the message as is if not translated.
+pub fn translate<'a>(locale: &str, message: &'a str) -> &'a str {
+    CATALOGS
+        .get(locale)
+        .and_then(|catalog| catalog.get(message).copied())
+        .unwrap_or(message)
+}
+
+/// Negotiate the supported locale of the response, i.e: the language of the authenticated user takes precedence,
+/// and then the 'Accept-Language' header, fallback to the English.
+pub fn negotiate(user_lang: Option<&str>, headers: &HeaderMap) -> &'static str {
+    if let Some(locale) = user_lang.and_then(match_locale) {
+        return locale;
+    }
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    parse_accept_language(accept_language)
+        .into_iter()
+        .find_map(match_locale)
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Match the supported locale of the language tag, e.g: 'zh_cn', 'zh-Hans' and 'zh' are 'zh-CN', 'ja-JP' is 'ja'
+pub fn match_locale(tag: &str) -> Option<&'static str> {
+    let tag = tag.trim().replace('_', "-");
+    if let Some(locale) = SUPPORTED_LOCALES.iter().find(|l| l.eq_ignore_ascii_case(&tag)) {
+        return Some(locale);
+    }
+    let primary = tag.split('-').next().unwrap_or_default();
+    SUPPORTED_LOCALES
+        .iter()
+        .find(|l| l.split('-').next().unwrap_or_default().eq_ignore_ascii_case(primary))
+        .copied()
+}
+
+// Parse the language tags ordered by the quality, e.g: 'fr;q=0.5, zh-CN, ja;q=0.8' is ['zh-CN', 'ja', 'fr']
+fn parse_accept_language(value: &str) -> Vec<&str> {
+    let mut tags = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect::<Vec<_>>();
+    // Notice: The stable sorting preserves the order of the same quality.
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn accept_language(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_negotiate_by_accept_language() {
+        assert_eq!(negotiate(None, &accept_language("zh-CN,zh;q=0.9,en;q=0.8")), "zh-CN");
+        assert_eq!(negotiate(None, &accept_language("fr;q=0.9, ja;q=0.95, en;q=0.5")), "ja");
+        assert_eq!(negotiate(None, &accept_language("zh-Hans")), "zh-CN");
+        assert_eq!(negotiate(None, &accept_language("fr, de;q=0.5")), "en");
+        assert_eq!(negotiate(None, &accept_language("ja;q=0, *")), "en");
+        assert_eq!(negotiate(None, &HeaderMap::new()), "en");
+    }
+
+    #[test]
+    fn test_negotiate_user_lang_takes_precedence() {
+        assert_eq!(negotiate(Some("zh_CN"), &accept_language("ja")), "zh-CN");
+        // The unsupported language of the user is ignored.
+        assert_eq!(negotiate(Some("fr"), &accept_language("ja")), "ja");
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate("zh-CN", "Invalid CSRF token"), "无效的 CSRF 令牌");
+        assert_eq!(translate("ja", "Invalid CSRF token"), "CSRF トークンが無効です");
+        assert_eq!(translate("en", "Invalid CSRF token"), "Invalid CSRF token");
+        // The untranslated message is fallback as is.
+        assert_eq!(translate("ja", "failed to exchange token"), "failed to exchange token");
+    }
+
+    #[test]
+    fn test_catalogs_have_same_keys() {
+        let keys = |messages: &[(&'static str, &'static str)]| messages.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(ZH_CN_MESSAGES), keys(JA_MESSAGES));
+    }
+}
@@ -0,0 +1,314 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config::{self, LoginChallengeProperties, LoginChallengeProvider};
+use crate::mgmt::apm::metrics::BOTWAF_LOGIN_CHALLENGE_TOTAL;
+use anyhow::Error;
+use async_trait::async_trait;
+use common_telemetry::warn;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<LoginChallenge> = {
+        let config = &config::get_config().auth.login_challenge;
+        Arc::new(LoginChallenge::new(
+            config,
+            Arc::new(SiteVerifyChallengeVerifier::new(config)),
+        ))
+    };
+}
+
+/// The server side verification of the solved challenge token.
+#[async_trait]
+pub trait IChallengeVerifier: Send + Sync {
+    async fn verify(&self, token: &str, remote_ip: &str) -> Result<bool, Error>;
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    #[serde(rename = "error-codes", default)]
+    error_codes: Vec<String>,
+}
+
+/// The 'siteverify' API of the hCaptcha and Turnstile, which are compatible, i.e: the form of the secret, response
+/// and remoteip, and the JSON result with the success.
+pub struct SiteVerifyChallengeVerifier {
+    verify_url: String,
+    secret_key: String,
+    client: reqwest::Client,
+}
+
+impl SiteVerifyChallengeVerifier {
+    pub fn new(config: &LoginChallengeProperties) -> Self {
+        SiteVerifyChallengeVerifier {
+            verify_url: config
+                .verify_url
+                .to_owned()
+                .unwrap_or_else(|| config.provider.default_verify_url().to_owned()),
+            secret_key: config.secret_key.to_owned().unwrap_or_default(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(*config.timeout_ms)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl IChallengeVerifier for SiteVerifyChallengeVerifier {
+    async fn verify(&self, token: &str, remote_ip: &str) -> Result<bool, Error> {
+        let resp: SiteVerifyResponse = self
+            .client
+            .post(&self.verify_url)
+            .form(&[
+                ("secret", self.secret_key.as_str()),
+                ("response", token),
+                ("remoteip", remote_ip),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if !resp.success {
+            warn!(
+                "The login challenge token is not verified, error codes: {:?}",
+                resp.error_codes
+            );
+        }
+        Ok(resp.success)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChallengeRejection {
+    // The challenge token is missing, the client should show the challenge widget.
+    Required,
+    // The challenge token is invalid (or failed to verify), the client should show the challenge widget again.
+    Failed,
+}
+
+impl ChallengeRejection {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ChallengeRejection::Required => "CHALLENGE_REQUIRED",
+            ChallengeRejection::Failed => "CHALLENGE_FAILED",
+        }
+    }
+}
+
+struct FailureWindow {
+    count: u32,
+    started: Instant,
+}
+
+/// The challenge step of the password login under attack, which is interposed once the failed logins of the
+/// client IP reached the threshold within the window, until the successful login or the window expired.
+pub struct LoginChallenge {
+    config: LoginChallengeProperties,
+    verifier: Arc<dyn IChallengeVerifier>,
+    failures: Mutex<HashMap<String, FailureWindow>>,
+}
+
+impl LoginChallenge {
+    pub fn get() -> Arc<LoginChallenge> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(config: &LoginChallengeProperties, verifier: Arc<dyn IChallengeVerifier>) -> Self {
+        LoginChallenge {
+            config: config.to_owned(),
+            verifier,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn provider(&self) -> LoginChallengeProvider {
+        self.config.provider
+    }
+
+    pub fn site_key(&self) -> Option<&str> {
+        self.config.site_key.as_deref()
+    }
+
+    /// Whether the failed logins of the client IP reached the threshold within the window.
+    pub fn is_required(&self, client_ip: &str) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        let failures = self.failures.lock().unwrap();
+        failures
+            .get(client_ip)
+            .filter(|w| w.started.elapsed() < *self.config.window_secs)
+            .is_some_and(|w| w.count >= self.config.threshold)
+    }
+
+    /// Check the challenge token of the password login, which is bypassed below the threshold.
+    pub async fn check(&self, client_ip: &str, token: Option<&str>) -> Result<(), ChallengeRejection> {
+        if !self.is_required(client_ip) {
+            return Ok(());
+        }
+        let token = match token.map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            Some(token) => token,
+            None => {
+                BOTWAF_LOGIN_CHALLENGE_TOTAL.with_label_values(&["required"]).inc();
+                return Err(ChallengeRejection::Required);
+            }
+        };
+        // Notice: Fail closed if the provider is unavailable, as the client IP is under attack.
+        match self.verifier.verify(token, client_ip).await {
+            Ok(true) => {
+                BOTWAF_LOGIN_CHALLENGE_TOTAL.with_label_values(&["passed"]).inc();
+                Ok(())
+            }
+            Ok(false) => {
+                BOTWAF_LOGIN_CHALLENGE_TOTAL.with_label_values(&["failed"]).inc();
+                Err(ChallengeRejection::Failed)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to verify the login challenge token of {}. cause: {}",
+                    client_ip, e
+                );
+                BOTWAF_LOGIN_CHALLENGE_TOTAL.with_label_values(&["failed"]).inc();
+                Err(ChallengeRejection::Failed)
+            }
+        }
+    }
+
+    pub fn record_failure(&self, client_ip: &str) {
+        if !self.config.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let window = *self.config.window_secs;
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= self.config.max_tracked_keys && !failures.contains_key(client_ip) {
+            // Evict the expired windows, which are equivalent to no failures.
+            failures.retain(|_, w| now.saturating_duration_since(w.started) < window);
+        }
+        let entry = failures
+            .entry(client_ip.to_owned())
+            .or_insert(FailureWindow { count: 0, started: now });
+        if now.saturating_duration_since(entry.started) >= window {
+            entry.count = 0;
+            entry.started = now;
+        }
+        entry.count = entry.count.saturating_add(1);
+    }
+
+    pub fn record_success(&self, client_ip: &str) {
+        if self.config.enabled {
+            self.failures.lock().unwrap().remove(client_ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Only the token 'solved' is verified, and counts the verifications.
+    #[derive(Default)]
+    struct MockChallengeVerifier {
+        verified: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl IChallengeVerifier for MockChallengeVerifier {
+        async fn verify(&self, token: &str, _remote_ip: &str) -> Result<bool, Error> {
+            self.verified.fetch_add(1, Ordering::SeqCst);
+            Ok(token == "solved")
+        }
+    }
+
+    fn create_test_challenge(verifier: Arc<MockChallengeVerifier>) -> LoginChallenge {
+        let config = LoginChallengeProperties {
+            enabled: true,
+            site_key: Some("site-key".to_owned()),
+            secret_key: Some("secret-key".to_owned()),
+            threshold: 3,
+            ..LoginChallengeProperties::default()
+        };
+        LoginChallenge::new(&config, verifier)
+    }
+
+    #[tokio::test]
+    async fn test_challenge_bypassed_below_threshold() {
+        let verifier = Arc::new(MockChallengeVerifier::default());
+        let challenge = create_test_challenge(verifier.clone());
+        for _ in 0..2 {
+            challenge.record_failure("10.0.0.1");
+        }
+        assert!(!challenge.is_required("10.0.0.1"));
+        assert_eq!(challenge.check("10.0.0.1", None).await, Ok(()));
+        // The verifier is never called below the threshold.
+        assert_eq!(verifier.verified.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_challenge_required_after_threshold() {
+        let verifier = Arc::new(MockChallengeVerifier::default());
+        let challenge = create_test_challenge(verifier.clone());
+        for _ in 0..3 {
+            challenge.record_failure("10.0.0.1");
+        }
+        assert!(challenge.is_required("10.0.0.1"));
+        assert_eq!(
+            challenge.check("10.0.0.1", None).await,
+            Err(ChallengeRejection::Required)
+        );
+        assert_eq!(
+            challenge.check("10.0.0.1", Some(" ")).await,
+            Err(ChallengeRejection::Required)
+        );
+        assert_eq!(
+            challenge.check("10.0.0.1", Some("forged")).await,
+            Err(ChallengeRejection::Failed)
+        );
+        assert_eq!(challenge.check("10.0.0.1", Some("solved")).await, Ok(()));
+        // The other IPs are not affected.
+        assert_eq!(challenge.check("10.0.0.2", None).await, Ok(()));
+
+        // The successful login clears the failures.
+        challenge.record_success("10.0.0.1");
+        assert!(!challenge.is_required("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_challenge_disabled() {
+        let challenge = LoginChallenge::new(
+            &LoginChallengeProperties::default(),
+            Arc::new(MockChallengeVerifier::default()),
+        );
+        for _ in 0..10 {
+            challenge.record_failure("10.0.0.1");
+        }
+        assert_eq!(challenge.check("10.0.0.1", None).await, Ok(()));
+    }
+}
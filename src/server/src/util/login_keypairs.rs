@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::config::config;
+use crate::mgmt::apm::metrics::{BOTWAF_LOGIN_KEYPAIR_GENERATION_SECONDS, BOTWAF_LOGIN_KEYPAIR_POOL_TOTAL};
+use anyhow::{anyhow, Error};
+use botwaf_utils::rsa_ciphers::RSACipher;
+use common_telemetry::warn;
+use lazy_static::lazy_static;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// The key size of the RSA keypair of the password login envelope.
+pub const LOGIN_KEYPAIR_BITS: u32 = 2048;
+
+lazy_static! {
+    static ref SINGLE_INSTANCE: Arc<LoginKeyPairPool> = Arc::new(LoginKeyPairPool::new(
+        config::get_config().auth.login_keypair.pool_size,
+        generate_login_keypair,
+    ));
+}
+
+/// The small pool of the pre-generated login RSA keypairs, the generation (tens to hundreds of milliseconds)
+/// always runs on the blocking threads, so that the pubkey requests never stall the async workers.
+pub struct LoginKeyPairPool {
+    capacity: usize,
+    generate: fn() -> Result<RSACipher, Error>,
+    pairs: Mutex<VecDeque<RSACipher>>,
+    refilling: AtomicBool,
+}
+
+impl LoginKeyPairPool {
+    pub fn get() -> Arc<LoginKeyPairPool> {
+        SINGLE_INSTANCE.clone()
+    }
+
+    pub fn new(capacity: usize, generate: fn() -> Result<RSACipher, Error>) -> Self {
+        Self {
+            capacity,
+            generate,
+            pairs: Mutex::new(VecDeque::with_capacity(capacity)),
+            refilling: AtomicBool::new(false),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take a keypair which is used by one login attempt only, fallback to generate on the blocking threads
+    /// if the pool is exhausted, e.g: the burst of the pubkey requests.
+    pub async fn take(self: &Arc<Self>) -> Result<RSACipher, Error> {
+        let pooled = self.pairs.lock().unwrap().pop_front();
+        self.prefill();
+        match pooled {
+            Some(pair) => {
+                BOTWAF_LOGIN_KEYPAIR_POOL_TOTAL.with_label_values(&["hit"]).inc();
+                Ok(pair)
+            }
+            None => {
+                BOTWAF_LOGIN_KEYPAIR_POOL_TOTAL.with_label_values(&["miss"]).inc();
+                let generate = self.generate;
+                tokio::task::spawn_blocking(move || Self::generate_timed(generate)).await?
+            }
+        }
+    }
+
+    /// Refill the pool up to the capacity in the background, which is no-op if already refilling.
+    pub fn prefill(self: &Arc<Self>) {
+        if self.capacity == 0 || self.refilling.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            while this.len() < this.capacity {
+                match Self::generate_timed(this.generate) {
+                    Ok(pair) => this.pairs.lock().unwrap().push_back(pair),
+                    Err(e) => {
+                        warn!("Failed to refill the login keypairs pool. cause: {}", e);
+                        break;
+                    }
+                }
+            }
+            this.refilling.store(false, Ordering::SeqCst);
+        });
+    }
+
+    fn generate_timed(generate: fn() -> Result<RSACipher, Error>) -> Result<RSACipher, Error> {
+        let started = Instant::now();
+        let result = generate();
+        let elapsed = started.elapsed();
+        BOTWAF_LOGIN_KEYPAIR_GENERATION_SECONDS.observe(elapsed.as_secs_f64());
+        tracing::debug!("Generated the login RSA keypair in {:?}", elapsed);
+        result
+    }
+}
+
+fn generate_login_keypair() -> Result<RSACipher, Error> {
+    RSACipher::new(LOGIN_KEYPAIR_BITS).map_err(|e| anyhow!("Failed to generate the login RSA keypair. {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread::ThreadId, time::Duration};
+
+    lazy_static! {
+        static ref GENERATED_ON: Mutex<Vec<ThreadId>> = Mutex::new(Vec::new());
+    }
+
+    fn mock_generate() -> Result<RSACipher, Error> {
+        GENERATED_ON.lock().unwrap().push(std::thread::current().id());
+        RSACipher::new(512).map_err(|e| anyhow!(e.to_string()))
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_generate_off_async_worker_and_refill() {
+        let pool = Arc::new(LoginKeyPairPool::new(2, mock_generate));
+        let worker = std::thread::current().id();
+
+        // The empty pool generates on demand, which must not be on the (only) async worker thread.
+        let pair = pool.take().await.unwrap();
+        assert!(pair.get_base64_public_key().is_ok());
+
+        // The background refill then serves the next requests without generation.
+        for _ in 0..100 {
+            if pool.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(pool.len(), 2);
+        let generated = GENERATED_ON.lock().unwrap().len();
+        assert!(pool.take().await.is_ok());
+        assert!(GENERATED_ON.lock().unwrap().iter().all(|id| *id != worker));
+        assert!(generated >= 3);
+    }
+}
@@ -17,7 +17,14 @@
 // IMPORTANT: Any software that fully or partially contains or uses materials
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
+pub mod auth_gate;
 pub mod auths;
+pub mod header_limits;
+pub mod i18n;
+pub mod login_challenge;
+pub mod login_keypairs;
 pub mod oauth2;
 pub mod oidcs;
+pub mod spec_runs;
+pub mod token_verify_cache;
 pub mod web;
@@ -18,22 +18,128 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::config::config::OAuth2Properties;
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use crate::config::config::{AuthProperties, OAuth2Properties};
+use anyhow::{Context, Error};
+use botwaf_utils::httpclients;
+use oauth2::{
+    basic::BasicClient,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    AuthUrl, ClientId, ClientSecret, HttpRequest, HttpResponse, RedirectUrl, TokenUrl,
+};
 
 // Using unified abstraction as OAuth2Config base class.
-pub async fn create_oauth2_client(oauth2_config: &OAuth2Properties) -> Option<BasicClient> {
+pub async fn create_oauth2_client(oauth2_config: &OAuth2Properties) -> Result<Option<BasicClient>, Error> {
     if oauth2_config.enabled.unwrap_or(false) {
-        Some(
+        let required = |value: &Option<String>, name: &str| {
+            value.to_owned().with_context(|| format!("Missing {} configured", name))
+        };
+        Ok(Some(
             BasicClient::new(
-                ClientId::new(oauth2_config.client_id.as_ref().unwrap().clone()),
-                Some(ClientSecret::new(oauth2_config.client_secret.as_ref().unwrap().clone())),
-                AuthUrl::new(oauth2_config.auth_url.as_ref().unwrap().clone()).unwrap(),
-                Some(TokenUrl::new(oauth2_config.token_url.as_ref().unwrap().clone()).unwrap()),
+                ClientId::new(required(&oauth2_config.client_id, "client id")?),
+                Some(ClientSecret::new(required(
+                    &oauth2_config.client_secret,
+                    "client secret",
+                )?)),
+                AuthUrl::new(required(&oauth2_config.auth_url, "auth url")?).context("Invalid auth url configured")?,
+                Some(
+                    TokenUrl::new(required(&oauth2_config.token_url, "token url")?)
+                        .context("Invalid token url configured")?,
+                ),
             )
-            .set_redirect_uri(RedirectUrl::new(oauth2_config.redirect_url.as_ref().unwrap().clone()).unwrap()),
-        )
+            .set_redirect_uri(
+                RedirectUrl::new(required(&oauth2_config.redirect_url, "redirect url")?)
+                    .context("Invalid redirect url configured")?,
+            ),
+        ))
     } else {
-        None
+        Ok(None)
+    }
+}
+
+/// Build the http client of the OIDC/OAuth2 providers with the outbound proxy of 'auth.http-proxy', which never
+/// follows the redirects to prevent the SSRF, same as the 'oauth2::reqwest::async_http_client'.
+pub fn build_oauth2_http_client(config: &AuthProperties) -> Result<reqwest::Client, Error> {
+    let builder = httpclients::default_builder().redirect(reqwest::redirect::Policy::none());
+    httpclients::with_proxy(builder, config.http_proxy.as_deref(), config.no_proxy.as_deref())
+        .and_then(|builder| builder.build())
+        .context("Invalid auth http-proxy configured")
+}
+
+/// Send the OIDC/OAuth2 request (e.g: the token exchange) with the given http client, which is compatible with
+/// the 'request_async', e.g: request_async(|request| async_http_client(client.clone(), request))
+pub async fn async_http_client(client: reqwest::Client, request: HttpRequest) -> Result<HttpResponse, reqwest::Error> {
+    let method = reqwest::Method::from_bytes(request.method.as_str().as_bytes()).unwrap_or(reqwest::Method::GET);
+    let mut builder = client.request(method, request.url.as_str()).body(request.body);
+    for (name, value) in request.headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    let response = builder.send().await?;
+    let status_code = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    // Notice: The 'oauth2' relies on the different major version of the 'http' types with the 'reqwest'.
+    let mut headers = HeaderMap::new();
+    for (name, value) in response.headers().iter() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_str().as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    let body = response.bytes().await?.to_vec();
+    Ok(HttpResponse {
+        status_code,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+    use oauth2::http::{header::CONTENT_TYPE, Method};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_async_http_client_with_configured_proxy() {
+        // The mock proxy responds the absolute URI of the proxied request.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().fallback(post(|uri: axum::http::Uri| async move {
+            (
+                [("content-type", "application/json")],
+                format!("{{\"uri\":\"{}\"}}", uri),
+            )
+        }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut config = AuthProperties {
+            http_proxy: Some(format!("http://{}", addr)),
+            no_proxy: Some(String::from("localhost")),
+            ..AuthProperties::default()
+        };
+        let client = build_oauth2_http_client(&config).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let request = HttpRequest {
+            url: "http://idp.botwaf.invalid/oauth/token".parse().unwrap(),
+            method: Method::POST,
+            headers,
+            body: b"grant_type=authorization_code&code=abc".to_vec(),
+        };
+        let response = async_http_client(client, request).await.unwrap();
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.headers.get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            "{\"uri\":\"http://idp.botwaf.invalid/oauth/token\"}"
+        );
+
+        config.http_proxy = Some(String::from("http://[::1"));
+        assert!(build_oauth2_http_client(&config).is_err());
     }
 }
@@ -20,11 +20,12 @@
 
 use openidconnect::{
     core::{CoreClient, CoreProviderMetadata},
-    reqwest::async_http_client,
     ClientId, ClientSecret, IssuerUrl, RedirectUrl,
 };
 
 use crate::config::config::OidcProperties;
+use crate::util::oauth2::async_http_client;
+use anyhow::{Context, Error};
 
 /*
 curl 'https://keycloak.myapp.com/realms/master/.well-known/openid-configuration'
@@ -319,31 +320,42 @@ curl 'https://keycloak.myapp.com/realms/master/.well-known/openid-configuration'
   }
 }
 */
-pub async fn create_oidc_client(config: &OidcProperties) -> Option<CoreClient> {
+pub async fn create_oidc_client(
+    config: &OidcProperties,
+    http_client: &reqwest::Client,
+) -> Result<Option<CoreClient>, Error> {
     if config.enabled.unwrap_or(false) {
-        let issuer_url = IssuerUrl::new(config.issue_url.to_owned().expect("Missing issue url configured"))
-            .expect("Invalid issue url configured");
+        let issuer_url = IssuerUrl::new(config.issue_url.to_owned().context("Missing issue url configured")?)
+            .context("Invalid issue url configured")?;
 
-        let client_id = ClientId::new(config.client_id.to_owned().expect("Missing client id configured"));
+        let client_id = ClientId::new(config.client_id.to_owned().context("Missing client id configured")?);
         let client_secret = ClientSecret::new(
             config
                 .client_secret
                 .to_owned()
-                .expect("Missing client secret configured"),
+                .context("Missing client secret configured")?,
         );
 
-        let redirect_url = RedirectUrl::new(config.redirect_url.to_owned().expect("Missing redirect url configured"))
-            .expect("Invalid redirect url configured");
+        let redirect_url = RedirectUrl::new(
+            config
+                .redirect_url
+                .to_owned()
+                .context("Missing redirect url configured")?,
+        )
+        .context("Invalid redirect url configured")?;
 
-        let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
-            .await
-            .expect("Failed to oidc discover metadata");
+        let http_client = http_client.clone();
+        let metadata = CoreProviderMetadata::discover_async(issuer_url, move |request| {
+            async_http_client(http_client.clone(), request)
+        })
+        .await
+        .context("Failed to oidc discover metadata")?;
 
         let client =
             CoreClient::from_provider_metadata(metadata, client_id, Some(client_secret)).set_redirect_uri(redirect_url);
 
-        Some(client)
+        Ok(Some(client))
     } else {
-        None
+        Ok(None)
     }
 }
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use botwaf_types::{
+    modules::scheduler::spec_run::{SpecRunResponse, SpecRunStatus},
+    RespBase,
+};
+use hyper::StatusCode;
+use sqlx::types::uuid::Uuid;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum SpecRunRejection {
+    NotFound(String),
+    AlreadyRunning(SpecRunResponse),
+}
+
+impl IntoResponse for SpecRunRejection {
+    fn into_response(self) -> Response {
+        match self {
+            SpecRunRejection::NotFound(name) => (
+                StatusCode::NOT_FOUND,
+                Json(RespBase::errmsg(&format!("Not found the spec '{}'", name))),
+            )
+                .into_response(),
+            SpecRunRejection::AlreadyRunning(run) => (
+                StatusCode::CONFLICT,
+                Json(RespBase::errmsg(&format!(
+                    "The spec '{}' is already running with run id '{}'",
+                    run.name, run.run_id
+                ))),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The guard of the manual and scheduled runs of the same updater/verifier spec, which prevents the overlapping runs.
+#[derive(Debug)]
+pub struct SpecRunGuard {
+    name: String,
+    // The current running or the last finished run.
+    last_run: Mutex<Option<SpecRunResponse>>,
+}
+
+/// The permit of the acquired run, which is released on drop.
+pub struct SpecRunPermit {
+    guard: Arc<SpecRunGuard>,
+    run: SpecRunResponse,
+}
+
+impl Drop for SpecRunPermit {
+    fn drop(&mut self) {
+        let mut last_run = self.guard.last_run.lock().unwrap();
+        *last_run = Some(SpecRunResponse {
+            status: SpecRunStatus::SUCCEEDED,
+            ..self.run.to_owned()
+        });
+    }
+}
+
+impl SpecRunGuard {
+    pub fn new(name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_owned(),
+            last_run: Mutex::new(None),
+        })
+    }
+
+    pub fn last_run(&self) -> Option<SpecRunResponse> {
+        self.last_run.lock().unwrap().to_owned()
+    }
+
+    pub fn try_acquire(self: &Arc<Self>) -> Result<SpecRunPermit, SpecRunRejection> {
+        let mut last_run = self.last_run.lock().unwrap();
+        if let Some(run) = last_run.as_ref().filter(|run| run.status == SpecRunStatus::RUNNING) {
+            return Err(SpecRunRejection::AlreadyRunning(run.to_owned()));
+        }
+        let run = SpecRunResponse {
+            run_id: Uuid::new_v4().to_string(),
+            name: self.name.to_owned(),
+            status: SpecRunStatus::RUNNING,
+        };
+        *last_run = Some(run.to_owned());
+        Ok(SpecRunPermit {
+            guard: self.to_owned(),
+            run,
+        })
+    }
+
+    /// Trigger the one-off run in background immediately, and returns the run id with the running status.
+    pub fn spawn<F>(self: &Arc<Self>, run: F) -> Result<SpecRunResponse, SpecRunRejection>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permit = self.try_acquire()?;
+        let response = permit.run.to_owned();
+        tokio::spawn(async move {
+            run.await;
+            drop(permit);
+        });
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_runs_rejected() {
+        let guard = SpecRunGuard::new("defaultUpdater");
+        let permit = guard.try_acquire().unwrap();
+        match guard.try_acquire() {
+            Err(SpecRunRejection::AlreadyRunning(run)) => assert_eq!(run.run_id, permit.run.run_id),
+            _ => panic!("The overlapping run should be rejected"),
+        }
+
+        drop(permit);
+        assert_eq!(guard.last_run().unwrap().status, SpecRunStatus::SUCCEEDED);
+        assert!(guard.try_acquire().is_ok());
+    }
+}
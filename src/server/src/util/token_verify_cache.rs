@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::cache::ICache;
+use crate::config::config::TokenVerifyCacheProperties;
+use crate::mgmt::apm::metrics::{BOTWAF_TOKEN_REVOCATION_LAG_SECONDS, BOTWAF_TOKEN_VERIFY_CACHE_TOTAL};
+use anyhow::Error;
+use chrono::Utc;
+use common_telemetry::warn;
+use sqlx::types::uuid::Uuid;
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+/// The revocation sequence which is bumped by each revocation (e.g: logout, password changed) of any replica,
+/// the value is formatted as '{revoked_millis}-{nonce}' to distinguish the revocations in the same millisecond.
+pub const LOGOUT_REVOCATION_SEQ_KEY: &str = "logout:revocation:seq";
+
+struct Verdict {
+    // The value of the revocation key, e.g: the logout time of the blacklisted token.
+    value: Option<String>,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct RevocationSync {
+    last_polled: Option<Instant>,
+    // None if never polled, or Some(None) if no revocations yet.
+    last_seq: Option<Option<String>>,
+}
+
+/// The two-tier verification of the access tokens, i.e: the local verdicts with the short TTL in front of the
+/// revocation keys (the source of truth), e.g: the logout blacklist and the token epoch of the users, which
+/// are invalidated once the revocation sequence changed.
+pub struct TokenVerifyCache {
+    config: TokenVerifyCacheProperties,
+    verdicts: Mutex<HashMap<String, Verdict>>,
+    sync: Mutex<RevocationSync>,
+}
+
+impl TokenVerifyCache {
+    pub fn new(config: &TokenVerifyCacheProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            verdicts: Mutex::new(HashMap::new()),
+            sync: Mutex::new(RevocationSync::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Whether the token of the blacklist key is revoked, which is checked against the blacklist directly
+    /// if disabled, otherwise the local verdict is preferred while it's not expired or invalidated.
+    pub async fn is_revoked(&self, cache: &dyn ICache<String>, blacklist_key: String) -> Result<bool, Error> {
+        Ok(self.get(cache, blacklist_key).await?.is_some())
+    }
+
+    /// Get the value of the revocation key, e.g: the token epoch of the user, see: is_revoked()
+    pub async fn get(&self, cache: &dyn ICache<String>, key: String) -> Result<Option<String>, Error> {
+        if !self.config.enabled {
+            return cache.get(key).await;
+        }
+        self.sync_revocations(cache).await;
+
+        let now = Instant::now();
+        let cached = {
+            let verdicts = self.verdicts.lock().unwrap();
+            verdicts
+                .get(&key)
+                .filter(|v| v.expires_at > now)
+                .map(|v| v.value.to_owned())
+        };
+        if let Some(value) = cached {
+            BOTWAF_TOKEN_VERIFY_CACHE_TOTAL.with_label_values(&["hit"]).inc();
+            return Ok(value);
+        }
+        BOTWAF_TOKEN_VERIFY_CACHE_TOTAL.with_label_values(&["miss"]).inc();
+
+        let value = cache.get(key.to_owned()).await?;
+        self.put(key, value.to_owned(), now);
+        Ok(value)
+    }
+
+    /// Bump the revocation sequence after the revocation key (e.g: the token blacklist) was set, so that the
+    /// other replicas invalidate their local verdicts on the next poll, and the local verdict is replaced.
+    pub async fn revoke(&self, cache: &dyn ICache<String>, key: String, value: String) -> Result<(), Error> {
+        let seq = format!("{}-{}", Utc::now().timestamp_millis(), Uuid::new_v4());
+        cache.set(LOGOUT_REVOCATION_SEQ_KEY.to_owned(), seq, None).await?;
+        if self.config.enabled {
+            self.put(key, Some(value), Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Fill the revocation key reloaded from the source of truth, e.g: the token epoch of the user store after
+    /// evicted, the revocation sequence is never bumped since nothing is revoked, and the newer value set
+    /// concurrently (e.g: the password changed meanwhile) is never overridden, returns the effective value.
+    pub async fn fill(&self, cache: &dyn ICache<String>, key: String, value: String) -> Result<Option<String>, Error> {
+        let value = if cache.set_nx(key.to_owned(), Some(value.to_owned())).await? {
+            Some(value)
+        } else {
+            cache.get(key.to_owned()).await?
+        };
+        if self.config.enabled {
+            self.put(key, value.to_owned(), Instant::now());
+        }
+        Ok(value)
+    }
+
+    fn put(&self, key: String, value: Option<String>, now: Instant) {
+        let mut verdicts = self.verdicts.lock().unwrap();
+        if verdicts.len() >= self.config.max_entries {
+            verdicts.retain(|_, v| v.expires_at > now);
+            // Still full of the fresh verdicts, e.g: the burst of distinct tokens.
+            if verdicts.len() >= self.config.max_entries {
+                verdicts.clear();
+            }
+        }
+        verdicts.insert(
+            key,
+            Verdict {
+                value,
+                expires_at: now + *self.config.max_staleness_ms,
+            },
+        );
+    }
+
+    // Poll the revocation sequence at most once per interval, which is claimed by the first request of the
+    // interval, so the other concurrent requests are never blocked by the polling.
+    async fn sync_revocations(&self, cache: &dyn ICache<String>) {
+        let now = Instant::now();
+        {
+            let mut sync = self.sync.lock().unwrap();
+            let interval = *self.config.poll_interval_ms;
+            if sync
+                .last_polled
+                .is_some_and(|t| now.saturating_duration_since(t) < interval)
+            {
+                return;
+            }
+            sync.last_polled = Some(now);
+        }
+
+        match cache.get(LOGOUT_REVOCATION_SEQ_KEY.to_owned()).await {
+            Ok(seq) => {
+                let mut sync = self.sync.lock().unwrap();
+                if sync.last_seq.as_ref() == Some(&seq) {
+                    return;
+                }
+                // The propagation lag of the revocation, the initial sequence is only recorded.
+                if sync.last_seq.is_some() {
+                    if let Some(revoked_millis) = seq.as_deref().and_then(Self::parse_revoked_millis) {
+                        let lag_millis = (Utc::now().timestamp_millis() - revoked_millis).max(0);
+                        BOTWAF_TOKEN_REVOCATION_LAG_SECONDS.observe(lag_millis as f64 / 1000.0);
+                    }
+                }
+                sync.last_seq = Some(seq);
+                self.verdicts.lock().unwrap().clear();
+            }
+            Err(e) => {
+                // Fail-safe: the local verdicts are no longer trusted if the revocations are unknown.
+                warn!(
+                    "Failed to poll the token revocation sequence, invalidated the local verdicts. cause: {}",
+                    e
+                );
+                self.verdicts.lock().unwrap().clear();
+            }
+        }
+    }
+
+    fn parse_revoked_millis(seq: &str) -> Option<i64> {
+        seq.split('-').next().and_then(|millis| millis.parse::<i64>().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::duration::DurationMillis;
+    use crate::context::test_support::InMemoryCache;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    fn mock_config(enabled: bool) -> TokenVerifyCacheProperties {
+        TokenVerifyCacheProperties {
+            enabled,
+            max_staleness_ms: DurationMillis::from_millis(60_000),
+            poll_interval_ms: DurationMillis::from_millis(60_000),
+            ..TokenVerifyCacheProperties::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_hits_without_redis_calls() {
+        let cache = InMemoryCache::default();
+        let calls = cache.calls();
+        let verifier = TokenVerifyCache::new(&mock_config(true));
+
+        // The first request polls the revocation sequence and checks the blacklist.
+        assert!(!verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        for _ in 0..1000 {
+            assert!(!verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_checks_blacklist_per_request() {
+        let cache = InMemoryCache::default();
+        let calls = cache.calls();
+        let verifier = TokenVerifyCache::new(&mock_config(false));
+
+        for _ in 0..10 {
+            assert!(!verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 10);
+
+        cache.set(String::from("k1"), String::from("1"), None).await.unwrap();
+        assert!(verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revocation_propagates_to_other_replicas() {
+        let cache = InMemoryCache::default();
+        let mut config = mock_config(true);
+        config.poll_interval_ms = DurationMillis::from_millis(50);
+        let replica1 = TokenVerifyCache::new(&config);
+        let replica2 = TokenVerifyCache::new(&config);
+
+        assert!(!replica1.is_revoked(&cache, String::from("k1")).await.unwrap());
+        assert!(!replica2.is_revoked(&cache, String::from("k1")).await.unwrap());
+
+        // The logout is handled by the replica1.
+        cache.set(String::from("k1"), String::from("1"), None).await.unwrap();
+        replica1
+            .revoke(&cache, String::from("k1"), String::from("1"))
+            .await
+            .unwrap();
+        assert!(replica1.is_revoked(&cache, String::from("k1")).await.unwrap());
+
+        // The replica2 is stale within the poll interval, and invalidated after that.
+        assert!(!replica2.is_revoked(&cache, String::from("k1")).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(replica2.is_revoked(&cache, String::from("k1")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verdicts_expired_after_max_staleness() {
+        let cache = InMemoryCache::default();
+        let mut config = mock_config(true);
+        config.max_staleness_ms = DurationMillis::from_millis(50);
+        let verifier = TokenVerifyCache::new(&config);
+
+        assert!(!verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+        // Revoked without bumping the sequence, e.g: the sequence write failed.
+        cache.set(String::from("k1"), String::from("1"), None).await.unwrap();
+        assert!(!verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(verifier.is_revoked(&cache, String::from("k1")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_fill_never_overrides_newer_value() {
+        let cache = InMemoryCache::default();
+        let verifier = TokenVerifyCache::new(&mock_config(true));
+
+        // The reloaded value is filled into both the cache and the local verdict.
+        let value = verifier
+            .fill(&cache, String::from("k1"), String::from("0"))
+            .await
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("0"));
+        assert_eq!(cache.get(String::from("k1")).await.unwrap().as_deref(), Some("0"));
+        assert_eq!(
+            verifier.get(&cache, String::from("k1")).await.unwrap().as_deref(),
+            Some("0")
+        );
+
+        // The newer value set concurrently wins, e.g: the password changed after reloaded from the store.
+        cache.set(String::from("k2"), String::from("2000"), None).await.unwrap();
+        let value = verifier
+            .fill(&cache, String::from("k2"), String::from("1000"))
+            .await
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("2000"));
+        assert_eq!(
+            verifier.get(&cache, String::from("k2")).await.unwrap().as_deref(),
+            Some("2000")
+        );
+    }
+
+    #[test]
+    fn test_evict_when_exceeded_max_entries() {
+        let mut config = mock_config(true);
+        config.max_entries = 2;
+        let verifier = TokenVerifyCache::new(&config);
+        let now = Instant::now();
+        verifier.put(String::from("k1"), None, now);
+        verifier.put(String::from("k2"), None, now);
+        verifier.put(String::from("k3"), None, now);
+        assert_eq!(verifier.verdicts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_revoked_millis() {
+        let seq = format!("1760572800000-{}", Uuid::new_v4());
+        assert_eq!(TokenVerifyCache::parse_revoked_millis(&seq), Some(1760572800000));
+        assert_eq!(TokenVerifyCache::parse_revoked_millis("invalid"), None);
+    }
+}
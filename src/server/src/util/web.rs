@@ -18,10 +18,14 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use crate::context::state::BotwafState;
+use crate::util::auths::{self, AuthUserClaims};
 use axum::extract::rejection::{JsonRejection, QueryRejection};
-use axum::extract::{FromRequest, Request};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
 use axum::response::{IntoResponse, Response};
 use axum::{extract::Query, Json};
+use botwaf_types::RespBase;
 use hyper::StatusCode;
 use serde::de::DeserializeOwned;
 use validator::Validate;
@@ -49,25 +53,152 @@ where
     }
 }
 
+/// The query string extractor which validates after the deserialization, the unparsable query is rejected
+/// with 400 and the invalid fields with 422, both are in the standard error shape, see: RespBase::invalid
 pub struct ValidatedQuery<T>(pub T);
 
-impl<T, S> FromRequest<S> for ValidatedQuery<T>
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
 where
     T: DeserializeOwned + Validate,
     S: Send + Sync,
-    Query<T>: FromRequest<S, Rejection = QueryRejection>,
+    Query<T>: FromRequestParts<S, Rejection = QueryRejection>,
 {
     type Rejection = Response;
 
-    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        let Query(value) = Query::<T>::from_request(req, state)
-            .await
-            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Query parsing error: {:?}", e)).into_response())?;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await.map_err(|e| {
+            let errmsg = format!("Query parsing error: {}", e.body_text());
+            (StatusCode::BAD_REQUEST, Json(RespBase::errmsg(&errmsg))).into_response()
+        })?;
 
         value
             .validate()
-            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", e)).into_response())?;
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, Json(RespBase::invalid(&e))).into_response())?;
 
         Ok(ValidatedQuery(value))
     }
 }
+
+/// The authenticated principal of the request, which is inserted into the request extensions by the
+/// auth_middleware, i.e: never shared with the other concurrent requests.
+pub struct CurrentUser(pub AuthUserClaims);
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUserClaims>()
+            .cloned()
+            .map(CurrentUser)
+            .ok_or_else(|| {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(RespBase::errmsg("Unauthorized, requires the authenticated principal.")),
+                )
+                    .into_response()
+            })
+    }
+}
+
+/// The authenticated principal of the request which must be the admin, otherwise rejected with 403.
+pub struct RequireAdmin(pub AuthUserClaims);
+
+impl FromRequestParts<BotwafState> for RequireAdmin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &BotwafState) -> Result<Self, Self::Rejection> {
+        let CurrentUser(claims) = CurrentUser::from_request_parts(parts, state).await?;
+        if !auths::is_admin(&state.config, &claims) {
+            return Err(forbidden("admin"));
+        }
+        Ok(RequireAdmin(claims))
+    }
+}
+
+/// The authenticated principal of the request which must be the operator (or admin), otherwise rejected with 403.
+pub struct RequireOperator(pub AuthUserClaims);
+
+impl FromRequestParts<BotwafState> for RequireOperator {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &BotwafState) -> Result<Self, Self::Rejection> {
+        let CurrentUser(claims) = CurrentUser::from_request_parts(parts, state).await?;
+        if !auths::is_operator(&state.config, &claims) {
+            return Err(forbidden("operator"));
+        }
+        Ok(RequireOperator(claims))
+    }
+}
+
+fn forbidden(role: &str) -> Response {
+    let errmsg = format!("Forbidden, requires the {} role.", role);
+    (StatusCode::FORBIDDEN, Json(RespBase::errmsg(&errmsg))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use botwaf_types::PageRequest;
+    use tower::ServiceExt;
+
+    async fn get_page(uri: &str) -> (StatusCode, serde_json::Value) {
+        let router = Router::new().route(
+            "/list",
+            get(|ValidatedQuery(page): ValidatedQuery<PageRequest>| async move {
+                Json(serde_json::json!({ "offset": page.get_offset(), "limit": page.get_limit() }))
+            }),
+        );
+        let resp = router
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_validated_query_page_request() {
+        let (status, body) = get_page("/list?num=3&limit=20").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "offset": 40, "limit": 20 }));
+
+        for uri in ["/list?limit=0", "/list?limit=5000", "/list?num=0"] {
+            let (status, body) = get_page(uri).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+            let field = uri.split(['?', '=']).nth(1).unwrap();
+            assert_eq!(body["errors"][field], serde_json::json!(["range"]), "{}", uri);
+        }
+
+        let (status, body) = get_page("/list?limit=abc").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["errmsg"].as_str().unwrap().starts_with("Query parsing error"),
+            "{}",
+            body
+        );
+    }
+
+    #[test]
+    fn test_page_request_offset_and_limit() {
+        let page = PageRequest {
+            num: Some(0),
+            limit: Some(100000),
+        };
+        assert_eq!(page.get_offset(), 0);
+        assert_eq!(page.get_limit(), PageRequest::MAX_LIMIT);
+
+        let page = PageRequest {
+            num: None,
+            limit: Some(0),
+        };
+        assert_eq!(page.get_offset(), 0);
+        assert_eq!(page.get_limit(), 1);
+    }
+}
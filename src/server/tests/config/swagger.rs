@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode};
+    use botwaf_server::config::{
+        config::{AppConfig, AppConfigProperties},
+        swagger,
+    };
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_openapi_contains_user_paths_and_schemas() {
+        let config = AppConfig::new(&AppConfigProperties::default());
+        let openapi = swagger::build_openapi(&config);
+
+        for path in [
+            "/sys/user/current",
+            "/sys/user/query",
+            "/sys/user/save",
+            "/sys/user/delete",
+            "/sys/user/status",
+            "/auth/password/verify",
+            "/auth/logout",
+        ] {
+            let path = swagger::join_context_path(&config, path.to_string());
+            assert!(openapi.paths.paths.contains_key(&path), "missing path: {}", path);
+        }
+
+        let schemas = &openapi.components.as_ref().expect("missing components").schemas;
+        for schema in [
+            "User",
+            "QueryUserResponse",
+            "SaveUserRequest",
+            "SaveUserResponse",
+            "DeleteUserRequest",
+            "SetUserStatusRequest",
+            "RespBase",
+        ] {
+            assert!(schemas.contains_key(schema), "missing schema: {}", schema);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi_json_served_without_swagger_ui() {
+        let mut props = AppConfigProperties::default();
+        props.swagger.enabled = false;
+        let config = AppConfig::new(&props);
+
+        let openapi_url = swagger::join_context_path(&config, config.swagger.openapi_url.to_string());
+        let req = Request::builder().uri(openapi_url).body(Body::empty()).unwrap();
+        let resp = swagger::init(&config).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let user_query_path = swagger::join_context_path(&config, "/sys/user/query".to_string());
+        assert!(spec["paths"].get(&user_query_path).is_some());
+        assert!(spec["components"]["schemas"].get("User").is_some());
+
+        // The swagger UI is not served when disabled.
+        let ui_path = swagger::join_context_path(&config, config.swagger.ui_path.to_string());
+        let req = Request::builder().uri(ui_path).body(Body::empty()).unwrap();
+        let resp = swagger::init(&config).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
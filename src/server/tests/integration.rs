@@ -19,5 +19,6 @@
 // This includes modifications and derived works.
 
 pub mod cache;
+pub mod config;
 pub mod store;
 pub mod sys;
@@ -19,3 +19,5 @@
 // This includes modifications and derived works.
 
 pub mod sqlite;
+pub mod postgres;
+pub mod pgvector;
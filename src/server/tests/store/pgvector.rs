@@ -0,0 +1,273 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use botwaf_server::{
+        config::config::{
+            EmbeddingLLMProperties, EmbeddingMismatchPolicy, PgVectorDBProperties, PostgresPropertiesBase,
+        },
+        modules::llm::handler::vector_maintenance::{
+            check_embedding_conflicts, EmbeddingRegistration, IVectorMaintenanceHandler, PgVectorMaintenanceHandler,
+            COLLECTION_TABLE_NAME, EMBEDDING_REGISTRY_TABLE_NAME, EMBEDDING_TABLE_NAME,
+        },
+    };
+    use botwaf_types::modules::llm::knowledge::{VectorIndexType, VectorReindexRequest};
+    use std::{env, sync::Arc};
+
+    fn create_test_config(prefix: &str) -> Option<PgVectorDBProperties> {
+        let host = env::var(format!("{}_HOST", prefix)).ok()?;
+        Some(PgVectorDBProperties {
+            inner: PostgresPropertiesBase {
+                host,
+                port: env::var(format!("{}_PORT", prefix))
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5432),
+                database: String::from("postgres"),
+                schema: String::from("public"),
+                username: String::from("postgres"),
+                password: Some(env::var(format!("{}_PASSWORD", prefix)).unwrap_or(String::from("changeit"))),
+                min_connections: Some(1),
+                max_connections: Some(2),
+                use_ssl: false,
+            },
+        })
+    }
+
+    // Notice: Requires a disposable postgres with the pgvector extension, e.g:
+    // docker run --rm -p 5432:5432 -e POSTGRES_PASSWORD=changeit pgvector/pgvector:pg16
+    fn create_test_handler() -> Option<Arc<PgVectorMaintenanceHandler>> {
+        create_test_config("IT_PGVECTOR").map(|config| PgVectorMaintenanceHandler::new(&config))
+    }
+
+    #[tokio::test]
+    async fn test_provision_without_extension_package_fails_with_actionable_error() {
+        // Notice: Requires a disposable postgres without the pgvector package, e.g:
+        // docker run --rm -p 5432:5432 -e POSTGRES_PASSWORD=changeit postgres:16
+        let Some(config) = create_test_config("IT_POSTGRES") else {
+            return;
+        };
+        let handler = PgVectorMaintenanceHandler::new(&config);
+
+        let err = handler.provision(1536).await.unwrap_err().to_string();
+        assert!(err.contains("pgvector extension is not installed"), "{}", err);
+        assert!(err.contains("CREATE EXTENSION vector"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_provision_idempotently_and_pre_delete_requires_force() {
+        let Some(config) = create_test_config("IT_PGVECTOR") else {
+            return;
+        };
+        let handler = PgVectorMaintenanceHandler::new(&config);
+        handler.provision(3).await.unwrap();
+        handler.provision(3).await.unwrap();
+        // The mismatched dimensions with the existing table is rejected.
+        let err = handler.provision(1536).await.unwrap_err().to_string();
+        assert!(err.contains("vector-dimensions"), "{}", err);
+
+        let mut embedding = EmbeddingLLMProperties::default();
+        assert!(!handler.resolve_pre_delete(&embedding).await.unwrap());
+
+        // Make the collection non-empty.
+        let pool = sqlx::PgPool::connect(&format!(
+            "postgres://{}:{}@{}:{}/{}",
+            config.username,
+            config.password.as_deref().unwrap_or(""),
+            config.host,
+            config.port,
+            config.database
+        ))
+        .await
+        .unwrap();
+        let collection_id = sqlx::types::uuid::Uuid::new_v4();
+        sqlx::query(&format!(
+            "INSERT INTO {} (name, cmetadata, uuid) VALUES ($1, '{{}}', $2)",
+            COLLECTION_TABLE_NAME
+        ))
+        .bind(format!("it-{}", collection_id))
+        .bind(collection_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!(
+            "INSERT INTO {} (collection_id, embedding, document, cmetadata, uuid) VALUES ($1, '[1,2,3]', 'it', '{{}}', $2)",
+            EMBEDDING_TABLE_NAME
+        ))
+        .bind(collection_id)
+        .bind(sqlx::types::uuid::Uuid::new_v4())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        embedding.pre_delete_collection = true;
+        assert!(!handler.resolve_pre_delete(&embedding).await.unwrap());
+        embedding.pre_delete_collection_force = true;
+        assert!(handler.resolve_pre_delete(&embedding).await.unwrap());
+
+        // Cleanup the provisioned tables of the test dimensions.
+        sqlx::query(&format!(
+            "DROP TABLE {}, {}",
+            EMBEDDING_TABLE_NAME, COLLECTION_TABLE_NAME
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    fn create_test_registration(component: &str, model: &str, vector_dimensions: usize) -> EmbeddingRegistration {
+        EmbeddingRegistration {
+            component: component.to_owned(),
+            model: model.to_owned(),
+            vector_dimensions,
+        }
+    }
+
+    #[test]
+    fn test_check_embedding_conflicts_with_policy() {
+        let registrations = vec![
+            create_test_registration("forwarder", "bge-m3", 1024),
+            create_test_registration("updater", "bge-m3", 1024),
+        ];
+        // The same model of the other components is not conflicting.
+        let current = create_test_registration("verifier", "bge-m3", 1024);
+        assert!(
+            check_embedding_conflicts(&current, &registrations, EmbeddingMismatchPolicy::REJECT)
+                .unwrap()
+                .is_empty()
+        );
+        // The re-registration of itself is only checked against the other components.
+        let current = create_test_registration("updater", "nomic-embed-text", 768);
+        assert!(
+            check_embedding_conflicts(&current, &registrations, EmbeddingMismatchPolicy::REJECT)
+                .unwrap_err()
+                .to_string()
+                .contains("'forwarder' with 'bge-m3' (1024 dimensions)")
+        );
+
+        // The different model of the same dimensions is also rejected.
+        let current = create_test_registration("verifier", "mxbai-embed-large", 1024);
+        let err = check_embedding_conflicts(&current, &registrations, EmbeddingMismatchPolicy::REJECT)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'forwarder' with 'bge-m3'"), "{}", err);
+        assert!(err.contains("'updater' with 'bge-m3'"), "{}", err);
+        assert!(err.contains(EMBEDDING_REGISTRY_TABLE_NAME), "{}", err);
+
+        let conflicts = check_embedding_conflicts(&current, &registrations, EmbeddingMismatchPolicy::WARN).unwrap();
+        assert_eq!(conflicts, registrations);
+    }
+
+    #[tokio::test]
+    async fn test_register_embedding_rejects_conflicting_registration() {
+        let Some(config) = create_test_config("IT_PGVECTOR") else {
+            return;
+        };
+        let handler = PgVectorMaintenanceHandler::new(&config);
+        handler.init().await.unwrap();
+        let pool = sqlx::PgPool::connect(&format!(
+            "postgres://{}:{}@{}:{}/{}",
+            config.username,
+            config.password.as_deref().unwrap_or(""),
+            config.host,
+            config.port,
+            config.database
+        ))
+        .await
+        .unwrap();
+        let cleanup_sql = format!(
+            "DELETE FROM {} WHERE component LIKE 'it-%'",
+            EMBEDDING_REGISTRY_TABLE_NAME
+        );
+        sqlx::query(&cleanup_sql).execute(&pool).await.unwrap();
+
+        let updater = create_test_registration("it-updater", "bge-m3", 1024);
+        let forwarder = create_test_registration("it-forwarder", "nomic-embed-text", 768);
+        handler
+            .register_embedding(&updater, EmbeddingMismatchPolicy::REJECT)
+            .await
+            .unwrap();
+        // Idempotently re-register on restart.
+        handler
+            .register_embedding(&updater, EmbeddingMismatchPolicy::REJECT)
+            .await
+            .unwrap();
+
+        let err = handler
+            .register_embedding(&forwarder, EmbeddingMismatchPolicy::REJECT)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'it-updater' with 'bge-m3' (1024 dimensions)"), "{}", err);
+        // The conflicting registration is allowed with the WARN policy.
+        let conflicts = handler
+            .register_embedding(&forwarder, EmbeddingMismatchPolicy::WARN)
+            .await
+            .unwrap();
+        assert!(conflicts.contains(&updater));
+        // Now the updater is conflicting with the forwarder registered by WARN.
+        assert!(handler
+            .register_embedding(&updater, EmbeddingMismatchPolicy::REJECT)
+            .await
+            .is_err());
+
+        // Cleanup the test registrations.
+        sqlx::query(&cleanup_sql).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_dry_run_removes_nothing() {
+        let Some(handler) = create_test_handler() else {
+            return;
+        };
+        handler.init().await.unwrap();
+
+        let result = handler.cleanup(true).await.unwrap();
+        assert!(result.dry_run);
+        assert_eq!(result.removed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_namespace_stats_and_reindex_dry_run() {
+        let Some(handler) = create_test_handler() else {
+            return;
+        };
+        handler.init().await.unwrap();
+
+        let stats = handler.namespace_stats().await.unwrap();
+        assert!(stats.iter().all(|s| s.documents >= 0));
+
+        let report = handler
+            .reindex(VectorReindexRequest {
+                index_type: VectorIndexType::HNSW,
+                ops: String::from("vector_cosine_ops"),
+                m: 16,
+                ef_construction: 64,
+                lists: 100,
+                samples: 5,
+                dry_run: true,
+            })
+            .await
+            .unwrap();
+        assert!(report.statement.contains("USING hnsw"));
+        assert!(report.after_avg_millis.is_none());
+    }
+}
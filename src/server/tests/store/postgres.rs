@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use botwaf_server::{
+        config::config::{PostgresAppDBProperties, PostgresPropertiesBase},
+        store::{postgres::PostgresRepository, AsyncRepository},
+        sys::store::users_postgresql::UserPostgresRepository,
+    };
+    use botwaf_types::{datetime::UtcDateTime, sys::user::User, BaseBean, PageRequest};
+    use std::{env, sync::Arc};
+
+    // Notice: Requires a disposable postgres, e.g:
+    // docker run --rm -p 5432:5432 -e POSTGRES_PASSWORD=changeit postgres:16
+    fn create_test_config() -> Option<PostgresAppDBProperties> {
+        let host = env::var("IT_POSTGRES_HOST").ok()?;
+        Some(PostgresAppDBProperties {
+            inner: PostgresPropertiesBase {
+                host,
+                port: env::var("IT_POSTGRES_PORT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5432),
+                database: String::from("postgres"),
+                schema: String::from("public"),
+                username: String::from("postgres"),
+                password: Some(env::var("IT_POSTGRES_PASSWORD").unwrap_or(String::from("changeit"))),
+                min_connections: Some(1),
+                max_connections: Some(2),
+                use_ssl: false,
+            },
+        })
+    }
+
+    async fn create_test_repository() -> Option<UserPostgresRepository> {
+        Some(UserPostgresRepository::new(&create_test_config()?).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_round_trip_utc_microseconds() {
+        let Some(repo) = create_test_repository().await else {
+            return;
+        };
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some("tom".to_string());
+        let update_time = user.base.update_time.unwrap();
+        let id = repo.insert(user).await.unwrap();
+
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(loaded.base.update_time, Some(update_time));
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_offset_timestamp_stored_as_utc() {
+        let Some(repo) = create_test_repository().await else {
+            return;
+        };
+
+        // The client sent the +08:00 offset timestamp.
+        let user: User = serde_json::from_value(serde_json::json!({
+            "name": "offset",
+            "update_time": "2026-10-16T08:00:00.000001+08:00",
+        }))
+        .unwrap();
+        let id = repo.insert(user).await.unwrap();
+
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(
+            loaded.base.update_time.unwrap().to_iso8601(),
+            "2026-10-16T00:00:00.000001Z"
+        );
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_first_logins_create_one_user() {
+        let Some(repo) = create_test_repository().await else {
+            return;
+        };
+        let repo = Arc::new(repo);
+        let oidc_sub = format!("it-oidc-{}", UtcDateTime::now().0.timestamp_micros());
+
+        // The two simultaneous first logins of the same oidc user.
+        let logins = (0..2).map(|_| {
+            let (repo, oidc_sub) = (repo.clone(), oidc_sub.to_owned());
+            tokio::spawn(async move {
+                let mut user = User::default();
+                user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+                user.oidc_claims_sub = Some(oidc_sub.to_owned());
+                repo.get_or_insert("oidc_claims_sub", &oidc_sub, user).await.unwrap()
+            })
+        });
+        let results = futures::future::join_all(logins)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(results[0].0, results[1].0);
+        assert_eq!(results.iter().filter(|(_, created)| *created).count(), 1);
+
+        repo.delete_by_id(results[0].0).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_select_excludes_soft_deleted_users() {
+        let Some(repo) = create_test_repository().await else {
+            return;
+        };
+        let raw = PostgresRepository::<User>::new(&create_test_config().unwrap())
+            .await
+            .unwrap();
+        let name = format!("it-soft-deleted-{}", UtcDateTime::now().0.timestamp_micros());
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some(name.to_owned());
+        let id = repo.insert(user).await.unwrap();
+        sqlx::query("UPDATE sys_user SET del_flag = 1 WHERE id = $1")
+            .bind(id)
+            .execute(raw.get_pool())
+            .await
+            .unwrap();
+
+        let mut param = User::default();
+        param.name = Some(name.to_owned());
+        let (page, users) = repo.select(param.to_owned(), PageRequest::default()).await.unwrap();
+        assert!(users.is_empty());
+        assert_eq!(page.total, Some(0));
+
+        let (_, users) = repo
+            .select_with_deleted(param, PageRequest::default(), true)
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].base.id, Some(id));
+
+        // The soft deleted user is never deleted by the repository.
+        sqlx::query("DELETE FROM sys_user WHERE id = $1")
+            .bind(id)
+            .execute(raw.get_pool())
+            .await
+            .unwrap();
+    }
+}
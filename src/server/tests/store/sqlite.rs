@@ -17,3 +17,210 @@
 // IMPORTANT: Any software that fully or partially contains or uses materials
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use botwaf_server::{
+        config::config::SqliteAppDBProperties,
+        store::{AsyncRepository, VersionConflictError},
+        sys::store::users_sqlite::UserSQLiteRepository,
+    };
+    use botwaf_types::{datetime::UtcDateTime, sys::user::User, BaseBean, PageRequest, RecordStatus};
+    use chrono::SubsecRound;
+    use std::{env, sync::Arc};
+
+    async fn create_test_repository() -> UserSQLiteRepository {
+        let dir = env::temp_dir().join(format!("botwaf-it-sqlite-{}", std::process::id()));
+        let config = SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        };
+        UserSQLiteRepository::new(&config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_update_with_stale_version_conflict() {
+        let repo = create_test_repository().await;
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some("jack".to_string());
+        let id = repo.insert(user).await.unwrap();
+
+        // Both of the editors loaded the same version.
+        let mut first = repo.select_by_id(id, None).await.unwrap();
+        let mut second = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(first.base.version, Some(0));
+
+        first.name = Some("jack-first".to_string());
+        repo.update(first).await.unwrap();
+
+        second.name = Some("jack-second".to_string());
+        let err = repo.update(second).await.unwrap_err();
+        let conflict = err.downcast_ref::<VersionConflictError>().expect("should be version conflict");
+        assert_eq!(conflict.id, id);
+        assert_eq!(conflict.version, 0);
+
+        let latest = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(latest.name, Some("jack-first".to_string()));
+        assert_eq!(latest.base.version, Some(1));
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_partial_update_clears_named_field_to_null() {
+        let repo = create_test_repository().await;
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some("lucy".to_string());
+        user.email = Some("lucy@example.com".to_string());
+        user.phone = Some("13800000000".to_string());
+        let id = repo.insert(user).await.unwrap();
+
+        // The full update never clears the none phone.
+        let mut loaded = repo.select_by_id(id, None).await.unwrap();
+        loaded.phone = None;
+        repo.update(loaded).await.unwrap();
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(loaded.phone, Some("13800000000".to_string()));
+
+        // The partial update clears the named phone, and leaves the unspecified email unchanged.
+        let mut param = User::default();
+        param.base = BaseBean::new_with_id(Some(id));
+        param.base.version = loaded.base.version;
+        param.email = Some("changed@example.com".to_string());
+        repo.update_fields(param, &["phone"]).await.unwrap();
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(loaded.phone, None);
+        assert_eq!(loaded.email, Some("lucy@example.com".to_string()));
+        assert_eq!(loaded.name, Some("lucy".to_string()));
+        assert_eq!(loaded.base.version, Some(2));
+
+        // The unknown field is rejected instead of silently ignored.
+        let mut param = User::default();
+        param.base = BaseBean::new_with_id(Some(id));
+        assert!(repo.update_fields(param, &["phnoe"]).await.is_err());
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_round_trip_utc_microseconds() {
+        let repo = create_test_repository().await;
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some("tom".to_string());
+        let update_time = user.base.update_time.unwrap();
+        let id = repo.insert(user).await.unwrap();
+
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(loaded.base.update_time, Some(update_time));
+        let create_time = loaded.base.create_time.unwrap();
+        assert_eq!(create_time.0, create_time.0.trunc_subsecs(6));
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_offset_timestamp_stored_as_utc() {
+        let repo = create_test_repository().await;
+
+        // The client sent the +08:00 offset timestamp.
+        let user: User = serde_json::from_value(serde_json::json!({
+            "name": "offset",
+            "update_time": "2026-10-16T08:00:00.000001+08:00",
+        }))
+        .unwrap();
+        let id = repo.insert(user).await.unwrap();
+
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert_eq!(
+            loaded.base.update_time.unwrap().to_iso8601(),
+            "2026-10-16T00:00:00.000001Z"
+        );
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disabled_user_excludable_from_listings() {
+        let repo = create_test_repository().await;
+
+        let mut user = User::default();
+        user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+        user.name = Some("disabled-rose".to_string());
+        let id = repo.insert(user).await.unwrap();
+
+        assert_eq!(repo.set_status(id, RecordStatus::Disabled).await.unwrap(), 1);
+        let loaded = repo.select_by_id(id, None).await.unwrap();
+        assert!(loaded.base.is_disabled());
+        assert_eq!(loaded.base.version, Some(1));
+        assert!(repo.select_by_id(id, Some(RecordStatus::Active)).await.is_err());
+        assert!(repo.select_by_id(id, Some(RecordStatus::Disabled)).await.is_ok());
+
+        let mut param = User::default();
+        param.name = Some("disabled-rose".to_string());
+        let (_, all) = repo.select(param.clone(), PageRequest::default()).await.unwrap();
+        assert_eq!(all.len(), 1);
+        param.base.status = Some(RecordStatus::Active.value());
+        let (_, active) = repo.select(param.clone(), PageRequest::default()).await.unwrap();
+        assert!(active.is_empty());
+
+        // Re-enable the user.
+        repo.set_status(id, RecordStatus::Active).await.unwrap();
+        let (_, active) = repo.select(param, PageRequest::default()).await.unwrap();
+        assert_eq!(active.len(), 1);
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_first_logins_create_one_user() {
+        let repo = Arc::new(create_test_repository().await);
+        let github_sub = format!("it-github-{}", UtcDateTime::now().0.timestamp_micros());
+
+        // The two simultaneous first logins of the same github user.
+        let logins = (0..2).map(|_| {
+            let (repo, github_sub) = (repo.clone(), github_sub.to_owned());
+            tokio::spawn(async move {
+                let mut user = User::default();
+                user.base = BaseBean::new_with_by(None, Some("it".to_string()), Some("it".to_string()));
+                user.name = Some(github_sub.to_owned());
+                user.github_claims_sub = Some(github_sub.to_owned());
+                repo.get_or_insert("github_claims_sub", &github_sub, user).await.unwrap()
+            })
+        });
+        let results = futures::future::join_all(logins)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(results[0].0, results[1].0);
+        assert_eq!(results.iter().filter(|(_, created)| *created).count(), 1);
+        let mut param = User::default();
+        param.github_claims_sub = Some(github_sub.to_owned());
+        let (_, users) = repo.select(param, PageRequest::default()).await.unwrap();
+        assert_eq!(users.len(), 1);
+
+        // The subsequent login gets the existing user.
+        let mut user = User::default();
+        user.github_claims_sub = Some(github_sub.to_owned());
+        let (id, created) = repo.get_or_insert("github_claims_sub", &github_sub, user).await.unwrap();
+        assert_eq!((id, created), (results[0].0, false));
+
+        repo.delete_by_id(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_or_insert_rejects_unsupported_key_column() {
+        let repo = create_test_repository().await;
+        let err = repo
+            .get_or_insert("name", "jack", User::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Unsupported"), "{}", err);
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use botwaf_server::{
+        config::config::{AppConfig, AppConfigProperties, AppDBType, SqliteAppDBProperties},
+        store::{AsyncRepository, RepositoryContainer},
+        sys::{
+            bootstrap::{BootstrapManager, BootstrapRejection, BootstrapSeed},
+            store::{bootstrap_sqlite::BootstrapSQLiteRepository, users_sqlite::UserSQLiteRepository},
+        },
+        util::auths,
+    };
+    use botwaf_types::{
+        sys::{bootstrap::BootstrapRequest, user::User},
+        PageRequest,
+    };
+    use std::{env, fs, path::PathBuf, sync::Arc};
+    use tokio::sync::Mutex;
+
+    fn create_test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("botwaf-it-bootstrap-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn create_test_manager(dir: &PathBuf, seed: Option<BootstrapSeed>) -> (BootstrapManager, Arc<AppConfig>) {
+        let sqlite = SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        };
+        let mut props = AppConfigProperties::default();
+        props.appdb.db_type = AppDBType::SQLITE;
+        props.appdb.sqlite = sqlite.to_owned();
+        let config = Arc::new(AppConfig::new(&props));
+
+        let user_repo = RepositoryContainer::<User>::new(
+            Some(Box::new(UserSQLiteRepository::new(&sqlite).await.unwrap())),
+            None,
+            None,
+        );
+        let bootstrap_repo = RepositoryContainer::new(
+            Some(Box::new(BootstrapSQLiteRepository::new(&sqlite).await.unwrap())),
+            None,
+            None,
+        );
+        let manager = BootstrapManager::new(
+            &config,
+            Arc::new(Mutex::new(user_repo)),
+            Arc::new(Mutex::new(bootstrap_repo)),
+            seed,
+        );
+        manager.init().await.unwrap();
+        (manager, config)
+    }
+
+    fn mock_bootstrap_request(username: &str) -> BootstrapRequest {
+        BootstrapRequest {
+            username: username.to_owned(),
+            password: Some("s3cret-passw0rd".to_owned()),
+            oidc_claims_sub: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_disabled_after_success() {
+        let dir = create_test_dir("disable");
+        let (manager, _) = create_test_manager(&dir, None).await;
+        assert!(manager.is_open().await.unwrap());
+
+        let result = manager.bootstrap(mock_bootstrap_request("root")).await.unwrap();
+        assert_eq!(result.name, "root");
+        assert!(!manager.is_open().await.unwrap());
+
+        // Any subsequent call is rejected with 410 gone.
+        let rejection = manager.bootstrap(mock_bootstrap_request("evil")).await.unwrap_err();
+        assert!(matches!(rejection, BootstrapRejection::Closed));
+
+        // The completed flag is persisted, so it's still closed after restarted.
+        let (restarted, _) = create_test_manager(&dir, None).await;
+        assert!(!restarted.is_open().await.unwrap());
+        assert!(matches!(
+            restarted.bootstrap(mock_bootstrap_request("evil")).await.unwrap_err(),
+            BootstrapRejection::Closed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_seeded_from_env_password_file() {
+        let password_file = env::temp_dir().join(format!("botwaf-it-bootstrap-seed-{}.txt", std::process::id()));
+        fs::write(&password_file, "s3cret-from-file\n").unwrap();
+        let seed = BootstrapSeed {
+            username: "ops".to_owned(),
+            password_file: password_file.to_string_lossy().to_string(),
+        };
+        let (manager, config) = create_test_manager(&create_test_dir("seed"), Some(seed)).await;
+
+        // The HTTP endpoint is never opened.
+        assert!(!manager.is_open().await.unwrap());
+        assert!(matches!(
+            manager.bootstrap(mock_bootstrap_request("evil")).await.unwrap_err(),
+            BootstrapRejection::Closed
+        ));
+
+        let user_repo = UserSQLiteRepository::new(&config.appdb.sqlite).await.unwrap();
+        let mut param = User::default();
+        param.name = Some("ops".to_owned());
+        let (_, users) = user_repo.select(param, PageRequest::default()).await.unwrap();
+        assert_eq!(users.len(), 1);
+        // The password is stored as the salted Argon2 hash, never the unsalted login password.
+        let stored_password = users[0].password.as_deref().unwrap();
+        assert!(stored_password.starts_with("$argon2"), "{}", stored_password);
+        let login_password = auths::hash_login_password("s3cret-from-file");
+        assert!(auths::verify_stored_password(
+            login_password.as_bytes(),
+            stored_password
+        ));
+        assert!(!auths::verify_stored_password(b"s3cret-from-file", stored_password));
+
+        fs::remove_file(&password_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_requires_password_or_oidc_subject() {
+        let (manager, _) = create_test_manager(&create_test_dir("invalid"), None).await;
+        let mut param = mock_bootstrap_request("root");
+        param.password = None;
+        assert!(matches!(
+            manager.bootstrap(param).await.unwrap_err(),
+            BootstrapRejection::Invalid(_)
+        ));
+        // Notice: The invalid attempt is also throttled globally.
+        assert!(matches!(
+            manager.bootstrap(mock_bootstrap_request("root")).await.unwrap_err(),
+            BootstrapRejection::Throttled { .. }
+        ));
+    }
+}
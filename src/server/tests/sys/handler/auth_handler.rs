@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+#[cfg(test)]
+mod tests {
+    use botwaf_server::sys::handler::auth_handler::AuthHandler;
+    use botwaf_types::{sys::user::User, BaseBean, RecordStatus};
+
+    fn mock_user(status: RecordStatus) -> User {
+        let mut user = User::default();
+        user.base = BaseBean::new_with_id(Some(1));
+        user.base.status = Some(status.value());
+        user.name = Some("jack".to_string());
+        user.password = Some("hashed-password".to_string());
+        user
+    }
+
+    #[test]
+    fn test_active_user_login_success() {
+        let user = mock_user(RecordStatus::Active);
+        assert!(AuthHandler::verify_login_user(&user, b"hashed-password").is_ok());
+        assert!(AuthHandler::verify_login_user(&user, b"wrong-password").is_err());
+    }
+
+    #[test]
+    fn test_disabled_user_login_failure() {
+        let user = mock_user(RecordStatus::Disabled);
+        let err = AuthHandler::verify_login_user(&user, b"hashed-password").unwrap_err();
+        assert_eq!(err.to_string(), "The user is disabled");
+    }
+}
@@ -17,3 +17,5 @@
 // IMPORTANT: Any software that fully or partially contains or uses materials
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
+
+pub mod auth_handler;
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+#[cfg(test)]
+mod tests {
+    use botwaf_server::{
+        config::config::{AppConfig, AppConfigProperties, AppDBType, SqliteAppDBProperties},
+        store::AsyncRepository,
+        sys::{identities, store::users_sqlite::UserSQLiteRepository},
+    };
+    use botwaf_types::{sys::user::User, BaseBean, PageRequest};
+    use std::{env, fs, sync::Arc};
+
+    async fn create_test_repo(name: &str) -> (UserSQLiteRepository, Arc<AppConfig>) {
+        let dir = env::temp_dir().join(format!("botwaf-it-identities-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let sqlite = SqliteAppDBProperties {
+            dir: Some(dir.to_string_lossy().to_string()),
+        };
+        let mut props = AppConfigProperties::default();
+        props.appdb.db_type = AppDBType::SQLITE;
+        props.appdb.sqlite = sqlite.to_owned();
+        let repo = UserSQLiteRepository::new(&sqlite).await.unwrap();
+        (repo, Arc::new(AppConfig::new(&props)))
+    }
+
+    // The same as the user handler saves, e.g: registered by password or auto-provisioned by the OIDC callback.
+    async fn save_user(
+        repo: &UserSQLiteRepository,
+        config: &AppConfig,
+        name: &str,
+        email: Option<&str>,
+        oidc_claims_sub: Option<&str>,
+    ) -> Result<i64, anyhow::Error> {
+        let mut user = User::default();
+        user.base = BaseBean::new_with_id(None);
+        user.name = Some(name.to_owned());
+        user.email = email.map(|e| e.to_owned());
+        user.oidc_claims_sub = oidc_claims_sub.map(|s| s.to_owned());
+        identities::normalize_user(config, &mut user);
+        identities::check_conflicts(repo, &user).await?;
+        repo.insert(user).await
+    }
+
+    async fn lookup_user(repo: &UserSQLiteRepository, name: Option<&str>, email: Option<&str>) -> Vec<User> {
+        let mut param = User::default();
+        param.name = name.map(|n| n.to_owned());
+        param.email = email.map(|e| e.to_owned());
+        repo.select(identities::to_lookup(param), PageRequest::default())
+            .await
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_normalize_username_and_email() {
+        assert_eq!(identities::normalize_username(" Alice "), "alice");
+        // The fullwidth compatibility characters are folded by NFKC.
+        assert_eq!(identities::normalize_username("ＡＬＩＣＥ"), "alice");
+        assert_eq!(
+            identities::normalize_email("Alice@Example.COM", true),
+            "Alice@example.com"
+        );
+        assert_eq!(
+            identities::normalize_email("Alice@Example.COM", false),
+            "alice@example.com"
+        );
+        assert_eq!(identities::email_key("Alice@Example.COM"), "alice@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_password_registration_mixed_case_collision() {
+        let (repo, config) = create_test_repo("password").await;
+        save_user(&repo, &config, "Alice", Some("Alice@Example.com"), None)
+            .await
+            .unwrap();
+
+        // The email domain is lowercased and the local part is preserved by default.
+        let users = lookup_user(&repo, None, Some("ALICE@example.COM")).await;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].email.as_deref(), Some("Alice@example.com"));
+        assert_eq!(lookup_user(&repo, Some("aLiCe"), None).await.len(), 1);
+
+        let err = save_user(&repo, &config, "ALICE", None, None).await.unwrap_err();
+        assert!(err.to_string().contains("already registered with different casing"));
+        let err = save_user(&repo, &config, "bob", Some("alice@example.com"), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already registered with different casing"));
+        let err = save_user(&repo, &config, "Alice", None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "The username 'Alice' is already registered");
+    }
+
+    #[tokio::test]
+    async fn test_oidc_provisioning_mixed_case_collision() {
+        let (repo, config) = create_test_repo("oidc").await;
+        save_user(&repo, &config, "carol", Some("carol@example.com"), None)
+            .await
+            .unwrap();
+
+        // The IdP returns the same user with the different email casing, which is not duplicated.
+        let err = save_user(&repo, &config, "carol.idp", Some("Carol@EXAMPLE.com"), Some("sub-1"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already registered with different casing"));
+
+        // The password registration collides with the auto-provisioned OIDC user.
+        save_user(&repo, &config, "Dave", Some("dave@example.com"), Some("sub-2"))
+            .await
+            .unwrap();
+        let err = save_user(&repo, &config, "dave", None, None).await.unwrap_err();
+        assert!(err.to_string().contains("already registered with different casing"));
+        assert_eq!(lookup_user(&repo, Some("DAVE"), None).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_self_not_conflicted() {
+        let (repo, config) = create_test_repo("update").await;
+        let id = save_user(&repo, &config, "erin", Some("erin@example.com"), None)
+            .await
+            .unwrap();
+
+        let mut user = lookup_user(&repo, Some("erin"), None).await.remove(0);
+        assert_eq!(user.base.id, Some(id));
+        user.name = Some("Erin".to_owned());
+        identities::normalize_user(&config, &mut user);
+        assert!(identities::check_conflicts(&repo, &user).await.is_ok());
+    }
+}
@@ -18,5 +18,7 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod bootstrap;
 pub mod handler;
+pub mod identities;
 pub mod route;
@@ -21,8 +21,55 @@
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
-    use axum::http;
-    use hyper::Request;
+    use axum::{
+        body::Body,
+        http::{self, StatusCode},
+        response::IntoResponse,
+        routing::{get, post},
+        Router,
+    };
+    use botwaf_server::{
+        config::config::{ApiKeyProperties, AppConfig, AppConfigProperties, PreAuthGateProperties},
+        config::duration::DurationSecs,
+        context::{
+            state::BotwafState,
+            test_support::{create_in_memory_cache, create_test_config, StaticLLMHandler},
+        },
+        mgmt::apm::metrics::BOTWAF_AUTH_FAILED_TOTAL,
+        sys::handler::auth_handler::{AuthHandler, IAuthHandler, InvalidPasswordError, PrincipalType},
+        sys::handler::user_handler::{IUserHandler, UserHandler},
+        sys::route::auth_router::{
+            self, auth_middleware, pre_auth_gate_middleware, should_redirect_root, AUTH_CALLBACK_GITHUB_URI,
+            AUTH_CALLBACK_OIDC_URI, AUTH_WALLET_ETHERS_VERIFY_URI,
+        },
+        util::auth_gate::{AuthFailure, AuthGate},
+        util::auths::{self, AuthUserClaims, ClientCertIdentity, SecurityContext, CSRF_COOKIE_NAME, CSRF_HEADER_NAME},
+        util::web::{CurrentUser, RequireAdmin},
+    };
+    use botwaf_types::sys::{
+        auth::{ChangePasswordRequest, PasswordLoginRequest, PasswordPubKeyRequest, ResetPasswordRequest},
+        user::{SaveUserRequest, SetUserStatusRequest},
+    };
+    use botwaf_types::RecordStatus;
+    use botwaf_utils::{base64s::Base64Helper, rsa_ciphers::RSACipher};
+    use hyper::{HeaderMap, Method, Request};
+    use modsecurity::Rules;
+    use openssl::{
+        asn1::Asn1Time,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder},
+    };
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::sync::Barrier;
+    use tower::ServiceExt;
     // use auth::tests::MockUserProvider;
     // use auth::UserProvider;
     // use http_body::Body;
@@ -76,7 +123,972 @@ mod tests {
         // );
     }
 
+    fn mock_config(success_url: &str, root_redirect: Option<bool>) -> Arc<AppConfig> {
+        let mut props = AppConfigProperties::default();
+        props.auth.success_url = Some(success_url.to_owned());
+        props.auth.root_redirect = root_redirect;
+        AppConfig::new(&props)
+    }
+
+    #[test]
+    fn test_root_redirect_enabled() {
+        let config = mock_config("/static/index.html", Some(true));
+        assert!(should_redirect_root(&config, "/"));
+        assert!(!should_redirect_root(&config, "/static/index.html"));
+    }
+
+    #[test]
+    fn test_root_redirect_disabled() {
+        let config = mock_config("/static/index.html", Some(false));
+        assert!(!should_redirect_root(&config, "/"));
+    }
+
+    #[test]
+    fn test_root_redirect_loop_prevention() {
+        let config = mock_config("/", None);
+        assert!(!should_redirect_root(&config, "/"));
+
+        let config = mock_config("/?from=login", None);
+        assert!(!should_redirect_root(&config, "/"));
+    }
+
+    fn mock_csrf_headers(cookie: Option<&str>, header: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(cookie) = cookie {
+            let value = format!("_ak=xxx; {}={}", CSRF_COOKIE_NAME, cookie);
+            headers.insert(http::header::COOKIE, value.parse().unwrap());
+        }
+        if let Some(header) = header {
+            headers.insert(CSRF_HEADER_NAME, header.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_csrf_valid_token_passed() {
+        let config = AppConfig::new(&AppConfigProperties::default());
+        let cookie = auths::create_csrf_cookie(&config, &HeaderMap::new());
+        let headers = mock_csrf_headers(Some(cookie.value()), Some(cookie.value()));
+        assert!(auths::verify_csrf_token(&headers));
+    }
+
+    #[test]
+    fn test_csrf_missing_or_mismatched_token_rejected() {
+        assert!(!auths::verify_csrf_token(&mock_csrf_headers(Some("abc"), None)));
+        assert!(!auths::verify_csrf_token(&mock_csrf_headers(None, Some("abc"))));
+        assert!(!auths::verify_csrf_token(&mock_csrf_headers(Some("abc"), Some("abd"))));
+        assert!(!auths::verify_csrf_token(&mock_csrf_headers(Some(""), Some(""))));
+    }
+
+    #[test]
+    fn test_csrf_required_methods() {
+        let mut props = AppConfigProperties::default();
+        let config = AppConfig::new(&props);
+        assert!(!auths::is_csrf_required(&config, &Method::GET));
+        assert!(auths::is_csrf_required(&config, &Method::POST));
+        assert!(auths::is_csrf_required(&config, &Method::PATCH));
+        assert!(auths::is_csrf_required(&config, &Method::DELETE));
+
+        props.auth.csrf_protection = Some(false);
+        let config = AppConfig::new(&props);
+        assert!(!auths::is_csrf_required(&config, &Method::POST));
+    }
+
+    fn mock_gated_router(props: &PreAuthGateProperties, handled: Arc<AtomicUsize>) -> Router {
+        Router::new()
+            .route(
+                AUTH_WALLET_ETHERS_VERIFY_URI,
+                post(move || {
+                    let handled = handled.clone();
+                    async move {
+                        // Simulates the expensive signature verification that always fails.
+                        handled.fetch_add(1, Ordering::SeqCst);
+                        let mut response = (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+                        response.extensions_mut().insert(AuthFailure);
+                        response
+                    }
+                }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                Arc::new(AuthGate::new(props)),
+                pre_auth_gate_middleware,
+            ))
+    }
+
+    fn mock_wallet_verify_request(signature: &str) -> Request<Body> {
+        let body = serde_json::json!({
+            "address": format!("0x{}", "a".repeat(40)),
+            "signature": signature,
+            "message": "Sign in to Botwaf",
+        });
+        Request::builder()
+            .method(Method::POST)
+            .uri(AUTH_WALLET_ETHERS_VERIFY_URI)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pre_auth_gate_throttles_invalid_signature_burst() {
+        let props = PreAuthGateProperties {
+            ip_capacity: 3,
+            ip_refill_per_sec: 0.0,
+            base_retry_after_secs: DurationSecs::from_secs(1),
+            max_retry_after_secs: DurationSecs::from_secs(8),
+            ..Default::default()
+        };
+        let handled = Arc::new(AtomicUsize::new(0));
+        let router = mock_gated_router(&props, handled.clone());
+        let failed_before = BOTWAF_AUTH_FAILED_TOTAL
+            .with_label_values(&[AUTH_WALLET_ETHERS_VERIFY_URI])
+            .get();
+
+        let signature = format!("0x{}", "b".repeat(130));
+        let mut retry_afters = Vec::new();
+        for _ in 0..6 {
+            let resp = router
+                .clone()
+                .oneshot(mock_wallet_verify_request(&signature))
+                .await
+                .unwrap();
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = resp.headers()[http::header::RETRY_AFTER].to_str().unwrap();
+                retry_afters.push(retry_after.parse::<u64>().unwrap());
+            } else {
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            }
+        }
+
+        // Only the bucket capacity reached the expensive handler, the rest are backed off exponentially.
+        assert_eq!(handled.load(Ordering::SeqCst), 3);
+        assert_eq!(retry_afters, vec![1, 2, 4]);
+        let failed_after = BOTWAF_AUTH_FAILED_TOTAL
+            .with_label_values(&[AUTH_WALLET_ETHERS_VERIFY_URI])
+            .get();
+        assert!(failed_after >= failed_before + 3);
+    }
+
+    #[tokio::test]
+    async fn test_pre_auth_gate_rejects_malformed_signature() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let router = mock_gated_router(&PreAuthGateProperties::default(), handled.clone());
+
+        for signature in ["0x1234", &format!("0x{}", "z".repeat(130))] {
+            let resp = router
+                .clone()
+                .oneshot(mock_wallet_verify_request(signature))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(handled.load(Ordering::SeqCst), 0);
+    }
+
     #[allow(unused)]
+    fn mock_client_cert_der(cn: &str, sans: &[&str]) -> Vec<u8> {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        name.append_entry_by_text("O", "Example").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let mut san = SubjectAlternativeName::new();
+        for dns in sans {
+            san.dns(dns);
+        }
+        let san = san.build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build().to_der().unwrap()
+    }
+
+    #[test]
+    fn test_client_cert_identity_from_der() {
+        let der = mock_client_cert_der("svc-a", &["svc-a.internal", "svc-a.example.com"]);
+        let identity = ClientCertIdentity::from_der(&der).unwrap();
+        assert_eq!(identity.subject, "CN=svc-a,O=Example");
+        assert_eq!(identity.common_name.as_deref(), Some("svc-a"));
+        assert_eq!(identity.sans, vec!["svc-a.internal", "svc-a.example.com"]);
+    }
+
+    #[test]
+    fn test_client_cert_allowlisted_principal() {
+        let mut props = AppConfigProperties::default();
+        props.auth.client_cert_allowlist = vec!["svc-a.internal".to_owned()];
+        let config = AppConfig::new(&props);
+
+        let der = mock_client_cert_der("svc-a", &["svc-a.internal"]);
+        let identity = ClientCertIdentity::from_der(&der).unwrap();
+        let claims = auths::authenticate_client_cert(&config, Some(&identity)).unwrap();
+        assert!(matches!(claims.ptype, PrincipalType::ClientCert));
+        assert_eq!(claims.uname, "svc-a.internal");
+        assert_eq!(
+            claims.ext.unwrap().get("subject").map(|s| s.as_str()),
+            Some("CN=svc-a,O=Example")
+        );
+    }
+
+    #[test]
+    fn test_client_cert_unknown_subject_rejected() {
+        let mut props = AppConfigProperties::default();
+        props.auth.client_cert_allowlist = vec!["svc-a".to_owned()];
+        let config = AppConfig::new(&props);
+
+        let der = mock_client_cert_der("svc-b", &["svc-b.internal"]);
+        let identity = ClientCertIdentity::from_der(&der).unwrap();
+        assert!(auths::authenticate_client_cert(&config, Some(&identity)).is_none());
+        assert!(auths::authenticate_client_cert(&config, None).is_none());
+    }
+
+    fn mock_trusted_header_config() -> Arc<AppConfig> {
+        let mut props = AppConfigProperties::default();
+        props.auth.trusted_identity_header = Some("X-Authenticated-User".to_owned());
+        props.auth.trusted_proxies = vec!["10.0.0.0/8".to_owned()];
+        AppConfig::new(&props)
+    }
+
+    #[test]
+    fn test_trusted_header_from_proxy() {
+        let config = mock_trusted_header_config();
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Authenticated-User", "alice".parse().unwrap());
+
+        let proxy: SocketAddr = "10.1.2.3:40000".parse().unwrap();
+        let claims = auths::authenticate_trusted_header(&config, &headers, Some(&proxy)).unwrap();
+        assert!(matches!(claims.ptype, PrincipalType::TrustedHeader));
+        assert_eq!(claims.uname, "alice");
+        assert_eq!(claims.ext.unwrap().get("proxy").map(|s| s.as_str()), Some("10.1.2.3"));
+    }
+
+    #[test]
+    fn test_trusted_header_ignored_from_direct_client() {
+        let config = mock_trusted_header_config();
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Authenticated-User", "alice".parse().unwrap());
+        // Notice: The spoofed X-Forwarded-For is never used to match the trusted proxies.
+        headers.insert("X-Forwarded-For", "10.1.2.3".parse().unwrap());
+
+        let client: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        assert!(auths::authenticate_trusted_header(&config, &headers, Some(&client)).is_none());
+        assert!(auths::authenticate_trusted_header(&config, &headers, None).is_none());
+
+        // The header is ignored if not configured even from the proxy.
+        let proxy: SocketAddr = "10.1.2.3:40000".parse().unwrap();
+        let config = AppConfig::new(&AppConfigProperties::default());
+        assert!(auths::authenticate_trusted_header(&config, &headers, Some(&proxy)).is_none());
+    }
+
+    fn mock_forwarded_request(peer: &str) -> Request<Body> {
+        let mut req = Request::builder()
+            .uri("/api/v1/protected")
+            .header(http::header::HOST, "10.1.2.10:9999")
+            .header(auths::X_FORWARDED_PROTO_HEADER_NAME, "https")
+            .header(auths::X_FORWARDED_HOST_HEADER_NAME, "waf.example.com")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(peer.parse::<SocketAddr>().unwrap());
+        req
+    }
+
+    #[test]
+    fn test_external_url_proxied_https() {
+        let config = mock_trusted_header_config();
+        let mut req = mock_forwarded_request("10.1.2.3:40000");
+        auths::strip_untrusted_forwarded(&config, &mut req);
+
+        assert!(auths::is_secure_request(&config, req.headers()));
+        assert_eq!(
+            auths::to_external_url(&config, req.headers(), "/static/login.html".to_owned()),
+            "https://waf.example.com/static/login.html"
+        );
+    }
+
+    #[test]
+    fn test_external_url_direct_http() {
+        // The spoofed forwarded headers of the direct client are stripped.
+        let config = mock_trusted_header_config();
+        let mut req = mock_forwarded_request("203.0.113.7:40000");
+        auths::strip_untrusted_forwarded(&config, &mut req);
+
+        assert!(!auths::is_secure_request(&config, req.headers()));
+        assert_eq!(
+            auths::to_external_url(&config, req.headers(), "/static/login.html".to_owned()),
+            "http://10.1.2.10:9999/static/login.html"
+        );
+        // The relative is kept if underivable.
+        assert_eq!(
+            auths::to_external_url(&config, &HeaderMap::new(), "/static/login.html".to_owned()),
+            "/static/login.html"
+        );
+    }
+
+    #[test]
+    fn test_external_url_configured_base_takes_precedence() {
+        let mut props = AppConfigProperties::default();
+        props.auth.external_base_url = Some("https://waf.example.com/".to_owned());
+        let config = AppConfig::new(&props);
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, "10.1.2.10:9999".parse().unwrap());
+
+        assert!(auths::is_secure_request(&config, &headers));
+        assert_eq!(
+            auths::to_external_url(&config, &headers, "/static/403.html".to_owned()),
+            "https://waf.example.com/static/403.html"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_success_cookies_secure_behind_tls_proxy() {
+        let state = mock_named_state("login-secure-cookies").await;
+        let uid = mock_password_user(&state, "secure-tester", "password").await;
+        let set_cookies = |resp: &axum::response::Response| {
+            resp.headers()
+                .get_all(http::header::SET_COOKIE)
+                .iter()
+                .map(|v| v.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        // Proxied https, i.e: the forwarded headers were kept by the auth middleware.
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, "waf.example.com".parse().unwrap());
+        headers.insert(auths::X_FORWARDED_PROTO_HEADER_NAME, "https".parse().unwrap());
+        let resp = AuthHandler::new(&state)
+            .handle_login_success(
+                &state.config,
+                PrincipalType::Password,
+                uid,
+                "secure-tester",
+                "",
+                &headers,
+            )
+            .await;
+        let cookies = set_cookies(&resp);
+        assert_eq!(cookies.len(), 3);
+        assert!(cookies.iter().all(|c| c.contains("Secure")));
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert!(json["redirectUrl"]
+            .as_str()
+            .unwrap()
+            .starts_with("https://waf.example.com/"));
+
+        // Direct http.
+        headers.remove(auths::X_FORWARDED_PROTO_HEADER_NAME);
+        let resp = AuthHandler::new(&state)
+            .handle_login_success(
+                &state.config,
+                PrincipalType::Password,
+                uid,
+                "secure-tester",
+                "",
+                &headers,
+            )
+            .await;
+        let cookies = set_cookies(&resp);
+        assert_eq!(cookies.len(), 3);
+        assert!(cookies.iter().all(|c| !c.contains("Secure")));
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert!(json["redirectUrl"]
+            .as_str()
+            .unwrap()
+            .starts_with("http://waf.example.com/"));
+    }
+
+    #[test]
+    fn test_api_key_authenticated_with_scopes() {
+        let mut props = AppConfigProperties::default();
+        props.auth.api_keys = vec![ApiKeyProperties {
+            name: "warehouse".to_owned(),
+            api_key: "s3cr3t".to_owned(),
+            scopes: vec!["events:export".to_owned(), "events:read".to_owned()],
+        }];
+        let config = AppConfig::new(&props);
+        let mut headers = HeaderMap::new();
+        headers.insert(auths::API_KEY_HEADER_NAME, "s3cr3t".parse().unwrap());
+
+        let claims = auths::authenticate_api_key(&config, &headers).unwrap();
+        assert!(matches!(claims.ptype, PrincipalType::ApiKey));
+        assert_eq!(claims.uname, "warehouse");
+        assert_eq!(
+            claims.ext.unwrap().get(auths::SCOPES_CLAIM_NAME).map(|s| s.as_str()),
+            Some("events:export events:read")
+        );
+
+        headers.insert(auths::API_KEY_HEADER_NAME, "s3cr3t-wrong".parse().unwrap());
+        assert!(auths::authenticate_api_key(&config, &headers).is_none());
+        assert!(auths::authenticate_api_key(&config, &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_roles_granted_to_user_principals_only() {
+        let mut props = AppConfigProperties::default();
+        props.auth.admin_users = Some(vec!["admin@example.com".to_owned()]);
+        props.auth.operator_users = Some(vec!["oncall".to_owned()]);
+        let config = AppConfig::new(&props);
+        let claims = |ptype: PrincipalType, uname: &str, email: &str| AuthUserClaims {
+            ptype,
+            uid: 0,
+            uname: uname.to_owned(),
+            email: email.to_owned(),
+            exp: 0,
+            iat: 0,
+            iat_ms: 0,
+            ext: None,
+        };
+
+        assert!(auths::is_admin(
+            &config,
+            &claims(PrincipalType::Password, "alice", "admin@example.com")
+        ));
+        assert!(auths::is_admin(
+            &config,
+            &claims(PrincipalType::OIDC, "Admin@Example.com", "")
+        ));
+        assert!(auths::is_operator(&config, &claims(PrincipalType::OIDC, "oncall", "")));
+
+        // The names of the other principals are same as the admin and operator users.
+        for ptype in [
+            PrincipalType::ClientCert,
+            PrincipalType::TrustedHeader,
+            PrincipalType::ApiKey,
+            PrincipalType::Github,
+            PrincipalType::EtherWallet,
+        ] {
+            let admin = claims(ptype.to_owned(), "admin@example.com", "admin@example.com");
+            assert!(!auths::is_admin(&config, &admin));
+            assert!(!auths::is_operator(&config, &admin));
+            assert!(!auths::is_operator(&config, &claims(ptype, "oncall", "")));
+        }
+    }
+
+    // The state with the in-memory fakes, which requires no external services.
+    async fn mock_state() -> BotwafState {
+        mock_named_state("auth-middleware").await
+    }
+
+    async fn mock_named_state(name: &str) -> BotwafState {
+        BotwafState::builder()
+            .with_config(&create_test_config(name))
+            .with_cache(create_in_memory_cache())
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(Rules::new(), Vec::new())
+            .build()
+            .await
+            .unwrap()
+    }
+
+    fn mock_protected_request(token: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().uri("/api/v1/protected");
+        if let Some(token) = token {
+            req = req.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware_without_external_services() {
+        let state = mock_state().await;
+        let router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+
+        let resp = router.to_owned().oneshot(mock_protected_request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let token = auths::create_jwt(&state.config, &PrincipalType::Password, 1, "tester", "", false, None);
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // The logged out token is in the blacklist of the cache.
+        let key = AuthHandler::new(&state).build_logout_blacklist_key(&token);
+        let cache = state.string_cache.get(&state.config);
+        cache.set(key, String::from("1"), None).await.unwrap();
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn mock_protected_paths_config() -> Arc<AppConfig> {
+        let mut properties = create_test_config("protected-paths").inner.to_owned();
+        properties.auth.anonymous_paths = Some(vec!["/public/**".to_owned()]);
+        properties.auth.protected_paths = Some(vec!["/public/admin".to_owned(), "/public/admin/**".to_owned()]);
+        AppConfig::new(&properties)
+    }
+
+    #[test]
+    fn test_protected_paths_take_precedence_over_anonymous_paths() {
+        let config = mock_protected_paths_config();
+        assert!(auths::is_anonymous_request(&config, &"/public/x".parse().unwrap()));
+        assert!(!auths::is_anonymous_request(&config, &"/public/admin".parse().unwrap()));
+        assert!(!auths::is_anonymous_request(
+            &config,
+            &"/public/admin/users".parse().unwrap()
+        ));
+        assert!(!auths::is_anonymous_request(
+            &config,
+            &"/api/v1/protected".parse().unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware_protected_paths() {
+        let state = BotwafState::builder()
+            .with_config(&mock_protected_paths_config())
+            .with_cache(create_in_memory_cache())
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(Rules::new(), Vec::new())
+            .build()
+            .await
+            .unwrap();
+        let router = Router::new()
+            .route("/public/x", get(|| async { "public" }))
+            .route("/public/admin", get(|| async { "admin" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let resp = router.to_owned().oneshot(request("/public/x")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = router.to_owned().oneshot(request("/public/admin")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // Create the password user, and envelope the passwords by the login pubkey of the fingerprint token.
+    async fn mock_password_user(state: &BotwafState, name: &str, password: &str) -> i64 {
+        mock_password_user_with_lang(state, name, password, None).await
+    }
+
+    async fn mock_password_user_with_lang(state: &BotwafState, name: &str, password: &str, lang: Option<&str>) -> i64 {
+        let login_password = auths::hash_login_password(password);
+        let stored_password = auths::hash_stored_password(login_password.as_bytes()).unwrap();
+        UserHandler::new(state)
+            .save(SaveUserRequest {
+                id: None,
+                version: None,
+                name: Some(name.to_owned()),
+                email: None,
+                phone: None,
+                password: Some(stored_password),
+                oidc_claims_sub: None,
+                oidc_claims_name: None,
+                oidc_claims_email: None,
+                github_claims_sub: None,
+                github_claims_name: None,
+                github_claims_email: None,
+                google_claims_sub: None,
+                google_claims_name: None,
+                google_claims_email: None,
+                ethers_address: None,
+                lang: lang.map(|l| l.to_owned()),
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn mock_enveloped_password(state: &BotwafState, fingerprint: &str, password: &str) -> String {
+        let handler = AuthHandler::new(state);
+        handler
+            .handle_password_pubkey(PasswordPubKeyRequest {
+                fingerprint_token: fingerprint.to_owned(),
+            })
+            .await
+            .unwrap();
+        let cache = state.string_cache.get(&state.config);
+        let private_key = cache
+            .get(handler.build_login_private_key(fingerprint))
+            .await
+            .unwrap()
+            .unwrap();
+        let cipher = RSACipher::from_base64(&private_key).unwrap();
+        let login_password = auths::hash_login_password(password);
+        Base64Helper::encode(&cipher.encrypt(login_password.as_bytes()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_password_change_with_wrong_current_password_rejected() {
+        let state = mock_named_state("password-change").await;
+        let uid = mock_password_user(&state, "change-tester", "old-password").await;
+        let handler = AuthHandler::new(&state);
+
+        let param = ChangePasswordRequest {
+            current_password: mock_enveloped_password(&state, "fp-change", "wrong-password").await,
+            new_password: mock_enveloped_password(&state, "fp-change", "new-password").await,
+            fingerprint_token: String::from("fp-change"),
+        };
+        let err = handler.handle_password_change(uid, param).await.unwrap_err();
+        assert!(err.downcast_ref::<InvalidPasswordError>().is_some());
+
+        // The password is unchanged.
+        let login = PasswordLoginRequest {
+            username: String::from("change-tester"),
+            password: mock_enveloped_password(&state, "fp-change", "old-password").await,
+            fingerprint_token: String::from("fp-change"),
+            challenge_token: None,
+        };
+        assert!(handler.handle_password_verify(login).await.is_ok());
+
+        // The password is changed with the correct current password.
+        let param = ChangePasswordRequest {
+            current_password: mock_enveloped_password(&state, "fp-change", "old-password").await,
+            new_password: mock_enveloped_password(&state, "fp-change", "new-password").await,
+            fingerprint_token: String::from("fp-change"),
+        };
+        handler.handle_password_change(uid, param).await.unwrap();
+        let login = PasswordLoginRequest {
+            username: String::from("change-tester"),
+            password: mock_enveloped_password(&state, "fp-change", "new-password").await,
+            fingerprint_token: String::from("fp-change"),
+            challenge_token: None,
+        };
+        assert!(handler.handle_password_verify(login).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_admin_reset_password_invalidates_old_tokens() {
+        let state = mock_named_state("password-reset").await;
+        let uid = mock_password_user(&state, "reset-tester", "old-password").await;
+        let router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+
+        let token = auths::create_jwt(
+            &state.config,
+            &PrincipalType::Password,
+            uid,
+            "reset-tester",
+            "",
+            false,
+            None,
+        );
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let handler = AuthHandler::new(&state);
+        let param = ResetPasswordRequest {
+            new_password: mock_enveloped_password(&state, "fp-reset", "new-password").await,
+            fingerprint_token: String::from("fp-reset"),
+        };
+        handler.handle_password_reset(uid, param).await.unwrap();
+
+        // The token issued before the reset is invalid.
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // The token issued right after the reset (i.e: in the same second) is valid.
+        let renewed_token = auths::create_jwt(
+            &state.config,
+            &PrincipalType::Password,
+            uid,
+            "reset-tester",
+            "",
+            false,
+            None,
+        );
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&renewed_token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // The epoch is persisted with the user, i.e: reloaded from the store even if evicted from the cache.
+        let evicted = mock_named_state("password-reset").await;
+        let evicted_router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(
+                evicted.to_owned(),
+                auth_middleware,
+            ))
+            .with_state(evicted.to_owned());
+        let resp = evicted_router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = evicted_router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&renewed_token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Login with the new password.
+        let login = PasswordLoginRequest {
+            username: String::from("reset-tester"),
+            password: mock_enveloped_password(&state, "fp-reset", "new-password").await,
+            fingerprint_token: String::from("fp-reset"),
+            challenge_token: None,
+        };
+        assert!(handler.handle_password_verify(login).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disable_user_invalidates_old_tokens() {
+        let state = mock_named_state("user-disable").await;
+        let uid = mock_password_user(&state, "disable-tester", "password").await;
+        let router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+
+        let token = auths::create_jwt(
+            &state.config,
+            &PrincipalType::Password,
+            uid,
+            "disable-tester",
+            "",
+            false,
+            None,
+        );
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let updated = UserHandler::new(&state)
+            .set_status(SetUserStatusRequest {
+                id: uid,
+                status: RecordStatus::Disabled,
+            })
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+
+        // The token issued before the user disabled is invalid.
+        let resp = router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // The epoch is persisted with the user, i.e: reloaded from the store even if evicted from the cache.
+        let evicted = mock_named_state("user-disable").await;
+        let evicted_router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(
+                evicted.to_owned(),
+                auth_middleware,
+            ))
+            .with_state(evicted.to_owned());
+        let resp = evicted_router
+            .to_owned()
+            .oneshot(mock_protected_request(Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_authorized_by_own_principal() {
+        let mut properties = create_test_config("concurrent-principals").inner.to_owned();
+        properties.auth.admin_users = Some(vec!["admin-tester".to_owned()]);
+        let state = BotwafState::builder()
+            .with_config(&AppConfig::new(&properties))
+            .with_cache(create_in_memory_cache())
+            .with_llm(Arc::new(StaticLLMHandler {
+                answer: String::from("PASS"),
+            }))
+            .with_rules(Rules::new(), Vec::new())
+            .build()
+            .await
+            .unwrap();
+        // Both the requests are in flight together, i.e: the later authenticated never overrides the former.
+        let barrier = Arc::new(Barrier::new(2));
+        let router = Router::new()
+            .route(
+                "/api/v1/protected",
+                get(move |CurrentUser(claims): CurrentUser| async move {
+                    barrier.wait().await;
+                    let current = SecurityContext::get_instance().get_current_uname().await;
+                    assert_eq!(current.as_deref(), Some(claims.uname.as_str()));
+                    claims.uname
+                }),
+            )
+            .route("/api/v1/admin", get(|_: RequireAdmin| async { "admin" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+        let token = |uid: i64, name: &str| {
+            auths::create_jwt(&state.config, &PrincipalType::Password, uid, name, "", false, None)
+        };
+        let (admin_token, user_token) = (token(1, "admin-tester"), token(2, "user-tester"));
+
+        let (admin_resp, user_resp) = tokio::join!(
+            router.to_owned().oneshot(mock_protected_request(Some(&admin_token))),
+            router.to_owned().oneshot(mock_protected_request(Some(&user_token)))
+        );
+        assert_eq!(admin_resp.unwrap().status(), StatusCode::OK);
+        assert_eq!(user_resp.unwrap().status(), StatusCode::OK);
+
+        let request = |token: &str| {
+            Request::builder()
+                .uri("/api/v1/admin")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = router.to_owned().oneshot(request(&admin_token)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = router.to_owned().oneshot(request(&user_token)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(read_errmsg(resp).await, "Forbidden, requires the admin role.");
+    }
+
+    async fn read_errmsg(resp: axum::response::Response) -> String {
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        json["errmsg"].as_str().unwrap_or_default().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware_localized_by_accept_language() {
+        let state = mock_named_state("i18n-accept-language").await;
+        let router = Router::new()
+            .route("/api/v1/protected", get(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+        let request = |accept_language: &str| {
+            Request::builder()
+                .uri("/api/v1/protected")
+                .header(http::header::ACCEPT_LANGUAGE, accept_language)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = router
+            .to_owned()
+            .oneshot(request("zh-CN,zh;q=0.9,en;q=0.8"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get(http::header::CONTENT_LANGUAGE).unwrap(), "zh-CN");
+        assert_eq!(read_errmsg(resp).await, "未登录或登录已过期");
+
+        let resp = router.to_owned().oneshot(request("ja-JP")).await.unwrap();
+        assert_eq!(
+            read_errmsg(resp).await,
+            "ログインしていないか、ログインの有効期限が切れています"
+        );
+
+        // Fallback to the English if unsupported.
+        let resp = router.to_owned().oneshot(request("fr-FR, de;q=0.5")).await.unwrap();
+        assert_eq!(resp.headers().get(http::header::CONTENT_LANGUAGE).unwrap(), "en");
+        assert_eq!(read_errmsg(resp).await, "Logout");
+    }
+
+    #[tokio::test]
+    async fn test_callback_access_denied_redirect_to_login() {
+        let state = mock_named_state("callback-access-denied").await;
+        let router = auth_router::init().with_state(state.to_owned());
+        // The providers may send the repeated and the unknown params on callback.
+        let request = |uri: &str, user_agent: &str| {
+            Request::builder()
+                .uri(format!(
+                    "{}?error=access_denied&error_description=The+user+denied&state=s1&state=s2&iss=https%3A%2F%2Fidp",
+                    uri
+                ))
+                .header(http::header::USER_AGENT, user_agent)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for uri in [AUTH_CALLBACK_OIDC_URI, AUTH_CALLBACK_GITHUB_URI] {
+            let resp = router.to_owned().oneshot(request(uri, "curl/8.0")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            assert_eq!(json["errmsg"], "The authorization is denied, please login again");
+            assert!(json["redirectUrl"].as_str().unwrap().ends_with("/static/login.html"));
+
+            // The browser is redirected to the login with the friendly message.
+            let resp = router.to_owned().oneshot(request(uri, "Mozilla/5.0")).await.unwrap();
+            assert!(resp.status().is_redirection());
+            let location = resp.headers().get(http::header::LOCATION).unwrap().to_str().unwrap();
+            assert!(location.contains("/static/login.html#help-troubleshooting-is-The authorization is denied"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_success_localized_by_user_lang() {
+        let state = mock_named_state("i18n-user-lang").await;
+        let uid = mock_password_user_with_lang(&state, "i18n-tester", "password", Some("zh-CN")).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::ACCEPT_LANGUAGE, "ja".parse().unwrap());
+
+        // The language of the user takes precedence over the 'Accept-Language'
+        let resp = AuthHandler::new(&state)
+            .handle_login_success(&state.config, PrincipalType::Password, uid, "i18n-tester", "", &headers)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_errmsg(resp).await, "认证成功");
+
+        // The language is carried by the issued token, e.g: the CSRF rejection of the cookie authenticated.
+        let mut ext = std::collections::HashMap::new();
+        ext.insert(auths::LANG_CLAIM_NAME.to_owned(), String::from("zh-CN"));
+        let token = auths::create_jwt(
+            &state.config,
+            &PrincipalType::Password,
+            uid,
+            "i18n-tester",
+            "",
+            false,
+            Some(ext),
+        );
+        let router = Router::new()
+            .route("/api/v1/protected", post(|| async { "protected" }))
+            .layer(axum::middleware::from_fn_with_state(state.to_owned(), auth_middleware))
+            .with_state(state.to_owned());
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/v1/protected")
+            .header(http::header::ACCEPT_LANGUAGE, "ja")
+            .header(
+                http::header::COOKIE,
+                format!("{}={}", state.config.auth_jwt_ak_name, token),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&body), "无效的 CSRF 令牌");
+    }
+
     fn mock_http_request(auth_header: Option<&str>, uri: Option<&str>) -> Result<Request<()>, Error> {
         let mut req =
             Request::builder().uri(uri.unwrap_or(format!("http://localhost:9000/_/healthz?foo=bar").as_str()));
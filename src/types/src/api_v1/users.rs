@@ -73,6 +73,9 @@ impl QueryUserApiV1Request {
             google_claims_email: None,
             ethers_address: None,
             lang: None,
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         }
     }
 }
@@ -147,6 +150,9 @@ impl SaveUserApiV1Request {
             google_claims_email: self.google_claims_email.clone(),
             ethers_address: self.ethers_address.clone(),
             lang: self.lang.clone(),
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         }
     }
 }
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, SubsecRound, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef},
+    Decode, Encode, Postgres, Sqlite, Type,
+};
+use std::{fmt, ops::Deref};
+
+/// The UTC timestamp of the beans, which is standardized across all the stores, that is the timestamptz in
+/// Postgres and the ISO-8601 UTC strings (e.g: 2024-07-10T08:37:54.123456Z) in SQLite.
+///
+/// Notice: The reading is tolerated both the legacy naive strings (regarded as UTC, e.g: the SQLite default
+/// current_timestamp '2024-07-10 08:37:54') and the strings with any offsets (e.g: +08:00 from the clients).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UtcDateTime(pub DateTime<Utc>);
+
+impl UtcDateTime {
+    // The legacy naive formats, which are regarded as UTC.
+    const NAIVE_FORMATS: [&'static str; 2] = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"];
+    // The offset formats of non RFC3339, e.g: the Postgres text output '2024-07-10 16:37:54.123456+08'
+    const OFFSET_FORMATS: [&'static str; 2] = ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%d %H:%M:%S%.f %#z"];
+
+    /// The current time truncated to microseconds, which is the max precision of Postgres timestamptz,
+    /// so that the values are equal after round-trip of any stores.
+    pub fn now() -> Self {
+        UtcDateTime(Utc::now().trunc_subsecs(6))
+    }
+
+    pub fn parse(value: &str) -> Result<Self, chrono::ParseError> {
+        let value = value.trim();
+        let parsed = DateTime::parse_from_rfc3339(value).map(|dt| dt.with_timezone(&Utc));
+        let parsed = Self::OFFSET_FORMATS.iter().fold(parsed, |parsed, format| {
+            parsed.or_else(|_| DateTime::parse_from_str(value, format).map(|dt| dt.with_timezone(&Utc)))
+        });
+        let parsed = Self::NAIVE_FORMATS.iter().fold(parsed, |parsed, format| {
+            parsed.or_else(|_| NaiveDateTime::parse_from_str(value, format).map(|dt| dt.and_utc()))
+        });
+        parsed.map(UtcDateTime)
+    }
+
+    /// The ISO-8601 UTC string with microseconds, e.g: 2024-07-10T08:37:54.123456Z
+    pub fn to_iso8601(&self) -> String {
+        self.0.to_rfc3339_opts(SecondsFormat::Micros, true)
+    }
+}
+
+impl Deref for UtcDateTime {
+    type Target = DateTime<Utc>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<DateTime<Utc>> for UtcDateTime {
+    fn from(value: DateTime<Utc>) -> Self {
+        UtcDateTime(value)
+    }
+}
+
+impl fmt::Display for UtcDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_iso8601())
+    }
+}
+
+impl Serialize for UtcDateTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_iso8601())
+    }
+}
+
+impl<'de> Deserialize<'de> for UtcDateTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        UtcDateTime::parse(&value).map_err(|e| serde::de::Error::custom(format!("Invalid datetime '{}'. {}", value, e)))
+    }
+}
+
+// The SQLite adapter, which stores the ISO-8601 UTC strings.
+
+impl Type<Sqlite> for UtcDateTime {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <DateTime<Utc> as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for UtcDateTime {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<IsNull, BoxDynError> {
+        <String as Encode<'q, Sqlite>>::encode(self.to_iso8601(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for UtcDateTime {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let text = <&str as Decode<'r, Sqlite>>::decode(value)?;
+        // Compatible with the legacy unix timestamps (in seconds) of the integer columns.
+        if let Ok(secs) = text.parse::<i64>() {
+            return DateTime::from_timestamp(secs, 0)
+                .map(UtcDateTime)
+                .ok_or_else(|| format!("Invalid unix timestamp {}", secs).into());
+        }
+        Ok(UtcDateTime::parse(text)?)
+    }
+}
+
+// The Postgres adapter, which stores the timestamptz.
+
+impl Type<Postgres> for UtcDateTime {
+    fn type_info() -> PgTypeInfo {
+        <DateTime<Utc> as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <DateTime<Utc> as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Postgres> for UtcDateTime {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <DateTime<Utc> as Encode<'q, Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for UtcDateTime {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(UtcDateTime(<DateTime<Utc> as Decode<'r, Postgres>>::decode(value)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_offset_converted_to_utc() {
+        let parsed = UtcDateTime::parse("2024-07-10T16:37:54.123456+08:00").unwrap();
+        let expected = Utc.with_ymd_and_hms(2024, 7, 10, 8, 37, 54).unwrap() + chrono::Duration::microseconds(123456);
+        assert_eq!(parsed.0, expected);
+        assert_eq!(parsed.to_iso8601(), "2024-07-10T08:37:54.123456Z");
+    }
+
+    #[test]
+    fn test_parse_legacy_naive_as_utc() {
+        let expected = Utc.with_ymd_and_hms(2024, 7, 10, 8, 37, 54).unwrap();
+        assert_eq!(UtcDateTime::parse("2024-07-10 08:37:54").unwrap().0, expected);
+        assert_eq!(UtcDateTime::parse("2024-07-10T08:37:54").unwrap().0, expected);
+        assert_eq!(UtcDateTime::parse("2024-07-10 16:37:54+08").unwrap().0, expected);
+        assert!(UtcDateTime::parse("not a datetime").is_err());
+    }
+
+    #[test]
+    fn test_serde_round_trip_microseconds() {
+        let now = UtcDateTime::now();
+        let json = serde_json::to_string(&now).unwrap();
+        assert!(json.ends_with("Z\""));
+        assert_eq!(serde_json::from_str::<UtcDateTime>(&json).unwrap(), now);
+    }
+
+    #[test]
+    fn test_deserialize_client_offset_regression() {
+        // The client sent the +08:00 offset timestamp should never be shifted by the local offset.
+        let parsed: UtcDateTime = serde_json::from_str("\"2026-10-16T08:00:00.000001+08:00\"").unwrap();
+        assert_eq!(parsed.to_iso8601(), "2026-10-16T00:00:00.000001Z");
+    }
+}
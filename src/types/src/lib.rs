@@ -19,15 +19,17 @@
 // This includes modifications and derived works.
 
 pub mod api_v1;
+pub mod datetime;
 pub mod modules;
 pub mod sys;
 
 use anyhow::Error;
-use chrono::{DateTime, Utc};
+use datetime::UtcDateTime;
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
-use validator::Validate;
+use std::collections::BTreeMap;
+use validator::{Validate, ValidationErrors};
 
 use botwaf_utils::snowflake::SnowflakeIdGenerator;
 // use sqlx::{ Decode, FromRow };
@@ -45,14 +47,38 @@ pub struct BaseBean {
     // also on the DB, so for simplicity, we will unifed use underscores.
     //#[serde(rename = "createBy")]
     pub create_by: Option<String>,
-    #[schema(read_only = true)]
-    pub create_time: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime, read_only = true)]
+    pub create_time: Option<UtcDateTime>,
     #[schema(read_only = true)]
     pub update_by: Option<String>,
-    #[schema(read_only = true)]
-    pub update_time: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime, read_only = true)]
+    pub update_time: Option<UtcDateTime>,
     #[serde(skip)]
     pub del_flag: Option<i32>,
+    // The optimistic locking version, the update will be rejected if it's stale.
+    #[sqlx(default)]
+    pub version: Option<i64>,
+}
+
+/// The status of the records, the disabled records are kept but excluded from the effective usage.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum RecordStatus {
+    Active = 0,
+    Disabled = 1,
+}
+
+impl RecordStatus {
+    pub fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    pub fn from_value(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(RecordStatus::Active),
+            1 => Some(RecordStatus::Disabled),
+            _ => None,
+        }
+    }
 }
 
 impl BaseBean {
@@ -65,11 +91,12 @@ impl BaseBean {
             update_by: None,
             update_time: None,
             del_flag: None,
+            version: None,
         }
     }
 
     pub fn new_with_id(id: Option<i64>) -> Self {
-        let now = Utc::now();
+        let now = UtcDateTime::now();
         Self {
             id,
             status: Some(0),
@@ -78,11 +105,12 @@ impl BaseBean {
             update_by: None,
             update_time: Some(now),
             del_flag: Some(0),
+            version: None,
         }
     }
 
     pub fn new_with_by(id: Option<i64>, create_by: Option<String>, update_by: Option<String>) -> Self {
-        let now = Utc::now();
+        let now = UtcDateTime::now();
         Self {
             id,
             status: Some(0),
@@ -91,24 +119,55 @@ impl BaseBean {
             update_by,
             update_time: Some(now),
             del_flag: Some(0),
+            version: None,
         }
     }
 
+    pub fn is_disabled(&self) -> bool {
+        self.status == Some(RecordStatus::Disabled.value())
+    }
+
     pub async fn pre_insert(&mut self, create_by: Option<String>) -> i64 {
         self.id = Some(SnowflakeIdGenerator::default_next_jssafe());
         self.create_by = create_by;
-        self.create_time = Some(Utc::now());
+        self.create_time = Some(UtcDateTime::now());
         self.del_flag = Some(0);
+        self.version = Some(0);
         self.id.unwrap()
     }
 
     pub async fn pre_update(&mut self, update_by: Option<String>) {
         self.update_by = update_by;
-        self.update_time = Some(Utc::now());
+        self.update_time = Some(UtcDateTime::now());
         self.del_flag = Some(0);
+        self.version = self.version.map(|v| v + 1);
     }
 }
 
+/// The entity persisted by the dynamic store (serde based) macros, every serialized field must be mapped into
+/// the SQL column (i.e: bool, number or string), otherwise it's rejected instead of silently dropped.
+///
+/// Notice: The arrays or nested objects fields which are intentionally not persisted (e.g: computed for the
+/// APIs only) must be explicitly opted out by the serialized name, e.g:
+///
+/// ```rust
+/// use botwaf_types::PersistableBean;
+///
+/// #[derive(serde::Serialize)]
+/// struct Report {
+///     name: Option<String>,
+///     // Computed on query, not persisted.
+///     tags: Vec<String>,
+/// }
+///
+/// impl PersistableBean for Report {
+///     const TRANSIENT_FIELDS: &'static [&'static str] = &["tags"];
+/// }
+/// ```
+pub trait PersistableBean: Serialize {
+    const TRANSIENT_FIELDS: &'static [&'static str] = &[];
+}
+
 #[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, utoipa::IntoParams)]
 pub struct PageRequest {
     #[schema(example = "1")]
@@ -123,6 +182,10 @@ pub struct PageRequest {
 }
 
 impl PageRequest {
+    /// The max per page records count, which is validated on the query extraction and clamped again
+    /// by get_limit() as the second line of defense.
+    pub const MAX_LIMIT: u32 = 1000;
+
     pub fn default() -> PageRequest {
         PageRequest {
             num: Some(1),
@@ -134,19 +197,14 @@ impl PageRequest {
     pub fn get_offset(&self) -> u32 {
         let n = self.num.unwrap_or(1);
         if n < 1 {
-            1
+            0
         } else {
-            (n - 1) * self.get_limit()
+            (n - 1).saturating_mul(self.get_limit())
         }
     }
 
     pub fn get_limit(&self) -> u32 {
-        let l = self.limit.unwrap_or(10);
-        if l < 1 {
-            1
-        } else {
-            l
-        }
+        self.limit.unwrap_or(10).clamp(1, Self::MAX_LIMIT)
     }
 }
 
@@ -170,6 +228,9 @@ impl PageResponse {
 pub struct RespBase {
     pub(crate) errcode: Option<i8>,
     pub(crate) errmsg: Option<String>,
+    // The field-level validation errors, e.g: {"limit": ["range"]}
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) errors: Option<BTreeMap<String, Vec<String>>>,
 }
 
 #[allow(unused)]
@@ -178,6 +239,7 @@ impl RespBase {
         Self {
             errcode: Some(0),
             errmsg: Some("ok".to_string()),
+            errors: None,
         }
     }
 
@@ -185,6 +247,7 @@ impl RespBase {
         Self {
             errcode: Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i8),
             errmsg: Some(e.to_string()),
+            errors: None,
         }
     }
 
@@ -193,6 +256,27 @@ impl RespBase {
         Self {
             errcode: Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i8),
             errmsg: Some(errmsg.to_owned()),
+            errors: None,
+        }
+    }
+
+    /// The validation failure with the field-level errors, each is the message if present, otherwise the code.
+    pub fn invalid(e: &ValidationErrors) -> Self {
+        let errors = e
+            .field_errors()
+            .into_iter()
+            .map(|(field, errs)| {
+                let errs = errs
+                    .iter()
+                    .map(|err| err.message.as_ref().unwrap_or(&err.code).to_string())
+                    .collect();
+                (field.to_string(), errs)
+            })
+            .collect();
+        Self {
+            errcode: Some(StatusCode::UNPROCESSABLE_ENTITY.as_u16() as i8),
+            errmsg: Some(String::from("Validation error")),
+            errors: Some(errors),
         }
     }
 
@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::forwarder::HttpIncomingRequest;
+use axum::{body::Bytes, http::Version};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BotwafAccessEvent {
+    // Request information.
+    pub method: String,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub headers: Option<HashMap<String, Option<String>>>,
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Option<String>,
+    // Additional request information.
+    pub req_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub start_time: u64,
+    // Response information.
+    pub resp_status_code: Option<i32>,
+    pub resp_headers: Option<HashMap<String, Option<String>>>,
+    pub resp_body: Option<String>,
+    // Additional response information.
+    pub duration: Option<u64>,
+    // The synthetic probe events should be excluded from the statistics.
+    #[serde(default)]
+    pub synthetic: bool,
+    // The matched rule id of the blocked request.
+    #[serde(default)]
+    pub rule_id: Option<String>,
+    // Whether any recorded header value was truncated, see: BotwafAccessEvent::truncate_headers
+    #[serde(default)]
+    pub headers_truncated: bool,
+    // The fired patterns of the rewritten response, see: ResponseRewriteSummary
+    #[serde(default)]
+    pub resp_rewrite: Option<ResponseRewriteSummary>,
+}
+
+/// The outcome of the response rewriting, which is summarized once the response body is streamed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseRewriteSummary {
+    // The fired patterns with the replacements, in the order of first fired.
+    pub hits: Vec<ResponseRewriteHit>,
+    // Whether the rewriting was abandoned after exceeding the time budget, the rest is passed through as is.
+    #[serde(default)]
+    pub abandoned: bool,
+    // The pattern of the BLOCK action which aborted the response.
+    #[serde(default)]
+    pub blocked_by: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResponseRewriteHit {
+    pub pattern: String,
+    pub replacements: usize,
+}
+
+impl ResponseRewriteSummary {
+    pub fn record_hit(&mut self, pattern: &str) {
+        match self.hits.iter_mut().find(|h| h.pattern == pattern) {
+            Some(hit) => hit.replacements += 1,
+            None => self.hits.push(ResponseRewriteHit {
+                pattern: pattern.to_owned(),
+                replacements: 1,
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty() && !self.abandoned && self.blocked_by.is_none()
+    }
+}
+
+impl BotwafAccessEvent {
+    /// Build the access event from the raw incoming request, the sensitive values are NOT scrubbed yet.
+    pub fn from_incoming(incoming: &HttpIncomingRequest, start_time: u64) -> Self {
+        BotwafAccessEvent {
+            method: incoming.method.to_owned(),
+            scheme: incoming.scheme.to_owned(),
+            host: incoming.host.to_owned(),
+            port: incoming.port,
+            headers: Some(incoming.headers.to_owned()),
+            path: incoming.path.to_owned(),
+            query: incoming.query.to_owned(),
+            body: incoming.body.as_ref().map(|b| String::from_utf8_lossy(b).to_string()),
+            req_id: None,
+            client_ip: incoming.client_ip.to_owned(),
+            start_time,
+            resp_status_code: None,
+            resp_headers: None,
+            resp_body: None,
+            duration: None,
+            synthetic: incoming.synthetic,
+            rule_id: None,
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    /// Truncate the recorded header values to the max bytes (at the char boundary) and flag the event, so that
+    /// the huge headers (e.g: the cookies of SSO) never bloat the stored events, 0 is unlimited.
+    pub fn truncate_headers(&mut self, max_bytes: usize) {
+        if max_bytes == 0 {
+            return;
+        }
+        for value in self.headers.iter_mut().flat_map(|h| h.values_mut()).flatten() {
+            if value.len() > max_bytes {
+                let mut end = max_bytes;
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                value.truncate(end);
+                self.headers_truncated = true;
+            }
+        }
+    }
+
+    /// Rebuild the incoming request from the recorded event for the re-evaluation, notice that the
+    /// protected (e.g: masked or dropped) values are evaluated as recorded.
+    pub fn to_incoming(&self) -> HttpIncomingRequest {
+        HttpIncomingRequest {
+            method: self.method.to_owned(),
+            scheme: self.scheme.to_owned(),
+            host: self.host.to_owned(),
+            port: self.port,
+            headers: self.headers.to_owned().unwrap_or_default(),
+            path: self.path.to_owned(),
+            query: self.query.to_owned(),
+            body: self.body.as_ref().map(|b| Bytes::from(b.to_owned())),
+            client_ip: self.client_ip.to_owned(),
+            peer_ip: None,
+            synthetic: self.synthetic,
+            version: Version::HTTP_11,
+        }
+    }
+}
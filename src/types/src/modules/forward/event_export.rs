@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use super::event_stream::AccessDecision;
+use serde::{Deserialize, Serialize};
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, utoipa::ToSchema)]
+pub enum EventExportFormat {
+    // The JSON lines, i.e: one access event JSON per line.
+    #[default]
+    #[serde(alias = "jsonl")]
+    JSONL,
+    // The CSV with the header row of the common (flat) fields.
+    #[serde(alias = "csv")]
+    CSV,
+    // The Parquet of the same (flat) columns as the CSV, each exported chunk is written as a row group.
+    #[serde(alias = "parquet")]
+    PARQUET,
+}
+
+/// The time range (the event start time in epoch milliseconds) and the optional filters of the export, all the
+/// specified filters must be matched.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventExportRequest {
+    // The inclusive start of the range.
+    pub from: u64,
+    // The exclusive end of the range.
+    pub to: u64,
+    pub decision: Option<AccessDecision>,
+    #[serde(rename = "path-prefix")]
+    pub path_prefix: Option<String>,
+    #[serde(rename = "client-ip")]
+    pub client_ip: Option<String>,
+    // The matched rule id, e.g: 942100, signature:expired
+    pub rule: Option<String>,
+    pub format: Option<EventExportFormat>,
+    // The max exported rows, which must not exceed the configured max rows.
+    pub limit: Option<usize>,
+}
@@ -23,6 +23,7 @@ use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 use axum::{
     body::{to_bytes, Body, Bytes},
     extract::Request,
+    http::{header, Version},
 };
 
 use serde::{Deserialize, Serialize};
@@ -45,18 +46,49 @@ pub struct HttpIncomingRequest {
     pub query: Option<String>,
     pub body: Option<Bytes>,
     pub client_ip: Option<String>,
+    // The immediate connection peer IP, i.e: the remote address, which is the last proxy if behind the proxies.
+    pub peer_ip: Option<String>,
+    // Whether is the synthetic probe request, which should be excluded from the access statistics.
+    pub synthetic: bool,
+    // The client-facing protocol, e.g: HTTP/1.1, HTTP/2.0, HTTP/3.0
+    pub version: Version,
+}
+
+/// The request extension marking the verified synthetic probe request, which is only inserted in-process
+/// (i.e: never derived from the client headers) by the prober or after the signed probe header is verified.
+#[derive(Debug, Clone, Copy)]
+pub struct SyntheticProbe;
+
+impl HttpIncomingRequest {
+    // The signed probe header of the end-to-end synthetic probes, which is stripped from all requests.
+    pub const SYNTHETIC_PROBE_HEADER: &'static str = "X-Botwaf-Probe";
+
+    /// The protocol label of the request metrics, e.g: http1.1, h2, h3
+    pub fn protocol(&self) -> &'static str {
+        match self.version {
+            Version::HTTP_09 => "http0.9",
+            Version::HTTP_10 => "http1.0",
+            Version::HTTP_2 => "h2",
+            Version::HTTP_3 => "h3",
+            _ => "http1.1",
+        }
+    }
 }
 
 impl HttpIncomingRequest {
-    pub async fn new(req: Request<Body>, max_body_bytes: usize) -> Arc<Self> {
+    /// Wrap the incoming request, the body is limited by per-request (per-stream for h2/h3) accounting,
+    /// and returns an error if exceeded the max_body_bytes.
+    pub async fn new(req: Request<Body>, max_body_bytes: usize) -> Result<Arc<Self>, anyhow::Error> {
         let (parts, body) = req.into_parts();
         let bytes = to_bytes(body, max_body_bytes)
             .await
-            .expect("Failed to collect request body");
+            .map_err(|e| anyhow::anyhow!("Failed to collect request body, cause: {}", e))?;
         let (req, body) = (Request::from_parts(parts, Body::from(bytes.clone())), bytes);
         let uri = req.uri();
 
         // Extract request headers.
+        // Notice: The h2/h3 pseudo-headers (:method, :path, :authority, :scheme) are never present in the
+        // header map, they are already mapped into the method and uri.
         let headers = req
             .headers()
             .iter()
@@ -68,17 +100,32 @@ impl HttpIncomingRequest {
             .collect();
 
         // Extract axum request client IP by using the X-Forwarded-For or X-Real-IP or the request remote address.
+        // Notice: The IPv4-mapped peer of the dual-stack listener is canonicalized to the IPv4.
+        let peer_ip = req
+            .extensions()
+            .get::<SocketAddr>()
+            .map(|addr| addr.ip().to_canonical().to_string());
         let client_ip = req
             .headers()
             .get("X-Forwarded-For")
             .or(req.headers().get("X-Real-IP"))
             .map(|addr| addr.to_str().map(|s| s.to_string()).unwrap_or_default())
-            .or_else(|| req.extensions().get::<SocketAddr>().map(|addr| addr.ip().to_string()));
+            .or_else(|| peer_ip.to_owned());
+
+        let synthetic = req.extensions().get::<SyntheticProbe>().is_some();
+
+        // The HTTP/1.1 origin-form uri has no authority, fallback to the Host header.
+        let host = uri.host().map(|s| s.to_string()).or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.split(':').next().unwrap_or_default().to_string())
+        });
 
-        Arc::new(HttpIncomingRequest {
+        Ok(Arc::new(HttpIncomingRequest {
             method: req.method().to_string(),
             scheme: uri.scheme().map(|s| s.to_string()),
-            host: uri.host().map(|s| s.to_string()),
+            host,
             port: uri.port_u16(),
             headers,
             path: uri.path().to_string(),
@@ -86,6 +133,9 @@ impl HttpIncomingRequest {
             //body: Some(String::from_utf8_lossy(&body).to_string()),
             body: Some(body),
             client_ip,
-        })
+            peer_ip,
+            synthetic,
+            version: req.version(),
+        }))
     }
 }
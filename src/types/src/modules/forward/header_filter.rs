@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Clone, Debug, utoipa::IntoParams)]
+pub struct StrippedHeadersQueryRequest {
+    /// The upstream url prefix, defaults to all the upstreams.
+    pub upstream: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct StrippedHeaderEntry {
+    pub header: String,
+    /// The estimated count, which is never less than the true count.
+    pub count: u64,
+    #[serde(rename = "lastStrippedAt")]
+    pub last_stripped_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct StrippedHeadersReport {
+    pub upstream: String,
+    /// The stripped headers of the upstream, ordered by the count descending.
+    pub headers: Vec<StrippedHeaderEntry>,
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Clone, Debug, Validate, utoipa::ToSchema)]
+pub struct IPFilterBlockRequest {
+    /// The IP address or CIDR, e.g: 1.1.1.1, 10.0.0.0/24, 2001:db8::/64
+    #[validate(length(min = 1, max = 64))]
+    pub ip: String,
+    /// The optional blocking TTL in seconds, blocked forever if not set.
+    pub ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug, Validate, utoipa::ToSchema)]
+pub struct IPFilterUnblockRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub ip: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct IPFilterEntry {
+    pub ip: String,
+    #[serde(rename = "expireAt")]
+    pub expire_at: Option<i64>,
+    #[serde(rename = "createBy")]
+    pub create_by: Option<String>,
+    #[serde(rename = "createAt")]
+    pub create_at: i64,
+}
+
+impl IPFilterEntry {
+    pub fn new(ip: String, ttl: Option<u64>, create_by: Option<String>) -> Self {
+        let now = Utc::now().timestamp_millis();
+        Self {
+            ip,
+            expire_at: ttl.map(|t| now + (t as i64) * 1000),
+            create_by,
+            create_at: now,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire_at.map(|t| t <= Utc::now().timestamp_millis()).unwrap_or(false)
+    }
+}
@@ -18,4 +18,10 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod access_event;
+pub mod event_export;
+pub mod event_stream;
 pub mod forwarder;
+pub mod header_filter;
+pub mod ipfilter;
+pub mod topk;
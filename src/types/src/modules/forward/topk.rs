@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, utoipa::ToSchema)]
+pub enum TopKDimension {
+    #[serde(rename = "path")]
+    PATH,
+    #[serde(rename = "ip")]
+    IP,
+    #[serde(rename = "rule")]
+    RULE,
+    #[serde(rename = "user_agent")]
+    USER_AGENT,
+}
+
+impl TopKDimension {
+    pub const ALL: [TopKDimension; 4] = [Self::PATH, Self::IP, Self::RULE, Self::USER_AGENT];
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, utoipa::ToSchema)]
+pub enum TopKWindow {
+    #[serde(rename = "5m")]
+    FIVE_MINUTES,
+    #[serde(rename = "1h")]
+    ONE_HOUR,
+    #[serde(rename = "24h")]
+    ONE_DAY,
+}
+
+impl TopKWindow {
+    pub const ALL: [TopKWindow; 3] = [Self::FIVE_MINUTES, Self::ONE_HOUR, Self::ONE_DAY];
+
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Self::FIVE_MINUTES => 5 * 60,
+            Self::ONE_HOUR => 60 * 60,
+            Self::ONE_DAY => 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, utoipa::IntoParams)]
+pub struct TopKQueryRequest {
+    pub dimension: TopKDimension,
+    pub window: TopKWindow,
+    /// The max number of the entries, defaults to 10.
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct TopKEntry {
+    pub key: String,
+    /// The estimated count, which is never less than the true count.
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct TopKResponse {
+    pub dimension: TopKDimension,
+    pub window: TopKWindow,
+    /// The total events of the window.
+    pub total: u64,
+    /// The upper bound of the over-estimation of each count with the probability of 'confidence'.
+    #[serde(rename = "errorBound")]
+    pub error_bound: u64,
+    pub confidence: f64,
+    pub entries: Vec<TopKEntry>,
+}
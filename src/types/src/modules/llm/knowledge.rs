@@ -59,6 +59,17 @@ pub struct KnowledgeUploadInfo {
     pub description: Option<String>,
     pub create_at: u64,
     pub create_by: Option<String>,
+    /// The sanitized original filename of the uploaded file, which is only for display.
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// The collision-free name of the stored file, i.e: the ULID with the original extension.
+    #[serde(default)]
+    pub storage_name: Option<String>,
+    /// The content type sniffed from the magic bytes of the uploaded file.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub size: Option<u64>,
 }
 
 impl KnowledgeUploadInfo {
@@ -80,6 +91,71 @@ impl KnowledgeUploadInfo {
             description: None,
             create_at: Utc::now().timestamp_millis() as u64,
             create_by,
+            file_name: None,
+            storage_name: None,
+            content_type: None,
+            size: None,
         })
     }
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct KnowledgeNamespaceStats {
+    pub namespace: String,
+    pub documents: i64,
+    #[serde(rename = "storageBytes")]
+    pub storage_bytes: i64,
+    #[serde(rename = "embeddingModel")]
+    pub embedding_model: Option<String>,
+    #[serde(rename = "lastWriteTime")]
+    pub last_write_time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct VectorCleanupRequest {
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, utoipa::ToSchema)]
+pub struct VectorCleanupResult {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    /// The embeddings of which collection has been dropped.
+    pub orphaned: u64,
+    /// The embeddings of which knowledge record is FAILED or deleted.
+    pub stale: u64,
+    pub removed: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum VectorIndexType {
+    HNSW,
+    IVFFLAT,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VectorReindexRequest {
+    pub index_type: VectorIndexType,
+    /// The operator class, e.g: vector_cosine_ops, vector_l2_ops, vector_ip_ops
+    pub ops: String,
+    /// The HNSW max number of connections per layer.
+    pub m: u32,
+    /// The HNSW size of the dynamic candidate list for constructing the graph.
+    pub ef_construction: u32,
+    /// The IVFFlat number of inverted lists.
+    pub lists: u32,
+    /// The number of sample queries for the latency report.
+    pub samples: u32,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VectorReindexReport {
+    pub index_name: String,
+    pub statement: String,
+    pub dry_run: bool,
+    pub samples: u32,
+    pub before_avg_millis: f64,
+    pub after_avg_millis: Option<f64>,
+}
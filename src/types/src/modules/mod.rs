@@ -20,3 +20,5 @@
 
 pub mod forward;
 pub mod llm;
+pub mod modsec;
+pub mod scheduler;
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PageResponse, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+/// The logical name prefix of the data files referenced by the rules, e.g: @pmFromFile botwaf-data:bad-user-agents.txt
+pub const DATA_FILE_REF_PREFIX: &'static str = "botwaf-data:";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum DataFileFormat {
+    // The phrases per line, used by @pmFromFile (@pmf).
+    PATTERNS,
+    // The IP addresses or CIDR ranges per line, used by @ipMatchFromFile (@ipMatchF).
+    IPS,
+}
+
+impl DataFileFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "PATTERNS" => Some(DataFileFormat::PATTERNS),
+            "IPS" => Some(DataFileFormat::IPS),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct DataFile {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    // The unique logical name, e.g: bad-user-agents.txt
+    pub name: Option<String>,
+    pub format: Option<DataFileFormat>,
+    pub content: Option<String>,
+    // The SHA-256 hex of the content, used to detect the changes for re-materialization.
+    #[schema(read_only = true)]
+    pub checksum: Option<String>,
+    #[schema(read_only = true)]
+    pub size: Option<i64>,
+}
+
+impl Default for DataFile {
+    fn default() -> Self {
+        DataFile {
+            base: BaseBean::new_empty(),
+            name: None,
+            format: None,
+            content: None,
+            checksum: None,
+            size: None,
+        }
+    }
+}
+
+impl PersistableBean for DataFile {}
+
+/// SqliteRow impl for DataFile.
+impl<'r> FromRow<'r, SqliteRow> for DataFile {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(DataFile {
+            base: BaseBean::from_row(row)?,
+            name: row.try_get("name")?,
+            format: row
+                .try_get::<Option<String>, _>("format")?
+                .and_then(|f| DataFileFormat::parse(&f)),
+            content: row.try_get("content")?,
+            checksum: row.try_get("checksum")?,
+            size: row.try_get("size")?,
+        })
+    }
+}
+
+/// Postgres Row impl for DataFile.
+impl<'r> FromRow<'r, PgRow> for DataFile {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(DataFile {
+            base: BaseBean::from_row(row)?,
+            name: row.try_get("name")?,
+            format: row
+                .try_get::<Option<String>, _>("format")?
+                .and_then(|f| DataFileFormat::parse(&f)),
+            content: row.try_get("content")?,
+            checksum: row.try_get("checksum")?,
+            size: row.try_get("size")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryDataFileRequest {
+    #[validate(length(min = 1, max = 128))]
+    pub name: Option<String>,
+}
+
+impl QueryDataFileRequest {
+    pub fn to_data_file(&self) -> DataFile {
+        DataFile {
+            name: self.name.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct QueryDataFileResponse {
+    pub page: Option<PageResponse>,
+    pub data: Option<Vec<DataFile>>,
+}
+
+impl QueryDataFileResponse {
+    pub fn new(page: PageResponse, data: Vec<DataFile>) -> Self {
+        QueryDataFileResponse {
+            page: Some(page),
+            data: Some(data),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct SaveDataFileRequest {
+    pub id: Option<i64>,
+    // The current version for optimistic locking, the stale update will be rejected with 409 conflict.
+    pub version: Option<i64>,
+    #[validate(length(min = 1, max = 128))]
+    pub name: String,
+    pub format: DataFileFormat,
+    pub content: String,
+}
+
+impl SaveDataFileRequest {
+    pub fn to_data_file(&self) -> DataFile {
+        let mut base = BaseBean::new_with_id(self.id);
+        base.version = self.version;
+        DataFile {
+            base,
+            name: Some(self.name.clone()),
+            format: Some(self.format),
+            content: Some(self.content.clone()),
+            checksum: None,
+            size: Some(self.content.len() as i64),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct SaveDataFileResponse {
+    pub id: i64,
+}
+
+impl SaveDataFileResponse {
+    pub fn new(id: i64) -> Self {
+        SaveDataFileResponse { id }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct DeleteDataFileRequest {
+    pub id: i64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct DeleteDataFileResponse {
+    pub count: u64,
+}
+
+impl DeleteDataFileResponse {
+    pub fn new(count: u64) -> Self {
+        DeleteDataFileResponse { count }
+    }
+}
@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+
+/// The effective capabilities of the ModSecurity engine build, e.g: the engine built without libinjection fails
+/// to compile the rules using '@detectXSS'.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecEngineCapabilities {
+    // The library version of the engine, e.g: ModSecurity v3.0.12 (Linux)
+    pub version: Option<String>,
+    pub capabilities: Vec<ModSecEngineCapability>,
+    // The time of the capabilities probed.
+    #[serde(rename = "probedAt")]
+    pub probed_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecEngineCapability {
+    // The name of the capability, e.g: libinjection, lua, curl
+    pub name: String,
+    pub supported: bool,
+    // The operators (or directives) which require the capability, e.g: @detectXSS, @detectSQLi
+    pub operators: Vec<String>,
+    // The compile error of the probe rule, if not supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+}
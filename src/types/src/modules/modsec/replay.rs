@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{modules::forward::event_stream::AccessDecision, BaseBean, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+/// The re-evaluated decision of the historical access event against the current rules, see: botwaf replay-events
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ReplayResult {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    // The replay job, which is resumed from the last replayed event id of the job.
+    pub job_id: Option<String>,
+    // The (id ordered) sequence of the event in the access events store.
+    pub event_id: Option<i64>,
+    pub old_decision: Option<AccessDecision>,
+    // The matched rule id of the original decision if blocked.
+    pub old_rule_id: Option<String>,
+    pub new_decision: Option<AccessDecision>,
+    // The matched rule ids of the new decision which differ from the original decision, separated by comma.
+    pub new_rule_ids: Option<String>,
+}
+
+impl Default for ReplayResult {
+    fn default() -> Self {
+        ReplayResult {
+            base: BaseBean::new_empty(),
+            job_id: None,
+            event_id: None,
+            old_decision: None,
+            old_rule_id: None,
+            new_decision: None,
+            new_rule_ids: None,
+        }
+    }
+}
+
+impl PersistableBean for ReplayResult {}
+
+impl ReplayResult {
+    pub fn parse_decision(value: &str) -> Option<AccessDecision> {
+        match value {
+            "BLOCK" => Some(AccessDecision::BLOCK),
+            "PASS" => Some(AccessDecision::PASS),
+            _ => None,
+        }
+    }
+}
+
+/// SqliteRow impl for ReplayResult.
+impl<'r> FromRow<'r, SqliteRow> for ReplayResult {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(ReplayResult {
+            base: BaseBean::from_row(row)?,
+            job_id: row.try_get("job_id")?,
+            event_id: row.try_get("event_id")?,
+            old_decision: row
+                .try_get::<Option<String>, _>("old_decision")?
+                .and_then(|s| Self::parse_decision(&s)),
+            old_rule_id: row.try_get("old_rule_id")?,
+            new_decision: row
+                .try_get::<Option<String>, _>("new_decision")?
+                .and_then(|s| Self::parse_decision(&s)),
+            new_rule_ids: row.try_get("new_rule_ids")?,
+        })
+    }
+}
+
+/// Postgres Row impl for ReplayResult.
+impl<'r> FromRow<'r, PgRow> for ReplayResult {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(ReplayResult {
+            base: BaseBean::from_row(row)?,
+            job_id: row.try_get("job_id")?,
+            event_id: row.try_get("event_id")?,
+            old_decision: row
+                .try_get::<Option<String>, _>("old_decision")?
+                .and_then(|s| Self::parse_decision(&s)),
+            old_rule_id: row.try_get("old_rule_id")?,
+            new_decision: row
+                .try_get::<Option<String>, _>("new_decision")?
+                .and_then(|s| Self::parse_decision(&s)),
+            new_rule_ids: row.try_get("new_rule_ids")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Validate, utoipa::ToSchema)]
+pub struct ReplayEventsRequest {
+    // The job to be resumed from the checkpoint, generated if not specified.
+    #[validate(length(min = 1, max = 64))]
+    pub job_id: Option<String>,
+    // The max events to be replayed of this run, unlimited if not specified.
+    #[validate(range(min = 1))]
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, utoipa::ToSchema)]
+pub struct ReplayEventsProgress {
+    pub job_id: String,
+    // The replayed events of this run.
+    pub replayed: u64,
+    // The checkpoint of the job, i.e: the last replayed event id.
+    pub last_event_id: Option<i64>,
+}
+
+/// The would-block delta of the rule, i.e: newly_blocked - no_longer_blocked.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, utoipa::ToSchema)]
+pub struct ReplayRuleDelta {
+    pub rule_id: String,
+    // The events passed originally but blocked by the rule now.
+    pub newly_blocked: u64,
+    // The events blocked by the rule originally but passed now.
+    pub no_longer_blocked: u64,
+    pub delta: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, utoipa::ToSchema)]
+pub struct ReplaySummaryResponse {
+    pub job_id: String,
+    pub total: u64,
+    pub old_blocked: u64,
+    pub new_blocked: u64,
+    // Sorted by the absolute delta descending.
+    pub rules: Vec<ReplayRuleDelta>,
+}
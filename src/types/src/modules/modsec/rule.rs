@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum ModSecRuleSource {
+    // The rules from config 'services.static-rules'.
+    STATIC,
+    // The rules managed by the APIs with the version history, the head version is effective.
+    MANAGED,
+    // The emergency rules compiled into the binary, loaded only when no other rules are effective.
+    EMBEDDED,
+}
+
+/// The lifecycle state of the rule, the newly generated rules are promoted as CANDIDATE -> SHADOW -> ACTIVE.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, utoipa::ToSchema)]
+pub enum ModSecRuleState {
+    // The rule is not evaluated, waiting to be reviewed.
+    CANDIDATE,
+    // The rule is evaluated and logged the would-block requests, but never blocks.
+    SHADOW,
+    // The rule is effective to block the requests.
+    #[default]
+    ACTIVE,
+}
+
+/// The observation statistics of the SHADOW rule, used to determine the promotion.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecShadowStats {
+    // The would-block requests.
+    pub hits: u64,
+    // The would-block requests reported as false positives.
+    #[serde(rename = "falsePositives")]
+    pub false_positives: u64,
+    pub precision: f64,
+    // The time of entered the SHADOW state.
+    pub since: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecRuleInfo {
+    pub name: String,
+    pub kind: String,
+    pub severity: String,
+    pub desc: String,
+    pub value: String,
+    pub source: ModSecRuleSource,
+    #[serde(rename = "readOnly")]
+    pub read_only: bool,
+    #[serde(default)]
+    pub state: ModSecRuleState,
+    #[serde(rename = "shadowStats", skip_serializing_if = "Option::is_none")]
+    pub shadow_stats: Option<ModSecShadowStats>,
+    // The LLM provider which generated the rule as the provenance, none for the rules not generated by LLM.
+    #[serde(rename = "generatedBy", default, skip_serializing_if = "Option::is_none")]
+    pub generated_by: Option<String>,
+    // The effective (head) version id of the MANAGED rule, see: rule_version::ModSecRuleVersion
+    #[serde(rename = "versionId", default, skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<i64>,
+    // The changeset which appended the head version of the MANAGED rule, see: ModSecRuleChangeset
+    #[serde(rename = "changesetId", default, skip_serializing_if = "Option::is_none")]
+    pub changeset_id: Option<i64>,
+    // The operators used by the rule but unsupported by the engine build, see: ModSecEngineCapabilities
+    #[serde(rename = "unsupportedOperators", default, skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_operators: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryRulesRequest {
+    // Only the rules whose head versions were appended by the changeset.
+    pub changeset: Option<i64>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct PromoteRuleRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct PromoteRuleResponse {
+    pub name: String,
+    pub state: ModSecRuleState,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct ReportFalsePositiveRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+}
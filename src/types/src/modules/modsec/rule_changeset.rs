@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use std::collections::BTreeMap;
+use validator::Validate;
+
+/// The review state of the changeset, which is drafted by the operator, and approved and applied by the admin.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, utoipa::ToSchema)]
+pub enum ModSecRuleChangesetState {
+    // Collecting the changes, which are not effective.
+    #[default]
+    DRAFT,
+    // The changes were validated and compiled as a whole, waiting to be applied.
+    APPROVED,
+    // The changes are effective, which could be rolled back as a unit.
+    APPLIED,
+    // The changes were reverted to the heads before applied.
+    ROLLED_BACK,
+}
+
+impl ModSecRuleChangesetState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "DRAFT" => Some(ModSecRuleChangesetState::DRAFT),
+            "APPROVED" => Some(ModSecRuleChangesetState::APPROVED),
+            "APPLIED" => Some(ModSecRuleChangesetState::APPLIED),
+            "ROLLED_BACK" => Some(ModSecRuleChangesetState::ROLLED_BACK),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum ModSecRuleChangeOp {
+    // Create or update the managed rule.
+    SAVE,
+    // Delete the managed rule, i.e: appends the disabled head version.
+    DELETE,
+}
+
+/// The change of the managed rule in the changeset, the content and severity are required on SAVE.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct ModSecRuleChange {
+    pub op: ModSecRuleChangeOp,
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    #[validate(length(min = 1))]
+    pub content: Option<String>,
+    #[validate(length(min = 1, max = 32))]
+    pub severity: Option<String>,
+    #[validate(length(max = 512))]
+    pub desc: Option<String>,
+}
+
+/// The multi rules changes which are validated, applied and rolled back as a unit, see: ModSecRuleChangesetState
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecRuleChangeset {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub state: Option<ModSecRuleChangesetState>,
+    // The JSON array of the changes, see: ModSecRuleChange
+    pub changes: Option<String>,
+    // The JSON object of the head version numbers before applied (null if the rule was absent), for the rollback.
+    pub baseline: Option<String>,
+    // The hash of the compiled managed rules set after applied, see: rule_loader::compiled_hash()
+    pub compiled_hash: Option<String>,
+    pub approved_by: Option<String>,
+    pub applied_by: Option<String>,
+}
+
+impl Default for ModSecRuleChangeset {
+    fn default() -> Self {
+        ModSecRuleChangeset {
+            base: BaseBean::new_empty(),
+            name: None,
+            description: None,
+            state: None,
+            changes: None,
+            baseline: None,
+            compiled_hash: None,
+            approved_by: None,
+            applied_by: None,
+        }
+    }
+}
+
+impl PersistableBean for ModSecRuleChangeset {}
+
+impl ModSecRuleChangeset {
+    pub fn parse_changes(&self) -> Result<Vec<ModSecRuleChange>, serde_json::Error> {
+        match self.changes.as_deref() {
+            Some(changes) => serde_json::from_str(changes),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn parse_baseline(&self) -> Result<BTreeMap<String, Option<i64>>, serde_json::Error> {
+        match self.baseline.as_deref() {
+            Some(baseline) => serde_json::from_str(baseline),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+}
+
+/// SqliteRow impl for ModSecRuleChangeset.
+impl<'r> FromRow<'r, SqliteRow> for ModSecRuleChangeset {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(ModSecRuleChangeset {
+            base: BaseBean::from_row(row)?,
+            name: row.try_get("name")?,
+            description: row.try_get("description")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| ModSecRuleChangesetState::parse(&s)),
+            changes: row.try_get("changes")?,
+            baseline: row.try_get("baseline")?,
+            compiled_hash: row.try_get("compiled_hash")?,
+            approved_by: row.try_get("approved_by")?,
+            applied_by: row.try_get("applied_by")?,
+        })
+    }
+}
+
+/// Postgres Row impl for ModSecRuleChangeset.
+impl<'r> FromRow<'r, PgRow> for ModSecRuleChangeset {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(ModSecRuleChangeset {
+            base: BaseBean::from_row(row)?,
+            name: row.try_get("name")?,
+            description: row.try_get("description")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| ModSecRuleChangesetState::parse(&s)),
+            changes: row.try_get("changes")?,
+            baseline: row.try_get("baseline")?,
+            compiled_hash: row.try_get("compiled_hash")?,
+            approved_by: row.try_get("approved_by")?,
+            applied_by: row.try_get("applied_by")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct CreateRuleChangesetRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    #[validate(length(max = 512))]
+    pub desc: Option<String>,
+    // The initial changes of the draft, more changes could be added later.
+    #[validate(nested)]
+    #[serde(default)]
+    pub changes: Vec<ModSecRuleChange>,
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use validator::Validate;
+
+/// The decision of the sample request evaluated against the tested rule.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleTestDecision {
+    BLOCK,
+    ALLOW,
+}
+
+/// The sample request replayed against the tested rule, with the expected decision.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct RuleTestSample {
+    #[serde(default = "RuleTestSample::default_method")]
+    #[validate(length(min = 1, max = 16))]
+    pub method: String,
+    // The path with the optional query string, e.g: /orders?id=1
+    #[validate(length(min = 1, max = 8192))]
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    pub expected: RuleTestDecision,
+}
+
+impl RuleTestSample {
+    fn default_method() -> String {
+        String::from("GET")
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct TestRuleRequest {
+    // The rule is compiled in isolation, i.e: without the effective rules and the engine config.
+    #[validate(length(min = 1))]
+    pub rule_text: String,
+    #[validate(length(min = 1, max = 100), nested)]
+    pub samples: Vec<RuleTestSample>,
+}
+
+/// The actual decision of the sample, in the order of the request samples.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct RuleTestResult {
+    pub method: String,
+    pub path: String,
+    pub expected: RuleTestDecision,
+    pub actual: RuleTestDecision,
+    // The id of the matched rule if blocked.
+    pub rule_id: Option<String>,
+    pub passed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct TestRuleResponse {
+    // Whether all the samples got the expected decisions.
+    pub passed: bool,
+    pub results: Vec<RuleTestResult>,
+}
@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+/// The immutable version of the managed rule, every create/update/rollback appends a new version (i.e: the head),
+/// and the author is the creator of the version.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecRuleVersion {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    pub rule_name: Option<String>,
+    // The sequence of the version per rule, starting from 1.
+    pub version_no: Option<i64>,
+    // The raw SecLang content of the rule.
+    pub content: Option<String>,
+    pub severity: Option<String>,
+    pub description: Option<String>,
+    // The change comment of the version, required on the update.
+    pub comment: Option<String>,
+    // The changeset which appended the version, none if saved individually, see: ModSecRuleChangeset
+    pub changeset_id: Option<i64>,
+}
+
+impl Default for ModSecRuleVersion {
+    fn default() -> Self {
+        ModSecRuleVersion {
+            base: BaseBean::new_empty(),
+            rule_name: None,
+            version_no: None,
+            content: None,
+            severity: None,
+            description: None,
+            comment: None,
+            changeset_id: None,
+        }
+    }
+}
+
+impl PersistableBean for ModSecRuleVersion {}
+
+/// SqliteRow impl for ModSecRuleVersion.
+impl<'r> FromRow<'r, SqliteRow> for ModSecRuleVersion {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(ModSecRuleVersion {
+            base: BaseBean::from_row(row)?,
+            rule_name: row.try_get("rule_name")?,
+            version_no: row.try_get("version_no")?,
+            content: row.try_get("content")?,
+            severity: row.try_get("severity")?,
+            description: row.try_get("description")?,
+            comment: row.try_get("comment")?,
+            changeset_id: row.try_get("changeset_id")?,
+        })
+    }
+}
+
+/// Postgres Row impl for ModSecRuleVersion.
+impl<'r> FromRow<'r, PgRow> for ModSecRuleVersion {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(ModSecRuleVersion {
+            base: BaseBean::from_row(row)?,
+            rule_name: row.try_get("rule_name")?,
+            version_no: row.try_get("version_no")?,
+            content: row.try_get("content")?,
+            severity: row.try_get("severity")?,
+            description: row.try_get("description")?,
+            comment: row.try_get("comment")?,
+            changeset_id: row.try_get("changeset_id")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct SaveRuleVersionRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    #[validate(length(min = 1))]
+    pub content: String,
+    #[validate(length(min = 1, max = 32))]
+    pub severity: String,
+    #[validate(length(max = 512))]
+    pub desc: Option<String>,
+    // The change comment, required if the rule already exists.
+    #[validate(length(min = 1, max = 512))]
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RollbackRuleRequest {
+    // The version number to be rolled back to.
+    #[validate(range(min = 1))]
+    pub version: i64,
+    #[validate(length(min = 1, max = 512))]
+    pub comment: Option<String>,
+}
+
+/// The version of the rule with the unified diff against the previous version (empty for the first version).
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ModSecRuleVersionDiff {
+    #[serde(flatten)]
+    pub version: ModSecRuleVersion,
+    pub diff: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct QueryRuleVersionResponse {
+    pub name: String,
+    // The version number of the head, i.e: the effective content.
+    pub head: Option<i64>,
+    // The versions in the descending order of the version number.
+    pub versions: Vec<ModSecRuleVersionDiff>,
+}
@@ -41,21 +41,110 @@ pub struct PasswordLoginRequest {
     pub password: String,
     #[serde(rename = "fpToken")]
     pub fingerprint_token: String,
+    // The token of the solved challenge widget, which is required under attack, see: LoginChallengeResponse
+    #[serde(rename = "challengeToken", default)]
+    pub challenge_token: Option<String>,
     //pub seccode: Option<String>, // TODO: SMS/Email security code.
 }
 
-// ----- OIDC login types. ------
+/// The password login is rejected until the client shows the challenge widget of the provider, and retries with
+/// the solved challenge token.
+#[derive(Serialize, Clone, Debug, utoipa::ToSchema)]
+pub struct LoginChallengeResponse {
+    #[serde(rename = "errcode")]
+    pub errcode: i16,
+    #[serde(rename = "errmsg")]
+    pub errmsg: String,
+    // The specific error code, i.e: CHALLENGE_REQUIRED (missing token) or CHALLENGE_FAILED (invalid token).
+    #[serde(rename = "challenge")]
+    pub challenge: String,
+    // The challenge provider, e.g: HCAPTCHA, TURNSTILE
+    #[serde(rename = "provider")]
+    pub provider: String,
+    #[serde(rename = "siteKey")]
+    pub site_key: Option<String>,
+}
+
+// ----- Password change types. -----
 
+// The passwords are enveloped by the login pubkey of the fingerprint token as same as the password login.
 #[derive(Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct ChangePasswordRequest {
+    #[serde(rename = "currentPassword")]
+    pub current_password: String,
+    #[serde(rename = "newPassword")]
+    pub new_password: String,
+    #[serde(rename = "fpToken")]
+    pub fingerprint_token: String,
+}
+
+#[derive(Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct ResetPasswordRequest {
+    #[serde(rename = "newPassword")]
+    pub new_password: String,
+    #[serde(rename = "fpToken")]
+    pub fingerprint_token: String,
+}
+
+// ----- OAuth2 callback types. ------
+
+// The error of the authorization response when the user denied the consent, see: RFC 6749 section 4.1.2.1
+pub const OAUTH2_ERROR_ACCESS_DENIED: &str = "access_denied";
+
+// Get the first non-empty value of the query param, since the providers may repeat the params on the callback.
+fn first_query_value(pairs: &[(String, String)], name: &str) -> Option<String> {
+    pairs
+        .iter()
+        .find(|(key, value)| key == name && !value.is_empty())
+        .map(|(_, value)| value.to_owned())
+}
+
+// ----- OIDC login types. ------
+
+#[derive(Deserialize, Clone, Debug, Default, utoipa::ToSchema)]
 pub struct CallbackOidcRequest {
     pub code: Option<String>,
+    pub state: Option<String>,
+    // The error of the authorization response, e.g: access_denied when the user denied the consent.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+impl CallbackOidcRequest {
+    /// Parse from the callback query params, the repeated params take the first value and the unknown params
+    /// are ignored.
+    pub fn from_query_pairs(pairs: &[(String, String)]) -> Self {
+        CallbackOidcRequest {
+            code: first_query_value(pairs, "code"),
+            state: first_query_value(pairs, "state"),
+            error: first_query_value(pairs, "error"),
+            error_description: first_query_value(pairs, "error_description"),
+        }
+    }
 }
 
 // ----- Github OAuth2 login types. -----
 
-#[derive(Deserialize, Clone, Debug, utoipa::ToSchema)]
+#[derive(Deserialize, Clone, Debug, Default, utoipa::ToSchema)]
 pub struct CallbackGithubRequest {
     pub code: Option<String>,
+    pub state: Option<String>,
+    // The error of the authorization response, e.g: access_denied when the user denied the consent.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+impl CallbackGithubRequest {
+    /// Parse from the callback query params, the repeated params take the first value and the unknown params
+    /// are ignored.
+    pub fn from_query_pairs(pairs: &[(String, String)]) -> Self {
+        CallbackGithubRequest {
+            code: first_query_value(pairs, "code"),
+            state: first_query_value(pairs, "state"),
+            error: first_query_value(pairs, "error"),
+            error_description: first_query_value(pairs, "error_description"),
+        }
+    }
 }
 
 /*
@@ -203,6 +292,8 @@ pub struct LoggedResponse {
     pub access_token: Option<TokenWrapper>,
     #[serde(rename = "refreshToken")]
     pub refresh_token: Option<TokenWrapper>,
+    #[serde(rename = "csrfToken")]
+    pub csrf_token: Option<String>,
 }
 
 #[derive(Serialize, Clone, Debug, utoipa::ToSchema)]
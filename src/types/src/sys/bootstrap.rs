@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+/// The completed flag of the first-run bootstrap, which is persisted once and never removed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct Bootstrap {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    // The initial administrator created by the bootstrap, which is granted as an admin user.
+    pub admin_name: Option<String>,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Bootstrap {
+            base: BaseBean::new_empty(),
+            admin_name: None,
+        }
+    }
+}
+
+impl PersistableBean for Bootstrap {}
+
+impl<'r> FromRow<'r, SqliteRow> for Bootstrap {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Bootstrap {
+            base: BaseBean::from_row(row)?,
+            admin_name: row.try_get("admin_name")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for Bootstrap {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Bootstrap {
+            base: BaseBean::from_row(row)?,
+            admin_name: row.try_get("admin_name")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct BootstrapRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub username: String,
+    // The plain password of the initial administrator, which is required if no OIDC subject binding.
+    #[validate(length(min = 8, max = 256))]
+    pub password: Option<String>,
+    // The OIDC claims sub to bind, e.g: login the initial administrator with the Keycloak.
+    #[validate(length(min = 1, max = 64))]
+    pub oidc_claims_sub: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct BootstrapResponse {
+    pub id: i64,
+    pub name: String,
+}
+
+impl BootstrapResponse {
+    pub fn new(id: i64, name: String) -> Self {
+        BootstrapResponse { id, name }
+    }
+}
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PageResponse, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum DeadLetterState {
+    // Waiting for the replay.
+    PENDING,
+    // Successfully replayed through the original pipeline.
+    REPLAYED,
+    // The poison message which failed the max replays, never be replayed again.
+    FAILED,
+}
+
+impl DeadLetterState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "PENDING" => Some(DeadLetterState::PENDING),
+            "REPLAYED" => Some(DeadLetterState::REPLAYED),
+            "FAILED" => Some(DeadLetterState::FAILED),
+            _ => None,
+        }
+    }
+}
+
+/// The failed async work, which is serialized with its kind for replaying through the original pipeline.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct DeadLetter {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    // The kind of the original pipeline, e.g: ACCESS_EVENTS, WEBHOOK
+    pub kind: Option<String>,
+    // The serialized (JSON) payload of the failed work.
+    pub payload: Option<String>,
+    // The last error of the failed work or replay.
+    pub error: Option<String>,
+    // The total attempts of the original and the replays.
+    pub attempts: Option<i64>,
+    // The failed replays count, marked FAILED once reached the max replays.
+    pub replays: Option<i64>,
+    pub state: Option<DeadLetterState>,
+    // The unix timestamp (in millis) of the last failure.
+    pub last_failed_time: Option<i64>,
+}
+
+impl Default for DeadLetter {
+    fn default() -> Self {
+        DeadLetter {
+            base: BaseBean::new_empty(),
+            kind: None,
+            payload: None,
+            error: None,
+            attempts: None,
+            replays: None,
+            state: None,
+            last_failed_time: None,
+        }
+    }
+}
+
+impl PersistableBean for DeadLetter {}
+
+/// SqliteRow impl for DeadLetter.
+impl<'r> FromRow<'r, SqliteRow> for DeadLetter {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(DeadLetter {
+            base: BaseBean::from_row(row)?,
+            kind: row.try_get("kind")?,
+            payload: row.try_get("payload")?,
+            error: row.try_get("error")?,
+            attempts: row.try_get("attempts")?,
+            replays: row.try_get("replays")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| DeadLetterState::parse(&s)),
+            last_failed_time: row.try_get("last_failed_time")?,
+        })
+    }
+}
+
+/// Postgres Row impl for DeadLetter.
+impl<'r> FromRow<'r, PgRow> for DeadLetter {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(DeadLetter {
+            base: BaseBean::from_row(row)?,
+            kind: row.try_get("kind")?,
+            payload: row.try_get("payload")?,
+            error: row.try_get("error")?,
+            attempts: row.try_get("attempts")?,
+            replays: row.try_get("replays")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| DeadLetterState::parse(&s)),
+            last_failed_time: row.try_get("last_failed_time")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryDeadLetterRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub kind: Option<String>,
+    pub state: Option<DeadLetterState>,
+}
+
+impl QueryDeadLetterRequest {
+    pub fn to_dead_letter(&self) -> DeadLetter {
+        DeadLetter {
+            kind: self.kind.clone(),
+            state: self.state,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct QueryDeadLetterResponse {
+    pub page: Option<PageResponse>,
+    pub data: Option<Vec<DeadLetter>>,
+}
+
+impl QueryDeadLetterResponse {
+    pub fn new(page: PageResponse, data: Vec<DeadLetter>) -> Self {
+        QueryDeadLetterResponse {
+            page: Some(page),
+            data: Some(data),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct ReplayDeadLetterRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<i64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ReplayDeadLetterResult {
+    pub id: i64,
+    pub replayed: bool,
+    pub state: Option<DeadLetterState>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct ReplayDeadLetterResponse {
+    pub results: Vec<ReplayDeadLetterResult>,
+}
@@ -0,0 +1,273 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+use utoipa::{PartialSchema, ToSchema};
+
+// ----- The public event payloads of the outbound notifications. -----
+// Notice: These are the public contracts of the consumers (e.g: webhooks), any change of the fields requires to
+// bump the schema version of the event, which is asserted by the snapshot of schemas, see: snapshots/events.json
+
+/// The fail-open budget of the subsystem is escalated (exceeded) or recovered.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct FailOpenBudgetV1 {
+    pub subsystem: String,
+    // The budget status, e.g: OK, ESCALATED
+    pub status: String,
+    // The fail-open occurrences within the current window.
+    #[serde(rename = "failOpens")]
+    pub fail_opens: u64,
+    #[serde(rename = "maxFailOpens")]
+    pub max_fail_opens: u64,
+    // The unix timestamp (in millis) of escalated, None if recovered.
+    #[serde(rename = "escalatedTime")]
+    pub escalated_time: Option<i64>,
+    #[serde(rename = "failClosed")]
+    pub fail_closed: bool,
+}
+
+/// The supervised component is failed permanently, i.e: exceeded the restart budget or not restartable.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ComponentFailedV1 {
+    pub component: String,
+    // The restarts within the restart window.
+    pub restarts: u32,
+    pub max_restarts: u32,
+    pub last_error: String,
+}
+
+/// The synthetic probe failed consecutively, i.e: the protection may not be working.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ProbeFailedV1 {
+    pub name: String,
+    // The expected and actual decisions, e.g: BLOCK, PASS
+    pub expected: String,
+    pub actual: String,
+    // The end-to-end decision via the public listener, None if not enabled.
+    pub end_to_end: Option<String>,
+    pub consecutive_failures: u32,
+}
+
+/// The rule is promoted to the next lifecycle state, e.g: SHADOW -> ACTIVE
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct RulePromotedV1 {
+    pub name: String,
+    // The lifecycle states, e.g: CANDIDATE, SHADOW, ACTIVE
+    pub from_state: String,
+    pub to_state: String,
+    // The would-block requests observed in the SHADOW state.
+    pub hits: u64,
+    pub false_positives: u64,
+    pub precision: f64,
+    // Whether promoted automatically once met the threshold, otherwise by the promotion API.
+    pub auto_promoted: bool,
+}
+
+/// The JSON schema of the event payload for the tooling, e.g: GET /events/schema of the management server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct EventSchema {
+    pub event: String,
+    pub schema_version: u32,
+    #[schema(value_type = Object)]
+    pub schema: serde_json::Value,
+}
+
+impl EventSchema {
+    fn of<T: ToSchema>(event: &str, schema_version: u32) -> Self {
+        EventSchema {
+            event: event.to_owned(),
+            schema_version,
+            schema: serde_json::to_value(T::schema()).unwrap_or_default(),
+        }
+    }
+}
+
+// The events are defined in one place, so that adding an event forces its payload type (with the schema) and
+// version, and all the exhaustive matches of the channels are generated.
+macro_rules! define_botwaf_events {
+    ($($variant:ident($payload:ty) => ($name:literal, $version:literal)),* $(,)?) => {
+        /// The outbound notification events, which are sent as the 'data' of the EventPayload.
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+        #[serde(tag = "event", content = "data")]
+        pub enum BotwafEvent {
+            $(
+                #[serde(rename = $name)]
+                $variant($payload),
+            )*
+        }
+
+        impl BotwafEvent {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(BotwafEvent::$variant(_) => $name,)*
+                }
+            }
+
+            pub fn schema_version(&self) -> u32 {
+                match self {
+                    $(BotwafEvent::$variant(_) => $version,)*
+                }
+            }
+
+            /// The JSON schemas of all the event payloads.
+            pub fn schemas() -> Vec<EventSchema> {
+                vec![$(EventSchema::of::<$payload>($name, $version),)*]
+            }
+        }
+    };
+}
+
+define_botwaf_events! {
+    FailOpenBudgetV1(FailOpenBudgetV1) => ("fail_open_budget", 1),
+    ProbeFailedV1(ProbeFailedV1) => ("probe_failed", 1),
+    RulePromotedV1(RulePromotedV1) => ("rule_promoted", 1),
+    ComponentFailedV1(ComponentFailedV1) => ("component_failed", 1),
+}
+
+/// The payload of all the notification channels (e.g: webhook), e.g:
+/// {"schema_version": 1, "time": 1760572800000, "event": "rule_promoted", "data": {...}}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct EventPayload {
+    pub schema_version: u32,
+    // The unix timestamp (in millis) of the event occurred.
+    pub time: i64,
+    #[serde(flatten)]
+    pub event: BotwafEvent,
+}
+
+impl EventPayload {
+    pub fn new(event: BotwafEvent) -> Self {
+        EventPayload {
+            schema_version: event.schema_version(),
+            time: chrono::Utc::now().timestamp_millis(),
+            event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SNAPSHOT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/snapshots/events.json");
+
+    // The contract of the schema, i.e: the fields with the types, and the required fields.
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct SchemaContract {
+        schema_version: u32,
+        fields: BTreeMap<String, String>,
+        required: Vec<String>,
+    }
+
+    impl SchemaContract {
+        fn from(schema: &EventSchema) -> Self {
+            let fields = schema.schema["properties"]
+                .as_object()
+                .map(|props| {
+                    props
+                        .iter()
+                        .map(|(name, prop)| {
+                            let ty = match &prop["type"] {
+                                serde_json::Value::Array(types) => {
+                                    types.iter().filter_map(|t| t.as_str()).collect::<Vec<_>>().join("|")
+                                }
+                                ty => ty.as_str().unwrap_or("object").to_owned(),
+                            };
+                            (name.to_owned(), ty)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let mut required: Vec<String> = schema.schema["required"]
+                .as_array()
+                .map(|r| r.iter().filter_map(|v| v.as_str().map(|s| s.to_owned())).collect())
+                .unwrap_or_default();
+            required.sort();
+            SchemaContract {
+                schema_version: schema.schema_version,
+                fields,
+                required,
+            }
+        }
+    }
+
+    // Regenerate the snapshot by: BOTWAF_UPDATE_SNAPSHOTS=1 cargo test -p botwaf-types event
+    #[test]
+    fn test_event_schemas_snapshot() {
+        let current: BTreeMap<String, SchemaContract> = BotwafEvent::schemas()
+            .iter()
+            .map(|s| (s.event.to_owned(), SchemaContract::from(s)))
+            .collect();
+        if std::env::var("BOTWAF_UPDATE_SNAPSHOTS").is_ok() {
+            let json = serde_json::to_string_pretty(&current).unwrap();
+            std::fs::write(SNAPSHOT_PATH, json + "\n").unwrap();
+            return;
+        }
+
+        let snapshot: BTreeMap<String, SchemaContract> =
+            serde_json::from_str(&std::fs::read_to_string(SNAPSHOT_PATH).unwrap()).unwrap();
+        for (event, contract) in current.iter() {
+            let expected = snapshot
+                .get(event)
+                .unwrap_or_else(|| panic!("The event '{}' is missing in the snapshot", event));
+            if contract.schema_version == expected.schema_version {
+                assert_eq!(
+                    contract, expected,
+                    "The schema of event '{}' is changed without the version bump",
+                    event
+                );
+            } else {
+                assert!(
+                    contract.schema_version > expected.schema_version,
+                    "The schema version of event '{}' is downgraded",
+                    event
+                );
+                panic!(
+                    "The schema version of event '{}' is bumped, please update the snapshot",
+                    event
+                );
+            }
+        }
+        assert_eq!(
+            current.len(),
+            snapshot.len(),
+            "The removed events are still in the snapshot"
+        );
+    }
+
+    #[test]
+    fn test_event_payload_with_schema_version() {
+        let payload = EventPayload::new(BotwafEvent::RulePromotedV1(RulePromotedV1 {
+            name: String::from("bad-bots"),
+            from_state: String::from("SHADOW"),
+            to_state: String::from("ACTIVE"),
+            hits: 120,
+            false_positives: 1,
+            precision: 0.99,
+            auto_promoted: true,
+        }));
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["event"], "rule_promoted");
+        assert_eq!(json["data"]["to_state"], "ACTIVE");
+        assert_eq!(serde_json::from_value::<EventPayload>(json).unwrap(), payload);
+    }
+}
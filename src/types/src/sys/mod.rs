@@ -19,4 +19,9 @@
 // This includes modifications and derived works.
 
 pub mod auth;
+pub mod bootstrap;
+pub mod dead_letter;
+pub mod event;
+pub mod signing_key;
+pub mod stats;
 pub mod user;
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::{BaseBean, PageResponse, PersistableBean};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, utoipa::ToSchema)]
+pub enum SigningKeyState {
+    // The key is used to verify the signed requests, at most two active keys per key id for the rotation.
+    ACTIVE,
+    // The key is retired by the rotation or manually, never be used again.
+    RETIRED,
+}
+
+impl SigningKeyState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ACTIVE" => Some(SigningKeyState::ACTIVE),
+            "RETIRED" => Some(SigningKeyState::RETIRED),
+            _ => None,
+        }
+    }
+}
+
+/// The HMAC secret of the request signing key, see: botwaf_utils::request_signing::RequestSigner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct SigningKey {
+    #[serde(flatten)]
+    pub base: BaseBean,
+    // The key id of the Authorization header, e.g: orders-service
+    pub key_id: Option<String>,
+    // The HMAC secret, which is only returned once when rotated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub state: Option<SigningKeyState>,
+}
+
+impl Default for SigningKey {
+    fn default() -> Self {
+        SigningKey {
+            base: BaseBean::new_empty(),
+            key_id: None,
+            secret: None,
+            state: None,
+        }
+    }
+}
+
+impl PersistableBean for SigningKey {}
+
+/// SqliteRow impl for SigningKey.
+impl<'r> FromRow<'r, SqliteRow> for SigningKey {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(SigningKey {
+            base: BaseBean::from_row(row)?,
+            key_id: row.try_get("key_id")?,
+            secret: row.try_get("secret")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| SigningKeyState::parse(&s)),
+        })
+    }
+}
+
+/// Postgres Row impl for SigningKey.
+impl<'r> FromRow<'r, PgRow> for SigningKey {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(SigningKey {
+            base: BaseBean::from_row(row)?,
+            key_id: row.try_get("key_id")?,
+            secret: row.try_get("secret")?,
+            state: row
+                .try_get::<Option<String>, _>("state")?
+                .and_then(|s| SigningKeyState::parse(&s)),
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuerySigningKeyRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub key_id: Option<String>,
+    pub state: Option<SigningKeyState>,
+}
+
+impl QuerySigningKeyRequest {
+    pub fn to_signing_key(&self) -> SigningKey {
+        SigningKey {
+            key_id: self.key_id.clone(),
+            state: self.state,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct QuerySigningKeyResponse {
+    pub page: Option<PageResponse>,
+    pub data: Option<Vec<SigningKey>>,
+}
+
+impl QuerySigningKeyResponse {
+    pub fn new(page: PageResponse, data: Vec<SigningKey>) -> Self {
+        QuerySigningKeyResponse {
+            page: Some(page),
+            data: Some(data),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct RotateSigningKeyRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub key_id: String,
+    // The HMAC secret, generated randomly if not specified.
+    #[validate(length(min = 32, max = 256))]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct RotateSigningKeyResponse {
+    pub id: i64,
+    pub key_id: String,
+    // The HMAC secret of the new key, which is only returned once.
+    pub secret: String,
+    // The ids of the retired keys beyond the max active keys.
+    pub retired: Vec<i64>,
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Clone, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryDependencyStatsRequest {
+    /// The last minutes of the aggregated stats, defaults to 5 and at most 60.
+    pub minutes: Option<u32>,
+}
+
+/// The aggregated calls of an external dependency by the operation class, e.g: redis/get, appdb/select
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct DependencyStats {
+    // The dependency, e.g: appdb, redis, vector_store, llm, upstream, webhook
+    pub dependency: String,
+    // The operation class, e.g: get, set, select, insert, forward
+    pub operation: String,
+    pub calls: u64,
+    pub errors: u64,
+    // The errors ratio of the calls, i.e: 0.0 ~ 1.0
+    pub error_rate: f64,
+    pub avg_ms: f64,
+    // The latency percentiles estimated by the linear interpolation of the histogram buckets.
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct QueryDependencyStatsResponse {
+    pub minutes: u32,
+    // Whether the stats are since the process started, i.e: it's not running over the window yet.
+    pub since_started: bool,
+    pub dependencies: Vec<DependencyStats>,
+}
@@ -18,7 +18,7 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
-use crate::{BaseBean, PageResponse};
+use crate::{BaseBean, PageResponse, PersistableBean, RecordStatus};
 use common_makestruct::MakeStructWith;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
@@ -46,6 +46,13 @@ pub struct User {
     pub google_claims_email: Option<String>,
     pub ethers_address: Option<String>,
     pub lang: Option<String>,
+    // The normalized keys of the name/email for the case-insensitive lookup and uniqueness, which are computed
+    // on save, see: botwaf_server::sys::identities
+    pub name_key: Option<String>,
+    pub email_key: Option<String>,
+    // The token epoch (milliseconds) bumped by the password changed or reset, i.e: the access tokens issued
+    // before are rejected, see: botwaf_server::sys::handler::auth_handler::get_token_epoch
+    pub token_epoch: Option<i64>,
 }
 
 impl Default for User {
@@ -67,10 +74,15 @@ impl Default for User {
             google_claims_email: None,
             ethers_address: None,
             lang: None,
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         }
     }
 }
 
+impl PersistableBean for User {}
+
 /// SqliteRow impl for User.
 impl<'r> FromRow<'r, SqliteRow> for User {
     fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
@@ -89,6 +101,9 @@ impl<'r> FromRow<'r, SqliteRow> for User {
             google_claims_sub: row.try_get("google_claims_sub")?,
             google_claims_name: row.try_get("google_claims_name")?,
             google_claims_email: row.try_get("google_claims_email")?,
+            name_key: row.try_get("name_key")?,
+            email_key: row.try_get("email_key")?,
+            token_epoch: row.try_get("token_epoch")?,
             ethers_address: row.try_get("ethers_address")?,
             lang: row.try_get("lang")?,
         })
@@ -114,6 +129,9 @@ impl<'r> FromRow<'r, PgRow> for User {
             google_claims_sub: row.try_get("google_claims_sub")?,
             google_claims_name: row.try_get("google_claims_name")?,
             google_claims_email: row.try_get("google_claims_email")?,
+            name_key: row.try_get("name_key")?,
+            email_key: row.try_get("email_key")?,
+            token_epoch: row.try_get("token_epoch")?,
             ethers_address: row.try_get("ethers_address")?,
             lang: row.try_get("lang")?,
         })
@@ -164,12 +182,16 @@ pub struct QueryUserRequest {
     pub google_claims_email: Option<String>,
     #[validate(length(min = 1, max = 64))]
     pub ethers_address: Option<String>,
+    // The optional status filter, e.g: Active to exclude the disabled users from listings.
+    pub status: Option<RecordStatus>,
 }
 
 impl QueryUserRequest {
     pub fn to_user(&self) -> User {
+        let mut base = BaseBean::new_empty();
+        base.status = self.status.map(|s| s.value());
         User {
-            base: BaseBean::new_empty(),
+            base,
             name: Some(self.name.clone().unwrap_or_default()),
             email: Some(self.email.clone().unwrap_or_default()),
             phone: self.phone.clone(),
@@ -185,6 +207,9 @@ impl QueryUserRequest {
             google_claims_email: None,
             ethers_address: None,
             lang: None,
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         }
     }
 }
@@ -205,10 +230,12 @@ impl QueryUserResponse {
 }
 
 #[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema, MakeStructWith)]
-#[excludes(id)]
+#[excludes(id, version)]
 // #[smart_copy(target = "SaveUserRequestWith")]
 pub struct SaveUserRequest {
     pub id: Option<i64>,
+    // The current version for optimistic locking, the stale update will be rejected with 409 conflict.
+    pub version: Option<i64>,
     #[validate(length(min = 1, max = 64))]
     pub name: Option<String>,
     #[validate(email)]
@@ -244,8 +271,10 @@ pub struct SaveUserRequest {
 
 impl SaveUserRequest {
     pub fn to_user(&self) -> User {
+        let mut base = BaseBean::new_with_id(self.id);
+        base.version = self.version;
         User {
-            base: BaseBean::new_with_id(self.id),
+            base,
             name: self.name.clone(), // self.name.as_ref().map(|n| n.to_string())
             email: self.email.clone(),
             phone: self.phone.clone(),
@@ -261,6 +290,9 @@ impl SaveUserRequest {
             google_claims_email: self.google_claims_email.clone(),
             ethers_address: self.ethers_address.clone(),
             lang: self.lang.clone(),
+            name_key: None,
+            email_key: None,
+            token_epoch: None,
         }
     }
 }
@@ -281,6 +313,23 @@ pub struct DeleteUserRequest {
     pub id: i64,
 }
 
+#[derive(Deserialize, Clone, Debug, PartialEq, Validate, utoipa::ToSchema)]
+pub struct SetUserStatusRequest {
+    pub id: i64,
+    pub status: RecordStatus,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
+pub struct SetUserStatusResponse {
+    pub count: u64,
+}
+
+impl SetUserStatusResponse {
+    pub fn new(count: u64) -> Self {
+        SetUserStatusResponse { count }
+    }
+}
+
 #[derive(Serialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
 pub struct DeleteUserResponse {
     pub count: u64,
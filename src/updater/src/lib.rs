@@ -18,5 +18,7 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+pub mod novelty_filter;
 pub mod updater_base;
+pub mod updater_router;
 pub mod updater_simple_llm;
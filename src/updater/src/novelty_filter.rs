@@ -0,0 +1,492 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use anyhow::Error;
+use async_trait::async_trait;
+use botwaf_server::{
+    config::config::NoveltyFilterProperties,
+    mgmt::apm::metrics::{BOTWAF_EMBEDDING_DEDUP_RATIO, BOTWAF_EMBEDDING_DEDUP_TOTAL},
+};
+use botwaf_types::modules::forward::access_event::BotwafAccessEvent;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+lazy_static! {
+    // The identifier like path segments, e.g: 12345, 5f1d7c2ab3e4, 123e4567-e89b-12d3-a456-426614174000
+    static ref ID_SEGMENT_REGEX: Regex = Regex::new(r"^([0-9]+|[0-9a-fA-F]{8,}|[0-9a-fA-F-]{32,36})$").unwrap();
+    static ref DIGITS_REGEX: Regex = Regex::new(r"[0-9]+").unwrap();
+}
+
+// The max characters of the body included in the normalized request.
+const MAX_NORMALIZED_BODY_CHARS: usize = 1024;
+
+// The weight of the 3-grams of the special characters in the simhash, see: simhash()
+const SPECIAL_SHINGLE_WEIGHT: i64 = 8;
+
+/// The embedder of the access events into the vector store, see: NoveltyFilter::embed_novel
+#[async_trait]
+pub trait IEventEmbedder: Send + Sync {
+    /// Embed the novel access events into the namespace (i.e: NORMAL, MALICIOUS) of the vector store.
+    async fn embed(&self, namespace: &str, events: &[BotwafAccessEvent]) -> Result<(), Error>;
+
+    /// Getting the max similarity (0-1) of the normalized request with the embedded documents of the namespace,
+    /// which confirms the borderline events.
+    async fn max_similarity(&self, namespace: &str, normalized: &str) -> Result<Option<f64>, Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoveltyReason {
+    NOVEL,
+    DUPLICATE,
+}
+
+/// The access event marked as processed by the novelty filter, the duplicate is skipped embedding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoveltyOutcome {
+    pub req_id: Option<String>,
+    pub reason: NoveltyReason,
+    // The hamming distance to the nearest embedded hash within the window, none if no any.
+    pub distance: Option<u32>,
+}
+
+/// The compact table of the recently embedded hashes (with the embedded time) of each namespace.
+struct NoveltyHashTable {
+    namespaces: Mutex<HashMap<String, VecDeque<(u64, i64)>>>,
+    max_hashes: usize,
+}
+
+impl NoveltyHashTable {
+    fn new(max_hashes: usize) -> Self {
+        Self {
+            namespaces: Mutex::new(HashMap::new()),
+            max_hashes,
+        }
+    }
+
+    // Getting the min hamming distance to the hashes embedded since, the expired are evicted.
+    fn nearest(&self, namespace: &str, hash: u64, since: i64) -> Option<u32> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        let hashes = namespaces.get_mut(namespace)?;
+        while hashes.front().is_some_and(|(_, at)| *at < since) {
+            hashes.pop_front();
+        }
+        hashes.iter().map(|(h, _)| hamming_distance(*h, hash)).min()
+    }
+
+    fn record(&self, namespace: &str, hashes: &[u64], at: i64) {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        let table = namespaces.entry(namespace.to_owned()).or_default();
+        table.extend(hashes.iter().map(|h| (*h, at)));
+        while table.len() > self.max_hashes {
+            table.pop_front();
+        }
+    }
+}
+
+/// The novelty filter before embedding the access events, which skips the near-identical events by the simhash
+/// of the normalized requests, see: NoveltyFilterProperties
+pub struct NoveltyFilter {
+    config: NoveltyFilterProperties,
+    table: NoveltyHashTable,
+}
+
+impl NoveltyFilter {
+    pub fn new(config: &NoveltyFilterProperties) -> Self {
+        Self {
+            config: config.to_owned(),
+            table: NoveltyHashTable::new(config.max_hashes),
+        }
+    }
+
+    /// Embed only the novel events of the namespace, and all the events are marked as processed with the reason.
+    /// Notice: None is marked if failed to embed, so that they are retried by the next run.
+    pub async fn embed_novel(
+        &self,
+        namespace: &str,
+        events: Vec<BotwafAccessEvent>,
+        embedder: &dyn IEventEmbedder,
+    ) -> Result<Vec<NoveltyOutcome>, Error> {
+        self.embed_novel_at(namespace, events, embedder, chrono::Utc::now().timestamp_millis())
+            .await
+    }
+
+    async fn embed_novel_at(
+        &self,
+        namespace: &str,
+        events: Vec<BotwafAccessEvent>,
+        embedder: &dyn IEventEmbedder,
+        now: i64,
+    ) -> Result<Vec<NoveltyOutcome>, Error> {
+        if !self.config.enabled {
+            embedder.embed(namespace, &events).await?;
+            return Ok(events
+                .iter()
+                .map(|e| NoveltyOutcome {
+                    req_id: e.req_id.to_owned(),
+                    reason: NoveltyReason::NOVEL,
+                    distance: None,
+                })
+                .collect());
+        }
+
+        let (max_distance, borderline_distance, window) = self.config.resolve(namespace);
+        let since = now - window.as_millis() as i64;
+        let (mut outcomes, mut novel_events, mut novel_hashes) = (Vec::new(), Vec::new(), Vec::new());
+        for event in events {
+            let normalized = normalize_request(&event);
+            let hash = simhash(&normalized);
+            // The novel events of the same batch are also compared with, before recorded on embedded.
+            let distance = novel_hashes
+                .iter()
+                .map(|h| hamming_distance(*h, hash))
+                .chain(self.table.nearest(namespace, hash, since))
+                .min();
+            let reason = match distance {
+                Some(d) if d <= max_distance => NoveltyReason::DUPLICATE,
+                Some(d) if d <= borderline_distance => self.confirm_borderline(namespace, &normalized, embedder).await,
+                _ => NoveltyReason::NOVEL,
+            };
+            if reason == NoveltyReason::NOVEL {
+                novel_hashes.push(hash);
+            }
+            outcomes.push(NoveltyOutcome {
+                req_id: event.req_id.to_owned(),
+                reason,
+                distance,
+            });
+            if reason == NoveltyReason::NOVEL {
+                novel_events.push(event);
+            }
+        }
+
+        if !novel_events.is_empty() {
+            embedder.embed(namespace, &novel_events).await?;
+            self.table.record(namespace, &novel_hashes, now);
+        }
+
+        let duplicates = outcomes.iter().filter(|o| o.reason == NoveltyReason::DUPLICATE).count();
+        BOTWAF_EMBEDDING_DEDUP_TOTAL
+            .with_label_values(&[namespace, "novel"])
+            .inc_by(novel_events.len() as u64);
+        BOTWAF_EMBEDDING_DEDUP_TOTAL
+            .with_label_values(&[namespace, "duplicate"])
+            .inc_by(duplicates as u64);
+        if !outcomes.is_empty() {
+            BOTWAF_EMBEDDING_DEDUP_RATIO
+                .with_label_values(&[namespace])
+                .set(duplicates as f64 / outcomes.len() as f64);
+        }
+        tracing::info!(
+            "Filtered the access events of namespace '{}', embedded: {}, skipped duplicates: {}",
+            namespace,
+            novel_events.len(),
+            duplicates
+        );
+        Ok(outcomes)
+    }
+
+    // The borderline event is regarded as novel, unless confirmed similar by the single vector similarity query.
+    async fn confirm_borderline(
+        &self,
+        namespace: &str,
+        normalized: &str,
+        embedder: &dyn IEventEmbedder,
+    ) -> NoveltyReason {
+        let min_similarity = match self.config.confirm_min_similarity {
+            Some(min_similarity) => min_similarity,
+            None => return NoveltyReason::NOVEL,
+        };
+        match embedder.max_similarity(namespace, normalized).await {
+            Ok(Some(similarity)) if similarity >= min_similarity => NoveltyReason::DUPLICATE,
+            Ok(_) => NoveltyReason::NOVEL,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to confirm the borderline event by similarity, regarded as novel. {}",
+                    e
+                );
+                NoveltyReason::NOVEL
+            }
+        }
+    }
+}
+
+/// Normalize the request of the access event, so that the near-identical requests (e.g: the different IDs, page
+/// numbers or timestamps) are normalized to the same representation, but the attack payloads are preserved.
+pub fn normalize_request(event: &BotwafAccessEvent) -> String {
+    let path = event
+        .path
+        .split('/')
+        .map(|segment| match ID_SEGMENT_REGEX.is_match(segment) {
+            true => String::from("{id}"),
+            false => DIGITS_REGEX.replace_all(&segment.to_lowercase(), "0").to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut params = url::form_urlencoded::parse(event.query.as_deref().unwrap_or_default().as_bytes())
+        .map(|(k, v)| {
+            format!(
+                "{}={}",
+                k.to_lowercase(),
+                DIGITS_REGEX.replace_all(&v.to_lowercase(), "0")
+            )
+        })
+        .collect::<Vec<_>>();
+    params.sort();
+    let body = event
+        .body
+        .as_deref()
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_NORMALIZED_BODY_CHARS)
+        .collect::<String>()
+        .to_lowercase();
+    format!(
+        "{} {}?{} {}",
+        event.method.to_uppercase(),
+        path,
+        params.join("&"),
+        DIGITS_REGEX.replace_all(&body, "0")
+    )
+}
+
+/// The 64 bits simhash over the character 3-grams, the similar texts are of the small hamming distance.
+/// Notice: The 3-grams of the special characters (e.g: quotes, brackets) are weighted, so that the short attack
+/// payload appended to the near-identical request is still far away.
+pub fn simhash(text: &str) -> u64 {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut weights = [0i64; 64];
+    for shingle in chars.windows(3.min(chars.len()).max(1)) {
+        let hash = fnv1a64(shingle.iter().collect::<String>().as_bytes());
+        let weight = match shingle.iter().any(|c| is_special_char(*c)) {
+            true => SPECIAL_SHINGLE_WEIGHT,
+            false => 1,
+        };
+        for (bit, w) in weights.iter_mut().enumerate() {
+            *w += if hash >> bit & 1 == 1 { weight } else { -weight };
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0u64, |hash, (bit, _)| hash | 1 << bit)
+}
+
+fn is_special_char(c: char) -> bool {
+    !(c.is_alphanumeric() || " /?&=.,_-+{}".contains(c))
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// The stable hash across the processes and versions, unlike the std DefaultHasher.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // The mock embedder which counts the embedded events, and responds the fixed similarity.
+    struct MockEmbedder {
+        embedded: AtomicUsize,
+        similarity: Option<f64>,
+        similarity_queries: AtomicUsize,
+    }
+
+    impl MockEmbedder {
+        fn new(similarity: Option<f64>) -> Self {
+            Self {
+                embedded: AtomicUsize::new(0),
+                similarity,
+                similarity_queries: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IEventEmbedder for MockEmbedder {
+        async fn embed(&self, _namespace: &str, events: &[BotwafAccessEvent]) -> Result<(), Error> {
+            self.embedded.fetch_add(events.len(), Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn max_similarity(&self, _namespace: &str, _normalized: &str) -> Result<Option<f64>, Error> {
+            self.similarity_queries.fetch_add(1, Ordering::SeqCst);
+            Ok(self.similarity)
+        }
+    }
+
+    fn create_event(req_id: usize, method: &str, path: &str, query: Option<&str>) -> BotwafAccessEvent {
+        BotwafAccessEvent {
+            method: method.to_owned(),
+            scheme: Some(String::from("https")),
+            host: Some(String::from("shop.example.com")),
+            port: Some(443),
+            headers: None,
+            path: path.to_owned(),
+            query: query.map(|q| q.to_owned()),
+            body: None,
+            req_id: Some(format!("req-{}", req_id)),
+            client_ip: Some(format!("10.0.{}.{}", req_id / 256, req_id % 256)),
+            start_time: 1700000000000 + req_id as u64,
+            resp_status_code: Some(200),
+            resp_headers: None,
+            resp_body: None,
+            duration: Some(12),
+            synthetic: false,
+            rule_id: None,
+            headers_truncated: false,
+            resp_rewrite: None,
+        }
+    }
+
+    // The stream of 90% near-duplicates (the different IDs and page numbers of the same APIs), with the few of
+    // genuinely different requests and an attack sample.
+    fn create_stream() -> Vec<BotwafAccessEvent> {
+        let mut events = Vec::new();
+        for i in 0..90 {
+            let event = match i % 2 {
+                0 => create_event(i, "GET", &format!("/api/v1/orders/{}", 10000 + i * 7), None),
+                _ => create_event(i, "GET", "/api/v1/products", Some(&format!("page={}&size=20", i))),
+            };
+            events.push(event);
+        }
+        let novel = [
+            ("POST", "/api/v1/cart/items", None),
+            ("GET", "/static/js/app.bundle.js", None),
+            ("DELETE", "/api/v1/sessions/current", None),
+            ("GET", "/api/v1/search", Some("q=wireless%20headphones&lang=en")),
+            ("PUT", "/api/v1/users/profile/avatar", None),
+            ("GET", "/healthz", None),
+            ("GET", "/api/v2/recommendations", Some("category=books&limit=5")),
+            ("POST", "/auth/login", None),
+            ("GET", "/sitemap.xml", None),
+        ];
+        for (i, (method, path, query)) in novel.iter().enumerate() {
+            events.push(create_event(90 + i, method, path, *query));
+        }
+        events.push(create_event(
+            99,
+            "GET",
+            "/api/v1/products",
+            Some("page=1%27%20UNION%20SELECT%20username,password%20FROM%20users--&size=20"),
+        ));
+        events
+    }
+
+    #[test]
+    fn test_normalized_near_duplicates_of_small_distance() {
+        let a = normalize_request(&create_event(1, "GET", "/api/v1/orders/10007", Some("page=2")));
+        let b = normalize_request(&create_event(2, "get", "/api/v1/orders/5f1d7c2ab3e4", Some("page=31")));
+        assert_eq!(a, b);
+        assert_eq!(a, "GET /api/v0/orders/{id}?page=0 ");
+        assert_eq!(simhash(&a), simhash(&b));
+
+        let attack = normalize_request(&create_event(
+            3,
+            "GET",
+            "/api/v1/orders/1",
+            Some("page=1%27%20or%20%271"),
+        ));
+        assert!(hamming_distance(simhash(&a), simhash(&attack)) > 8);
+        assert_eq!(simhash(""), 0);
+    }
+
+    #[tokio::test]
+    async fn test_embed_only_novel_events() {
+        let filter = NoveltyFilter::new(&NoveltyFilterProperties::default());
+        let embedder = MockEmbedder::new(None);
+        let events = create_stream();
+        let attack_req_id = events.last().unwrap().req_id.to_owned();
+
+        let outcomes = filter.embed_novel("NORMAL", events, &embedder).await.unwrap();
+        // All the events are marked as processed, and the duplicates are skipped embedding.
+        assert_eq!(outcomes.len(), 100);
+        let embedded = embedder.embedded.load(Ordering::SeqCst);
+        assert!(embedded <= 15, "embedded: {}", embedded);
+        let duplicates = outcomes.iter().filter(|o| o.reason == NoveltyReason::DUPLICATE).count();
+        assert_eq!(duplicates + embedded, 100);
+        let attack = outcomes.iter().find(|o| o.req_id == attack_req_id).unwrap();
+        assert_eq!(attack.reason, NoveltyReason::NOVEL);
+        assert!(BOTWAF_EMBEDDING_DEDUP_RATIO.with_label_values(&["NORMAL"]).get() >= 0.85);
+
+        // The same stream of the next run is all duplicates within the window.
+        let outcomes = filter.embed_novel("NORMAL", create_stream(), &embedder).await.unwrap();
+        assert!(outcomes.iter().all(|o| o.reason == NoveltyReason::DUPLICATE));
+        assert_eq!(embedder.embedded.load(Ordering::SeqCst), embedded);
+
+        // The other namespace is tracked separately.
+        filter
+            .embed_novel("MALICIOUS", create_stream(), &embedder)
+            .await
+            .unwrap();
+        assert_eq!(embedder.embedded.load(Ordering::SeqCst), embedded * 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_hashes_and_borderline_confirmation() {
+        let config = NoveltyFilterProperties {
+            max_distance: 0,
+            borderline_distance: 64,
+            confirm_min_similarity: Some(0.9),
+            ..NoveltyFilterProperties::default()
+        };
+        let filter = NoveltyFilter::new(&config);
+        let window = config.window.as_millis() as i64;
+        let embedder = MockEmbedder::new(Some(0.95));
+
+        let event = |i| create_event(i, "GET", "/api/v1/search", Some(&format!("q=shoes+size+{}", i)));
+        filter
+            .embed_novel_at("NORMAL", vec![event(1)], &embedder, 0)
+            .await
+            .unwrap();
+        // The borderline is confirmed as duplicate by the similarity query.
+        let outcomes = filter
+            .embed_novel_at(
+                "NORMAL",
+                vec![create_event(2, "GET", "/api/v1/search", Some("q=boots"))],
+                &embedder,
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcomes[0].reason, NoveltyReason::DUPLICATE);
+        assert_eq!(embedder.similarity_queries.load(Ordering::SeqCst), 1);
+
+        // The embedded hashes out of the window are expired.
+        let outcomes = filter
+            .embed_novel_at("NORMAL", vec![event(3)], &embedder, window + 1)
+            .await
+            .unwrap();
+        assert_eq!(outcomes[0].reason, NoveltyReason::NOVEL);
+        assert_eq!(outcomes[0].distance, None);
+        assert_eq!(embedder.embedded.load(Ordering::SeqCst), 2);
+    }
+}
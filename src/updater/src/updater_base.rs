@@ -21,10 +21,15 @@
 use crate::updater_simple_llm::SimpleLLMUpdater;
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_server::config::config;
+use botwaf_server::{
+    config::config::{self, UpdaterKind, UpdaterProperties},
+    mgmt::apm::metrics::BOTWAF_UPDATER_RUNS_TOTAL,
+    util::spec_runs::{SpecRunGuard, SpecRunRejection},
+};
+pub use botwaf_types::modules::forward::access_event::BotwafAccessEvent;
+use botwaf_types::modules::scheduler::spec_run::SpecRunResponse;
 use common_telemetry::info;
 use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
@@ -33,12 +38,25 @@ use std::{
 #[async_trait]
 pub trait IBotwafUpdater: Send + Sync {
     async fn init(&self);
+
+    async fn update(&self);
+
+    // The guard shared by the manual and scheduled runs, see: SpecRunGuard
+    fn run_guard(&self) -> &Arc<SpecRunGuard>;
 }
 
 lazy_static! {
     static ref SINGLE_INSTANCE: RwLock<BotwafUpdaterManager> = RwLock::new(BotwafUpdaterManager::new());
 }
 
+/// Build the updater instance of the spec config kind, the match is exhaustive, so that adding a new kind
+/// requires only to implement the IBotwafUpdater and register it here, otherwise it fails to compile.
+pub async fn build_updater(config: &UpdaterProperties) -> Arc<dyn IBotwafUpdater + Send + Sync> {
+    match config.kind {
+        UpdaterKind::SIMPLE_LLM => SimpleLLMUpdater::new(config).await,
+    }
+}
+
 pub struct BotwafUpdaterManager {
     pub implementations: HashMap<String, Arc<dyn IBotwafUpdater + Send + Sync>>,
 }
@@ -54,6 +72,8 @@ impl BotwafUpdaterManager {
         &SINGLE_INSTANCE
     }
 
+    /// Initialize all the enabled updaters, which is idempotent for the restart of the supervisor, i.e: the
+    /// already initialized updaters are skipped, so that their schedulers aren't registered duplicated.
     pub async fn init() {
         info!("Register All Botwaf updaters ...");
 
@@ -62,28 +82,32 @@ impl BotwafUpdaterManager {
                 info!("Skipping implementation updater: {}", config.name);
                 continue;
             }
-            // TODO: Full use similar java spi provider mechanism.
-            if config.kind == SimpleLLMUpdater::KIND {
-                match Self::get()
-                    .write() // If acquire fails, then it block until acquired.
-                    .unwrap() // If acquire fails, then it should panic.
-                    .register(config.kind.to_owned(), SimpleLLMUpdater::new(config).await)
-                {
-                    Ok(registered) => {
-                        info!("Initializing Botwaf Updater ...");
-                        let _ = registered.init().await;
-                    }
-                    Err(e) => panic!("Failed to register Botwaf Updater: {}", e),
-                }
+            if Self::get().read().unwrap().implementations.contains_key(&config.name) {
+                info!("Skipping the initialized updater: {}", config.name);
+                continue;
+            }
+            let updater = build_updater(config).await;
+            // Register after initialized, so that the failed is re-initialized on restarting.
+            info!(
+                "Initializing Botwaf Updater '{}' of kind {:?} ...",
+                config.name, config.kind
+            );
+            updater.init().await;
+            if let Err(e) = Self::get()
+                .write() // If acquire fails, then it block until acquired.
+                .unwrap() // If acquire fails, then it should panic.
+                .register(config.name.to_owned(), updater)
+            {
+                panic!("Failed to register Botwaf Updater: {}", e);
             }
         }
     }
 
-    fn register<T: IBotwafUpdater + Send + Sync + 'static>(
+    fn register(
         &mut self,
         name: String,
-        handler: Arc<T>,
-    ) -> Result<Arc<T>, Error> {
+        handler: Arc<dyn IBotwafUpdater + Send + Sync>,
+    ) -> Result<Arc<dyn IBotwafUpdater + Send + Sync>, Error> {
         if self.implementations.contains_key(&name) {
             tracing::debug!("Already register the Updater '{}'", name);
             return Ok(handler);
@@ -102,27 +126,116 @@ impl BotwafUpdaterManager {
             return Err(Error::msg(errmsg));
         }
     }
+
+    /// Trigger an immediate one-off run of the named updater spec outside the schedule.
+    pub async fn run(name: String) -> Result<SpecRunResponse, SpecRunRejection> {
+        let implementation = match Self::get_implementation(name.to_owned()).await {
+            Ok(implementation) => implementation,
+            Err(_) => return Err(SpecRunRejection::NotFound(name)),
+        };
+        let guard = implementation.run_guard().to_owned();
+        guard.spawn(async move {
+            info!("Manual running the Updater '{}' ...", name);
+            BOTWAF_UPDATER_RUNS_TOTAL.with_label_values(&["manual"]).inc();
+            implementation.update().await;
+        })
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct BotwafAccessEvent {
-    // Request information.
-    pub method: String,
-    pub scheme: Option<String>,
-    pub host: Option<String>,
-    pub port: Option<u16>,
-    pub headers: Option<HashMap<String, Option<String>>>,
-    pub path: String,
-    pub query: Option<String>,
-    pub body: Option<String>,
-    // Additional request information.
-    pub req_id: Option<String>,
-    pub client_ip: Option<String>,
-    pub start_time: u64,
-    // Response information.
-    pub resp_status_code: Option<i32>,
-    pub resp_headers: Option<HashMap<String, Option<String>>>,
-    pub resp_body: Option<String>,
-    // Additional response information.
-    pub duration: Option<u64>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use botwaf_types::modules::scheduler::spec_run::SpecRunStatus;
+    use hyper::StatusCode;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::sync::Notify;
+
+    struct MockUpdater {
+        updated: AtomicUsize,
+        release: Notify,
+        run_guard: Arc<SpecRunGuard>,
+    }
+
+    #[async_trait]
+    impl IBotwafUpdater for MockUpdater {
+        async fn init(&self) {}
+
+        async fn update(&self) {
+            self.release.notified().await;
+            self.updated.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn run_guard(&self) -> &Arc<SpecRunGuard> {
+            &self.run_guard
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manual_run_and_reject_overlapping() {
+        let name = "mockUpdater".to_owned();
+        let updater = Arc::new(MockUpdater {
+            updated: AtomicUsize::new(0),
+            release: Notify::new(),
+            run_guard: SpecRunGuard::new(&name),
+        });
+        BotwafUpdaterManager::get()
+            .write()
+            .unwrap()
+            .register(name.to_owned(), updater.to_owned())
+            .unwrap();
+
+        let run = BotwafUpdaterManager::run(name.to_owned()).await.unwrap();
+        assert_eq!(run.status, SpecRunStatus::RUNNING);
+
+        // The concurrent manual run while one is in progress.
+        let rejection = BotwafUpdaterManager::run(name.to_owned()).await.unwrap_err();
+        assert_eq!(rejection.into_response().status(), StatusCode::CONFLICT);
+
+        updater.release.notify_one();
+        for _ in 0..100 {
+            if updater.run_guard.last_run().unwrap().status == SpecRunStatus::SUCCEEDED {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(updater.updated.load(Ordering::SeqCst), 1);
+        assert_eq!(updater.run_guard.last_run().unwrap().run_id, run.run_id);
+
+        let rejection = BotwafUpdaterManager::run("nonexistent".to_owned()).await.unwrap_err();
+        assert_eq!(rejection.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_instances_of_same_kind_with_different_crons() {
+        let fast = UpdaterProperties {
+            name: "fastUpdater".to_owned(),
+            kind: SimpleLLMUpdater::KIND,
+            cron: "* * * * * *".to_owned(), // Every second
+            ..UpdaterProperties::default()
+        };
+        let slow = UpdaterProperties {
+            name: "slowUpdater".to_owned(),
+            cron: "0/2 * * * * *".to_owned(), // Every two seconds
+            ..fast.clone()
+        };
+
+        let fast = build_updater(&fast).await;
+        let slow = build_updater(&slow).await;
+        fast.init().await;
+        slow.init().await;
+
+        for _ in 0..50 {
+            if fast.run_guard().last_run().is_some() && slow.run_guard().last_run().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // Both the instances are fired by their own schedules.
+        assert_eq!(fast.run_guard().last_run().unwrap().name, "fastUpdater");
+        assert_eq!(slow.run_guard().last_run().unwrap().name, "slowUpdater");
+    }
 }
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::updater_base::BotwafUpdaterManager;
+use axum::{extract::Path, response::IntoResponse, routing::post, Json, Router};
+use botwaf_server::{context::state::BotwafState, util::web::RequireAdmin};
+use botwaf_types::{
+    modules::scheduler::spec_run::{SpecRunResponse, SpecRunStatus},
+    RespBase,
+};
+use hyper::StatusCode;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(handle_updater_run), components(schemas(SpecRunStatus, SpecRunResponse)))]
+pub struct UpdaterApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/updaters/{name}/run", post(handle_updater_run))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/updaters/{name}/run",
+    params(("name" = String, Path, description = "The name of updater spec.")),
+    responses(
+        (status = 200, description = "Triggered an immediate one-off run of the updater.", body = SpecRunResponse),
+        (status = 404, description = "Not found the updater.", body = RespBase),
+        (status = 409, description = "The updater is already running.", body = RespBase),
+    ),
+    tag = "Updater"
+)]
+async fn handle_updater_run(_: RequireAdmin, Path(name): Path<String>) -> impl IntoResponse {
+    match BotwafUpdaterManager::run(name).await {
+        Ok(run) => (StatusCode::OK, Json(run)).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
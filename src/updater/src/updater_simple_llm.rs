@@ -19,9 +19,16 @@
 // This includes modifications and derived works.
 
 // use openai::chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole};
+use super::novelty_filter::{IEventEmbedder, NoveltyFilter, NoveltyOutcome};
 use super::updater_base::{BotwafAccessEvent, IBotwafUpdater};
+use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_server::{config::config::UpdaterProperties, modules::llm::handler::llm_base::LLMManager};
+use botwaf_server::{
+    config::config::{UpdaterKind, UpdaterProperties},
+    mgmt::apm::metrics::BOTWAF_UPDATER_RUNS_TOTAL,
+    modules::llm::handler::{llm_base::LLMManager, llm_langchain::LangchainLLMHandler},
+    util::spec_runs::SpecRunGuard,
+};
 use common_telemetry::info;
 use std::sync::Arc;
 use tokio_cron_scheduler::{Job, JobScheduler};
@@ -30,42 +37,39 @@ use tokio_cron_scheduler::{Job, JobScheduler};
 pub struct SimpleLLMUpdater {
     config: UpdaterProperties,
     scheduler: Arc<JobScheduler>,
+    run_guard: Arc<SpecRunGuard>,
+    novelty_filter: Arc<NoveltyFilter>,
 }
 
 impl SimpleLLMUpdater {
-    pub const KIND: &'static str = "SIMPLE_LLM";
+    pub const KIND: UpdaterKind = UpdaterKind::SIMPLE_LLM;
 
     pub async fn new(config: &UpdaterProperties) -> Arc<Self> {
         // Create the this updater handler instance.
         Arc::new(Self {
             config: config.to_owned(),
             scheduler: Arc::new(JobScheduler::new_with_channel_size(config.channel_size).await.unwrap()),
+            run_guard: SpecRunGuard::new(&config.name),
+            novelty_filter: Arc::new(NoveltyFilter::new(&config.novelty_filter)),
         })
     }
 
-    pub(super) async fn update(&self) {
-        info!("Updating ModSec Rules ...");
-
-        // TODO: Unified create the llm handler instance with 'server/src/context/state.rs#llm_handler'
-        let llm_handler = LLMManager::get_default_implementation();
-
-        let prompt = "TODO".to_owned();
-        match llm_handler.generate(prompt).await {
-            Ok(result) => {
-                info!("Generated by LLM: {}", result);
-                // TODO: continue anthoer processing ...
-            }
-            Err(e) => {
-                tracing::error!("Failed to generate rules: {}", e);
-                return;
-            }
-        }
-    }
-
     #[allow(unused)]
     async fn fetch_events(&self, page_index: i64, page_size: i64) -> Vec<BotwafAccessEvent> {
         todo!()
     }
+
+    // Embed only the novel events of the namespace, and the all fetched events are marked as processed with
+    // the reason (e.g: duplicate), so that the skipped don't pile up.
+    #[allow(unused)]
+    async fn embed_events(
+        &self,
+        namespace: &str,
+        events: Vec<BotwafAccessEvent>,
+        embedder: &dyn IEventEmbedder,
+    ) -> Result<Vec<NoveltyOutcome>, Error> {
+        self.novelty_filter.embed_novel(namespace, events, embedder).await
+    }
 }
 
 #[async_trait]
@@ -87,12 +91,19 @@ impl IBotwafUpdater for SimpleLLMUpdater {
             }
         };
 
-        info!("Starting Analytics handler with cron '{}'", cron);
+        info!("Starting Analytics handler '{}' with cron '{}'", self.config.name, cron);
         let job = Job::new_async(cron, move |_uuid, _lock| {
             let that = this.clone();
             Box::pin(async move {
                 info!("{:?} Hi I ran", chrono::Utc::now());
-                that.update().await;
+                // Skip this tick if the manual run of the same spec is still in progress.
+                match that.run_guard.try_acquire() {
+                    Ok(_permit) => {
+                        BOTWAF_UPDATER_RUNS_TOTAL.with_label_values(&["scheduled"]).inc();
+                        that.update().await
+                    }
+                    Err(e) => tracing::warn!("Skipped the scheduled update. {:?}", e),
+                }
             })
         })
         .unwrap();
@@ -104,6 +115,36 @@ impl IBotwafUpdater for SimpleLLMUpdater {
         // Notice: It's will keep the program running
         // tokio::signal::ctrl_c().await.unwrap();
     }
+
+    async fn update(&self) {
+        info!("Updating ModSec Rules ...");
+
+        // TODO: Unified create the llm handler instance with 'server/src/context/state.rs#llm_handler'
+        let llm_handler = match LLMManager::get_implementation(LangchainLLMHandler::NAME.to_owned()) {
+            Ok(llm_handler) => llm_handler,
+            Err(e) => {
+                tracing::error!("Failed to update the rules of '{}'. {}", self.config.name, e);
+                return;
+            }
+        };
+
+        let prompt = "TODO".to_owned();
+        match llm_handler.generate(prompt).await {
+            Ok(result) => {
+                // The provider is the provenance of the generated rules, which may be the fallback provider.
+                info!("Generated by LLM provider '{}': {}", result.provider, result.content);
+                // TODO: continue anthoer processing ...
+            }
+            Err(e) => {
+                tracing::error!("Failed to generate rules: {}", e);
+                return;
+            }
+        }
+    }
+
+    fn run_guard(&self) -> &Arc<SpecRunGuard> {
+        &self.run_guard
+    }
 }
 
 #[cfg(test)]
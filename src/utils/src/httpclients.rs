@@ -18,15 +18,87 @@
 // covered by this license must also be released under the GNU GPL license.
 // This includes modifications and derived works.
 
+use reqwest::{ClientBuilder, NoProxy, Proxy};
 use std::time::Duration;
 
-pub fn build_default() -> reqwest::Client {
-    reqwest::ClientBuilder::new()
+pub fn default_builder() -> ClientBuilder {
+    ClientBuilder::new()
         .connect_timeout(Duration::new(3, 0))
         .read_timeout(Duration::new(6, 0))
         .timeout(Duration::new(6, 0))
-        //.proxy(Proxy::http("http://127.0.0.1:1080").unwrap())
         .connection_verbose(false)
-        .build()
-        .unwrap()
+}
+
+pub fn build_default() -> reqwest::Client {
+    default_builder().build().unwrap()
+}
+
+/// Apply the outbound proxy (of both http and https) to the client builder, the hosts of the 'no_proxy' (comma
+/// separated, e.g: localhost,.internal,10.0.0.0/8) are connected directly, defaults to the env 'NO_PROXY'.
+pub fn with_proxy(
+    builder: ClientBuilder,
+    http_proxy: Option<&str>,
+    no_proxy: Option<&str>,
+) -> Result<ClientBuilder, reqwest::Error> {
+    match http_proxy.map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        Some(proxy) => {
+            let no_proxy = no_proxy.map_or_else(NoProxy::from_env, NoProxy::from_string);
+            Ok(builder.proxy(Proxy::all(proxy)?.no_proxy(no_proxy)))
+        }
+        None => Ok(builder),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // The mock proxy which responds the request line of the proxied request.
+    async fn spawn_mock_proxy() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]).to_string();
+                let line = head.lines().next().unwrap_or_default().to_owned();
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    line.len(),
+                    line
+                );
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_build_with_configured_proxy() {
+        let proxy = spawn_mock_proxy().await;
+        let client = with_proxy(default_builder(), Some(&proxy), Some("localhost"))
+            .unwrap()
+            .build()
+            .unwrap();
+        // The unresolvable host is reachable only through the proxy.
+        let resp = client.get("http://botwaf-llm.invalid/v1/models").send().await.unwrap();
+        assert_eq!(
+            resp.text().await.unwrap(),
+            "GET http://botwaf-llm.invalid/v1/models HTTP/1.1"
+        );
+
+        // The host of the 'no_proxy' is connected directly.
+        let client = with_proxy(default_builder(), Some(&proxy), Some("localhost,.invalid"))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(client.get("http://botwaf-llm.invalid/v1/models").send().await.is_err());
+
+        assert!(with_proxy(default_builder(), Some("http://[::1"), None).is_err());
+    }
 }
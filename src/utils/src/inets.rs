@@ -42,3 +42,79 @@ pub fn get_local_non_loopback_ip() -> Option<Ipv4Addr> {
         Err(_) => None,
     }
 }
+
+/// Canonicalize the IP string of the client, i.e: the IPv4-mapped IPv6 (e.g: ::ffff:10.0.0.1 of the dual-stack
+/// listener) to the IPv4 and the IPv6 to the compressed lowercase, so that the same client is keyed uniformly by
+/// the rate limiting, the unparsable is returned as is.
+pub fn canonicalize_ip(ip: &str) -> String {
+    match ip.trim().parse::<IpAddr>() {
+        Ok(parsed) => parsed.to_canonical().to_string(),
+        Err(_) => ip.trim().to_owned(),
+    }
+}
+
+/// Whether the IP is contained in the CIDR (e.g: 10.0.0.0/8) or equals to the plain IP, the invalid CIDR
+/// is never matched.
+pub fn is_ip_in_cidr(ip: &IpAddr, cidr: &str) -> bool {
+    let (addr, prefix) = match cidr.trim().split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>(), prefix.parse::<u8>().ok()),
+        None => (cidr.trim().parse::<IpAddr>(), None),
+    };
+    match (addr, ip) {
+        (Ok(IpAddr::V4(network)), IpAddr::V4(ip)) => {
+            let prefix = prefix.unwrap_or(32);
+            if prefix > 32 {
+                return false;
+            }
+            let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+            u32::from(network) & mask == u32::from(*ip) & mask
+        }
+        (Ok(IpAddr::V6(network)), IpAddr::V6(ip)) => {
+            let prefix = prefix.unwrap_or(128);
+            if prefix > 128 {
+                return false;
+            }
+            let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix) };
+            u128::from(network) & mask == u128::from(*ip) & mask
+        }
+        // The IPv4-mapped IPv6 peer address (e.g: ::ffff:10.0.0.1) of the dual-stack listener.
+        (Ok(IpAddr::V4(_)), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_ip_in_cidr(&IpAddr::V4(v4), cidr),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_is_ip_in_cidr() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+        assert!(is_ip_in_cidr(&ip, "10.0.0.0/8"));
+        assert!(is_ip_in_cidr(&ip, "10.1.2.3"));
+        assert!(is_ip_in_cidr(&ip, "0.0.0.0/0"));
+        assert!(!is_ip_in_cidr(&ip, "10.1.2.4"));
+        assert!(!is_ip_in_cidr(&ip, "192.168.0.0/16"));
+        assert!(!is_ip_in_cidr(&ip, "10.0.0.0/33"));
+        assert!(!is_ip_in_cidr(&ip, "invalid"));
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(10, 1, 2, 3).to_ipv6_mapped());
+        assert!(is_ip_in_cidr(&mapped, "10.0.0.0/8"));
+
+        let ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1));
+        assert!(is_ip_in_cidr(&ip, "fd00::/8"));
+        assert!(!is_ip_in_cidr(&ip, "10.0.0.0/8"));
+    }
+
+    #[test]
+    fn test_canonicalize_ip() {
+        assert_eq!(canonicalize_ip("::ffff:10.1.2.3"), "10.1.2.3");
+        assert_eq!(canonicalize_ip(" 10.1.2.3 "), "10.1.2.3");
+        assert_eq!(canonicalize_ip("FD00:0:0::1"), "fd00::1");
+        assert_eq!(canonicalize_ip("unknown"), "unknown");
+    }
+}
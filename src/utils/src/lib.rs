@@ -25,10 +25,15 @@ pub mod httpclients;
 pub mod inets;
 pub mod mems;
 pub mod panics;
+pub mod paths;
+pub mod request_signing;
 pub mod rsa_ciphers;
 pub mod secrets;
 pub mod serde_beans;
 pub mod snowflake;
+pub mod text_diffs;
 pub mod tokio_signal;
 pub mod types;
+pub mod ulids;
+pub mod upstream_signals;
 pub mod webs;
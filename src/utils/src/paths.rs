@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+/// The request path normalized as the upstream resolves it, which is matched by the path globs of the security
+/// controls (e.g: the bypass paths, the rule exclusions and the signed routes) instead of the raw path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizedPath {
+    pub path: String,
+    // Whether the path may be resolved differently by the upstreams, i.e: the malformed or residual (e.g: double
+    // encoded) percent-encoding, or the invalid UTF-8 after decoding.
+    pub ambiguous: bool,
+}
+
+/// Normalize the request path, i.e: percent-decoded once, the backslashes and duplicated slashes are folded,
+/// and the dot segments are resolved, e.g: '/public/..%2finternal//x' is normalized to '/internal/x'.
+pub fn normalize_path(path: &str) -> NormalizedPath {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut ambiguous = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = path
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.bytes().all(|b| b.is_ascii_hexdigit()));
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                // The malformed escape is kept as is.
+                ambiguous |= bytes[i] == b'%';
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let decoded = match String::from_utf8(decoded) {
+        Ok(decoded) => {
+            ambiguous |= decoded.contains('%');
+            decoded
+        }
+        Err(e) => {
+            ambiguous = true;
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    };
+
+    let mut segments = Vec::new();
+    for segment in decoded.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    NormalizedPath {
+        path: format!("/{}", segments.join("/")),
+        ambiguous,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        for (raw, expected) in [
+            ("/internal/x", "/internal/x"),
+            ("", "/"),
+            ("//internal//x/", "/internal/x"),
+            ("/internal/./x", "/internal/x"),
+            ("/public/../internal/x", "/internal/x"),
+            ("/../../internal/x", "/internal/x"),
+            ("/%69nternal/x", "/internal/x"),
+            ("/public/..%2finternal/x", "/internal/x"),
+            ("/public/%2e%2e/internal/x", "/internal/x"),
+            ("/public\\..\\internal\\x", "/internal/x"),
+        ] {
+            assert_eq!(
+                normalize_path(raw),
+                NormalizedPath {
+                    path: expected.to_owned(),
+                    ambiguous: false,
+                },
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_path_ambiguous() {
+        for (raw, expected) in [
+            ("/internal/%252e%252e/x", "/internal/%2e%2e/x"),
+            ("/files/100%25", "/files/100%"),
+            ("/internal/%zz", "/internal/%zz"),
+            ("/internal/%2", "/internal/%2"),
+            ("/internal/%ff", "/internal/\u{fffd}"),
+        ] {
+            let normalized = normalize_path(raw);
+            assert!(normalized.ambiguous, "{}", raw);
+            assert_eq!(normalized.path, expected, "{}", raw);
+        }
+    }
+}
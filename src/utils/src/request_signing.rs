@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ring::hmac;
+use sha2::{Digest, Sha256};
+
+/// The HMAC-SHA256 request signing of the internal service-to-service calls through the proxy, which is
+/// a simplified (SigV4-lite) scheme without the derived signing key and the credential scope, e.g:
+///
+/// ```txt
+/// CanonicalRequest =
+///   HTTPMethod + '\n' +
+///   CanonicalURI + '\n' +
+///   CanonicalQueryString + '\n' +      // The raw 'k=v' pairs sorted, joined with '&'
+///   CanonicalHeaders + '\n' +          // The 'lowercase(name):trim(value)\n' of the signed headers in order
+///   SignedHeaders + '\n' +             // The lowercase names of the signed headers joined with ';'
+///   HexEncode(SHA256(Body))
+///
+/// StringToSign =
+///   "BOTWAF-HMAC-SHA256" + '\n' +
+///   X-Botwaf-Date + '\n' +             // e.g: 20261016T120000Z
+///   HexEncode(SHA256(CanonicalRequest))
+///
+/// Signature = HexEncode(HMAC-SHA256(Secret, StringToSign))
+///
+/// Authorization: BOTWAF-HMAC-SHA256 KeyId=<key-id>, SignedHeaders=host;x-botwaf-content-sha256;x-botwaf-date, Signature=<hex>
+/// ```
+pub struct RequestSigner {}
+
+/// The request to be signed, the headers (e.g: host) are signed in order.
+#[derive(Debug, Clone)]
+pub struct SigningRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub headers: &'a [(&'a str, &'a str)],
+    pub body: &'a [u8],
+}
+
+/// The parsed Authorization header of the signed request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureAuthorization {
+    pub key_id: String,
+    pub signed_headers: Vec<String>,
+    pub signature: String,
+}
+
+impl RequestSigner {
+    pub const ALGORITHM: &'static str = "BOTWAF-HMAC-SHA256";
+    pub const AUTHORIZATION_HEADER: &'static str = "authorization";
+    pub const DATE_HEADER: &'static str = "x-botwaf-date";
+    pub const CONTENT_SHA256_HEADER: &'static str = "x-botwaf-content-sha256";
+    pub const DATE_FORMAT: &'static str = "%Y%m%dT%H%M%SZ";
+
+    /// Sign the request, and returns the headers to be added, i.e: x-botwaf-date, x-botwaf-content-sha256 and
+    /// authorization.
+    pub fn sign(key_id: &str, secret: &[u8], request: &SigningRequest, now: DateTime<Utc>) -> Vec<(String, String)> {
+        let date = now.format(Self::DATE_FORMAT).to_string();
+        let content_sha256 = Self::content_sha256(request.body);
+
+        let mut signed: Vec<(String, String)> = request
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+            .collect();
+        signed.push((Self::CONTENT_SHA256_HEADER.to_owned(), content_sha256.to_owned()));
+        signed.push((Self::DATE_HEADER.to_owned(), date.to_owned()));
+        let signed_refs = signed.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect::<Vec<_>>();
+
+        let canonical = Self::canonical_request(
+            request.method,
+            request.path,
+            request.query,
+            &signed_refs,
+            &content_sha256,
+        );
+        let signature = Self::signature(secret, &Self::string_to_sign(&date, &canonical));
+        let signed_headers = signed.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join(";");
+        vec![
+            (Self::DATE_HEADER.to_owned(), date),
+            (Self::CONTENT_SHA256_HEADER.to_owned(), content_sha256),
+            (
+                Self::AUTHORIZATION_HEADER.to_owned(),
+                format!(
+                    "{} KeyId={}, SignedHeaders={}, Signature={}",
+                    Self::ALGORITHM,
+                    key_id,
+                    signed_headers,
+                    signature
+                ),
+            ),
+        ]
+    }
+
+    pub fn content_sha256(body: &[u8]) -> String {
+        hex::encode(Sha256::digest(body))
+    }
+
+    /// The canonical request, which the headers are the lowercase names with values in the signed order.
+    pub fn canonical_request(
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: &[(&str, &str)],
+        content_sha256: &str,
+    ) -> String {
+        let mut pairs = query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>();
+        pairs.sort_unstable();
+        let canonical_headers = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect::<String>();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.to_uppercase(),
+            if path.is_empty() { "/" } else { path },
+            pairs.join("&"),
+            canonical_headers,
+            signed_headers,
+            content_sha256
+        )
+    }
+
+    pub fn string_to_sign(date: &str, canonical_request: &str) -> String {
+        format!(
+            "{}\n{}\n{}",
+            Self::ALGORITHM,
+            date,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        )
+    }
+
+    pub fn signature(secret: &[u8], string_to_sign: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        hex::encode(hmac::sign(&key, string_to_sign.as_bytes()).as_ref())
+    }
+
+    /// Verify the hex signature in constant time.
+    pub fn verify(secret: &[u8], string_to_sign: &str, signature: &str) -> bool {
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        hmac::verify(&key, string_to_sign.as_bytes(), &signature).is_ok()
+    }
+
+    pub fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+        NaiveDateTime::parse_from_str(date, Self::DATE_FORMAT)
+            .ok()
+            .map(|d| d.and_utc())
+    }
+
+    /// Parse the Authorization header, e.g: BOTWAF-HMAC-SHA256 KeyId=k1, SignedHeaders=host;x-botwaf-date, Signature=ab12
+    pub fn parse_authorization(value: &str) -> Option<SignatureAuthorization> {
+        let params = value.trim().strip_prefix(Self::ALGORITHM)?;
+        let (mut key_id, mut signed_headers, mut signature) = (None, None, None);
+        for param in params.split(',') {
+            match param.trim().split_once('=') {
+                Some(("KeyId", v)) => key_id = Some(v.trim().to_owned()),
+                Some(("SignedHeaders", v)) => {
+                    signed_headers = Some(v.split(';').map(|h| h.trim().to_lowercase()).collect::<Vec<_>>())
+                }
+                Some(("Signature", v)) => signature = Some(v.trim().to_owned()),
+                _ => return None,
+            }
+        }
+        Some(SignatureAuthorization {
+            key_id: key_id.filter(|k| !k.is_empty())?,
+            signed_headers: signed_headers?,
+            signature: signature?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_parse_authorization() {
+        let now = RequestSigner::parse_date("20261016T120000Z").unwrap();
+        let request = SigningRequest {
+            method: "post",
+            path: "/orders",
+            query: Some("b=2&a=1"),
+            headers: &[("Host", "orders.internal")],
+            body: b"{}",
+        };
+        let headers = RequestSigner::sign("orders", b"secret", &request, now);
+        assert_eq!(
+            headers[0],
+            (String::from("x-botwaf-date"), String::from("20261016T120000Z"))
+        );
+        assert_eq!(headers[1].1, RequestSigner::content_sha256(b"{}"));
+
+        let auth = RequestSigner::parse_authorization(&headers[2].1).unwrap();
+        assert_eq!(auth.key_id, "orders");
+        assert_eq!(
+            auth.signed_headers,
+            vec!["host", "x-botwaf-content-sha256", "x-botwaf-date"]
+        );
+
+        // The query pairs are sorted, and the method is upper case.
+        let canonical = RequestSigner::canonical_request(
+            "POST",
+            "/orders",
+            Some("a=1&b=2"),
+            &[
+                ("host", "orders.internal"),
+                ("x-botwaf-content-sha256", &headers[1].1),
+                ("x-botwaf-date", "20261016T120000Z"),
+            ],
+            &headers[1].1,
+        );
+        let string_to_sign = RequestSigner::string_to_sign("20261016T120000Z", &canonical);
+        assert!(RequestSigner::verify(b"secret", &string_to_sign, &auth.signature));
+        assert!(!RequestSigner::verify(b"other", &string_to_sign, &auth.signature));
+    }
+
+    #[test]
+    fn test_parse_authorization_malformed() {
+        assert!(RequestSigner::parse_authorization("Bearer abc").is_none());
+        assert!(RequestSigner::parse_authorization("BOTWAF-HMAC-SHA256 KeyId=k1, Signature=ab").is_none());
+        assert!(
+            RequestSigner::parse_authorization("BOTWAF-HMAC-SHA256 KeyId=, SignedHeaders=host, Signature=ab").is_none()
+        );
+    }
+}
@@ -19,7 +19,10 @@
 // This includes modifications and derived works.
 
 use crate::base64s::Base64Helper;
-use ring::rand::{SecureRandom, SystemRandom};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
 
 pub struct SecretHelper {}
 
@@ -30,4 +33,9 @@ impl SecretHelper {
         rng.fill(&mut secret).expect("Failed to generate random secret.");
         Base64Helper::encode(&secret)
     }
+
+    pub fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        hex::encode(hmac::sign(&key, data).as_ref())
+    }
 }
@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+/// The max cells of the LCS table (i.e: the changed old lines x the changed new lines), beyond which the changed
+/// lines are diffed as the whole replacement to bound the memory and CPU.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffOp<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Generate the line based unified diff (same as `diff -u`) from the old text to the new text, with the number of
+/// the context lines around the changes, e.g:
+///
+/// ```rust
+/// use botwaf_utils::text_diffs::unified_diff;
+///
+/// let diff = unified_diff("a\nb\nc", "a\nB\nc", "v1", "v2", 1);
+/// assert_eq!(diff, "--- v1\n+++ v2\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+/// ```
+///
+/// Returns the empty string if there is no any changes.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str, context: usize) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let ops = diff_lines(&old_lines, &new_lines);
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal(_)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // The old and new line positions (0-based) before each op.
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in ops.iter() {
+        positions.push((old_pos, new_pos));
+        match op {
+            DiffOp::Equal(_) => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            DiffOp::Delete(_) => old_pos += 1,
+            DiffOp::Insert(_) => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    // Group the changes into the hunks, the changes within the twice context lines are merged.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in changes.iter() {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let (old_len, new_len) = (old_end - old_start, new_end - new_start);
+        // Notice: The start of the empty range is the line before, same as `diff -u`.
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if old_len > 0 { old_start + 1 } else { old_start },
+            old_len,
+            if new_len > 0 { new_start + 1 } else { new_start },
+            new_len
+        ));
+        for op in ops[start..end].iter() {
+            let (prefix, line) = match op {
+                DiffOp::Equal(line) => (' ', line),
+                DiffOp::Delete(line) => ('-', line),
+                DiffOp::Insert(line) => ('+', line),
+            };
+            diff.push(prefix);
+            diff.push_str(line);
+            diff.push('\n');
+        }
+    }
+    diff
+}
+
+/// Diff the lines by the longest common subsequence, after trimmed the common prefix and suffix.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut ops = old[..prefix].iter().map(|l| DiffOp::Equal(l)).collect::<Vec<_>>();
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if n.saturating_mul(m) > MAX_LCS_CELLS {
+        ops.extend(old_mid.iter().map(|l| DiffOp::Delete(l)));
+        ops.extend(new_mid.iter().map(|l| DiffOp::Insert(l)));
+    } else {
+        // The lcs[i][j] is the length of the LCS of the old_mid[i..] and new_mid[j..]
+        let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if old_mid[i] == new_mid[j] {
+                ops.push(DiffOp::Equal(old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                ops.push(DiffOp::Delete(old_mid[i]));
+                i += 1;
+            } else {
+                ops.push(DiffOp::Insert(new_mid[j]));
+                j += 1;
+            }
+        }
+        ops.extend(old_mid[i..].iter().map(|l| DiffOp::Delete(l)));
+        ops.extend(new_mid[j..].iter().map(|l| DiffOp::Insert(l)));
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|l| DiffOp::Equal(l)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_unchanged() {
+        assert_eq!(unified_diff("a\nb", "a\nb", "v1", "v2", 3), "");
+        assert_eq!(unified_diff("", "", "v1", "v2", 3), "");
+    }
+
+    #[test]
+    fn test_unified_diff_multi_line_hunks() {
+        let old = "SecRuleEngine On\n\
+                   SecRule ARGS \"@rx union\" \"id:1001,phase:2,deny\"\n\
+                   SecRule ARGS \"@rx select\" \"id:1002,phase:2,deny\"\n\
+                   # 1\n# 2\n# 3\n# 4\n# 5\n# 6\n\
+                   SecRule REQUEST_URI \"@rx admin\" \"id:1003,phase:1,deny\"";
+        let new = "SecRuleEngine On\n\
+                   SecRule ARGS \"@rx (?i)union\" \"id:1001,phase:2,deny\"\n\
+                   SecRule ARGS \"@rx (?i)select\" \"id:1002,phase:2,deny\"\n\
+                   SecRule ARGS \"@rx sleep\\(\" \"id:1004,phase:2,deny\"\n\
+                   # 1\n# 2\n# 3\n# 4\n# 5\n# 6";
+        let diff = unified_diff(old, new, "v1", "v2", 1);
+        assert_eq!(
+            diff,
+            "--- v1\n\
+             +++ v2\n\
+             @@ -1,4 +1,5 @@\n \
+             SecRuleEngine On\n\
+             -SecRule ARGS \"@rx union\" \"id:1001,phase:2,deny\"\n\
+             -SecRule ARGS \"@rx select\" \"id:1002,phase:2,deny\"\n\
+             +SecRule ARGS \"@rx (?i)union\" \"id:1001,phase:2,deny\"\n\
+             +SecRule ARGS \"@rx (?i)select\" \"id:1002,phase:2,deny\"\n\
+             +SecRule ARGS \"@rx sleep\\(\" \"id:1004,phase:2,deny\"\n \
+             # 1\n\
+             @@ -9,2 +10,1 @@\n \
+             # 6\n\
+             -SecRule REQUEST_URI \"@rx admin\" \"id:1003,phase:1,deny\"\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_from_empty() {
+        assert_eq!(
+            unified_diff("", "a\nb", "/dev/null", "v1", 3),
+            "--- /dev/null\n+++ v1\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+use ring::rand::{SecureRandom, SystemRandom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The Crockford's base32 alphabet, which excludes the ambiguous I, L, O and U.
+const ENCODING: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generate the ULID, i.e: the 48 bits milliseconds timestamp and the 80 random bits encoded as 26 characters
+/// of Crockford's base32, which is lexicographically sortable by the time generated.
+/// see:https://github.com/ulid/spec
+pub fn new_ulid() -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let mut random = [0u8; 10];
+    SystemRandom::new()
+        .fill(&mut random)
+        .expect("Failed to generate the ULID random.");
+    encode_ulid(millis, &random)
+}
+
+fn encode_ulid(millis: u64, random: &[u8; 10]) -> String {
+    let mut value = (millis as u128 & 0xFFFF_FFFF_FFFF) << 80;
+    for (i, b) in random.iter().enumerate() {
+        value |= (*b as u128) << (8 * (9 - i));
+    }
+    (0..26)
+        .rev()
+        .map(|i| ENCODING[((value >> (5 * i)) & 0x1F) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ulid() {
+        assert_eq!(encode_ulid(0, &[0u8; 10]), "00000000000000000000000000");
+        assert_eq!(encode_ulid(0xFFFF_FFFF_FFFF, &[0xFF; 10]), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+        // The timestamp part of the spec example, i.e: 1469918176385 is '01ARYZ6S41'
+        assert!(encode_ulid(1469918176385, &[0u8; 10]).starts_with("01ARYZ6S41"));
+    }
+
+    #[test]
+    fn test_new_ulid_unique_and_sortable() {
+        let first = new_ulid();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = new_ulid();
+        assert_eq!(first.len(), 26);
+        assert_ne!(first, second);
+        assert!(first < second);
+    }
+}
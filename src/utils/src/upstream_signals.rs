@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use crate::request_signing::RequestSigner;
+
+/// The signals of the Botwaf evaluation injected into the forwarded requests, with the optional HMAC-SHA256
+/// signature so that the upstream applications can verify they genuinely came from Botwaf, e.g:
+///
+/// ```txt
+/// StringToSign =
+///   "BOTWAF-SIGNALS-HMAC-SHA256" + '\n' +
+///   HTTPMethod + '\n' +                // The uppercase method, e.g: POST
+///   Path + '\n' +                      // The request path without the query string, e.g: /api/login
+///   X-Botwaf-Decision + '\n' +         // The raw received header values, empty if absent.
+///   X-Botwaf-Score + '\n' +
+///   X-Botwaf-Flags
+///
+/// X-Botwaf-Signature = HexEncode(HMAC-SHA256(Secret, StringToSign))
+/// ```
+///
+/// The upstream applications (in any language) verify by recomputing the signature with the shared secret
+/// of 'services.forward.upstream-signals.signing-secret', and comparing in constant time, or in Rust:
+///
+/// ```rust
+/// use botwaf_utils::upstream_signals::UpstreamSignals;
+///
+/// let secret = b"shared-secret";
+/// let signals = UpstreamSignals {
+///     method: "POST",
+///     path: "/api/login",
+///     decision: "PASS",
+///     score: "60",
+///     flags: "shadow:credential-stuffing",
+/// };
+/// let signature = signals.sign(secret);
+/// assert!(signals.verify(secret, &signature));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamSignals<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub decision: &'a str,
+    pub score: &'a str,
+    pub flags: &'a str,
+}
+
+impl<'a> UpstreamSignals<'a> {
+    pub const ALGORITHM: &'static str = "BOTWAF-SIGNALS-HMAC-SHA256";
+
+    pub fn string_to_sign(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            Self::ALGORITHM,
+            self.method.to_uppercase(),
+            if self.path.is_empty() { "/" } else { self.path },
+            self.decision.trim(),
+            self.score.trim(),
+            self.flags.trim()
+        )
+    }
+
+    pub fn sign(&self, secret: &[u8]) -> String {
+        RequestSigner::signature(secret, &self.string_to_sign())
+    }
+
+    /// Verify the hex signature in constant time.
+    pub fn verify(&self, secret: &[u8], signature: &str) -> bool {
+        RequestSigner::verify(secret, &self.string_to_sign(), signature.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals<'a>(score: &'a str) -> UpstreamSignals<'a> {
+        UpstreamSignals {
+            method: "post",
+            path: "/api/login",
+            decision: "PASS",
+            score,
+            flags: "shadow:credential-stuffing,uninspected-method",
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify_signals() {
+        let signature = signals("60").sign(b"secret");
+        assert_eq!(signature.len(), 64);
+        assert!(signals("60").verify(b"secret", &signature));
+
+        // The tampered values, the other secret and the malformed signature are all rejected.
+        assert!(!signals("0").verify(b"secret", &signature));
+        assert!(!signals("60").verify(b"other-secret", &signature));
+        assert!(!signals("60").verify(b"secret", "not-hex"));
+    }
+}
@@ -19,4 +19,5 @@
 // This includes modifications and derived works.
 
 pub mod verifier_base;
+pub mod verifier_router;
 pub mod verifier_simple_execution;
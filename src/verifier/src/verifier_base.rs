@@ -21,7 +21,11 @@
 use super::verifier_simple_execution::SimpleExecuteBasedVerifier;
 use anyhow::Error;
 use async_trait::async_trait;
-use botwaf_server::config::config;
+use botwaf_server::{
+    config::config::{self, VerifierKind, VerifierProperties},
+    util::spec_runs::{SpecRunGuard, SpecRunRejection},
+};
+use botwaf_types::modules::scheduler::spec_run::SpecRunResponse;
 use common_telemetry::info;
 use lazy_static::lazy_static;
 use std::{
@@ -32,12 +36,25 @@ use std::{
 #[async_trait]
 pub trait IBotwafVerifier: Send + Sync {
     async fn init(&self);
+
+    async fn verify(&self);
+
+    // The guard shared by the manual and scheduled runs, see: SpecRunGuard
+    fn run_guard(&self) -> &Arc<SpecRunGuard>;
 }
 
 lazy_static! {
     static ref SINGLE_INSTANCE: RwLock<BotwafVerifierManager> = RwLock::new(BotwafVerifierManager::new());
 }
 
+/// Build the verifier instance of the spec config kind, the match is exhaustive, so that adding a new kind
+/// requires only to implement the IBotwafVerifier and register it here, otherwise it fails to compile.
+pub async fn build_verifier(config: &VerifierProperties) -> Arc<dyn IBotwafVerifier + Send + Sync> {
+    match config.kind {
+        VerifierKind::SIMPLE_EXECUTE => SimpleExecuteBasedVerifier::new(config).await,
+    }
+}
+
 pub struct BotwafVerifierManager {
     pub implementations: HashMap<String, Arc<dyn IBotwafVerifier + Send + Sync>>,
 }
@@ -53,6 +70,8 @@ impl BotwafVerifierManager {
         &SINGLE_INSTANCE
     }
 
+    /// Initialize all the enabled verifiers, which is idempotent for the restart of the supervisor, i.e: the
+    /// already initialized verifiers are skipped, so that their schedulers aren't registered duplicated.
     pub async fn init() {
         info!("Register All Botwaf updaters ...");
 
@@ -61,28 +80,32 @@ impl BotwafVerifierManager {
                 info!("Skipping implementation updater: {}", config.name);
                 continue;
             }
-            // TODO: Full use similar java spi provider mechanism.
-            if config.kind == SimpleExecuteBasedVerifier::KIND {
-                match Self::get()
-                    .write() // If acquire fails, then it block until acquired.
-                    .unwrap() // If acquire fails, then it should panic.
-                    .register(config.kind.to_owned(), SimpleExecuteBasedVerifier::new(config).await)
-                {
-                    Ok(registered) => {
-                        info!("Initializing Botwaf Verifier ...");
-                        let _ = registered.init().await;
-                    }
-                    Err(e) => panic!("Failed to register Botwaf Verifier: {}", e),
-                }
+            if Self::get().read().unwrap().implementations.contains_key(&config.name) {
+                info!("Skipping the initialized verifier: {}", config.name);
+                continue;
+            }
+            let verifier = build_verifier(config).await;
+            // Register after initialized, so that the failed is re-initialized on restarting.
+            info!(
+                "Initializing Botwaf Verifier '{}' of kind {:?} ...",
+                config.name, config.kind
+            );
+            verifier.init().await;
+            if let Err(e) = Self::get()
+                .write() // If acquire fails, then it block until acquired.
+                .unwrap() // If acquire fails, then it should panic.
+                .register(config.name.to_owned(), verifier)
+            {
+                panic!("Failed to register Botwaf Verifier: {}", e);
             }
         }
     }
 
-    fn register<T: IBotwafVerifier + Send + Sync + 'static>(
+    fn register(
         &mut self,
         name: String,
-        handler: Arc<T>,
-    ) -> Result<Arc<T>, Error> {
+        handler: Arc<dyn IBotwafVerifier + Send + Sync>,
+    ) -> Result<Arc<dyn IBotwafVerifier + Send + Sync>, Error> {
         if self.implementations.contains_key(&name) {
             tracing::debug!("Already register the Verifier '{}'", name);
             return Ok(handler);
@@ -101,4 +124,17 @@ impl BotwafVerifierManager {
             return Err(Error::msg(errmsg));
         }
     }
+
+    /// Trigger an immediate one-off run of the named verifier spec outside the schedule.
+    pub async fn run(name: String) -> Result<SpecRunResponse, SpecRunRejection> {
+        let implementation = match Self::get_implementation(name.to_owned()).await {
+            Ok(implementation) => implementation,
+            Err(_) => return Err(SpecRunRejection::NotFound(name)),
+        };
+        let guard = implementation.run_guard().to_owned();
+        guard.spawn(async move {
+            info!("Manual running the Verifier '{}' ...", name);
+            implementation.verify().await;
+        })
+    }
 }
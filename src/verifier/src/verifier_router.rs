@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+//
+// Copyleft (c) 2024 James Wong. This file is part of James Wong.
+// is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// James Wong is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+//
+// IMPORTANT: Any software that fully or partially contains or uses materials
+// covered by this license must also be released under the GNU GPL license.
+// This includes modifications and derived works.
+
+use super::verifier_base::BotwafVerifierManager;
+use axum::{extract::Path, response::IntoResponse, routing::post, Json, Router};
+use botwaf_server::{context::state::BotwafState, util::web::RequireAdmin};
+use botwaf_types::{
+    modules::scheduler::spec_run::{SpecRunResponse, SpecRunStatus},
+    RespBase,
+};
+use hyper::StatusCode;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(handle_verifier_run), components(schemas(SpecRunStatus, SpecRunResponse)))]
+pub struct VerifierApiDoc;
+
+pub fn init() -> Router<BotwafState> {
+    Router::new().route("/api/v1/verifiers/{name}/run", post(handle_verifier_run))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/verifiers/{name}/run",
+    params(("name" = String, Path, description = "The name of verifier spec.")),
+    responses(
+        (status = 200, description = "Triggered an immediate one-off run of the verifier.", body = SpecRunResponse),
+        (status = 404, description = "Not found the verifier.", body = RespBase),
+        (status = 409, description = "The verifier is already running.", body = RespBase),
+    ),
+    tag = "Verifier"
+)]
+async fn handle_verifier_run(_: RequireAdmin, Path(name): Path<String>) -> impl IntoResponse {
+    match BotwafVerifierManager::run(name).await {
+        Ok(run) => (StatusCode::OK, Json(run)).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
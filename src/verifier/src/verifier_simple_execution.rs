@@ -20,7 +20,10 @@
 
 use super::verifier_base::IBotwafVerifier;
 use async_trait::async_trait;
-use botwaf_server::config::config::VerifierProperties;
+use botwaf_server::{
+    config::config::{VerifierKind, VerifierProperties},
+    util::spec_runs::SpecRunGuard,
+};
 use common_telemetry::info;
 use std::sync::Arc;
 use tokio_cron_scheduler::{Job, JobScheduler};
@@ -29,22 +32,19 @@ use tokio_cron_scheduler::{Job, JobScheduler};
 pub struct SimpleExecuteBasedVerifier {
     config: VerifierProperties,
     scheduler: Arc<JobScheduler>,
+    run_guard: Arc<SpecRunGuard>,
 }
 
 impl SimpleExecuteBasedVerifier {
-    pub const KIND: &'static str = "SIMPLE_EXECUTE";
+    pub const KIND: VerifierKind = VerifierKind::SIMPLE_EXECUTE;
 
     pub async fn new(config: &VerifierProperties) -> Arc<Self> {
         Arc::new(Self {
             config: config.to_owned(),
             scheduler: Arc::new(JobScheduler::new_with_channel_size(config.channel_size).await.unwrap()),
+            run_guard: SpecRunGuard::new(&config.name),
         })
     }
-
-    pub(super) async fn verify(&self) {
-        info!("Simple Execute verifing ...");
-        info!("TODO");
-    }
 }
 
 #[async_trait]
@@ -71,7 +71,11 @@ impl IBotwafVerifier for SimpleExecuteBasedVerifier {
             let that = this.clone();
             Box::pin(async move {
                 info!("{:?} Hi I ran", chrono::Utc::now());
-                that.verify().await;
+                // Skip this tick if the manual run of the same spec is still in progress.
+                match that.run_guard.try_acquire() {
+                    Ok(_permit) => that.verify().await,
+                    Err(e) => tracing::warn!("Skipped the scheduled verify. {:?}", e),
+                }
             })
         })
         .unwrap();
@@ -83,6 +87,15 @@ impl IBotwafVerifier for SimpleExecuteBasedVerifier {
         // Notice: It's will keep the program running
         // tokio::signal::ctrl_c().await.unwrap();
     }
+
+    async fn verify(&self) {
+        info!("Simple Execute verifing ...");
+        info!("TODO");
+    }
+
+    fn run_guard(&self) -> &Arc<SpecRunGuard> {
+        &self.run_guard
+    }
 }
 
 #[cfg(test)]
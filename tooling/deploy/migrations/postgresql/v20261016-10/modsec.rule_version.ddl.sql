@@ -0,0 +1,45 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the rule versions table, which records the immutable versions of the managed rules, the head (i.e: the max
+-- version number) of the rule is effective, and the rollback appends the new head equal to the old version.
+CREATE TABLE IF NOT EXISTS botwaf_rule_version (
+    id BIGINT PRIMARY KEY NOT NULL,
+    rule_name VARCHAR(64) NOT NULL,
+    -- "The name of the managed rule"
+    version_no BIGINT NOT NULL,
+    -- "The sequence of the version per rule, starting from 1"
+    content TEXT NOT NULL,
+    -- "The raw SecLang content of the rule"
+    severity VARCHAR(32) NULL,
+    -- "Options: low|medium|high|critical"
+    description VARCHAR(512) NULL,
+    comment VARCHAR(512) NULL,
+    -- "The change comment, required on the update"
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS uk_botwaf_rule_version_rule_name_version_no ON botwaf_rule_version (rule_name, version_no);
@@ -0,0 +1,49 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the rule changesets table, which records the multi rules changes validated, applied and rolled back as a
+-- unit, the review state is DRAFT -> APPROVED -> APPLIED (-> ROLLED_BACK).
+CREATE TABLE IF NOT EXISTS botwaf_rule_changeset (
+    id BIGINT PRIMARY KEY NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    description VARCHAR(512) NULL,
+    state VARCHAR(16) NOT NULL default 'DRAFT',
+    -- "Options: DRAFT|APPROVED|APPLIED|ROLLED_BACK"
+    changes TEXT NULL,
+    -- "The JSON array of the rule changes"
+    baseline TEXT NULL,
+    -- "The JSON object of the head version numbers before applied, for the rollback"
+    compiled_hash VARCHAR(64) NULL,
+    -- "The hash of the compiled managed rules set after applied"
+    approved_by VARCHAR(64) NULL,
+    applied_by VARCHAR(64) NULL,
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+
+-- The changeset which appended the rule version, and the disabled (status = 1) head is the deleted rule.
+ALTER TABLE botwaf_rule_version ADD COLUMN IF NOT EXISTS changeset_id BIGINT NULL;
+CREATE INDEX IF NOT EXISTS idx_botwaf_rule_version_changeset_id ON botwaf_rule_version (changeset_id);
@@ -0,0 +1,40 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the managed data files table of the ModSecurity rules, e.g: @pmFromFile botwaf-data:bad-user-agents.txt
+CREATE TABLE IF NOT EXISTS botwaf_data_file (
+    id BIGINT PRIMARY KEY NOT NULL,
+    name VARCHAR(128) NOT NULL,
+    -- "The unique logical name referenced by the rules"
+    format VARCHAR(32) NOT NULL,
+    -- "Options: PATTERNS|IPS"
+    content TEXT NULL,
+    checksum VARCHAR(64) NULL,
+    -- "The SHA-256 hex of the content"
+    size BIGINT NULL default 0,
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS uk_botwaf_data_file_name ON botwaf_data_file (name);
@@ -0,0 +1,32 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Add the normalized name/email keys for the case-insensitive lookup and uniqueness, which are computed by the
+-- application on save (NFKC + casefold, see: sys::identities), and backfilled by lower() for the existing users.
+-- Notice: The duplicated users (case-insensitively) are reported and refused to migrate on startup, please
+-- resolve them first, e.g: merge into one user and delete the others.
+ALTER TABLE sys_user ADD COLUMN IF NOT EXISTS name_key VARCHAR(64) NULL;
+ALTER TABLE sys_user ADD COLUMN IF NOT EXISTS email_key VARCHAR(64) NULL;
+
+UPDATE sys_user SET name_key = lower(trim(name)) WHERE name IS NOT NULL AND trim(name) != '';
+UPDATE sys_user SET email_key = lower(trim(email)) WHERE email IS NOT NULL AND trim(email) != '';
+
+CREATE UNIQUE INDEX IF NOT EXISTS uk_sys_user_name_key ON sys_user (name_key) WHERE del_flag = 0;
+CREATE UNIQUE INDEX IF NOT EXISTS uk_sys_user_email_key ON sys_user (email_key) WHERE del_flag = 0;
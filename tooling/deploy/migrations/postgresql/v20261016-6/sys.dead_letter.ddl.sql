@@ -0,0 +1,45 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the dead letters table of the failed async works, which is bounded by the retention purging.
+CREATE TABLE IF NOT EXISTS sys_dead_letter (
+    id BIGINT PRIMARY KEY NOT NULL,
+    kind VARCHAR(64) NOT NULL,
+    -- "The kind of the original pipeline, e.g: ACCESS_EVENTS|WEBHOOK"
+    payload TEXT NULL,
+    -- "The serialized (JSON) payload of the failed work"
+    error TEXT NULL,
+    -- "The last error of the failed work or replay"
+    attempts BIGINT NULL default 0,
+    replays BIGINT NULL default 0,
+    state VARCHAR(32) NOT NULL,
+    -- "Options: PENDING|REPLAYED|FAILED"
+    last_failed_time BIGINT NULL,
+    -- "The unix timestamp (in millis) of the last failure"
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+CREATE INDEX IF NOT EXISTS idx_sys_dead_letter_kind_state ON sys_dead_letter (kind, state);
+CREATE INDEX IF NOT EXISTS idx_sys_dead_letter_create_time ON sys_dead_letter (create_time);
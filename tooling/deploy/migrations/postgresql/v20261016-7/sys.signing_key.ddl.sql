@@ -0,0 +1,38 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the request signing keys table, which at most two ACTIVE keys per key id for the rotation.
+CREATE TABLE IF NOT EXISTS sys_signing_key (
+    id BIGINT PRIMARY KEY NOT NULL,
+    key_id VARCHAR(64) NOT NULL,
+    -- "The key id of the Authorization header, e.g: orders-service"
+    secret VARCHAR(256) NOT NULL,
+    -- "The HMAC secret"
+    state VARCHAR(32) NOT NULL,
+    -- "Options: ACTIVE|RETIRED"
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+CREATE INDEX IF NOT EXISTS idx_sys_signing_key_key_id_state ON sys_signing_key (key_id, state);
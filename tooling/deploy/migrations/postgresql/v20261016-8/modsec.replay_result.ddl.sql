@@ -0,0 +1,45 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the replay results table, which records the historical access events re-evaluated against the current rules.
+CREATE TABLE IF NOT EXISTS botwaf_replay_result (
+    id BIGINT PRIMARY KEY NOT NULL,
+    job_id VARCHAR(64) NOT NULL,
+    -- "The replay job, which is resumed from the max event id of the job"
+    event_id BIGINT NOT NULL,
+    -- "The id ordered sequence of the event in the access events store"
+    old_decision VARCHAR(32) NOT NULL,
+    -- "Options: BLOCK|PASS"
+    old_rule_id VARCHAR(64) NULL,
+    -- "The matched rule id of the original decision"
+    new_decision VARCHAR(32) NOT NULL,
+    -- "Options: BLOCK|PASS"
+    new_rule_ids VARCHAR(512) NULL,
+    -- "The newly matched rule ids, separated by comma"
+    status INTEGER NULL default 0,
+    create_by VARCHAR(64) NULL,
+    create_time TIMESTAMPTZ default current_timestamp,
+    update_by VARCHAR(64) NULL,
+    update_time TIMESTAMPTZ default current_timestamp,
+    del_flag INTEGER NOT NULL default 0,
+    version BIGINT NOT NULL default 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS uk_botwaf_replay_result_job_id_event_id ON botwaf_replay_result (job_id, event_id);
@@ -0,0 +1,40 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the rule versions table, which records the immutable versions of the managed rules, the head (i.e: the max
+-- version number) of the rule is effective, and the rollback appends the new head equal to the old version.
+create table if not exists botwaf_rule_version (
+    id integer primary key not null,
+    rule_name varchar(64) not null, -- "The name of the managed rule"
+    version_no integer not null, -- "The sequence of the version per rule, starting from 1"
+    content text not null, -- "The raw SecLang content of the rule"
+    severity varchar(32) null, -- "Options: low|medium|high|critical"
+    description varchar(512) null,
+    comment varchar(512) null, -- "The change comment, required on the update"
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
+create unique index if not exists uk_botwaf_rule_version_rule_name_version_no on botwaf_rule_version (rule_name, version_no);
@@ -0,0 +1,45 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the rule changesets table, which records the multi rules changes validated, applied and rolled back as a
+-- unit, the review state is DRAFT -> APPROVED -> APPLIED (-> ROLLED_BACK).
+create table if not exists botwaf_rule_changeset (
+    id integer primary key not null,
+    name varchar(64) not null,
+    description varchar(512) null,
+    state varchar(16) not null default 'DRAFT', -- "Options: DRAFT|APPROVED|APPLIED|ROLLED_BACK"
+    changes text null, -- "The JSON array of the rule changes"
+    baseline text null, -- "The JSON object of the head version numbers before applied, for the rollback"
+    compiled_hash varchar(64) null, -- "The hash of the compiled managed rules set after applied"
+    approved_by varchar(64) null,
+    applied_by varchar(64) null,
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
+
+-- The changeset which appended the rule version, and the disabled (status = 1) head is the deleted rule.
+alter table botwaf_rule_version add column changeset_id integer null;
+create index if not exists idx_botwaf_rule_version_changeset_id on botwaf_rule_version (changeset_id);
@@ -0,0 +1,26 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Normalize the legacy naive timestamps (the current_timestamp default is UTC) and the offset timestamps
+-- into the ISO-8601 UTC strings, e.g: '2024-07-10 08:37:54' => '2024-07-10T08:37:54.000Z'
+update sys_user set create_time = strftime('%Y-%m-%dT%H:%M:%fZ', create_time)
+    where typeof(create_time) = 'text' and create_time not like '%Z';
+update sys_user set update_time = strftime('%Y-%m-%dT%H:%M:%fZ', update_time)
+    where typeof(update_time) = 'text' and update_time not like '%Z';
@@ -0,0 +1,37 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the managed data files table of the ModSecurity rules, e.g: @pmFromFile botwaf-data:bad-user-agents.txt
+create table if not exists botwaf_data_file (
+    id integer primary key not null,
+    name varchar(128) not null, -- "The unique logical name referenced by the rules"
+    format varchar(32) not null, -- "Options: PATTERNS|IPS"
+    content text null,
+    checksum varchar(64) null, -- "The SHA-256 hex of the content"
+    size integer null default 0,
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
+create unique index if not exists uk_botwaf_data_file_name on botwaf_data_file (name);
@@ -0,0 +1,32 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the first-run bootstrap completed flag table, the bootstrap endpoint is closed once any row exists.
+create table if not exists sys_bootstrap (
+    id integer primary key not null,
+    admin_name varchar(64) null, -- "The initial administrator created by the bootstrap"
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
@@ -0,0 +1,40 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+-- Create the dead letters table of the failed async works, which is bounded by the retention purging.
+create table if not exists sys_dead_letter (
+    id integer primary key not null,
+    kind varchar(64) not null, -- "The kind of the original pipeline, e.g: ACCESS_EVENTS|WEBHOOK"
+    payload text null, -- "The serialized (JSON) payload of the failed work"
+    error text null, -- "The last error of the failed work or replay"
+    attempts integer null default 0,
+    replays integer null default 0,
+    state varchar(32) not null, -- "Options: PENDING|REPLAYED|FAILED"
+    last_failed_time integer null, -- "The unix timestamp (in millis) of the last failure"
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
+create index if not exists idx_sys_dead_letter_kind_state on sys_dead_letter (kind, state);
+create index if not exists idx_sys_dead_letter_create_time on sys_dead_letter (create_time);
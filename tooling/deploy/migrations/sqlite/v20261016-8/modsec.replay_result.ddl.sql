@@ -0,0 +1,39 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Create the replay results table, which records the historical access events re-evaluated against the current rules.
+create table if not exists botwaf_replay_result (
+    id integer primary key not null,
+    job_id varchar(64) not null, -- "The replay job, which is resumed from the max event id of the job"
+    event_id integer not null, -- "The id ordered sequence of the event in the access events store"
+    old_decision varchar(32) not null, -- "Options: BLOCK|PASS"
+    old_rule_id varchar(64) null, -- "The matched rule id of the original decision"
+    new_decision varchar(32) not null, -- "Options: BLOCK|PASS"
+    new_rule_ids varchar(512) null, -- "The newly matched rule ids, separated by comma"
+    status integer null default 0,
+    create_by varchar(64) null,
+    create_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    update_by varchar(64) null,
+    update_time text default (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    del_flag integer not null default 0,
+    version integer not null default 0
+);
+create unique index if not exists uk_botwaf_replay_result_job_id_event_id on botwaf_replay_result (job_id, event_id);
@@ -0,0 +1,28 @@
+-- SPDX-License-Identifier: GNU GENERAL PUBLIC LICENSE Version 3
+--
+-- Copyleft (c) 2024 James Wong. This file is part of James Wong.
+-- is free software: you can redistribute it and/or modify it under
+-- the terms of the GNU General Public License as published by the
+-- Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+--
+-- James Wong is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY; without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+--
+-- You should have received a copy of the GNU General Public License
+-- along with James Wong.  If not, see <https://www.gnu.org/licenses/>.
+--
+-- IMPORTANT: Any software that fully or partially contains or uses materials
+-- covered by this license must also be released under the GNU GPL license.
+-- This includes modifications and derived works.
+
+
+-- Add the unique claims subjects of the users, which resolves the concurrent first logins of the same OAuth2 user
+-- by the get-or-create within a transaction (see: AsyncRepository::get_or_insert) instead of duplicating.
+-- Notice: The duplicated users (by the claims subjects) are reported and refused to migrate on startup, please
+-- resolve them first, e.g: merge into one user and delete the others.
+create unique index if not exists uk_sys_user_oidc_claims_sub on sys_user (oidc_claims_sub) where del_flag = 0;
+create unique index if not exists uk_sys_user_github_claims_sub on sys_user (github_claims_sub) where del_flag = 0;
+create unique index if not exists uk_sys_user_google_claims_sub on sys_user (google_claims_sub) where del_flag = 0;